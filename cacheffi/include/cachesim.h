@@ -0,0 +1,170 @@
+#ifndef CACHESIM_H
+#define CACHESIM_H
+
+/* Generated by cbindgen from cacheffi/src/lib.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The version of the interface described by this header
+#define CACHESIM_API_VERSION 1
+
+// The kind of an access which reads its bytes, which a zeroed access is
+#define CACHESIM_READ 0
+
+// The kind of an access which writes its bytes, simulated as a read unless the configuration
+// simulates writes
+#define CACHESIM_WRITE 1
+
+// The kind of an instruction fetch, which takes the instruction cache of configurations with one
+#define CACHESIM_FETCH 2
+
+// The kind of an access which reads then writes the same bytes, such as an increment, which is
+// two accesses to each line
+#define CACHESIM_MODIFY 3
+
+// A simulator for a configuration, which keeps the contents of its caches between calls
+//
+// Only one thread may use a simulator at a time, but it may be moved between threads.
+struct cachesim_simulator;
+
+// A single access, as made by the program being simulated
+struct cachesim_access {
+  // The address of the instruction making the access, or 0 if it isn't known
+  uint64_t pc;
+  uint64_t address;
+  // The number of bytes accessed
+  uint16_t size;
+  // One of `CACHESIM_READ`, `CACHESIM_WRITE`, `CACHESIM_FETCH` or `CACHESIM_MODIFY`
+  uint8_t kind;
+};
+
+// The statistics of one cache
+struct cachesim_cache_stats {
+  uint64_t hits;
+  uint64_t misses;
+  // Lines brought into the cache by its prefetcher
+  uint64_t prefetches;
+};
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Gets the version of the interface the library implements, which should equal the
+// `CACHESIM_API_VERSION` of the header the caller was compiled with
+uint32_t cachesim_api_version(void);
+
+// Gets the error message of the last call on this thread which failed
+//
+// The message is owned by the library, and is valid until the next failing call on this thread.
+// It is empty if no call has failed.
+const char *cachesim_last_error(void);
+
+// Creates a simulator with empty caches from a configuration, in the same JSON format as
+// configuration files
+//
+// Configurations can't use `include` or variables, load them with the command line's
+// `--dump-effective-config` first. Returns null if the configuration is invalid.
+//
+// # Safety
+//
+// `config_json` must be null or a nul terminated string
+struct cachesim_simulator *cachesim_create(const char *config_json);
+
+// Destroys a simulator, freeing its caches
+//
+// # Safety
+//
+// `simulator` must be null or have been returned by `cachesim_create` and not yet destroyed
+void cachesim_destroy(struct cachesim_simulator *simulator);
+
+// Simulates a batch of accesses made by one core, in order
+//
+// `core` is the core making the accesses, as used in the `shared_by` lists of the configuration,
+// or 0 for configurations without them. Batching many accesses in each call avoids the overhead of
+// crossing the interface for each one. Returns 0, or -1 if an access has an unknown kind, in which
+// case none are simulated, or if the core has no caches.
+//
+// # Safety
+//
+// `simulator` must be a live simulator, and `accesses` must point to `count` accesses, or may be
+// null if `count` is 0
+int32_t cachesim_simulate(struct cachesim_simulator *simulator,
+                          const struct cachesim_access *accesses,
+                          size_t count,
+                          uint32_t core);
+
+// Simulates a trace in the 40 byte record format, such as the contents of a trace file, as
+// accesses made by one core
+//
+// Returns 0, or -1 if the trace ends part way through a record or the core has no caches.
+//
+// # Safety
+//
+// `simulator` must be a live simulator, and `trace` must point to `length` bytes, or may be null
+// if `length` is 0
+int32_t cachesim_simulate_trace(struct cachesim_simulator *simulator,
+                                const uint8_t *trace,
+                                size_t length,
+                                uint32_t core);
+
+// Gets the number of caches, which are numbered from 0 in the order of the configuration
+//
+// # Safety
+//
+// `simulator` must be a live simulator
+size_t cachesim_cache_count(const struct cachesim_simulator *simulator);
+
+// Gets the name of a cache, or null if there is no cache with that index
+//
+// The name is owned by the simulator, and is valid until it is destroyed.
+//
+// # Safety
+//
+// `simulator` must be a live simulator
+const char *cachesim_cache_name(const struct cachesim_simulator *simulator, size_t cache);
+
+// Gets the statistics of a cache so far
+//
+// Returns 0, or -1 if there is no cache with that index.
+//
+// # Safety
+//
+// `simulator` must be a live simulator, and `stats` must point to writable statistics
+int32_t cachesim_get_cache_stats(const struct cachesim_simulator *simulator,
+                                 size_t cache,
+                                 struct cachesim_cache_stats *stats);
+
+// Gets the number of accesses so far which missed every cache and went to main memory
+//
+// # Safety
+//
+// `simulator` must be a live simulator
+uint64_t cachesim_main_memory_accesses(const struct cachesim_simulator *simulator);
+
+// Gets the results so far as JSON, in the format of the specification
+//
+// The string must be freed with `cachesim_free_string`. Returns null if it couldn't be serialised.
+//
+// # Safety
+//
+// `simulator` must be a live simulator
+char *cachesim_result_json(const struct cachesim_simulator *simulator);
+
+// Frees a string returned by the library
+//
+// # Safety
+//
+// `string` must be null or have been returned by a function documented as needing this, and not
+// yet freed
+void cachesim_free_string(char *string);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CACHESIM_H */
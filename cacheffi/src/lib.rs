@@ -0,0 +1,375 @@
+//! # CacheFFI
+//!
+//! A C interface to cachelib, so simulators written in C or C++ can use it as their memory-side
+//! model, feeding it accesses as they are made and reading the statistics back
+//!
+//! The header, `include/cachesim.h`, is generated from this file by cbindgen. Every function is
+//! prefixed with `cachesim_`, and [CACHESIM_API_VERSION] is increased whenever an existing function
+//! or struct changes, so code built against one header can check it is running against a
+//! compatible library.
+//!
+//! Functions which can fail return null or a negative number, and the reason can then be read with
+//! [cachesim_last_error] on the same thread.
+
+#![deny(unsafe_op_in_unsafe_fn, clippy::undocumented_unsafe_blocks)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use cachelib::config::{config_from_value, LayeredCacheConfig};
+use cachelib::layered::AccessKind;
+use cachelib::simulator::{Access, Simulator as CacheSimulator};
+
+/// The version of the interface described by this header
+pub const CACHESIM_API_VERSION: u32 = 1;
+
+/// The kind of an access which reads its bytes, which a zeroed access is
+pub const CACHESIM_READ: u8 = 0;
+
+/// The kind of an access which writes its bytes, simulated as a read unless the configuration
+/// simulates writes
+pub const CACHESIM_WRITE: u8 = 1;
+
+/// The kind of an instruction fetch, which takes the instruction cache of configurations with one
+pub const CACHESIM_FETCH: u8 = 2;
+
+/// The kind of an access which reads then writes the same bytes, such as an increment, which is
+/// two accesses to each line
+pub const CACHESIM_MODIFY: u8 = 3;
+
+/// A simulator for a configuration, which keeps the contents of its caches between calls
+///
+/// Only one thread may use a simulator at a time, but it may be moved between threads.
+pub struct Simulator {
+    simulator: CacheSimulator,
+    // Kept nul terminated for cachesim_cache_name
+    cache_names: Vec<CString>,
+}
+
+/// A single access, as made by the program being simulated
+#[repr(C)]
+pub struct CachesimAccess {
+    /// The address of the instruction making the access, or 0 if it isn't known
+    pub pc: u64,
+    pub address: u64,
+    /// The number of bytes accessed
+    pub size: u16,
+    /// One of `CACHESIM_READ`, `CACHESIM_WRITE`, `CACHESIM_FETCH` or `CACHESIM_MODIFY`
+    pub kind: u8,
+}
+
+/// The statistics of one cache
+#[repr(C)]
+pub struct CachesimCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Lines brought into the cache by its prefetcher
+    pub prefetches: u64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(error: String) {
+    // An interior nul would truncate the message rather than lose it
+    let error = CString::new(error.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+/// Records the error of a failed call and returns -1, for functions returning a status
+fn status(result: Result<(), String>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            set_error(error);
+            -1
+        }
+    }
+}
+
+/// Gets the kind of an access from its `kind` field
+fn access_kind(kind: u8) -> Result<AccessKind, String> {
+    match kind {
+        CACHESIM_READ => Ok(AccessKind::Read),
+        CACHESIM_WRITE => Ok(AccessKind::Write),
+        CACHESIM_FETCH => Ok(AccessKind::Fetch),
+        CACHESIM_MODIFY => Ok(AccessKind::Modify),
+        _ => Err(format!("{kind} isn't a kind of access, which must be CACHESIM_READ, CACHESIM_WRITE, CACHESIM_FETCH or CACHESIM_MODIFY")),
+    }
+}
+
+fn load_config(config_json: *const c_char) -> Result<LayeredCacheConfig, String> {
+    if config_json.is_null() {
+        return Err("The configuration is null".to_string());
+    }
+    // SAFETY: the configuration isn't null, and the caller passes a nul terminated string
+    let json = unsafe { CStr::from_ptr(config_json) }.to_str().map_err(|_| "The configuration isn't UTF-8".to_string())?;
+    let document = serde_json::from_str(json).map_err(|e| format!("Couldn't parse the config: {e}"))?;
+    config_from_value(document).map(|(config, _)| config)
+}
+
+/// Gets the version of the interface the library implements, which should equal the
+/// `CACHESIM_API_VERSION` of the header the caller was compiled with
+#[no_mangle]
+pub extern "C" fn cachesim_api_version() -> u32 {
+    CACHESIM_API_VERSION
+}
+
+/// Gets the error message of the last call on this thread which failed
+///
+/// The message is owned by the library, and is valid until the next failing call on this thread.
+/// It is empty if no call has failed.
+#[no_mangle]
+pub extern "C" fn cachesim_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Creates a simulator with empty caches from a configuration, in the same JSON format as
+/// configuration files
+///
+/// Configurations can't use `include` or variables, load them with the command line's
+/// `--dump-effective-config` first. Returns null if the configuration is invalid.
+///
+/// # Safety
+///
+/// `config_json` must be null or a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_create(config_json: *const c_char) -> *mut Simulator {
+    match load_config(config_json) {
+        Ok(config) => {
+            let simulator = CacheSimulator::new(&config);
+            let cache_names = simulator.get_result().caches().iter()
+                .map(|cache| CString::new(cache.name().replace('\0', " ")).unwrap())
+                .collect();
+            Box::into_raw(Box::new(Simulator { simulator, cache_names }))
+        }
+        Err(error) => {
+            set_error(error);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Destroys a simulator, freeing its caches
+///
+/// # Safety
+///
+/// `simulator` must be null or have been returned by `cachesim_create` and not yet destroyed
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_destroy(simulator: *mut Simulator) {
+    if !simulator.is_null() {
+        // SAFETY: the simulator was boxed by cachesim_create, and the caller doesn't use it again
+        drop(unsafe { Box::from_raw(simulator) });
+    }
+}
+
+/// Simulates a batch of accesses made by one core, in order
+///
+/// `core` is the core making the accesses, as used in the `shared_by` lists of the configuration,
+/// or 0 for configurations without them. Batching many accesses in each call avoids the overhead of
+/// crossing the interface for each one. Returns 0, or -1 if an access has an unknown kind, in which
+/// case none are simulated, or if the core has no caches.
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator, and `accesses` must point to `count` accesses, or may be
+/// null if `count` is 0
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_simulate(simulator: *mut Simulator, accesses: *const CachesimAccess, count: usize, core: u32) -> i32 {
+    // SAFETY: the caller passes a live simulator, which nothing else uses during the call
+    let simulator = unsafe { &mut (*simulator).simulator };
+    // SAFETY: the caller passes count accesses, which aren't read when there are none
+    let accesses = if count == 0 { &[] } else { unsafe { std::slice::from_raw_parts(accesses, count) } };
+    if let Some((index, Err(error))) = accesses.iter().map(|access| access_kind(access.kind)).enumerate().find(|(_, kind)| kind.is_err()) {
+        return status(Err(format!("Access {index}: {error}")));
+    }
+    let accesses = accesses.iter().map(|access| Access {
+        pc: access.pc,
+        address: access.address,
+        size: access.size,
+        kind: access_kind(access.kind).unwrap(),
+    });
+    status(simulator.simulate_accesses(accesses, core).map(|_| ()))
+}
+
+/// Simulates a trace in the 40 byte record format, such as the contents of a trace file, as
+/// accesses made by one core
+///
+/// Returns 0, or -1 if the trace ends part way through a record or the core has no caches.
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator, and `trace` must point to `length` bytes, or may be null
+/// if `length` is 0
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_simulate_trace(simulator: *mut Simulator, trace: *const u8, length: usize, core: u32) -> i32 {
+    // SAFETY: the caller passes a live simulator, which nothing else uses during the call
+    let simulator = unsafe { &mut (*simulator).simulator };
+    if !length.is_multiple_of(40) {
+        return status(Err("The trace ends part way through a record".to_string()));
+    }
+    // SAFETY: the caller passes length bytes, which aren't read when there are none
+    let trace = if length == 0 { &[] } else { unsafe { std::slice::from_raw_parts(trace, length) } };
+    status(simulator.simulate_core(trace, core).map(|_| ()))
+}
+
+/// Gets the number of caches, which are numbered from 0 in the order of the configuration
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_cache_count(simulator: *const Simulator) -> usize {
+    // SAFETY: the caller passes a live simulator
+    let simulator = unsafe { &*simulator };
+    simulator.simulator.get_result().caches().len()
+}
+
+/// Gets the name of a cache, or null if there is no cache with that index
+///
+/// The name is owned by the simulator, and is valid until it is destroyed.
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_cache_name(simulator: *const Simulator, cache: usize) -> *const c_char {
+    // SAFETY: the caller passes a live simulator
+    let simulator = unsafe { &*simulator };
+    match simulator.cache_names.get(cache) {
+        Some(name) => name.as_ptr(),
+        None => {
+            set_error(format!("There is no cache {cache}"));
+            ptr::null()
+        }
+    }
+}
+
+/// Gets the statistics of a cache so far
+///
+/// Returns 0, or -1 if there is no cache with that index.
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator, and `stats` must point to writable statistics
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_get_cache_stats(simulator: *const Simulator, cache: usize, stats: *mut CachesimCacheStats) -> i32 {
+    // SAFETY: the caller passes a live simulator
+    let simulator = unsafe { &*simulator };
+    status(match simulator.simulator.get_result().caches().get(cache) {
+        Some(result) => {
+            // SAFETY: the caller passes writable statistics
+            unsafe { *stats = CachesimCacheStats { hits: result.hits(), misses: result.misses(), prefetches: result.prefetches() } };
+            Ok(())
+        }
+        None => Err(format!("There is no cache {cache}")),
+    })
+}
+
+/// Gets the number of accesses so far which missed every cache and went to main memory
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_main_memory_accesses(simulator: *const Simulator) -> u64 {
+    // SAFETY: the caller passes a live simulator
+    let simulator = unsafe { &*simulator };
+    simulator.simulator.get_result().main_memory_accesses()
+}
+
+/// Gets the results so far as JSON, in the format of the specification
+///
+/// The string must be freed with `cachesim_free_string`. Returns null if it couldn't be serialised.
+///
+/// # Safety
+///
+/// `simulator` must be a live simulator
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_result_json(simulator: *const Simulator) -> *mut c_char {
+    // SAFETY: the caller passes a live simulator
+    let simulator = unsafe { &*simulator };
+    match serde_json::to_string(simulator.simulator.get_result()) {
+        Ok(json) => CString::new(json).unwrap().into_raw(),
+        Err(e) => {
+            set_error(format!("Couldn't serialise the output {e}"));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees a string returned by the library
+///
+/// # Safety
+///
+/// `string` must be null or have been returned by a function documented as needing this, and not
+/// yet freed
+#[no_mangle]
+pub unsafe extern "C" fn cachesim_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        // SAFETY: the string was returned by CString::into_raw, and the caller doesn't use it again
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        // SAFETY: the last error is a nul terminated string owned by the library
+        unsafe { CStr::from_ptr(cachesim_last_error()) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn null_pointers_are_rejected_or_ignored() {
+        // SAFETY: each function is documented as accepting these nulls
+        unsafe {
+            assert!(cachesim_create(ptr::null()).is_null());
+            assert_eq!(last_error(), "The configuration is null");
+            cachesim_destroy(ptr::null_mut());
+            cachesim_free_string(ptr::null_mut());
+        }
+        let config = CString::new(r#"{"caches": [{"name": "L1", "size": 1024}]}"#).unwrap();
+        // SAFETY: the configuration is nul terminated, the simulator is destroyed once, and the
+        // accesses and trace may be null as there are none
+        unsafe {
+            let simulator = cachesim_create(config.as_ptr());
+            assert!(!simulator.is_null());
+            assert_eq!(cachesim_simulate(simulator, ptr::null(), 0, 0), 0);
+            assert_eq!(cachesim_simulate_trace(simulator, ptr::null(), 0, 0), 0);
+            assert!(cachesim_cache_name(simulator, 1).is_null());
+            assert_eq!(last_error(), "There is no cache 1");
+            cachesim_destroy(simulator);
+        }
+    }
+
+    #[test]
+    fn a_simulator_is_created_run_and_freed() {
+        let config = CString::new(r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 4096}]}"#).unwrap();
+        let access = |address, kind| CachesimAccess { pc: 0, address, size: 4, kind };
+        let accesses = [access(0, CACHESIM_READ), access(0, CACHESIM_READ), access(64, CACHESIM_WRITE)];
+        // SAFETY: the configuration is nul terminated, the accesses and statistics are valid, and
+        // the simulator and result are each freed once, after their last use
+        unsafe {
+            let simulator = cachesim_create(config.as_ptr());
+            assert!(!simulator.is_null());
+            assert_eq!(cachesim_simulate(simulator, accesses.as_ptr(), accesses.len(), 0), 0);
+            assert_eq!(cachesim_simulate(simulator, [access(0, 9)].as_ptr(), 1, 0), -1);
+            assert!(last_error().contains("9 isn't a kind of access"));
+            assert_eq!(cachesim_cache_count(simulator), 2);
+            assert_eq!(CStr::from_ptr(cachesim_cache_name(simulator, 1)).to_str(), Ok("L2"));
+            let mut stats = CachesimCacheStats { hits: 0, misses: 0, prefetches: 0 };
+            assert_eq!(cachesim_get_cache_stats(simulator, 0, &mut stats), 0);
+            assert_eq!((stats.hits, stats.misses), (1, 2));
+            assert_eq!(cachesim_get_cache_stats(simulator, 2, &mut stats), -1);
+            assert_eq!(cachesim_main_memory_accesses(simulator), 2);
+            let json = cachesim_result_json(simulator);
+            let result: serde_json::Value = serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert_eq!(result["main_memory_accesses"], 2);
+            cachesim_free_string(json);
+            cachesim_destroy(simulator);
+        }
+    }
+}
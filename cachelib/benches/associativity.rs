@@ -0,0 +1,109 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use cachelib::cache::{Cache, CacheTrait};
+use cachelib::config::arbitrary::SplitMix64;
+use cachelib::replacement_policies::{CompactLeastRecentlyUsed, LeastFrequentlyUsed, LeastRecentlyUsed, ReplacementPolicy, RoundRobin};
+
+const SIZE: u64 = 32 * 1024;
+const LINE_SIZE: u64 = 64;
+
+/// Reads addresses spread over twice the size of the cache, so a good share of reads miss
+fn addresses() -> Vec<u64> {
+    let mut rng = SplitMix64::new(2419);
+    (0..1 << 20).map(|_| 0x7ff0_0000_0000 + rng.below(2 * SIZE)).collect()
+}
+
+fn read_all<C: CacheTrait>(cache: &mut C, addresses: &[u64]) -> u64 {
+    addresses.iter().map(|a| cache.read_and_update_line(*a) as u64).sum()
+}
+
+/// Compares caches with a fixed number of ways, whose loops over a set are unrolled, against the
+/// same caches with the associativity read at runtime. GenericCache only has fixed variants where
+/// these show a speedup
+fn bench_ways<const WAYS: u64, R: ReplacementPolicy>(c: &mut Criterion, policy: &str, new_policy: impl Fn(u64, u64) -> R) {
+    let mut group = c.benchmark_group(format!("{WAYS} way {policy}"));
+    let addresses = addresses();
+    let (lines, sets) = (SIZE / LINE_SIZE, SIZE / LINE_SIZE / WAYS);
+    group.bench_with_input(BenchmarkId::new("fixed", WAYS), &addresses, |bench, addresses| {
+        bench.iter(|| read_all(&mut Cache::<R, u64, WAYS>::new(SIZE, LINE_SIZE, sets, new_policy(lines, sets)), addresses));
+    });
+    group.bench_with_input(BenchmarkId::new("runtime", WAYS), &addresses, |bench, addresses| {
+        bench.iter(|| read_all(&mut Cache::<R>::new(SIZE, LINE_SIZE, sets, new_policy(lines, sets)), addresses));
+    });
+    group.finish();
+}
+
+/// Compares the 64-bit timestamps of LeastRecentlyUsed with the packed recency order of
+/// CompactLeastRecentlyUsed, on a cache as large as a last level cache, whose policy state doesn't
+/// fit in the processor's own caches
+fn bench_lru_state<const WAYS: u64>(c: &mut Criterion) {
+    const LLC_SIZE: u64 = 8 * 1024 * 1024;
+    let mut group = c.benchmark_group(format!("{WAYS} way lru state"));
+    let mut rng = SplitMix64::new(2441);
+    let addresses: Vec<u64> = (0..1 << 20).map(|_| 0x7ff0_0000_0000 + rng.below(2 * LLC_SIZE)).collect();
+    let (lines, sets) = (LLC_SIZE / LINE_SIZE, LLC_SIZE / LINE_SIZE / WAYS);
+    let mut timestamps = Cache::<LeastRecentlyUsed, u64, WAYS>::new(LLC_SIZE, LINE_SIZE, sets, LeastRecentlyUsed::new(lines));
+    group.bench_with_input(BenchmarkId::new("timestamps", WAYS), &addresses, |bench, addresses| {
+        bench.iter(|| read_all(&mut timestamps, addresses));
+    });
+    let mut compact = Cache::<CompactLeastRecentlyUsed, u64, WAYS>::new(LLC_SIZE, LINE_SIZE, sets, CompactLeastRecentlyUsed::new(lines, WAYS));
+    group.bench_with_input(BenchmarkId::new("compact", WAYS), &addresses, |bench, addresses| {
+        bench.iter(|| read_all(&mut compact, addresses));
+    });
+    group.finish();
+}
+
+/// Compares finding lines through the hash table of tags with scanning every line, for fully
+/// associative caches, where the scan is the slowest part of a simulation
+fn bench_tag_index<R: ReplacementPolicy>(c: &mut Criterion, policy: &str, new_policy: impl Fn(u64) -> R) {
+    let mut group = c.benchmark_group(format!("full {policy} tag index"));
+    let addresses = addresses();
+    let lines = SIZE / LINE_SIZE;
+    for indexed in [false, true] {
+        let name = if indexed { "indexed" } else { "scan" };
+        group.bench_with_input(BenchmarkId::new(name, lines), &addresses, |bench, addresses| {
+            bench.iter(|| read_all(&mut Cache::<R>::new(SIZE, LINE_SIZE, 1, new_policy(lines)).with_tag_index(indexed), addresses));
+        });
+    }
+    group.finish();
+}
+
+/// Compares searching sets with SIMD instructions against comparing a way at a time, with round
+/// robin replacement so the search is most of the work, on a cache as large as a last level cache
+fn bench_simd_scan<const WAYS: u64>(c: &mut Criterion) {
+    const LLC_SIZE: u64 = 8 * 1024 * 1024;
+    let mut group = c.benchmark_group(format!("{WAYS} way simd scan"));
+    let mut rng = SplitMix64::new(2450);
+    let addresses: Vec<u64> = (0..1 << 20).map(|_| 0x7ff0_0000_0000 + rng.below(2 * LLC_SIZE)).collect();
+    let sets = LLC_SIZE / LINE_SIZE / WAYS;
+    for simd in [false, true] {
+        let name = if simd { "simd" } else { "scalar" };
+        let mut cache = Cache::<RoundRobin, u64, WAYS>::new(LLC_SIZE, LINE_SIZE, sets, RoundRobin::new(sets)).with_simd_scan(simd);
+        group.bench_with_input(BenchmarkId::new(name, WAYS), &addresses, |bench, addresses| {
+            bench.iter(|| read_all(&mut cache, addresses));
+        });
+    }
+    group.finish();
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    bench_ways::<2, _>(c, "lru", |lines, _| LeastRecentlyUsed::new(lines));
+    bench_ways::<4, _>(c, "lru", |lines, _| LeastRecentlyUsed::new(lines));
+    bench_ways::<8, _>(c, "lru", |lines, _| LeastRecentlyUsed::new(lines));
+    bench_ways::<4, _>(c, "lfu", |lines, _| LeastFrequentlyUsed::new(lines));
+    bench_ways::<8, _>(c, "lfu", |lines, _| LeastFrequentlyUsed::new(lines));
+    bench_ways::<8, _>(c, "rr", |_, sets| RoundRobin::new(sets));
+    bench_lru_state::<4>(c);
+    bench_lru_state::<8>(c);
+    bench_lru_state::<16>(c);
+    bench_tag_index(c, "lru", LeastRecentlyUsed::new);
+    bench_tag_index(c, "lfu", LeastFrequentlyUsed::new);
+    bench_simd_scan::<8>(c);
+    bench_simd_scan::<16>(c);
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().significance_level(0.1).sample_size(10);
+    targets = criterion_benchmark
+);
+criterion_main!(benches);
@@ -1,7 +1,7 @@
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::Read;
 use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
-use cachelib::config::LayeredCacheConfig;
+use cachelib::config::load_layered_config;
 use cachelib::simulator::Simulator;
 use cachelib::util::get_configs;
 
@@ -13,9 +13,8 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         .unwrap()
         .iter()
         .for_each(|case| {
-            let config_file = File::open(case.config.clone()).unwrap();
             // Ignoring expected output
-            let config: LayeredCacheConfig = serde_json::from_reader(BufReader::new(config_file)).unwrap();
+            let config = load_layered_config(&[&case.config]).unwrap();
             let mut trace_file = File::open(case.trace.clone()).unwrap();
             let mut buf = Vec::new();
             // For the purposes of this we aren't interested in IO effects, and the given examples,
@@ -0,0 +1,63 @@
+use core::fmt::Debug;
+use core::ops::{BitAnd, BitOr, Not, Shl, Shr};
+
+/// An unsigned integer type which can hold a memory address
+///
+/// A single [Cache](crate::cache::Cache) is generic over its address type, so narrow embedded
+/// addresses or 128-bit capability addresses can be looked up in it as well as the usual 64-bit
+/// ones. Only the bit operations used to split an address into a set and tag are needed, so the
+/// 64-bit case compiles to the same code as if `u64` were used directly. Everything built on caches,
+/// from the hierarchy to the [Simulator](crate::simulator::Simulator) and the trace format, uses
+/// `u64`
+pub trait Address: Copy + Eq + Debug + Send + Sync + 'static
+    + BitAnd<Output = Self> + BitOr<Output = Self> + Not<Output = Self>
+    + Shl<u32, Output = Self> + Shr<u32, Output = Self>
+{
+    /// The width of the address in bits
+    const BITS: u32;
+
+    /// The zero address, which the cache uses to mark empty lines
+    const ZERO: Self;
+
+    /// Converts a set index or mask into an address, which must fit in the address type
+    fn from_u64(value: u64) -> Self;
+
+    /// Converts the low bits of an address to a u64, such as after shifting out a set index
+    fn low_u64(self) -> u64;
+
+    /// Parses an address from hexadecimal digits, without a `0x` prefix
+    ///
+    /// # Arguments
+    ///
+    /// * `hex`: The digits, at most [BITS](Address::BITS) / 4 of them
+    ///
+    /// returns: Option<Self>, None if there are no digits, too many, or a non-hexadecimal character
+    fn parse_hex(hex: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_address {
+    ($($t:ty),*) => {$(
+        impl Address for $t {
+            const BITS: u32 = <$t>::BITS;
+            const ZERO: Self = 0;
+
+            fn from_u64(value: u64) -> Self {
+                value as $t
+            }
+
+            fn low_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn parse_hex(hex: &[u8]) -> Option<Self> {
+                if hex.is_empty() || hex.len() > (Self::BITS / 4) as usize {
+                    return None;
+                }
+                // from_str_radix would also accept a leading +
+                hex.iter().try_fold(0, |address: $t, digit| Some(address << 4 | (*digit as char).to_digit(16)? as $t))
+            }
+        }
+    )*};
+}
+
+impl_address!(u16, u32, u64, u128);
@@ -0,0 +1,24 @@
+/// Compares the misses of caches of one capacity at several associativities
+mod associativity;
+/// Characterises the spatial and temporal locality of a trace
+mod locality;
+/// Counts the misses of Belady's optimal replacement on the reads of each cache of a simulation
+mod optimal;
+/// Moves the regions of a trace's address space to random places, as address space layout
+/// randomisation does
+mod placement;
+/// Reorders the records of a trace within bounded windows, to see how sensitive a result is to
+/// the interleaving of threads
+mod reorder;
+/// Simulates LRU caches of every size and associativity in a single pass
+mod sweep_lru;
+/// Estimates the working set of a trace over windows of accesses
+mod working_set;
+
+pub use associativity::{AssociativityPoint, AssociativityReport, AssociativitySweep, DEFAULT_ASSOCIATIVITIES};
+pub use optimal::{CacheGap, OptimalCache, OptimalGap, SetGap, DEFAULT_GAP_SETS};
+pub use placement::{PlacementRandomiser, DEFAULT_PLACEMENT_REGION_SIZE};
+pub use reorder::{ReorderReport, ReorderStrategy, TraceReorderer, DEFAULT_REORDER_WINDOW};
+pub use sweep_lru::{SweepLru, SweepLruPoint};
+pub use locality::{LocalityAnalysis, LocalityReport, ReusePoint, StrideBucket, DEFAULT_LOCALITY_WINDOW, DEFAULT_MAX_REUSE_DISTANCE};
+pub use working_set::{WorkingSetAnalysis, WorkingSetPoint, WorkingSetReport, DEFAULT_WORKING_SET_WINDOW};
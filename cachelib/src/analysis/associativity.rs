@@ -0,0 +1,277 @@
+use alloc::collections::BTreeMap;
+use serde::Serialize;
+use crate::config::{InclusivityConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+use crate::layered::AccessKind;
+use crate::prelude::*;
+use crate::simulator::Access;
+use crate::topology::Topology;
+
+/// The associativities compared, unless set
+pub const DEFAULT_ASSOCIATIVITIES: [u64; 5] = [1, 2, 4, 8, 16];
+
+/// The hits and misses of a cache of one associativity, out of caches of the same capacity
+#[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq)]
+pub struct AssociativityPoint {
+    pub ways: u64,
+    pub sets: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// The misses fewer than the associativity before it, which are all conflict misses, as the
+    /// capacity is the same. Negative if it misses more, and not known for the first
+    pub misses_removed: Option<i64>,
+    /// The misses more than a fully associative cache of the same capacity, the conflict misses
+    /// left to remove
+    pub conflict_misses: i64,
+}
+
+/// How the misses of a cache of a capacity change with its associativity, see
+/// [AssociativitySweep]
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AssociativityReport {
+    /// The size of every cache in bytes
+    pub size: u64,
+    pub line_size: u64,
+    /// The caches with each associativity, in increasing order of ways
+    pub points: Vec<AssociativityPoint>,
+    /// The fully associative cache the conflict misses are counted against, which also removes
+    /// itself from the point before it
+    pub fully_associative: AssociativityPoint,
+}
+
+impl AssociativityReport {
+    /// Builds a report from the hits and misses of caches of the same capacity
+    ///
+    /// # Arguments
+    ///
+    /// * `size`: The size of every cache in bytes
+    /// * `line_size`: The line size of every cache
+    /// * `counts`: The ways, hits and misses of each cache, in increasing order of ways
+    /// * `fully_associative`: The hits and misses of the fully associative cache
+    ///
+    /// returns: AssociativityReport
+    pub fn new(size: u64, line_size: u64, counts: &[(u64, u64, u64)], fully_associative: (u64, u64)) -> Self {
+        let lines = size / line_size;
+        let full_misses = fully_associative.1;
+        let point = |ways: u64, hits: u64, misses: u64, before: Option<u64>| AssociativityPoint {
+            ways,
+            sets: lines / ways,
+            hits,
+            misses,
+            misses_removed: before.map(|before| before as i64 - misses as i64),
+            conflict_misses: misses as i64 - full_misses as i64,
+        };
+        let mut before = None;
+        let points = counts.iter().map(|&(ways, hits, misses)| {
+            let point = point(ways, hits, misses, before);
+            before = Some(misses);
+            point
+        }).collect();
+        Self {
+            size,
+            line_size,
+            points,
+            fully_associative: point(lines, fully_associative.0, full_misses, before),
+        }
+    }
+}
+
+/// The LRU stacks of every set of a cache of one associativity
+struct Associativity {
+    ways: usize,
+    // The lines of each set, most recently used first, in `ways` slots per set
+    stacks: Vec<u64>,
+    lengths: Vec<u32>,
+    hits: u64,
+    // Whether the set of the first line has held nothing else, see SweepLru
+    first_line_alone: bool,
+}
+
+/// A fully associative LRU cache, found by the age of its lines rather than a stack, as it can have
+/// many more ways than the others
+struct FullyAssociative {
+    lines: u64,
+    last_used: BTreeMap<u64, u64>,
+    by_age: BTreeMap<u64, u64>,
+    hits: u64,
+    first_line_alone: bool,
+}
+
+/// Simulates LRU caches of one capacity at several associativities, and fully associative, in a
+/// single pass over a trace
+///
+/// Each line read is found in every cache at once, with the stack of lines of its set in each kept
+/// in order of use, so the cost of an access is the sum of the associativities rather than a
+/// simulation of each cache. The fully associative cache orders its lines by age instead, so costs
+/// the logarithm of its size.
+///
+/// Since every cache holds the same number of lines, the misses removed by each step up in
+/// associativity are conflict misses, and those of the fully associative cache are the compulsory
+/// and capacity misses, so the misses beyond it are the conflict misses left.
+///
+/// The counts are exactly those of a single level [Simulator](crate::simulator::Simulator) using
+/// LRU, including its treatment of the first line it reads, as for
+/// [SweepLru](crate::analysis::SweepLru).
+pub struct AssociativitySweep {
+    size: u64,
+    line_bits: u32,
+    associativities: Vec<Associativity>,
+    fully_associative: FullyAssociative,
+    first_line: Option<u64>,
+    accesses: u64,
+}
+
+impl AssociativitySweep {
+    /// Creates a sweep with empty caches
+    ///
+    /// # Arguments
+    ///
+    /// * `line_size`: The line size of every cache, a power of two
+    /// * `size`: The size of every cache in bytes, a power of two number of lines
+    /// * `ways`: The associativities to simulate, each a power of two up to the number of lines
+    ///
+    /// returns: Result<AssociativitySweep, String>
+    pub fn new(line_size: u64, size: u64, ways: &[u64]) -> Result<Self, String> {
+        if !line_size.is_power_of_two() {
+            return Err(format!("The line size must be a power of two, not {line_size}"));
+        }
+        let lines = size / line_size;
+        if !size.is_multiple_of(line_size) || !lines.is_power_of_two() {
+            return Err(format!("A cache of {size} bytes must be a power of two number of {line_size}-byte lines"));
+        }
+        let mut ways = ways.to_vec();
+        ways.sort_unstable();
+        ways.dedup();
+        let associativities = ways.iter().map(|&ways| {
+            if !ways.is_power_of_two() || ways > lines {
+                return Err(format!("A cache of {lines} lines can't have {ways} ways, which must be a power of two up to the number of lines"));
+            }
+            let sets = (lines / ways) as usize;
+            Ok(Associativity { ways: ways as usize, stacks: vec![0; lines as usize], lengths: vec![0; sets], hits: 0, first_line_alone: true })
+        }).collect::<Result<_, String>>()?;
+        Ok(Self {
+            size,
+            line_bits: line_size.trailing_zeros(),
+            associativities,
+            fully_associative: FullyAssociative { lines, last_used: BTreeMap::new(), by_age: BTreeMap::new(), hits: 0, first_line_alone: true },
+            first_line: None,
+            accesses: 0,
+        })
+    }
+
+    /// Simulates a read of the line holding an address in every cache
+    pub fn access(&mut self, address: u64) {
+        let line = address >> self.line_bits;
+        let first_line = *self.first_line.get_or_insert(line);
+        let now = self.accesses;
+        self.accesses += 1;
+        for associativity in &mut self.associativities {
+            let ways = associativity.ways;
+            let set = (line & (associativity.lengths.len() as u64 - 1)) as usize;
+            let stack = &mut associativity.stacks[set * ways..(set + 1) * ways];
+            let length = associativity.lengths[set] as usize;
+            if let Some(depth) = stack[..length].iter().position(|&l| l == line) {
+                associativity.hits += 1;
+                stack[..=depth].rotate_right(1);
+                if line == first_line {
+                    associativity.first_line_alone = false;
+                }
+                continue;
+            }
+            // The first line is replaced by the next line of its set, as in SweepLru
+            let length = if associativity.first_line_alone && length == 1 && stack[0] == first_line && line != first_line {
+                associativity.first_line_alone = false;
+                0
+            } else {
+                length
+            };
+            let length = (length + 1).min(ways);
+            stack[..length].rotate_right(1);
+            stack[0] = line;
+            associativity.lengths[set] = length as u32;
+        }
+        let full = &mut self.fully_associative;
+        if let Some(last_used) = full.last_used.insert(line, now) {
+            full.hits += 1;
+            full.by_age.remove(&last_used);
+            if line == first_line {
+                full.first_line_alone = false;
+            }
+        } else if full.first_line_alone && full.last_used.len() == 2 && line != first_line {
+            full.first_line_alone = false;
+            full.last_used.remove(&first_line);
+            full.by_age.remove(&0);
+        } else if full.last_used.len() as u64 > full.lines {
+            let (_, oldest) = full.by_age.pop_first().unwrap();
+            full.last_used.remove(&oldest);
+        }
+        full.by_age.insert(now, line);
+    }
+
+    /// Simulates accesses in every cache, each of which reads every line it touches
+    pub fn simulate_accesses(&mut self, accesses: impl IntoIterator<Item = Access>) {
+        for access in accesses {
+            let line_size = 1 << self.line_bits;
+            let mut line = access.address & !(line_size - 1);
+            while line < access.address + access.size as u64 {
+                self.access(line);
+                line += line_size;
+            }
+        }
+    }
+
+    /// Simulates a trace in the format of the specification in every cache
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    ///
+    /// returns: Result<(), String>, an error if the trace ends part way through a record
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if !bytes.len().is_multiple_of(40) {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        self.simulate_accesses(bytes.chunks_exact(40).map(|record| Access::from_record(record.try_into().unwrap())));
+        Ok(())
+    }
+
+    /// Whether a cache of a configuration has the hits and misses of the cache simulated alone, so
+    /// a sweep gives those of the configuration with the cache at each associativity
+    ///
+    /// The cache must be an LRU cache without a prefetcher, slices or context switches, which is
+    /// the first level of the first core, with nothing below it back-invalidating its lines. As a
+    /// sweep reads every line once, the configuration can't simulate writes or have instruction
+    /// caches, and the trace can't have instruction fetches or modifies.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration
+    /// * `index`: The index of the cache in the configuration
+    /// * `bytes`: The trace, in the format of the specification
+    ///
+    /// returns: Result<bool, String>, an error if the configuration's caches aren't linked properly
+    pub fn matches_simulation(config: &LayeredCacheConfig, index: usize, bytes: &[u8]) -> Result<bool, String> {
+        let topology = Topology::from_config(config)?;
+        let Some(path) = topology.cores().next().and_then(|core| topology.path(core)) else {
+            return Ok(false);
+        };
+        let cache = &config.caches[index];
+        Ok(path[0] == index
+            && cache.replacement_policy == ReplacementPolicyConfig::LeastRecentlyUsed
+            && cache.prefetcher.is_none()
+            && cache.slices.is_none()
+            && config.context_switches.is_none()
+            && !config.simulates_writes()
+            && config.caches.iter().all(|cache| !cache.instructions)
+            && path.iter().all(|&cache| config.caches[cache].inclusivity == InclusivityConfig::Nine)
+            && bytes.chunks_exact(40).all(|record| !matches!(Access::from_record(record.try_into().unwrap()).kind, AccessKind::Fetch | AccessKind::Modify)))
+    }
+
+    /// Gets the hits and misses of every cache so far
+    pub fn report(&self) -> AssociativityReport {
+        let counts: Vec<(u64, u64, u64)> = self.associativities.iter()
+            .map(|associativity| (associativity.ways as u64, associativity.hits, self.accesses - associativity.hits))
+            .collect();
+        let full_hits = self.fully_associative.hits;
+        AssociativityReport::new(self.size, 1 << self.line_bits, &counts, (full_hits, self.accesses - full_hits))
+    }
+}
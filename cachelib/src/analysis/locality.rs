@@ -0,0 +1,189 @@
+use serde::Serialize;
+use crate::analysis::SweepLru;
+use crate::prelude::*;
+use crate::simulator::Access;
+
+/// The number of accesses in each window of the spatial locality score, unless set
+pub const DEFAULT_LOCALITY_WINDOW: u64 = 100_000;
+
+/// The most distinct lines a reuse is looked for behind, unless set
+pub const DEFAULT_MAX_REUSE_DISTANCE: u64 = 4096;
+
+/// The accesses whose stride from the access before fell in a range
+#[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq)]
+pub struct StrideBucket {
+    /// The smallest stride in the bucket, in bytes
+    pub min: i64,
+    /// The largest stride in the bucket, in bytes
+    pub max: i64,
+    pub count: u64,
+}
+
+/// The fraction of accesses to lines which had been used within the last `lines` distinct lines
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct ReusePoint {
+    pub lines: u64,
+    pub fraction: f64,
+}
+
+/// The locality metrics of a trace, see [LocalityAnalysis]
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct LocalityReport {
+    pub accesses: u64,
+    pub line_size: u64,
+    /// The strides between consecutive accesses, by powers of two of their magnitude, from the most
+    /// negative, leaving out empty buckets
+    pub strides: Vec<StrideBucket>,
+    /// The fraction of all accesses within a line of the access before
+    pub spatial_score: f64,
+    /// The spatial score of each window of accesses, in order, the last of which may be short
+    pub spatial_windows: Vec<f64>,
+    /// The cumulative distribution of reuse distances, at each power of two number of lines up to
+    /// the largest looked for
+    pub reuse: Vec<ReusePoint>,
+}
+
+/// Characterises the locality of a trace in a single pass, independently of any cache
+///
+/// * Spatial locality is measured from the stride between the addresses of consecutive accesses,
+///   giving a histogram of strides, and a score, the fraction of accesses within a line of the one
+///   before, over the whole trace and over each window of accesses, so phases stand out.
+/// * Temporal locality is measured by the reuse distance of each line read, the number of distinct
+///   lines read since it was last read. The distribution is found with the stacks of a [SweepLru]
+///   with a single set, so the fraction at each number of lines is the hit ratio of a fully
+///   associative LRU cache of that many lines.
+pub struct LocalityAnalysis {
+    line_size: u64,
+    window: u64,
+    accesses: u64,
+    last_address: Option<u64>,
+    // The strides of 0, and the positive and negative strides with each bit length
+    zero_strides: u64,
+    positive_strides: [u64; 64],
+    negative_strides: [u64; 64],
+    near: u64,
+    window_accesses: u64,
+    window_near: u64,
+    windows: Vec<f64>,
+    max_distance: u64,
+    reuse: SweepLru,
+}
+
+impl LocalityAnalysis {
+    /// Creates an analysis which has seen no accesses
+    ///
+    /// # Arguments
+    ///
+    /// * `line_size`: The line size used for the spatial score and reuse distances, a power of two
+    /// * `window`: The number of accesses in each window of the spatial score
+    /// * `max_distance`: The most distinct lines a reuse is looked for behind, with reuses further
+    ///   back counted as never reused. The cost of each access grows with it
+    ///
+    /// returns: Result<LocalityAnalysis, String>
+    pub fn new(line_size: u64, window: u64, max_distance: u64) -> Result<Self, String> {
+        if max_distance == 0 {
+            return Err("Reuses must be looked for behind at least 1 line".to_string());
+        }
+        if window == 0 {
+            return Err("The windows of the spatial score must be at least 1 access".to_string());
+        }
+        Ok(Self {
+            line_size,
+            window,
+            accesses: 0,
+            last_address: None,
+            zero_strides: 0,
+            positive_strides: [0; 64],
+            negative_strides: [0; 64],
+            near: 0,
+            window_accesses: 0,
+            window_near: 0,
+            windows: Vec::new(),
+            max_distance,
+            reuse: SweepLru::new(line_size, 1, max_distance)?,
+        })
+    }
+
+    /// Adds an access, reading every line it touches
+    pub fn access(&mut self, access: Access) {
+        if let Some(last_address) = self.last_address {
+            let stride = access.address.wrapping_sub(last_address) as i64;
+            let magnitude = stride.unsigned_abs();
+            match stride {
+                0 => self.zero_strides += 1,
+                1.. => self.positive_strides[magnitude.ilog2() as usize] += 1,
+                _ => self.negative_strides[magnitude.ilog2() as usize] += 1,
+            }
+            if magnitude < self.line_size {
+                self.near += 1;
+                self.window_near += 1;
+            }
+        }
+        self.last_address = Some(access.address);
+        self.accesses += 1;
+        self.window_accesses += 1;
+        if self.window_accesses == self.window {
+            self.finish_window();
+        }
+        self.reuse.simulate_accesses([access]);
+    }
+
+    fn finish_window(&mut self) {
+        self.windows.push(self.window_near as f64 / self.window_accesses as f64);
+        self.window_accesses = 0;
+        self.window_near = 0;
+    }
+
+    /// Adds a trace in the format of the specification
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    ///
+    /// returns: Result<(), String>, an error if the trace ends part way through a record
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if !bytes.len().is_multiple_of(40) {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        for record in bytes.chunks_exact(40) {
+            self.access(Access::from_record(record.try_into().unwrap()));
+        }
+        Ok(())
+    }
+
+    /// Gets the metrics of the accesses so far
+    pub fn report(&self) -> LocalityReport {
+        // The magnitudes with a bit length cover 2^bits up to 2^(bits + 1) - 1, limited to the
+        // strides which fit
+        let clamp = |stride: i128| stride.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+        let negative = self.negative_strides.iter().enumerate().rev().map(|(bits, &count)| {
+            StrideBucket { min: clamp(-((2 << bits) - 1)), max: clamp(-(1 << bits)), count }
+        });
+        let positive = self.positive_strides.iter().enumerate().map(|(bits, &count)| {
+            StrideBucket { min: clamp(1 << bits), max: clamp((2 << bits) - 1), count }
+        });
+        let strides = negative
+            .chain([StrideBucket { min: 0, max: 0, count: self.zero_strides }])
+            .chain(positive)
+            .filter(|bucket| bucket.count > 0)
+            .collect();
+        let fraction = |count: u64, total: u64| if total == 0 { 0.0 } else { count as f64 / total as f64 };
+        let mut spatial_windows = self.windows.clone();
+        if self.window_accesses > 0 {
+            spatial_windows.push(fraction(self.window_near, self.window_accesses));
+        }
+        let reuse = (0..=self.max_distance.ilog2())
+            .map(|bits| 1 << bits)
+            .chain((!self.max_distance.is_power_of_two()).then_some(self.max_distance))
+            .map(|lines| ReusePoint { lines, fraction: fraction(self.reuse.hits(1, lines).unwrap(), self.reuse.accesses()) })
+            .collect();
+        LocalityReport {
+            accesses: self.accesses,
+            line_size: self.line_size,
+            strides,
+            spatial_score: fraction(self.near, self.accesses),
+            spatial_windows,
+            reuse,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use serde::Serialize;
+use crate::config::LayeredCacheConfig;
+use crate::prelude::*;
+use crate::simulator::{AccessObserver, CacheAccess};
+
+/// The sets of each cache listed in a report, unless set
+pub const DEFAULT_GAP_SETS: usize = 5;
+
+/// Records the lines a cache reads, to count the misses of Belady's optimal replacement on them
+/// once the future is known
+///
+/// The optimal policy evicts the line of the set which is next used furthest in the future. Like
+/// the caches it's compared with, it always allocates the line missed, rather than bypassing the
+/// cache when the line is used later than any in its set.
+#[derive(Debug, Clone)]
+pub struct OptimalCache {
+    line_bits: u32,
+    sets: u64,
+    ways: u64,
+    lines: Vec<u64>,
+}
+
+impl OptimalCache {
+    /// Creates a cache of the given geometry with no lines read
+    ///
+    /// # Arguments
+    ///
+    /// * `line_size`: The line size in bytes, a power of two
+    /// * `sets`: The number of sets, a power of two
+    /// * `ways`: The lines in each set
+    ///
+    /// returns: Result<OptimalCache, String>
+    pub fn new(line_size: u64, sets: u64, ways: u64) -> Result<Self, String> {
+        if !line_size.is_power_of_two() || !sets.is_power_of_two() || ways == 0 {
+            return Err(format!("An optimal cache needs a power of two line size and number of sets, and at least one way, not {line_size}, {sets} and {ways}"));
+        }
+        Ok(Self { line_bits: line_size.trailing_zeros(), sets, ways, lines: Vec::new() })
+    }
+
+    /// Records a read of the line holding an address
+    pub fn read(&mut self, address: u64) {
+        self.lines.push(address >> self.line_bits);
+    }
+
+    /// Gets the number of reads recorded
+    pub fn reads(&self) -> u64 {
+        self.lines.len() as u64
+    }
+
+    /// Simulates the optimal policy on the reads recorded
+    ///
+    /// returns: Vec<u64>, the misses of each set, indexed by set
+    pub fn misses_by_set(&self) -> Vec<u64> {
+        // The position of the next read of the line of each read, past the end if there isn't one
+        let mut next_read = vec![u64::MAX; self.lines.len()];
+        let mut last_read = BTreeMap::new();
+        for (position, &line) in self.lines.iter().enumerate().rev() {
+            if let Some(next) = last_read.insert(line, position as u64) {
+                next_read[position] = next;
+            }
+        }
+        let mut misses = vec![0; self.sets as usize];
+        // The resident lines of each set ordered by their next read, and the next read of each
+        let mut sets: Vec<BTreeSet<(u64, u64)>> = vec![BTreeSet::new(); self.sets as usize];
+        let mut resident = BTreeMap::new();
+        for (&line, &next) in self.lines.iter().zip(&next_read) {
+            let set = (line & (self.sets - 1)) as usize;
+            match resident.insert(line, next) {
+                Some(previous) => {
+                    sets[set].remove(&(previous, line));
+                }
+                None => {
+                    misses[set] += 1;
+                    if sets[set].len() as u64 == self.ways {
+                        let (_, victim) = sets[set].pop_last().unwrap();
+                        resident.remove(&victim);
+                    }
+                }
+            }
+            sets[set].insert((next, line));
+        }
+        misses
+    }
+}
+
+/// How far the misses of one set are from optimal
+#[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq)]
+pub struct SetGap {
+    pub set: u64,
+    pub misses: u64,
+    pub optimal_misses: u64,
+    /// The misses more than optimal
+    pub gap: i64,
+}
+
+/// How far the misses of one cache are from optimal, see [OptimalGap]
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CacheGap {
+    pub name: String,
+    /// The reads of the cache, which both policies see
+    pub reads: u64,
+    pub misses: u64,
+    pub optimal_misses: u64,
+    /// The misses more than optimal, the most a better replacement policy could remove
+    pub gap: i64,
+    /// The sets with the largest gaps, largest first
+    pub sets: Vec<SetGap>,
+}
+
+/// Compares the misses of each cache of a simulation with those of Belady's optimal replacement on
+/// the same reads, as an observer of the simulation
+///
+/// Each cache's optimal policy sees the reads the cache itself saw, the misses of the caches above
+/// it under their configured policies, so the gap of each level is what its own policy leaves on
+/// the table. A cache can beat the optimal policy where it's given lines it didn't read, by its
+/// prefetcher or by evictions into an exclusive cache, or where writes missing it aren't
+/// allocated.
+#[derive(Debug, Clone)]
+pub struct OptimalGap {
+    names: Vec<String>,
+    optimal: Vec<OptimalCache>,
+    // The misses of each set of each cache under its configured policy
+    misses: Vec<Vec<u64>>,
+}
+
+impl OptimalGap {
+    /// Creates the comparison of each cache of a configuration, with nothing read
+    pub fn new(config: &LayeredCacheConfig) -> Result<Self, String> {
+        let optimal = config.caches.iter()
+            .map(|cache| {
+                if cache.slices.is_some() {
+                    return Err(format!("Cache {} is sliced, so its sets aren't chosen by bit selection as the optimal policy's are", cache.name));
+                }
+                let lines = cache.size / cache.line_size;
+                let ways = cache.kind.ways(lines);
+                OptimalCache::new(cache.line_size, lines / ways, ways).map_err(|e| format!("Cache {}: {e}", cache.name))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self {
+            names: config.caches.iter().map(|cache| cache.name.clone()).collect(),
+            misses: optimal.iter().map(|cache| vec![0; cache.sets as usize]).collect(),
+            optimal,
+        })
+    }
+
+    /// Simulates the optimal policy of each cache, and compares it with the configured policy
+    ///
+    /// # Arguments
+    ///
+    /// * `top_sets`: The number of sets of each cache to list, those with the largest gaps
+    ///
+    /// returns: Vec<CacheGap>, one for each cache, in the order of the configuration
+    pub fn report(&self, top_sets: usize) -> Vec<CacheGap> {
+        self.names.iter().zip(&self.optimal).zip(&self.misses)
+            .map(|((name, optimal), misses)| {
+                let optimal_misses = optimal.misses_by_set();
+                let mut sets: Vec<SetGap> = misses.iter().zip(&optimal_misses).enumerate()
+                    .map(|(set, (&misses, &optimal_misses))| SetGap { set: set as u64, misses, optimal_misses, gap: misses as i64 - optimal_misses as i64 })
+                    .collect();
+                sets.sort_by_key(|set| (core::cmp::Reverse(set.gap), set.set));
+                sets.truncate(top_sets);
+                let (misses, optimal_misses) = (misses.iter().sum::<u64>(), optimal_misses.iter().sum::<u64>());
+                CacheGap { name: name.clone(), reads: optimal.reads(), misses, optimal_misses, gap: misses as i64 - optimal_misses as i64, sets }
+            })
+            .collect()
+    }
+}
+
+impl AccessObserver for OptimalGap {
+    fn observe(&mut self, access: CacheAccess) {
+        let optimal = &mut self.optimal[access.cache];
+        optimal.read(access.line_address);
+        if !access.hit {
+            let set = (access.line_address >> optimal.line_bits) & (optimal.sets - 1);
+            self.misses[access.cache][set as usize] += 1;
+        }
+    }
+}
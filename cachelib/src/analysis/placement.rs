@@ -0,0 +1,77 @@
+use crate::config::arbitrary::SplitMix64;
+use crate::prelude::*;
+use crate::simulator::Access;
+
+/// The size of the regions moved, unless set, a page
+pub const DEFAULT_PLACEMENT_REGION_SIZE: u64 = 4096;
+
+/// The bits of an address which are randomised, those of a virtual address on x86_64 and aarch64
+const ADDRESS_BITS: u32 = 48;
+
+/// The rounds of mixing applied to the number of each region
+const ROUNDS: usize = 3;
+
+/// Moves each region of the address space to a random place, keeping the offset of each address
+/// within its region, as address space layout randomisation places the pages of a process
+///
+/// The number of each region is passed through a keyed bijection of the region numbers below
+/// 2<sup>48</sup>, so no two regions land in the same place, and lines within a region keep their
+/// order. Bits above the 48th are left as they are. A record which spans the end of a region is
+/// moved along with the region it starts in.
+///
+/// The misses of a set associative cache depend on which sets the lines of a trace fall in, so one
+/// placement can happen to have many more or fewer conflicts than is typical. Simulating several
+/// placements shows how much of a result is down to where the trace's regions happened to be.
+#[derive(Debug, Clone)]
+pub struct PlacementRandomiser {
+    region_bits: u32,
+    // The mask of the bits of a region number which are randomised
+    mask: u64,
+    keys: [u64; ROUNDS],
+    multipliers: [u64; ROUNDS],
+}
+
+impl PlacementRandomiser {
+    /// Creates the placement of a seed
+    ///
+    /// # Arguments
+    ///
+    /// * `region_size`: The size of each region moved, a power of two of at most 2<sup>47</sup>
+    ///   bytes
+    /// * `seed`: Chooses the placement, the same seed always giving the same placement
+    ///
+    /// returns: Result<PlacementRandomiser, String>
+    pub fn new(region_size: u64, seed: u64) -> Result<Self, String> {
+        if !region_size.is_power_of_two() || region_size.trailing_zeros() >= ADDRESS_BITS {
+            return Err(format!("The region size must be a power of two below 2^{ADDRESS_BITS} bytes, not {region_size}"));
+        }
+        let region_bits = region_size.trailing_zeros();
+        let mut rng = SplitMix64::new(seed);
+        Ok(Self {
+            region_bits,
+            mask: (1 << (ADDRESS_BITS - region_bits)) - 1,
+            keys: core::array::from_fn(|_| rng.next_u64()),
+            // Multiplying by an odd number is a bijection modulo a power of two
+            multipliers: core::array::from_fn(|_| rng.next_u64() | 1),
+        })
+    }
+
+    /// Gets the address an address is moved to
+    pub fn map(&self, address: u64) -> u64 {
+        let offset = address & ((1 << self.region_bits) - 1);
+        let high = address & !((1 << ADDRESS_BITS) - 1);
+        let region_bits = ADDRESS_BITS - self.region_bits;
+        let mut region = (address >> self.region_bits) & self.mask;
+        for (key, multiplier) in self.keys.iter().zip(&self.multipliers) {
+            region = ((region ^ key) & self.mask).wrapping_mul(*multiplier) & self.mask;
+            // Folding the upper bits down makes the lower bits, those picking a set, depend on them
+            region ^= region >> region_bits.div_ceil(2);
+        }
+        high | region << self.region_bits | offset
+    }
+
+    /// Gets an access moved to the address its address is moved to
+    pub fn map_access(&self, access: Access) -> Access {
+        Access { address: self.map(access.address), ..access }
+    }
+}
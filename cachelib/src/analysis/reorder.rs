@@ -0,0 +1,213 @@
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use serde::Serialize;
+use crate::config::arbitrary::SplitMix64;
+use crate::prelude::*;
+use crate::simulator::Access;
+
+/// The records reordered together, unless set
+pub const DEFAULT_REORDER_WINDOW: usize = 64;
+
+/// How the records of each window are reordered
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReorderStrategy {
+    /// Each record is taken from a thread chosen at random, a random interleaving of the threads
+    /// within the window, as a scheduler perturbing their timing might give
+    Shuffle { seed: u64 },
+    /// Each record is taken from a thread whose next record reads the line just read, if any, or
+    /// otherwise from the thread whose next record came first, clustering the accesses to each line
+    /// as a locality-aware scheduler might
+    Cluster,
+}
+
+/// How much a trace was reordered, see [TraceReorderer]
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ReorderReport {
+    pub records: u64,
+    pub threads: u64,
+    pub windows: u64,
+    /// The records which ended up at a different position than in the original interleaving
+    pub moved: u64,
+    /// The mean distance each record moved, in records
+    pub mean_displacement: f64,
+    pub max_displacement: u64,
+    /// The records reading the same line as the record before, in the original interleaving
+    pub same_line_before: u64,
+    /// The records reading the same line as the record before, once reordered
+    pub same_line_after: u64,
+}
+
+/// Reorders the records of the traces of several threads within bounded windows, keeping the order
+/// of each thread's own records, to see how sensitive a result is to the interleaving of the
+/// threads
+///
+/// The threads are first interleaved a record at a time, as `--process` does by default, and that
+/// order is split into windows of consecutive records. The records of each window are then
+/// reordered by the [strategy](ReorderStrategy), so no record moves further than the window, and
+/// each thread's records stay in order, as the accesses of a thread would under any schedule.
+///
+/// A single trace has no threads to tell apart, so [reorder_records](Self::reorder_records) lets
+/// every record move freely within its window instead.
+#[derive(Debug, Clone)]
+pub struct TraceReorderer {
+    window: usize,
+    line_bits: u32,
+    strategy: ReorderStrategy,
+}
+
+impl TraceReorderer {
+    /// # Arguments
+    ///
+    /// * `window`: The records reordered together, at least 1
+    /// * `line_size`: The size of the lines clustered and counted in the report, a power of two
+    /// * `strategy`: How the records of each window are reordered
+    ///
+    /// returns: Result<TraceReorderer, String>
+    pub fn new(window: usize, line_size: u64, strategy: ReorderStrategy) -> Result<Self, String> {
+        if window == 0 {
+            return Err("The reordering window must hold at least one record".to_string());
+        }
+        if !line_size.is_power_of_two() {
+            return Err(format!("The line size must be a power of two, not {line_size}"));
+        }
+        Ok(Self { window, line_bits: line_size.trailing_zeros(), strategy })
+    }
+
+    /// Reorders the traces of several threads, passing each record of the new trace to `emit` in
+    /// order
+    ///
+    /// # Arguments
+    ///
+    /// * `threads`: The trace of each thread, in the format of the specification
+    /// * `emit`: Called with each record of the reordered trace
+    ///
+    /// returns: Result<ReorderReport, String>, an error if a trace ends part way through a record
+    pub fn reorder(&self, threads: &[&[u8]], emit: impl FnMut(&[u8])) -> Result<ReorderReport, String> {
+        if let Some(thread) = threads.iter().position(|trace| !trace.len().is_multiple_of(40)) {
+            return Err(format!("The trace of thread {thread} ends part way through a record"));
+        }
+        // The original interleaving, one record of each thread in turn
+        let mut cursors: Vec<_> = threads.iter().map(|trace| trace.chunks_exact(40)).collect();
+        let mut turn = 0;
+        let interleaved = core::iter::from_fn(|| {
+            for _ in 0..cursors.len() {
+                let thread = turn;
+                turn = (turn + 1) % cursors.len();
+                if let Some(record) = cursors[thread].next() {
+                    return Some((thread, record));
+                }
+            }
+            None
+        });
+        Ok(self.reorder_interleaved(threads.len(), interleaved, emit))
+    }
+
+    /// Reorders a single trace, where any record may move anywhere within its window
+    ///
+    /// # Arguments
+    ///
+    /// * `trace`: The trace, in the format of the specification
+    /// * `emit`: Called with each record of the reordered trace
+    ///
+    /// returns: Result<ReorderReport, String>, an error if the trace ends part way through a record
+    pub fn reorder_records(&self, trace: &[u8], emit: impl FnMut(&[u8])) -> Result<ReorderReport, String> {
+        if !trace.len().is_multiple_of(40) {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        // Each record is a thread of its own, so is free to move
+        Ok(self.reorder_interleaved(1, trace.chunks_exact(40).enumerate(), emit))
+    }
+
+    /// Reorders an interleaving of records, each given with the thread making it
+    fn reorder_interleaved<'a>(&self, threads: usize, records: impl Iterator<Item = (usize, &'a [u8])>, mut emit: impl FnMut(&[u8])) -> ReorderReport {
+        let mut report = ReorderReport { records: 0, threads: threads as u64, windows: 0, moved: 0, mean_displacement: 0.0, max_displacement: 0, same_line_before: 0, same_line_after: 0 };
+        let (mut last_before, mut last_after) = (None, None);
+        let mut rng = SplitMix64::new(match self.strategy {
+            ReorderStrategy::Shuffle { seed } => seed,
+            ReorderStrategy::Cluster => 0,
+        });
+        let mut total_displacement = 0;
+        let mut window: Vec<(usize, &[u8])> = Vec::with_capacity(self.window);
+        let mut records = records.peekable();
+        while records.peek().is_some() {
+            window.clear();
+            window.extend(records.by_ref().take(self.window));
+            for &(_, record) in &window {
+                let line = self.line(record);
+                report.same_line_before += u64::from(last_before == Some(line));
+                last_before = Some(line);
+            }
+            for (position, original) in self.order(&window, &mut rng).into_iter().enumerate() {
+                let record = window[original].1;
+                let line = self.line(record);
+                report.same_line_after += u64::from(last_after == Some(line));
+                last_after = Some(line);
+                let displacement = position.abs_diff(original) as u64;
+                report.moved += u64::from(displacement > 0);
+                report.max_displacement = report.max_displacement.max(displacement);
+                total_displacement += displacement;
+                emit(record);
+            }
+            report.records += window.len() as u64;
+            report.windows += 1;
+        }
+        if report.records > 0 {
+            report.mean_displacement = total_displacement as f64 / report.records as f64;
+        }
+        report
+    }
+
+    fn line(&self, record: &[u8]) -> u64 {
+        Access::from_record(record.try_into().unwrap()).address >> self.line_bits
+    }
+
+    /// Chooses the new order of a window, as the position in the window of each record in turn
+    fn order(&self, window: &[(usize, &[u8])], rng: &mut SplitMix64) -> Vec<usize> {
+        // The positions of each thread's records, in order
+        let mut queues: BTreeMap<usize, VecDeque<usize>> = BTreeMap::new();
+        for (position, &(thread, _)) in window.iter().enumerate() {
+            queues.entry(thread).or_default().push_back(position);
+        }
+        let mut order = Vec::with_capacity(window.len());
+        match self.strategy {
+            ReorderStrategy::Shuffle { .. } => {
+                let mut active: Vec<VecDeque<usize>> = queues.into_values().collect();
+                while !active.is_empty() {
+                    let thread = rng.below(active.len() as u64) as usize;
+                    order.push(active[thread].pop_front().unwrap());
+                    if active[thread].is_empty() {
+                        active.swap_remove(thread);
+                    }
+                }
+            }
+            ReorderStrategy::Cluster => {
+                // The next record of each thread, both in order and by the line it reads
+                let mut heads = BTreeSet::new();
+                let mut heads_by_line: BTreeMap<u64, BTreeSet<usize>> = BTreeMap::new();
+                let add_head = |position: usize, heads: &mut BTreeSet<usize>, heads_by_line: &mut BTreeMap<u64, BTreeSet<usize>>| {
+                    heads.insert(position);
+                    heads_by_line.entry(self.line(window[position].1)).or_default().insert(position);
+                };
+                for queue in queues.values_mut() {
+                    add_head(queue.pop_front().unwrap(), &mut heads, &mut heads_by_line);
+                }
+                let mut last_line = None;
+                while let Some(&first) = heads.first() {
+                    let next = last_line.and_then(|line| heads_by_line.get(&line)?.first().copied()).unwrap_or(first);
+                    let line = self.line(window[next].1);
+                    heads.remove(&next);
+                    let same_line = heads_by_line.get_mut(&line).unwrap();
+                    same_line.remove(&next);
+                    if same_line.is_empty() {
+                        heads_by_line.remove(&line);
+                    }
+                    if let Some(following) = queues.get_mut(&window[next].0).unwrap().pop_front() {
+                        add_head(following, &mut heads, &mut heads_by_line);
+                    }
+                    order.push(next);
+                    last_line = Some(line);
+                }
+            }
+        }
+        order
+    }
+}
@@ -0,0 +1,195 @@
+use serde::Serialize;
+use crate::prelude::*;
+use crate::simulator::Access;
+
+/// The hits and misses of one LRU cache simulated by a [SweepLru]
+#[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq)]
+pub struct SweepLruPoint {
+    pub sets: u64,
+    pub ways: u64,
+    /// The size of the cache in bytes
+    pub size: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// The LRU stacks of every set of a cache with one number of sets
+struct SetCount {
+    // The lines of each set, most recently used first, in max_ways slots per set
+    stacks: Vec<u64>,
+    lengths: Vec<u32>,
+    // The number of hits at each depth of a stack, so with each number of ways
+    depths: Vec<u64>,
+    // Whether the set of the first line has held nothing else, see SweepLru::access
+    first_line_alone: bool,
+}
+
+/// Simulates LRU caches of every power of two number of sets and every associativity, for one
+/// line size, in a single pass over a trace
+///
+/// LRU is a stack algorithm: a cache with more ways holds every line a cache with fewer ways and
+/// the same sets would hold. So rather than simulating each cache, the lines of each set are kept
+/// in order of use, and the depth at which a line is found is the fewest ways which hit. Keeping a
+/// stack for each number of sets gives the hits of every size and associativity at once, for
+/// about the cost of simulating the largest caches, rather than of sweeping every point.
+///
+/// The counts are exactly those of a single level [Simulator](crate::simulator::Simulator) using
+/// LRU, including its treatment of the first line it reads: that line's last use is recorded at
+/// the same time as empty lines, so the next different line in its set replaces it, unless it has
+/// been read again first.
+pub struct SweepLru {
+    line_bits: u32,
+    max_ways: usize,
+    set_counts: Vec<SetCount>,
+    first_line: Option<u64>,
+    accesses: u64,
+}
+
+impl SweepLru {
+    /// Creates a sweep with empty caches
+    ///
+    /// # Arguments
+    ///
+    /// * `line_size`: The line size of every cache, a power of two
+    /// * `max_sets`: The most sets a cache has, a power of two. Every power of two up to it is
+    ///   simulated
+    /// * `max_ways`: The most ways a cache has. Every number of ways up to it is simulated
+    ///
+    /// returns: Result<SweepLru, String>
+    pub fn new(line_size: u64, max_sets: u64, max_ways: u64) -> Result<Self, String> {
+        if !line_size.is_power_of_two() {
+            return Err(format!("The line size must be a power of two, not {line_size}"));
+        }
+        if !max_sets.is_power_of_two() {
+            return Err(format!("The number of sets must be a power of two, not {max_sets}"));
+        }
+        if max_ways == 0 || max_ways > u32::MAX as u64 {
+            return Err(format!("The number of ways must be between 1 and {}, not {max_ways}", u32::MAX));
+        }
+        let set_counts = (0..=max_sets.trailing_zeros())
+            .map(|set_bits| {
+                let sets = 1 << set_bits;
+                SetCount {
+                    stacks: vec![0; sets * max_ways as usize],
+                    lengths: vec![0; sets],
+                    depths: vec![0; max_ways as usize],
+                    first_line_alone: true,
+                }
+            })
+            .collect();
+        Ok(Self {
+            line_bits: line_size.trailing_zeros(),
+            max_ways: max_ways as usize,
+            set_counts,
+            first_line: None,
+            accesses: 0,
+        })
+    }
+
+    /// Simulates a read of the line holding an address in every cache
+    pub fn access(&mut self, address: u64) {
+        let line = address >> self.line_bits;
+        let first_line = *self.first_line.get_or_insert(line);
+        self.accesses += 1;
+        let max_ways = self.max_ways;
+        for set_count in &mut self.set_counts {
+            let set = (line & (set_count.lengths.len() as u64 - 1)) as usize;
+            let stack = &mut set_count.stacks[set * max_ways..(set + 1) * max_ways];
+            let length = set_count.lengths[set] as usize;
+            let depth = stack[..length].iter().position(|&l| l == line);
+            if let Some(depth) = depth {
+                set_count.depths[depth] += 1;
+                stack[..=depth].rotate_right(1);
+                if line == first_line {
+                    set_count.first_line_alone = false;
+                }
+                continue;
+            }
+            // The first line looks as old as the empty lines, and comes before them, so it's the
+            // one replaced by the next line of its set, whatever the associativity
+            let length = if set_count.first_line_alone && length == 1 && stack[0] == first_line && line != first_line {
+                set_count.first_line_alone = false;
+                0
+            } else {
+                length
+            };
+            let length = (length + 1).min(max_ways);
+            stack[..length].rotate_right(1);
+            stack[0] = line;
+            set_count.lengths[set] = length as u32;
+        }
+    }
+
+    /// Simulates accesses in every cache, each of which reads every line it touches
+    pub fn simulate_accesses(&mut self, accesses: impl IntoIterator<Item = Access>) {
+        for access in accesses {
+            let line_size = 1 << self.line_bits;
+            let mut line = access.address & !(line_size - 1);
+            while line < access.address + access.size as u64 {
+                self.access(line);
+                line += line_size;
+            }
+        }
+    }
+
+    /// Simulates a trace in the format of the specification in every cache
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    ///
+    /// returns: Result<(), String>, an error if the trace ends part way through a record
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if !bytes.len().is_multiple_of(40) {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        self.simulate_accesses(bytes.chunks_exact(40).map(|record| Access::from_record(record.try_into().unwrap())));
+        Ok(())
+    }
+
+    /// Gets the number of lines read so far, which each cache either hits or misses
+    pub fn accesses(&self) -> u64 {
+        self.accesses
+    }
+
+    /// Gets the hits of a cache so far
+    ///
+    /// # Arguments
+    ///
+    /// * `sets`: The number of sets
+    /// * `ways`: The number of ways in each set
+    ///
+    /// returns: Option<u64>, None if the cache isn't one which was simulated
+    pub fn hits(&self, sets: u64, ways: u64) -> Option<u64> {
+        if !sets.is_power_of_two() || ways == 0 || ways > self.max_ways as u64 {
+            return None;
+        }
+        let set_count = self.set_counts.get(sets.trailing_zeros() as usize)?;
+        Some(set_count.depths[..ways as usize].iter().sum())
+    }
+
+    /// Gets the misses of a cache so far, see [hits](Self::hits)
+    pub fn misses(&self, sets: u64, ways: u64) -> Option<u64> {
+        self.hits(sets, ways).map(|hits| self.accesses - hits)
+    }
+
+    /// Gets the hits and misses of every cache so far, ordered by sets and then ways
+    pub fn points(&self) -> Vec<SweepLruPoint> {
+        let mut points = Vec::with_capacity(self.set_counts.len() * self.max_ways);
+        for (set_bits, set_count) in self.set_counts.iter().enumerate() {
+            let sets = 1 << set_bits;
+            let mut hits = 0;
+            for (ways, depth_hits) in (1..).zip(&set_count.depths) {
+                hits += depth_hits;
+                points.push(SweepLruPoint {
+                    sets,
+                    ways,
+                    size: (sets * ways) << self.line_bits,
+                    hits,
+                    misses: self.accesses - hits,
+                });
+            }
+        }
+        points
+    }
+}
@@ -0,0 +1,170 @@
+use alloc::collections::BTreeMap;
+use serde::Serialize;
+use crate::prelude::*;
+use crate::simulator::Access;
+
+/// The number of accesses in each window of the working set series, unless set
+pub const DEFAULT_WORKING_SET_WINDOW: u64 = 100_000;
+
+/// The average working set over every window of a number of accesses
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct WorkingSetPoint {
+    /// The number of accesses in each window
+    pub window: u64,
+    /// The average number of distinct lines touched in a window
+    pub lines: f64,
+}
+
+/// The working sets of a trace, see [WorkingSetAnalysis]
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct WorkingSetReport {
+    pub accesses: u64,
+    pub line_size: u64,
+    /// The number of distinct lines touched by the whole trace
+    pub footprint: u64,
+    /// The number of accesses in each window of the series
+    pub window: u64,
+    /// The number of distinct lines touched in each window of accesses, in order, the last of
+    /// which may be short
+    pub windows: Vec<u64>,
+    /// The average working set for each window size asked for, in increasing order of size
+    pub curve: Vec<WorkingSetPoint>,
+}
+
+/// Estimates the working set of a trace, the distinct lines it touches in a window of accesses, in
+/// a single pass
+///
+/// The series splits the trace into consecutive windows of a fixed number of accesses, showing how
+/// the working set changes between phases. The curve gives, for each of a list of window sizes,
+/// the working set averaged over the window ending at every access, as defined by Denning. It's
+/// found from the interval until each line is next touched, since a line touched at one access is
+/// counted by the windows ending before it's touched again, up to the size of the window, so the
+/// curve costs a step per window size per line touched rather than per window.
+///
+/// A cache holding the average working set for a window size misses about once per that many
+/// accesses outside of changes of phase, which makes the curve a guide to the cache sizes worth
+/// sweeping.
+pub struct WorkingSetAnalysis {
+    line_size: u64,
+    window: u64,
+    curve_windows: Vec<u64>,
+    accesses: u64,
+    // The access which last touched each line
+    last_touched: BTreeMap<u64, u64>,
+    window_lines: u64,
+    windows: Vec<u64>,
+    // For each window size of the curve, the sum over the lines touched so far of the windows
+    // counting them
+    curve_sums: Vec<u64>,
+}
+
+impl WorkingSetAnalysis {
+    /// Creates an analysis which has seen no accesses
+    ///
+    /// # Arguments
+    ///
+    /// * `line_size`: The line size the working set is counted in, a power of two
+    /// * `window`: The number of accesses in each window of the series
+    /// * `curve_windows`: The window sizes to find the average working set for, which may be empty
+    ///
+    /// returns: Result<WorkingSetAnalysis, String>
+    pub fn new(line_size: u64, window: u64, curve_windows: &[u64]) -> Result<Self, String> {
+        if !line_size.is_power_of_two() {
+            return Err(format!("The line size must be a power of two, not {line_size}"));
+        }
+        if window == 0 || curve_windows.contains(&0) {
+            return Err("The windows of a working set must be at least 1 access".to_string());
+        }
+        let mut curve_windows = curve_windows.to_vec();
+        curve_windows.sort_unstable();
+        curve_windows.dedup();
+        Ok(Self {
+            line_size,
+            window,
+            curve_sums: vec![0; curve_windows.len()],
+            curve_windows,
+            accesses: 0,
+            last_touched: BTreeMap::new(),
+            window_lines: 0,
+            windows: Vec::new(),
+        })
+    }
+
+    /// Adds an access, touching every line it covers
+    pub fn access(&mut self, access: Access) {
+        let now = self.accesses;
+        let window_start = now - now % self.window;
+        let last_byte = access.address.saturating_add((access.size as u64).max(1) - 1);
+        let mut line = access.address & !(self.line_size - 1);
+        while line <= last_byte {
+            match self.last_touched.insert(line, now) {
+                Some(last) if last == now => {}
+                Some(last) => {
+                    if last < window_start {
+                        self.window_lines += 1;
+                    }
+                    self.count_interval(now - last);
+                }
+                None => self.window_lines += 1,
+            }
+            let Some(next) = line.checked_add(self.line_size) else { break };
+            line = next;
+        }
+        self.accesses += 1;
+        if self.accesses.is_multiple_of(self.window) {
+            self.windows.push(core::mem::take(&mut self.window_lines));
+        }
+    }
+
+    /// Counts a line touched `interval` accesses before it was next touched, or before the end
+    fn count_interval(&mut self, interval: u64) {
+        for (sum, &window) in self.curve_sums.iter_mut().zip(&self.curve_windows) {
+            *sum += interval.min(window);
+        }
+    }
+
+    /// Adds a trace in the format of the specification
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    ///
+    /// returns: Result<(), String>, an error if the trace ends part way through a record
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if !bytes.len().is_multiple_of(40) {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        for record in bytes.chunks_exact(40) {
+            self.access(Access::from_record(record.try_into().unwrap()));
+        }
+        Ok(())
+    }
+
+    /// Gets the working sets of the accesses so far
+    pub fn report(&self) -> WorkingSetReport {
+        let mut windows = self.windows.clone();
+        if !self.accesses.is_multiple_of(self.window) {
+            windows.push(self.window_lines);
+        }
+        // The lines still to be touched again are counted by every window up to the end
+        let mut curve_sums = self.curve_sums.clone();
+        for &last in self.last_touched.values() {
+            let interval = self.accesses - last;
+            for (sum, &window) in curve_sums.iter_mut().zip(&self.curve_windows) {
+                *sum += interval.min(window);
+            }
+        }
+        let curve = self.curve_windows.iter().zip(curve_sums).map(|(&window, sum)| WorkingSetPoint {
+            window,
+            lines: if self.accesses == 0 { 0.0 } else { sum as f64 / self.accesses as f64 },
+        }).collect();
+        WorkingSetReport {
+            accesses: self.accesses,
+            line_size: self.line_size,
+            footprint: self.last_touched.len() as u64,
+            window: self.window,
+            windows,
+            curve,
+        }
+    }
+}
@@ -1,4 +1,25 @@
-use crate::replacement_policies::{LeastFrequentlyUsed, LeastRecentlyUsed, NoPolicy, ReplacementPolicy, RoundRobin};
+use serde::Serialize;
+use crate::address::Address;
+use crate::replacement_policies::{CompactLeastRecentlyUsed, DynamicPolicy, HeapLeastFrequentlyUsed, LeastFrequentlyUsed, LeastRecentlyUsed, NoPolicy, ReplacementPolicy, RoundRobin};
+use crate::prelude::*;
+use self::tag_index::TagIndex;
+pub use self::lazy::{LazyCache, SetFactory};
+pub use self::sliced::{SlicedCache, INTEL_SLICE_MASKS};
+
+/// Contains the hash table of the tags of a cache with one set
+mod tag_index;
+
+/// Contains the cache whose sets are allocated the first time they're used
+mod lazy;
+
+/// Contains the cache split into slices chosen by a hash of the address
+mod sliced;
+
+/// Compares a tag with every way of a set at once with SIMD instructions where every processor of
+/// the target has them, which SSE2 on x86_64 and NEON on aarch64 do, so no runtime detection is
+/// needed
+#[cfg(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64"))]
+mod simd;
 
 /// A generic trait for caches
 ///
@@ -7,7 +28,9 @@ use crate::replacement_policies::{LeastFrequentlyUsed, LeastRecentlyUsed, NoPoli
 ///
 /// The trait assumes that ensuring reads spanning multiple cache lines are split properly is the
 /// responsibility of the caller
-pub trait CacheTrait {
+///
+/// Addresses are u64 unless another [Address] type is given
+pub trait CacheTrait<A: Address = u64> {
 
     /// Converts an address into a set and a tag. Both respect cache line alignment.
     ///
@@ -19,8 +42,8 @@ pub trait CacheTrait {
     ///
     /// * `input`:
     ///
-    /// returns: (u64, u64)
-    fn address_to_set_and_tag(&self, input: u64) -> (u64, u64);
+    /// returns: (u64, A)
+    fn address_to_set_and_tag(&self, input: A) -> (u64, A);
 
 
     /// Tries to read a cache line, returning true on a cache hit, and false otherwise
@@ -31,23 +54,197 @@ pub trait CacheTrait {
     /// # Arguments
     ///
     /// * `input`: The address of the read. Note this is for the line at that address, hence no size
-    /// argument
+    ///   argument
     ///
     /// returns: bool
-    fn read_and_update_line(&mut self, input: u64) -> bool;
+    fn read_and_update_line(&mut self, input: A) -> bool;
+
+    /// Reads a batch of cache lines, in the same way as calling
+    /// [read_and_update_line](CacheTrait::read_and_update_line) for each in turn, adding their hits
+    /// and misses to `counters`
+    ///
+    /// [GenericCache] branches on the type of its cache once for the batch, rather than for every
+    /// line, and the counters are only updated at the end
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs`: The addresses of the reads, one per line
+    /// * `counters`: The counts to add the hits and misses of the batch to
+    ///
+    /// returns: ()
+    fn read_and_update_lines(&mut self, inputs: &[A], counters: &mut HitMissCounters) {
+        let mut hits = 0;
+        for &input in inputs {
+            hits += self.read_and_update_line(input) as u64;
+        }
+        counters.hits += hits;
+        counters.misses += inputs.len() as u64 - hits;
+    }
+
+    /// Reads a batch of cache lines in the same way as
+    /// [read_and_update_lines](CacheTrait::read_and_update_lines), appending the addresses which
+    /// missed to `misses` rather than counting them, such as to read them from the next level of a
+    /// hierarchy
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs`: The addresses of the reads, one per line
+    /// * `misses`: Where the addresses of the reads which missed are appended, in order
+    ///
+    /// returns: ()
+    fn read_and_update_lines_keeping_misses(&mut self, inputs: &[A], misses: &mut Vec<A>) {
+        for &input in inputs {
+            if !self.read_and_update_line(input) {
+                misses.push(input);
+            }
+        }
+    }
+
+    /// Reads a cache line in the same way as [read_and_update_line](CacheTrait::read_and_update_line),
+    /// but reports the address of any line evicted, and can skip allocating a line on a miss
+    ///
+    /// This is slightly slower, so is only used when the hierarchy needs to react to evictions
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The address of the read
+    /// * `allocate`: Whether to allocate a line on a miss
+    ///
+    /// returns: LineAccess
+    fn access_line(&mut self, input: A, allocate: bool) -> LineAccess<A>;
+
+    /// Places a line in the cache without it counting as a use, such as for prefetches or victims
+    /// from the level above. If the line is already present nothing happens, and the replacement
+    /// policy isn't updated
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The address of the line to fill
+    ///
+    /// returns: LineAccess, where a hit means the line was already present
+    fn fill_line(&mut self, input: A) -> LineAccess<A>;
+
+    /// Removes a line from the cache if it is present, returning whether it was
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The address of the line to invalidate
+    ///
+    /// returns: bool
+    fn invalidate_line(&mut self, input: A) -> bool;
+
+    /// Finds the way of its set holding a line, without it counting as a use
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: The address of the line
+    ///
+    /// returns: Option<u64>, None if the line isn't present
+    fn way_of(&self, input: A) -> Option<u64>;
+
+    /// Removes every line from the cache, as a flush does, leaving the replacement policy's state
+    /// as it is
+    ///
+    /// # Arguments
+    ///
+    /// * `flushed`: Where the address of each line which was present is added
+    fn invalidate_all(&mut self, flushed: &mut Vec<A>);
 
     /// Gets the bit mask used to align the address
-    fn get_alignment_bit_mask(&self) -> u64;
+    fn get_alignment_bit_mask(&self) -> A;
 
     /// Gets the line size used by this cache
     fn get_line_size(&self) -> u64;
 
+    /// Gets the number of sets in the cache
+    fn get_set_count(&self) -> u64;
+
     /// Gets the number of uninitialised cache lines. Useful for analysing cache performance or
     /// debugging
     fn get_uninitialised_line_count(&self) -> usize;
+
+    /// Advances the clock of the replacement policy without using a line, see
+    /// [advance_clock](ReplacementPolicy::advance_clock)
+    fn advance_policy_clock(&mut self);
+
+    /// Gets every line of the cache, ordered by set and then way, for inspecting its contents
+    fn lines(&self) -> Vec<CacheLine<A>>;
+
+    /// Checks the cache's state is consistent, with no line held twice in a set, and the
+    /// replacement policy's state agreeing with which lines are valid, see
+    /// [check_set](ReplacementPolicy::check_set)
+    ///
+    /// This reads every line, so is only for verifying a simulation
+    ///
+    /// returns: Result<(), (u64, String)>, the set and a description of the first inconsistency
+    /// found
+    fn check(&self) -> Result<(), (u64, String)>;
+
+    /// Gets the bytes of host memory held by the cache's tags and its replacement policy's state,
+    /// which are allocated when the cache is created
+    fn memory_usage(&self) -> MemoryUsage;
+}
+
+/// A line of a cache, as seen when inspecting its contents
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheLine<A: Address = u64> {
+    pub set: u64,
+    pub way: u64,
+    /// The address of the start of the line held, or None if the line isn't valid
+    pub address: Option<A>,
+    /// Whether the line has been written since it was read from below. The hierarchy keeps the
+    /// dirty lines of its caches, so this is only filled in by
+    /// [LayeredCache::lines](crate::layered::LayeredCache::lines). Omitted from the output when
+    /// false
+    #[serde(skip_serializing_if = "is_false")]
+    pub dirty: bool,
+    /// The replacement policy's state for the line, see
+    /// [line_metadata](ReplacementPolicy::line_metadata)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<serde_json::Value>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// The hits and misses of a batch of reads, see
+/// [read_and_update_lines](CacheTrait::read_and_update_lines)
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct HitMissCounters {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// The host memory held by a cache, in bytes, see [memory_usage](CacheTrait::memory_usage)
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct MemoryUsage {
+    /// The tags of the lines, along with which lines are valid and any index of the tags
+    pub tags: usize,
+    /// The state of the replacement policy
+    pub policy: usize,
+}
+
+impl MemoryUsage {
+    /// Gets the bytes held altogether
+    pub fn total(&self) -> usize {
+        self.tags + self.policy
+    }
 }
 
-/// A generic cache implementation, parameterised by a replacement policy
+/// The outcome of an access to a cache line
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LineAccess<A: Address = u64> {
+    /// Whether the line was present
+    pub hit: bool,
+    /// The aligned address of the line evicted to make room for this one, if a valid line was evicted
+    pub evicted: Option<A>,
+    /// The way of the set the replacement policy chose for the line, if one was allocated
+    pub way: Option<u64>,
+}
+
+/// A generic cache implementation, parameterised by a replacement policy, and optionally by the
+/// type of its addresses and its associativity
 ///
 /// The general approach here is to have one solid implementation which is easy to maintain and
 /// expand with more replacement policies without compromising too much on performance
@@ -61,160 +258,620 @@ pub trait CacheTrait {
 /// of two, but it would increase compile times more than I'd like, and either reduces flexibility,
 /// or requires adding another *almost* identical implementation
 ///
-/// Note that for optimisation reasons the cache assumes that accessing 0 is not possible, as it
-/// would cause an error on most systems
-pub struct Cache<R: ReplacementPolicy>
+/// The associativity is the exception, as there are only a few common values, and a fixed number
+/// of `WAYS` lets the compiler fully unroll the scan of a set and the replacement policy's loops.
+/// `WAYS` of 0, the default, reads the associativity from the geometry at runtime instead
+///
+/// The address type `A` is generic too, so a single cache can be fed 128-bit or narrow addresses.
+/// Only the cache is: the hierarchy and [Simulator](crate::simulator::Simulator) use `u64`, as the
+/// records of the trace format have room for 16 hex digits of address
+///
+/// Which lines are valid is kept in a bitset beside the tags. Empty lines have a tag of 0, so the
+/// bitset is only read when a line's tag is 0, to tell an empty line from one holding address 0
+///
+/// Tags are stored without the offset and set bits, so for realistic address ranges they fit in 32
+/// bits, halving the memory of the tags of a multi-MB cache compared to storing the whole address.
+/// Caches with addresses wider than 32 bits start with 32-bit tags, and widen them the first time a
+/// line is stored whose tag doesn't fit, which is only once, and only for addresses at or above
+/// 2<sup>32</sup> times the line size times the number of sets
+///
+/// Caches with one set of at least [INDEXED_LINES] lines, as fully associative caches usually are,
+/// keep a hash table from the tag of each valid line to its way, so a hit doesn't scan every line
+///
+/// Sets of 8 or 16 ways with 32-bit tags are searched by comparing the tag with 4 ways at a time
+/// with SIMD instructions on x86_64 and aarch64, taking the lowest way which matches, rather than
+/// comparing the ways one by one
+#[derive(Clone)]
+pub struct Cache<R: ReplacementPolicy, A: Address = u64, const WAYS: u64 = 0>
 {
-    set_selection_bit_mask: u64,
-    tag_selection_bit_mask: u64,
-    cache_alignment_bit_mask: u64,
+    set_selection_bit_mask: A,
+    tag_selection_bit_mask: A,
+    cache_alignment_bit_mask: A,
     line_size: u64,
-    cache: Vec<u64>,
+    tags: Tags<A>,
+    valid: ValidLines,
+    index: Option<TagIndex<A>>,
     replacement_policy: R,
     cache_alignment_bits: u8,
+    tag_shift: u32,
     set_size: u64,
+    simd_scan: bool,
 }
 
-impl<R: ReplacementPolicy> Cache<R> {
+/// The tags of every line of a cache, shifted down past the set bits
+#[derive(Clone)]
+enum Tags<A: Address> {
+    Narrow(Vec<u32>),
+    Wide(Vec<A>),
+}
+
+impl<R: ReplacementPolicy, A: Address, const WAYS: u64> Cache<R, A, WAYS> {
+    /// # Panics
+    ///
+    /// If `WAYS` isn't 0 and doesn't match the number of lines per set
     pub fn new(size: u64, line_size: u64, num_sets: u64, policy: R) -> Self {
         let cache_alignment_bits = line_size.trailing_zeros() as u8;
         let set_selection_bits = num_sets.trailing_zeros() as u8;
         let cache_lines = size / line_size;
+        assert!(WAYS == 0 || WAYS == cache_lines / num_sets, "A {WAYS} way cache can't have {} lines per set", cache_lines / num_sets);
         Self {
             set_size: cache_lines / num_sets,
-            set_selection_bit_mask: (num_sets - 1) << cache_alignment_bits,
-            tag_selection_bit_mask: ((1 << (u64::BITS - set_selection_bits as u32 - cache_alignment_bits as u32)) - 1) << (cache_alignment_bits + set_selection_bits),
-            cache_alignment_bit_mask: !((1 << (cache_alignment_bits as u32)) - 1),
+            set_selection_bit_mask: A::from_u64(num_sets - 1) << cache_alignment_bits as u32,
+            tag_selection_bit_mask: !A::ZERO << (cache_alignment_bits + set_selection_bits) as u32,
+            cache_alignment_bit_mask: !A::ZERO << cache_alignment_bits as u32,
             line_size,
             cache_alignment_bits,
-            cache: vec![0; cache_lines as usize],
+            tag_shift: (cache_alignment_bits + set_selection_bits) as u32,
+            tags: if A::BITS > u32::BITS {
+                Tags::Narrow(vec![0; cache_lines as usize])
+            } else {
+                Tags::Wide(vec![A::ZERO; cache_lines as usize])
+            },
+            valid: ValidLines::new(cache_lines),
+            index: (num_sets == 1 && cache_lines >= INDEXED_LINES).then(|| TagIndex::new(cache_lines)),
             replacement_policy: policy,
+            simd_scan: SIMD_SCAN && matches!(cache_lines / num_sets, 8 | 16),
+        }
+    }
+
+    /// Sets whether lines are found through a hash table of their tags, rather than by scanning
+    /// their set, which is only possible for caches with one set
+    ///
+    /// By default caches with one set of at least [INDEXED_LINES] lines are indexed
+    ///
+    /// # Arguments
+    ///
+    /// * `indexed`: Whether to index the tags, ignored if the cache has more than one set
+    ///
+    /// returns: Cache<R, A, WAYS>
+    pub fn with_tag_index(mut self, indexed: bool) -> Self {
+        self.index = None;
+        if indexed && self.ways() as usize == self.line_count() {
+            let mut index = TagIndex::new(self.ways());
+            for line in 0..self.ways() {
+                if self.valid.contains(line) {
+                    index.insert(self.tag_at(line), line);
+                }
+            }
+            self.index = Some(index);
         }
+        self
+    }
+
+    /// Whether lines are found through a hash table of their tags, see
+    /// [with_tag_index](Cache::with_tag_index)
+    pub fn has_tag_index(&self) -> bool {
+        self.index.is_some()
+    }
+
+    /// Sets whether sets are searched with SIMD instructions, rather than a way at a time, which is
+    /// only possible for sets of 8 or 16 ways on x86_64 and aarch64
+    ///
+    /// By default those caches use SIMD instructions. Either way the same lines are found
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled`: Whether to use SIMD instructions, ignored where they can't be used
+    ///
+    /// returns: Cache<R, A, WAYS>
+    pub fn with_simd_scan(mut self, enabled: bool) -> Self {
+        self.simd_scan = enabled && SIMD_SCAN && matches!(self.ways(), 8 | 16);
+        self
+    }
+
+    /// Whether sets are searched with SIMD instructions, see [with_simd_scan](Cache::with_simd_scan)
+    pub fn has_simd_scan(&self) -> bool {
+        self.simd_scan
+    }
+
+    /// Whether the cache's tags are still stored in 32 bits, rather than having been widened for a
+    /// tag which didn't fit, or being as wide as the addresses
+    pub fn has_narrow_tags(&self) -> bool {
+        matches!(self.tags, Tags::Narrow(_))
     }
 }
 
-impl<R: ReplacementPolicy> CacheTrait for Cache<R> {
+impl<R: ReplacementPolicy, A: Address, const WAYS: u64> Cache<R, A, WAYS> {
+    /// The number of lines per set, which is a constant the compiler can unroll loops over when the
+    /// associativity is fixed
+    #[inline(always)]
+    fn ways(&self) -> u64 {
+        if WAYS == 0 { self.set_size } else { WAYS }
+    }
 
-    fn address_to_set_and_tag(&self, input: u64) -> (u64, u64) {
-        (((input & self.set_selection_bit_mask) >> self.cache_alignment_bits), input & (self.tag_selection_bit_mask))
+    /// The number of lines in the cache
+    fn line_count(&self) -> usize {
+        match &self.tags {
+            Tags::Narrow(tags) => tags.len(),
+            Tags::Wide(tags) => tags.len(),
+        }
     }
 
-    // Cache hit is true, cache miss is false
-    fn read_and_update_line(&mut self, input: u64) -> bool {
-        let (set, tag) = self.address_to_set_and_tag(input);
-        let set_inclusive_lower_bound = set * self.set_size;
-        let set_exclusive_upper_bound = set_inclusive_lower_bound + self.set_size;
-        // Only search the relevant set
+    /// The tag stored in a line
+    fn tag_at(&self, line: u64) -> A {
+        match &self.tags {
+            Tags::Narrow(tags) => A::from_u64(tags[line as usize] as u64),
+            Tags::Wide(tags) => tags[line as usize],
+        }
+    }
+
+    /// Converts an address to the tag stored for it
+    #[inline(always)]
+    fn tag_of(&self, input: A) -> A {
+        input >> self.tag_shift
+    }
+
+    /// Converts a stored tag and its set back into the address of the start of the line
+    fn line_address(&self, tag: A, set: u64) -> A {
+        tag << self.tag_shift | (A::from_u64(set) << self.cache_alignment_bits as u32)
+    }
+
+    /// The tag as 32 bits, if it fits
+    #[inline(always)]
+    fn narrow(tag: A) -> Option<u32> {
+        (tag & !A::from_u64(u32::MAX as u64) == A::ZERO).then(|| tag.low_u64() as u32)
+    }
+
+    /// Finds the index of the line holding a tag within a set
+    #[inline(always)]
+    fn find_line(&self, set_inclusive_lower_bound: u64, tag: A) -> Option<u64> {
+        if let Some(index) = &self.index {
+            return index.get(tag);
+        }
+        let zero = tag == A::ZERO;
+        match &self.tags {
+            // A tag which doesn't fit can't have been stored
+            Tags::Narrow(tags) => Self::narrow(tag).and_then(|tag| {
+                #[cfg(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64"))]
+                if self.simd_scan {
+                    return self.scan_simd(tags, set_inclusive_lower_bound, tag, zero);
+                }
+                self.scan(tags, set_inclusive_lower_bound, tag, zero)
+            }),
+            Tags::Wide(tags) => self.scan(tags, set_inclusive_lower_bound, tag, zero),
+        }
+    }
+
+    /// Scans a set for a tag
+    ///
+    /// Empty lines have a tag of 0, so only a tag of 0 needs the valid bit checking, and the check
+    /// is lifted out of the scan of a set for every other tag
+    #[inline(always)]
+    fn scan<T: Copy + Eq>(&self, tags: &[T], set_inclusive_lower_bound: u64, tag: T, zero: bool) -> Option<u64> {
         let mut x = set_inclusive_lower_bound;
-        while x < set_exclusive_upper_bound {
-            // Cache hit
-            if self.cache[x as usize] == tag {
-                // Update replacement policy, report hit
-                self.replacement_policy.update_on_read(x);
-                return true;
+        while x < set_inclusive_lower_bound + self.ways() {
+            if tags[x as usize] == tag && (!zero || self.valid.contains(x)) {
+                return Some(x);
             }
             x += 1;
         }
+        None
+    }
+
+    /// Scans a set of 8 or 16 32-bit tags for a tag with SIMD instructions, finding the same line as
+    /// [scan](Cache::scan)
+    #[cfg(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64"))]
+    #[inline(always)]
+    fn scan_simd(&self, tags: &[u32], set_inclusive_lower_bound: u64, tag: u32, zero: bool) -> Option<u64> {
+        let set = &tags[set_inclusive_lower_bound as usize..(set_inclusive_lower_bound + self.ways()) as usize];
+        let mut matches = simd::matching_ways(set, tag);
+        if zero {
+            matches &= self.valid.ways(set_inclusive_lower_bound, self.ways());
+        }
+        (matches != 0).then(|| set_inclusive_lower_bound + matches.trailing_zeros() as u64)
+    }
+
+    /// Stores the tag of a line, returning the tag it replaces, and widening the tags if it
+    /// doesn't fit in 32 bits
+    #[inline(always)]
+    fn store_tag(&mut self, line: u64, tag: A) -> A {
+        if let Tags::Narrow(tags) = &mut self.tags {
+            match Self::narrow(tag) {
+                Some(narrow) => return A::from_u64(core::mem::replace(&mut tags[line as usize], narrow) as u64),
+                None => self.widen(),
+            }
+        }
+        match &mut self.tags {
+            Tags::Wide(tags) => core::mem::replace(&mut tags[line as usize], tag),
+            Tags::Narrow(_) => unreachable!("The tags were widened"),
+        }
+    }
+
+    /// Stores a tag in a line and marks the line valid, returning the tag it held if it was valid
+    #[inline(always)]
+    fn set_line(&mut self, line: u64, tag: A) -> Option<A> {
+        let previous = self.store_tag(line, tag);
+        let was_valid = self.valid.insert(line);
+        if let Some(index) = &mut self.index {
+            if was_valid {
+                index.remove(previous);
+            }
+            index.insert(tag, line);
+        }
+        was_valid.then_some(previous)
+    }
+
+    /// Converts 32-bit tags to the width of the address
+    #[cold]
+    fn widen(&mut self) {
+        if let Tags::Narrow(tags) = &self.tags {
+            self.tags = Tags::Wide(tags.iter().map(|&tag| A::from_u64(tag as u64)).collect());
+        }
+    }
+
+    /// Replaces a line in the set chosen by the replacement policy, returning the address of the
+    /// evicted line if it was valid, and the way chosen
+    fn replace_line(&mut self, set_inclusive_lower_bound: u64, set: u64, tag: A) -> LineAccess<A> {
+        let line = self.replacement_policy.get_new_line(set_inclusive_lower_bound, set, self.ways());
+        let evicted_tag = self.set_line(line, tag);
+        LineAccess {
+            hit: false,
+            evicted: evicted_tag.map(|evicted_tag| self.line_address(evicted_tag, set)),
+            way: Some(line - set_inclusive_lower_bound),
+        }
+    }
+}
+
+impl<R: ReplacementPolicy, A: Address, const WAYS: u64> CacheTrait<A> for Cache<R, A, WAYS> {
+
+    fn address_to_set_and_tag(&self, input: A) -> (u64, A) {
+        (((input & self.set_selection_bit_mask) >> self.cache_alignment_bits as u32).low_u64(), input & (self.tag_selection_bit_mask))
+    }
+
+    // Cache hit is true, cache miss is false
+    fn read_and_update_line(&mut self, input: A) -> bool {
+        let (set, _) = self.address_to_set_and_tag(input);
+        let tag = self.tag_of(input);
+        let set_inclusive_lower_bound = set * self.ways();
+        // Only search the relevant set
+        if let Some(x) = self.find_line(set_inclusive_lower_bound, tag) {
+            // Cache hit, update replacement policy, report hit
+            self.replacement_policy.update_on_read(x);
+            return true;
+        }
         // Cache miss, update
-        let line = self.replacement_policy.get_new_line(set_inclusive_lower_bound, set, self.set_size);
-        self.cache[line as usize] = tag;
+        let line = self.replacement_policy.get_new_line(set_inclusive_lower_bound, set, self.ways());
+        self.set_line(line, tag);
         false
     }
-    fn get_alignment_bit_mask(&self) -> u64 {
+    fn access_line(&mut self, input: A, allocate: bool) -> LineAccess<A> {
+        let (set, _) = self.address_to_set_and_tag(input);
+        let tag = self.tag_of(input);
+        let set_inclusive_lower_bound = set * self.ways();
+        if let Some(x) = self.find_line(set_inclusive_lower_bound, tag) {
+            self.replacement_policy.update_on_read(x);
+            return LineAccess { hit: true, evicted: None, way: None };
+        }
+        if allocate {
+            self.replace_line(set_inclusive_lower_bound, set, tag)
+        } else {
+            LineAccess { hit: false, evicted: None, way: None }
+        }
+    }
+    fn fill_line(&mut self, input: A) -> LineAccess<A> {
+        let (set, _) = self.address_to_set_and_tag(input);
+        let tag = self.tag_of(input);
+        let set_inclusive_lower_bound = set * self.ways();
+        if self.find_line(set_inclusive_lower_bound, tag).is_some() {
+            return LineAccess { hit: true, evicted: None, way: None };
+        }
+        self.replace_line(set_inclusive_lower_bound, set, tag)
+    }
+    fn way_of(&self, input: A) -> Option<u64> {
+        let (set, _) = self.address_to_set_and_tag(input);
+        let set_inclusive_lower_bound = set * self.ways();
+        self.find_line(set_inclusive_lower_bound, self.tag_of(input)).map(|line| line - set_inclusive_lower_bound)
+    }
+    fn invalidate_line(&mut self, input: A) -> bool {
+        let (set, _) = self.address_to_set_and_tag(input);
+        let tag = self.tag_of(input);
+        match self.find_line(set * self.ways(), tag) {
+            Some(x) => {
+                self.store_tag(x, A::ZERO);
+                self.valid.remove(x);
+                if let Some(index) = &mut self.index {
+                    index.remove(tag);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    fn get_alignment_bit_mask(&self) -> A {
         self.cache_alignment_bit_mask
     }
     fn get_line_size(&self) -> u64 {
         self.line_size
     }
+    fn get_set_count(&self) -> u64 {
+        self.line_count() as u64 / self.ways()
+    }
     fn get_uninitialised_line_count(&self) -> usize {
-        self.cache.iter().filter(|a| **a == 0).count()
+        self.line_count() - self.valid.count()
     }
-}
 
-/// Enum for all 4 types of cache provided by the library
-///
-/// Using trait objects in Rust reduces boilerplate, but it is surprisingly slow, as this is
-/// completely opaque to the compiler
-///
-/// For most cases this isn't an issue, but for our use case we would be de-referencing for each
-/// line in the input file, which imposes significant overhead
-///
-/// It's much faster to explicitly branch on all implementations, as the compiler can reason about
-/// the concrete types, perform function inlining etc
-pub enum GenericCache {
-    RoundRobin(Cache<RoundRobin>),
-    LeastRecentlyUsed(Cache<LeastRecentlyUsed>),
-    LeastFrequentlyUsed(Cache<LeastFrequentlyUsed>),
-    NoPolicy(Cache<NoPolicy>),
-}
+    fn advance_policy_clock(&mut self) {
+        self.replacement_policy.advance_clock()
+    }
 
-impl From<Cache<RoundRobin>> for GenericCache {
-    fn from(value: Cache<RoundRobin>) -> Self {
-        Self::RoundRobin(value)
+    fn invalidate_all(&mut self, flushed: &mut Vec<A>) {
+        let ways = self.ways();
+        for line in 0..self.line_count() as u64 {
+            if self.valid.contains(line) {
+                let tag = self.store_tag(line, A::ZERO);
+                self.valid.remove(line);
+                if let Some(index) = &mut self.index {
+                    index.remove(tag);
+                }
+                flushed.push(self.line_address(tag, line / ways));
+            }
+        }
     }
-}
 
-impl From<Cache<LeastRecentlyUsed>> for GenericCache {
-    fn from(value: Cache<LeastRecentlyUsed>) -> Self {
-        Self::LeastRecentlyUsed(value)
+    fn lines(&self) -> Vec<CacheLine<A>> {
+        let ways = self.ways();
+        (0..self.line_count() as u64).map(|index| {
+            let (set, tag) = (index / ways, self.tag_at(index));
+            CacheLine {
+                set,
+                way: index % ways,
+                address: self.valid.contains(index).then(|| self.line_address(tag, set)),
+                dirty: false,
+                policy: self.replacement_policy.line_metadata(index, set, ways),
+            }
+        }).collect()
     }
-}
 
-impl From<Cache<LeastFrequentlyUsed>> for GenericCache {
-    fn from(value: Cache<LeastFrequentlyUsed>) -> Self {
-        Self::LeastFrequentlyUsed(value)
+    fn check(&self) -> Result<(), (u64, String)> {
+        let ways = self.ways();
+        let mut valid = vec![false; ways as usize];
+        for set in 0..self.line_count() as u64 / ways {
+            let first = set * ways;
+            for way in 0..ways {
+                valid[way as usize] = self.valid.contains(first + way);
+            }
+            for way in 0..ways {
+                let tag = self.tag_at(first + way);
+                if !valid[way as usize] {
+                    if tag != A::ZERO {
+                        return Err((set, format!("way {way} isn't valid, but holds tag {tag:?} rather than 0")));
+                    }
+                    continue;
+                }
+                // The index only finds one line for each tag, so also rules out a tag held twice
+                match &self.index {
+                    Some(index) => if index.get(tag) != Some(first + way) {
+                        return Err((set, format!("way {way} holds tag {tag:?}, which the index of tags finds at {:?}", index.get(tag))));
+                    },
+                    None => if let Some(other) = (0..way).find(|&other| valid[other as usize] && self.tag_at(first + other) == tag) {
+                        return Err((set, format!("ways {other} and {way} both hold the line at {:?}", self.line_address(tag, set))));
+                    },
+                }
+            }
+            self.replacement_policy.check_set(first, set, &valid).map_err(|e| (set, e))?;
+        }
+        Ok(())
     }
-}
 
-impl From<Cache<NoPolicy>> for GenericCache {
-    fn from(value: Cache<NoPolicy>) -> Self {
-        Self::NoPolicy(value)
+    fn memory_usage(&self) -> MemoryUsage {
+        let tags = match &self.tags {
+            Tags::Narrow(tags) => size_of_val(tags.as_slice()),
+            Tags::Wide(tags) => size_of_val(tags.as_slice()),
+        };
+        MemoryUsage {
+            tags: tags + size_of_val(self.valid.words.as_slice()) + self.index.as_ref().map_or(0, TagIndex::memory_usage),
+            policy: self.replacement_policy.memory_usage(),
+        }
     }
 }
 
-impl CacheTrait for GenericCache {
-    fn address_to_set_and_tag(&self, input: u64) -> (u64, u64) {
-        match self {
-            GenericCache::RoundRobin(c) => c.address_to_set_and_tag(input),
-            GenericCache::LeastRecentlyUsed(c) => c.address_to_set_and_tag(input),
-            GenericCache::LeastFrequentlyUsed(c) => c.address_to_set_and_tag(input),
-            GenericCache::NoPolicy(c) => c.address_to_set_and_tag(input)
-        }
+/// The fewest lines a cache with one set has for its tags to be indexed by default, below which
+/// scanning the set is about as fast
+pub const INDEXED_LINES: u64 = 8;
+
+/// Whether the target has the SIMD instructions [Cache] searches sets with
+const SIMD_SCAN: bool = cfg!(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64"));
+
+/// A bitset of the lines of a cache holding a valid line
+#[derive(Clone)]
+struct ValidLines {
+    words: Vec<u64>,
+}
+
+impl ValidLines {
+    fn new(lines: u64) -> Self {
+        Self { words: vec![0; lines.div_ceil(u64::BITS as u64) as usize] }
     }
 
-    fn read_and_update_line(&mut self, input: u64) -> bool {
-        match self {
-            GenericCache::RoundRobin(c) => c.read_and_update_line(input),
-            GenericCache::LeastRecentlyUsed(c) => c.read_and_update_line(input),
-            GenericCache::LeastFrequentlyUsed(c) => c.read_and_update_line(input),
-            GenericCache::NoPolicy(c) => c.read_and_update_line(input)
-        }
+    #[inline(always)]
+    fn contains(&self, line: u64) -> bool {
+        self.words[(line / u64::BITS as u64) as usize] & (1 << (line % u64::BITS as u64)) != 0
     }
 
-    fn get_alignment_bit_mask(&self) -> u64 {
-        match self {
-            GenericCache::RoundRobin(c) => c.get_alignment_bit_mask(),
-            GenericCache::LeastRecentlyUsed(c) => c.get_alignment_bit_mask(),
-            GenericCache::LeastFrequentlyUsed(c) => c.get_alignment_bit_mask(),
-            GenericCache::NoPolicy(c) => c.get_alignment_bit_mask()
-        }
+    /// Gets the valid bits of the ways of a set, the first way in the lowest bit, for sets of up to
+    /// 32 ways which don't cross a word, as sets of a power of two ways don't
+    #[inline(always)]
+    fn ways(&self, first: u64, ways: u64) -> u32 {
+        let word = self.words[(first / u64::BITS as u64) as usize] >> (first % u64::BITS as u64);
+        (word & ((1 << ways) - 1)) as u32
     }
 
-    fn get_line_size(&self) -> u64 {
-        match self {
-            GenericCache::RoundRobin(c) => c.get_line_size(),
-            GenericCache::LeastRecentlyUsed(c) => c.get_line_size(),
-            GenericCache::LeastFrequentlyUsed(c) => c.get_line_size(),
-            GenericCache::NoPolicy(c) => c.get_line_size()
-        }
+    /// Marks a line as valid, returning whether it already was
+    #[inline(always)]
+    fn insert(&mut self, line: u64) -> bool {
+        let word = &mut self.words[(line / u64::BITS as u64) as usize];
+        let bit = 1 << (line % u64::BITS as u64);
+        let was_valid = *word & bit != 0;
+        *word |= bit;
+        was_valid
     }
 
-    fn get_uninitialised_line_count(&self) -> usize {
-        match self {
-            GenericCache::RoundRobin(c) => c.get_uninitialised_line_count(),
-            GenericCache::LeastRecentlyUsed(c) => c.get_uninitialised_line_count(),
-            GenericCache::LeastFrequentlyUsed(c) => c.get_uninitialised_line_count(),
-            GenericCache::NoPolicy(c) => c.get_uninitialised_line_count()
+    fn remove(&mut self, line: u64) {
+        self.words[(line / u64::BITS as u64) as usize] &= !(1 << (line % u64::BITS as u64));
+    }
+
+    /// Counts the valid lines, a word at a time
+    fn count(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+}
+
+/// Generates an enum with a variant holding each of a list of [Cache] types, along with `From` impls
+/// for each cache, and a [CacheTrait] impl which forwards to the cache held
+///
+/// Adding a policy only needs new lines in the list, rather than a variant, an impl and a match
+/// arm in every method
+macro_rules! generic_cache {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($(#[$variant_meta:meta])* $variant:ident($cache:ty)),* $(,)? }) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant($cache),)*
         }
+
+        $(
+            impl From<$cache> for $name {
+                fn from(value: $cache) -> Self {
+                    Self::$variant(value)
+                }
+            }
+        )*
+
+        impl CacheTrait for $name {
+            fn address_to_set_and_tag(&self, input: u64) -> (u64, u64) {
+                match self { $(Self::$variant(c) => c.address_to_set_and_tag(input),)* }
+            }
+
+            fn read_and_update_line(&mut self, input: u64) -> bool {
+                match self { $(Self::$variant(c) => c.read_and_update_line(input),)* }
+            }
+
+            fn read_and_update_lines(&mut self, inputs: &[u64], counters: &mut HitMissCounters) {
+                match self { $(Self::$variant(c) => c.read_and_update_lines(inputs, counters),)* }
+            }
+
+            fn read_and_update_lines_keeping_misses(&mut self, inputs: &[u64], misses: &mut Vec<u64>) {
+                match self { $(Self::$variant(c) => c.read_and_update_lines_keeping_misses(inputs, misses),)* }
+            }
+
+            fn access_line(&mut self, input: u64, allocate: bool) -> LineAccess {
+                match self { $(Self::$variant(c) => c.access_line(input, allocate),)* }
+            }
+
+            fn fill_line(&mut self, input: u64) -> LineAccess {
+                match self { $(Self::$variant(c) => c.fill_line(input),)* }
+            }
+
+            fn invalidate_line(&mut self, input: u64) -> bool {
+                match self { $(Self::$variant(c) => c.invalidate_line(input),)* }
+            }
+
+            fn way_of(&self, input: u64) -> Option<u64> {
+                match self { $(Self::$variant(c) => c.way_of(input),)* }
+            }
+
+            fn invalidate_all(&mut self, flushed: &mut Vec<u64>) {
+                match self { $(Self::$variant(c) => c.invalidate_all(flushed),)* }
+            }
+
+            fn get_alignment_bit_mask(&self) -> u64 {
+                match self { $(Self::$variant(c) => c.get_alignment_bit_mask(),)* }
+            }
+
+            fn get_line_size(&self) -> u64 {
+                match self { $(Self::$variant(c) => c.get_line_size(),)* }
+            }
+
+            fn get_set_count(&self) -> u64 {
+                match self { $(Self::$variant(c) => c.get_set_count(),)* }
+            }
+
+            fn get_uninitialised_line_count(&self) -> usize {
+                match self { $(Self::$variant(c) => c.get_uninitialised_line_count(),)* }
+            }
+
+            fn advance_policy_clock(&mut self) {
+                match self { $(Self::$variant(c) => c.advance_policy_clock(),)* }
+            }
+
+            fn lines(&self) -> Vec<CacheLine> {
+                match self { $(Self::$variant(c) => c.lines(),)* }
+            }
+
+            fn check(&self) -> Result<(), (u64, String)> {
+                match self { $(Self::$variant(c) => c.check(),)* }
+            }
+
+            fn memory_usage(&self) -> MemoryUsage {
+                match self { $(Self::$variant(c) => c.memory_usage(),)* }
+            }
+        }
+    };
+}
+
+generic_cache! {
+    /// Enum for the types of cache provided by the library, and for caches using policies only known
+    /// at runtime
+    ///
+    /// Using trait objects in Rust reduces boilerplate, but it is surprisingly slow, as this is
+    /// completely opaque to the compiler
+    ///
+    /// For most cases this isn't an issue, but for our use case we would be de-referencing for each
+    /// line in the input file, which imposes significant overhead
+    ///
+    /// It's much faster to explicitly branch on all implementations, as the compiler can reason about
+    /// the concrete types, perform function inlining etc. The `generic_cache!` macro writes the
+    /// branches, so a new policy only needs adding to this list
+    ///
+    /// Each policy has variants with a fixed number of ways for common associativities, whose scans
+    /// of a set are unrolled, along with one for any associativity, see benches/associativity.rs
+    /// and the measurements in the README. Timestamp lru caches are only used for sets too large
+    /// for [CompactLeastRecentlyUsed], or of a number of ways which isn't a power of two, so have no
+    /// fixed variants. Lfu caches with at least
+    /// [MIN_WAYS](HeapLeastFrequentlyUsed::MIN_WAYS) ways keep each set in a heap instead
+    #[derive(Clone)]
+    pub enum GenericCache {
+        RoundRobin(Cache<RoundRobin>),
+        RoundRobinTwoWay(Cache<RoundRobin, u64, 2>),
+        RoundRobinFourWay(Cache<RoundRobin, u64, 4>),
+        RoundRobinEightWay(Cache<RoundRobin, u64, 8>),
+        LeastRecentlyUsed(Cache<LeastRecentlyUsed>),
+        CompactLeastRecentlyUsed(Cache<CompactLeastRecentlyUsed>),
+        CompactLeastRecentlyUsedTwoWay(Cache<CompactLeastRecentlyUsed, u64, 2>),
+        CompactLeastRecentlyUsedFourWay(Cache<CompactLeastRecentlyUsed, u64, 4>),
+        CompactLeastRecentlyUsedEightWay(Cache<CompactLeastRecentlyUsed, u64, 8>),
+        LeastFrequentlyUsed(Cache<LeastFrequentlyUsed>),
+        LeastFrequentlyUsedTwoWay(Cache<LeastFrequentlyUsed, u64, 2>),
+        LeastFrequentlyUsedFourWay(Cache<LeastFrequentlyUsed, u64, 4>),
+        HeapLeastFrequentlyUsed(Cache<HeapLeastFrequentlyUsed>),
+        NoPolicy(Cache<NoPolicy>),
+        /// A cache using a policy from the registry, which is slower than the others as each call
+        /// to the policy goes through a trait object, so is only used when the policy isn't built in
+        Dynamic(Cache<Box<dyn DynamicPolicy>>),
+        /// A cache whose sets are each a cache of one of the other kinds, allocated the first time
+        /// the set is used
+        Lazy(LazyCache),
+        /// A cache split into slices, each a cache of one of the other kinds, chosen by a hash of
+        /// the address
+        Sliced(SlicedCache),
     }
-}
\ No newline at end of file
+}
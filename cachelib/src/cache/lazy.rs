@@ -0,0 +1,161 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use crate::cache::{CacheLine, CacheTrait, GenericCache, LineAccess, MemoryUsage};
+use crate::prelude::*;
+
+/// Creates the cache holding a single set of a [LazyCache]
+pub type SetFactory = Arc<dyn Fn() -> GenericCache + Send + Sync>;
+
+/// A set associative cache whose sets are only allocated the first time they're used, so a huge
+/// cache simulated against a trace with a small footprint only holds the sets the trace touches
+///
+/// Each set is a cache of its own with one set, which is given the whole address, so its tags keep
+/// the set bits, and the addresses of its evicted lines are those the whole cache would evict. Its
+/// replacement policy is created for that set alone, which evicts the same lines as one policy for
+/// every set for the built-in policies. A registered policy sharing state between sets sees each
+/// set separately.
+///
+/// Sets are found through an ordered map, which makes each access slower than for a cache allocated
+/// upfront, so this is only worth it when most of the cache is never used.
+#[derive(Clone)]
+pub struct LazyCache {
+    sets: BTreeMap<u64, GenericCache>,
+    new_set: SetFactory,
+    line_size: u64,
+    num_sets: u64,
+    ways: u64,
+    cache_alignment_bits: u32,
+    // Whether the clock of a policy for the whole cache would have passed 0, see
+    // [advance_clock](crate::replacement_policies::ReplacementPolicy::advance_clock)
+    started: bool,
+}
+
+impl LazyCache {
+    /// # Arguments
+    ///
+    /// * `size`: The size of the whole cache in bytes
+    /// * `line_size`: The size of a line in bytes
+    /// * `num_sets`: The number of sets
+    /// * `new_set`: Creates an empty cache with one set of the cache's associativity
+    ///
+    /// returns: LazyCache
+    pub fn new(size: u64, line_size: u64, num_sets: u64, new_set: SetFactory) -> Self {
+        Self {
+            sets: BTreeMap::new(),
+            new_set,
+            line_size,
+            num_sets,
+            ways: size / line_size / num_sets,
+            cache_alignment_bits: line_size.trailing_zeros(),
+            started: false,
+        }
+    }
+
+    /// Gets the number of sets which have been allocated
+    pub fn allocated_set_count(&self) -> usize {
+        self.sets.len()
+    }
+
+    /// The set an address maps to
+    #[inline(always)]
+    fn set_of(&self, input: u64) -> u64 {
+        (input >> self.cache_alignment_bits) & (self.num_sets - 1)
+    }
+
+    /// Gets the cache holding a set, allocating it if this is its first use
+    #[inline(always)]
+    fn set_mut(&mut self, set: u64) -> &mut GenericCache {
+        let (new_set, started) = (&self.new_set, &mut self.started);
+        self.sets.entry(set).or_insert_with(|| {
+            let mut cache = new_set();
+            // Only the first line used in the whole cache shares its timestamp with the empty
+            // lines, so every other set starts with its clock past it
+            if core::mem::replace(started, true) {
+                cache.advance_policy_clock();
+            }
+            cache
+        })
+    }
+}
+
+impl CacheTrait for LazyCache {
+    fn address_to_set_and_tag(&self, input: u64) -> (u64, u64) {
+        let tag_shift = self.cache_alignment_bits + self.num_sets.trailing_zeros();
+        (self.set_of(input), input & (u64::MAX << tag_shift))
+    }
+
+    fn read_and_update_line(&mut self, input: u64) -> bool {
+        self.set_mut(self.set_of(input)).read_and_update_line(input)
+    }
+
+    fn access_line(&mut self, input: u64, allocate: bool) -> LineAccess {
+        if !allocate && !self.sets.contains_key(&self.set_of(input)) {
+            return LineAccess { hit: false, evicted: None, way: None };
+        }
+        self.set_mut(self.set_of(input)).access_line(input, allocate)
+    }
+
+    fn fill_line(&mut self, input: u64) -> LineAccess {
+        self.set_mut(self.set_of(input)).fill_line(input)
+    }
+
+    fn invalidate_line(&mut self, input: u64) -> bool {
+        // A set which hasn't been allocated holds no lines, so isn't allocated to find that out
+        self.sets.get_mut(&self.set_of(input)).is_some_and(|set| set.invalidate_line(input))
+    }
+
+    fn way_of(&self, input: u64) -> Option<u64> {
+        self.sets.get(&self.set_of(input)).and_then(|set| set.way_of(input))
+    }
+
+    fn invalidate_all(&mut self, flushed: &mut Vec<u64>) {
+        for set in self.sets.values_mut() {
+            set.invalidate_all(flushed);
+        }
+    }
+
+    fn get_alignment_bit_mask(&self) -> u64 {
+        u64::MAX << self.cache_alignment_bits
+    }
+
+    fn get_line_size(&self) -> u64 {
+        self.line_size
+    }
+
+    fn get_set_count(&self) -> u64 {
+        self.num_sets
+    }
+
+    fn get_uninitialised_line_count(&self) -> usize {
+        let unallocated = (self.num_sets as usize - self.sets.len()) * self.ways as usize;
+        unallocated + self.sets.values().map(CacheTrait::get_uninitialised_line_count).sum::<usize>()
+    }
+
+    fn advance_policy_clock(&mut self) {
+        self.started = true;
+        for set in self.sets.values_mut() {
+            set.advance_policy_clock();
+        }
+    }
+
+    fn lines(&self) -> Vec<CacheLine> {
+        // Sets which haven't been allocated look like a newly created one
+        let empty = (self.new_set)().lines();
+        (0..self.num_sets).flat_map(|set| {
+            self.sets.get(&set).map_or_else(|| empty.clone(), CacheTrait::lines).into_iter().map(move |line| CacheLine { set, ..line })
+        }).collect()
+    }
+
+    fn check(&self) -> Result<(), (u64, String)> {
+        // Each set is a cache of one set, so its problems are in the set it's held for
+        self.sets.iter().try_for_each(|(&set, cache)| cache.check().map_err(|(_, e)| (set, e)))
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        // The map's entries are counted with the tags, as the bookkeeping of the sets
+        let entries = self.sets.len() * size_of::<(u64, GenericCache)>();
+        self.sets.values().map(CacheTrait::memory_usage).fold(MemoryUsage { tags: entries, policy: 0 }, |total, set| {
+            MemoryUsage { tags: total.tags + set.tags, policy: total.policy + set.policy }
+        })
+    }
+}
@@ -0,0 +1,39 @@
+// The tags of a set are contiguous, so each group of 4 ways is one load, compared with the probe
+// tag in every lane at once. The comparison of each group is then reduced to a 4-bit mask, the
+// lowest way in the lowest bit, and the masks of the groups are joined.
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+#[inline(always)]
+pub(super) fn matching_ways(tags: &[u32], tag: u32) -> u32 {
+    use core::arch::x86_64::*;
+    let mut mask = 0;
+    // SAFETY: SSE2 is enabled for the target, and each unaligned load reads exactly the 4 tags of
+    // a chunk
+    unsafe {
+        let probe = _mm_set1_epi32(tag as i32);
+        for (index, ways) in tags.chunks_exact(4).enumerate() {
+            let equal = _mm_cmpeq_epi32(_mm_loadu_si128(ways.as_ptr() as *const __m128i), probe);
+            mask |= (_mm_movemask_ps(_mm_castsi128_ps(equal)) as u32) << (4 * index);
+        }
+    }
+    mask
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(super) fn matching_ways(tags: &[u32], tag: u32) -> u32 {
+    use core::arch::aarch64::*;
+    let mut mask = 0;
+    // SAFETY: NEON is part of the aarch64 baseline, and each load reads exactly the 4 tags of a
+    // chunk, or the 4 bits of the lanes
+    unsafe {
+        let probe = vdupq_n_u32(tag);
+        let bits = vld1q_u32([1, 2, 4, 8].as_ptr());
+        for (index, ways) in tags.chunks_exact(4).enumerate() {
+            let equal = vceqq_u32(vld1q_u32(ways.as_ptr()), probe);
+            // NEON has no movemask, so each lane keeps its own bit and the lanes are summed
+            mask |= vaddvq_u32(vandq_u32(equal, bits)) << (4 * index);
+        }
+    }
+    mask
+}
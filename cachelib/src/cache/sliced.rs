@@ -0,0 +1,134 @@
+use crate::cache::{CacheLine, CacheTrait, GenericCache, LineAccess, MemoryUsage};
+use crate::prelude::*;
+
+/// The slice hash functions of Intel parts with 2, 4 and 8 slices, reverse engineered by Maurice et
+/// al. in "Reverse Engineering Intel Last-Level Cache Complex Addressing Using Performance
+/// Counters" (RAID 2015). Each bit of the slice number is the parity of the address bits in its
+/// mask, so parts with fewer slices use the first of them
+pub const INTEL_SLICE_MASKS: [u64; 3] = [0x1b5f575440, 0x2eb5faa880, 0x3cccc93100];
+
+/// A cache split into slices, as the last level caches of Intel parts are, each address going to
+/// the slice chosen by a hash of its bits and then to a set of that slice by the usual bit
+/// selection
+///
+/// Each bit of the slice number is the parity of the address bits selected by a mask. Each slice is
+/// a cache of its own with a share of the sets, which is given the whole address, so its tags keep
+/// every bit above its own set bits, and the addresses of its evicted lines are those of the whole
+/// cache. The sets of slice `s` are numbered from `s` times the sets of a slice. As with
+/// [LazyCache](crate::cache::LazyCache), a registered policy sharing state between sets sees each
+/// slice separately.
+///
+/// Bit selection puts addresses a multiple of the cache's span apart in the same set, where the
+/// hash spreads them over the slices, so strided traces conflict much less than a simple index
+/// would suggest.
+#[derive(Clone)]
+pub struct SlicedCache {
+    slices: Vec<GenericCache>,
+    masks: Vec<u64>,
+    sets_per_slice: u64,
+    cache_alignment_bits: u32,
+}
+
+impl SlicedCache {
+    /// # Arguments
+    ///
+    /// * `slices`: The slices, each an empty cache with the same geometry, a power of two of them
+    /// * `masks`: The address bits hashed for each bit of the slice number, lowest first, one for
+    ///   each bit of the number of slices
+    ///
+    /// returns: SlicedCache
+    pub fn new(slices: Vec<GenericCache>, masks: Vec<u64>) -> Self {
+        assert_eq!(1 << masks.len(), slices.len(), "{} slices can't be chosen with {} masks", slices.len(), masks.len());
+        Self {
+            sets_per_slice: slices[0].get_set_count(),
+            cache_alignment_bits: slices[0].get_line_size().trailing_zeros(),
+            slices,
+            masks,
+        }
+    }
+
+    /// Gets the slice an address maps to
+    #[inline(always)]
+    pub fn slice_of(&self, input: u64) -> usize {
+        self.masks.iter().enumerate().fold(0, |slice, (bit, mask)| slice | ((input & mask).count_ones() as usize & 1) << bit)
+    }
+}
+
+impl CacheTrait for SlicedCache {
+    fn address_to_set_and_tag(&self, input: u64) -> (u64, u64) {
+        let slice = self.slice_of(input);
+        let (set, tag) = self.slices[slice].address_to_set_and_tag(input);
+        (slice as u64 * self.sets_per_slice + set, tag)
+    }
+
+    fn read_and_update_line(&mut self, input: u64) -> bool {
+        let slice = self.slice_of(input);
+        self.slices[slice].read_and_update_line(input)
+    }
+
+    fn access_line(&mut self, input: u64, allocate: bool) -> LineAccess {
+        let slice = self.slice_of(input);
+        self.slices[slice].access_line(input, allocate)
+    }
+
+    fn fill_line(&mut self, input: u64) -> LineAccess {
+        let slice = self.slice_of(input);
+        self.slices[slice].fill_line(input)
+    }
+
+    fn invalidate_line(&mut self, input: u64) -> bool {
+        let slice = self.slice_of(input);
+        self.slices[slice].invalidate_line(input)
+    }
+
+    fn way_of(&self, input: u64) -> Option<u64> {
+        self.slices[self.slice_of(input)].way_of(input)
+    }
+
+    fn invalidate_all(&mut self, flushed: &mut Vec<u64>) {
+        for slice in &mut self.slices {
+            slice.invalidate_all(flushed);
+        }
+    }
+
+    fn get_alignment_bit_mask(&self) -> u64 {
+        u64::MAX << self.cache_alignment_bits
+    }
+
+    fn get_line_size(&self) -> u64 {
+        1 << self.cache_alignment_bits
+    }
+
+    fn get_set_count(&self) -> u64 {
+        self.sets_per_slice * self.slices.len() as u64
+    }
+
+    fn get_uninitialised_line_count(&self) -> usize {
+        self.slices.iter().map(CacheTrait::get_uninitialised_line_count).sum()
+    }
+
+    fn advance_policy_clock(&mut self) {
+        for slice in &mut self.slices {
+            slice.advance_policy_clock();
+        }
+    }
+
+    fn lines(&self) -> Vec<CacheLine> {
+        self.slices.iter().enumerate().flat_map(|(slice, cache)| {
+            let first_set = slice as u64 * self.sets_per_slice;
+            cache.lines().into_iter().map(move |line| CacheLine { set: first_set + line.set, ..line })
+        }).collect()
+    }
+
+    fn check(&self) -> Result<(), (u64, String)> {
+        self.slices.iter().enumerate().try_for_each(|(slice, cache)| {
+            cache.check().map_err(|(set, e)| (slice as u64 * self.sets_per_slice + set, e))
+        })
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.slices.iter().map(CacheTrait::memory_usage).fold(MemoryUsage { tags: 0, policy: 0 }, |total, slice| {
+            MemoryUsage { tags: total.tags + slice.tags, policy: total.policy + slice.policy }
+        })
+    }
+}
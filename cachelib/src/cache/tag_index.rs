@@ -0,0 +1,93 @@
+use crate::address::Address;
+use crate::prelude::*;
+
+/// Marks a slot holding no line
+const EMPTY: u64 = u64::MAX;
+
+/// An open addressing hash table from the tags of a cache's valid lines to the lines holding them,
+/// so a cache with one set finds a line without scanning every way
+///
+/// Probing is linear, and removals shift the later entries of a run back rather than leaving
+/// tombstones, so a lookup never probes further than the entries present need it to
+#[derive(Clone)]
+pub(super) struct TagIndex<A: Address> {
+    slots: Vec<(A, u64)>,
+    shift: u32,
+}
+
+impl<A: Address> TagIndex<A> {
+    /// Creates an index for up to `lines` lines, which keeps it at most half full
+    pub(super) fn new(lines: u64) -> Self {
+        let capacity = (lines * 2).next_power_of_two().max(2);
+        Self {
+            slots: vec![(A::ZERO, EMPTY); capacity as usize],
+            shift: u64::BITS - capacity.trailing_zeros(),
+        }
+    }
+
+    /// The slot a tag is placed in if no other tag is there, by Fibonacci hashing
+    #[inline(always)]
+    fn home(&self, tag: A) -> usize {
+        (tag.low_u64().wrapping_mul(0x9e37_79b9_7f4a_7c15) >> self.shift) as usize
+    }
+
+    /// Gets the bytes of memory the table holds
+    pub(super) fn memory_usage(&self) -> usize {
+        size_of_val(self.slots.as_slice())
+    }
+
+    /// Finds the line holding a tag
+    #[inline(always)]
+    pub(super) fn get(&self, tag: A) -> Option<u64> {
+        let mask = self.slots.len() - 1;
+        let mut slot = self.home(tag);
+        loop {
+            let (key, line) = self.slots[slot];
+            if line == EMPTY {
+                return None;
+            }
+            if key == tag {
+                return Some(line);
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    /// Adds the line holding a tag, which mustn't already be in the index
+    pub(super) fn insert(&mut self, tag: A, line: u64) {
+        let mask = self.slots.len() - 1;
+        let mut slot = self.home(tag);
+        while self.slots[slot].1 != EMPTY {
+            slot = (slot + 1) & mask;
+        }
+        self.slots[slot] = (tag, line);
+    }
+
+    /// Removes a tag, if it is in the index
+    pub(super) fn remove(&mut self, tag: A) {
+        let mask = self.slots.len() - 1;
+        let mut gap = self.home(tag);
+        loop {
+            let (key, line) = self.slots[gap];
+            if line == EMPTY {
+                return;
+            }
+            if key == tag {
+                break;
+            }
+            gap = (gap + 1) & mask;
+        }
+        // Move back each later entry of the run whose home is at or before the gap, as probing
+        // for it would otherwise stop at the gap
+        let mut next = (gap + 1) & mask;
+        while self.slots[next].1 != EMPTY {
+            let home = self.home(self.slots[next].0);
+            if next.wrapping_sub(home) & mask >= next.wrapping_sub(gap) & mask {
+                self.slots[gap] = self.slots[next];
+                gap = next;
+            }
+            next = (next + 1) & mask;
+        }
+        self.slots[gap].1 = EMPTY;
+    }
+}
@@ -1,50 +1,880 @@
-use serde::Deserialize;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::BufReader;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+#[cfg(feature = "std")]
+use substitution::substitute_variables;
+use migration::{migrate_config, CURRENT_CONFIG_VERSION, UNVERSIONED_CONFIG_VERSION};
+use crate::cache::INTEL_SLICE_MASKS;
+use crate::replacement_policies::{registered_policy, validate_policy_params, LeastFrequentlyUsed, PolicyParameter};
+use crate::topology::Topology;
+use crate::prelude::*;
+
+/// Contains the upgrading of configuration documents from older versions of the format
+pub mod migration;
+
+/// Contains the checks for suspicious but valid configurations
+pub mod lint;
+
+/// Contains the substitution of variables into configuration documents
+pub mod substitution;
+
+/// Contains the generation of random valid configurations, for differential testing
+pub mod arbitrary;
+
+/// Contains the mapping of Dinero IV command lines onto configurations
+pub mod dinero;
 
 /// A cache configuration with multiple layers
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LayeredCacheConfig {
+    /// The version of the configuration format. Documents loaded through [load_layered_config] are
+    /// migrated to the current version, see [migration]
+    #[serde(default = "unversioned_config_version")]
+    pub version: u64,
     pub caches: Vec<CacheConfig>,
+    /// The latency of a main memory access in cycles, used for timing estimates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_latency: Option<u64>,
+    /// Flushes of the caches made at regular intervals, modelling context switches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_switches: Option<ContextSwitchConfig>,
+    /// The size of a page of virtual memory. When given, accesses which cross a page boundary are
+    /// split at it and counted, as they need a translation for each page
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_optional_byte_size")]
+    pub page_size: Option<u64>,
+    /// Whether writes are simulated as writes, making lines dirty in write-back caches, rather than
+    /// as reads, as the specification counts them. Implied by any write-through cache, cache which
+    /// doesn't allocate on write misses, or writeback buffer, see
+    /// [simulates_writes](Self::simulates_writes). Defaults to false
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub simulate_writes: bool,
+}
+
+/// Context switches, which flush caches every so many accesses, as another process running in
+/// between would leave nothing of the trace's lines behind
+///
+/// Only the caches are flushed. Address translation isn't modelled, so invalidating a TLB is out of
+/// scope.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContextSwitchConfig {
+    /// The accesses between each context switch
+    pub interval: u64,
+    /// The names of the caches flushed. Defaults to every cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caches: Option<Vec<String>>,
+    /// The accesses after each context switch counted as disturbed by it, rather than steady
+    /// state. Defaults to a tenth of the interval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<u64>,
+}
+
+impl ContextSwitchConfig {
+    /// Gets the recovery period, filling in the default where applicable
+    pub fn recovery(&self) -> u64 {
+        self.recovery.unwrap_or(self.interval / 10)
+    }
+
+    /// Gets the indices of the caches flushed, in the order of the configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `caches`: The caches of the configuration
+    ///
+    /// returns: Result<Vec<usize>, String>, an error naming a cache which doesn't exist
+    pub fn cache_indices(&self, caches: &[CacheConfig]) -> Result<Vec<usize>, String> {
+        match &self.caches {
+            None => Ok((0..caches.len()).collect()),
+            Some(names) => {
+                let mut indices = names.iter()
+                    .map(|name| caches.iter().position(|cache| &cache.name == name).ok_or(format!("The context switches flush cache {name}, but there is no cache named {name}")))
+                    .collect::<Result<Vec<_>, String>>()?;
+                indices.sort_unstable();
+                indices.dedup();
+                Ok(indices)
+            }
+        }
+    }
+
+    /// Checks the context switches can be made with the caches of a configuration
+    fn validate(&self, caches: &[CacheConfig]) -> Result<(), String> {
+        if self.interval == 0 {
+            return Err("The context switches have an interval of 0, which must be at least 1 access".to_string());
+        }
+        if self.recovery() > self.interval {
+            return Err(format!("The context switches have a recovery of {} accesses, longer than their interval of {}", self.recovery(), self.interval));
+        }
+        self.cache_indices(caches).map(|_| ())
+    }
 }
 
 /// A configuration for a single cache
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CacheConfig {
     pub name: String,
+    #[serde(deserialize_with = "deserialize_byte_size")]
     pub size: u64,
+    /// The size of a line in bytes. Defaults to 64
+    #[serde(default = "default_line_size", deserialize_with = "deserialize_byte_size")]
     pub line_size: u64,
+    #[serde(default)]
     pub kind: CacheKindConfig,
-    #[serde(default = "ReplacementPolicyConfig::default")]
+    #[serde(default)]
     pub replacement_policy: ReplacementPolicyConfig,
+    /// Parameters for the replacement policy, checked against those the policy accepts
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub policy_params: Map<String, Value>,
+    /// The latency of a hit in this cache in cycles, used for timing estimates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hit_latency: Option<u64>,
+    /// The additional cycles taken by a miss in this cache. If not given, the penalty is the
+    /// access time of the next level, or the memory latency for the last level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub miss_penalty: Option<u64>,
+    #[serde(default)]
+    pub write_policy: WritePolicyConfig,
+    /// Whether a write miss allocates a line in this cache, which only write-through caches may
+    /// leave false. Defaults to true
+    #[serde(default = "default_write_allocate")]
+    pub write_allocate: bool,
+    /// A buffer for the dirty lines this cache evicts, which requires a write-back cache. Without
+    /// one, dirty lines are written below as they're evicted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub writeback_buffer: Option<WritebackBufferConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefetcher: Option<PrefetcherConfig>,
+    #[serde(default)]
+    pub inclusivity: InclusivityConfig,
+    /// The name of the cache which misses are passed to. See [Topology] for how caches are linked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    /// The cores which use this cache. See [Topology] for how caches are linked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_by: Option<Vec<u32>>,
+    /// Whether this is a first level instruction cache, which only the instruction fetches of the
+    /// cores it serves pass through. See [Topology] for how it's linked. Defaults to false
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub instructions: bool,
+    /// Whether each set's tags and policy state are allocated the first time the set is used,
+    /// rather than when the cache is created, so huge caches cost only the sets a trace touches.
+    /// Defaults to false
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub lazy_sets: bool,
+    /// Splits the cache into slices chosen by a hash of the address, as Intel last level caches
+    /// are, rather than choosing its sets by bit selection alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slices: Option<SliceConfig>,
+    /// A cache simulated on the same accesses as this one using tags alone, whose hits and misses
+    /// are reported beside this cache's, as a counterfactual without a second pass over the trace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<ShadowConfig>,
 }
 
-/// The kind of cache - direct, full, 2way, 4way, or 8way
-#[derive(Debug, Deserialize)]
+impl CacheConfig {
+    /// Checks the size, line size and kind give a power of two number of whole sets, as sets are
+    /// chosen by bit selection
+    ///
+    /// # Arguments
+    ///
+    /// * `cache`: How the cache is referred to in errors, such as `Cache L1`
+    ///
+    /// returns: Result<(), String>
+    fn validate_geometry(&self, cache: &str) -> Result<(), String> {
+        if !self.line_size.is_power_of_two() {
+            return Err(format!("{cache} has a line size of {} bytes, which must be a power of two", self.line_size));
+        }
+        if self.size < self.line_size || !self.size.is_multiple_of(self.line_size) {
+            return Err(format!("{cache} has a size of {} bytes, which must be a whole number of its {}-byte lines", self.size, self.line_size));
+        }
+        let lines = self.size / self.line_size;
+        let ways = self.kind.ways(lines);
+        if ways > lines {
+            return Err(format!("{cache} has {lines} lines, too few for {ways} ways"));
+        }
+        if !lines.is_multiple_of(ways) || !(lines / ways).is_power_of_two() {
+            return Err(format!("{cache} has {lines} lines, which don't make a power of two number of {ways}-way sets"));
+        }
+        Ok(())
+    }
+}
+
+fn default_line_size() -> u64 {
+    64
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// The kind of cache - direct, full, 2way, 4way, 8way, or 16way. Defaults to 8way
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub enum CacheKindConfig {
-    #[serde(alias = "direct")]
+    #[serde(rename = "direct", alias = "Direct")]
     Direct,
-    #[serde(alias = "full")]
+    #[serde(rename = "full", alias = "Full")]
     Full,
-    #[serde(alias = "2way")]
+    #[serde(rename = "2way", alias = "TwoWay")]
     TwoWay,
-    #[serde(alias = "4way")]
+    #[serde(rename = "4way", alias = "FourWay")]
     FourWay,
-    #[serde(alias = "8way")]
+    #[default]
+    #[serde(rename = "8way", alias = "EightWay")]
     EightWay,
+    #[serde(rename = "16way")]
+    SixteenWay,
+}
+
+impl CacheKindConfig {
+    /// Gets the number of ways in each set of a cache of this kind
+    ///
+    /// # Arguments
+    ///
+    /// * `lines`: The number of lines in the cache, which is the number of ways if fully associative
+    ///
+    /// returns: u64
+    pub fn ways(&self, lines: u64) -> u64 {
+        match self {
+            CacheKindConfig::Direct => 1,
+            CacheKindConfig::Full => lines,
+            CacheKindConfig::TwoWay => 2,
+            CacheKindConfig::FourWay => 4,
+            CacheKindConfig::EightWay => 8,
+            CacheKindConfig::SixteenWay => 16,
+        }
+    }
 }
 
-/// The replacement policy, if applicable - round robin, lru, or lfu. Defaults to round robin.
-#[derive(Debug, Copy, Clone, Deserialize)]
+/// The replacement policy, if applicable - round robin, lru, lfu, or the name of a policy
+/// registered with [register_policy](crate::replacement_policies::register_policy). Defaults to
+/// lru, or to round robin in configurations from before version 2
+///
+/// Any name is accepted when parsing, names which aren't built in are checked against the registry
+/// by [validate](LayeredCacheConfig::validate)
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum ReplacementPolicyConfig {
-    #[serde(alias = "rr")]
     RoundRobin,
-    #[serde(alias = "lru")]
+    #[default]
     LeastRecentlyUsed,
-    #[serde(alias = "lfu")]
     LeastFrequentlyUsed,
+    Registered(String),
+}
+
+impl From<String> for ReplacementPolicyConfig {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "rr" | "RoundRobin" => ReplacementPolicyConfig::RoundRobin,
+            "lru" | "LeastRecentlyUsed" => ReplacementPolicyConfig::LeastRecentlyUsed,
+            "lfu" | "LeastFrequentlyUsed" => ReplacementPolicyConfig::LeastFrequentlyUsed,
+            _ => ReplacementPolicyConfig::Registered(name),
+        }
+    }
+}
+
+impl From<ReplacementPolicyConfig> for String {
+    fn from(policy: ReplacementPolicyConfig) -> Self {
+        policy.name().to_string()
+    }
+}
+
+impl ReplacementPolicyConfig {
+    /// Gets the name of the policy, as used in configuration files
+    pub fn name(&self) -> &str {
+        match self {
+            ReplacementPolicyConfig::RoundRobin => "rr",
+            ReplacementPolicyConfig::LeastRecentlyUsed => "lru",
+            ReplacementPolicyConfig::LeastFrequentlyUsed => "lfu",
+            ReplacementPolicyConfig::Registered(name) => name,
+        }
+    }
+
+    /// Gets the parameters the policy accepts through `policy_params`
+    ///
+    /// returns: Result<Vec<PolicyParameter>, String>, an error if the policy isn't built in or
+    /// registered
+    pub fn parameters(&self) -> Result<Vec<PolicyParameter>, String> {
+        match self {
+            ReplacementPolicyConfig::RoundRobin => Ok(Vec::new()),
+            ReplacementPolicyConfig::LeastRecentlyUsed => Ok(Vec::new()),
+            ReplacementPolicyConfig::LeastFrequentlyUsed => Ok(LeastFrequentlyUsed::PARAMETERS.to_vec()),
+            ReplacementPolicyConfig::Registered(name) => registered_policy(name).map(|policy| policy.parameters),
+        }
+    }
+}
+
+/// How a cache relates to the caches above it - inclusive, exclusive, or NINE (non-inclusive
+/// non-exclusive). Defaults to NINE.
+///
+/// An inclusive cache holds every line held by the caches above it, invalidating lines above it
+/// when it evicts them. An exclusive cache holds no line held by the cache directly above it; it is
+/// filled by that cache's evictions, and lines which hit in it move up.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum InclusivityConfig {
+    #[serde(rename = "inclusive", alias = "Inclusive")]
+    Inclusive,
+    #[serde(rename = "exclusive", alias = "Exclusive")]
+    Exclusive,
+    #[default]
+    #[serde(rename = "nine", alias = "Nine", alias = "NINE")]
+    Nine,
+}
+
+/// The write policy - write-back or write-through. Defaults to write-back.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum WritePolicyConfig {
+    #[default]
+    #[serde(rename = "write-back", alias = "WriteBack", alias = "wb")]
+    WriteBack,
+    #[serde(rename = "write-through", alias = "WriteThrough", alias = "wt")]
+    WriteThrough,
+}
+
+fn unversioned_config_version() -> u64 {
+    UNVERSIONED_CONFIG_VERSION
+}
+
+fn default_write_allocate() -> bool {
+    true
+}
+
+/// A buffer between a write-back cache and the level below, which holds the dirty lines the cache
+/// evicts until they're written below
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WritebackBufferConfig {
+    /// The number of lines the buffer holds
+    pub entries: u64,
+    /// The accesses to the cache between each line written from the buffer to the level below.
+    /// Defaults to 8
+    #[serde(default = "default_drain_interval")]
+    pub drain_interval: u64,
+}
+
+fn default_drain_interval() -> u64 {
+    8
+}
+
+impl WritebackBufferConfig {
+    /// Checks the buffer can be used by a cache
+    fn validate(&self, cache: &CacheConfig) -> Result<(), String> {
+        if cache.write_policy != WritePolicyConfig::WriteBack {
+            return Err(format!("Cache {} has a writeback buffer, but is write-through, so never has dirty lines to buffer", cache.name));
+        }
+        if cache.inclusivity == InclusivityConfig::Exclusive {
+            return Err(format!("Cache {} has a writeback buffer, which isn't supported for exclusive caches", cache.name));
+        }
+        if self.entries == 0 {
+            return Err(format!("The writeback buffer of cache {} has no entries", cache.name));
+        }
+        if self.drain_interval == 0 {
+            return Err(format!("The writeback buffer of cache {} has a drain_interval of 0, which must be at least 1 access", cache.name));
+        }
+        Ok(())
+    }
+}
+
+/// The slices of a cache, each holding an equal share of its sets. Each bit of the slice an address
+/// goes to is the parity of the address bits selected by a mask, and the set within the slice is
+/// chosen by bit selection as usual
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SliceConfig {
+    /// The number of slices, a power of two
+    pub count: u64,
+    /// The mask of each bit of the slice number, lowest first, given as numbers or hexadecimal
+    /// strings. Defaults to the hash functions of Intel parts with 2, 4 or 8 slices, see
+    /// [INTEL_SLICE_MASKS](crate::cache::INTEL_SLICE_MASKS)
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_optional_masks")]
+    pub masks: Option<Vec<u64>>,
+}
+
+impl SliceConfig {
+    /// Gets the masks, filling in the default where applicable
+    pub fn masks(&self) -> Vec<u64> {
+        self.masks.clone().unwrap_or_else(|| INTEL_SLICE_MASKS.iter().copied().take(self.count.trailing_zeros() as usize).collect())
+    }
+
+    /// Checks the cache can be split into the slices
+    fn validate(&self, cache: &CacheConfig) -> Result<(), String> {
+        if !self.count.is_power_of_two() || self.count < 2 {
+            return Err(format!("Cache {} has {} slices, which must be a power of two of at least 2", cache.name, self.count));
+        }
+        let lines = cache.size / cache.line_size;
+        let sets = lines / cache.kind.ways(lines);
+        if sets < self.count {
+            return Err(format!("Cache {} has {sets} sets, too few to split between {} slices", cache.name, self.count));
+        }
+        match &self.masks {
+            None if self.count > 1 << INTEL_SLICE_MASKS.len() => {
+                Err(format!("Cache {} has {} slices, but the Intel hash only chooses between up to {}, give the masks for more", cache.name, self.count, 1 << INTEL_SLICE_MASKS.len()))
+            }
+            Some(masks) if 1 << masks.len() != self.count => {
+                Err(format!("Cache {} has {} slices, which need {} masks, not {}", cache.name, self.count, self.count.trailing_zeros(), masks.len()))
+            }
+            Some(masks) if masks.iter().any(|&mask| mask & (u64::MAX << cache.line_size.trailing_zeros()) == 0) => {
+                Err(format!("Cache {} has a slice mask selecting none of the address bits above the offset within a line", cache.name))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Deserialises slice masks, each given either as an integer or a hexadecimal string starting with
+/// `0x`
+fn deserialize_optional_masks<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u64>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Mask {
+        Number(u64),
+        Text(String),
+    }
+    Vec::<Mask>::deserialize(deserializer)?.into_iter()
+        .map(|mask| match mask {
+            Mask::Number(mask) => Ok(mask),
+            Mask::Text(text) => text.strip_prefix("0x")
+                .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| serde::de::Error::custom(format!("The slice mask \"{text}\" must be a number or a hexadecimal string starting with 0x"))),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/// The shadow of a cache, which reads the lines of each access to the cache and nothing else. Each
+/// setting left out is the same as the cache's, so a shadow of `{"replacement_policy": "lru"}` is
+/// the cache with LRU replacement
+///
+/// The shadow has no effect on the hierarchy: it isn't inclusive or exclusive, has no prefetcher or
+/// writeback buffer, and reads the line of a write as it would any other access.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShadowConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_optional_byte_size")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<CacheKindConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement_policy: Option<ReplacementPolicyConfig>,
+    /// Parameters for the shadow's replacement policy. Defaults to the cache's if the policy is the
+    /// same, or to none if it differs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_params: Option<Map<String, Value>>,
+}
+
+impl ShadowConfig {
+    /// Gets the configuration of the cache simulated as the shadow of a cache, with the settings of
+    /// the shadow and the line size, slices and lazy sets of the cache
+    pub fn cache_config(&self, cache: &CacheConfig) -> CacheConfig {
+        let replacement_policy = self.replacement_policy.clone().unwrap_or_else(|| cache.replacement_policy.clone());
+        let policy_params = match &self.policy_params {
+            Some(params) => params.clone(),
+            None if replacement_policy == cache.replacement_policy => cache.policy_params.clone(),
+            None => Map::new(),
+        };
+        CacheConfig {
+            name: format!("{} shadow", cache.name),
+            size: self.size.unwrap_or(cache.size),
+            kind: self.kind.unwrap_or(cache.kind),
+            replacement_policy,
+            policy_params,
+            writeback_buffer: None,
+            prefetcher: None,
+            inclusivity: InclusivityConfig::Nine,
+            next: None,
+            shared_by: None,
+            instructions: false,
+            shadow: None,
+            ..cache.clone()
+        }
+    }
+
+    /// Checks the shadow of a cache can be simulated
+    fn validate(&self, cache: &CacheConfig) -> Result<(), String> {
+        let shadow = self.cache_config(cache);
+        shadow.validate_geometry(&format!("The shadow of cache {}", cache.name))?;
+        if let Some(slices) = &shadow.slices {
+            slices.validate(&shadow)?;
+        }
+        shadow.replacement_policy.parameters()
+            .and_then(|parameters| validate_policy_params(shadow.replacement_policy.name(), &parameters, &shadow.policy_params))
+            .map_err(|e| format!("The shadow of cache {}: {e}", cache.name))
+    }
+}
+
+/// A prefetcher attached to a cache
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrefetcherConfig {
+    pub kind: PrefetcherKindConfig,
+    /// The number of lines prefetched each time the prefetcher triggers. Defaults to 1
+    #[serde(default = "default_prefetch_degree")]
+    pub degree: u64,
+    /// How far ahead the first prefetched line is, in lines for next-line prefetchers and in
+    /// strides for stride prefetchers. Defaults to 1
+    #[serde(default = "default_prefetch_distance")]
+    pub distance: u64,
+    /// The number of entries in the stride prefetcher's reference prediction table. Must be a power
+    /// of two, defaults to 64. Not applicable to next-line prefetchers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table_size: Option<u64>,
+}
+
+/// The kind of prefetcher - next-line or stride
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PrefetcherKindConfig {
+    #[serde(rename = "next-line", alias = "NextLine")]
+    NextLine,
+    #[serde(rename = "stride", alias = "Stride")]
+    Stride,
+}
+
+const DEFAULT_STRIDE_TABLE_SIZE: u64 = 64;
+
+fn default_prefetch_degree() -> u64 {
+    1
+}
+
+fn default_prefetch_distance() -> u64 {
+    1
+}
+
+impl PrefetcherConfig {
+    /// Gets the table size, filling in the default where applicable
+    pub fn table_size(&self) -> u64 {
+        self.table_size.unwrap_or(DEFAULT_STRIDE_TABLE_SIZE)
+    }
+
+    /// Checks the parameters are valid for the kind of prefetcher
+    fn validate(&self, cache_name: &str) -> Result<(), String> {
+        if self.degree == 0 {
+            return Err(format!("The prefetcher for cache {cache_name} has a degree of 0, it would never prefetch"));
+        }
+        if self.distance == 0 {
+            return Err(format!("The prefetcher for cache {cache_name} has a distance of 0, it would only prefetch lines already being accessed"));
+        }
+        match self.kind {
+            PrefetcherKindConfig::NextLine => {
+                if self.table_size.is_some() {
+                    return Err(format!("The next-line prefetcher for cache {cache_name} has a table_size, which only applies to stride prefetchers"));
+                }
+            }
+            PrefetcherKindConfig::Stride => {
+                if !self.table_size().is_power_of_two() {
+                    return Err(format!("The stride prefetcher for cache {cache_name} has a table_size of {}, which must be a power of two", self.table_size()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LayeredCacheConfig {
+    /// Whether writes are simulated as writes rather than as reads, which is when asked for with
+    /// `simulate_writes`, or when any cache is write-through, doesn't allocate on write misses or
+    /// has a writeback buffer, as they would do nothing otherwise
+    pub fn simulates_writes(&self) -> bool {
+        self.simulate_writes || self.caches.iter().any(|cache| {
+            cache.write_policy == WritePolicyConfig::WriteThrough || !cache.write_allocate || cache.writeback_buffer.is_some()
+        })
+    }
+
+    /// Checks the configuration for combinations of settings which can't be simulated
+    ///
+    /// returns: Result<(), String>, with a description of the first problem found
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version > CURRENT_CONFIG_VERSION {
+            return Err(format!("The config is version {}, but this simulator only supports versions up to {CURRENT_CONFIG_VERSION}", self.version));
+        }
+        if self.caches.is_empty() {
+            return Err("The list of caches is empty".to_string());
+        }
+        for (index, cache) in self.caches.iter().enumerate() {
+            if self.caches[..index].iter().any(|c| c.name == cache.name) {
+                return Err(format!("There is more than one cache named {}", cache.name));
+            }
+            cache.validate_geometry(&format!("Cache {}", cache.name))?;
+            if cache.write_policy == WritePolicyConfig::WriteBack && !cache.write_allocate {
+                return Err(format!("Cache {} is write-back without write allocation, which isn't supported; write-back caches must allocate on write misses", cache.name));
+            }
+            if let Some(prefetcher) = &cache.prefetcher {
+                prefetcher.validate(&cache.name)?;
+            }
+            if let Some(buffer) = &cache.writeback_buffer {
+                buffer.validate(cache)?;
+            }
+            if let Some(slices) = &cache.slices {
+                slices.validate(cache)?;
+            }
+            if let Some(shadow) = &cache.shadow {
+                shadow.validate(cache)?;
+            }
+            cache.replacement_policy.parameters()
+                .and_then(|parameters| validate_policy_params(cache.replacement_policy.name(), &parameters, &cache.policy_params))
+                .map_err(|e| format!("Cache {}: {e}", cache.name))?;
+        }
+        if let Some(context_switches) = &self.context_switches {
+            context_switches.validate(&self.caches)?;
+        }
+        if let Some(page_size) = self.page_size {
+            let line_size = self.caches.iter().map(|cache| cache.line_size).max().unwrap();
+            if !page_size.is_power_of_two() || page_size < line_size {
+                return Err(format!("The page size must be a power of two of at least the largest line size, {line_size} bytes, not {page_size}"));
+            }
+        }
+        Topology::from_config(self).map(|_| ())
+    }
+}
+
+/// Parses a human-readable byte size, such as `64B`, `32KiB` or `2MiB`
+///
+/// Binary units are used throughout; `K`, `M` and `G` are accepted as shorthands for `KiB`, `MiB`
+/// and `GiB`. `KB`, `MB` and `GB` are rejected, as they are ambiguous between decimal and binary
+/// units, which is an easy way to end up with a cache 2.4% smaller than intended. A bare number is
+/// taken as a number of bytes.
+///
+/// # Arguments
+///
+/// * `input`: The size to parse
+///
+/// returns: Result<u64, String>
+///
+/// # Examples
+///
+/// ```
+/// use cachelib::config::parse_byte_size;
+/// assert_eq!(parse_byte_size("32KiB"), Ok(32 * 1024));
+/// assert_eq!(parse_byte_size("64 B"), Ok(64));
+/// assert!(parse_byte_size("32KB").is_err());
+/// ```
+pub fn parse_byte_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (digits, unit) = input.split_at(split);
+    let value: u64 = digits.parse().map_err(|_| format!("The size \"{input}\" must start with a whole number of bytes"))?;
+    let multiplier: u64 = match unit.trim_start() {
+        "" | "B" => 1,
+        "K" | "KiB" => 1 << 10,
+        "M" | "MiB" => 1 << 20,
+        "G" | "GiB" => 1 << 30,
+        "KB" | "kB" | "MB" | "GB" => return Err(format!("The unit in the size \"{input}\" is ambiguous, use KiB, MiB or GiB instead")),
+        other => return Err(format!("Unknown unit \"{other}\" in the size \"{input}\", expected B, KiB, MiB or GiB")),
+    };
+    value.checked_mul(multiplier).ok_or(format!("The size \"{input}\" is too large"))
+}
+
+/// Deserialises a byte size, given either as an integer or a string accepted by [parse_byte_size]
+fn deserialize_byte_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ByteSize {
+        Bytes(u64),
+        Text(String),
+    }
+    match ByteSize::deserialize(deserializer)? {
+        ByteSize::Bytes(bytes) => Ok(bytes),
+        ByteSize::Text(text) => parse_byte_size(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Deserialises a byte size which may be left out, see [deserialize_byte_size]
+fn deserialize_optional_byte_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    deserialize_byte_size(deserializer).map(Some)
+}
+
+/// The key used by configuration fragments to include other fragments
+#[cfg(feature = "std")]
+const INCLUDE_KEY: &str = "include";
+
+/// Loads a configuration from one or more files, merging each fragment over the previous ones, and
+/// validates the result
+///
+/// Any deprecation warnings from migrating the configuration to the current version are discarded,
+/// use [load_layered_config_with_warnings] to report them.
+///
+/// Each file may contain an `include` key, holding a path or a list of paths to other fragments.
+/// Included fragments are merged first, in order, and the including file is then merged on top, so
+/// a base hierarchy can be shared across many experiments with only the varying fields overridden.
+/// Relative include paths are resolved against the directory of the including file.
+///
+/// Objects are merged key by key, and caches are matched by name, so an override only needs to
+/// state the name of the cache and the fields which differ. Caches with new names are appended to
+/// the hierarchy. Any other value in a later fragment replaces the earlier value outright.
+///
+/// Environment variables are substituted into each fragment as it is read, including its include
+/// paths. See [substitute_variables] for the syntax.
+///
+/// # Arguments
+///
+/// * `paths`: The configuration fragments, in order of increasing precedence
+///
+/// returns: Result<LayeredCacheConfig, String>
+#[cfg(feature = "std")]
+pub fn load_layered_config<P: AsRef<Path>>(paths: &[P]) -> Result<LayeredCacheConfig, String> {
+    load_layered_config_with_warnings(paths).map(|(config, _)| config)
+}
+
+/// Loads a configuration in the same way as [load_layered_config], also returning the deprecation
+/// warnings from migrating it to the current version of the format
+///
+/// The version of the merged document is used, so fragments overriding a versioned base don't need
+/// to declare a version themselves.
+///
+/// # Arguments
+///
+/// * `paths`: The configuration fragments, in order of increasing precedence
+///
+/// returns: Result<(LayeredCacheConfig, Vec<String>), String>
+#[cfg(feature = "std")]
+pub fn load_layered_config_with_warnings<P: AsRef<Path>>(paths: &[P]) -> Result<(LayeredCacheConfig, Vec<String>), String> {
+    let mut merged = Value::Object(Map::new());
+    for path in paths {
+        let fragment = load_fragment(path.as_ref(), &mut Vec::new())?;
+        merge_fragments(&mut merged, fragment);
+    }
+    config_from_value(merged)
+}
+
+/// Migrates, parses and validates a configuration document which is already in memory, returning
+/// the deprecation warnings from migrating it
+///
+/// Unlike [load_layered_config], includes and variables aren't resolved, so a document from an
+/// untrusted source can't read files or the environment.
+///
+/// # Arguments
+///
+/// * `document`: The configuration document
+///
+/// returns: Result<(LayeredCacheConfig, Vec<String>), String>
+pub fn config_from_value(mut document: Value) -> Result<(LayeredCacheConfig, Vec<String>), String> {
+    let warnings = migrate_config(&mut document)?;
+    let config: LayeredCacheConfig = serde_json::from_value(document).map_err(|e| format!("Couldn't parse the config file: {e}"))?;
+    config.validate()?;
+    Ok((config, warnings))
+}
+
+/// Reads a single fragment, recursively resolving its includes
+///
+/// `stack` holds the canonical paths of the fragments currently being loaded, to detect cycles
+#[cfg(feature = "std")]
+fn load_fragment(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Value, String> {
+    let canonical = path.canonicalize().map_err(|e| format!("Couldn't open the config file at path {}: {e}", path.display()))?;
+    if stack.contains(&canonical) {
+        return Err(format!("The config file at path {} includes itself", path.display()));
+    }
+    let file = File::open(&canonical).map_err(|e| format!("Couldn't open the config file at path {}: {e}", path.display()))?;
+    let mut fragment: Value = serde_json::from_reader(BufReader::new(file)).map_err(|e| format!("Couldn't parse the config file at path {}: {e}", path.display()))?;
+    substitute_variables(&mut fragment, &|name| std::env::var(name).ok()).map_err(|e| format!("In the config file at path {}: {e}", path.display()))?;
+    let includes = match fragment.as_object_mut().and_then(|o| o.remove(INCLUDE_KEY)) {
+        None => Vec::new(),
+        Some(Value::String(include)) => vec![include],
+        Some(Value::Array(includes)) => includes.into_iter()
+            .map(|i| i.as_str().map(str::to_string).ok_or(format!("Included paths in {} must be strings", path.display())))
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(format!("The include key in {} must be a path or a list of paths", path.display())),
+    };
+    if includes.is_empty() {
+        return Ok(fragment);
+    }
+    stack.push(canonical.clone());
+    let base_directory = canonical.parent().unwrap_or(Path::new(""));
+    let mut merged = Value::Object(Map::new());
+    for include in includes {
+        merge_fragments(&mut merged, load_fragment(&base_directory.join(include), stack)?);
+    }
+    stack.pop();
+    merge_fragments(&mut merged, fragment);
+    Ok(merged)
+}
+
+/// Merges `overlay` into `base`. See [load_layered_config] for the merging rules
+#[cfg(feature = "std")]
+fn merge_fragments(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_fragments(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) if overlay.iter().all(|v| v.get("name").is_some()) => {
+            for value in overlay {
+                match base.iter_mut().find(|existing| existing.get("name") == value.get("name")) {
+                    Some(existing) => merge_fragments(existing, value),
+                    None => base.push(value),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// A difference between two configurations, found by [diff_configs]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigDifference {
+    /// The path to the field, such as `caches[L2].replacement_policy`
+    pub path: String,
+    /// The value in the first configuration, or None if the field is only in the second
+    pub left: Option<Value>,
+    /// The value in the second configuration, or None if the field is only in the first
+    pub right: Option<Value>,
+}
+
+/// Finds the field-level differences between two configurations
+///
+/// The configurations are compared after defaults have been filled in, so a field which is set to
+/// its default in one and left out of the other doesn't count as a difference. Caches are matched by
+/// name rather than position.
+///
+/// # Arguments
+///
+/// * `left`: The first configuration
+/// * `right`: The second configuration
+///
+/// returns: Vec<ConfigDifference>, empty if the configurations are equivalent
+pub fn diff_configs(left: &LayeredCacheConfig, right: &LayeredCacheConfig) -> Vec<ConfigDifference> {
+    let mut differences = Vec::new();
+    // Serialising these types can't fail, they only contain strings, numbers and maps with string keys
+    let left = serde_json::to_value(left).unwrap();
+    let right = serde_json::to_value(right).unwrap();
+    diff_values(String::new(), Some(&left), Some(&right), &mut differences);
+    differences
 }
 
-impl Default for ReplacementPolicyConfig {
-    fn default() -> Self {
-        ReplacementPolicyConfig::RoundRobin
+fn diff_values(path: String, left: Option<&Value>, right: Option<&Value>, differences: &mut Vec<ConfigDifference>) {
+    match (left, right) {
+        (Some(Value::Object(left)), Some(Value::Object(right))) => {
+            let mut keys: Vec<&String> = left.keys().chain(right.keys().filter(|k| !left.contains_key(*k))).collect();
+            keys.sort();
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+                diff_values(child, left.get(key), right.get(key), differences);
+            }
+        }
+        (Some(Value::Array(left)), Some(Value::Array(right))) if left.iter().chain(right).all(|v| v.get("name").is_some()) => {
+            let name = |v: &Value| v["name"].as_str().map(str::to_string).unwrap_or_else(|| v["name"].to_string());
+            for l in left {
+                let r = right.iter().find(|r| r.get("name") == l.get("name"));
+                diff_values(format!("{path}[{}]", name(l)), Some(l), r, differences);
+            }
+            for r in right.iter().filter(|r| !left.iter().any(|l| l.get("name") == r.get("name"))) {
+                diff_values(format!("{path}[{}]", name(r)), None, Some(r), differences);
+            }
+        }
+        (left, right) if left != right => differences.push(ConfigDifference {
+            path,
+            left: left.cloned(),
+            right: right.cloned(),
+        }),
+        _ => {}
     }
 }
@@ -0,0 +1,73 @@
+use serde_json::json;
+use crate::config::LayeredCacheConfig;
+use crate::config::migration::CURRENT_CONFIG_VERSION;
+use crate::prelude::*;
+
+/// A small, seedable pseudo-random number generator (SplitMix64), so generated configurations can
+/// be reproduced from their seed
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Gets a number from 0 up to, but not including, `bound`
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Picks an element of a non-empty slice
+    pub fn choose<'a, T>(&mut self, options: &'a [T]) -> &'a T {
+        &options[self.below(options.len() as u64) as usize]
+    }
+}
+
+/// Generates a random, valid cache hierarchy
+///
+/// The hierarchy is a chain of one to three levels, with power of two sizes, line sizes which
+/// don't decrease with level, and at least as many lines as ways, using any of the built-in kinds
+/// and policies. Features the reference simulator doesn't model, such as prefetchers, inclusivity
+/// and explicit topologies, are left at their defaults.
+///
+/// # Arguments
+///
+/// * `rng`: The source of randomness
+///
+/// returns: LayeredCacheConfig
+pub fn arbitrary_config(rng: &mut SplitMix64) -> LayeredCacheConfig {
+    let levels = 1 + rng.below(3);
+    let mut line_size = *rng.choose(&[16, 32, 64]);
+    let mut caches = Vec::new();
+    for level in 1..=levels {
+        if level > 1 && rng.below(2) == 0 {
+            line_size *= 2;
+        }
+        // At least 8 lines, so every kind has at least one set
+        let lines = 1u64 << (3 + rng.below(6));
+        let mut cache = json!({
+            "name": format!("L{level}"),
+            "size": lines * line_size,
+            "line_size": line_size,
+            "kind": rng.choose(&["direct", "full", "2way", "4way", "8way"]),
+            "replacement_policy": rng.choose(&["rr", "lru", "lfu"]),
+        });
+        if cache["replacement_policy"] == "lfu" && rng.below(2) == 0 {
+            cache["policy_params"] = json!({"counter_bits": 1 + rng.below(8)});
+        }
+        caches.push(cache);
+    }
+    serde_json::from_value(json!({"version": CURRENT_CONFIG_VERSION, "caches": caches}))
+        .expect("Generated configurations should always be well formed")
+}
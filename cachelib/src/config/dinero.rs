@@ -0,0 +1,247 @@
+use alloc::collections::BTreeMap;
+use serde_json::Map;
+use crate::config::migration::CURRENT_CONFIG_VERSION;
+use crate::config::{CacheConfig, CacheKindConfig, ContextSwitchConfig, InclusivityConfig, LayeredCacheConfig, ReplacementPolicyConfig, WritePolicyConfig};
+use crate::layered::AccessKind;
+use crate::prelude::*;
+use crate::simulator::Access;
+
+/// The levels of cache Dinero IV can simulate
+const DINERO_LEVELS: u32 = 5;
+
+/// A Dinero IV command line, mapped onto a configuration, see [parse_dinero_options]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DineroOptions {
+    /// The hierarchy of the `-lN-T...` options, with a cache for each named `lN-Tcache`, as Dinero
+    /// names them in its output. With `-flushcount`, it has context switches flushing every cache
+    pub config: LayeredCacheConfig,
+    /// The references skipped before simulating, from `-skipcount`
+    pub skip_count: u64,
+    /// The most references simulated after those skipped, from `-maxcount`
+    pub max_count: Option<u64>,
+    /// The options which were accepted but don't change the results, with why
+    pub ignored: Vec<String>,
+}
+
+/// The settings of one of Dinero's caches, as given on the command line
+#[derive(Debug, Clone, Default)]
+struct DineroCache {
+    size: Option<u64>,
+    block_size: Option<u64>,
+    sub_block_size: Option<u64>,
+    associativity: Option<u64>,
+    replacement: Option<String>,
+    fetch: Option<String>,
+    write_allocate: Option<String>,
+    write_back: Option<String>,
+}
+
+/// Parses the options of a Dinero IV command line, such as
+/// `-l1-dsize 16k -l1-dbsize 32 -l1-dassoc 2`, so scripts written for Dinero can run unchanged
+///
+/// Each cache is given by options of the form `-lN-Tparameter`, where `N` is the level, from 1 to
+/// 5, and `T` is `u` for a unified cache, or `i` or `d` for the instruction and data caches of a
+/// split first level. Every level up to the last needs a cache, and each cache a `size` and
+/// `bsize`. Sizes and counts may end in `k`, `m` or `g`, powers of 1024 as in Dinero.
+///
+/// Dinero's options map onto the configuration as follows. Anything else is rejected, rather than
+/// giving results which differ from Dinero's.
+///
+/// * `assoc`: the ways of each set, 1 by default. 1, 2, 4, 8 and 16 ways and fully associative
+///   caches are supported
+/// * `repl`: `l` for LRU, the default, or `f` for FIFO, which is round robin
+/// * `fetch`: only `d`, demand fetching, the default
+/// * `wback` and `walloc`: `a` or `n`, for write-back and write-allocate or not, both `a` by
+///   default. Writes are simulated, so dirty blocks are written to the level below as Dinero copies
+///   them back, and a write-back cache must allocate on write misses
+/// * `sbsize`: only the same as `bsize`, as sub-blocks aren't modelled
+/// * `ccc`, `-stat-interval` and `-stat-idcombine` are accepted, but only change what Dinero prints
+/// * `-informat`: only `d` or `D`, din traces, see [parse_din_record]
+/// * `-skipcount`, `-maxcount`: the references skipped, and the most simulated after them
+/// * `-flushcount`: the references between flushes of every cache, as context switches
+///
+/// # Arguments
+///
+/// * `args`: The options, without the name of the program
+///
+/// returns: Result<DineroOptions, String>, an error for an option which isn't supported, or if
+/// the configuration isn't valid
+pub fn parse_dinero_options(args: &[String]) -> Result<DineroOptions, String> {
+    let mut caches: BTreeMap<(u32, char), DineroCache> = BTreeMap::new();
+    let (mut skip_count, mut max_count, mut flush_count) = (0, None, 0);
+    let mut ignored = Vec::new();
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        let mut value = || args.next().map(String::as_str).ok_or(format!("The Dinero option {option} needs a value"));
+        let Some(cache) = option.strip_prefix("-l") else {
+            match option.as_str() {
+                "-informat" => match value()? {
+                    "d" | "D" => {}
+                    format => return Err(format!("Only din traces, -informat d, are supported, not -informat {format}")),
+                },
+                "-skipcount" => skip_count = parse_dinero_number(option, value()?)?,
+                "-maxcount" => max_count = Some(parse_dinero_number(option, value()?)?),
+                "-flushcount" => flush_count = parse_dinero_number(option, value()?)?,
+                "-stat-interval" => {
+                    value()?;
+                    ignored.push(format!("{option}: only the statistics of the whole trace are printed"));
+                }
+                "-stat-idcombine" => ignored.push(format!("{option}: the instruction and data caches are always printed apart")),
+                _ => return Err(format!("The Dinero option {option} isn't supported")),
+            }
+            continue;
+        };
+        let (level, parameter) = cache.split_once('-')
+            .and_then(|(level, parameter)| Some((level.parse::<u32>().ok().filter(|level| (1..=DINERO_LEVELS).contains(level))?, parameter)))
+            .ok_or(format!("The Dinero option {option} isn't of the form -lN-Tparameter, with a level N from 1 to {DINERO_LEVELS}"))?;
+        let mut parameter = parameter.chars();
+        let kind = parameter.next().filter(|kind| matches!(kind, 'u' | 'i' | 'd'))
+            .ok_or(format!("The Dinero option {option} must be for a unified, instruction or data cache, -l{level}-u, -l{level}-i or -l{level}-d"))?;
+        let cache = caches.entry((level, kind)).or_default();
+        match parameter.as_str() {
+            "size" => cache.size = Some(parse_dinero_number(option, value()?)?),
+            "bsize" => cache.block_size = Some(parse_dinero_number(option, value()?)?),
+            "sbsize" => cache.sub_block_size = Some(parse_dinero_number(option, value()?)?),
+            "assoc" => cache.associativity = Some(parse_dinero_number(option, value()?)?),
+            "repl" => cache.replacement = Some(value()?.to_string()),
+            "fetch" => cache.fetch = Some(value()?.to_string()),
+            "walloc" => cache.write_allocate = Some(value()?.to_string()),
+            "wback" => cache.write_back = Some(value()?.to_string()),
+            "ccc" => ignored.push(format!("{option}: misses aren't classified as compulsory, capacity or conflict")),
+            _ => return Err(format!("The Dinero option {option} isn't supported")),
+        }
+    }
+    let levels = caches.keys().map(|&(level, _)| level).max().ok_or("The Dinero options have no caches, give at least -l1-usize and -l1-ubsize")?;
+    let mut config = LayeredCacheConfig { version: CURRENT_CONFIG_VERSION, caches: Vec::new(), memory_latency: None, context_switches: None, page_size: None, simulate_writes: true };
+    for level in 1..=levels {
+        let kinds: Vec<char> = caches.range((level, 'a')..=(level, 'z')).map(|(&(_, kind), _)| kind).collect();
+        match kinds.as_slice() {
+            ['u'] => {}
+            ['d', 'i'] if level == 1 => {}
+            [] => return Err(format!("There's no level {level} cache, but there is a level {levels} cache")),
+            _ if kinds.contains(&'u') => return Err(format!("Level {level} has both a unified cache and split instruction and data caches")),
+            _ if level == 1 => return Err("The first level needs both an instruction and a data cache, -l1-i and -l1-d, or a unified cache, -l1-u".to_string()),
+            _ => return Err(format!("Level {level} is split into instruction and data caches, but only the first level can be")),
+        }
+        // The instruction cache is listed first, as Dinero prints it first
+        for kind in kinds.into_iter().rev() {
+            config.caches.push(dinero_cache_config(level, kind, &caches[&(level, kind)])?);
+        }
+    }
+    if flush_count > 0 {
+        config.context_switches = Some(ContextSwitchConfig { interval: flush_count, caches: None, recovery: None });
+    }
+    config.validate()?;
+    Ok(DineroOptions { config, skip_count, max_count, ignored })
+}
+
+/// Maps the settings of one of Dinero's caches onto the configuration of a cache
+fn dinero_cache_config(level: u32, kind: char, cache: &DineroCache) -> Result<CacheConfig, String> {
+    let name = format!("l{level}-{kind}cache");
+    let option = |parameter: &str| format!("-l{level}-{kind}{parameter}");
+    let size = cache.size.ok_or(format!("Cache {name} has no size, give {}", option("size")))?;
+    let line_size = cache.block_size.ok_or(format!("Cache {name} has no block size, give {}", option("bsize")))?;
+    if cache.sub_block_size.is_some_and(|sub_block_size| sub_block_size != line_size) {
+        return Err(format!("Cache {name} has sub-blocks, which aren't supported, {} must be the same as {}", option("sbsize"), option("bsize")));
+    }
+    if !line_size.is_power_of_two() || size < line_size || !size.is_multiple_of(line_size) {
+        return Err(format!("Cache {name} must be a whole number of blocks, with a block size which is a power of two"));
+    }
+    let lines = size / line_size;
+    let kind_config = match cache.associativity.unwrap_or(1) {
+        ways if ways == 0 || ways > lines => return Err(format!("Cache {name} has {lines} blocks, so can't be {ways}-way set associative")),
+        1 => CacheKindConfig::Direct,
+        ways if ways == lines => CacheKindConfig::Full,
+        2 => CacheKindConfig::TwoWay,
+        4 => CacheKindConfig::FourWay,
+        8 => CacheKindConfig::EightWay,
+        16 => CacheKindConfig::SixteenWay,
+        ways => return Err(format!("Cache {name} is {ways}-way set associative, but only 1, 2, 4, 8 and 16 ways or fully associative caches are supported")),
+    };
+    let replacement_policy = match cache.replacement.as_deref().unwrap_or("l") {
+        "l" => ReplacementPolicyConfig::LeastRecentlyUsed,
+        // Lines only leave a set to make room for another, so evicting them in turn is first in,
+        // first out
+        "f" => ReplacementPolicyConfig::RoundRobin,
+        "r" => return Err(format!("Cache {name} uses random replacement, which isn't supported, {} must be l or f", option("repl"))),
+        other => return Err(format!("Cache {name} has an unknown replacement policy {other}, {} must be l or f", option("repl"))),
+    };
+    if let Some(fetch) = cache.fetch.as_deref().filter(|&fetch| fetch != "d") {
+        return Err(format!("Cache {name} uses the fetch policy {fetch}, but only demand fetching, {} d, is supported", option("fetch")));
+    }
+    let write_policy = match cache.write_back.as_deref().unwrap_or("a") {
+        "a" => WritePolicyConfig::WriteBack,
+        "n" => WritePolicyConfig::WriteThrough,
+        other => return Err(format!("Cache {name} has the write back policy {other}, but only a and n are supported for {}", option("wback"))),
+    };
+    let write_allocate = match cache.write_allocate.as_deref().unwrap_or("a") {
+        "a" => true,
+        "n" if write_policy == WritePolicyConfig::WriteBack => return Err(format!("Cache {name} is write-back without write allocation, which isn't supported, {} n needs {} n", option("walloc"), option("wback"))),
+        "n" => false,
+        other => return Err(format!("Cache {name} has the write allocate policy {other}, but only a and n are supported for {}", option("walloc"))),
+    };
+    Ok(CacheConfig {
+        name,
+        size,
+        line_size,
+        kind: kind_config,
+        replacement_policy,
+        policy_params: Map::new(),
+        hit_latency: None,
+        miss_penalty: None,
+        write_policy,
+        write_allocate,
+        writeback_buffer: None,
+        prefetcher: None,
+        inclusivity: InclusivityConfig::Nine,
+        next: None,
+        shared_by: None,
+        instructions: kind == 'i',
+        lazy_sets: false,
+        slices: None,
+        shadow: None,
+    })
+}
+
+/// Parses a size or count as Dinero does, a whole number optionally followed by `k`, `m` or `g`
+fn parse_dinero_number(option: &str, value: &str) -> Result<u64, String> {
+    let (digits, shift) = match value.char_indices().last() {
+        Some((end, 'k' | 'K')) => (&value[..end], 10),
+        Some((end, 'm' | 'M')) => (&value[..end], 20),
+        Some((end, 'g' | 'G')) => (&value[..end], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or(format!("The value {value} of the Dinero option {option} isn't a whole number, such as 32 or 16k"))
+}
+
+/// Parses a record of a trace in Dinero's din format: a label giving the kind of reference, and
+/// its address in hexadecimal, separated by whitespace. Anything after the address is ignored
+///
+/// The labels are 0 for a read, 1 for a write, 2 for an instruction fetch, and 3 for a reference
+/// of an unknown kind, which is read. Label 4, which flushes the caches, isn't supported. Each
+/// reference is of a single byte, as din records don't give a size.
+///
+/// # Arguments
+///
+/// * `record`: The line of the trace
+///
+/// returns: Result<Option<Access>, String>, None for a blank line
+pub fn parse_din_record(record: &str) -> Result<Option<Access>, String> {
+    let mut fields = record.split_whitespace();
+    let Some(label) = fields.next() else {
+        return Ok(None);
+    };
+    let kind = match label {
+        "0" | "3" => AccessKind::Read,
+        "1" => AccessKind::Write,
+        "2" => AccessKind::Fetch,
+        "4" => return Err("Flush records, with label 4, aren't supported".to_string()),
+        _ => return Err(format!("The label {label} isn't one of din's, 0 to 4")),
+    };
+    let address = fields.next().ok_or("The record has a label, but no address")?;
+    let digits = address.strip_prefix("0x").or_else(|| address.strip_prefix("0X")).unwrap_or(address);
+    let address = u64::from_str_radix(digits, 16).map_err(|_| format!("The address {address} isn't hexadecimal"))?;
+    Ok(Some(Access { pc: 0, address, size: 1, kind }))
+}
@@ -0,0 +1,50 @@
+use crate::config::{CacheKindConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+use crate::topology::Topology;
+use crate::prelude::*;
+
+/// Fully associative caches larger than this are flagged, as every access searches every line
+const LARGE_FULLY_ASSOCIATIVE_SIZE: u64 = 1 << 20;
+
+/// Finds settings which are valid, but are likely to be mistakes or to make a simulation
+/// meaningless or needlessly slow
+///
+/// # Arguments
+///
+/// * `config`: A validated configuration
+///
+/// returns: Vec<String>, a description of each problem found, in the order of the caches
+pub fn lint_config(config: &LayeredCacheConfig) -> Vec<String> {
+    let mut lints = Vec::new();
+    let topology = Topology::from_config(config).ok();
+    for (index, cache) in config.caches.iter().enumerate() {
+        let name = &cache.name;
+        let lines = cache.size / cache.line_size;
+        let ways = cache.kind.ways(lines);
+        if cache.size % cache.line_size != 0 {
+            lints.push(format!("Cache {name}: the size of {} bytes isn't a multiple of the line size of {} bytes, the remainder is unused", cache.size, cache.line_size));
+        }
+        if lines == 0 {
+            lints.push(format!("Cache {name}: the size of {} bytes is smaller than a line, so it holds no lines", cache.size));
+        } else if ways > lines {
+            lints.push(format!("Cache {name}: it is {ways}-way set associative, but only holds {lines} lines"));
+        } else if !(lines / ways).is_power_of_two() {
+            lints.push(format!("Cache {name}: it has {} sets, which isn't a power of two, so some sets are never used", lines / ways));
+        }
+        if cache.kind == CacheKindConfig::Full && cache.size > LARGE_FULLY_ASSOCIATIVE_SIZE {
+            lints.push(format!("Cache {name}: it is fully associative with {lines} lines, every access searches them all, so simulation will be slow"));
+        }
+        if cache.lazy_sets && lines > 0 && ways >= lines {
+            lints.push(format!("Cache {name}: it has a single set, so lazy_sets has no effect"));
+        }
+        if ways == 1 && (cache.replacement_policy != ReplacementPolicyConfig::default() || !cache.policy_params.is_empty()) {
+            lints.push(format!("Cache {name}: it is direct mapped, so its replacement policy has no effect"));
+        }
+        if let Some(next) = topology.as_ref().and_then(|t| t.next(index)) {
+            let next = &config.caches[next];
+            if cache.size >= next.size {
+                lints.push(format!("Cache {name}: it is at least as large as cache {}, which it passes its misses to", next.name));
+            }
+        }
+    }
+    lints
+}
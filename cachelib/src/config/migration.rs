@@ -0,0 +1,104 @@
+use serde_json::Value;
+use crate::prelude::*;
+
+/// The version of the configuration format produced by this version of the library
+pub const CURRENT_CONFIG_VERSION: u64 = 2;
+
+/// The version of documents which don't declare one, the format before versioning was introduced
+pub const UNVERSIONED_CONFIG_VERSION: u64 = 1;
+
+/// An upgrade of a configuration document from one version of the format to the next
+struct Migration {
+    /// The version the migration upgrades from, to the following version
+    from: u64,
+    /// Upgrades the document in place, pushing warnings for anything the user should change
+    apply: fn(&mut Value, &mut Vec<String>),
+}
+
+/// Every migration, in order. A document is upgraded by applying each migration from its version
+/// onwards
+const MIGRATIONS: &[Migration] = &[
+    Migration { from: 1, apply: upgrade_from_version_1 },
+];
+
+/// Upgrades a configuration document to the current version of the format
+///
+/// The document's version is taken from its `version` field, or is
+/// [UNVERSIONED_CONFIG_VERSION] if it has none. Afterwards the field holds
+/// [CURRENT_CONFIG_VERSION].
+///
+/// # Arguments
+///
+/// * `document`: The configuration document, with any includes already merged
+///
+/// returns: Result<Vec<String>, String>, the deprecation warnings for the document, or an error if
+/// the document is from a newer version of the format
+pub fn migrate_config(document: &mut Value) -> Result<Vec<String>, String> {
+    let mut warnings = Vec::new();
+    let Some(object) = document.as_object_mut() else {
+        // Not a configuration at all, leave it for deserialisation to report
+        return Ok(warnings);
+    };
+    let version = match object.get("version") {
+        None => UNVERSIONED_CONFIG_VERSION,
+        Some(version) => version.as_u64().ok_or(format!("The config version must be a whole number, got {version}"))?,
+    };
+    if version > CURRENT_CONFIG_VERSION {
+        return Err(format!("The config is version {version}, but this simulator only supports versions up to {CURRENT_CONFIG_VERSION}"));
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        (migration.apply)(document, &mut warnings);
+    }
+    document["version"] = CURRENT_CONFIG_VERSION.into();
+    Ok(warnings)
+}
+
+/// Version 2 deprecates the Rust names of the cache kinds and replacement policies, and changes the
+/// default replacement policy to lru
+fn upgrade_from_version_1(document: &mut Value, warnings: &mut Vec<String>) {
+    canonicalise_enum_names(document, warnings);
+    pin_round_robin_default(document);
+}
+
+/// Version 1 accepted the Rust names of the cache kinds and replacement policies, such as `TwoWay`
+/// and `LeastRecentlyUsed`, alongside the names used in the specification. They are replaced by the
+/// specification's names
+fn canonicalise_enum_names(document: &mut Value, warnings: &mut Vec<String>) {
+    const RENAMES: &[(&str, &str, &str)] = &[
+        ("kind", "Direct", "direct"),
+        ("kind", "Full", "full"),
+        ("kind", "TwoWay", "2way"),
+        ("kind", "FourWay", "4way"),
+        ("kind", "EightWay", "8way"),
+        ("replacement_policy", "RoundRobin", "rr"),
+        ("replacement_policy", "LeastRecentlyUsed", "lru"),
+        ("replacement_policy", "LeastFrequentlyUsed", "lfu"),
+    ];
+    let Some(caches) = document.get_mut("caches").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for cache in caches {
+        let name = cache.get("name").and_then(Value::as_str).unwrap_or("(unnamed)").to_string();
+        for (field, old, new) in RENAMES {
+            if cache.get(*field).and_then(Value::as_str) == Some(*old) {
+                warnings.push(format!("Cache {name}: the {field} {old} is deprecated, use {new} instead"));
+                cache[*field] = Value::from(*new);
+            }
+        }
+    }
+}
+
+/// Version 1 defaulted the replacement policy to round robin. Documents which relied on the default
+/// are given round robin explicitly, so they simulate the same caches. Direct mapped caches don't
+/// use their policy, so are left alone
+fn pin_round_robin_default(document: &mut Value) {
+    let Some(caches) = document.get_mut("caches").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for cache in caches.iter_mut().filter_map(Value::as_object_mut) {
+        if matches!(cache.get("kind").and_then(Value::as_str), Some("direct" | "Direct")) {
+            continue;
+        }
+        cache.entry("replacement_policy").or_insert_with(|| Value::from("rr"));
+    }
+}
@@ -0,0 +1,156 @@
+use serde_json::Value;
+use crate::config::parse_byte_size;
+use crate::prelude::*;
+
+/// The fields of a configuration which hold numbers, or lists or maps of them, so a string there
+/// which becomes an arithmetic expression is replaced by its value. Policy parameters are numbers
+/// apart from flags, which are never arithmetic expressions
+const NUMERIC_FIELDS: [&str; 18] = [
+    "version", "memory_latency", "page_size", "interval", "recovery", "size", "line_size", "hit_latency",
+    "miss_penalty", "shared_by", "entries", "drain_interval", "count", "masks", "degree", "distance",
+    "table_size", "policy_params",
+];
+
+/// Substitutes variables into the strings of a configuration document
+///
+/// Each `${NAME}` in a string is replaced by the value of the variable, or `${NAME:-default}` by
+/// the default when the variable isn't set. If the string is the value of a numeric field, such as
+/// `size`, and the whole of it becomes an arithmetic expression, such as `${L1_SIZE}*2`, it is
+/// replaced by the number it evaluates to. Expressions may use `+`, `-`, `*`, `/` and brackets over
+/// whole numbers and sizes with units, such as `32KiB`. Strings of other fields, such as names,
+/// stay strings whatever they become, and strings without variables are left as they are.
+///
+/// # Arguments
+///
+/// * `document`: The configuration document
+/// * `lookup`: Gets the value of a variable, usually from the environment
+///
+/// returns: Result<(), String>, an error if a variable isn't set and has no default
+pub fn substitute_variables(document: &mut Value, lookup: &dyn Fn(&str) -> Option<String>) -> Result<(), String> {
+    substitute_value(document, false, lookup)
+}
+
+/// Substitutes variables into a value, `numeric` being whether it belongs to a numeric field
+fn substitute_value(value: &mut Value, numeric: bool, lookup: &dyn Fn(&str) -> Option<String>) -> Result<(), String> {
+    match value {
+        Value::String(string) if string.contains("${") => {
+            let substituted = substitute_string(string, lookup)?;
+            *value = match numeric.then(|| evaluate(&substituted)).flatten() {
+                Some(number) => Value::from(number),
+                None => Value::String(substituted),
+            };
+        }
+        Value::Array(values) => {
+            for value in values {
+                substitute_value(value, numeric, lookup)?;
+            }
+        }
+        Value::Object(object) => {
+            for (field, value) in object.iter_mut() {
+                substitute_value(value, numeric || NUMERIC_FIELDS.contains(&field.as_str()), lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces each `${NAME}` or `${NAME:-default}` in a string
+fn substitute_string(string: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut output = String::with_capacity(string.len());
+    let mut rest = string;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or(format!("The variable in \"{string}\" is missing its closing brace"))?;
+        let variable = &rest[start + 2..start + end];
+        let (name, default) = match variable.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (variable, None),
+        };
+        let value = lookup(name)
+            .or(default.map(str::to_string))
+            .ok_or(format!("The variable {name} used in \"{string}\" isn't set"))?;
+        output.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Evaluates an arithmetic expression over whole numbers and sizes, or None if the string isn't
+/// one, or overflows
+fn evaluate(expression: &str) -> Option<u64> {
+    let tokens = tokenise(expression)?;
+    let mut position = 0;
+    let value = parse_sum(&tokens, &mut position)?;
+    (position == tokens.len()).then_some(value)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(u64),
+    Operator(char),
+}
+
+fn tokenise(expression: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            ' ' => {}
+            '+' | '-' | '*' | '/' | '(' | ')' => tokens.push(Token::Operator(c)),
+            '0'..='9' => {
+                let mut end = start + 1;
+                while let Some((i, c)) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Number(parse_byte_size(&expression[start..end]).ok()?));
+            }
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+/// sum = product (('+' | '-') product)*
+fn parse_sum(tokens: &[Token], position: &mut usize) -> Option<u64> {
+    let mut value = parse_product(tokens, position)?;
+    while let Some(Token::Operator(operator @ ('+' | '-'))) = tokens.get(*position) {
+        *position += 1;
+        let rhs = parse_product(tokens, position)?;
+        value = if *operator == '+' { value.checked_add(rhs)? } else { value.checked_sub(rhs)? };
+    }
+    Some(value)
+}
+
+/// product = term (('*' | '/') term)*
+fn parse_product(tokens: &[Token], position: &mut usize) -> Option<u64> {
+    let mut value = parse_term(tokens, position)?;
+    while let Some(Token::Operator(operator @ ('*' | '/'))) = tokens.get(*position) {
+        *position += 1;
+        let rhs = parse_term(tokens, position)?;
+        value = if *operator == '*' { value.checked_mul(rhs)? } else { value.checked_div(rhs)? };
+    }
+    Some(value)
+}
+
+/// term = number | '(' sum ')'
+fn parse_term(tokens: &[Token], position: &mut usize) -> Option<u64> {
+    let token = *tokens.get(*position)?;
+    *position += 1;
+    match token {
+        Token::Number(value) => Some(value),
+        Token::Operator('(') => {
+            let value = parse_sum(tokens, position)?;
+            (tokens.get(*position) == Some(&Token::Operator(')'))).then(|| {
+                *position += 1;
+                value
+            })
+        }
+        Token::Operator(_) => None,
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+use crate::config::LayeredCacheConfig;
+use crate::parallel::{parse_batch, DEFAULT_BATCH_RECORDS};
+use crate::simulator::{Access, LayeredCacheResult, Simulator};
+
+/// The number of parsed batches a thread can fall behind the parser before the parser waits
+const BATCHES_IN_FLIGHT: usize = 4;
+
+/// Simulates many configurations on the same trace, parsing it once for all of them
+///
+/// The trace is parsed in batches small enough to stay in the CPU's caches while every simulator
+/// works through them, so the trace is read from memory once however many configurations there
+/// are. The simulators can be spread across threads, which share each parsed batch. Each
+/// simulator is independent, so its results are the same as simulating it alone.
+pub struct Ensemble {
+    simulators: Vec<Simulator>,
+    threads: usize,
+    batch_records: usize,
+}
+
+impl Ensemble {
+    /// Creates an ensemble with a simulator with empty caches for each configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `configs`: Validated cache configurations
+    ///
+    /// returns: Ensemble
+    pub fn new<'a>(configs: impl IntoIterator<Item = &'a LayeredCacheConfig>) -> Self {
+        Self::from_simulators(configs.into_iter().map(Simulator::new).collect())
+    }
+
+    /// Creates an ensemble from existing simulators, which keep the contents of their caches
+    pub fn from_simulators(simulators: Vec<Simulator>) -> Self {
+        Self { simulators, threads: 1, batch_records: DEFAULT_BATCH_RECORDS }
+    }
+
+    /// Spreads the simulators across a number of threads, as well as the thread parsing the trace
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets the number of records parsed in each batch
+    pub fn with_batch_records(mut self, batch_records: usize) -> Self {
+        self.batch_records = batch_records.max(1);
+        self
+    }
+
+    /// Simulates a trace on every simulator, as accesses from each one's lowest numbered core
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array, see [Simulator::simulate]
+    ///
+    /// returns: Result<(), String>
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let cores: Vec<u32> = self.simulators.iter().map(|s| s.topology().cores().next().unwrap()).collect();
+        self.simulate_cores(bytes, &cores)
+    }
+
+    /// Simulates a trace on every simulator, as accesses from the same core
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array, see [Simulator::simulate]
+    /// * `core`: The core making the accesses, which every configuration must have
+    ///
+    /// returns: Result<(), String>, an error before anything is simulated if a configuration
+    /// doesn't have the core
+    pub fn simulate_core(&mut self, bytes: &[u8], core: u32) -> Result<(), String> {
+        for (index, simulator) in self.simulators.iter().enumerate() {
+            if simulator.topology().path(core).is_none() {
+                return Err(format!("Configuration {index} has no caches for core {core}"));
+            }
+        }
+        self.simulate_cores(bytes, &vec![core; self.simulators.len()])
+    }
+
+    fn simulate_cores(&mut self, bytes: &[u8], cores: &[u32]) -> Result<(), String> {
+        assert_eq!(bytes.len() % 40, 0);
+        let batches = bytes.chunks(self.batch_records * 40).map(parse_batch);
+        if self.threads == 1 || self.simulators.len() < 2 {
+            for batch in batches {
+                for (simulator, &core) in self.simulators.iter_mut().zip(cores) {
+                    simulator.simulate_accesses(batch.iter().copied(), core)?;
+                }
+            }
+            return Ok(());
+        }
+        let group_size = self.simulators.len().div_ceil(self.threads);
+        thread::scope(|scope| {
+            let (senders, workers): (Vec<SyncSender<Arc<Vec<Access>>>>, Vec<_>) = self.simulators.chunks_mut(group_size)
+                .zip(cores.chunks(group_size))
+                .map(|(group, cores)| {
+                    let (sender, receiver) = sync_channel::<Arc<Vec<Access>>>(BATCHES_IN_FLIGHT);
+                    let worker = scope.spawn(move || {
+                        for batch in receiver {
+                            for (simulator, &core) in group.iter_mut().zip(cores) {
+                                simulator.simulate_accesses(batch.iter().copied(), core)?;
+                            }
+                        }
+                        Ok::<(), String>(())
+                    });
+                    (sender, worker)
+                })
+                .unzip();
+            for batch in batches.map(Arc::new) {
+                // A thread only stops early on an error, which is reported below
+                if senders.iter().any(|sender| sender.send(batch.clone()).is_err()) {
+                    break;
+                }
+            }
+            drop(senders);
+            workers.into_iter().try_for_each(|worker| worker.join().expect("A simulator thread panicked"))
+        })
+    }
+
+    /// Gets the simulators, in the order they were given
+    pub fn simulators(&self) -> &[Simulator] {
+        &self.simulators
+    }
+
+    /// Gets the results of each simulator so far, in the order they were given
+    pub fn results(&self) -> Vec<&LayeredCacheResult> {
+        self.simulators.iter().map(Simulator::get_result).collect()
+    }
+
+    /// Takes the results of each simulator, in the order they were given
+    pub fn into_results(self) -> Vec<LayeredCacheResult> {
+        self.simulators.into_iter().map(Simulator::into_result).collect()
+    }
+
+    /// Takes the simulators, in the order they were given
+    pub fn into_simulators(self) -> Vec<Simulator> {
+        self.simulators
+    }
+}
@@ -0,0 +1,863 @@
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use crate::cache::{Cache, CacheLine, CacheTrait, GenericCache, HitMissCounters, LazyCache, MemoryUsage, SlicedCache};
+use crate::config::{CacheConfig, CacheKindConfig, InclusivityConfig, LayeredCacheConfig, ReplacementPolicyConfig, WritePolicyConfig, WritebackBufferConfig};
+use crate::prefetchers::{GenericPrefetcher, Prefetcher};
+use crate::replacement_policies::{registered_policy, CompactLeastRecentlyUsed, HeapLeastFrequentlyUsed, LeastFrequentlyUsed, LeastRecentlyUsed, NoPolicy, RoundRobin};
+use crate::topology::Topology;
+use crate::prelude::*;
+
+/// The kind of an access to a hierarchy
+///
+/// Writes find and allocate lines in the same way as reads, unless the configuration
+/// [simulates writes](LayeredCacheConfig::simulates_writes), when they make lines dirty in
+/// write-back caches. Instruction fetches are reads which take the core's path through its
+/// instruction cache, if it has one, see [Topology].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum AccessKind {
+    #[default]
+    Read,
+    Write,
+    Fetch,
+    /// A read followed by a write of the same bytes, such as an increment, which is two accesses
+    /// to each line
+    Modify,
+}
+
+/// Where an access to a line was served from
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HitLevel {
+    /// The cache which hit, by its index in the order of the configuration
+    Cache(usize),
+    /// Every cache on the path missed
+    Memory,
+}
+
+/// An invariant of a hierarchy found broken by [check_invariants](LayeredCache::check_invariants)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InvariantViolation {
+    /// What is broken, naming the caches concerned
+    pub description: String,
+    /// The sets whose contents show the problem, as the index of a cache and a set of it
+    pub sets: Vec<(usize, u64)>,
+}
+
+/// Sees what happens in a hierarchy during an access, so a front end can collect statistics
+/// without the hierarchy collecting them itself
+///
+/// Every method is inlined into the hierarchy, so those which do nothing cost nothing.
+pub(crate) trait HierarchyEvents {
+    /// Called after each access to a cache, in the order they are made
+    ///
+    /// # Arguments
+    ///
+    /// * `cache`: The index of the cache
+    /// * `model`: The cache, for finding the set of the line
+    /// * `line_address`: The address of the line, aligned to the line size of the first level
+    /// * `hit`: Whether the line was present
+    fn access(&mut self, cache: usize, model: &GenericCache, line_address: u64, hit: bool);
+
+    /// Called when a prefetcher brings a line into a cache which wasn't already there
+    fn prefetch(&mut self, cache: usize);
+
+    /// Whether [replacement](Self::replacement), [resident](Self::resident) and
+    /// [eviction](Self::eviction) need calling, which is only possible on the slower path which
+    /// tracks evictions
+    fn tracks_evictions(&self) -> bool {
+        false
+    }
+
+    /// Called when a cache's replacement policy chooses the way of a set for a line
+    fn replacement(&mut self, _cache: usize, _model: &GenericCache, _line_address: u64, _way: u64) {}
+
+    /// Called after [access](Self::access) when the access leaves the line in the cache, which is
+    /// every access but misses in exclusive caches
+    fn resident(&mut self, _cache: usize, _model: &GenericCache, _line_address: u64) {}
+
+    /// Called when a line leaves a cache, whether evicted, invalidated to keep a cache below
+    /// inclusive, or moved up from an exclusive cache
+    ///
+    /// # Arguments
+    ///
+    /// * `cache`: The index of the cache
+    /// * `model`: The cache
+    /// * `line_address`: The address of the line, aligned to the cache's line size
+    fn eviction(&mut self, _cache: usize, _model: &GenericCache, _line_address: u64) {}
+
+    /// Called when a cache writes a dirty line to the level below, as it's evicted, or from its
+    /// writeback buffer
+    fn writeback(&mut self, _cache: usize) {}
+
+    /// Called when a cache evicts a dirty line while its writeback buffer is full, so waits for the
+    /// oldest line in the buffer to be written below
+    fn writeback_stall(&mut self, _cache: usize) {}
+
+    /// Called when a line which missed a cache is found in its writeback buffer, and so isn't read
+    /// from the level below
+    fn writeback_buffer_hit(&mut self, _cache: usize) {}
+
+    /// Called after [access](Self::access) when the access is a write, which is only when writes
+    /// are simulated, including the writes a cache makes to the level below
+    fn write(&mut self, _cache: usize, _hit: bool) {}
+
+    /// Called when a line is written to main memory, from a write-through or dirty line leaving
+    /// the last level, or a write which the last level didn't allocate after a miss
+    fn memory_write(&mut self) {}
+}
+
+/// Ignores everything, for accesses which only need to know where they were served from
+impl HierarchyEvents for () {
+    fn access(&mut self, _cache: usize, _model: &GenericCache, _line_address: u64, _hit: bool) {}
+
+    fn prefetch(&mut self, _cache: usize) {}
+}
+
+/// A hierarchy of caches, through which accesses pass from the first level towards main memory,
+/// with the inclusivity, prefetchers and topology of a configuration
+///
+/// This is the part of a [Simulator](crate::simulator::Simulator) which doesn't depend on traces
+/// or statistics, for front ends such as CPU models which make accesses one at a time and only need
+/// to know where each was served from.
+///
+/// Cloning a hierarchy copies the contents of its caches and the state of their policies and
+/// prefetchers.
+#[derive(Clone)]
+pub struct LayeredCache {
+    topology: Topology,
+    levels: Levels,
+    // The names of the caches, for describing broken invariants
+    names: Vec<String>,
+}
+
+/// The caches of a hierarchy, kept apart from its [Topology] so accesses can borrow a core's path
+/// from the topology while updating the caches
+#[derive(Clone)]
+struct Levels {
+    caches: Vec<GenericCache>,
+    prefetchers: Vec<Option<GenericPrefetcher>>,
+    // Reused between accesses to avoid allocating for each prefetch
+    prefetch_buffer: Vec<u64>,
+    needs_pc: bool,
+    inclusivity: Vec<InclusivityConfig>,
+    // Whether writes make lines dirty, rather than being simulated as reads
+    simulates_writes: bool,
+    // Whether each cache passes writes on to the level below rather than holding them as dirty lines
+    write_through: Vec<bool>,
+    // Whether each cache allocates a line for a write which misses
+    write_allocate: Vec<bool>,
+    // The lines of each write-back cache which have been written since they were read from below,
+    // when writes are simulated
+    dirty: Vec<BTreeSet<u64>>,
+    writeback_buffers: Vec<Option<WritebackBuffer>>,
+    // Whether any cache is inclusive or exclusive, or writes are simulated, requiring the slower
+    // path which tracks evictions
+    reacts_to_evictions: bool,
+    // Whether any cache has smaller lines than a cache above it, so a miss above reads several of
+    // its lines, which the slower path handles
+    narrows: bool,
+    // Evictions waiting to be handled at the end of an access, as (level, line address)
+    pending_evictions: Vec<(usize, u64)>,
+    // Lines waiting to be written to the level below a cache at the end of an access, as (level,
+    // line address)
+    pending_writes: Vec<(usize, u64)>,
+    // The misses of a batch at the level being read and at the level above it, reused between
+    // batches
+    batch_misses: [Vec<u64>; 2],
+}
+
+/// The buffer holding the dirty lines a write-back cache has evicted until they're written to the
+/// level below
+#[derive(Clone)]
+struct WritebackBuffer {
+    entries: usize,
+    drain_interval: u64,
+    // The evicted lines yet to be written below, oldest first
+    lines: VecDeque<u64>,
+    accesses_since_drain: u64,
+}
+
+impl WritebackBuffer {
+    fn new(config: &WritebackBufferConfig) -> Self {
+        Self {
+            entries: config.entries as usize,
+            drain_interval: config.drain_interval,
+            lines: VecDeque::new(),
+            accesses_since_drain: 0,
+        }
+    }
+
+    /// Counts an access to the cache, giving the oldest line in the buffer to write below once
+    /// every `drain_interval` accesses
+    fn drain(&mut self) -> Option<u64> {
+        self.accesses_since_drain += 1;
+        if self.accesses_since_drain < self.drain_interval {
+            return None;
+        }
+        self.accesses_since_drain = 0;
+        self.lines.pop_front()
+    }
+
+    /// Takes a dirty line leaving the cache into the buffer, giving the oldest line to write below
+    /// at once if the buffer is full
+    fn push(&mut self, cache: usize, line_address: u64, events: &mut impl HierarchyEvents) -> Option<u64> {
+        let written = (self.lines.len() == self.entries).then(|| self.lines.pop_front()).flatten();
+        if written.is_some() {
+            events.writeback_stall(cache);
+        }
+        self.lines.push_back(line_address);
+        written
+    }
+
+    /// Takes a line back from the buffer into the cache if the buffer holds it
+    fn take(&mut self, line_address: u64) -> bool {
+        let Some(position) = self.lines.iter().position(|&line| line == line_address) else {
+            return false;
+        };
+        self.lines.remove(position);
+        true
+    }
+}
+
+impl LayeredCache {
+    /// Creates the hierarchy of a configuration, with every cache empty
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: A cache configuration, usually resulting from parsing JSON
+    ///
+    /// returns: LayeredCache
+    ///
+    /// # Panics
+    ///
+    /// If the configuration's topology is invalid, which is checked by
+    /// [validate](LayeredCacheConfig::validate), or if a cache uses a replacement policy which isn't
+    /// registered, which is also checked by validate
+    pub fn new(config: &LayeredCacheConfig) -> Self {
+        let prefetchers: Vec<Option<GenericPrefetcher>> = config.caches.iter()
+            .map(|cache| cache.prefetcher.as_ref().map(|p| GenericPrefetcher::from_config(p, cache.line_size)))
+            .collect();
+        let inclusivity: Vec<InclusivityConfig> = config.caches.iter().map(|cache| cache.inclusivity).collect();
+        let topology = Topology::from_config(config).expect("The configuration should be validated before creating a hierarchy");
+        let writeback_buffers: Vec<Option<WritebackBuffer>> = config.caches.iter()
+            .map(|cache| cache.writeback_buffer.as_ref().map(WritebackBuffer::new))
+            .collect();
+        let narrows = (0..config.caches.len()).any(|cache| {
+            topology.next(cache).is_some_and(|next| config.caches[next].line_size < config.caches[cache].line_size)
+        });
+        Self {
+            topology,
+            names: config.caches.iter().map(|cache| cache.name.clone()).collect(),
+            levels: Levels {
+                caches: config.caches.iter().map(Self::config_to_cache).collect(),
+                needs_pc: prefetchers.iter().flatten().any(|p| p.needs_pc()),
+                prefetchers,
+                prefetch_buffer: Vec::new(),
+                reacts_to_evictions: inclusivity.iter().any(|i| *i != InclusivityConfig::Nine) || config.simulates_writes(),
+                narrows,
+                inclusivity,
+                simulates_writes: config.simulates_writes(),
+                write_through: config.caches.iter().map(|cache| cache.write_policy == WritePolicyConfig::WriteThrough).collect(),
+                write_allocate: config.caches.iter().map(|cache| cache.write_allocate).collect(),
+                dirty: vec![BTreeSet::new(); config.caches.len()],
+                writeback_buffers,
+                pending_evictions: Vec::new(),
+                pending_writes: Vec::new(),
+                batch_misses: [Vec::new(), Vec::new()],
+            },
+        }
+    }
+
+    /// Accesses the line holding an address, as an access from the first core with no program
+    /// counter
+    ///
+    /// # Arguments
+    ///
+    /// * `address`: The address accessed
+    /// * `kind`: Whether the access is a read, a write or an instruction fetch
+    ///
+    /// returns: HitLevel
+    pub fn access(&mut self, address: u64, kind: AccessKind) -> HitLevel {
+        let first_core = self.topology.cores().next().unwrap();
+        self.access_core(address, 0, kind, first_core).unwrap()
+    }
+
+    /// Accesses the line holding an address, as an access from the given core
+    ///
+    /// The line passes through each cache on the core's path until one hits, with any misses
+    /// allocated and any evictions handled as the configuration's inclusivity requires.
+    ///
+    /// # Arguments
+    ///
+    /// * `address`: The address accessed
+    /// * `pc`: The program counter of the instruction making the access, or 0 if no prefetcher
+    ///   needs it, see [needs_pc](Self::needs_pc)
+    /// * `kind`: Whether the access is a read, a write or an instruction fetch
+    /// * `core`: The core making the access, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<HitLevel, String>, an error if the configuration has no caches for the core
+    pub fn access_core(&mut self, address: u64, pc: u64, kind: AccessKind, core: u32) -> Result<HitLevel, String> {
+        let mut path = self.topology.path(core).ok_or(format!("The configuration has no caches for core {core}"))?;
+        if kind == AccessKind::Fetch {
+            path = self.topology.instruction_path(core).unwrap_or(path);
+        }
+        let line_address = address & self.levels.caches[path[0]].get_alignment_bit_mask();
+        if kind == AccessKind::Modify {
+            let hit_level = self.levels.access_line(&self.topology, path, line_address, pc, AccessKind::Read, &mut ());
+            self.levels.access_line(&self.topology, path, line_address, pc, AccessKind::Write, &mut ());
+            return Ok(hit_level);
+        }
+        Ok(self.levels.access_line(&self.topology, path, line_address, pc, kind, &mut ()))
+    }
+
+    /// Accesses a line on a path through the hierarchy, telling `events` what happens
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path of the core making the access, from the [Topology]
+    /// * `line_address`: The address of the line, aligned to the line size of the first level
+    /// * `pc`: The program counter of the instruction making the access
+    /// * `kind`: Whether the access is a read or a write, a modify being a read then a write
+    /// * `events`: Told about each access to a cache, and each prefetch and replacement
+    ///
+    /// returns: HitLevel, where the read of a modify was served from
+    #[inline(always)]
+    pub(crate) fn access_line(&mut self, path: &[usize], line_address: u64, pc: u64, kind: AccessKind, events: &mut impl HierarchyEvents) -> HitLevel {
+        if kind == AccessKind::Modify {
+            let hit_level = self.levels.access_line(&self.topology, path, line_address, pc, AccessKind::Read, events);
+            self.levels.access_line(&self.topology, path, line_address, pc, AccessKind::Write, events);
+            return hit_level;
+        }
+        self.levels.access_line(&self.topology, path, line_address, pc, kind, events)
+    }
+
+    /// Accesses a batch of lines through the caches of a path, a level at a time, passing the
+    /// misses of each level to the next and adding the hits and misses of each cache to its
+    /// counters
+    ///
+    /// Each level sees the same lines in the same order as when they're accessed one at a time, so
+    /// the results are the same, as long as [batches](Self::batches) is true for the path
+    pub(crate) fn access_lines(&mut self, path: &[usize], lines: &[u64], counters: &mut [HitMissCounters]) {
+        debug_assert!(self.batches(path));
+        let levels = &mut self.levels;
+        let [mut misses, mut next_misses] = core::mem::take(&mut levels.batch_misses);
+        for (index, &level) in path.iter().enumerate() {
+            let inputs = if index == 0 { lines } else { &misses[..] };
+            let (cache, counters) = (&mut levels.caches[level], &mut counters[level]);
+            if index == path.len() - 1 {
+                cache.read_and_update_lines(inputs, counters);
+                break;
+            }
+            next_misses.clear();
+            cache.read_and_update_lines_keeping_misses(inputs, &mut next_misses);
+            counters.misses += next_misses.len() as u64;
+            counters.hits += (inputs.len() - next_misses.len()) as u64;
+            core::mem::swap(&mut misses, &mut next_misses);
+            if misses.is_empty() {
+                break;
+            }
+        }
+        levels.batch_misses = [misses, next_misses];
+    }
+
+    /// Whether a path can be accessed in batches by [access_lines](Self::access_lines), which is
+    /// only when no cache reacts to the others, so not for hierarchies with prefetchers, simulated
+    /// writes or inclusive or exclusive caches, and when each line missed is one line of the level
+    /// below
+    pub(crate) fn batches(&self, path: &[usize]) -> bool {
+        !self.levels.reacts_to_evictions && !self.levels.narrows && path.iter().all(|&level| self.levels.prefetchers[level].is_none())
+    }
+
+    /// Invalidates every line of some caches, as a flush at a context switch does, telling `events`
+    /// about each line which leaves a cache
+    ///
+    /// Dirty lines are written below, through the writeback buffer of their cache, and lines flushed from an inclusive
+    /// cache are invalidated in the caches above it.
+    pub(crate) fn flush(&mut self, caches: &[usize], events: &mut impl HierarchyEvents) {
+        let mut flushed = Vec::new();
+        for &level in caches {
+            flushed.clear();
+            self.levels.caches[level].invalidate_all(&mut flushed);
+            for &line in &flushed {
+                events.eviction(level, &self.levels.caches[level], line);
+                self.levels.release_line(&self.topology, level, line, events);
+            }
+            // Dirty lines reach the caches below before they're flushed in turn
+            self.levels.finish_access(&self.topology, events);
+        }
+    }
+
+    /// Gets the topology of the caches
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    /// Gets the caches, in the order of the configuration
+    pub fn caches(&self) -> &[GenericCache] {
+        &self.levels.caches
+    }
+
+    /// Gets the caches of a path, in its order, so each can be read on its own thread
+    #[cfg(feature = "std")]
+    pub(crate) fn path_caches_mut(&mut self, path: &[usize]) -> Vec<&mut GenericCache> {
+        let mut caches: Vec<Option<&mut GenericCache>> = self.levels.caches.iter_mut().map(Some).collect();
+        path.iter().map(|&level| caches[level].take().expect("A path passes through each cache once")).collect()
+    }
+
+    /// Gets a cache to change its state directly, such as to advance its policy's clock
+    #[cfg(feature = "std")]
+    pub(crate) fn cache_mut(&mut self, cache: usize) -> &mut GenericCache {
+        &mut self.levels.caches[cache]
+    }
+
+    /// Gets the lines of a cache, ordered by set and then way, with those written since they were
+    /// read from below marked dirty, see [lines](CacheTrait::lines)
+    ///
+    /// # Arguments
+    ///
+    /// * `cache`: The index of the cache, in the order of the configuration
+    ///
+    /// returns: Vec<CacheLine>
+    pub fn lines(&self, cache: usize) -> Vec<CacheLine> {
+        let dirty = &self.levels.dirty[cache];
+        self.levels.caches[cache].lines().into_iter()
+            .map(|line| CacheLine { dirty: line.address.is_some_and(|address| dirty.contains(&address)), ..line })
+            .collect()
+    }
+
+    /// Gets the bytes of host memory held by each cache, in the order of the configuration, see
+    /// [memory_usage](CacheTrait::memory_usage)
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        self.levels.caches.iter().map(CacheTrait::memory_usage).collect()
+    }
+
+    /// Checks the invariants the configuration promises hold between accesses
+    ///
+    /// Each cache is [consistent](CacheTrait::check) on its own, an inclusive cache holds every line
+    /// of the caches above it, an exclusive cache holds no line of the caches directly above it,
+    /// a write-through cache has no dirty lines, and every dirty line is held by its cache.
+    ///
+    /// This reads every line of every cache, so is only for verifying a simulation, see
+    /// [verify_invariants](crate::simulator::builder::SimulatorBuilder::verify_invariants).
+    ///
+    /// returns: Result<(), InvariantViolation>, the first broken invariant found
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let levels = &self.levels;
+        let set_of = |cache: usize, line: u64| levels.caches[cache].address_to_set_and_tag(line).0;
+        for (cache, model) in levels.caches.iter().enumerate() {
+            model.check().map_err(|(set, e)| InvariantViolation {
+                description: format!("Set {set} of cache {} is inconsistent: {e}", self.names[cache]),
+                sets: vec![(cache, set)],
+            })?;
+        }
+        let resident: Vec<BTreeSet<u64>> = levels.caches.iter()
+            .map(|model| model.lines().into_iter().filter_map(|line| line.address).collect())
+            .collect();
+        for (cache, inclusivity) in levels.inclusivity.iter().enumerate() {
+            let uppers: Vec<usize> = match inclusivity {
+                InclusivityConfig::Inclusive => self.topology.above(cache).to_vec(),
+                InclusivityConfig::Exclusive => (0..levels.caches.len()).filter(|&upper| self.topology.next(upper) == Some(cache)).collect(),
+                InclusivityConfig::Nine => continue,
+            };
+            let (line_size, mask) = (levels.caches[cache].get_line_size(), levels.caches[cache].get_alignment_bit_mask());
+            for upper in uppers {
+                let upper_line_size = levels.caches[upper].get_line_size();
+                for &upper_line in &resident[upper] {
+                    // The lines of this cache within the line above, or the one holding it
+                    let mut line = upper_line & mask;
+                    while line < upper_line + upper_line_size {
+                        let held = resident[cache].contains(&line);
+                        let broken = match inclusivity {
+                            InclusivityConfig::Inclusive if !held => Some("is inclusive, but doesn't hold"),
+                            InclusivityConfig::Exclusive if held => Some("is exclusive, but also holds"),
+                            _ => None,
+                        };
+                        if let Some(broken) = broken {
+                            return Err(InvariantViolation {
+                                description: format!("Cache {} {broken} the line at {line:#x}, which cache {} holds at {upper_line:#x}", self.names[cache], self.names[upper]),
+                                sets: vec![(upper, set_of(upper, upper_line)), (cache, set_of(cache, line))],
+                            });
+                        }
+                        line += line_size;
+                    }
+                }
+            }
+        }
+        for (cache, dirty) in levels.dirty.iter().enumerate() {
+            let broken = match dirty.first() {
+                Some(&dirty) if levels.write_through[cache] => Some((dirty, "is write-through, but has a")),
+                _ => dirty.iter().find(|&line| !resident[cache].contains(line)).map(|&dirty| (dirty, "doesn't hold its")),
+            };
+            if let Some((dirty, broken)) = broken {
+                return Err(InvariantViolation {
+                    description: format!("Cache {} {broken} dirty line at {dirty:#x}", self.names[cache]),
+                    sets: vec![(cache, set_of(cache, dirty))],
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a prefetcher needs the program counter of each access. Parsing it has a cost, so
+    /// front ends can pass 0 when it isn't needed
+    pub fn needs_pc(&self) -> bool {
+        self.levels.needs_pc
+    }
+
+    /// Creates a new cache from a cache configuration
+    pub(crate) fn config_to_cache(config: &CacheConfig) -> GenericCache {
+        let num_lines = config.size / config.line_size;
+        let num_sets = match config.kind {
+            CacheKindConfig::Direct => {
+                num_lines
+            }
+            CacheKindConfig::Full => {
+                1
+            }
+            CacheKindConfig::TwoWay => {
+                num_lines / 2
+            }
+            CacheKindConfig::FourWay => {
+                num_lines / 4
+            }
+            CacheKindConfig::EightWay => {
+                num_lines / 8
+            }
+            CacheKindConfig::SixteenWay => {
+                num_lines / 16
+            }
+        };
+        if let Some(slices) = &config.slices {
+            // Each slice is a cache with its share of the sets, created as it would be on its own
+            let slice_config = CacheConfig { size: config.size / slices.count, slices: None, ..config.clone() };
+            let slice_caches = (0..slices.count).map(|_| Self::config_to_cache(&slice_config)).collect();
+            return GenericCache::from(SlicedCache::new(slice_caches, slices.masks()));
+        }
+        if config.lazy_sets && num_sets > 1 {
+            // Each set is a fully associative cache of the set's lines, created as it would be for
+            // the whole cache
+            let set_config = CacheConfig { size: num_lines / num_sets * config.line_size, kind: CacheKindConfig::Full, lazy_sets: false, ..config.clone() };
+            return GenericCache::from(LazyCache::new(config.size, config.line_size, num_sets, Arc::new(move || Self::config_to_cache(&set_config))));
+        }
+        // Associativities with a GenericCache variant get a cache with a fixed number of ways, which
+        // is faster
+        macro_rules! with_ways {
+            ($policy:expr; $($ways:literal),*) => {
+                match num_lines / num_sets {
+                    $($ways => GenericCache::from(Cache::<_, u64, $ways>::new(config.size, config.line_size, num_sets, $policy)),)*
+                    _ => GenericCache::from(Cache::<_, u64>::new(config.size, config.line_size, num_sets, $policy)),
+                }
+            };
+        }
+        if num_sets == num_lines {
+            GenericCache::from(Cache::new(config.size, config.line_size, num_sets, NoPolicy))
+        } else {
+            match &config.replacement_policy {
+                ReplacementPolicyConfig::RoundRobin => {
+                    with_ways!(RoundRobin::new(num_sets); 2, 4, 8)
+                }
+                ReplacementPolicyConfig::LeastRecentlyUsed => {
+                    // Sets small enough for the compact policy's ranks evict the same lines, using a
+                    // word per set rather than per line. Fully associative caches with more than
+                    // MAX_WAYS lines keep the timestamps
+                    let ways = num_lines / num_sets;
+                    if ways.is_power_of_two() && ways <= CompactLeastRecentlyUsed::MAX_WAYS {
+                        with_ways!(CompactLeastRecentlyUsed::new(num_lines, ways); 2, 4, 8)
+                    } else {
+                        GenericCache::from(Cache::<_, u64>::new(config.size, config.line_size, num_sets, LeastRecentlyUsed::new(num_lines)))
+                    }
+                }
+                ReplacementPolicyConfig::LeastFrequentlyUsed => {
+                    let counter_bits = config.policy_params.get("counter_bits").and_then(|v| v.as_u64()).unwrap_or(u64::BITS as u64);
+                    // Large sets find their victims through a heap rather than scanning, evicting the
+                    // same lines
+                    let ways = num_lines / num_sets;
+                    if ways >= HeapLeastFrequentlyUsed::MIN_WAYS {
+                        GenericCache::from(Cache::<_, u64>::new(config.size, config.line_size, num_sets, HeapLeastFrequentlyUsed::with_counter_bits(num_lines, ways, counter_bits)))
+                    } else {
+                        with_ways!(LeastFrequentlyUsed::with_counter_bits(num_lines, counter_bits); 2, 4)
+                    }
+                }
+                ReplacementPolicyConfig::Registered(name) => {
+                    let policy = registered_policy(name).unwrap_or_else(|e| panic!("Cache {}: {e}", config.name));
+                    GenericCache::from(Cache::new(config.size, config.line_size, num_sets, (policy.factory)(num_lines, num_sets, &config.policy_params)))
+                }
+            }
+        }
+    }
+}
+
+impl Levels {
+    /// Accesses a line, see [LayeredCache::access_line]
+    ///
+    /// Always inlined, so the events which do nothing are optimised away
+    #[inline(always)]
+    fn access_line(&mut self, topology: &Topology, path: &[usize], line_address: u64, pc: u64, kind: AccessKind, events: &mut impl HierarchyEvents) -> HitLevel {
+        if self.reacts_to_evictions || self.narrows || events.tracks_evictions() {
+            return self.access_line_with_evictions(topology, path, line_address, pc, kind, events);
+        }
+        for &level in path {
+            let cache = &mut self.caches[level];
+            let hit = cache.read_and_update_line(line_address);
+            if let Some(prefetcher) = &mut self.prefetchers[level] {
+                prefetcher.observe(line_address & cache.get_alignment_bit_mask(), pc, hit, &mut self.prefetch_buffer);
+                for prefetch_address in self.prefetch_buffer.drain(..) {
+                    if !cache.fill_line(prefetch_address & cache.get_alignment_bit_mask()).hit {
+                        events.prefetch(level);
+                    }
+                }
+            }
+            events.access(level, cache, line_address, hit);
+            if hit {
+                return HitLevel::Cache(level);
+            }
+        }
+        HitLevel::Memory
+    }
+
+    /// Accesses a single line through a hierarchy containing inclusive or exclusive caches, or
+    /// simulating writes, or caches with smaller lines than those above them, or for events
+    /// which track replacements
+    ///
+    /// Exclusive caches don't allocate on a miss, and give up lines which hit in them to the cache
+    /// above. Evictions are passed to [handle_eviction](Self::handle_eviction) once the access has
+    /// finished, so a victim placed in an exclusive cache swaps with the line which moved up rather
+    /// than displacing it first
+    fn access_line_with_evictions(&mut self, topology: &Topology, path: &[usize], line_address: u64, pc: u64, mut kind: AccessKind, events: &mut impl HierarchyEvents) -> HitLevel {
+        if kind == AccessKind::Write && !self.simulates_writes {
+            kind = AccessKind::Read;
+        }
+        let hit_level = self.access_path_with_evictions(path, line_address, pc, kind, events);
+        self.finish_access(topology, events);
+        hit_level
+    }
+
+    /// Handles the evictions an access left pending, then writes the lines it left to write below,
+    /// which may evict and write others in turn
+    fn finish_access(&mut self, topology: &Topology, events: &mut impl HierarchyEvents) {
+        loop {
+            let mut pending_evictions = core::mem::take(&mut self.pending_evictions);
+            for (level, evicted) in pending_evictions.drain(..) {
+                self.handle_eviction(topology, level, evicted, events);
+            }
+            self.pending_evictions = pending_evictions;
+            if self.pending_writes.is_empty() {
+                return;
+            }
+            let mut pending_writes = core::mem::take(&mut self.pending_writes);
+            for (level, line) in pending_writes.drain(..) {
+                self.write_below(topology, level, line, events);
+            }
+            if self.pending_writes.is_empty() {
+                self.pending_writes = pending_writes;
+            }
+        }
+    }
+
+    /// Writes a line of a cache to the level below, as a write through the caches below it, or to
+    /// main memory from the last level
+    fn write_below(&mut self, topology: &Topology, level: usize, line_address: u64, events: &mut impl HierarchyEvents) {
+        let below = topology.below(level);
+        let Some(&next) = below.first() else {
+            events.memory_write();
+            return;
+        };
+        // The cache below may have smaller lines, each written on its own
+        let end = line_address + self.caches[level].get_line_size();
+        let mut address = line_address & self.caches[next].get_alignment_bit_mask();
+        while address < end {
+            self.access_path_with_evictions(below, address, 0, AccessKind::Write, events);
+            address += self.caches[next].get_line_size();
+        }
+    }
+
+    /// Accesses a line through the caches of a path until one hits, leaving evictions pending
+    ///
+    /// A miss in a cache whose lines are larger than those of the cache below it reads every line
+    /// of the cache below within the line missed, each continuing down the path on its own, and the
+    /// access is served from the furthest level any of them reached.
+    ///
+    /// When writes are simulated, a write makes its line dirty in the first write-back cache it
+    /// reaches, and is a read of the line in the caches below that one. A write-through cache
+    /// passes the write on, to the caches below it if it misses, or at the end of the access if it
+    /// hits, and a cache which doesn't allocate on write misses passes on the writes it misses. A
+    /// miss in a cache whose writeback buffer holds the line takes it back from the buffer,
+    /// still dirty, rather than reading it from below.
+    fn access_path_with_evictions(&mut self, path: &[usize], line_address: u64, pc: u64, mut kind: AccessKind, events: &mut impl HierarchyEvents) -> HitLevel {
+        // Whether the cache above the current one on the path holds the line after the access
+        let mut held_above = false;
+        for (index, &level) in path.iter().enumerate() {
+            let exclusive = self.inclusivity[level] == InclusivityConfig::Exclusive;
+            let write = kind == AccessKind::Write;
+            let allocate = !exclusive && (!write || self.write_allocate[level]);
+            let access = self.caches[level].access_line(line_address, allocate);
+            if let Some(way) = access.way {
+                events.replacement(level, &self.caches[level], line_address, way);
+            }
+            if let Some(evicted) = access.evicted {
+                events.eviction(level, &self.caches[level], evicted);
+                self.pending_evictions.push((level, evicted));
+            }
+            let line = line_address & self.caches[level].get_alignment_bit_mask();
+            let mut buffer_hit = false;
+            if let Some(buffer) = &mut self.writeback_buffers[level] {
+                if let Some(drained) = buffer.drain() {
+                    events.writeback(level);
+                    self.pending_writes.push((level, drained));
+                }
+                if !access.hit && allocate && buffer.take(line) {
+                    events.writeback_buffer_hit(level);
+                    self.dirty[level].insert(line);
+                    buffer_hit = true;
+                }
+            }
+            if kind == AccessKind::Write && allocate && !self.write_through[level] {
+                self.dirty[level].insert(line);
+                kind = AccessKind::Read;
+            }
+            if let Some(prefetcher) = &mut self.prefetchers[level] {
+                let alignment_bit_mask = self.caches[level].get_alignment_bit_mask();
+                prefetcher.observe(line_address & alignment_bit_mask, pc, access.hit, &mut self.prefetch_buffer);
+                let mut prefetches = core::mem::take(&mut self.prefetch_buffer);
+                for prefetch_address in prefetches.drain(..) {
+                    let fill = self.caches[level].fill_line(prefetch_address & alignment_bit_mask);
+                    if let Some(way) = fill.way {
+                        events.replacement(level, &self.caches[level], prefetch_address, way);
+                    }
+                    if !fill.hit {
+                        events.prefetch(level);
+                    }
+                    if let Some(evicted) = fill.evicted {
+                        events.eviction(level, &self.caches[level], evicted);
+                        self.pending_evictions.push((level, evicted));
+                    }
+                }
+                self.prefetch_buffer = prefetches;
+            }
+            events.access(level, &self.caches[level], line_address, access.hit);
+            if write {
+                events.write(level, access.hit);
+            }
+            if access.hit || allocate {
+                events.resident(level, &self.caches[level], line_address);
+            }
+            if access.hit {
+                if exclusive && held_above {
+                    // The line has already been allocated in the level above, and takes its dirty
+                    // data up with it
+                    self.caches[level].invalidate_line(line_address);
+                    events.eviction(level, &self.caches[level], line);
+                    if self.dirty[level].remove(&line) || kind == AccessKind::Write {
+                        let upper = path[index - 1];
+                        if self.write_through[upper] {
+                            self.write_back(level, line, events);
+                        } else {
+                            self.dirty[upper].insert(line);
+                        }
+                    }
+                } else if kind == AccessKind::Write {
+                    self.write_hit(level, line);
+                }
+                return HitLevel::Cache(level);
+            }
+            if buffer_hit {
+                return HitLevel::Cache(level);
+            }
+            held_above = allocate;
+            let line_size = self.caches[level].get_line_size();
+            if let Some(&next) = path.get(index + 1).filter(|&&next| self.caches[next].get_line_size() < line_size) {
+                let (below, next_line_size) = (&path[index + 1..], self.caches[next].get_line_size());
+                let first = line_address & self.caches[level].get_alignment_bit_mask();
+                return (0..line_size / next_line_size)
+                    .map(|line| self.access_path_with_evictions(below, first + line * next_line_size, pc, kind, events))
+                    .reduce(|furthest, hit_level| match (furthest, hit_level) {
+                        (HitLevel::Cache(furthest), HitLevel::Cache(level)) if below.iter().position(|&l| l == furthest) < below.iter().position(|&l| l == level) => hit_level,
+                        (HitLevel::Cache(_), HitLevel::Memory) => hit_level,
+                        _ => furthest,
+                    })
+                    .unwrap();
+            }
+        }
+        // A write the last level allocated still goes on to memory, while one it didn't is the
+        // access to memory its miss counts
+        if kind == AccessKind::Write && held_above {
+            events.memory_write();
+        }
+        HitLevel::Memory
+    }
+
+    /// Writes a line held by a cache, making it dirty if the cache is write-back, or writing it
+    /// below at the end of the access if the cache is write-through
+    fn write_hit(&mut self, level: usize, line_address: u64) {
+        if self.write_through[level] {
+            self.pending_writes.push((level, line_address));
+        } else {
+            self.dirty[level].insert(line_address);
+        }
+    }
+
+    /// Maintains inclusivity after a line is evicted from a cache
+    ///
+    /// Inclusive caches invalidate the line in every cache above them, and an exclusive cache below
+    /// takes the line as a victim, which may in turn evict another line
+    fn handle_eviction(&mut self, topology: &Topology, level: usize, evicted: u64, events: &mut impl HierarchyEvents) {
+        self.release_line(topology, level, evicted, events);
+        if let Some(next) = topology.next(level) {
+            if self.inclusivity[next] == InclusivityConfig::Exclusive {
+                let fill = self.caches[next].fill_line(evicted);
+                if let Some(way) = fill.way {
+                    events.replacement(next, &self.caches[next], evicted, way);
+                }
+                if let Some(victim_evicted) = fill.evicted {
+                    events.eviction(next, &self.caches[next], victim_evicted);
+                    self.handle_eviction(topology, next, victim_evicted, events);
+                }
+            }
+        }
+    }
+
+    /// Invalidates a line which left a cache in the caches above it if it's inclusive, then writes
+    /// the line below if it was dirty, in the cache or in any of those above it
+    fn release_line(&mut self, topology: &Topology, level: usize, line_address: u64, events: &mut impl HierarchyEvents) {
+        let dirty_above = self.invalidate_above(topology, level, line_address, events);
+        if self.dirty[level].remove(&line_address) || dirty_above {
+            self.write_back(level, line_address, events);
+        }
+    }
+
+    /// Writes a dirty line which left a cache to the level below, through the cache's writeback
+    /// buffer if it has one
+    fn write_back(&mut self, level: usize, line_address: u64, events: &mut impl HierarchyEvents) {
+        let written = match &mut self.writeback_buffers[level] {
+            Some(buffer) => buffer.push(level, line_address, events),
+            None => Some(line_address),
+        };
+        if let Some(written) = written {
+            events.writeback(level);
+            self.pending_writes.push((level, written));
+        }
+    }
+
+    /// Invalidates a line which left an inclusive cache in every cache above it
+    ///
+    /// returns: bool, whether any of the lines invalidated were dirty
+    fn invalidate_above(&mut self, topology: &Topology, level: usize, evicted: u64, events: &mut impl HierarchyEvents) -> bool {
+        let mut dirty = false;
+        if self.inclusivity[level] != InclusivityConfig::Inclusive {
+            return dirty;
+        }
+        let line_size = self.caches[level].get_line_size();
+        for &upper in topology.above(level) {
+            // Lines above may be smaller, so invalidate all of them within the evicted line
+            let upper_line_size = self.caches[upper].get_line_size();
+            let mut address = evicted;
+            while address < evicted + line_size {
+                if self.caches[upper].invalidate_line(address) {
+                    events.eviction(upper, &self.caches[upper], address);
+                    dirty |= self.dirty[upper].remove(&address);
+                }
+                address += upper_line_size;
+            }
+        }
+        dirty
+    }
+}
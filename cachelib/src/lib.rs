@@ -7,6 +7,32 @@
 //!
 //! While designed to accommodate high performance, it prioritises flexibility, being easy to
 //! maintain and expand with new policies
+//!
+//! Without the default `std` feature, the library builds with `no_std` and `alloc`, leaving out
+//! loading configurations from files, timing simulations and the policy registry. The test
+//! utilities in `util` are only built with the `util` feature, and the harness for the examples
+//! with `examples-harness`
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(clippy::undocumented_unsafe_blocks)]
+
+extern crate alloc;
+
+/// The parts of the standard prelude which come from alloc, so modules build the same with and
+/// without std
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::format;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
+/// Contains the trait for the integer types a single cache can use as addresses
+pub mod address;
+
+/// Contains analyses of traces which answer questions about many caches at once, rather than
+/// simulating each cache separately
+pub mod analysis;
 
 /// Contains the implementation of the cache, and a utility enum for the existing cache types
 pub mod cache;
@@ -15,13 +41,38 @@ pub mod cache;
 /// policies
 pub mod config;
 
+/// Contains the provided prefetchers, with a trait for implementing custom prefetchers
+pub mod prefetchers;
+
 /// Contains the provided replacement policies, with a trait for implementing custom replacement
 /// policies
 pub mod replacement_policies;
 
+/// Contains the resolution of the links between caches into the path taken by each core's accesses
+pub mod topology;
+
+/// Contains the hierarchy of caches accesses pass through, for front ends which make accesses one
+/// at a time
+pub mod layered;
+
+/// Contains the versioned output document of a simulation, which extends the format of the
+/// specification with any further statistics collected
+pub mod results;
+
 /// Contains the simulator used to simulate a program with a given cache configuration
 pub mod simulator;
-// Generated from the build.rs, private
+
+/// Contains pipelines which parse a trace on other threads while it is simulated, or simulate each
+/// level of a hierarchy on its own thread
+#[cfg(feature = "std")]
+pub mod parallel;
+
+/// Contains an engine simulating many configurations on a trace in a single pass
+#[cfg(feature = "std")]
+pub mod ensemble;
+// Generated from the build.rs, private. The lookup table is a const, so clippy's warning about
+// large const arrays doesn't apply
+#[allow(clippy::large_const_arrays)]
 mod hex {
     include!(concat!(env!("OUT_DIR"), "/hex.rs"));
 }
@@ -29,4 +80,5 @@ mod hex {
 mod test;
 
 /// Contains utilities for running tests and benchmarks.
+#[cfg(feature = "util")]
 pub mod util;
\ No newline at end of file
@@ -0,0 +1,213 @@
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+use crate::cache::{CacheTrait, GenericCache, HitMissCounters};
+use crate::simulator::{Access, LayeredCacheResult, Simulator};
+
+/// The number of records parsed into each batch
+pub const DEFAULT_BATCH_RECORDS: usize = 16 * 1024;
+
+/// The number of parsed batches each parser can get ahead of the simulator before it waits, which
+/// bounds the memory used when parsing is faster than simulating
+const BATCHES_IN_FLIGHT: usize = 4;
+
+/// The number of batches a [LevelPipeline] simulates between calls to its progress callback, each
+/// run starting the threads of the levels afresh
+const LEVEL_PIPELINE_BATCHES: usize = 64;
+
+/// Simulates a trace with a pipeline, parsing records into [Access] batches on other threads while
+/// the simulator works through earlier batches
+///
+/// Batches are handed out to the parsers in turn and collected in the same order, so the accesses
+/// reach the simulator in the order of the trace and the results are identical to
+/// [simulate_core](Simulator::simulate_core). Parsers block once they are a few batches ahead,
+/// so a slow simulation doesn't fill memory with parsed accesses.
+///
+/// The simulator's [execution time](Simulator::get_execution_time) only counts simulating the
+/// batches, not waiting for them to be parsed.
+pub struct Pipeline {
+    parsers: usize,
+    batch_records: usize,
+}
+
+impl Pipeline {
+    /// Creates a pipeline using a number of threads in total, one of which simulates while the rest
+    /// parse
+    ///
+    /// # Arguments
+    ///
+    /// * `threads`: The number of threads, at least 2
+    ///
+    /// returns: Result<Pipeline, String>
+    pub fn new(threads: usize) -> Result<Self, String> {
+        if threads < 2 {
+            return Err(format!("A pipeline needs at least 2 threads, one to parse and one to simulate, not {threads}"));
+        }
+        Ok(Self { parsers: threads - 1, batch_records: DEFAULT_BATCH_RECORDS })
+    }
+
+    /// Sets the number of records in each batch. Larger batches have less overhead, smaller ones
+    /// start simulating sooner and use less memory
+    pub fn with_batch_records(mut self, batch_records: usize) -> Self {
+        self.batch_records = batch_records.max(1);
+        self
+    }
+
+    /// Simulates a trace as accesses from the given core, in the same way as
+    /// [simulate_core](Simulator::simulate_core)
+    ///
+    /// # Arguments
+    ///
+    /// * `simulator`: The simulator, which keeps its state between calls as usual
+    /// * `bytes`: The input byte array
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn simulate_core<'a>(&self, simulator: &'a mut Simulator, bytes: &[u8], core: u32) -> Result<&'a LayeredCacheResult, String> {
+        self.simulate_core_with_progress(simulator, bytes, core, &mut |_| {})
+    }
+
+    /// Simulates a trace in the same way as [simulate_core](Self::simulate_core), calling
+    /// `progress` with the number of bytes of the input simulated so far after each batch
+    pub fn simulate_core_with_progress<'a>(&self, simulator: &'a mut Simulator, bytes: &[u8], core: u32, progress: &mut dyn FnMut(usize)) -> Result<&'a LayeredCacheResult, String> {
+        assert_eq!(bytes.len() % 40, 0);
+        if simulator.topology().path(core).is_none() {
+            return Err(format!("The configuration has no caches for core {core}"));
+        }
+        let batches: Vec<&[u8]> = bytes.chunks(self.batch_records * 40).collect();
+        thread::scope(|scope| {
+            let receivers: Vec<Receiver<Vec<Access>>> = (0..self.parsers).map(|parser| {
+                let (sender, receiver) = sync_channel(BATCHES_IN_FLIGHT);
+                let batches = &batches;
+                let parsers = self.parsers;
+                scope.spawn(move || {
+                    for batch in batches.iter().skip(parser).step_by(parsers) {
+                        let accesses = parse_batch(batch);
+                        // The simulator stopped early, which it only does on an error
+                        if sender.send(accesses).is_err() {
+                            break;
+                        }
+                    }
+                });
+                receiver
+            }).collect();
+            for index in 0..batches.len() {
+                let accesses = receivers[index % self.parsers].recv()
+                    .map_err(|_| "A parser thread stopped before parsing its batches".to_string())?;
+                simulator.simulate_accesses(accesses, core)?;
+                progress(((index + 1) * self.batch_records * 40).min(bytes.len()));
+            }
+            Ok::<(), String>(())
+        })?;
+        Ok(simulator.get_result())
+    }
+}
+
+/// Simulates a trace with each cache of a core's path on its own thread, the threads connected by
+/// bounded queues carrying batches of the lines which missed in the level above, so the first level
+/// reads one batch while the last reads the misses of an earlier one
+///
+/// Each level sees the same lines in the same order as in a serial simulation, so the results are
+/// identical to [simulate_core](Simulator::simulate_core). Levels which hit most of what they see
+/// leave the levels below little to do, so this pays off for deep hierarchies with first levels
+/// which filter out relatively few accesses. Only hierarchies which can be read in batches are
+/// supported, so not those with prefetchers, instruction caches, inclusive or exclusive caches,
+/// shadows or a page size, nor simulators with observers, set statistics, decision logs or fetched program counters, as
+/// these need every level to have finished with an access before the next, or fetches to take
+/// their own path.
+pub struct LevelPipeline {
+    batch_records: usize,
+}
+
+impl Default for LevelPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LevelPipeline {
+    /// Creates a pipeline with a thread for each level of the path simulated, which parses the
+    /// trace on the calling thread
+    pub fn new() -> Self {
+        Self { batch_records: DEFAULT_BATCH_RECORDS }
+    }
+
+    /// Sets the number of records in each batch. Larger batches have less overhead, smaller ones
+    /// keep more of the levels busy at once on short traces
+    pub fn with_batch_records(mut self, batch_records: usize) -> Self {
+        self.batch_records = batch_records.max(1);
+        self
+    }
+
+    /// Simulates a trace as accesses from the given core, in the same way as
+    /// [simulate_core](Simulator::simulate_core), including any warm up or intervals
+    ///
+    /// # Arguments
+    ///
+    /// * `simulator`: The simulator, which keeps its state between calls as usual
+    /// * `bytes`: The input byte array
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>, an error if the hierarchy or simulator can't
+    /// be pipelined by level
+    pub fn simulate_core<'a>(&self, simulator: &'a mut Simulator, bytes: &[u8], core: u32) -> Result<&'a LayeredCacheResult, String> {
+        self.simulate_core_with_progress(simulator, bytes, core, &mut |_| {})
+    }
+
+    /// Simulates a trace in the same way as [simulate_core](Self::simulate_core), calling
+    /// `progress` with the number of bytes of the input simulated so far after every few batches
+    pub fn simulate_core_with_progress<'a>(&self, simulator: &'a mut Simulator, bytes: &[u8], core: u32, progress: &mut dyn FnMut(usize)) -> Result<&'a LayeredCacheResult, String> {
+        let path = simulator.topology().path(core).ok_or(format!("The configuration has no caches for core {core}"))?.to_vec();
+        if !simulator.batches(&path) {
+            return Err("Only hierarchies without prefetchers, instruction caches, inclusive or exclusive caches, shadows or a page size, simulated without observers, set statistics, decision logs or fetched program counters, can be pipelined by level".to_string());
+        }
+        let mut processed = 0;
+        for block in bytes.chunks(LEVEL_PIPELINE_BATCHES * self.batch_records * 40) {
+            simulator.simulate_core_blocks(block, core, |simulator, records, path| simulator.simulate_records_by_level(records, path, self.batch_records))?;
+            processed += block.len();
+            progress(processed);
+        }
+        Ok(simulator.get_result())
+    }
+}
+
+/// Reads batches of lines through caches each on their own thread, passing the misses of each
+/// cache to the next, returning the hits and misses of each cache
+pub(crate) fn access_lines_by_level(caches: Vec<&mut GenericCache>, batches: impl Iterator<Item = Vec<u64>>) -> Vec<HitMissCounters> {
+    let levels = caches.len();
+    thread::scope(|scope| {
+        let (first_sender, mut receiver) = sync_channel::<Vec<u64>>(BATCHES_IN_FLIGHT);
+        let threads: Vec<_> = caches.into_iter().enumerate().map(|(index, cache)| {
+            let (sender, next_receiver) = sync_channel::<Vec<u64>>(BATCHES_IN_FLIGHT);
+            let lines = core::mem::replace(&mut receiver, next_receiver);
+            let last = index == levels - 1;
+            scope.spawn(move || {
+                let mut counters = HitMissCounters::default();
+                // Ends once the level above has sent its last batch and dropped its sender
+                for lines in lines {
+                    if last {
+                        cache.read_and_update_lines(&lines, &mut counters);
+                        continue;
+                    }
+                    let mut misses = Vec::new();
+                    cache.read_and_update_lines_keeping_misses(&lines, &mut misses);
+                    counters.misses += misses.len() as u64;
+                    counters.hits += (lines.len() - misses.len()) as u64;
+                    if !misses.is_empty() {
+                        sender.send(misses).expect("The next level reads until this level finishes");
+                    }
+                }
+                counters
+            })
+        }).collect();
+        for batch in batches {
+            first_sender.send(batch).expect("The first level reads until the batches finish");
+        }
+        drop(first_sender);
+        threads.into_iter().map(|thread| thread.join().expect("A level of the pipeline panicked")).collect()
+    })
+}
+
+/// Parses a batch of records in the format of the specification
+pub(crate) fn parse_batch(records: &[u8]) -> Vec<Access> {
+    records.chunks_exact(40).map(|record| Access::from_record(record.try_into().unwrap())).collect()
+}
@@ -0,0 +1,164 @@
+use crate::config::{PrefetcherConfig, PrefetcherKindConfig};
+use crate::prelude::*;
+
+/// A generic trait for prefetchers, which observe the demand accesses to a cache and suggest lines
+/// to bring into it ahead of time
+pub trait Prefetcher {
+    /// Observes a demand access to a cache line, pushing the addresses of any lines to prefetch
+    ///
+    /// # Arguments
+    ///
+    /// * `line_address`: The line-aligned address of the access
+    /// * `pc`: The program counter of the instruction making the access
+    /// * `hit`: Whether the access hit in the cache
+    /// * `prefetches`: The output buffer for the line addresses to prefetch. These don't need to be
+    ///   aligned, the simulator aligns them to the cache line
+    ///
+    /// returns: ()
+    fn observe(&mut self, line_address: u64, pc: u64, hit: bool, prefetches: &mut Vec<u64>);
+
+    /// Whether the prefetcher needs the program counter of each access. Parsing it has a cost,
+    /// so the simulator only does so when a prefetcher asks for it
+    fn needs_pc(&self) -> bool {
+        false
+    }
+}
+
+/// Prefetches the lines following a line which missed
+#[derive(Clone)]
+pub struct NextLine {
+    line_size: u64,
+    degree: u64,
+    distance: u64,
+}
+
+impl NextLine {
+    pub fn new(line_size: u64, degree: u64, distance: u64) -> Self {
+        Self {
+            line_size,
+            degree,
+            distance,
+        }
+    }
+}
+
+impl Prefetcher for NextLine {
+    fn observe(&mut self, line_address: u64, _pc: u64, hit: bool, prefetches: &mut Vec<u64>) {
+        if !hit {
+            for step in self.distance..self.distance + self.degree {
+                prefetches.push(line_address.wrapping_add(step * self.line_size));
+            }
+        }
+    }
+}
+
+/// A reference prediction table entry for the stride prefetcher
+#[derive(Clone, Copy, Default)]
+struct StrideEntry {
+    pc: u64,
+    last_address: u64,
+    stride: i64,
+    confidence: u8,
+}
+
+/// Classic PC-indexed stride prefetcher
+///
+/// Each entry of the reference prediction table tracks the last address and the stride between
+/// accesses made by one instruction. Once the same stride has been seen twice in a row, lines are
+/// prefetched along the stride
+#[derive(Clone)]
+pub struct Stride {
+    table: Vec<StrideEntry>,
+    degree: u64,
+    distance: u64,
+}
+
+/// The confidence needed before the stride prefetcher issues prefetches
+const STRIDE_CONFIDENCE_THRESHOLD: u8 = 2;
+
+/// The maximum confidence of a stride prefetcher entry
+const STRIDE_MAX_CONFIDENCE: u8 = 3;
+
+impl Stride {
+    pub fn new(table_size: u64, degree: u64, distance: u64) -> Self {
+        Self {
+            table: vec![StrideEntry::default(); table_size as usize],
+            degree,
+            distance,
+        }
+    }
+}
+
+impl Prefetcher for Stride {
+    fn observe(&mut self, line_address: u64, pc: u64, _hit: bool, prefetches: &mut Vec<u64>) {
+        // Table sizes are validated as powers of two
+        let index = (pc & (self.table.len() as u64 - 1)) as usize;
+        let entry = &mut self.table[index];
+        if entry.pc != pc {
+            *entry = StrideEntry {
+                pc,
+                last_address: line_address,
+                stride: 0,
+                confidence: 0,
+            };
+            return;
+        }
+        let stride = line_address.wrapping_sub(entry.last_address) as i64;
+        if stride == 0 {
+            // Same line again, nothing new to learn
+            return;
+        }
+        if stride == entry.stride {
+            entry.confidence = (entry.confidence + 1).min(STRIDE_MAX_CONFIDENCE);
+        } else {
+            entry.confidence = entry.confidence.saturating_sub(1);
+            if entry.confidence == 0 {
+                entry.stride = stride;
+            }
+        }
+        entry.last_address = line_address;
+        if entry.confidence >= STRIDE_CONFIDENCE_THRESHOLD {
+            for step in self.distance..self.distance + self.degree {
+                prefetches.push(line_address.wrapping_add((entry.stride as u64).wrapping_mul(step)));
+            }
+        }
+    }
+
+    fn needs_pc(&self) -> bool {
+        true
+    }
+}
+
+/// Enum for the prefetchers provided by the library, for the same reasons as
+/// [GenericCache](crate::cache::GenericCache)
+#[derive(Clone)]
+pub enum GenericPrefetcher {
+    NextLine(NextLine),
+    Stride(Stride),
+}
+
+impl GenericPrefetcher {
+    /// Creates a prefetcher from its configuration, for a cache with the given line size
+    pub fn from_config(config: &PrefetcherConfig, line_size: u64) -> Self {
+        match config.kind {
+            PrefetcherKindConfig::NextLine => Self::NextLine(NextLine::new(line_size, config.degree, config.distance)),
+            PrefetcherKindConfig::Stride => Self::Stride(Stride::new(config.table_size(), config.degree, config.distance)),
+        }
+    }
+}
+
+impl Prefetcher for GenericPrefetcher {
+    fn observe(&mut self, line_address: u64, pc: u64, hit: bool, prefetches: &mut Vec<u64>) {
+        match self {
+            GenericPrefetcher::NextLine(p) => p.observe(line_address, pc, hit, prefetches),
+            GenericPrefetcher::Stride(p) => p.observe(line_address, pc, hit, prefetches),
+        }
+    }
+
+    fn needs_pc(&self) -> bool {
+        match self {
+            GenericPrefetcher::NextLine(p) => p.needs_pc(),
+            GenericPrefetcher::Stride(p) => p.needs_pc(),
+        }
+    }
+}
@@ -1,3 +1,9 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::sync::RwLock;
+use crate::prelude::*;
+
 /// A generic trait for implementing new replacement policies. Can be used to parameterise a Cache.
 pub trait ReplacementPolicy {
     /// Updates the policy when a cache line is read
@@ -21,16 +27,254 @@ pub trait ReplacementPolicy {
     /// # Arguments
     ///
     /// * `set_lower_bound_index`: The lower bound for the cache lines of the set. This is equal to
-    /// set * cache_lines_per_set, but this allows it to be cached, as it is already known by the
-    /// cache
+    ///   set * cache_lines_per_set, but this allows it to be cached, as it is already known by the
+    ///   cache
     /// * `set`: The cache set
     /// * `cache_lines_per_set`: The number of cache lines per set
     ///
     /// returns: u64
     fn get_new_line(&mut self, set_lower_bound_index: u64, set: u64, cache_lines_per_set: u64) -> u64;
+
+    /// Advances any logical clock the policy keeps, as if a line in a set simulated elsewhere had
+    /// been used
+    ///
+    /// Sharded simulation uses this so caches holding only some of the sets order their lines in
+    /// the same way as the whole cache would. Policies without a clock shared between sets don't
+    /// need to implement it
+    fn advance_clock(&mut self) {}
+
+    /// Describes the policy's state for a line, such as when it was last used, for inspecting the
+    /// contents of a cache
+    ///
+    /// Policies without state for each line don't need to implement it
+    ///
+    /// # Arguments
+    ///
+    /// * `cache_index`: The index of the cache line
+    /// * `set`: The cache set holding the line
+    /// * `cache_lines_per_set`: The number of cache lines per set
+    ///
+    /// returns: Option<serde_json::Value>
+    fn line_metadata(&self, _cache_index: u64, _set: u64, _cache_lines_per_set: u64) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Gets the bytes of host memory the policy's state holds, for predicting the memory a
+    /// simulation needs
+    ///
+    /// Policies without state don't need to implement it
+    fn memory_usage(&self) -> usize {
+        0
+    }
+
+    /// Checks the policy's state for a set is consistent with which of its lines are valid, for
+    /// verifying a simulation, see
+    /// [verify_invariants](crate::simulator::builder::SimulatorBuilder::verify_invariants)
+    ///
+    /// Policies without invariants of their own don't need to implement it
+    ///
+    /// # Arguments
+    ///
+    /// * `set_lower_bound_index`: The index of the first line of the set
+    /// * `set`: The cache set
+    /// * `valid`: Whether each line of the set is valid, by way
+    ///
+    /// returns: Result<(), String>, a description of the first inconsistency found
+    fn check_set(&self, _set_lower_bound_index: u64, _set: u64, _valid: &[bool]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Boxed policies are used for policies which aren't known until runtime, such as those from the
+/// registry
+impl<P: ReplacementPolicy + ?Sized> ReplacementPolicy for Box<P> {
+    fn update_on_read(&mut self, cache_index: u64) {
+        (**self).update_on_read(cache_index)
+    }
+
+    fn get_new_line(&mut self, set_lower_bound_index: u64, set: u64, cache_lines_per_set: u64) -> u64 {
+        (**self).get_new_line(set_lower_bound_index, set, cache_lines_per_set)
+    }
+
+    fn advance_clock(&mut self) {
+        (**self).advance_clock()
+    }
+
+    fn line_metadata(&self, cache_index: u64, set: u64, cache_lines_per_set: u64) -> Option<serde_json::Value> {
+        (**self).line_metadata(cache_index, set, cache_lines_per_set)
+    }
+
+    fn memory_usage(&self) -> usize {
+        (**self).memory_usage()
+    }
+
+    fn check_set(&self, set_lower_bound_index: u64, set: u64, valid: &[bool]) -> Result<(), String> {
+        (**self).check_set(set_lower_bound_index, set, valid)
+    }
+}
+
+/// A policy which can be held behind a trait object, as registered policies are
+///
+/// Every policy which is `Clone`, `Send` and `Sync` is one, so simulators using registered policies
+/// can be cloned and shared between threads like those using the built-in policies
+pub trait DynamicPolicy: ReplacementPolicy + Send + Sync {
+    /// Copies the policy and its state into a new box
+    fn clone_box(&self) -> Box<dyn DynamicPolicy>;
+}
+
+impl<P: ReplacementPolicy + Clone + Send + Sync + 'static> DynamicPolicy for P {
+    fn clone_box(&self) -> Box<dyn DynamicPolicy> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn DynamicPolicy> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+/// A parameter accepted by a replacement policy through the `policy_params` of a cache config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyParameter {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: PolicyParameterKind,
+}
+
+/// The values a policy parameter accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyParameterKind {
+    /// A whole number within the inclusive range
+    Integer { min: u64, max: u64 },
+    Boolean,
+}
+
+impl PolicyParameter {
+    /// Checks a value is acceptable for this parameter
+    pub fn validate(&self, value: &serde_json::Value) -> Result<(), String> {
+        match self.kind {
+            PolicyParameterKind::Integer { min, max } => match value.as_u64() {
+                Some(v) if (min..=max).contains(&v) => Ok(()),
+                _ => Err(format!("The policy parameter {} must be a whole number from {min} to {max}, got {value}", self.name)),
+            },
+            PolicyParameterKind::Boolean => match value {
+                serde_json::Value::Bool(_) => Ok(()),
+                _ => Err(format!("The policy parameter {} must be true or false, got {value}", self.name)),
+            },
+        }
+    }
 }
 
-#[derive(Default)]
+/// Checks a set of policy parameters against those a policy accepts
+///
+/// Unknown parameters are rejected, with a suggestion if the name is close to an accepted one, so
+/// typos produce an error rather than being silently ignored
+///
+/// # Arguments
+///
+/// * `policy`: The name of the policy, for error messages
+/// * `accepted`: The parameters the policy accepts
+/// * `params`: The configured parameters
+///
+/// returns: Result<(), String>
+pub fn validate_policy_params(policy: &str, accepted: &[PolicyParameter], params: &serde_json::Map<String, serde_json::Value>) -> Result<(), String> {
+    for (name, value) in params {
+        match accepted.iter().find(|p| p.name == name) {
+            Some(parameter) => parameter.validate(value)?,
+            None => {
+                let suggestion = accepted.iter()
+                    .min_by_key(|p| edit_distance(p.name, name))
+                    .filter(|p| edit_distance(p.name, name) <= 2)
+                    .map(|p| format!(", did you mean {}?", p.name))
+                    .unwrap_or_default();
+                let accepted_names = if accepted.is_empty() {
+                    "it doesn't accept any parameters".to_string()
+                } else {
+                    format!("accepted parameters are {}", accepted.iter().map(|p| p.name).collect::<Vec<_>>().join(", "))
+                };
+                return Err(format!("Unknown parameter {name} for the {policy} policy, {accepted_names}{suggestion}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates a registered policy for a cache, given its number of lines, its number of sets, and its
+/// validated `policy_params`
+pub type PolicyFactory = Arc<dyn Fn(u64, u64, &serde_json::Map<String, serde_json::Value>) -> Box<dyn DynamicPolicy> + Send + Sync>;
+
+/// A replacement policy registered at runtime, which configurations can refer to by name
+#[derive(Clone)]
+pub struct RegisteredPolicy {
+    pub name: String,
+    /// The parameters the policy accepts through `policy_params`
+    pub parameters: Vec<PolicyParameter>,
+    pub factory: PolicyFactory,
+}
+
+/// The names of the policies built into the library, which can't be registered over
+pub const BUILT_IN_POLICIES: &[&str] = &["rr", "lru", "lfu"];
+
+/// The policies registered at runtime, in the order they were registered
+#[cfg(feature = "std")]
+static REGISTRY: RwLock<Vec<RegisteredPolicy>> = RwLock::new(Vec::new());
+
+/// Registers a policy so configurations can refer to it by name
+///
+/// # Arguments
+///
+/// * `policy`: The policy to register
+///
+/// returns: Result<(), String>, an error if a policy with the same name is built in or has already
+/// been registered
+#[cfg(feature = "std")]
+pub fn register_policy(policy: RegisteredPolicy) -> Result<(), String> {
+    let mut registry = REGISTRY.write().map_err(|_| "The policy registry is poisoned".to_string())?;
+    if BUILT_IN_POLICIES.contains(&policy.name.as_str()) || registry.iter().any(|p| p.name == policy.name) {
+        return Err(format!("There is already a replacement policy named {}", policy.name));
+    }
+    registry.push(policy);
+    Ok(())
+}
+
+/// Gets a registered policy by name, or an error listing the known policies if there is none
+///
+/// Without the std feature there is no registry, so this always returns an error
+pub fn registered_policy(name: &str) -> Result<RegisteredPolicy, String> {
+    #[cfg(feature = "std")]
+    let registry = REGISTRY.read().map_err(|_| "The policy registry is poisoned".to_string())?;
+    #[cfg(not(feature = "std"))]
+    let registry: [RegisteredPolicy; 0] = [];
+    match registry.iter().find(|p| p.name == name) {
+        Some(policy) => Ok(policy.clone()),
+        None => {
+            let known: Vec<&str> = BUILT_IN_POLICIES.iter().copied().chain(registry.iter().map(|p| p.name.as_str())).collect();
+            let suggestion = known.iter()
+                .min_by_key(|known| edit_distance(known, name))
+                .filter(|known| edit_distance(known, name) <= 2)
+                .map(|known| format!(", did you mean {known}?"))
+                .unwrap_or_default();
+            Err(format!("Unknown replacement policy {name}, known policies are {}{suggestion}", known.join(", ")))
+        }
+    }
+}
+
+/// The Levenshtein distance between two strings, for suggesting corrections to misspelt names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(ca != *cb)).min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[derive(Default, Clone, Copy)]
 /// NoPolicy is used for direct mapped caches. It does nothing when updating on read, and simply
 /// returns the set lower bound index when a new line is requested
 ///
@@ -47,6 +291,7 @@ impl ReplacementPolicy for NoPolicy {
 }
 
 /// Standard round robin replacement policy, which keeps separate indices for each set
+#[derive(Clone)]
 pub struct RoundRobin {
     set_indices: Vec<u64>,
 }
@@ -68,6 +313,22 @@ impl ReplacementPolicy for RoundRobin {
         *set_index = (*set_index + 1) % cache_lines_per_set;
         val
     }
+
+    fn line_metadata(&self, cache_index: u64, set: u64, cache_lines_per_set: u64) -> Option<serde_json::Value> {
+        let next = set * cache_lines_per_set + self.set_indices[set as usize];
+        Some(serde_json::json!({"next_victim": cache_index == next}))
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of_val(self.set_indices.as_slice())
+    }
+
+    fn check_set(&self, _set_lower_bound_index: u64, set: u64, valid: &[bool]) -> Result<(), String> {
+        match self.set_indices[set as usize] {
+            next if next < valid.len() as u64 => Ok(()),
+            next => Err(format!("the next victim is way {next} of a {} way set", valid.len())),
+        }
+    }
 }
 
 /// Least Recently Used replacement policy
@@ -75,6 +336,7 @@ impl ReplacementPolicy for RoundRobin {
 /// This implementation keeps track of when each line was last used, and also keeps track of a
 /// logical clock, which is updated each time a line is used. This saves comparisons during search
 /// for a new line, we already know what the timestamp should be
+#[derive(Clone)]
 pub struct LeastRecentlyUsed {
     last_used_times: Vec<u64>,
     // Tracking logical time means we have fewer comparisons when finding a new line
@@ -112,24 +374,174 @@ impl ReplacementPolicy for LeastRecentlyUsed {
         self.time += 1;
         (min_index) as u64
     }
+
+    fn advance_clock(&mut self) {
+        self.time += 1;
+    }
+
+    fn line_metadata(&self, cache_index: u64, _set: u64, _cache_lines_per_set: u64) -> Option<serde_json::Value> {
+        Some(serde_json::json!({"last_used": self.last_used_times[cache_index as usize]}))
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of_val(self.last_used_times.as_slice())
+    }
+
+    fn check_set(&self, set_lower_bound_index: u64, _set: u64, valid: &[bool]) -> Result<(), String> {
+        // Every valid line was last used at a different tick of the clock, before the current one
+        let mut times = BTreeMap::new();
+        for way in (0..valid.len()).filter(|&way| valid[way]) {
+            let time = self.last_used_times[set_lower_bound_index as usize + way];
+            if time >= self.time {
+                return Err(format!("way {way} was last used at {time}, but the clock is only at {}", self.time));
+            }
+            if let Some(other) = times.insert(time, way) {
+                return Err(format!("ways {other} and {way} were both last used at {time}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Least Recently Used replacement policy keeping the recency order of each set as a permutation
+/// packed into a single word, rather than a 64-bit timestamp per line, for sets of up to
+/// [MAX_WAYS](Self::MAX_WAYS) lines, so only fully associative caches with that few lines can
+/// use it
+///
+/// Each set's word holds its ways as 4-bit digits, from the least recently used in the lowest digit
+/// to the most recently used, so the victim is the lowest digit and using a line moves its digit to
+/// the top. Ways start in order, and ways which haven't been used stay below the rest in that order,
+/// matching [LeastRecentlyUsed], where empty lines and the first line ever used share a timestamp
+/// of 0 and the lowest way wins the tie. Evictions are the same as LeastRecentlyUsed's, with the
+/// state of a whole set taking the space of a single timestamp.
+#[derive(Clone)]
+pub struct CompactLeastRecentlyUsed {
+    orders: Vec<u64>,
+    ways: u64,
+    way_bits: u32,
+    // Whether LeastRecentlyUsed's clock would have passed 0, so the line used next doesn't keep a
+    // timestamp of 0
+    started: bool,
+}
+
+impl CompactLeastRecentlyUsed {
+    /// The largest number of ways the policy supports, as many as fit in a word as 4-bit digits
+    pub const MAX_WAYS: u64 = 16;
+
+    /// # Panics
+    ///
+    /// If `ways` isn't a power of two of at most [MAX_WAYS](Self::MAX_WAYS)
+    pub fn new(num_lines: u64, ways: u64) -> Self {
+        assert!(ways.is_power_of_two() && ways <= Self::MAX_WAYS, "Compact LRU needs a power of two ways up to {}, not {ways}", Self::MAX_WAYS);
+        // The ways in order, 0 in the lowest digit
+        let initial = 0xfedc_ba98_7654_3210 & (u64::MAX >> (64 - 4 * ways));
+        Self {
+            orders: vec![initial; (num_lines / ways) as usize],
+            ways,
+            way_bits: ways.trailing_zeros(),
+            started: false,
+        }
+    }
+
+    /// Makes a way the most recently used of its set
+    #[inline(always)]
+    fn touch(&mut self, set: usize, way: u64, cache_lines_per_set: u64) {
+        if !self.started {
+            self.started = true;
+            return;
+        }
+        let order = self.orders[set];
+        // Finds the lowest digit equal to the way, which is exact even though higher digits can
+        // give false positives
+        let differences = order ^ (way * 0x1111_1111_1111_1111);
+        let zeros = differences.wrapping_sub(0x1111_1111_1111_1111) & !differences & 0x8888_8888_8888_8888;
+        let position = zeros.trailing_zeros() / 4;
+        let top = cache_lines_per_set as u32 - 1;
+        if position == top {
+            return;
+        }
+        let below = order & ((1 << (4 * position)) - 1);
+        let above = order >> (4 * (position + 1));
+        self.orders[set] = below | above << (4 * position) | way << (4 * top);
+    }
+}
+
+impl ReplacementPolicy for CompactLeastRecentlyUsed {
+    fn update_on_read(&mut self, cache_index: u64) {
+        self.touch((cache_index >> self.way_bits) as usize, cache_index & (self.ways - 1), self.ways);
+    }
+
+    fn get_new_line(&mut self, set_lower_bound_index: u64, set: u64, cache_lines_per_set: u64) -> u64 {
+        let way = self.orders[set as usize] & 0xf;
+        self.touch(set as usize, way, cache_lines_per_set);
+        set_lower_bound_index + way
+    }
+
+    fn advance_clock(&mut self) {
+        self.started = true;
+    }
+
+    fn line_metadata(&self, cache_index: u64, set: u64, cache_lines_per_set: u64) -> Option<serde_json::Value> {
+        let way = cache_index - set * cache_lines_per_set;
+        let order = self.orders[set as usize];
+        let recency = (0..cache_lines_per_set).find(|&position| (order >> (4 * position)) & 0xf == way);
+        Some(serde_json::json!({"recency": recency}))
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of_val(self.orders.as_slice())
+    }
+
+    fn check_set(&self, _set_lower_bound_index: u64, set: u64, valid: &[bool]) -> Result<(), String> {
+        // The digits of the order hold each way exactly once, and nothing above them
+        let order = self.orders[set as usize];
+        let digits = valid.len() as u32;
+        let ways = (0..digits).fold(0u64, |ways, position| ways | 1 << ((order >> (4 * position)) & 0xf));
+        match ways == (1 << digits) - 1 && order.checked_shr(4 * digits).unwrap_or(0) == 0 {
+            true => Ok(()),
+            false => Err(format!("the recency order {order:#x} isn't an order of the {digits} ways")),
+        }
+    }
 }
 
 /// Least frequently used replacement policy
+///
+/// Usage counters saturate at a configurable width, as they would in hardware
+#[derive(Clone)]
 pub struct LeastFrequentlyUsed {
-    usages: Vec<u64>
+    usages: Vec<u64>,
+    max_usage: u64,
 }
 
 impl LeastFrequentlyUsed {
+    /// The parameters accepted through `policy_params`
+    pub const PARAMETERS: &'static [PolicyParameter] = &[
+        PolicyParameter {
+            name: "counter_bits",
+            description: "The width of the saturating usage counters, defaults to 64",
+            kind: PolicyParameterKind::Integer { min: 1, max: 64 },
+        },
+    ];
+
     pub fn new(num_lines: u64) -> Self {
+        Self::with_counter_bits(num_lines, u64::BITS as u64)
+    }
+
+    /// Creates the policy with usage counters of the given width, which saturate rather than wrap
+    pub fn with_counter_bits(num_lines: u64, counter_bits: u64) -> Self {
         Self {
-            usages: vec![0; num_lines as usize]
+            usages: vec![0; num_lines as usize],
+            max_usage: u64::MAX >> (u64::BITS as u64 - counter_bits),
         }
     }
 }
 
 impl ReplacementPolicy for LeastFrequentlyUsed {
     fn update_on_read(&mut self, cache_index: u64) {
-        self.usages[cache_index as usize] += 1;
+        let usage = &mut self.usages[cache_index as usize];
+        if *usage < self.max_usage {
+            *usage += 1;
+        }
     }
 
     fn get_new_line(&mut self, set_lower_bound_index: u64, _set: u64, cache_lines_per_set: u64) -> u64 {
@@ -149,4 +561,133 @@ impl ReplacementPolicy for LeastFrequentlyUsed {
         self.usages[min_index] = 1;
         (min_index) as u64
     }
+
+    fn line_metadata(&self, cache_index: u64, _set: u64, _cache_lines_per_set: u64) -> Option<serde_json::Value> {
+        Some(serde_json::json!({"uses": self.usages[cache_index as usize]}))
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of_val(self.usages.as_slice())
+    }
+
+    fn check_set(&self, set_lower_bound_index: u64, _set: u64, valid: &[bool]) -> Result<(), String> {
+        check_usages(&self.usages[set_lower_bound_index as usize..][..valid.len()], valid, self.max_usage)
+    }
+}
+
+/// Least frequently used replacement policy keeping each set's lines in a binary heap ordered by
+/// their uses, so choosing a victim doesn't scan the set, for caches with large sets
+///
+/// Lines are ordered by their uses and then by their way, so ties go to the lowest way as they do
+/// for [LeastFrequentlyUsed], and the two evict the same lines. Using a line or choosing a victim
+/// takes time logarithmic in the number of ways, rather than a victim taking time linear in it, so
+/// this is only faster for sets of at least [MIN_WAYS](Self::MIN_WAYS) lines.
+#[derive(Clone)]
+pub struct HeapLeastFrequentlyUsed {
+    usages: Vec<u64>,
+    // The lines of each set in heap order, within the set's own range of indices
+    heap: Vec<u32>,
+    // The position of each line within its set's heap
+    positions: Vec<u32>,
+    ways: u64,
+    max_usage: u64,
+}
+
+impl HeapLeastFrequentlyUsed {
+    /// The fewest ways for which the heap was measured to be faster than scanning the set
+    pub const MIN_WAYS: u64 = 64;
+
+    /// Creates the policy with usage counters of the given width, which saturate rather than wrap
+    ///
+    /// # Panics
+    ///
+    /// If there are more than 2<sup>32</sup> lines
+    pub fn with_counter_bits(num_lines: u64, ways: u64, counter_bits: u64) -> Self {
+        assert!(num_lines <= u32::MAX as u64, "The heap of a cache can't index more than 2^32 lines");
+        Self {
+            usages: vec![0; num_lines as usize],
+            // Lines in order are already a heap, as they all start unused
+            heap: (0..num_lines as u32).collect(),
+            positions: (0..num_lines).map(|line| (line % ways) as u32).collect(),
+            ways,
+            max_usage: u64::MAX >> (u64::BITS as u64 - counter_bits),
+        }
+    }
+
+    /// Moves a line whose uses have increased down its set's heap, until it's evicted before both
+    /// of its children
+    fn sift_down(&mut self, set_lower_bound_index: usize, line: u32) {
+        let ways = self.ways as usize;
+        let heap = &mut self.heap[set_lower_bound_index..set_lower_bound_index + ways];
+        let usages = &self.usages;
+        let evicts_before = |line: u32, other: u32| (usages[line as usize], line) < (usages[other as usize], other);
+        let mut position = self.positions[line as usize] as usize;
+        loop {
+            let left = 2 * position + 1;
+            if left >= ways {
+                break;
+            }
+            let right = left + 1;
+            let first = if right < ways && evicts_before(heap[right], heap[left]) { right } else { left };
+            if !evicts_before(heap[first], line) {
+                break;
+            }
+            heap[position] = heap[first];
+            self.positions[heap[position] as usize] = position as u32;
+            position = first;
+        }
+        heap[position] = line;
+        self.positions[line as usize] = position as u32;
+    }
+}
+
+impl ReplacementPolicy for HeapLeastFrequentlyUsed {
+    fn update_on_read(&mut self, cache_index: u64) {
+        let usage = &mut self.usages[cache_index as usize];
+        if *usage < self.max_usage {
+            *usage += 1;
+            self.sift_down((cache_index - cache_index % self.ways) as usize, cache_index as u32);
+        }
+    }
+
+    fn get_new_line(&mut self, set_lower_bound_index: u64, _set: u64, _cache_lines_per_set: u64) -> u64 {
+        let victim = self.heap[set_lower_bound_index as usize];
+        // The victim had the fewest uses, so stays first if it had more than 1
+        self.usages[victim as usize] = 1;
+        self.sift_down(set_lower_bound_index as usize, victim);
+        victim as u64
+    }
+
+    fn line_metadata(&self, cache_index: u64, _set: u64, _cache_lines_per_set: u64) -> Option<serde_json::Value> {
+        Some(serde_json::json!({"uses": self.usages[cache_index as usize]}))
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of_val(self.usages.as_slice()) + size_of_val(self.heap.as_slice()) + size_of_val(self.positions.as_slice())
+    }
+
+    fn check_set(&self, set_lower_bound_index: u64, _set: u64, valid: &[bool]) -> Result<(), String> {
+        let first = set_lower_bound_index as usize;
+        check_usages(&self.usages[first..][..valid.len()], valid, self.max_usage)?;
+        let heap = &self.heap[first..][..valid.len()];
+        for (position, &line) in heap.iter().enumerate() {
+            if !(first..first + valid.len()).contains(&(line as usize)) || self.positions[line as usize] as usize != position {
+                return Err(format!("position {position} of the heap holds line {line}, whose position is recorded elsewhere"));
+            }
+            let parent = heap[position.saturating_sub(1) / 2];
+            if (self.usages[line as usize], line) < (self.usages[parent as usize], parent) {
+                return Err(format!("line {line} would be evicted before its parent in the heap, line {parent}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the usage counters of the lines of a set, each valid line having been used at least
+/// once, when it was allocated, and no more than the counters hold
+fn check_usages(usages: &[u64], valid: &[bool], max_usage: u64) -> Result<(), String> {
+    match (0..valid.len()).find(|&way| valid[way] && !(1..=max_usage).contains(&usages[way])) {
+        Some(way) => Err(format!("way {way} is valid with {} uses, outside 1 to {max_usage}", usages[way])),
+        None => Ok(()),
+    }
 }
\ No newline at end of file
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use crate::config::LayeredCacheConfig;
+use crate::simulator::{CacheResult, LayeredCacheResult};
+use crate::prelude::*;
+
+/// The version of the output schema produced by this version of the library
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The version of documents which don't declare one, the minimal format of the specification, which
+/// is still produced when no extended statistics are present
+pub const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+/// The output document of a simulation
+///
+/// Without extended statistics this serialises to exactly the format of the specification, with no
+/// `schema_version`. Any extended statistic, or the configuration, raises the document to
+/// [SCHEMA_VERSION], which is then written out so parsers can tell which fields to expect. Extended
+/// fields are omitted when they are empty or zero, so each document only grows by the statistics
+/// which were collected.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SimulationOutput {
+    /// The version of the schema the document follows, set by [new](Self::new) and the `with_`
+    /// methods from the statistics present
+    #[serde(default = "legacy_schema_version", skip_serializing_if = "is_legacy_schema_version")]
+    pub schema_version: u32,
+    pub main_memory_accesses: u64,
+    /// The lines written to main memory when writes are simulated, also counted in
+    /// `main_memory_accesses`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub main_memory_writes: u64,
+    pub caches: Vec<CacheOutput>,
+    /// The accesses which crossed a page boundary, when the configuration has a page size
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub page_crossing_accesses: u64,
+    /// The results of each interval, if the simulation kept them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub intervals: Vec<LayeredCacheResult>,
+    /// The fully-resolved configuration the result was produced with, with defaults filled in, if
+    /// it was added
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<LayeredCacheConfig>,
+}
+
+/// The output for an individual cache
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CacheOutput {
+    pub name: String,
+    pub hits: u64,
+    pub misses: u64,
+    /// Lines brought into the cache by its prefetcher
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub prefetches: u64,
+    /// The hits and misses of every access but writes, including instruction fetches, when writes
+    /// are simulated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reads: Option<AccessCounts>,
+    /// The hits and misses of writes alone, including those from the level above, when writes are
+    /// simulated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub writes: Option<AccessCounts>,
+    /// Dirty lines written to the level below when evicted
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub writebacks: u64,
+    /// Dirty lines evicted while the writeback buffer was full
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub writeback_stalls: u64,
+    /// Misses found in the writeback buffer rather than read from below
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub writeback_buffer_hits: u64,
+    /// The hits within the recovery period after each context switch, also counted in `hits`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub disturbed_hits: u64,
+    /// The misses within the recovery period after each context switch, also counted in `misses`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub disturbed_misses: u64,
+    /// The hits of instruction fetches, also counted in `hits`, when the configuration has
+    /// instruction caches
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fetch_hits: u64,
+    /// The misses of instruction fetches, also counted in `misses`, when the configuration has
+    /// instruction caches
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fetch_misses: u64,
+    /// The hits of the cache's shadow, on the same accesses as the cache
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub shadow_hits: u64,
+    /// The misses of the cache's shadow, on the same accesses as the cache
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub shadow_misses: u64,
+}
+
+/// The hits and misses of one kind of access to a cache
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct AccessCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+fn legacy_schema_version() -> u32 {
+    LEGACY_SCHEMA_VERSION
+}
+
+fn is_legacy_schema_version(version: &u32) -> bool {
+    *version == LEGACY_SCHEMA_VERSION
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+impl SimulationOutput {
+    /// Creates the output document of a result
+    ///
+    /// # Arguments
+    ///
+    /// * `result`: The result of a simulation
+    ///
+    /// returns: SimulationOutput
+    pub fn new(result: &LayeredCacheResult) -> Self {
+        let mut output = Self {
+            schema_version: LEGACY_SCHEMA_VERSION,
+            main_memory_accesses: result.main_memory_accesses,
+            main_memory_writes: result.main_memory_writes,
+            caches: result.caches.iter().map(CacheOutput::from).collect(),
+            page_crossing_accesses: result.page_crossing_accesses,
+            intervals: Vec::new(),
+            config: None,
+        };
+        output.update_schema_version();
+        output
+    }
+
+    /// Adds the results of each interval of the simulation
+    pub fn with_intervals(mut self, intervals: &[LayeredCacheResult]) -> Self {
+        self.intervals = intervals.to_vec();
+        self.update_schema_version();
+        self
+    }
+
+    /// Adds the configuration the result was produced with, so the document records which values
+    /// were used for the settings it left to their defaults
+    pub fn with_config(mut self, config: &LayeredCacheConfig) -> Self {
+        self.config = Some(config.clone());
+        self.update_schema_version();
+        self
+    }
+
+    /// Whether the document has any statistics or metadata beyond those of the specification
+    pub fn is_extended(&self) -> bool {
+        !self.intervals.is_empty() || self.config.is_some() || self.page_crossing_accesses != 0 || self.main_memory_writes != 0 || self.caches.iter().any(|cache| {
+            cache.prefetches != 0 || cache.reads.is_some() || cache.writes.is_some() || cache.writebacks != 0
+                || cache.writeback_stalls != 0 || cache.writeback_buffer_hits != 0 || cache.disturbed_hits != 0 || cache.disturbed_misses != 0
+                || cache.fetch_hits != 0 || cache.fetch_misses != 0 || cache.shadow_hits != 0 || cache.shadow_misses != 0
+        })
+    }
+
+    /// Sets the schema version from the statistics present, after changing the fields directly
+    pub fn update_schema_version(&mut self) {
+        self.schema_version = if self.is_extended() { SCHEMA_VERSION } else { LEGACY_SCHEMA_VERSION };
+    }
+
+    /// Parses an output document, checking this version of the library understands its schema
+    ///
+    /// # Arguments
+    ///
+    /// * `json`: The document
+    ///
+    /// returns: Result<SimulationOutput, String>, an error if the document isn't valid or is from a
+    /// newer version of the schema
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let output: Self = serde_json::from_str(json).map_err(|e| format!("Couldn't parse the output {e}"))?;
+        if output.schema_version > SCHEMA_VERSION {
+            return Err(format!("The output is schema version {}, but this simulator only supports versions up to {SCHEMA_VERSION}", output.schema_version));
+        }
+        Ok(output)
+    }
+
+    /// Gets the counts of the document as a result, leaving out the extended statistics
+    pub fn result(&self) -> LayeredCacheResult {
+        LayeredCacheResult {
+            main_memory_accesses: self.main_memory_accesses,
+            main_memory_writes: self.main_memory_writes,
+            caches: self.caches.iter().map(|cache| CacheResult {
+                name: cache.name.clone(),
+                hits: cache.hits,
+                misses: cache.misses,
+                prefetches: cache.prefetches,
+                writebacks: cache.writebacks,
+                writeback_stalls: cache.writeback_stalls,
+                writeback_buffer_hits: cache.writeback_buffer_hits,
+                disturbed_hits: cache.disturbed_hits,
+                disturbed_misses: cache.disturbed_misses,
+                write_hits: cache.writes.map_or(0, |writes| writes.hits),
+                write_misses: cache.writes.map_or(0, |writes| writes.misses),
+                fetch_hits: cache.fetch_hits,
+                fetch_misses: cache.fetch_misses,
+                shadow_hits: cache.shadow_hits,
+                shadow_misses: cache.shadow_misses,
+            }).collect(),
+            page_crossing_accesses: self.page_crossing_accesses,
+        }
+    }
+}
+
+impl From<&CacheResult> for CacheOutput {
+    fn from(result: &CacheResult) -> Self {
+        // A cache which saw no writes can't tell whether they were simulated, so leaves the split out
+        let writes = result.write_hits + result.write_misses > 0;
+        let counts = |(hits, misses)| AccessCounts { hits, misses };
+        Self {
+            name: result.name.clone(),
+            hits: result.hits,
+            misses: result.misses,
+            prefetches: result.prefetches,
+            reads: writes.then(|| counts(result.reads())),
+            writes: writes.then(|| counts(result.writes())),
+            writebacks: result.writebacks,
+            writeback_stalls: result.writeback_stalls,
+            writeback_buffer_hits: result.writeback_buffer_hits,
+            disturbed_hits: result.disturbed_hits,
+            disturbed_misses: result.disturbed_misses,
+            fetch_hits: result.fetch_hits,
+            fetch_misses: result.fetch_misses,
+            shadow_hits: result.shadow_hits,
+            shadow_misses: result.shadow_misses,
+        }
+    }
+}
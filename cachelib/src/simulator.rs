@@ -1,9 +1,53 @@
-use std::time::{Duration, Instant};
+use alloc::collections::BTreeMap;
+use core::ops::{Add, AddAssign};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io::{ErrorKind, Read};
+#[cfg(feature = "std")]
+use std::time::Instant;
 use serde::{Deserialize, Serialize};
-use crate::cache::{Cache, CacheTrait, GenericCache};
-use crate::config::{CacheConfig, CacheKindConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+use crate::cache::{CacheLine, CacheTrait, GenericCache, HitMissCounters, MemoryUsage};
+use crate::config::LayeredCacheConfig;
 use crate::hex::HEX_LOOKUP;
-use crate::replacement_policies::{LeastFrequentlyUsed, LeastRecentlyUsed, NoPolicy, RoundRobin};
+use crate::layered::{AccessKind, HierarchyEvents, HitLevel, LayeredCache};
+use crate::results::SimulationOutput;
+use crate::topology::Topology;
+use crate::prelude::*;
+use self::builder::{ContextSwitches, Options, SimulatorBuilder};
+use self::decisions::{Decision, DecisionLog, DecisionTracking};
+use self::verification::Verification;
+
+/// Contains the builder for simulators with options such as warming up and sampling
+pub mod builder;
+/// Contains the comparison of the results of two simulations
+pub mod diff;
+/// Contains the log of the replacement decisions of a simulation, for checking another makes the
+/// same ones
+pub mod decisions;
+/// Checks the invariants of the hierarchy as a simulation runs
+mod verification;
+/// Contains a writer of traces, and an observer writing the lines which miss a cache as a trace
+#[cfg(feature = "std")]
+pub mod miss_trace;
+/// Contains the merging of the traces of several processes, each in its own address space
+pub mod processes;
+/// Simulates a trace on several threads by splitting the sets of every cache between them
+#[cfg(feature = "std")]
+pub mod sharded;
+/// Simulates a trace on several threads by splitting it into chunks, reconciling the state of the
+/// caches where the chunks meet
+#[cfg(feature = "std")]
+pub mod speculative;
+/// Parses addresses with SIMD instructions where every processor of the target has them, which SSE2
+/// on x86_64 and NEON on aarch64 do, so no runtime detection is needed
+#[cfg(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64"))]
+mod simd;
+/// Simulates traces from async streams
+#[cfg(feature = "tokio")]
+mod async_reader;
+
+#[cfg(feature = "tokio")]
+pub use async_reader::ASYNC_YIELD_RECORDS;
 
 const LINE_SIZE: usize = 40;
 const ADDRESS_OFFSET: usize = 17;
@@ -11,174 +55,1712 @@ const ADDRESS_SIZE: usize = 16;
 const ADDRESS_UPPER: usize = ADDRESS_OFFSET + ADDRESS_SIZE;
 const RW_MODE: usize = ADDRESS_UPPER + 1;
 const SIZE: usize = RW_MODE + 2;
+// The number of records simulated between calls to a progress callback
+const PROGRESS_INTERVAL: usize = 64 * 1024;
+// The number of records read from a stream before simulating them
+#[cfg(feature = "std")]
+const READER_BUFFER_RECORDS: usize = 64 * 1024;
+// The number of records split into lines before the hierarchy reads them, when reading in batches
+const BATCH_RECORDS: usize = 4 * 1024;
+
+/// The simulator handles line alignment when using the caches, and collects results.
+///
+/// It supports calling simulate multiple times, and will update the time taken to simulate and the
+/// results accordingly
+///
+/// Cloning a simulator copies the contents of its caches and the state of their policies and
+/// prefetchers, so simulations can be forked from a warmed up state, see [snapshot](Self::snapshot)
+#[derive(Clone)]
+pub struct Simulator {
+    hierarchy: LayeredCache,
+    // Per-set hits and misses for each cache, only collected when enabled
+    set_statistics: Option<Vec<SetStatistics>>,
+    // Per-instruction hits and misses for each cache, only collected when enabled
+    pc_statistics: Option<Vec<PcStatistics>>,
+    // How much of each line each cache used before evicting it, only collected when enabled
+    utilisation: Option<Vec<LineUtilisation>>,
+    // Per-way hits and fills for each cache, only collected when enabled
+    way_statistics: Option<Vec<WayStatistics>>,
+    // The level which served each access, only collected when enabled
+    latencies: Option<LatencyHistogram>,
+    // The path instruction fetches take in place of each first level data cache, None where they
+    // take the data path. Empty when the configuration has no instruction caches, as fetches are
+    // then reads, counted with the others
+    fetch_paths: Vec<Option<Vec<usize>>>,
+    // The bits of the offset within a page, when accesses are split at page boundaries
+    page_bits: Option<u32>,
+    // Called with each access to a cache, empty while warming up
+    observers: Vec<Box<dyn DynamicObserver>>,
+    // Whether the replacement decisions are recorded or checked against a log
+    decisions: Option<DecisionTracking>,
+    // Checks the invariants of the hierarchy between accesses, only when enabled
+    verification: Option<Verification>,
+    // The shadow of each cache, None for caches without one
+    shadows: Vec<Option<GenericCache>>,
+    options: Options,
+    result: LayeredCacheResult,
+    simulation_time: Duration,
+    // The line geometry of each cache, read from the caches once rather than on every access
+    geometry: Vec<LineGeometry>,
+}
+
+/// The line size of a cache, its log2 and the mask aligning addresses to it, kept by the simulator
+/// for each cache so each access splits into lines without branching on the type of the first
+/// cache
+#[derive(Debug, Copy, Clone)]
+struct LineGeometry {
+    line_size: u64,
+    line_shift: u32,
+    alignment_bit_mask: u64,
+}
+
+impl LineGeometry {
+    fn of(cache: &GenericCache) -> Self {
+        let line_size = cache.get_line_size();
+        Self { line_size, line_shift: line_size.trailing_zeros(), alignment_bit_mask: cache.get_alignment_bit_mask() }
+    }
+
+    /// Gets the first line an access reads and the number of lines it reads, those starting before
+    /// its end, which saturates at the top of the address space
+    #[inline(always)]
+    fn lines(&self, address: u64, size: u16) -> (u64, u64) {
+        let (line, end) = (address & self.alignment_bit_mask, address.saturating_add(size as u64));
+        let count = if end > line { ((end - 1) >> self.line_shift) - (line >> self.line_shift) + 1 } else { 0 };
+        (line, count)
+    }
+
+    /// Splits records in the trace format into the lines they read, appending them in order
+    ///
+    /// Batches don't track dirty lines, so a write is a read, and a modify reads each line twice.
+    #[inline(always)]
+    fn split_records(&self, records: &[u8], lines: &mut Vec<u64>) {
+        for buffer in records.chunks_exact(LINE_SIZE) {
+            let address = parse_address((&buffer[ADDRESS_OFFSET..ADDRESS_UPPER]).try_into().unwrap());
+            let size = parse_size((&buffer[SIZE..LINE_SIZE - 1]).try_into().unwrap());
+            let modify = buffer[RW_MODE] == b'M';
+            let (mut line, count) = self.lines(address, size);
+            for _ in 0..count {
+                lines.push(line);
+                if modify {
+                    lines.push(line);
+                }
+                line = line.wrapping_add(self.line_size);
+            }
+        }
+    }
+}
+
+// Snapshots are moved to other threads to run forked simulations, so this fails to build if a
+// cache, policy or prefetcher stops being Send or Sync
+const _: fn() = || {
+    fn assert_send_sync<T: Clone + Send + Sync>() {}
+    assert_send_sync::<Simulator>();
+};
+
+/// An access to one cache, as seen by an [AccessObserver]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CacheAccess {
+    /// The index of the cache, in the order of the configuration
+    pub cache: usize,
+    /// The address of the line, aligned to the cache's line size
+    pub line_address: u64,
+    pub hit: bool,
+}
+
+/// Watches the accesses to the caches of a simulator, for statistics it doesn't collect itself
+///
+/// Closures taking a [CacheAccess] are observers.
+pub trait AccessObserver {
+    /// Called after each access to a cache, in the order they are made
+    fn observe(&mut self, access: CacheAccess);
+}
+
+impl<F: FnMut(CacheAccess)> AccessObserver for F {
+    fn observe(&mut self, access: CacheAccess) {
+        self(access)
+    }
+}
+
+/// An observer which can be held by a simulator, which every observer which is `Clone`, `Send` and
+/// `Sync` is, see [DynamicPolicy](crate::replacement_policies::DynamicPolicy)
+pub trait DynamicObserver: AccessObserver + Send + Sync {
+    /// Copies the observer and its state into a new box
+    fn clone_box(&self) -> Box<dyn DynamicObserver>;
+}
+
+impl<O: AccessObserver + Clone + Send + Sync + 'static> DynamicObserver for O {
+    fn clone_box(&self) -> Box<dyn DynamicObserver> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn DynamicObserver> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+/// Collects the statistics of a simulator from the events of its hierarchy, borrowing them for the
+/// length of a read
+struct Counters<'a> {
+    caches: &'a mut [CacheResult],
+    memory_writes: &'a mut u64,
+    set_statistics: Option<&'a mut [SetStatistics]>,
+    pc_statistics: Option<&'a mut [PcStatistics]>,
+    utilisation: Option<&'a mut [LineUtilisation]>,
+    way_statistics: Option<&'a mut [WayStatistics]>,
+    // The program counter of the instruction making the read
+    pc: u64,
+    // The bytes of the line being accessed which the read covers, from the first to past the last
+    touched: (u64, u64),
+    // Whether the read is an instruction fetch counted apart from data accesses
+    fetching: bool,
+    observers: &'a mut [Box<dyn DynamicObserver>],
+    decisions: Option<&'a mut DecisionTracking>,
+    shadows: &'a mut [Option<GenericCache>],
+}
+
+impl HierarchyEvents for Counters<'_> {
+    #[inline(always)]
+    fn access(&mut self, cache: usize, model: &GenericCache, line_address: u64, hit: bool) {
+        let result = &mut self.caches[cache];
+        if hit {
+            result.hits += 1;
+        } else {
+            result.misses += 1;
+        }
+        if self.fetching {
+            if hit {
+                result.fetch_hits += 1;
+            } else {
+                result.fetch_misses += 1;
+            }
+        }
+        if let Some(statistics) = &mut self.set_statistics {
+            statistics[cache].record(model.address_to_set_and_tag(line_address).0, hit);
+        }
+        if let Some(statistics) = &mut self.pc_statistics {
+            statistics[cache].record(self.pc, hit);
+        }
+        if let (Some(statistics), true) = (&mut self.way_statistics, hit) {
+            // An exclusive cache gives up a line which hits after this, so it is still in its way
+            if let Some(way) = model.way_of(line_address) {
+                statistics[cache].hits[way as usize] += 1;
+            }
+        }
+        for observer in self.observers.iter_mut() {
+            observer.observe(CacheAccess { cache, line_address: line_address & model.get_alignment_bit_mask(), hit });
+        }
+        if let Some(shadow) = &mut self.shadows[cache] {
+            if shadow.read_and_update_line(line_address) {
+                result.shadow_hits += 1;
+            } else {
+                result.shadow_misses += 1;
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn prefetch(&mut self, cache: usize) {
+        self.caches[cache].prefetches += 1;
+    }
+
+    fn write(&mut self, cache: usize, hit: bool) {
+        if hit {
+            self.caches[cache].write_hits += 1;
+        } else {
+            self.caches[cache].write_misses += 1;
+        }
+    }
+
+    fn tracks_evictions(&self) -> bool {
+        self.decisions.is_some() || self.utilisation.is_some() || self.way_statistics.is_some()
+    }
+
+    fn replacement(&mut self, cache: usize, model: &GenericCache, line_address: u64, way: u64) {
+        if let Some(statistics) = &mut self.way_statistics {
+            statistics[cache].fills[way as usize] += 1;
+        }
+        if let Some(decisions) = &mut self.decisions {
+            let set = model.address_to_set_and_tag(line_address).0;
+            decisions.decide(Decision { cache, set, way }, self.caches);
+        }
+    }
+
+    fn resident(&mut self, cache: usize, model: &GenericCache, line_address: u64) {
+        if let Some(utilisation) = &mut self.utilisation {
+            utilisation[cache].touch(line_address & model.get_alignment_bit_mask(), self.touched);
+        }
+    }
+
+    fn eviction(&mut self, cache: usize, _model: &GenericCache, line_address: u64) {
+        if let Some(utilisation) = &mut self.utilisation {
+            utilisation[cache].evict(line_address);
+        }
+    }
+
+    fn writeback(&mut self, cache: usize) {
+        self.caches[cache].writebacks += 1;
+    }
+
+    fn writeback_stall(&mut self, cache: usize) {
+        self.caches[cache].writeback_stalls += 1;
+    }
+
+    fn writeback_buffer_hit(&mut self, cache: usize) {
+        self.caches[cache].writeback_buffer_hits += 1;
+    }
+
+    fn memory_write(&mut self) {
+        *self.memory_writes += 1;
+    }
+}
+
+/// The contents of a cache, for inspecting what it holds, such as when debugging a policy
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheContents {
+    pub name: String,
+    pub line_size: u64,
+    pub sets: u64,
+    pub ways: u64,
+    /// Every line, ordered by set and then way
+    pub lines: Vec<CacheLine>,
+}
+
+/// The hits and misses of each set of a cache, for finding conflicts between sets
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SetStatistics {
+    hits: Vec<u64>,
+    misses: Vec<u64>,
+}
+
+impl SetStatistics {
+    fn new(sets: u64) -> Self {
+        Self {
+            hits: vec![0; sets as usize],
+            misses: vec![0; sets as usize],
+        }
+    }
+
+    fn record(&mut self, set: u64, hit: bool) {
+        if hit {
+            self.hits[set as usize] += 1;
+        } else {
+            self.misses[set as usize] += 1;
+        }
+    }
+
+    /// Gets the hits of each set, indexed by set
+    pub fn hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// Gets the misses of each set, indexed by set
+    pub fn misses(&self) -> &[u64] {
+        &self.misses
+    }
+}
+
+/// The hits and fills of each way of a cache, summed over its sets, for finding ways which are used
+/// more than others
+///
+/// Over a long trace, each way of a set should see a similar share of the hits and fills. Ways used
+/// unevenly, such as by a policy which keeps choosing the same way, point to a problem with the
+/// indexing or the policy. A fill is any line placed in a way, whether read, prefetched or a victim
+/// from the cache above.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct WayStatistics {
+    hits: Vec<u64>,
+    fills: Vec<u64>,
+}
+
+impl WayStatistics {
+    fn new(ways: u64) -> Self {
+        Self {
+            hits: vec![0; ways as usize],
+            fills: vec![0; ways as usize],
+        }
+    }
+
+    /// Gets the hits in each way, indexed by way
+    pub fn hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// Gets the lines placed in each way, indexed by way
+    pub fn fills(&self) -> &[u64] {
+        &self.fills
+    }
+
+    /// Gets how unevenly the ways were used, as the hits and fills of the busiest way over the
+    /// mean of every way, from 1 when every way was used equally up to the number of ways when
+    /// only one was
+    ///
+    /// returns: Option<f64>, None if no way has been used
+    pub fn skew(&self) -> Option<f64> {
+        let uses: Vec<u64> = self.hits.iter().zip(&self.fills).map(|(hits, fills)| hits + fills).collect();
+        let total: u64 = uses.iter().sum();
+        (total > 0).then(|| *uses.iter().max().unwrap() as f64 * uses.len() as f64 / total as f64)
+    }
+}
+
+/// The hits and misses of the accesses to a cache made by each instruction, by its program counter,
+/// for finding the loads and stores behind the misses
+///
+/// Accesses without a program counter, from traces which don't record them, are counted under 0.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct PcStatistics {
+    counts: BTreeMap<u64, (u64, u64)>,
+}
+
+impl PcStatistics {
+    fn record(&mut self, pc: u64, hit: bool) {
+        let (hits, misses) = self.counts.entry(pc).or_default();
+        if hit {
+            *hits += 1;
+        } else {
+            *misses += 1;
+        }
+    }
+
+    /// Gets the hits and misses of each program counter which accessed the cache, in order of
+    /// program counter
+    pub fn counts(&self) -> &BTreeMap<u64, (u64, u64)> {
+        &self.counts
+    }
+}
+
+/// How much of each line a cache held was used, for judging whether its line size suits a workload
+///
+/// Each line counts the bytes read from it while it was in the cache, by the accesses which reached
+/// the cache, from when it was allocated until it was evicted or invalidated. Lines brought in by a
+/// prefetcher and evicted unused count as using none of their bytes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LineUtilisation {
+    line_size: u64,
+    // The bytes used of each line in the cache, a bit per byte, by the line's address
+    resident: BTreeMap<u64, Vec<u64>>,
+    evicted_lines: u64,
+    evicted_bytes_used: u64,
+}
+
+impl LineUtilisation {
+    fn new(line_size: u64) -> Self {
+        Self { line_size, resident: BTreeMap::new(), evicted_lines: 0, evicted_bytes_used: 0 }
+    }
+
+    /// Marks the bytes from `touched.0` up to `touched.1` of a line as used
+    fn touch(&mut self, line_address: u64, touched: (u64, u64)) {
+        let words = self.line_size.div_ceil(u64::BITS as u64) as usize;
+        let bits = self.resident.entry(line_address).or_insert_with(|| vec![0; words]);
+        let line_end = line_address + self.line_size;
+        for byte in touched.0.max(line_address)..touched.1.min(line_end) {
+            let offset = byte - line_address;
+            bits[(offset / u64::BITS as u64) as usize] |= 1 << (offset % u64::BITS as u64);
+        }
+    }
+
+    /// Counts the bytes used of a line which has left the cache
+    fn evict(&mut self, line_address: u64) {
+        let used = self.resident.remove(&line_address).map_or(0, |bits| bits.iter().map(|word| word.count_ones() as u64).sum());
+        self.evicted_lines += 1;
+        self.evicted_bytes_used += used;
+    }
+
+    /// Forgets the lines evicted so far, keeping the bytes used of the lines in the cache
+    fn reset(&mut self) {
+        self.evicted_lines = 0;
+        self.evicted_bytes_used = 0;
+    }
+
+    /// Gets the line size of the cache
+    pub fn line_size(&self) -> u64 {
+        self.line_size
+    }
+
+    /// Gets the number of lines which have left the cache
+    pub fn evicted_lines(&self) -> u64 {
+        self.evicted_lines
+    }
+
+    /// Gets the bytes used of the lines which have left the cache, in total
+    pub fn evicted_bytes_used(&self) -> u64 {
+        self.evicted_bytes_used
+    }
+
+    /// Gets the fraction of the bytes of each line used, on average, over the lines which have left
+    /// the cache and those still in it, which count the bytes used so far as though the end of the
+    /// trace evicted them
+    ///
+    /// returns: Option<f64>, None if the cache has held no lines
+    pub fn average(&self) -> Option<f64> {
+        let lines = self.evicted_lines + self.resident.len() as u64;
+        let resident_bytes_used: u64 = self.resident.values().flatten().map(|word| word.count_ones() as u64).sum();
+        (lines > 0).then(|| (self.evicted_bytes_used + resident_bytes_used) as f64 / (lines * self.line_size) as f64)
+    }
+}
+
+/// How many accesses each level of the hierarchy served, for the distribution of their latencies
+/// rather than only their average
+///
+/// An access is served by the level furthest from the core which any of its lines reached, as the
+/// access waits for its slowest line.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LatencyHistogram {
+    // The path from each first level cache, empty for the other caches
+    paths: Vec<Vec<usize>>,
+    // The accesses served at each level of the path from each first level cache, followed by main
+    // memory
+    served: Vec<Vec<u64>>,
+}
+
+/// The accesses served with one latency, from one level of the hierarchy
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct LatencyBucket {
+    /// The cycles each access took
+    pub latency: u64,
+    /// The cache which served the accesses, or main memory
+    pub level: String,
+    pub accesses: u64,
+    /// The fraction of all accesses served with this latency from this level
+    pub fraction: f64,
+    /// The fraction of all accesses served with this latency or less
+    pub cumulative_fraction: f64,
+}
+
+impl LatencyHistogram {
+    fn new(topology: &Topology, caches: usize) -> Self {
+        let mut paths = vec![Vec::new(); caches];
+        for core in topology.cores() {
+            for path in [topology.path(core), topology.instruction_path(core)].into_iter().flatten() {
+                paths[path[0]] = path.to_vec();
+            }
+        }
+        Self { served: paths.iter().map(|path| if path.is_empty() { Vec::new() } else { vec![0; path.len() + 1] }).collect(), paths }
+    }
+
+    fn reset(&mut self) {
+        self.served.iter_mut().flatten().for_each(|served| *served = 0);
+    }
+
+    /// Gets the accesses served at each level of the path from a first level cache, followed by main
+    /// memory, or an empty slice if the cache isn't a first level
+    pub fn served(&self, first_level: usize) -> &[u64] {
+        &self.served[first_level]
+    }
+
+    /// Gets the accesses served with each latency, using the latencies of a configuration in the same
+    /// way as [average_memory_access_time](LayeredCacheResult::average_memory_access_time)
+    ///
+    /// An access served by a cache takes the hit latencies of each level down to it. An access which
+    /// misses a level with a `miss_penalty` takes that penalty after the level's hit latency,
+    /// whichever level below served it, and one served by main memory takes the memory latency
+    /// after the hit latencies of every level.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration the histogram was collected with
+    ///
+    /// returns: Result<Vec<LatencyBucket>, String>, the buckets with any accesses in increasing order
+    /// of latency, or an error if a latency of any level isn't configured
+    pub fn buckets(&self, config: &LayeredCacheConfig) -> Result<Vec<LatencyBucket>, String> {
+        let mut buckets: BTreeMap<(u64, String), u64> = BTreeMap::new();
+        for (path, served) in self.paths.iter().zip(&self.served) {
+            for (depth, &accesses) in served.iter().enumerate() {
+                // Every latency is checked, so an empty histogram checks the configuration has them
+                let latency = Self::latency(config, path, depth)?;
+                if accesses > 0 {
+                    let level = path.get(depth).map_or("main memory".to_string(), |&cache| config.caches[cache].name.clone());
+                    *buckets.entry((latency, level)).or_default() += accesses;
+                }
+            }
+        }
+        let total: u64 = buckets.values().sum();
+        let mut cumulative = 0;
+        Ok(buckets.into_iter()
+            .map(|((latency, level), accesses)| {
+                cumulative += accesses;
+                LatencyBucket { latency, level, accesses, fraction: accesses as f64 / total as f64, cumulative_fraction: cumulative as f64 / total as f64 }
+            })
+            .collect())
+    }
+
+    /// Gets the latency of an access served at a depth of a path, the length of the path for main
+    /// memory
+    fn latency(config: &LayeredCacheConfig, path: &[usize], depth: usize) -> Result<u64, String> {
+        let mut latency = 0;
+        for (level, &cache) in path.iter().enumerate() {
+            let cache = &config.caches[cache];
+            latency += cache.hit_latency.ok_or(format!("Cache {} has no hit_latency, which the latency histogram needs", cache.name))?;
+            if level == depth {
+                return Ok(latency);
+            }
+            if let Some(penalty) = cache.miss_penalty {
+                return Ok(latency + penalty);
+            }
+        }
+        Ok(latency + config.memory_latency.ok_or("The configuration has no memory_latency, which the latency histogram needs")?)
+    }
+}
+
+/// A single access, as read from one record of a trace
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Access {
+    /// The address of the instruction making the access, or 0 if it isn't known
+    pub pc: u64,
+    pub address: u64,
+    /// The number of bytes accessed
+    pub size: u16,
+    pub kind: AccessKind,
+}
+
+impl Access {
+    /// Parses a record of a trace in the format of the specification
+    ///
+    /// # Arguments
+    ///
+    /// * `record`: The record, including its newline
+    ///
+    /// returns: Access
+    pub fn from_record(record: &[u8; LINE_SIZE]) -> Self {
+        Access {
+            pc: parse_address((&record[..ADDRESS_SIZE]).try_into().unwrap()),
+            address: parse_address((&record[ADDRESS_OFFSET..ADDRESS_UPPER]).try_into().unwrap()),
+            size: parse_size((&record[SIZE..LINE_SIZE - 1]).try_into().unwrap()),
+            kind: parse_kind(record[RW_MODE]),
+        }
+    }
+}
+
+/// The result of a cache simulation. Can be serialised to the required output format
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct LayeredCacheResult {
+    pub(crate) main_memory_accesses: u64,
+    /// The lines written to main memory, when writes are simulated, which are also counted in
+    /// `main_memory_accesses`. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) main_memory_writes: u64,
+    pub(crate) caches: Vec<CacheResult>,
+    /// The accesses which crossed a page boundary, when the configuration has a page size.
+    /// Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) page_crossing_accesses: u64,
+}
+
+/// The result for an individual cache. Can be serialised to the required output format
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CacheResult {
+    pub(crate) name: String,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    /// Lines brought into the cache by its prefetcher. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) prefetches: u64,
+    /// Dirty lines written to the level below, from the cache's writeback buffer if it has one.
+    /// Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) writebacks: u64,
+    /// Dirty lines evicted while the writeback buffer was full. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) writeback_stalls: u64,
+    /// Misses found in the writeback buffer. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) writeback_buffer_hits: u64,
+    /// The hits within the recovery period after each context switch, which are also counted in
+    /// `hits`. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) disturbed_hits: u64,
+    /// The misses within the recovery period after each context switch, which are also counted in
+    /// `misses`. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) disturbed_misses: u64,
+    /// The hits of writes, which are also counted in `hits`, when writes are simulated. Omitted
+    /// from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) write_hits: u64,
+    /// The misses of writes, which are also counted in `misses`, when writes are simulated.
+    /// Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) write_misses: u64,
+    /// The hits of instruction fetches, which are also counted in `hits`, when the configuration
+    /// has instruction caches. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) fetch_hits: u64,
+    /// The misses of instruction fetches, which are also counted in `misses`, when the
+    /// configuration has instruction caches. Omitted from the output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) fetch_misses: u64,
+    /// The hits of the cache's shadow, on the same accesses as the cache. Omitted from the output
+    /// when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) shadow_hits: u64,
+    /// The misses of the cache's shadow, on the same accesses as the cache. Omitted from the
+    /// output when zero
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) shadow_misses: u64,
+}
+
+impl CacheResult {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Gets the number of lines brought into the cache by its prefetcher
+    pub fn prefetches(&self) -> u64 {
+        self.prefetches
+    }
+
+    /// Gets the number of dirty lines written to the level below, from the cache's writeback buffer
+    /// if it has one
+    pub fn writebacks(&self) -> u64 {
+        self.writebacks
+    }
+
+    /// Gets the number of dirty lines evicted from the cache while its writeback buffer was full
+    pub fn writeback_stalls(&self) -> u64 {
+        self.writeback_stalls
+    }
+
+    /// Gets the number of misses in the cache which were found in its writeback buffer
+    pub fn writeback_buffer_hits(&self) -> u64 {
+        self.writeback_buffer_hits
+    }
+
+    /// Gets the hits and misses within the recovery period after each context switch
+    pub fn disturbed(&self) -> (u64, u64) {
+        (self.disturbed_hits, self.disturbed_misses)
+    }
+
+    /// Gets the hits and misses outside the recovery period after each context switch, the steady
+    /// state of the cache
+    pub fn steady(&self) -> (u64, u64) {
+        (self.hits - self.disturbed_hits, self.misses - self.disturbed_misses)
+    }
+
+    /// Gets the hits and misses of writes, including those from the level above, which are only
+    /// counted when writes are simulated, see
+    /// [simulates_writes](LayeredCacheConfig::simulates_writes)
+    pub fn writes(&self) -> (u64, u64) {
+        (self.write_hits, self.write_misses)
+    }
+
+    /// Gets the hits and misses of every access but writes, which is all of them unless writes are
+    /// simulated
+    pub fn reads(&self) -> (u64, u64) {
+        (self.hits - self.write_hits, self.misses - self.write_misses)
+    }
+
+    /// Gets the hits and misses of instruction fetches, the I-side of the cache, which are only
+    /// counted when the configuration has instruction caches
+    pub fn fetches(&self) -> (u64, u64) {
+        (self.fetch_hits, self.fetch_misses)
+    }
+
+    /// Gets the hits and misses of reads and writes, the D-side of the cache
+    pub fn data(&self) -> (u64, u64) {
+        (self.hits - self.fetch_hits, self.misses - self.fetch_misses)
+    }
+
+    /// Gets the hits and misses of the cache's shadow, which saw the same accesses as the cache
+    ///
+    /// returns: Option<(u64, u64)>, None if the cache has no shadow, or hasn't been accessed
+    pub fn shadow(&self) -> Option<(u64, u64)> {
+        (self.shadow_hits + self.shadow_misses > 0).then_some((self.shadow_hits, self.shadow_misses))
+    }
+
+    /// Gets how many more misses the cache's shadow had than the cache, negative if the shadow
+    /// missed less, or None if there's no [shadow](Self::shadow) to compare with
+    pub fn shadow_miss_delta(&self) -> Option<i64> {
+        self.shadow().map(|(_, misses)| misses as i64 - self.misses as i64)
+    }
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+impl LayeredCacheResult {
+    /// Gets the number of accesses which missed every cache and went to main memory, including
+    /// the [writes](Self::main_memory_writes)
+    pub fn main_memory_accesses(&self) -> u64 {
+        self.main_memory_accesses
+    }
+
+    /// Gets the number of lines written to main memory, which is only counted when writes are
+    /// simulated, see [simulates_writes](LayeredCacheConfig::simulates_writes)
+    pub fn main_memory_writes(&self) -> u64 {
+        self.main_memory_writes
+    }
+
+    /// Gets the results for each cache, in the order of the configuration
+    pub fn caches(&self) -> &[CacheResult] {
+        &self.caches
+    }
+
+    /// Gets the number of accesses which crossed a page boundary, each needing a translation of
+    /// both pages, which are only counted when the configuration has a `page_size`
+    pub fn page_crossing_accesses(&self) -> u64 {
+        self.page_crossing_accesses
+    }
+
+    /// Adds the counts of another result to this one, such as those of another shard of the trace,
+    /// or of another trace simulated with the same configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `other`: A result with the same caches, in the same order
+    ///
+    /// returns: Result<(), String>, an error if the caches don't match, in which case this result
+    /// is unchanged
+    pub fn merge(&mut self, other: &LayeredCacheResult) -> Result<(), String> {
+        if self.caches.len() != other.caches.len() {
+            return Err(format!("Results with {} and {} caches can't be merged", self.caches.len(), other.caches.len()));
+        }
+        if let Some((index, (cache, other))) = self.caches.iter().zip(&other.caches).enumerate().find(|(_, (cache, other))| cache.name != other.name) {
+            return Err(format!("Results can't be merged, as cache {index} is {} in one and {} in the other", cache.name, other.name));
+        }
+        self.main_memory_accesses += other.main_memory_accesses;
+        self.main_memory_writes += other.main_memory_writes;
+        self.page_crossing_accesses += other.page_crossing_accesses;
+        for (cache, other) in self.caches.iter_mut().zip(&other.caches) {
+            cache.hits += other.hits;
+            cache.misses += other.misses;
+            cache.prefetches += other.prefetches;
+            cache.writebacks += other.writebacks;
+            cache.writeback_stalls += other.writeback_stalls;
+            cache.writeback_buffer_hits += other.writeback_buffer_hits;
+            cache.disturbed_hits += other.disturbed_hits;
+            cache.disturbed_misses += other.disturbed_misses;
+            cache.write_hits += other.write_hits;
+            cache.write_misses += other.write_misses;
+            cache.fetch_hits += other.fetch_hits;
+            cache.fetch_misses += other.fetch_misses;
+            cache.shadow_hits += other.shadow_hits;
+            cache.shadow_misses += other.shadow_misses;
+        }
+        Ok(())
+    }
+
+    /// Gets the counts accumulated since an earlier result of the same simulation
+    fn counts_since(&self, start: &LayeredCacheResult) -> LayeredCacheResult {
+        LayeredCacheResult {
+            main_memory_accesses: self.main_memory_accesses - start.main_memory_accesses,
+            main_memory_writes: self.main_memory_writes - start.main_memory_writes,
+            caches: self.caches.iter().zip(&start.caches).map(|(cache, start)| CacheResult {
+                name: cache.name.clone(),
+                hits: cache.hits - start.hits,
+                misses: cache.misses - start.misses,
+                prefetches: cache.prefetches - start.prefetches,
+                writebacks: cache.writebacks - start.writebacks,
+                writeback_stalls: cache.writeback_stalls - start.writeback_stalls,
+                writeback_buffer_hits: cache.writeback_buffer_hits - start.writeback_buffer_hits,
+                disturbed_hits: cache.disturbed_hits - start.disturbed_hits,
+                disturbed_misses: cache.disturbed_misses - start.disturbed_misses,
+                write_hits: cache.write_hits - start.write_hits,
+                write_misses: cache.write_misses - start.write_misses,
+                fetch_hits: cache.fetch_hits - start.fetch_hits,
+                fetch_misses: cache.fetch_misses - start.fetch_misses,
+                shadow_hits: cache.shadow_hits - start.shadow_hits,
+                shadow_misses: cache.shadow_misses - start.shadow_misses,
+            }).collect(),
+            page_crossing_accesses: self.page_crossing_accesses - start.page_crossing_accesses,
+        }
+    }
+
+    /// Calculates the average memory access time in cycles, using the latencies in the
+    /// configuration the result was produced with
+    ///
+    /// Each level costs its hit latency, plus its miss rate multiplied by its miss penalty. Where a
+    /// level has no explicit miss penalty, the penalty is the average access time of the next level,
+    /// or the memory latency for the last level.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration used to produce this result
+    ///
+    /// returns: Option<f64>, None if a required latency isn't configured
+    pub fn average_memory_access_time(&self, config: &LayeredCacheConfig) -> Option<f64> {
+        let mut penalty = config.memory_latency.map(|latency| latency as f64);
+        for (cache, result) in config.caches.iter().zip(&self.caches).rev() {
+            let accesses = result.hits + result.misses;
+            let miss_rate = if accesses == 0 { 0.0 } else { result.misses as f64 / accesses as f64 };
+            let miss_penalty = cache.miss_penalty.map(|p| p as f64).or(penalty);
+            penalty = match (cache.hit_latency, miss_penalty) {
+                (Some(hit_latency), Some(miss_penalty)) => Some(hit_latency as f64 + miss_rate * miss_penalty),
+                _ => None,
+            };
+        }
+        penalty
+    }
+}
+
+/// Sums results in the same way as [merge](LayeredCacheResult::merge)
+///
+/// # Panics
+///
+/// If the results don't have the same caches, in the same order
+impl AddAssign<&LayeredCacheResult> for LayeredCacheResult {
+    fn add_assign(&mut self, other: &LayeredCacheResult) {
+        self.merge(other).unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+impl AddAssign for LayeredCacheResult {
+    fn add_assign(&mut self, other: LayeredCacheResult) {
+        *self += &other;
+    }
+}
+
+impl Add<&LayeredCacheResult> for LayeredCacheResult {
+    type Output = LayeredCacheResult;
+
+    fn add(mut self, other: &LayeredCacheResult) -> LayeredCacheResult {
+        self += other;
+        self
+    }
+}
+
+impl Add for LayeredCacheResult {
+    type Output = LayeredCacheResult;
+
+    fn add(self, other: LayeredCacheResult) -> LayeredCacheResult {
+        self + &other
+    }
+}
+
+impl Simulator {
+
+    /// Creates a new simulator for a given configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: A cache configuration, usually resulting from parsing JSON
+    ///
+    /// returns: Simulator
+    ///
+    /// # Panics
+    ///
+    /// If the configuration's topology is invalid, which is checked by
+    /// [validate](LayeredCacheConfig::validate), or if a cache uses a replacement policy which isn't
+    /// registered, which is also checked by validate
+    pub fn new(config: &LayeredCacheConfig) -> Self {
+        let result = LayeredCacheResult {
+            main_memory_accesses: 0,
+            main_memory_writes: 0,
+            caches: config.caches.iter().map(|cache| CacheResult {
+                name: cache.name.clone(),
+                ..CacheResult::default()
+            }).collect(),
+            page_crossing_accesses: 0,
+        };
+        let hierarchy = LayeredCache::new(config);
+        let topology = hierarchy.topology();
+        let mut fetch_paths = Vec::new();
+        if topology.has_instruction_caches() {
+            fetch_paths = vec![None; config.caches.len()];
+            for core in topology.cores() {
+                fetch_paths[topology.path(core).unwrap()[0]] = topology.instruction_path(core).map(<[usize]>::to_vec);
+            }
+        }
+        Self {
+            geometry: hierarchy.caches().iter().map(LineGeometry::of).collect(),
+            fetch_paths,
+            page_bits: config.page_size.map(u64::trailing_zeros),
+            hierarchy,
+            set_statistics: None,
+            pc_statistics: None,
+            utilisation: None,
+            way_statistics: None,
+            latencies: None,
+            observers: Vec::new(),
+            decisions: None,
+            verification: None,
+            shadows: config.caches.iter()
+                .map(|cache| cache.shadow.as_ref().map(|shadow| LayeredCache::config_to_cache(&shadow.cache_config(cache))))
+                .collect(),
+            options: Options {
+                context_switches: config.context_switches.as_ref().map(|switches| ContextSwitches {
+                    interval: switches.interval,
+                    recovery: switches.recovery(),
+                    caches: switches.cache_indices(&config.caches).expect("The configuration should be validated before creating a simulator"),
+                }),
+                ..Options::default()
+            },
+            result,
+            simulation_time: Duration::new(0, 0),
+        }
+    }
+
+    /// Starts configuring a simulator with options [new](Self::new) doesn't have
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: A validated cache configuration
+    ///
+    /// returns: SimulatorBuilder
+    pub fn builder(config: &LayeredCacheConfig) -> SimulatorBuilder<'_> {
+        SimulatorBuilder::new(config)
+    }
+
+
+    /// Reads or writes a value in memory, at a given address with a given size
+    ///
+    /// The simulator will handle splitting the read so caches can be checked for each relevant line,
+    /// and at page boundaries when the configuration has a page size
+    ///
+    /// # Arguments
+    ///
+    /// * `address`: The address of the read
+    /// * `size`: The size of the read in bytes
+    /// * `pc`: The program counter of the instruction making the read, or 0 if no prefetcher needs it
+    /// * `kind`: Whether the access is a read, a write or an instruction fetch
+    /// * `path`: The caches the read passes through, from the [Topology], which instruction fetches
+    ///   leave for the core's instruction path
+    ///
+    /// returns: (), internally the result is updated
+    #[inline(always)]
+    fn read(&mut self, address: u64, size: u16, pc: u64, kind: AccessKind, path: &[usize]) {
+        // Accesses running past the end of the address space stop at it
+        let end = address.saturating_add(size as u64);
+        let served = match self.page_bits {
+            Some(page_bits) if size != 0 && address >> page_bits != (end - 1) >> page_bits => {
+                // The part in each page is an access of its own, as it is translated on its own,
+                // but the access as a whole waits for the slowest of them
+                self.result.page_crossing_accesses += 1;
+                let mut start = address;
+                let mut served = (path[0], 0);
+                while start < end {
+                    let part_end = end.min((start | ((1 << page_bits) - 1)).saturating_add(1));
+                    let (first_level, depth) = self.read_lines(start, (part_end - start) as u16, pc, kind, path);
+                    served = (first_level, served.1.max(depth));
+                    start = part_end;
+                }
+                served
+            }
+            _ => self.read_lines(address, size, pc, kind, path),
+        };
+        if let Some(latencies) = &mut self.latencies {
+            latencies.served[served.0][served.1] += 1;
+        }
+    }
+
+    /// Reads the lines of an access within a page, see [read](Self::read)
+    ///
+    /// returns: (usize, usize), the first level cache of the path taken, and how far down the path
+    /// the access was served, if the latencies are counted
+    #[inline(always)]
+    fn read_lines(&mut self, address: u64, size: u16, pc: u64, kind: AccessKind, path: &[usize]) -> (usize, usize) {
+        let (path, fetching) = match (kind, self.fetch_paths.get(path[0])) {
+            (AccessKind::Fetch, Some(fetch_path)) => (fetch_path.as_deref().unwrap_or(path), true),
+            _ => (path, false),
+        };
+        // Assume line size doesn't decrease with level
+        let geometry = self.geometry[path[0]];
+        let (line_size, end) = (geometry.line_size, address.saturating_add(size as u64));
+        let (mut line, count) = geometry.lines(address, size);
+        let mut counters = Counters {
+            caches: &mut self.result.caches,
+            memory_writes: &mut self.result.main_memory_writes,
+            set_statistics: self.set_statistics.as_deref_mut(),
+            pc_statistics: self.pc_statistics.as_deref_mut(),
+            utilisation: self.utilisation.as_deref_mut(),
+            way_statistics: self.way_statistics.as_deref_mut(),
+            pc,
+            touched: (address, end),
+            fetching,
+            observers: &mut self.observers,
+            decisions: self.decisions.as_mut(),
+            shadows: &mut self.shadows,
+        };
+        // The access waits for its slowest line, the one served furthest from the core
+        let mut depth = 0;
+        for _ in 0..count {
+            // Lower levels only see the part of the read within the line of the first level
+            counters.touched = (address.max(line), end.min(line.saturating_add(line_size)));
+            let level = self.hierarchy.access_line(path, line, pc, kind, &mut counters);
+            if let Some(verification) = &mut self.verification {
+                verification.after_access(&self.hierarchy);
+            }
+            if self.latencies.is_some() {
+                depth = depth.max(match level {
+                    HitLevel::Cache(cache) => path.iter().position(|&c| c == cache).unwrap_or(path.len()),
+                    HitLevel::Memory => path.len(),
+                });
+            }
+            line = line.wrapping_add(line_size);
+        }
+        (path[0], depth)
+    }
+
+    /// Fetches the instruction making an access from its program counter, before the access, when
+    /// [enabled](builder::SimulatorBuilder::fetch_program_counters)
+    #[inline(always)]
+    fn fetch_pc(&mut self, pc: u64, kind: AccessKind, path: &[usize]) {
+        if self.options.fetch_pcs && pc != 0 && kind != AccessKind::Fetch {
+            self.read(pc, 1, pc, AccessKind::Fetch, path);
+        }
+    }
+
+    /// Simulates the cache using a reference to a byte array, as accesses from the first core.
+    ///
+    /// The byte array must follow the specified format and must have a length which is a multiple
+    /// of 40 (not contain partial lines).
+    ///
+    /// For speed, we don't verify the input format; if the input format may be invalid it should be
+    /// validated before using this function, or the simulator built with
+    /// [strict](builder::SimulatorBuilder::strict) checking. While it won't panic, it may produce
+    /// incorrect results
+    ///
+    /// Note that reads from the byte array are *guaranteed to be sequential*. This means that when
+    /// using something like mmap, one can advise the operating system that sequential reads will be
+    /// used, which can increase read performance
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    ///
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<&LayeredCacheResult, String> {
+        let first_core = self.hierarchy.topology().cores().next().unwrap();
+        self.simulate_core(bytes, first_core)
+    }
+
+    /// Simulates the cache using a reference to a byte array, as accesses from the given core.
+    ///
+    /// See [simulate](Self::simulate) for the requirements on the input. Cores can be simulated in
+    /// turn, sharing the state of any caches they share.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn simulate_core(&mut self, bytes: &[u8], core: u32) -> Result<&LayeredCacheResult, String> {
+        self.simulate_core_blocks(bytes, core, Self::simulate_records)
+    }
+
+    /// Simulates records in the trace format as accesses from the given core, as
+    /// [simulate_core](Self::simulate_core) does, with `simulate` reading each block of records
+    /// which isn't skipped by the options
+    #[inline(always)]
+    pub(crate) fn simulate_core_blocks(&mut self, bytes: &[u8], core: u32, mut simulate: impl FnMut(&mut Self, &[u8], &[usize])) -> Result<&LayeredCacheResult, String> {
+        assert_eq!(bytes.len() % 40, 0);
+        let path = self.hierarchy.topology().path(core).ok_or(format!("The configuration has no caches for core {core}"))?.to_vec();
+        if self.options.strict {
+            for (index, record) in bytes.chunks_exact(LINE_SIZE).enumerate() {
+                check_record(record).map_err(|e| format!("Record {} isn't in the trace format: {e}", self.options.records + index as u64 + 1))?;
+            }
+        }
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+        if self.options.is_plain() {
+            simulate(self, bytes, &path);
+            self.options.records += (bytes.len() / LINE_SIZE) as u64;
+        } else {
+            let mut remaining = bytes;
+            while !remaining.is_empty() && !self.broken() {
+                let (records, simulated) = self.options.next_block((remaining.len() / LINE_SIZE) as u64);
+                let (block, rest) = remaining.split_at(records as usize * LINE_SIZE);
+                if simulated {
+                    self.simulate_block(|simulator| simulate(simulator, block, &path));
+                }
+                self.finish_block(records);
+                remaining = rest;
+            }
+        }
+        #[cfg(feature = "std")]
+        {
+            self.simulation_time += start.elapsed();
+        }
+        self.update_main_memory_accesses();
+        if let Some(decisions) = &self.decisions {
+            decisions.divergence()?;
+        }
+        if let Some(verification) = &self.verification {
+            verification.violation(&self.result.caches)?;
+        }
+        Ok(&self.result)
+    }
 
-/// The simulator handles line alignment when using the caches, and collects results.
-///
-/// It supports calling simulate multiple times, and will update the time taken to simulate and the
-/// results accordingly
-pub struct Simulator {
-    caches: Vec<GenericCache>,
-    result: LayeredCacheResult,
-    simulation_time: Duration,
-}
+    /// Whether an invariant checked by [verify_invariants](SimulatorBuilder::verify_invariants) has
+    /// been found broken, after which nothing more is simulated
+    fn broken(&self) -> bool {
+        self.verification.as_ref().is_some_and(Verification::broken)
+    }
 
-/// The result of a cache simulation. Can be serialised to the required output format
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
-pub struct LayeredCacheResult {
-    main_memory_accesses: u64,
-    caches: Vec<CacheResult>,
-}
+    /// Whether accesses through a path can be simulated in batches, which needs nothing to happen
+    /// between accesses and every access to be read the same way, see [read_batch](Self::read_batch)
+    pub(crate) fn batches(&self, path: &[usize]) -> bool {
+        self.observers.is_empty() && self.set_statistics.is_none() && self.pc_statistics.is_none() && self.utilisation.is_none() && self.way_statistics.is_none() && self.latencies.is_none() && self.decisions.is_none() && self.verification.is_none()
+            && self.shadows.iter().all(Option::is_none) && self.fetch_paths.is_empty() && !self.options.fetch_pcs && self.page_bits.is_none() && self.hierarchy.batches(path)
+    }
 
-/// The result for an individual cache. Can be serialised to the required output format
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
-pub struct CacheResult {
-    name: String,
-    hits: u64,
-    misses: u64,
-}
+    /// Adds the counts of a batch to the results
+    fn add_counters(&mut self, counters: &[HitMissCounters]) {
+        for (result, counters) in self.result.caches.iter_mut().zip(counters) {
+            result.hits += counters.hits;
+            result.misses += counters.misses;
+        }
+    }
 
-impl Simulator {
+    /// Simulates records in the trace format, without checking them, in batches of lines when
+    /// possible
+    ///
+    /// Always inlined, as the loop was measurably slower as a separate function
+    #[inline(always)]
+    fn simulate_records(&mut self, bytes: &[u8], path: &[usize]) {
+        if self.batches(path) {
+            self.simulate_record_batches(bytes, path);
+            return;
+        }
+        let needs_pc = self.hierarchy.needs_pc() || self.options.fetch_pcs || self.pc_statistics.is_some();
+        for buffer in bytes.chunks_exact(LINE_SIZE) {
+            // Re-implemented, as parse and from_str_radix end up being the bottleneck for smaller caches
+            let address = parse_address((&buffer[ADDRESS_OFFSET..ADDRESS_UPPER]).try_into().unwrap());
+            let size = parse_size((&buffer[SIZE..LINE_SIZE - 1]).try_into().unwrap());
+            let pc = if needs_pc { parse_address((&buffer[..ADDRESS_SIZE]).try_into().unwrap()) } else { 0 };
+            let kind = parse_kind(buffer[RW_MODE]);
+            self.fetch_pc(pc, kind, path);
+            self.read(address, size, pc, kind, path);
+            if self.broken() {
+                return;
+            }
+        }
+    }
 
-    /// Creates a new simulator for a given configuration
+    /// Simulates records in the trace format a batch at a time, splitting each into lines then
+    /// reading them with [access_lines](LayeredCache::access_lines)
+    fn simulate_record_batches(&mut self, bytes: &[u8], path: &[usize]) {
+        let geometry = self.geometry[path[0]];
+        let mut counters = vec![HitMissCounters::default(); self.result.caches.len()];
+        let mut lines = Vec::with_capacity(BATCH_RECORDS);
+        for batch in bytes.chunks(BATCH_RECORDS * LINE_SIZE) {
+            lines.clear();
+            geometry.split_records(batch, &mut lines);
+            self.hierarchy.access_lines(path, &lines, &mut counters);
+        }
+        self.add_counters(&counters);
+    }
+
+    /// Simulates records in the trace format with each cache of the path on its own thread, see
+    /// [LevelPipeline](crate::parallel::LevelPipeline), or one at a time if the path can't be
+    /// read in batches, as once observers start after a warm up
+    #[cfg(feature = "std")]
+    pub(crate) fn simulate_records_by_level(&mut self, bytes: &[u8], path: &[usize], batch_records: usize) {
+        if !self.batches(path) {
+            self.simulate_records(bytes, path);
+            return;
+        }
+        let geometry = self.geometry[path[0]];
+        let batches = bytes.chunks(batch_records * LINE_SIZE).map(|batch| {
+            let mut lines = Vec::with_capacity(batch_records);
+            geometry.split_records(batch, &mut lines);
+            lines
+        });
+        let level_counters = crate::parallel::access_lines_by_level(self.hierarchy.path_caches_mut(path), batches);
+        for (&level, counters) in path.iter().zip(level_counters) {
+            self.result.caches[level].hits += counters.hits;
+            self.result.caches[level].misses += counters.misses;
+        }
+    }
+
+    /// Simulates a block of records, adding its hits and misses to the disturbed counts of each
+    /// cache if it's within the recovery period after a context switch
+    fn simulate_block(&mut self, simulate: impl FnOnce(&mut Self)) {
+        if !self.options.disturbed() {
+            simulate(self);
+            return;
+        }
+        let start: Vec<(u64, u64)> = self.result.caches.iter().map(|cache| (cache.hits, cache.misses)).collect();
+        simulate(self);
+        for (cache, (hits, misses)) in self.result.caches.iter_mut().zip(start) {
+            cache.disturbed_hits += cache.hits - hits;
+            cache.disturbed_misses += cache.misses - misses;
+        }
+    }
+
+    /// Flushes the caches of the context switches of the configuration
+    fn context_switch(&mut self) {
+        let Some(switches) = &self.options.context_switches else {
+            return;
+        };
+        let mut counters = Counters {
+            caches: &mut self.result.caches,
+            memory_writes: &mut self.result.main_memory_writes,
+            set_statistics: self.set_statistics.as_deref_mut(),
+            pc_statistics: self.pc_statistics.as_deref_mut(),
+            utilisation: self.utilisation.as_deref_mut(),
+            way_statistics: self.way_statistics.as_deref_mut(),
+            pc: 0,
+            touched: (0, 0),
+            fetching: false,
+            observers: &mut self.observers,
+            decisions: self.decisions.as_mut(),
+            shadows: &mut self.shadows,
+        };
+        self.hierarchy.flush(&switches.caches, &mut counters);
+        // A shadow is flushed with its cache, so sees the same cold start after the switch
+        for &cache in &switches.caches {
+            if let Some(shadow) = &mut self.shadows[cache] {
+                shadow.invalidate_all(&mut Vec::new());
+            }
+        }
+    }
+
+    /// Moves the options past a block of records, finishing the warm up and intervals and reporting
+    /// progress as they are reached, and making any context switch
+    fn finish_block(&mut self, records: u64) {
+        self.options.records += records;
+        if self.options.context_switches.as_ref().is_some_and(|switches| self.options.records.is_multiple_of(switches.interval)) {
+            self.context_switch();
+        }
+        if self.options.warmup > 0 {
+            self.options.warmup -= records;
+            if self.options.warmup == 0 {
+                self.reset_counts();
+                self.observers = core::mem::take(&mut self.options.observers);
+            }
+        } else {
+            self.options.counted += records;
+            if let Some(interval) = self.options.interval {
+                if self.options.counted.is_multiple_of(interval) {
+                    self.update_main_memory_accesses();
+                    let start = self.options.interval_start.replace(self.result.clone()).unwrap();
+                    let interval = self.result.counts_since(&start);
+                    if let Some(callback) = &self.options.on_interval {
+                        callback(self.options.intervals.len() as u64, &interval);
+                    }
+                    self.options.intervals.push(interval);
+                }
+            }
+        }
+        if let Some((every, progress)) = &self.options.progress {
+            if self.options.records.is_multiple_of(*every) {
+                progress(self.options.records);
+            }
+        }
+    }
+
+    /// Simulates a trace read from a stream, such as a pipe, socket or decompressor, as accesses
+    /// from the first core
+    ///
+    /// See [simulate_reader_core](Self::simulate_reader_core)
+    #[cfg(feature = "std")]
+    pub fn simulate_reader(&mut self, reader: impl Read) -> Result<&LayeredCacheResult, String> {
+        let first_core = self.hierarchy.topology().cores().next().unwrap();
+        self.simulate_reader_core(reader, first_core)
+    }
+
+    /// Simulates a trace read from a stream, as accesses from the given core
+    ///
+    /// The stream is read into a buffer of whole records, which is simulated each time it fills,
+    /// so the trace is never held in memory at once, and reads may end part way through a record.
+    /// The input has the same requirements as [simulate](Self::simulate).
     ///
     /// # Arguments
     ///
-    /// * `config`: A cache configuration, usually resulting from parsing JSON
+    /// * `reader`: The stream, which is read until it ends. It's read in large blocks, so doesn't
+    ///   need buffering
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
     ///
-    /// returns: Simulator
-    pub fn new(config: &LayeredCacheConfig) -> Self {
-        let caches: Vec<GenericCache> = config.caches.iter().map(Self::config_to_cache).collect();
-        let result = LayeredCacheResult {
-            main_memory_accesses: 0,
-            caches: config.caches.iter().map(|cache| CacheResult {
-                hits: 0,
-                misses: 0,
-                name: cache.name.clone(),
-            }).collect(),
-        };
-        Self {
-            caches,
-            result,
-            simulation_time: Duration::new(0, 0),
+    /// returns: Result<&LayeredCacheResult, String>, an error if the stream can't be read or ends
+    /// part way through a record, in which case the records before it have been simulated
+    #[cfg(feature = "std")]
+    pub fn simulate_reader_core(&mut self, mut reader: impl Read, core: u32) -> Result<&LayeredCacheResult, String> {
+        if self.hierarchy.topology().path(core).is_none() {
+            return Err(format!("The configuration has no caches for core {core}"));
+        }
+        let mut buffer = vec![0; READER_BUFFER_RECORDS * LINE_SIZE];
+        let mut filled = 0;
+        loop {
+            let read = match reader.read(&mut buffer[filled..]) {
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("Couldn't read the trace: {e}")),
+            };
+            filled += read;
+            if read != 0 && filled < buffer.len() {
+                continue;
+            }
+            let whole_records = filled - filled % LINE_SIZE;
+            self.simulate_core(&buffer[..whole_records], core)?;
+            buffer.copy_within(whole_records..filled, 0);
+            filled -= whole_records;
+            if read == 0 {
+                break;
+            }
+        }
+        if filled != 0 {
+            return Err("The trace ends part way through a record".to_string());
         }
+        Ok(&self.result)
     }
 
+    /// Simulates reads of the same size at each of a list of addresses, as accesses from the given
+    /// core, for callers which already have the addresses as numbers rather than in the trace format
+    ///
+    /// The reads have no program counter, so stride prefetchers see them all as coming from one
+    /// instruction.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses`: The addresses of the reads
+    /// * `size`: The size of each read in bytes
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn simulate_reads(&mut self, addresses: &[u64], size: u16, core: u32) -> Result<&LayeredCacheResult, String> {
+        self.simulate_accesses(addresses.iter().map(|&address| Access { pc: 0, address, size, kind: AccessKind::Read }), core)
+    }
 
-    /// Reads a value from memory, at a given address with a given size
+    /// Reads a batch of lines from the given core, each at any address within a line of the first
+    /// cache of the core
     ///
-    /// The simulator will handle splitting the read so caches can be checked for each relevant line
+    /// The batch is read by each cache in turn, the misses of one being read by the next, so
+    /// branching on the type of each cache and counting its hits and misses happen once per batch
+    /// rather than once per access. The results are the same as reading the lines one at a time
+    /// with [simulate_reads](Self::simulate_reads) and a size of 1, which is what happens for
+    /// hierarchies with prefetchers, instruction caches, inclusive or exclusive caches or shadows,
+    /// and for simulators with observers, set statistics, decision logs, invariant verification, or any of the options of the
+    /// [builder](Self::builder) which act between records.
     ///
     /// # Arguments
     ///
-    /// * `address`: The address of the read
-    /// * `size`: The size of the read in bytes
+    /// * `lines`: The addresses of the reads, one per line
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
     ///
-    /// returns: (), internally the result is updated
-    fn read(&mut self, address: u64, size: u16) {
-        // Assume line size doesn't decrease with level
-        let first_cache = self.caches.first().unwrap();
-        let lowest_line_size = first_cache.get_line_size();
-        let alignment_diff = address & !first_cache.get_alignment_bit_mask();
-        let mut current_aligned_address = address - alignment_diff;
-        while current_aligned_address < (address + size as u64) {
-            for (cache, res) in self.caches.iter_mut().zip(&mut self.result.caches) {
-                if cache.read_and_update_line(current_aligned_address) {
-                    // Hit
-                    res.hits += 1;
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn read_batch(&mut self, lines: &[u64], core: u32) -> Result<&LayeredCacheResult, String> {
+        let path = self.hierarchy.topology().path(core).ok_or(format!("The configuration has no caches for core {core}"))?.to_vec();
+        if !self.options.is_plain() || !self.batches(&path) {
+            return self.simulate_reads(lines, 1, core);
+        }
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+        let mut counters = vec![HitMissCounters::default(); self.result.caches.len()];
+        self.hierarchy.access_lines(&path, lines, &mut counters);
+        self.add_counters(&counters);
+        self.options.records += lines.len() as u64;
+        #[cfg(feature = "std")]
+        {
+            self.simulation_time += start.elapsed();
+        }
+        self.update_main_memory_accesses();
+        Ok(&self.result)
+    }
+
+    /// Simulates a sequence of accesses from the given core, for callers such as other simulators
+    /// which produce accesses one at a time rather than in the trace format
+    ///
+    /// # Arguments
+    ///
+    /// * `accesses`: The accesses, in the order they are made
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn simulate_accesses(&mut self, accesses: impl IntoIterator<Item = Access>, core: u32) -> Result<&LayeredCacheResult, String> {
+        let path = self.hierarchy.topology().path(core).ok_or(format!("The configuration has no caches for core {core}"))?.to_vec();
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+        if self.options.is_plain() {
+            let mut records = 0;
+            for access in accesses {
+                self.fetch_pc(access.pc, access.kind, &path);
+                self.read(access.address, access.size, access.pc, access.kind, &path);
+                records += 1;
+                if self.broken() {
                     break;
-                } else {
-                    // Miss
-                    res.misses += 1;
                 }
             }
-            current_aligned_address += lowest_line_size;
+            self.options.records += records;
+        } else {
+            let mut accesses = accesses.into_iter().peekable();
+            while accesses.peek().is_some() && !self.broken() {
+                let (records, simulate) = self.options.next_block(u64::MAX);
+                let mut read = 0;
+                self.simulate_block(|simulator| {
+                    for access in accesses.by_ref().take(records as usize) {
+                        if simulate {
+                            simulator.fetch_pc(access.pc, access.kind, &path);
+                            simulator.read(access.address, access.size, access.pc, access.kind, &path);
+                        }
+                        read += 1;
+                        if simulator.broken() {
+                            break;
+                        }
+                    }
+                });
+                self.finish_block(read);
+            }
+        }
+        #[cfg(feature = "std")]
+        {
+            self.simulation_time += start.elapsed();
         }
+        self.update_main_memory_accesses();
+        if let Some(decisions) = &self.decisions {
+            decisions.divergence()?;
+        }
+        if let Some(verification) = &self.verification {
+            verification.violation(&self.result.caches)?;
+        }
+        Ok(&self.result)
     }
 
+    /// Main memory accesses are whatever misses the last level caches, and the lines written to
+    /// memory
+    fn update_main_memory_accesses(&mut self) {
+        self.result.main_memory_accesses = self.result.caches.iter()
+            .enumerate()
+            .filter(|(index, _)| self.hierarchy.topology().is_last_level(*index))
+            .map(|(_, cache)| cache.misses)
+            .sum::<u64>() + self.result.main_memory_writes;
+    }
 
-    /// Simulates the cache using a reference to a byte array.
+    /// Simulates the cache in the same way as [simulate_core](Self::simulate_core), reporting
+    /// progress as it goes
     ///
-    /// The byte array must follow the specified format and must have a length which is a multiple
-    /// of 40 (not contain partial lines).
-    ///
-    /// For speed, we don't verify the input format; if the input format may be invalid it should be
-    /// validated before using this function. While it won't panic, it may produce incorrect results
-    ///
-    /// Note that reads from the byte array are *guaranteed to be sequential*. This means that when
-    /// using something like mmap, one can advise the operating system that sequential reads will be
-    /// used, which can increase read performance
+    /// The trace is simulated in blocks of records, calling `progress` after each block, so the
+    /// callback costs nothing per access.
     ///
     /// # Arguments
     ///
     /// * `bytes`: The input byte array
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    /// * `progress`: Called with the number of bytes of the input simulated so far
     ///
     /// returns: Result<&LayeredCacheResult, String>
-    pub fn simulate(&mut self, bytes: &[u8]) -> Result<&LayeredCacheResult, String> {
-        assert_eq!(bytes.len() % 40, 0);
-        let start = Instant::now();
-        let mut i: usize = 0;
-        while i < bytes.len() {
-            // Alias for clarity, no overhead when compiled
-            let buffer = &bytes[i..i + 40];
-            // Re-implemented, as parse and from_str_radix end up being the bottleneck for smaller caches
-            let address = parse_address((&buffer[ADDRESS_OFFSET..ADDRESS_UPPER]).try_into().unwrap());
-            let size = parse_size((&buffer[SIZE..LINE_SIZE - 1]).try_into().unwrap());
-            self.read(address, size);
-            i += 40;
+    pub fn simulate_core_with_progress(&mut self, bytes: &[u8], core: u32, progress: &mut dyn FnMut(usize)) -> Result<&LayeredCacheResult, String> {
+        let mut processed = 0;
+        for block in bytes.chunks(PROGRESS_INTERVAL * LINE_SIZE) {
+            self.simulate_core(block, core)?;
+            processed += block.len();
+            progress(processed);
         }
-        let end = Instant::now();
-        self.simulation_time += end - start;
-        // Main memory accesses are whatever misses the last cache
-        self.result.main_memory_accesses = self.result.caches.last().unwrap().misses;
         Ok(&self.result)
     }
 
-    /// Gets the wall-clock execution time for processing
+    /// Starts collecting the hits and misses of each set of each cache, from the next access
+    ///
+    /// This has a small cost on every access, so is off by default
+    pub fn enable_set_statistics(&mut self) {
+        if self.set_statistics.is_none() {
+            self.set_statistics = Some(self.hierarchy.caches().iter().map(|cache| SetStatistics::new(cache.get_set_count())).collect());
+        }
+    }
+
+    /// Gets the hits and misses of each set of each cache, in the order of the configuration, if
+    /// [enabled](Self::enable_set_statistics)
+    pub fn set_statistics(&self) -> Option<&[SetStatistics]> {
+        self.set_statistics.as_deref()
+    }
+
+    /// Gets the hits and misses of each instruction in each cache, in the order of the
+    /// configuration, if [enabled](builder::SimulatorBuilder::pc_statistics)
+    pub fn pc_statistics(&self) -> Option<&[PcStatistics]> {
+        self.pc_statistics.as_deref()
+    }
+
+    /// Gets how much of each line each cache used, in the order of the configuration, if
+    /// [enabled](builder::SimulatorBuilder::line_utilisation)
+    pub fn line_utilisation(&self) -> Option<&[LineUtilisation]> {
+        self.utilisation.as_deref()
+    }
+
+    /// Gets the hits and fills of each way of each cache, summed over its sets, in the order of the
+    /// configuration, if [enabled](builder::SimulatorBuilder::way_statistics)
+    pub fn way_statistics(&self) -> Option<&[WayStatistics]> {
+        self.way_statistics.as_deref()
+    }
+
+    /// Gets the level which served each access, if
+    /// [enabled](builder::SimulatorBuilder::latency_histogram)
+    pub fn latency_histogram(&self) -> Option<&LatencyHistogram> {
+        self.latencies.as_ref()
+    }
+
+    /// Gets the replacement decisions made so far, if
+    /// [recorded](builder::SimulatorBuilder::record_decisions)
+    pub fn decision_log(&self) -> Option<&DecisionLog> {
+        match &self.decisions {
+            Some(DecisionTracking::Record(log)) => Some(log),
+            _ => None,
+        }
+    }
+
+    /// Checks a [replayed](builder::SimulatorBuilder::replay_decisions) simulation made every
+    /// decision in the log, and no others
+    ///
+    /// Simulating returns an error as soon as a decision differs from the log, but only this can
+    /// tell that the simulation stopped short of the end of the log.
+    ///
+    /// returns: Result<(), String>, an error describing the first difference from the log
+    pub fn finish_replay(&self) -> Result<(), String> {
+        self.decisions.as_ref().map_or(Ok(()), DecisionTracking::finish)
+    }
+
+    /// Gets the current contents of a cache
+    ///
+    /// # Arguments
+    ///
+    /// * `cache`: The index of the cache, in the order of the configuration
+    ///
+    /// returns: Option<CacheContents>, None if there is no cache with that index
+    pub fn cache_contents(&self, cache: usize) -> Option<CacheContents> {
+        let model = self.hierarchy.caches().get(cache)?;
+        let lines = self.hierarchy.lines(cache);
+        let sets = model.get_set_count();
+        Some(CacheContents {
+            name: self.result.caches[cache].name.clone(),
+            line_size: model.get_line_size(),
+            sets,
+            ways: lines.len() as u64 / sets,
+            lines,
+        })
+    }
+
+    /// Gets the current contents of every cache, in the order of the configuration
+    pub fn contents(&self) -> Vec<CacheContents> {
+        (0..self.hierarchy.caches().len()).filter_map(|cache| self.cache_contents(cache)).collect()
+    }
+
+    /// Dumps the current contents of every cache as JSON, see [contents](Self::contents)
+    pub fn contents_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.contents()).map_err(|e| format!("Couldn't serialise the cache contents {e}"))
+    }
+
+    /// Gets the topology of the simulated caches
+    pub fn topology(&self) -> &Topology {
+        self.hierarchy.topology()
+    }
+
+    /// Gets the hierarchy of caches being simulated, which holds their current contents
+    pub fn layered_cache(&self) -> &LayeredCache {
+        &self.hierarchy
+    }
+
+    /// Gets the results accumulated so far
+    pub fn get_result(&self) -> &LayeredCacheResult {
+        &self.result
+    }
+
+    /// Gets the output document of the results so far, with the results of each interval if they
+    /// are kept
+    pub fn output(&self) -> SimulationOutput {
+        SimulationOutput::new(&self.result).with_intervals(&self.options.intervals)
+    }
+
+    /// Consumes the simulator, returning the results accumulated so far
+    pub fn into_result(self) -> LayeredCacheResult {
+        self.result
+    }
+
+    /// Copies the contents of the caches and the state of their policies and prefetchers, with the
+    /// results, set statistics and execution time reset
+    ///
+    /// The copy carries on from the state of this simulator, but only counts what it simulates
+    /// afterwards, so several what-if simulations can be run from one warm up, on other threads if
+    /// need be.
+    ///
+    /// returns: Simulator
+    pub fn snapshot(&self) -> Simulator {
+        let mut snapshot = self.clone();
+        snapshot.reset_counts();
+        snapshot.simulation_time = Duration::new(0, 0);
+        snapshot
+    }
+
+    /// Zeroes the results, set and instruction statistics and interval results, leaving the caches as they are
+    fn reset_counts(&mut self) {
+        self.result.main_memory_accesses = 0;
+        self.result.main_memory_writes = 0;
+        self.result.page_crossing_accesses = 0;
+        for cache in &mut self.result.caches {
+            *cache = CacheResult { name: cache.name.clone(), ..CacheResult::default() };
+        }
+        if let Some(statistics) = &mut self.set_statistics {
+            for sets in statistics {
+                *sets = SetStatistics::new(sets.hits.len() as u64);
+            }
+        }
+        for pcs in self.pc_statistics.iter_mut().flatten() {
+            *pcs = PcStatistics::default();
+        }
+        for utilisation in self.utilisation.iter_mut().flatten() {
+            utilisation.reset();
+        }
+        for ways in self.way_statistics.iter_mut().flatten() {
+            *ways = WayStatistics::new(ways.hits.len() as u64);
+        }
+        if let Some(latencies) = &mut self.latencies {
+            latencies.reset();
+        }
+        self.options.counted = 0;
+        self.options.intervals.clear();
+        if self.options.interval.is_some() {
+            self.options.interval_start = Some(self.result.clone());
+        }
+    }
+
+    /// Gets the results of each interval so far, if
+    /// [enabled](builder::SimulatorBuilder::interval_statistics)
+    ///
+    /// Each result only counts the accesses of its interval. Records after the last full interval
+    /// aren't included until the interval is completed by a later call.
+    pub fn interval_results(&self) -> &[LayeredCacheResult] {
+        &self.options.intervals
+    }
+
+    /// Gets the wall-clock execution time for processing. Always zero without the std feature, as
+    /// there is no clock
     pub fn get_execution_time(&self) -> &Duration {
         &self.simulation_time
     }
 
+    /// Gets the bytes of host memory held by the tags and policy state of each cache, in the order
+    /// of the configuration
+    ///
+    /// The memory is allocated when the simulator is created, so this predicts the memory a
+    /// simulation needs before simulating anything
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        self.hierarchy.memory_usage()
+    }
+
     /// Gets the number of initialised lines for each cache
     pub fn get_uninitialised_line_counts(&self) -> Vec<u64> {
-        self.caches.iter().map(|x| x.get_uninitialised_line_count() as u64).collect()
+        self.hierarchy.caches().iter().map(|x| x.get_uninitialised_line_count() as u64).collect()
     }
+}
 
-    /// Creates a new cache from a cache configuration
-    fn config_to_cache(config: &CacheConfig) -> GenericCache {
-        let num_lines = config.size / config.line_size;
-        let num_sets = match config.kind {
-            CacheKindConfig::Direct => {
-                num_lines
-            }
-            CacheKindConfig::Full => {
-                1
-            }
-            CacheKindConfig::TwoWay => {
-                num_lines / 2
-            }
-            CacheKindConfig::FourWay => {
-                num_lines / 4
-            }
-            CacheKindConfig::EightWay => {
-                num_lines / 8
-            }
-        };
-        if num_sets == num_lines {
-            GenericCache::from(Cache::new(config.size, config.line_size, num_sets, NoPolicy::default()))
-        } else {
-            match config.replacement_policy {
-                ReplacementPolicyConfig::RoundRobin => {
-                    GenericCache::from(Cache::new(config.size, config.line_size, num_sets, RoundRobin::new(num_sets)))
-                }
-                ReplacementPolicyConfig::LeastRecentlyUsed => {
-                    GenericCache::from(Cache::new(config.size, config.line_size, num_sets, LeastRecentlyUsed::new(num_lines)))
-                }
-                ReplacementPolicyConfig::LeastFrequentlyUsed => {
-                    GenericCache::from(Cache::new(config.size, config.line_size, num_sets, LeastFrequentlyUsed::new(num_lines)))
-                }
-            }
-        }
+/// Checks a record follows the format of the specification, which the parsers assume without
+/// checking
+fn check_record(record: &[u8]) -> Result<(), String> {
+    // Positions are of the byte within its field, so are reported relative to the record
+    let invalid = |field: &str, digits: &str, offset: usize, position: usize| {
+        format!("the {field} isn't {digits} digits, as byte {} of the record is {:?}", offset + position + 1, record[offset + position] as char)
+    };
+    try_parse_address(record[..ADDRESS_SIZE].try_into().unwrap()).map_err(|position| invalid("program counter", "16 hexadecimal", 0, position))?;
+    try_parse_address(record[ADDRESS_OFFSET..ADDRESS_UPPER].try_into().unwrap()).map_err(|position| invalid("address", "16 hexadecimal", ADDRESS_OFFSET, position))?;
+    if [ADDRESS_SIZE, ADDRESS_UPPER, RW_MODE + 1].iter().any(|&i| record[i] != b' ') {
+        return Err("the fields aren't separated by single spaces".to_string());
+    }
+    if !matches!(record[RW_MODE], b'R' | b'W' | b'I' | b'M') {
+        return Err(format!("the mode is {:?}, rather than R, W, I or M", record[RW_MODE] as char));
     }
+    try_parse_size(record[SIZE..LINE_SIZE - 1].try_into().unwrap()).map_err(|position| invalid("size", "3 decimal", SIZE, position))?;
+    if record[LINE_SIZE - 1] != b'\n' {
+        return Err("it doesn't end with a newline".to_string());
+    }
+    Ok(())
 }
 
 /// Parses a 64-bit value from a 16 byte hexadecimal address
@@ -189,11 +1771,14 @@ impl Simulator {
 ///
 /// This is significantly faster than using the standard library, but omits checks for the input
 /// format. While it is guaranteed not to panic, if the input format is incorrect it may produce
-/// incorrect results.
+/// incorrect results. Use [try_parse_address] for input which hasn't been validated.
+///
+/// On x86_64 and aarch64 every digit is converted at once with SIMD instructions, in a handful of
+/// instructions for the whole field.
 ///
-/// This function makes use of a lookup table of 2^16 bytes, which performs lookups for each
-/// pair of hex values. This gets unrolled by the compiler, and has been shown to be
-/// significantly faster than individual lookups of each byte, or branching approaches
+/// Other targets use a lookup table of 2^16 bytes, which performs lookups for each pair of hex
+/// values. This gets unrolled by the compiler, and has been shown to be significantly faster than
+/// individual lookups of each byte, or branching approaches
 ///
 /// The lookup table is defined in the hex module, which is automatically generated at compile
 /// time. We use build.rs for this instead of a const fn in this module as build.rs is much
@@ -219,6 +1804,26 @@ impl Simulator {
 /// assert_eq!(parse_address(&address), 10)
 /// ```
 pub fn parse_address(buf: &[u8; 16]) -> u64 {
+    #[cfg(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64"))]
+    let res = simd::parse_address(buf);
+    #[cfg(not(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64")))]
+    let res = parse_address_with_lookup(buf);
+    debug_assert_eq!(
+        {
+            let addr_as_str = core::str::from_utf8(buf).unwrap();
+            u64::from_str_radix(addr_as_str, 16).unwrap()
+        },
+        res
+    );
+    res
+}
+
+/// Parses an address a pair of digits at a time with the lookup table, on targets without the SIMD
+/// instructions [parse_address] uses
+///
+/// Kept on every target, so the tests can check the SIMD parser against it
+#[cfg_attr(any(all(target_arch = "x86_64", target_feature = "sse2"), target_arch = "aarch64"), allow(dead_code))]
+pub(crate) fn parse_address_with_lookup(buf: &[u8; 16]) -> u64 {
     let mut res: u64 = 0;
     let mut x = 0;
     while x < 15 {
@@ -226,13 +1831,6 @@ pub fn parse_address(buf: &[u8; 16]) -> u64 {
         res |= HEX_LOOKUP[buf[x] as usize][buf[x + 1] as usize] as u64;
         x += 2;
     }
-    debug_assert_eq!(
-        {
-            let addr_as_str = std::str::from_utf8(buf).unwrap();
-            u64::from_str_radix(addr_as_str, 16).unwrap()
-        },
-        res
-    );
     res
 }
 
@@ -241,7 +1839,8 @@ pub fn parse_address(buf: &[u8; 16]) -> u64 {
 /// a lookup table
 ///
 /// The performance difference isn't as large as it is for parse_address as the input is smaller,
-/// but it's enough to have a significant impact
+/// but it's enough to have a significant impact. Like parse_address it doesn't check its input,
+/// use [try_parse_size] for input which hasn't been validated
 ///
 /// # Arguments
 ///
@@ -262,10 +1861,72 @@ pub fn parse_size(buf: &[u8; 3]) -> u16 {
     res += 100u16 * (buf[0] - b'0') as u16;
     debug_assert_eq!(
         {
-            let size_as_str = std::str::from_utf8(buf).unwrap();
+            let size_as_str = core::str::from_utf8(buf).unwrap();
             size_as_str.parse::<u16>().unwrap()
         },
         res
     );
     res
 }
+
+/// Parses the mode of a record, `W` being a write, `I` an instruction fetch and `M` a modify, as in
+/// Lackey's traces, and anything else a read
+#[inline(always)]
+fn parse_kind(mode: u8) -> AccessKind {
+    match mode {
+        b'W' => AccessKind::Write,
+        b'I' => AccessKind::Fetch,
+        b'M' => AccessKind::Modify,
+        _ => AccessKind::Read,
+    }
+}
+
+/// Parses a 64-bit value from a 16 byte hexadecimal address, checking every byte is a hexadecimal
+/// digit
+///
+/// This is slower than [parse_address], which should be preferred once the input is known to be
+/// well formed, such as after [strict](builder::SimulatorBuilder::strict) checking.
+///
+/// # Arguments
+///
+/// * `buf`: The byte buffer
+///
+/// returns: Result<u64, usize>, the position of the first byte which isn't a hexadecimal digit if
+/// the input is malformed
+///
+/// # Examples
+///
+/// ```
+/// use cachelib::simulator::try_parse_address;
+/// assert_eq!(try_parse_address(b"000000000000000A"), Ok(10));
+/// assert_eq!(try_parse_address(b"00000000000000x0"), Err(14));
+/// ```
+pub fn try_parse_address(buf: &[u8; 16]) -> Result<u64, usize> {
+    buf.iter().enumerate().try_fold(0, |res, (position, &byte)| {
+        let digit = (byte as char).to_digit(16).ok_or(position)?;
+        Ok(res << 4 | digit as u64)
+    })
+}
+
+/// Parses a 3 byte decimal size, checking every byte is a decimal digit, see [try_parse_address]
+///
+/// # Arguments
+///
+/// * `buf`: The input
+///
+/// returns: Result<u16, usize>, the position of the first byte which isn't a decimal digit if the
+/// input is malformed
+///
+/// # Examples
+///
+/// ```
+/// use cachelib::simulator::try_parse_size;
+/// assert_eq!(try_parse_size(b"010"), Ok(10));
+/// assert_eq!(try_parse_size(b"0 1"), Err(1));
+/// ```
+pub fn try_parse_size(buf: &[u8; 3]) -> Result<u16, usize> {
+    buf.iter().enumerate().try_fold(0, |res, (position, &byte)| {
+        let digit = (byte as char).to_digit(10).ok_or(position)?;
+        Ok(res * 10 + digit as u16)
+    })
+}
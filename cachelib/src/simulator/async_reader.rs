@@ -0,0 +1,58 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::simulator::{LayeredCacheResult, Simulator, LINE_SIZE};
+
+/// The number of records simulated between yielding to the runtime, unless set by
+/// [async_yield_records](crate::simulator::builder::SimulatorBuilder::async_yield_records)
+pub const ASYNC_YIELD_RECORDS: u64 = 16 * 1024;
+
+impl Simulator {
+    /// Simulates a trace read from an async stream, such as a network connection or a download
+    /// from object storage, as accesses from the first core
+    ///
+    /// See [simulate_async_core](Self::simulate_async_core)
+    pub async fn simulate_async(&mut self, reader: impl AsyncRead + Unpin) -> Result<&LayeredCacheResult, String> {
+        let first_core = self.hierarchy.topology().cores().next().unwrap();
+        self.simulate_async_core(reader, first_core).await
+    }
+
+    /// Simulates a trace read from an async stream, as accesses from the given core
+    ///
+    /// The stream is read in the same way as by [simulate_reader_core](Self::simulate_reader_core),
+    /// but without blocking. Simulating is still work on the runtime's thread, so after each block
+    /// of records the task yields, letting other tasks run while a long trace is simulated.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`: The stream, which is read until it ends
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>, an error if the stream can't be read or ends
+    /// part way through a record, in which case the records before it have been simulated
+    pub async fn simulate_async_core(&mut self, mut reader: impl AsyncRead + Unpin, core: u32) -> Result<&LayeredCacheResult, String> {
+        if self.hierarchy.topology().path(core).is_none() {
+            return Err(format!("The configuration has no caches for core {core}"));
+        }
+        let records = self.options.yield_records.unwrap_or(ASYNC_YIELD_RECORDS) as usize;
+        let mut buffer = vec![0; records * LINE_SIZE];
+        let mut filled = 0;
+        loop {
+            let read = reader.read(&mut buffer[filled..]).await.map_err(|e| format!("Couldn't read the trace: {e}"))?;
+            filled += read;
+            if read != 0 && filled < buffer.len() {
+                continue;
+            }
+            let whole_records = filled - filled % LINE_SIZE;
+            self.simulate_core(&buffer[..whole_records], core)?;
+            buffer.copy_within(whole_records..filled, 0);
+            filled -= whole_records;
+            if read == 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        if filled != 0 {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        Ok(&self.result)
+    }
+}
@@ -0,0 +1,326 @@
+use alloc::sync::Arc;
+use crate::config::LayeredCacheConfig;
+use crate::cache::CacheTrait;
+use crate::simulator::{DynamicObserver, LatencyHistogram, LayeredCacheResult, LineUtilisation, PcStatistics, Simulator, WayStatistics};
+use crate::simulator::decisions::{DecisionLog, DecisionTracking};
+use crate::simulator::verification::Verification;
+use crate::prelude::*;
+
+/// Called with the number of records a simulator has read so far
+pub type ProgressCallback = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Called with the index and results of each interval as it ends
+pub type IntervalCallback = Arc<dyn Fn(u64, &LayeredCacheResult) + Send + Sync>;
+
+/// Simulates only part of a trace, the first `length` records of every `period`
+///
+/// The records in between are skipped, leaving the caches as they were, so the results estimate
+/// those of the whole trace for a fraction of the time.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Sampling {
+    pub length: u64,
+    pub period: u64,
+}
+
+/// When the caches are flushed by context switches, from the
+/// [ContextSwitchConfig](crate::config::ContextSwitchConfig) of the configuration
+#[derive(Clone)]
+pub(super) struct ContextSwitches {
+    pub(super) interval: u64,
+    pub(super) recovery: u64,
+    pub(super) caches: Vec<usize>,
+}
+
+/// The options of a simulator, see [SimulatorBuilder]
+#[derive(Clone, Default)]
+pub(super) struct Options {
+    /// Records left to simulate before counting results
+    pub(super) warmup: u64,
+    pub(super) sampling: Option<Sampling>,
+    pub(super) strict: bool,
+    /// Whether each record also fetches the instruction at its program counter
+    pub(super) fetch_pcs: bool,
+    pub(super) interval: Option<u64>,
+    pub(super) on_interval: Option<IntervalCallback>,
+    pub(super) progress: Option<(u64, ProgressCallback)>,
+    /// Records simulated between yielding to the async runtime, if not the default
+    pub(super) yield_records: Option<u64>,
+    /// Observers waiting for the warm up to finish
+    pub(super) observers: Vec<Box<dyn DynamicObserver>>,
+    /// Records read, including those warming up and skipped
+    pub(super) records: u64,
+    /// Records read since the warm up finished
+    pub(super) counted: u64,
+    /// The results when the current interval started
+    pub(super) interval_start: Option<LayeredCacheResult>,
+    pub(super) intervals: Vec<LayeredCacheResult>,
+    pub(super) context_switches: Option<ContextSwitches>,
+}
+
+impl Options {
+    /// Whether every record is simulated and counted, with nothing to do between them
+    pub(super) fn is_plain(&self) -> bool {
+        self.warmup == 0 && self.sampling.is_none() && self.interval.is_none() && self.progress.is_none() && self.context_switches.is_none()
+    }
+
+    /// Whether the next record is within the recovery period after a context switch
+    pub(super) fn disturbed(&self) -> bool {
+        self.context_switches.as_ref().is_some_and(|switches| self.records >= switches.interval && self.records % switches.interval < switches.recovery)
+    }
+
+    /// Gets how many of the next records, up to `available`, are handled in the same way before
+    /// something needs doing, and whether they are simulated rather than skipped
+    pub(super) fn next_block(&self, available: u64) -> (u64, bool) {
+        let mut records = available;
+        let mut simulate = true;
+        if self.warmup > 0 {
+            records = records.min(self.warmup);
+        } else {
+            if let Some(Sampling { length, period }) = self.sampling {
+                let phase = self.counted % period;
+                simulate = phase < length;
+                records = records.min(if simulate { length - phase } else { period - phase });
+            }
+            if let Some(interval) = self.interval {
+                records = records.min(interval - self.counted % interval);
+            }
+        }
+        if let Some((every, _)) = &self.progress {
+            records = records.min(every - self.records % every);
+        }
+        if let Some(switches) = &self.context_switches {
+            let phase = self.records % switches.interval;
+            let end = if self.disturbed() { switches.recovery } else { switches.interval };
+            records = records.min(end - phase);
+        }
+        (records, simulate)
+    }
+}
+
+/// Configures a [Simulator] before creating it, see [Simulator::builder]
+///
+/// Every option is off by default, giving the same simulator as [Simulator::new].
+pub struct SimulatorBuilder<'a> {
+    config: &'a LayeredCacheConfig,
+    options: Options,
+    set_statistics: bool,
+    pc_statistics: bool,
+    line_utilisation: bool,
+    way_statistics: bool,
+    latency_histogram: bool,
+    decisions: Option<DecisionTracking>,
+    verify_every: Option<u64>,
+}
+
+impl<'a> SimulatorBuilder<'a> {
+    pub(super) fn new(config: &'a LayeredCacheConfig) -> Self {
+        Self { config, options: Options::default(), set_statistics: false, pc_statistics: false, line_utilisation: false, way_statistics: false, latency_histogram: false, decisions: None, verify_every: None }
+    }
+
+    /// Simulates the first records read without counting them, so the results only cover caches
+    /// which have been filled
+    pub fn warmup(mut self, records: u64) -> Self {
+        self.options.warmup = records;
+        self
+    }
+
+    /// Simulates only the first `length` records of every `period` after the warm up, see
+    /// [Sampling]
+    pub fn sampling(mut self, length: u64, period: u64) -> Self {
+        self.options.sampling = Some(Sampling { length, period });
+        self
+    }
+
+    /// Calls an observer with every access to every cache once the warm up has finished
+    ///
+    /// Observers have a cost on every access. Cloning the simulator clones its observers, so
+    /// observers which should be shared between copies can hold their state in an `Arc`.
+    pub fn observer(mut self, observer: impl DynamicObserver + 'static) -> Self {
+        self.options.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Checks the format of every record of a trace before simulating it, rather than giving
+    /// incorrect results for a malformed trace
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Fetches the instruction at the program counter of each record before making its access,
+    /// for traces of data accesses which don't have instruction fetches of their own
+    ///
+    /// Each fetch reads one byte through the core's instruction cache, or its data caches if it has
+    /// none, on the slower path which reads one record at a time. Records without a program
+    /// counter, and fetches already in the trace, aren't fetched again.
+    pub fn fetch_program_counters(mut self, enabled: bool) -> Self {
+        self.options.fetch_pcs = enabled;
+        self
+    }
+
+    /// Records the results of each run of `records` records after the warm up, see
+    /// [Simulator::interval_results]
+    pub fn interval_statistics(mut self, records: u64) -> Self {
+        self.options.interval = Some(records);
+        self
+    }
+
+    /// Calls `callback` with the index and results of each interval as soon as it ends, so they
+    /// can be written out during a long simulation. Needs [interval_statistics](Self::interval_statistics)
+    pub fn on_interval(mut self, callback: impl Fn(u64, &LayeredCacheResult) + Send + Sync + 'static) -> Self {
+        self.options.on_interval = Some(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` with the number of records read so far every `records` records, including
+    /// those warming up or skipped by sampling
+    pub fn progress(mut self, records: u64, callback: impl Fn(u64) + Send + Sync + 'static) -> Self {
+        self.options.progress = Some((records, Arc::new(callback)));
+        self
+    }
+
+    /// Sets how many records [simulate_async](Simulator::simulate_async) simulates between yielding
+    /// to the runtime, which defaults to [ASYNC_YIELD_RECORDS](crate::simulator::ASYNC_YIELD_RECORDS)
+    ///
+    /// Fewer records let other tasks run sooner, more have less overhead.
+    #[cfg(feature = "tokio")]
+    pub fn async_yield_records(mut self, records: u64) -> Self {
+        self.options.yield_records = Some(records);
+        self
+    }
+
+    /// Collects the hits and misses of each set of each cache, see
+    /// [enable_set_statistics](Simulator::enable_set_statistics)
+    pub fn set_statistics(mut self, enabled: bool) -> Self {
+        self.set_statistics = enabled;
+        self
+    }
+
+    /// Collects the hits and misses of the accesses each instruction makes to each cache, by program
+    /// counter, see [pc_statistics](Simulator::pc_statistics)
+    ///
+    /// Like set statistics, this reads one record at a time, and parses the program counter of
+    /// every record.
+    pub fn pc_statistics(mut self, enabled: bool) -> Self {
+        self.pc_statistics = enabled;
+        self
+    }
+
+    /// Tracks the bytes of each line used while it's in each cache, see
+    /// [line_utilisation](Simulator::line_utilisation)
+    ///
+    /// Evictions are tracked on the slower path, which also keeps a bit per byte of every line
+    /// held.
+    pub fn line_utilisation(mut self, enabled: bool) -> Self {
+        self.line_utilisation = enabled;
+        self
+    }
+
+    /// Counts the hits and fills of each way of each cache, summed over its sets, see
+    /// [way_statistics](Simulator::way_statistics)
+    ///
+    /// Fills are seen on the slower path, which tracks replacements.
+    pub fn way_statistics(mut self, enabled: bool) -> Self {
+        self.way_statistics = enabled;
+        self
+    }
+
+    /// Counts the accesses served by each level of the hierarchy, see
+    /// [latency_histogram](Simulator::latency_histogram)
+    ///
+    /// Accesses are counted on the slower path, which reads one line at a time.
+    pub fn latency_histogram(mut self, enabled: bool) -> Self {
+        self.latency_histogram = enabled;
+        self
+    }
+
+    /// Records the way each cache's replacement policy chooses every time it allocates a line, see
+    /// [decision_log](Simulator::decision_log)
+    ///
+    /// The decisions are made on the slower path which tracks evictions, and replaces
+    /// [replay_decisions](Self::replay_decisions).
+    pub fn record_decisions(mut self, enabled: bool) -> Self {
+        self.decisions = enabled.then(|| DecisionTracking::Record(DecisionLog::default()));
+        self
+    }
+
+    /// Checks every replacement decision against a log recorded by another simulation of the same
+    /// configuration and trace, so that simulating returns an error at the first which differs,
+    /// see [finish_replay](Simulator::finish_replay)
+    ///
+    /// This replaces [record_decisions](Self::record_decisions).
+    pub fn replay_decisions(mut self, log: DecisionLog) -> Self {
+        self.decisions = Some(DecisionTracking::Replay { expected: log, position: 0, checked: 0, divergence: None });
+        self
+    }
+
+    /// Checks the invariants of the hierarchy after every `every` accesses to a line, so simulating
+    /// stops at the record which breaks one, returning an error describing it with the contents of
+    /// the sets concerned, see [check_invariants](crate::layered::LayeredCache::check_invariants)
+    ///
+    /// Each check reads every line of every cache, so this is for debugging the simulator or a
+    /// policy rather than for measuring, and accesses are made one at a time.
+    pub fn verify_invariants(mut self, every: u64) -> Self {
+        self.verify_every = Some(every);
+        self
+    }
+
+    /// Creates the simulator
+    ///
+    /// returns: Result<Simulator, String>, an error if an option is invalid
+    ///
+    /// # Panics
+    ///
+    /// If the configuration is invalid, see [Simulator::new]
+    pub fn build(self) -> Result<Simulator, String> {
+        let mut options = self.options;
+        if let Some(Sampling { length, period }) = options.sampling {
+            if length == 0 || length > period {
+                return Err(format!("Sampling must simulate between 1 and {period} records of every {period}, not {length}"));
+            }
+        }
+        if options.interval == Some(0) {
+            return Err("The interval for statistics must be at least 1 record".to_string());
+        }
+        if options.on_interval.is_some() && options.interval.is_none() {
+            return Err("A callback for each interval needs interval statistics".to_string());
+        }
+        if options.yield_records == Some(0) {
+            return Err("The simulator must simulate at least 1 record between yielding, not 0".to_string());
+        }
+        if self.verify_every == Some(0) {
+            return Err("The invariants must be checked at least every access, not every 0".to_string());
+        }
+        if matches!(options.progress, Some((0, _))) {
+            return Err("Progress must be reported at least every record, not every 0".to_string());
+        }
+        let mut simulator = Simulator::new(self.config);
+        options.context_switches = simulator.options.context_switches.take();
+        if self.set_statistics {
+            simulator.enable_set_statistics();
+        }
+        if self.pc_statistics {
+            simulator.pc_statistics = Some(vec![PcStatistics::default(); simulator.hierarchy.caches().len()]);
+        }
+        if self.line_utilisation {
+            simulator.utilisation = Some(simulator.hierarchy.caches().iter().map(|cache| LineUtilisation::new(cache.get_line_size())).collect());
+        }
+        if self.way_statistics {
+            let ways = self.config.caches.iter().zip(simulator.hierarchy.caches()).map(|(config, cache)| config.size / config.line_size / cache.get_set_count());
+            simulator.way_statistics = Some(ways.map(WayStatistics::new).collect());
+        }
+        if self.latency_histogram {
+            simulator.latencies = Some(LatencyHistogram::new(simulator.hierarchy.topology(), simulator.hierarchy.caches().len()));
+        }
+        simulator.decisions = self.decisions;
+        simulator.verification = self.verify_every.map(Verification::new);
+        if options.warmup == 0 {
+            simulator.observers = core::mem::take(&mut options.observers);
+        }
+        if options.interval.is_some() {
+            options.interval_start = Some(simulator.result.clone());
+        }
+        simulator.options = options;
+        Ok(simulator)
+    }
+}
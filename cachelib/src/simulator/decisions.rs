@@ -0,0 +1,166 @@
+use crate::simulator::CacheResult;
+use crate::prelude::*;
+
+/// The bytes at the start of a decision log written by [to_bytes](DecisionLog::to_bytes), ending
+/// with the version of the format
+const MAGIC: &[u8] = b"cachesim-decisions\x01";
+
+/// A replacement decision, the way of a set a cache's policy chose for a line
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Decision {
+    /// The index of the cache, in the order of the configuration
+    pub cache: usize,
+    pub set: u64,
+    pub way: u64,
+}
+
+/// The replacement decisions of a simulation, in the order they were made
+///
+/// Each decision is held as three variable length integers, so most take three or four bytes, and
+/// a log of every decision in a long trace stays small enough to keep in memory and write to disk.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DecisionLog {
+    bytes: Vec<u8>,
+    len: u64,
+}
+
+impl DecisionLog {
+    fn push(&mut self, decision: Decision) {
+        for value in [decision.cache as u64, decision.set, decision.way] {
+            write_varint(&mut self.bytes, value);
+        }
+        self.len += 1;
+    }
+
+    /// Gets the number of decisions in the log
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the decisions in the order they were made
+    pub fn iter(&self) -> impl Iterator<Item = Decision> + '_ {
+        let mut position = 0;
+        (0..self.len).map(move |_| read_decision(&self.bytes, &mut position).expect("The log should only hold whole decisions"))
+    }
+
+    /// Writes the log in a compact binary format, which [from_bytes](Self::from_bytes) reads
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + self.bytes.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.bytes);
+        bytes
+    }
+
+    /// Reads a log written by [to_bytes](Self::to_bytes)
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The log
+    ///
+    /// returns: Result<DecisionLog, String>, an error if the bytes aren't a decision log
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let bytes = bytes.strip_prefix(MAGIC).ok_or("The file isn't a decision log from this version of the simulator")?;
+        let mut position = 0;
+        let mut len = 0;
+        while position < bytes.len() {
+            read_decision(bytes, &mut position).ok_or(format!("The decision log ends part way through decision {}", len + 1))?;
+            len += 1;
+        }
+        Ok(Self { bytes: bytes.to_vec(), len })
+    }
+}
+
+/// What a simulator does with the replacement decisions of its caches
+#[derive(Debug, Clone)]
+pub(super) enum DecisionTracking {
+    /// Adds each decision to a log
+    Record(DecisionLog),
+    /// Checks each decision against a log, stopping at the first which differs
+    Replay {
+        expected: DecisionLog,
+        /// The position of the next decision in the expected log's bytes
+        position: usize,
+        /// The number of decisions checked so far
+        checked: u64,
+        divergence: Option<String>,
+    },
+}
+
+impl DecisionTracking {
+    /// Records a decision, or checks it against the log
+    ///
+    /// # Arguments
+    ///
+    /// * `decision`: The decision made
+    /// * `caches`: The results of the caches, for their names
+    pub(super) fn decide(&mut self, decision: Decision, caches: &[CacheResult]) {
+        match self {
+            Self::Record(log) => log.push(decision),
+            Self::Replay { expected, position, checked, divergence } => {
+                if divergence.is_some() {
+                    return;
+                }
+                *checked += 1;
+                let describe = |d: Decision| format!("way {} of set {} of {}", d.way, d.set, caches[d.cache].name);
+                *divergence = match read_decision(&expected.bytes, position) {
+                    Some(logged) if logged == decision => None,
+                    Some(logged) => Some(format!("Decision {checked} differs from the log, {} was chosen rather than {}", describe(decision), describe(logged))),
+                    None => Some(format!("Decision {checked}, {}, is past the end of the log of {} decisions", describe(decision), expected.len)),
+                };
+            }
+        }
+    }
+
+    /// Gets an error describing the first decision which differed from the log, if any
+    pub(super) fn divergence(&self) -> Result<(), String> {
+        match self {
+            Self::Replay { divergence: Some(divergence), .. } => Err(divergence.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets an error if a decision differed from the log, or if the log has decisions which
+    /// weren't made
+    pub(super) fn finish(&self) -> Result<(), String> {
+        self.divergence()?;
+        match self {
+            Self::Replay { expected, checked, .. } if *checked < expected.len => {
+                Err(format!("Only {checked} of the {} decisions in the log were made", expected.len))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_decision(bytes: &[u8], position: &mut usize) -> Option<Decision> {
+    Some(Decision {
+        cache: read_varint(bytes, position)? as usize,
+        set: read_varint(bytes, position)?,
+        way: read_varint(bytes, position)?,
+    })
+}
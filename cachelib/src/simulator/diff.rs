@@ -0,0 +1,92 @@
+use serde::Serialize;
+use crate::simulator::LayeredCacheResult;
+use crate::prelude::*;
+
+/// A metric of two results, side by side
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricDelta {
+    /// The metric, such as `L1.misses`, `L1.miss_ratio` or `main_memory_accesses`
+    pub metric: String,
+    /// The value in the first result, or None if the cache isn't in it or had no accesses
+    pub first: Option<f64>,
+    /// The value in the second result, or None if the cache isn't in it or had no accesses
+    pub second: Option<f64>,
+}
+
+impl MetricDelta {
+    /// Gets the change from the first value to the second, or None if either is missing
+    pub fn absolute(&self) -> Option<f64> {
+        Some(self.second? - self.first?)
+    }
+
+    /// Gets the change from the first value to the second as a fraction of the first, or None if
+    /// either is missing
+    ///
+    /// A change from zero is infinite, and no change from zero is zero.
+    pub fn relative(&self) -> Option<f64> {
+        let (first, second) = (self.first?, self.second?);
+        Some(match (first, second - first) {
+            (_, 0.0) => 0.0,
+            (0.0, delta) => delta.signum() * f64::INFINITY,
+            (first, delta) => delta / first,
+        })
+    }
+
+    /// Checks whether the metric changed by more than a fraction of its first value, or is only
+    /// present in one of the results
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold`: The largest allowed relative change, such as 0.01 for 1%
+    ///
+    /// returns: bool
+    pub fn exceeds(&self, threshold: f64) -> bool {
+        match (self.first, self.second) {
+            (None, None) => false,
+            (Some(_), None) | (None, Some(_)) => true,
+            _ => self.relative().unwrap().abs() > threshold,
+        }
+    }
+}
+
+/// Lines up the metrics of two results, matching caches by name
+///
+/// Each cache has its hits, misses and miss ratio, in the order of the first result followed by
+/// any caches only in the second, then the main memory accesses.
+///
+/// # Arguments
+///
+/// * `first`: The baseline result
+/// * `second`: The result to compare against the baseline
+///
+/// returns: Vec<MetricDelta>, every metric, whether it changed or not
+pub fn diff_results(first: &LayeredCacheResult, second: &LayeredCacheResult) -> Vec<MetricDelta> {
+    let mut names: Vec<&str> = first.caches().iter().map(|c| c.name()).collect();
+    for cache in second.caches() {
+        if !names.contains(&cache.name()) {
+            names.push(cache.name());
+        }
+    }
+    let mut deltas = Vec::with_capacity(names.len() * 3 + 1);
+    for name in names {
+        let find = |result: &LayeredCacheResult| result.caches().iter().find(|c| c.name() == name).cloned();
+        let (first, second) = (find(first), find(second));
+        let metric = |metric: &str, value: &dyn Fn(&crate::simulator::CacheResult) -> Option<f64>| MetricDelta {
+            metric: format!("{name}.{metric}"),
+            first: first.as_ref().and_then(value),
+            second: second.as_ref().and_then(value),
+        };
+        deltas.push(metric("hits", &|c| Some(c.hits() as f64)));
+        deltas.push(metric("misses", &|c| Some(c.misses() as f64)));
+        deltas.push(metric("miss_ratio", &|c| match c.hits() + c.misses() {
+            0 => None,
+            accesses => Some(c.misses() as f64 / accesses as f64),
+        }));
+    }
+    deltas.push(MetricDelta {
+        metric: "main_memory_accesses".to_string(),
+        first: Some(first.main_memory_accesses() as f64),
+        second: Some(second.main_memory_accesses() as f64),
+    });
+    deltas
+}
@@ -0,0 +1,131 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use crate::layered::AccessKind;
+use crate::simulator::{Access, AccessObserver, CacheAccess};
+
+/// The largest size a record can hold, as the size field has 3 digits
+const MAX_RECORD_SIZE: u64 = 999;
+
+/// Longer accesses are split into records of this size, which keep the alignment of a line
+const SPLIT_RECORD_SIZE: u64 = 512;
+
+/// Writes accesses as the records of a trace in the format of the specification, which can be
+/// simulated like any other trace
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    records: u64,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Creates a writer with no records written. Every record is a separate write, so the writer
+    /// should be buffered
+    pub fn new(writer: W) -> Self {
+        Self { writer, records: 0 }
+    }
+
+    /// Writes an access, splitting accesses too long for a record into records of 512 bytes
+    pub fn write(&mut self, access: Access) -> std::io::Result<()> {
+        let size = access.size as u64;
+        let mode = match access.kind {
+            AccessKind::Read => 'R',
+            AccessKind::Write => 'W',
+            AccessKind::Fetch => 'I',
+            AccessKind::Modify => 'M',
+        };
+        if size <= MAX_RECORD_SIZE {
+            self.records += 1;
+            return writeln!(self.writer, "{:016x} {:016x} {mode} {size:03}", access.pc, access.address);
+        }
+        for offset in (0..size).step_by(SPLIT_RECORD_SIZE as usize) {
+            self.records += 1;
+            writeln!(self.writer, "{:016x} {:016x} {mode} {:03}", access.pc, access.address + offset, SPLIT_RECORD_SIZE.min(size - offset))?;
+        }
+        Ok(())
+    }
+
+    /// Gets the number of records written so far
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Flushes the records written, and takes the writer
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// The writer of a [MissTrace], shared between its copies, with the first error it returned
+struct SharedWriter<W: Write> {
+    writer: TraceWriter<W>,
+    error: Option<std::io::Error>,
+}
+
+/// An observer writing each line which misses a cache as a record of a trace, a read of the whole
+/// line, so the trace holds the accesses the levels below the cache see
+///
+/// Simulating the trace with the levels below reproduces their results, unless a level reacts to
+/// the cache above it: an exclusive level, which is also filled by its evictions, or an inclusive
+/// level, whose evictions invalidate its lines. Lines the cache prefetches aren't read from below,
+/// so aren't written. Program counters aren't known to observers, so records have a program counter
+/// of 0.
+///
+/// Copies of the observer, such as those of a cloned simulator, write to the same writer. Writing
+/// stops at the first error, which [finish](Self::finish) returns.
+pub struct MissTrace<W: Write> {
+    cache: usize,
+    line_size: u16,
+    shared: Arc<Mutex<SharedWriter<W>>>,
+}
+
+impl<W: Write> Clone for MissTrace<W> {
+    fn clone(&self) -> Self {
+        Self { cache: self.cache, line_size: self.line_size, shared: self.shared.clone() }
+    }
+}
+
+impl<W: Write> MissTrace<W> {
+    /// Creates an observer writing the misses of a cache
+    ///
+    /// # Arguments
+    ///
+    /// * `cache`: The index of the cache, in the order of the configuration
+    /// * `line_size`: The line size of the cache, the size of each record
+    /// * `writer`: Where the trace is written, which should be buffered
+    ///
+    /// returns: MissTrace<W>
+    pub fn new(cache: usize, line_size: u64, writer: W) -> Self {
+        Self {
+            cache,
+            line_size: line_size.min(u16::MAX as u64) as u16,
+            shared: Arc::new(Mutex::new(SharedWriter { writer: TraceWriter::new(writer), error: None })),
+        }
+    }
+
+    /// Flushes the trace
+    ///
+    /// returns: Result<u64, String>, the number of records written, or the first error writing them
+    pub fn finish(&self) -> Result<u64, String> {
+        let mut shared = self.shared.lock().unwrap();
+        let SharedWriter { writer, error } = &mut *shared;
+        if let Some(error) = error.take().or_else(|| writer.writer.flush().err()) {
+            return Err(format!("Couldn't write the miss trace: {error}"));
+        }
+        Ok(writer.records())
+    }
+}
+
+impl<W: Write> AccessObserver for MissTrace<W> {
+    fn observe(&mut self, access: CacheAccess) {
+        if access.cache != self.cache || access.hit {
+            return;
+        }
+        let mut shared = self.shared.lock().unwrap();
+        if shared.error.is_none() {
+            let record = Access { pc: 0, address: access.line_address, size: self.line_size, kind: AccessKind::Read };
+            if let Err(error) = shared.writer.write(record) {
+                shared.error = Some(error);
+            }
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::CacheConfig;
+use crate::prelude::*;
+use crate::simulator::{Access, CacheResult, LayeredCacheResult, Simulator, LINE_SIZE};
+
+/// The size of the pages whose colours partition a cache
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The first bit of the address space id in a tagged address, above the 48 bits of a virtual address
+/// on x86_64 and aarch64
+const ASID_SHIFT: u32 = 48;
+
+/// The most processes whose address spaces can be tagged, one for each address space id which fits
+/// above the 48th bit
+pub const MAX_TAGGED_PROCESSES: usize = 1 << (64 - ASID_SHIFT);
+
+/// How the records of the traces of several processes are interleaved
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Interleaving {
+    /// Each process makes `quantum` accesses in turn, skipping processes whose traces have ended
+    RoundRobin { quantum: u64 },
+    /// The records of each trace are spread evenly over the run, as if each trace took the same
+    /// time, so traces of different lengths end together. Traces have no timestamps, so each
+    /// record's position within its trace stands in for one
+    Proportional,
+}
+
+/// How the processes are kept from reading each other's lines
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AddressSpaces {
+    /// The address space id of each process, the position of its trace, is included in the tag of
+    /// each of its addresses, above the 48th bit, so processes compete for every set of every cache.
+    /// Every access must be within the first 48 bits of the address space
+    Tagged,
+    /// Each process is given its own share of the page colours of a cache, as an operating system
+    /// partitions a shared cache by choosing the physical pages of each process, so processes only
+    /// compete for the caches above it
+    Partitioned {
+        /// The number of colours, the pages spanned by the sets of the cache
+        colours: u64,
+    },
+}
+
+impl AddressSpaces {
+    /// Partitions a cache between the processes by page colour
+    ///
+    /// returns: Result<AddressSpaces, String>, an error if the sets of the cache span less than a
+    /// page, so it has a single colour
+    pub fn partitioning(cache: &CacheConfig) -> Result<Self, String> {
+        let span = cache.size / cache.kind.ways(cache.size / cache.line_size);
+        if span < 2 * PAGE_SIZE {
+            return Err(format!("Cache {} can't be partitioned by page colour, its sets span {span} bytes, less than two {PAGE_SIZE}-byte pages", cache.name));
+        }
+        Ok(AddressSpaces::Partitioned { colours: span / PAGE_SIZE })
+    }
+}
+
+/// Merges the traces of several processes into a single stream of accesses, each given with the
+/// process making it, in the address space of that process
+///
+/// Processes are numbered by the position of their traces, which is also their address space id.
+pub struct ProcessTraces<'a> {
+    traces: Vec<&'a [u8]>,
+    // The number of records of each trace which have been read
+    positions: Vec<u64>,
+    interleaving: Interleaving,
+    address_spaces: AddressSpaces,
+    // The process whose turn it is, and the records left in its turn, when interleaving round robin
+    turn: usize,
+    left_in_turn: u64,
+}
+
+impl<'a> ProcessTraces<'a> {
+    /// Creates the merge of the traces of several processes
+    ///
+    /// # Arguments
+    ///
+    /// * `traces`: The trace of each process, in the format of the specification
+    /// * `interleaving`: The order the records of the traces are merged in
+    /// * `address_spaces`: How the address spaces of the processes are kept apart
+    ///
+    /// returns: Result<ProcessTraces, String>, an error if a trace ends part way through a record,
+    /// there are too many processes to keep apart, or an access of a tagged address space reaches
+    /// the bits of its address space id
+    pub fn new(traces: Vec<&'a [u8]>, interleaving: Interleaving, address_spaces: AddressSpaces) -> Result<Self, String> {
+        if traces.is_empty() {
+            return Err("At least one process is needed".to_string());
+        }
+        if let Some(process) = traces.iter().position(|trace| !trace.len().is_multiple_of(LINE_SIZE)) {
+            return Err(format!("The trace of process {process} ends part way through a record"));
+        }
+        if interleaving == (Interleaving::RoundRobin { quantum: 0 }) {
+            return Err("Each process must make at least one access in its turn".to_string());
+        }
+        match address_spaces {
+            AddressSpaces::Tagged if traces.len() > MAX_TAGGED_PROCESSES => {
+                return Err(format!("At most {MAX_TAGGED_PROCESSES} processes can be given address space ids, not {}", traces.len()));
+            }
+            AddressSpaces::Tagged => {
+                // The id would otherwise be mixed into the address, aliasing another process's lines
+                for (process, trace) in traces.iter().enumerate() {
+                    let beyond = trace.chunks_exact(LINE_SIZE)
+                        .map(|record| Access::from_record(record.try_into().unwrap()))
+                        .position(|access| access.address.saturating_add(access.size as u64) > 1 << ASID_SHIFT);
+                    if let Some(record) = beyond {
+                        return Err(format!("Record {record} of the trace of process {process} reaches beyond the {ASID_SHIFT} bits of address which tagged address spaces leave below the address space id, partition a cache instead"));
+                    }
+                }
+            }
+            AddressSpaces::Partitioned { colours } if colours < traces.len() as u64 => {
+                return Err(format!("The partitioned cache has {colours} page colours, too few to give each of the {} processes its own", traces.len()));
+            }
+            _ => {}
+        }
+        let quantum = match interleaving {
+            Interleaving::RoundRobin { quantum } => quantum,
+            Interleaving::Proportional => 0,
+        };
+        Ok(Self { positions: vec![0; traces.len()], traces, interleaving, address_spaces, turn: 0, left_in_turn: quantum })
+    }
+
+    /// Gets the number of processes
+    pub fn processes(&self) -> usize {
+        self.traces.len()
+    }
+
+    /// Gets the address an address of a process is moved to in the shared address space
+    pub fn translate(&self, process: usize, address: u64) -> u64 {
+        match self.address_spaces {
+            AddressSpaces::Tagged => address ^ ((process as u64) << ASID_SHIFT),
+            AddressSpaces::Partitioned { colours } => {
+                // Each process has the colours [process * share, (process + 1) * share), and the pages
+                // of each colour are packed in order, so pages of a process stay distinct
+                let share = colours / self.traces.len() as u64;
+                let (page, offset) = (address / PAGE_SIZE, address % PAGE_SIZE);
+                let page = (page / share).wrapping_mul(colours).wrapping_add(process as u64 * share + page % share);
+                page.wrapping_mul(PAGE_SIZE) | offset
+            }
+        }
+    }
+
+    /// Whether every record of a process has been read
+    fn finished(&self, process: usize) -> bool {
+        self.positions[process] * LINE_SIZE as u64 == self.traces[process].len() as u64
+    }
+
+    /// Chooses the process making the next access, if any are left
+    fn next_process(&mut self) -> Option<usize> {
+        match self.interleaving {
+            Interleaving::RoundRobin { quantum } => {
+                for _ in 0..=self.traces.len() {
+                    if self.left_in_turn > 0 && !self.finished(self.turn) {
+                        self.left_in_turn -= 1;
+                        return Some(self.turn);
+                    }
+                    self.turn = (self.turn + 1) % self.traces.len();
+                    self.left_in_turn = quantum;
+                }
+                None
+            }
+            Interleaving::Proportional => {
+                // The next record of each trace is at the fraction position / records of the run
+                let records = |process: usize| (self.traces[process].len() / LINE_SIZE) as u128;
+                (0..self.traces.len())
+                    .filter(|&process| !self.finished(process))
+                    .min_by(|&a, &b| (self.positions[a] as u128 * records(b)).cmp(&(self.positions[b] as u128 * records(a))))
+            }
+        }
+    }
+}
+
+impl Iterator for ProcessTraces<'_> {
+    type Item = (usize, Access);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let process = self.next_process()?;
+        let start = self.positions[process] as usize * LINE_SIZE;
+        self.positions[process] += 1;
+        let access = Access::from_record(self.traces[process][start..start + LINE_SIZE].try_into().unwrap());
+        Some((process, Access { address: self.translate(process, access.address), ..access }))
+    }
+}
+
+impl Simulator {
+    /// Simulates the merged traces of several processes, each running on a core of its own where
+    /// there are enough, so processes interfere in the caches they share
+    ///
+    /// # Arguments
+    ///
+    /// * `processes`: The merged traces
+    /// * `cores`: The cores the processes run on, process `i` running on core `cores[i % cores.len()]`
+    ///
+    /// returns: Result<Vec<LayeredCacheResult>, String>, the counts of each cache, and the main
+    /// memory accesses, caused by the accesses of each process. Those of the whole simulation are
+    /// in [get_result](Self::get_result) as usual
+    pub fn simulate_processes(&mut self, processes: ProcessTraces, cores: &[u32]) -> Result<Vec<LayeredCacheResult>, String> {
+        if cores.is_empty() {
+            return Err("The processes need at least one core to run on".to_string());
+        }
+        let empty = LayeredCacheResult {
+            main_memory_accesses: 0,
+            main_memory_writes: 0,
+            caches: self.result.caches.iter().map(|cache| CacheResult { name: cache.name.clone(), ..CacheResult::default() }).collect(),
+            page_crossing_accesses: 0,
+        };
+        let mut results = vec![empty; processes.processes()];
+        let mut processes = processes.peekable();
+        while let Some(&(process, _)) = processes.peek() {
+            let warming_up = self.options.warmup > 0;
+            let start = self.result.clone();
+            // Each run of consecutive accesses by one process is simulated at once
+            let run = core::iter::from_fn(|| processes.next_if(|(next, _)| *next == process)).map(|(_, access)| access);
+            self.simulate_accesses(run, cores[process % cores.len()])?;
+            // The counts are reset once the warm up ends, so only those since count
+            if !warming_up {
+                results[process] += self.result.counts_since(&start);
+            } else if self.options.warmup == 0 {
+                results[process] += &self.result;
+            }
+        }
+        Ok(results)
+    }
+}
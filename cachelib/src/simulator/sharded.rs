@@ -0,0 +1,232 @@
+use core::time::Duration;
+use std::thread;
+use std::time::Instant;
+use crate::cache::{CacheTrait, MemoryUsage};
+use crate::config::{CacheKindConfig, InclusivityConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+use crate::simulator::{parse_address, parse_kind, parse_size, LayeredCacheResult, Simulator, ADDRESS_OFFSET, ADDRESS_UPPER, LINE_SIZE, RW_MODE, SIZE};
+
+/// Simulates a trace on several threads, by splitting the sets of every cache between them
+///
+/// A line always maps to the same set, so the address bits which select a set in every cache can
+/// choose a shard, and each shard simulates the lines with its value of those bits through caches
+/// holding only their share of the sets. The results are exactly those of [Simulator], as long as
+/// nothing moves lines between shards, so prefetchers, exclusive caches and registered policies,
+/// whose state may span sets, aren't supported.
+///
+/// Each shard reads the whole trace, skipping the lines of other shards, so parsing is shared
+/// between the threads as well as the simulation.
+pub struct ShardedSimulator {
+    shards: Vec<Simulator>,
+    // The lowest of the address bits which select the shard
+    shard_bit: u32,
+    // Whether each cache has used its policy's clock, see simulate_core
+    clock_started: Vec<bool>,
+    result: LayeredCacheResult,
+    simulation_time: Duration,
+}
+
+impl ShardedSimulator {
+    /// Creates a sharded simulator for a given configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: A validated cache configuration
+    /// * `shards`: The number of shards, each simulated on its own thread, which must be a power of
+    ///   two
+    ///
+    /// returns: Result<ShardedSimulator, String>, an error if the configuration can't be split
+    /// into that many shards
+    pub fn new(config: &LayeredCacheConfig, shards: usize) -> Result<Self, String> {
+        if !shards.is_power_of_two() {
+            return Err(format!("The number of shards must be a power of two, not {shards}"));
+        }
+        let shard_bits = shards.trailing_zeros();
+        // The shard bits must be above the offset within a line in every cache
+        let shard_bit = config.caches.iter().map(|cache| cache.line_size.trailing_zeros()).max().unwrap_or(0);
+        if config.context_switches.is_some() {
+            return Err("Context switches aren't supported when sharding, as each shard only sees part of the trace".to_string());
+        }
+        if config.page_size.is_some() {
+            return Err("Page crossing accesses aren't counted when sharding, as each shard only sees the lines of the trace".to_string());
+        }
+        for cache in &config.caches {
+            if cache.prefetcher.is_some() {
+                return Err(format!("Cache {} has a prefetcher, which can fetch lines from other shards", cache.name));
+            }
+            if cache.inclusivity == InclusivityConfig::Exclusive {
+                return Err(format!("Cache {} is exclusive, which isn't supported when sharding", cache.name));
+            }
+            if cache.writeback_buffer.is_some() {
+                return Err(format!("Cache {} has a writeback buffer, which drains in the order of the whole trace rather than of each shard", cache.name));
+            }
+            if cache.instructions {
+                return Err(format!("Cache {} is an instruction cache, but shards read every record as data", cache.name));
+            }
+            if cache.slices.is_some() {
+                return Err(format!("Cache {} is sliced, and its slice hash reads the address bits shards are split on", cache.name));
+            }
+            if cache.shadow.is_some() {
+                return Err(format!("Cache {} has a shadow, whose sets may not split between shards as the cache's do", cache.name));
+            }
+            let lines = cache.size / cache.line_size;
+            let ways = cache.kind.ways(lines);
+            if ways > 1 {
+                if let ReplacementPolicyConfig::Registered(name) = &cache.replacement_policy {
+                    return Err(format!("Cache {} uses the registered policy {name}, which may share state between sets", cache.name));
+                }
+            }
+            let set_bits = (lines / ways).trailing_zeros();
+            let line_bits = cache.line_size.trailing_zeros();
+            if shard_bit + shard_bits > line_bits + set_bits {
+                return Err(if cache.kind == CacheKindConfig::Full {
+                    format!("Cache {} is fully associative, so can't be split into shards", cache.name)
+                } else {
+                    format!("Cache {} has {} sets of {} byte lines, too few to split into {shards} shards", cache.name, lines / ways, cache.line_size)
+                });
+            }
+        }
+        let mut shard_config = config.clone();
+        for cache in &mut shard_config.caches {
+            cache.size /= shards as u64;
+        }
+        let shards: Vec<Simulator> = (0..shards).map(|_| Simulator::new(&shard_config)).collect();
+        let result = LayeredCacheResult {
+            main_memory_accesses: 0,
+            main_memory_writes: 0,
+            caches: shards[0].result.caches.clone(),
+            page_crossing_accesses: 0,
+        };
+        Ok(Self {
+            clock_started: vec![false; config.caches.len()],
+            shards,
+            shard_bit,
+            result,
+            simulation_time: Duration::new(0, 0),
+        })
+    }
+
+    /// Simulates a trace as accesses from the first core, see [Simulator::simulate]
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    ///
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<&LayeredCacheResult, String> {
+        let first_core = self.shards[0].hierarchy.topology().cores().next().unwrap();
+        self.simulate_core(bytes, first_core)
+    }
+
+    /// Simulates a trace as accesses from the given core, see [Simulator::simulate_core]
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>
+    pub fn simulate_core(&mut self, bytes: &[u8], core: u32) -> Result<&LayeredCacheResult, String> {
+        assert_eq!(bytes.len() % LINE_SIZE, 0);
+        let path = self.shards[0].hierarchy.topology().path(core).ok_or(format!("The configuration has no caches for core {core}"))?.to_vec();
+        // LRU gives the first line a cache uses the same time as empty lines, so the shard making
+        // a cache's first access must be the only one whose clock starts there. The first line of
+        // the trace misses every cache on the path which hasn't been used yet, so makes the first
+        // access to each of them
+        if let Some(record) = bytes.get(..LINE_SIZE) {
+            let address = parse_address((&record[ADDRESS_OFFSET..ADDRESS_UPPER]).try_into().unwrap());
+            let owner = self.shard_of(address);
+            for &level in &path {
+                if !self.clock_started[level] {
+                    self.clock_started[level] = true;
+                    for (_, shard) in self.shards.iter_mut().enumerate().filter(|(index, _)| *index != owner) {
+                        shard.hierarchy.cache_mut(level).advance_policy_clock();
+                    }
+                }
+            }
+        }
+        let start = Instant::now();
+        let (shard_bit, shard_mask) = (self.shard_bit, self.shards.len() as u64 - 1);
+        thread::scope(|scope| {
+            for (index, shard) in self.shards.iter_mut().enumerate() {
+                let path = &path;
+                scope.spawn(move || shard.simulate_shard(bytes, index as u64, shard_bit, shard_mask, path));
+            }
+        });
+        self.simulation_time += start.elapsed();
+        self.merge_results();
+        Ok(&self.result)
+    }
+
+    fn shard_of(&self, address: u64) -> usize {
+        ((address >> self.shard_bit) & (self.shards.len() as u64 - 1)) as usize
+    }
+
+    /// Sums the results of the shards
+    fn merge_results(&mut self) {
+        self.result = self.shards[1..].iter().fold(self.shards[0].result.clone(), |total, shard| total + &shard.result);
+    }
+
+    /// Gets the results of all the shards so far
+    pub fn get_result(&self) -> &LayeredCacheResult {
+        &self.result
+    }
+
+    /// Takes the results, discarding the simulator
+    pub fn into_result(self) -> LayeredCacheResult {
+        self.result
+    }
+
+    /// Gets the time spent simulating, from starting the threads until the last finished
+    pub fn get_execution_time(&self) -> &Duration {
+        &self.simulation_time
+    }
+
+    /// Gets the number of empty lines in each cache, across all the shards
+    pub fn get_uninitialised_line_counts(&self) -> Vec<u64> {
+        let mut counts = vec![0; self.result.caches.len()];
+        for shard in &self.shards {
+            for (count, shard_count) in counts.iter_mut().zip(shard.get_uninitialised_line_counts()) {
+                *count += shard_count;
+            }
+        }
+        counts
+    }
+
+    /// Gets the bytes of host memory held by each cache, across all the shards, see
+    /// [memory_usage](Simulator::memory_usage)
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        let mut usage = vec![MemoryUsage::default(); self.result.caches.len()];
+        for shard in &self.shards {
+            for (usage, shard_usage) in usage.iter_mut().zip(shard.memory_usage()) {
+                usage.tags += shard_usage.tags;
+                usage.policy += shard_usage.policy;
+            }
+        }
+        usage
+    }
+}
+
+impl Simulator {
+    /// Simulates the lines of a trace belonging to one shard of a [ShardedSimulator]
+    ///
+    /// The shard bits are removed from each line's address, so the set bits which remain index the
+    /// shard's smaller caches, and the tag bits are unchanged.
+    fn simulate_shard(&mut self, bytes: &[u8], shard: u64, shard_bit: u32, shard_mask: u64, path: &[usize]) {
+        let geometry = self.geometry[path[0]];
+        let shard_bits = shard_mask.count_ones();
+        let low_mask = (1 << shard_bit) - 1;
+        for record in bytes.chunks_exact(LINE_SIZE) {
+            let address = parse_address((&record[ADDRESS_OFFSET..ADDRESS_UPPER]).try_into().unwrap());
+            let size = parse_size((&record[SIZE..LINE_SIZE - 1]).try_into().unwrap());
+            let (mut line, count) = geometry.lines(address, size);
+            for _ in 0..count {
+                if (line >> shard_bit) & shard_mask == shard {
+                    let compacted = (line & low_mask) | ((line >> (shard_bit + shard_bits)) << shard_bit);
+                    self.read(compacted, 1, 0, parse_kind(record[RW_MODE]), path);
+                }
+                line = line.wrapping_add(geometry.line_size);
+            }
+        }
+        self.update_main_memory_accesses();
+    }
+}
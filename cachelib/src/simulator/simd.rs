@@ -0,0 +1,36 @@
+// Each digit's value is its low nibble, plus 9 if it's a letter, which is any byte above '@' in a
+// well formed field. Pairs of digits are then joined into bytes in 16-bit lanes, narrowed to 8
+// bytes, and swapped to big endian order, as the first digit is the most significant.
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+pub(super) fn parse_address(buf: &[u8; 16]) -> u64 {
+    use core::arch::x86_64::*;
+    // SAFETY: SSE2 is enabled for the target, and the unaligned load reads exactly the 16 bytes of
+    // the buffer
+    unsafe {
+        let digits = _mm_loadu_si128(buf.as_ptr() as *const __m128i);
+        let letters = _mm_cmpgt_epi8(digits, _mm_set1_epi8(b'@' as i8));
+        let nibbles = _mm_add_epi8(_mm_and_si128(digits, _mm_set1_epi8(0x0f)), _mm_and_si128(letters, _mm_set1_epi8(9)));
+        // Each lane holds a pair of digits, the more significant in its low byte
+        let pairs = _mm_or_si128(_mm_slli_epi16(nibbles, 4), _mm_srli_epi16(nibbles, 8));
+        let bytes = _mm_packus_epi16(_mm_and_si128(pairs, _mm_set1_epi16(0xff)), _mm_setzero_si128());
+        (_mm_cvtsi128_si64(bytes) as u64).swap_bytes()
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(super) fn parse_address(buf: &[u8; 16]) -> u64 {
+    use core::arch::aarch64::*;
+    // SAFETY: NEON is part of the aarch64 baseline, and the load reads exactly the 16 bytes of the
+    // buffer
+    unsafe {
+        let digits = vld1q_u8(buf.as_ptr());
+        let letters = vcgtq_u8(digits, vdupq_n_u8(b'@'));
+        let nibbles = vaddq_u8(vandq_u8(digits, vdupq_n_u8(0x0f)), vandq_u8(letters, vdupq_n_u8(9)));
+        // Each lane holds a pair of digits, the more significant in its low byte
+        let lanes = vreinterpretq_u16_u8(nibbles);
+        let pairs = vorrq_u16(vshlq_n_u16::<4>(lanes), vshrq_n_u16::<8>(lanes));
+        let bytes = vmovn_u16(pairs);
+        vget_lane_u64::<0>(vreinterpret_u64_u8(bytes)).swap_bytes()
+    }
+}
@@ -0,0 +1,253 @@
+use core::time::Duration;
+use std::thread;
+use std::time::Instant;
+use crate::cache::MemoryUsage;
+use crate::config::{InclusivityConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+use crate::simulator::{CacheContents, LayeredCacheResult, Simulator, LINE_SIZE};
+use crate::topology::Topology;
+
+/// Simulates a trace on several threads by splitting it into chunks, each simulated from empty
+/// caches after warming them up on the records before it, then checking where the chunks meet
+///
+/// Each chunk but the first speculates that its warm up leaves the caches as the chunks before it
+/// did. Once every chunk is simulated, the state each chunk reached at the end is reconciled with
+/// the state the next chunk started counting from, and the sets which differ are reported in a
+/// [Reconciliation]. The results are exactly those of [Simulator] when no set differs.
+///
+/// Only caches whose state converges quickly are supported, direct mapped and round robin caches,
+/// without prefetchers. A direct mapped set holds the last line used in it, so it agrees with the
+/// chunk before as soon as the warm up uses it, and a window of a few accesses per set is usually
+/// enough for the results to be exact. Round robin sets agree once the warm up has missed in them
+/// as many times as they have ways, and until then may never agree.
+pub struct SpeculativeSimulator {
+    config: LayeredCacheConfig,
+    topology: Topology,
+    chunks: usize,
+    overlap: u64,
+    result: LayeredCacheResult,
+    // The results of simulating nothing, which the chunks' results are added to
+    empty: LayeredCacheResult,
+    reconciliation: Reconciliation,
+    uninitialised_lines: Vec<u64>,
+    memory_usage: Vec<MemoryUsage>,
+    simulation_time: Duration,
+}
+
+/// How the chunks of a [SpeculativeSimulator] agreed where they met
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// For each boundary between two chunks, in order, the number of sets of each cache, in the
+    /// order of the configuration, whose lines differed between the end of the first chunk and the
+    /// start of the second
+    pub divergent_sets: Vec<Vec<u64>>,
+    /// The most the hits and the misses of each cache, in the order of the configuration, can each
+    /// differ from those of a serial simulation, followed by the most the main memory accesses can
+    /// differ. Only known when every cache is direct mapped and neither inclusive nor exclusive
+    ///
+    /// A direct mapped set which differed agrees again after its next access, changing the counts
+    /// of its cache by at most one, but that access may or may not reach the next level, which
+    /// can then differ in the same way, so each set which differed `k` levels above a cache
+    /// changes its counts by at most 2<sup>k</sup>. Main memory accesses differ by at most as much
+    /// as the counts of the last level.
+    pub error_bounds: Option<Vec<u64>>,
+}
+
+impl Reconciliation {
+    /// Whether every chunk started from the state the chunk before ended in, so the results are
+    /// exactly those of a serial simulation
+    pub fn is_exact(&self) -> bool {
+        self.divergent_sets.iter().flatten().all(|&sets| sets == 0)
+    }
+}
+
+/// The result of simulating one chunk
+struct Chunk {
+    result: LayeredCacheResult,
+    // The state of each cache, see normalised_sets, when counting started and at the end
+    start: Vec<Vec<Vec<Option<u64>>>>,
+    end: Vec<Vec<Vec<Option<u64>>>>,
+    uninitialised_lines: Vec<u64>,
+    memory_usage: Vec<MemoryUsage>,
+}
+
+impl SpeculativeSimulator {
+    /// Creates a speculative simulator for a given configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: A validated cache configuration
+    /// * `chunks`: The number of chunks, each simulated on its own thread
+    /// * `overlap`: The number of records before each chunk which warm up its caches
+    ///
+    /// returns: Result<SpeculativeSimulator, String>, an error if a cache's state can't be
+    /// reconciled
+    pub fn new(config: &LayeredCacheConfig, chunks: usize, overlap: u64) -> Result<Self, String> {
+        if chunks == 0 {
+            return Err("A speculative simulation needs at least 1 chunk".to_string());
+        }
+        if config.context_switches.is_some() {
+            return Err("Context switches aren't supported by speculative simulation, as a chunk doesn't know when the switches before it were".to_string());
+        }
+        if config.simulates_writes() {
+            return Err("Writes aren't simulated by speculative simulation, as the dirty lines of each cache aren't reconciled between chunks".to_string());
+        }
+        for cache in &config.caches {
+            if cache.prefetcher.is_some() {
+                return Err(format!("Cache {} has a prefetcher, whose state isn't reconciled between chunks", cache.name));
+            }
+            if cache.shadow.is_some() {
+                return Err(format!("Cache {} has a shadow, whose state isn't reconciled between chunks", cache.name));
+            }
+            let ways = cache.kind.ways(cache.size / cache.line_size);
+            if ways > 1 && cache.replacement_policy != ReplacementPolicyConfig::RoundRobin {
+                return Err(format!("Cache {} uses {}, but speculative simulation only supports direct mapped and round robin caches, whose state converges quickly", cache.name, cache.replacement_policy.name()));
+            }
+        }
+        let simulator = Simulator::new(config);
+        Ok(Self {
+            config: config.clone(),
+            topology: simulator.topology().clone(),
+            chunks,
+            overlap,
+            uninitialised_lines: simulator.get_uninitialised_line_counts(),
+            memory_usage: simulator.memory_usage(),
+            empty: simulator.get_result().clone(),
+            result: simulator.into_result(),
+            reconciliation: Reconciliation::default(),
+            simulation_time: Duration::new(0, 0),
+        })
+    }
+
+    /// Simulates a trace as accesses from the first core, see [Simulator::simulate]
+    pub fn simulate(&mut self, bytes: &[u8]) -> Result<&LayeredCacheResult, String> {
+        let first_core = self.topology.cores().next().unwrap();
+        self.simulate_core(bytes, first_core)
+    }
+
+    /// Simulates a trace from empty caches as accesses from the given core, replacing the results
+    /// of any earlier trace
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The input byte array
+    /// * `core`: The core making the accesses, as used in the `shared_by` lists of the configuration
+    ///
+    /// returns: Result<&LayeredCacheResult, String>, an error if the trace ends part way through a
+    /// record, or the core has no caches
+    pub fn simulate_core(&mut self, bytes: &[u8], core: u32) -> Result<&LayeredCacheResult, String> {
+        if !bytes.len().is_multiple_of(LINE_SIZE) {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        let path = self.topology.path(core).ok_or(format!("The configuration has no caches for core {core}"))?.to_vec();
+        let records = (bytes.len() / LINE_SIZE) as u64;
+        let chunk_records = records.div_ceil(self.chunks as u64).max(1);
+        let start = Instant::now();
+        let chunks = thread::scope(|scope| {
+            let threads: Vec<_> = (0..records.div_ceil(chunk_records)).map(|chunk| {
+                let first = chunk * chunk_records;
+                let (warm, end) = (first.saturating_sub(self.overlap), (first + chunk_records).min(records));
+                let config = &self.config;
+                scope.spawn(move || simulate_chunk(config, &bytes[warm as usize * LINE_SIZE..end as usize * LINE_SIZE], first - warm, core))
+            }).collect();
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect::<Result<Vec<Chunk>, String>>()
+        })?;
+        self.simulation_time = start.elapsed();
+        self.reconciliation = self.reconcile(&chunks, &path);
+        if let Some(last) = chunks.last() {
+            self.uninitialised_lines = last.uninitialised_lines.clone();
+            self.memory_usage = chunks.iter().map(|chunk| &chunk.memory_usage).fold(vec![MemoryUsage::default(); self.config.caches.len()], |total, usage| {
+                total.iter().zip(usage).map(|(total, usage)| MemoryUsage { tags: total.tags + usage.tags, policy: total.policy + usage.policy }).collect()
+            });
+        }
+        self.result = chunks.iter().fold(self.empty.clone(), |total, chunk| total + &chunk.result);
+        Ok(&self.result)
+    }
+
+    /// Compares the state each chunk ended in with the state the next started counting from
+    fn reconcile(&self, chunks: &[Chunk], path: &[usize]) -> Reconciliation {
+        let divergent_sets: Vec<Vec<u64>> = chunks.windows(2).map(|pair| {
+            pair[0].end.iter().zip(&pair[1].start).map(|(end, start)| {
+                end.iter().zip(start).filter(|(end, start)| end != start).count() as u64
+            }).collect()
+        }).collect();
+        let bounded = self.config.caches.iter().all(|cache| {
+            cache.kind.ways(cache.size / cache.line_size) == 1 && cache.inclusivity == InclusivityConfig::Nine
+        });
+        let error_bounds = bounded.then(|| {
+            let mut bounds = vec![0; self.config.caches.len() + 1];
+            for boundary in &divergent_sets {
+                // The sets which differed above a level, doubling at each level passed
+                let mut carried = 0;
+                for (&cache, index) in path.iter().zip(0..) {
+                    carried = 2 * carried + boundary[cache];
+                    bounds[cache] += carried;
+                    if index == path.len() - 1 {
+                        bounds[self.config.caches.len()] += carried;
+                    }
+                }
+            }
+            bounds
+        });
+        Reconciliation { divergent_sets, error_bounds }
+    }
+
+    /// Gets how the chunks of the last trace agreed where they met
+    pub fn reconciliation(&self) -> &Reconciliation {
+        &self.reconciliation
+    }
+
+    /// Gets the results of the last trace
+    pub fn get_result(&self) -> &LayeredCacheResult {
+        &self.result
+    }
+
+    /// Takes the results, discarding the simulator
+    pub fn into_result(self) -> LayeredCacheResult {
+        self.result
+    }
+
+    /// Gets the time spent simulating the last trace, from starting the threads until the last
+    /// finished
+    pub fn get_execution_time(&self) -> &Duration {
+        &self.simulation_time
+    }
+
+    /// Gets the number of empty lines in each cache at the end of the last chunk
+    pub fn get_uninitialised_line_counts(&self) -> Vec<u64> {
+        self.uninitialised_lines.clone()
+    }
+
+    /// Gets the bytes of host memory held by each cache, across all the chunks, see
+    /// [memory_usage](Simulator::memory_usage)
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        self.memory_usage.clone()
+    }
+}
+
+/// Simulates a chunk, counting the records after the first `warmup`
+fn simulate_chunk(config: &LayeredCacheConfig, bytes: &[u8], warmup: u64, core: u32) -> Result<Chunk, String> {
+    let mut simulator = Simulator::builder(config).warmup(warmup).build()?;
+    let (warm, counted) = bytes.split_at(warmup as usize * LINE_SIZE);
+    simulator.simulate_core(warm, core)?;
+    let start = normalised_sets(&simulator.contents());
+    simulator.simulate_core(counted, core)?;
+    Ok(Chunk {
+        start,
+        end: normalised_sets(&simulator.contents()),
+        uninitialised_lines: simulator.get_uninitialised_line_counts(),
+        memory_usage: simulator.memory_usage(),
+        result: simulator.into_result(),
+    })
+}
+
+/// Gets the lines of each set of each cache in the order they'll be replaced, from the next
+/// victim, which decides every later hit and miss of a direct mapped or round robin cache
+/// regardless of which ways the lines are in
+fn normalised_sets(contents: &[CacheContents]) -> Vec<Vec<Vec<Option<u64>>>> {
+    contents.iter().map(|cache| {
+        cache.lines.chunks_exact(cache.ways as usize).map(|set| {
+            let next = set.iter().position(|line| line.policy.as_ref().is_some_and(|policy| policy["next_victim"] == true)).unwrap_or(0);
+            set[next..].iter().chain(&set[..next]).map(|line| line.address).collect()
+        }).collect()
+    }).collect()
+}
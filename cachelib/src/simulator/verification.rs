@@ -0,0 +1,69 @@
+use crate::cache::CacheLine;
+use crate::layered::{InvariantViolation, LayeredCache};
+use crate::simulator::CacheResult;
+use crate::prelude::*;
+
+/// Checks the invariants of a simulator's hierarchy every so many accesses, keeping the first
+/// which is found broken, see
+/// [verify_invariants](super::builder::SimulatorBuilder::verify_invariants)
+#[derive(Debug, Clone)]
+pub(super) struct Verification {
+    every: u64,
+    /// The accesses to lines made so far
+    accesses: u64,
+    violation: Option<Violation>,
+}
+
+/// A broken invariant, with the contents of the sets concerned when it was found
+#[derive(Debug, Clone)]
+struct Violation {
+    access: u64,
+    violation: InvariantViolation,
+    sets: Vec<Vec<CacheLine>>,
+}
+
+impl Verification {
+    pub(super) fn new(every: u64) -> Self {
+        Self { every, accesses: 0, violation: None }
+    }
+
+    /// Counts an access to a line, checking the hierarchy once every `every` accesses until an
+    /// invariant is found broken
+    pub(super) fn after_access(&mut self, hierarchy: &LayeredCache) {
+        if self.violation.is_some() {
+            return;
+        }
+        self.accesses += 1;
+        if !self.accesses.is_multiple_of(self.every) {
+            return;
+        }
+        if let Err(violation) = hierarchy.check_invariants() {
+            let sets = violation.sets.iter()
+                .map(|&(cache, set)| hierarchy.lines(cache).into_iter().filter(|line| line.set == set).collect())
+                .collect();
+            self.violation = Some(Violation { access: self.accesses, violation, sets });
+        }
+    }
+
+    /// Whether an invariant has been found broken, so the simulation should stop
+    pub(super) fn broken(&self) -> bool {
+        self.violation.is_some()
+    }
+
+    /// Gets an error describing the first broken invariant, if any, with a dump of the contents of
+    /// the sets concerned as JSON
+    ///
+    /// # Arguments
+    ///
+    /// * `caches`: The results of the caches, for their names
+    pub(super) fn violation(&self, caches: &[CacheResult]) -> Result<(), String> {
+        let Some(Violation { access, violation, sets }) = &self.violation else {
+            return Ok(());
+        };
+        let dump: Vec<serde_json::Value> = violation.sets.iter().zip(sets)
+            .map(|(&(cache, set), lines)| serde_json::json!({"cache": caches[cache].name, "set": set, "lines": lines}))
+            .collect();
+        let dump = serde_json::to_string_pretty(&dump).map_err(|e| format!("Couldn't serialise the contents of the sets {e}"))?;
+        Err(format!("An invariant was broken by access {access} to a line: {}\nThe sets concerned held:\n{dump}", violation.description))
+    }
+}
@@ -1,35 +1,2064 @@
 use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
-use memmap2::{Advice, Mmap};
-use crate::config::{LayeredCacheConfig};
-use crate::simulator::{LayeredCacheResult, Simulator};
-use crate::util::{get_configs};
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::address::Address;
+use crate::cache::{Cache, CacheTrait, HitMissCounters, LineAccess, MemoryUsage};
+use crate::config::{diff_configs, load_layered_config, CacheKindConfig, InclusivityConfig, LayeredCacheConfig, ReplacementPolicyConfig, WritePolicyConfig};
+use crate::config::dinero::{parse_din_record, parse_dinero_options};
+use crate::config::lint::lint_config;
+use crate::layered::{AccessKind, HitLevel, LayeredCache};
+use crate::config::migration::{migrate_config, CURRENT_CONFIG_VERSION};
+use crate::config::substitution::substitute_variables;
+use crate::results::{AccessCounts, SimulationOutput, SCHEMA_VERSION};
+use crate::replacement_policies::{register_policy, CompactLeastRecentlyUsed, ReplacementPolicy, LeastFrequentlyUsed, LeastRecentlyUsed, registered_policy, NoPolicy, RoundRobin, PolicyParameter, PolicyParameterKind, RegisteredPolicy};
+use crate::simulator::{parse_address, parse_address_with_lookup, Access, AccessObserver, CacheAccess, LayeredCacheResult, Simulator};
+use crate::simulator::decisions::DecisionLog;
+use crate::simulator::diff::diff_results;
+use crate::simulator::miss_trace::MissTrace;
+use crate::simulator::processes::{AddressSpaces, Interleaving, ProcessTraces};
+use crate::simulator::sharded::ShardedSimulator;
+use crate::simulator::speculative::SpeculativeSimulator;
+use crate::parallel::{LevelPipeline, Pipeline};
+use crate::topology::Topology;
+use crate::ensemble::Ensemble;
+use crate::analysis::{AssociativitySweep, LocalityAnalysis, OptimalCache, OptimalGap, PlacementRandomiser, ReorderStrategy, StrideBucket, SweepLru, TraceReorderer, WorkingSetAnalysis};
+use crate::config::arbitrary::{arbitrary_config, SplitMix64};
+use crate::util::{arbitrary_trace, get_configs, map_trace};
+use crate::util::reference::simulate_reference;
+use crate::util::snapshot::{Snapshot, SnapshotStore, Tolerance};
+use crate::util::proptest::{check_against_reference, config_strategy, trace_strategy};
 
 #[test]
 fn run_all_examples() -> Result<(), Box<dyn Error>> {
     for test in get_configs()? {
         // Get file name
         println!("Running test for {}", test.output);
-        // Get input files
-        let trace_file = File::open(test.trace)?;
-        let config_file = File::open(test.config)?;
         // Read expected output
-        let expected_output_file = File::open(test.output.clone())?;
-        let expected_output: LayeredCacheResult = serde_json::from_reader(BufReader::new(expected_output_file))?;
+        let expected_output = Snapshot::load(std::path::Path::new(&test.output))?;
         // Simulate!
-        let config: LayeredCacheConfig = serde_json::from_reader(BufReader::new(config_file))?;
+        let config = load_layered_config(&[&test.config])?;
         let mut simulator = Simulator::new(&config);
-        let mmap = unsafe {
-            let m = Mmap::map(&trace_file).map_err(|e| format!("Couldn't memory map the file: {e}"))?;
-            m.advise(Advice::Sequential).map_err(|e| format!("Failed to provide access advice to the OS, {e}"))?;
-            m
-        };
+        let mmap = map_trace(&test.trace)?;
         let result = simulator.simulate(&mmap)?;
-        assert_eq!(*result, expected_output);
+        assert_eq!(expected_output.mismatches(&Snapshot::new(result.clone()), Tolerance::default()), []);
         // Check results
         let time = simulator.get_execution_time();
         println!("Success for {}, time: {}", test.output, time.as_nanos() as f64 / 1e9);
     }
     Ok(())
 }
+
+#[test]
+fn config_fragments_merge_by_cache_name() -> Result<(), Box<dyn Error>> {
+    let directory = test_directory("config_fragments_merge_by_cache_name")?;
+    std::fs::write(directory.join("base.json"), r#"{"caches": [
+        {"name": "L1", "size": 1024, "line_size": 64, "kind": "direct"},
+        {"name": "L2", "size": 8192, "line_size": 64, "kind": "4way"}
+    ]}"#)?;
+    std::fs::write(directory.join("override.json"), r#"{"include": "base.json", "caches": [
+        {"name": "L2", "replacement_policy": "lru"},
+        {"name": "L3", "size": 65536, "line_size": 64, "kind": "full"}
+    ]}"#)?;
+    let config = load_layered_config(&[directory.join("override.json")])?;
+    let names: Vec<&str> = config.caches.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["L1", "L2", "L3"]);
+    assert_eq!(config.caches[1].size, 8192);
+    assert!(matches!(config.caches[1].replacement_policy, ReplacementPolicyConfig::LeastRecentlyUsed));
+    std::fs::remove_dir_all(&directory)?;
+    Ok(())
+}
+
+#[test]
+fn config_round_trips_through_json() -> Result<(), Box<dyn Error>> {
+    let config: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [
+        {"name": "L1", "size": 1024, "line_size": 64, "kind": "TwoWay", "replacement_policy": "LeastRecentlyUsed"}
+    ]}"#)?;
+    let round_tripped: LayeredCacheConfig = serde_json::from_str(&serde_json::to_string(&config)?)?;
+    assert_eq!(config, round_tripped);
+    Ok(())
+}
+
+/// An empty directory for a test's files, named after the test and the process, so tests running
+/// at the same time, in this process or another, don't share one
+fn test_directory(test: &str) -> Result<std::path::PathBuf, Box<dyn Error>> {
+    let directory = std::env::temp_dir().join(format!("cachelib-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory)?;
+    Ok(directory)
+}
+
+/// Builds a trace of reads in the 40 byte record format
+fn trace_of_reads(addresses: &[u64]) -> Vec<u8> {
+    addresses.iter()
+        .map(|address| format!("{:016x} {address:016x} R 001\n", 0x400000))
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn simulate_config(config: &str, trace: &[u8]) -> Result<serde_json::Value, Box<dyn Error>> {
+    let mut document: serde_json::Value = serde_json::from_str(config)?;
+    migrate_config(&mut document)?;
+    let config: LayeredCacheConfig = serde_json::from_value(document)?;
+    config.validate()?;
+    let mut simulator = Simulator::new(&config);
+    Ok(serde_json::to_value(simulator.simulate(trace)?)?)
+}
+
+#[test]
+fn average_memory_access_time_uses_the_configured_latencies() -> Result<(), Box<dyn Error>> {
+    // 0x1000 and 0x2000 share the set of L1, which hits twice and misses twice, while L2 misses both
+    let trace = trace_of_reads(&[0x1000, 0x1000, 0x1000, 0x2000]);
+    let config = |l1_latencies: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_str(&format!(r#"{{"memory_latency": 100, "caches": [
+            {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct"{l1_latencies}}},
+            {{"name": "L2", "size": 1024, "line_size": 64, "kind": "direct", "hit_latency": 10}}
+        ]}}"#))?)
+    };
+    let with_latencies = config(r#", "hit_latency": 1"#)?;
+    let mut simulator = Simulator::new(&with_latencies);
+    let result = simulator.simulate(&trace)?;
+    // L2 costs 10 + 1 * 100 cycles, and L1 1 + 0.5 * 110
+    assert_eq!(result.average_memory_access_time(&with_latencies), Some(56.0));
+    // An explicit miss penalty replaces the time of the levels below
+    assert_eq!(result.average_memory_access_time(&config(r#", "hit_latency": 1, "miss_penalty": 20"#)?), Some(11.0));
+    assert_eq!(result.average_memory_access_time(&config("")?), None);
+    Ok(())
+}
+
+#[test]
+fn unknown_config_fields_are_rejected() -> Result<(), Box<dyn Error>> {
+    let directory = test_directory("unknown_config_fields_are_rejected")?;
+    for (name, config, field) in [
+        ("cache.json", r#"{"caches": [{"name": "L1", "size": 1024, "sise": 2048}]}"#, "sise"),
+        ("top.json", r#"{"caches": [{"name": "L1", "size": 1024}], "memory_latncy": 100}"#, "memory_latncy"),
+    ] {
+        std::fs::write(directory.join(name), config)?;
+        let error = load_layered_config(&[directory.join(name)]).unwrap_err();
+        assert!(error.contains(&format!("unknown field `{field}`")), "{error}");
+    }
+    std::fs::remove_dir_all(directory)?;
+    Ok(())
+}
+
+#[test]
+fn config_diffs_match_caches_by_name() -> Result<(), Box<dyn Error>> {
+    let base: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 4096}]}"#)?;
+    // Defaults spelled out, and the caches in another order
+    let explicit: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [
+        {"name": "L2", "size": 4096, "line_size": 64, "kind": "8way", "replacement_policy": "lru"},
+        {"name": "L1", "size": 1024, "line_size": 64}
+    ]}"#)?;
+    assert_eq!(diff_configs(&base, &base), []);
+    assert_eq!(diff_configs(&base, &explicit), []);
+
+    let changed: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 8192}]}"#)?;
+    let differences = diff_configs(&base, &changed);
+    assert_eq!(differences.len(), 1);
+    assert_eq!(differences[0].path, "caches[L2].size");
+    assert_eq!((differences[0].left.clone(), differences[0].right.clone()), (Some(serde_json::json!(4096)), Some(serde_json::json!(8192))));
+
+    let added: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 4096}, {"name": "L3", "size": 65536}]}"#)?;
+    let differences = diff_configs(&base, &added);
+    assert!(!differences.is_empty());
+    assert!(differences.iter().all(|d| d.path.starts_with("caches[L3]") && d.left.is_none() && d.right.is_some()), "{differences:?}");
+    let differences = diff_configs(&added, &base);
+    assert!(!differences.is_empty());
+    assert!(differences.iter().all(|d| d.path.starts_with("caches[L3]") && d.left.is_some() && d.right.is_none()), "{differences:?}");
+    Ok(())
+}
+
+#[test]
+fn exclusive_cache_swaps_lines_with_level_above() -> Result<(), Box<dyn Error>> {
+    let trace = trace_of_reads(&[0x1000, 0x2000, 0x1000, 0x2000]);
+    let exclusive = simulate_config(r#"{"caches": [
+        {"name": "L1", "size": 64, "line_size": 64, "kind": "direct"},
+        {"name": "L2", "size": 64, "line_size": 64, "kind": "full", "inclusivity": "exclusive"}
+    ]}"#, &trace)?;
+    assert_eq!(exclusive["caches"][1]["hits"], 2);
+    assert_eq!(exclusive["main_memory_accesses"], 2);
+    Ok(())
+}
+
+#[test]
+fn inclusive_cache_back_invalidates_level_above() -> Result<(), Box<dyn Error>> {
+    let trace = trace_of_reads(&[0x1000, 0x2000, 0x1000]);
+    let config = |inclusivity: &str| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 128, "line_size": 64, "kind": "full"}},
+        {{"name": "L2", "size": 64, "line_size": 64, "kind": "direct", "inclusivity": "{inclusivity}"}}
+    ]}}"#);
+    assert_eq!(simulate_config(&config("nine"), &trace)?["caches"][0]["hits"], 1);
+    assert_eq!(simulate_config(&config("inclusive"), &trace)?["caches"][0]["hits"], 0);
+    Ok(())
+}
+
+#[test]
+fn cores_share_the_last_level_cache() -> Result<(), Box<dyn Error>> {
+    let config: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [
+        {"name": "L1-0", "size": 64, "line_size": 64, "kind": "direct", "shared_by": [0], "next": "L2"},
+        {"name": "L1-1", "size": 64, "line_size": 64, "kind": "direct", "shared_by": [1], "next": "L2"},
+        {"name": "L2", "size": 1024, "line_size": 64, "kind": "direct"}
+    ]}"#)?;
+    config.validate()?;
+    let trace = trace_of_reads(&[0x1000]);
+    let mut simulator = Simulator::new(&config);
+    simulator.simulate_core(&trace, 0)?;
+    let result = serde_json::to_value(simulator.simulate_core(&trace, 1)?)?;
+    assert_eq!(result["caches"][1]["misses"], 1);
+    assert_eq!(result["caches"][2]["hits"], 1);
+    assert_eq!(result["main_memory_accesses"], 1);
+    Ok(())
+}
+
+#[test]
+fn unversioned_configs_are_migrated_with_warnings() -> Result<(), Box<dyn Error>> {
+    let mut document = serde_json::json!({"caches": [
+        {"name": "L1", "size": 1024, "line_size": 64, "kind": "TwoWay", "replacement_policy": "lru"}
+    ]});
+    let warnings = migrate_config(&mut document)?;
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(document["caches"][0]["kind"], "2way");
+    assert_eq!(document["version"], CURRENT_CONFIG_VERSION);
+    Ok(())
+}
+
+#[test]
+fn omitted_cache_fields_take_defaults() -> Result<(), Box<dyn Error>> {
+    let current = serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [{"name": "L1", "size": "32KiB"}]});
+    let config: LayeredCacheConfig = serde_json::from_value(current)?;
+    let cache = &config.caches[0];
+    assert_eq!(cache.line_size, 64);
+    assert_eq!(cache.kind, CacheKindConfig::EightWay);
+    assert_eq!(cache.replacement_policy, ReplacementPolicyConfig::LeastRecentlyUsed);
+
+    // Older documents keep the round robin default they were written against
+    let mut unversioned = serde_json::json!({"caches": [{"name": "L1", "size": "32KiB"}]});
+    migrate_config(&mut unversioned)?;
+    let config: LayeredCacheConfig = serde_json::from_value(unversioned)?;
+    assert_eq!(config.caches[0].replacement_policy, ReplacementPolicyConfig::RoundRobin);
+    Ok(())
+}
+
+#[test]
+fn cache_geometry_is_validated() -> Result<(), Box<dyn Error>> {
+    let validate = |cache: serde_json::Value| -> Result<Result<(), String>, Box<dyn Error>> {
+        let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [cache]}))?;
+        Ok(config.validate())
+    };
+    for (cache, error) in [
+        (serde_json::json!({"name": "L1", "size": 0}), "Cache L1 has a size of 0 bytes, which must be a whole number of its 64-byte lines"),
+        (serde_json::json!({"name": "L1", "size": 1024, "line_size": 0}), "Cache L1 has a line size of 0 bytes, which must be a power of two"),
+        (serde_json::json!({"name": "L1", "size": 1536, "line_size": 48}), "Cache L1 has a line size of 48 bytes, which must be a power of two"),
+        (serde_json::json!({"name": "L1", "size": 1000}), "Cache L1 has a size of 1000 bytes, which must be a whole number of its 64-byte lines"),
+        (serde_json::json!({"name": "L1", "size": 256, "kind": "8way"}), "Cache L1 has 4 lines, too few for 8 ways"),
+        (serde_json::json!({"name": "L1", "size": 1536, "kind": "8way"}), "Cache L1 has 24 lines, which don't make a power of two number of 8-way sets"),
+        (serde_json::json!({"name": "L1", "size": 1536, "kind": "16way"}), "Cache L1 has 24 lines, which don't make a power of two number of 16-way sets"),
+        (serde_json::json!({"name": "L1", "size": 192, "kind": "direct"}), "Cache L1 has 3 lines, which don't make a power of two number of 1-way sets"),
+    ] {
+        assert_eq!(validate(cache)?.unwrap_err(), error);
+    }
+    // A fully associative cache is a single set of any number of lines
+    validate(serde_json::json!({"name": "L1", "size": 1536, "kind": "full"}))??;
+    validate(serde_json::json!({"name": "L1", "size": "32KiB", "line_size": 32, "kind": "4way"}))??;
+    Ok(())
+}
+
+#[test]
+fn policy_names_are_resolved_against_the_registry() -> Result<(), Box<dyn Error>> {
+    let config = |policy: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+            {"name": "L1", "size": 1024, "replacement_policy": policy, "policy_params": {"depth": 2}}
+        ]}))?)
+    };
+    let error = config("test-mru")?.validate().unwrap_err();
+    assert!(error.contains("Unknown replacement policy test-mru"), "{error}");
+
+    register_policy(RegisteredPolicy {
+        name: "test-mru".to_string(),
+        parameters: vec![PolicyParameter { name: "depth", description: "Unused", kind: PolicyParameterKind::Integer { min: 1, max: 4 } }],
+        factory: Arc::new(|_, _, _| Box::new(NoPolicy)),
+    })?;
+    config("test-mru")?.validate()?;
+    let error = config("test-mr")?.validate().unwrap_err();
+    assert!(error.ends_with("did you mean test-mru?"), "{error}");
+    assert!(register_policy(registered_policy("test-mru")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn registered_policies_are_simulated() -> Result<(), Box<dyn Error>> {
+    // Always replacing the first way makes the second read of 0x1000 miss, where rr would hit
+    register_policy(RegisteredPolicy {
+        name: "test-first-way".to_string(),
+        parameters: Vec::new(),
+        factory: Arc::new(|_, _, _| Box::new(NoPolicy)),
+    })?;
+    let trace = trace_of_reads(&[0x1000, 0x1200, 0x1000]);
+    for (policy, hits) in [("rr", 1), ("test-first-way", 0)] {
+        let result = simulate_config(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+            {{"name": "L1", "size": 1024, "line_size": 64, "kind": "2way", "replacement_policy": "{policy}"}}
+        ]}}"#), &trace)?;
+        assert_eq!(result["caches"][0]["hits"], hits, "{policy}");
+    }
+    Ok(())
+}
+
+#[test]
+fn lint_flags_suspicious_settings() -> Result<(), Box<dyn Error>> {
+    let mut document = serde_json::json!({"caches": [
+        {"name": "L1", "size": 4096, "line_size": 64, "kind": "direct"},
+        {"name": "L2", "size": 2048, "line_size": 64, "kind": "full"}
+    ]});
+    migrate_config(&mut document)?;
+    let config: LayeredCacheConfig = serde_json::from_value(document)?;
+    config.validate()?;
+    // The round robin pinned onto old documents isn't flagged on the direct mapped cache
+    assert_eq!(lint_config(&config), vec!["Cache L1: it is at least as large as cache L2, which it passes its misses to"]);
+    Ok(())
+}
+
+#[test]
+fn variables_are_substituted_and_evaluated() -> Result<(), Box<dyn Error>> {
+    let lookup = |name: &str| match name {
+        "L1_SIZE" => Some("32KiB".to_string()),
+        "CORE" => Some("1".to_string()),
+        _ => None,
+    };
+    let mut document = serde_json::json!({"caches": [
+        {"name": "L1-${CORE}", "size": "${L1_SIZE}", "line_size": "${LINE:-64}"},
+        {"name": "L2", "size": "(${L1_SIZE} + 32KiB) * 2", "shared_by": ["${CORE}"], "policy_params": {"counter_bits": "${CORE}+1"}},
+        // Only numeric fields become numbers, even if the whole string looks like one
+        {"name": "${CORE}", "size": "${CORE}KiB", "kind": "${CORE}-${CORE}"}
+    ]});
+    substitute_variables(&mut document, &lookup)?;
+    assert_eq!(document, serde_json::json!({"caches": [
+        {"name": "L1-1", "size": 32768, "line_size": 64},
+        {"name": "L2", "size": 131072, "shared_by": [1], "policy_params": {"counter_bits": 2}},
+        {"name": "1", "size": 1024, "kind": "1-1"}
+    ]}));
+    let error = substitute_variables(&mut serde_json::json!({"size": "${MISSING}"}), &lookup).unwrap_err();
+    assert!(error.contains("MISSING"), "{error}");
+    Ok(())
+}
+
+#[test]
+fn simulator_matches_reference_on_arbitrary_configs() -> Result<(), Box<dyn Error>> {
+    for seed in 0..200 {
+        let mut rng = SplitMix64::new(seed);
+        let config = arbitrary_config(&mut rng);
+        config.validate()?;
+        let trace = arbitrary_trace(&mut rng, 2000);
+        let mut simulator = Simulator::new(&config);
+        let result = simulator.simulate(&trace)?;
+        assert_eq!(*result, simulate_reference(&config, &trace)?, "seed {seed}, config {config:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn sharded_simulation_matches_serial() -> Result<(), Box<dyn Error>> {
+    let mut compared = 0;
+    for seed in 0..200 {
+        let mut rng = SplitMix64::new(seed);
+        let config = arbitrary_config(&mut rng);
+        let trace = arbitrary_trace(&mut rng, 2000);
+        let shards = 1 << (1 + seed % 3);
+        // Fully associative caches and caches with few sets can't be sharded
+        let Ok(mut sharded) = ShardedSimulator::new(&config, shards) else { continue };
+        let mut serial = Simulator::new(&config);
+        // In two parts, to check the caches carry over between calls
+        let (first, second) = trace.split_at(1000 * 40);
+        serial.simulate(first)?;
+        sharded.simulate(first)?;
+        assert_eq!(serial.simulate(second)?, sharded.simulate(second)?, "seed {seed}, config {config:?}");
+        compared += 1;
+    }
+    assert!(compared > 20, "Only {compared} configurations could be sharded");
+    Ok(())
+}
+
+#[test]
+fn lazy_sets_match_sets_allocated_upfront() -> Result<(), Box<dyn Error>> {
+    for seed in 0..100 {
+        let mut rng = SplitMix64::new(seed);
+        let config = arbitrary_config(&mut rng);
+        let mut lazy_config = config.clone();
+        for cache in &mut lazy_config.caches {
+            cache.lazy_sets = true;
+        }
+        let trace = arbitrary_trace(&mut rng, 2000);
+        let mut eager = Simulator::new(&config);
+        let mut lazy = Simulator::new(&lazy_config);
+        assert_eq!(eager.simulate(&trace)?, lazy.simulate(&trace)?, "seed {seed}, config {config:?}");
+        assert_eq!(eager.contents(), lazy.contents(), "seed {seed}");
+        assert_eq!(eager.get_uninitialised_line_counts(), lazy.get_uninitialised_line_counts(), "seed {seed}");
+        // Shards advance the clocks of the sets they don't start with
+        if let Ok(mut sharded) = ShardedSimulator::new(&lazy_config, 2) {
+            assert_eq!(eager.get_result(), sharded.simulate(&trace)?, "seed {seed}, sharded");
+        }
+    }
+    // Only the sets used are allocated, so a 1GiB cache costs little more than its footprint
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": "1GiB", "line_size": 64, "kind": "8way", "lazy_sets": true},
+    ]}))?;
+    let mut simulator = Simulator::new(&config);
+    assert_eq!(simulator.memory_usage()[0].total(), 0);
+    simulator.simulate(&trace_of_reads(&(0..100).map(|line| line * 64).collect::<Vec<_>>()))?;
+    assert!(simulator.memory_usage()[0].total() < 100 * 1024);
+    assert_eq!(simulator.get_uninitialised_line_counts(), [(1 << 24) - 100]);
+    Ok(())
+}
+
+#[test]
+fn speculative_simulation_is_within_its_error_bounds() -> Result<(), Box<dyn Error>> {
+    let direct: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 1024, "line_size": 64, "kind": "direct"},
+        {"name": "L2", "size": 8192, "line_size": 64, "kind": "direct"},
+    ]}))?;
+    let mut rng = SplitMix64::new(2452);
+    let trace = arbitrary_trace(&mut rng, 20_000);
+    let serial = Simulator::new(&direct).simulate(&trace)?.clone();
+    for overlap in [0, 10, 5000] {
+        let mut speculative = SpeculativeSimulator::new(&direct, 4, overlap)?;
+        let result = speculative.simulate(&trace)?.clone();
+        let reconciliation = speculative.reconciliation();
+        assert_eq!(reconciliation.divergent_sets.len(), 3);
+        let bounds = reconciliation.error_bounds.as_ref().unwrap();
+        for ((serial, speculative), bound) in serial.caches().iter().zip(result.caches()).zip(bounds) {
+            assert!(serial.hits().abs_diff(speculative.hits()) <= *bound, "overlap {overlap}");
+            assert!(serial.misses().abs_diff(speculative.misses()) <= *bound, "overlap {overlap}");
+        }
+        assert!(serial.main_memory_accesses().abs_diff(result.main_memory_accesses()) <= bounds[2]);
+        // Enough accesses to use every set converge, and the empty chunk starts never do
+        assert_eq!(reconciliation.is_exact(), overlap == 5000, "overlap {overlap}");
+        if reconciliation.is_exact() {
+            assert_eq!(result, serial);
+            assert_eq!(bounds, &[0, 0, 0]);
+        }
+    }
+    // Round robin sets converge once the warm up has filled them, but have no bound until then
+    let round_robin: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 1024, "line_size": 64, "kind": "4way", "replacement_policy": "rr"},
+    ]}))?;
+    let mut speculative = SpeculativeSimulator::new(&round_robin, 3, 5000)?;
+    assert_eq!(speculative.simulate(&trace)?, Simulator::new(&round_robin).simulate(&trace)?);
+    assert!(speculative.reconciliation().is_exact() && speculative.reconciliation().error_bounds.is_none());
+    assert_eq!(speculative.simulate(&trace[..trace.len() - 1]).unwrap_err(), "The trace ends part way through a record");
+    let lru: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 1024, "line_size": 64, "kind": "4way", "replacement_policy": "lru"},
+    ]}))?;
+    assert!(SpeculativeSimulator::new(&lru, 2, 100).is_err());
+    Ok(())
+}
+
+#[test]
+fn prefetchers_cut_the_misses_of_regular_traces() -> Result<(), Box<dyn Error>> {
+    let config = |prefetcher: &str| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 4096, "line_size": 64, "kind": "8way"{prefetcher}}}
+    ]}}"#);
+    let misses = |prefetcher: &str, trace: &[u8]| -> Result<u64, Box<dyn Error>> {
+        Ok(simulate_config(&config(prefetcher), trace)?["caches"][0]["misses"].as_u64().unwrap())
+    };
+    let next_line = r#", "prefetcher": {"kind": "next-line", "degree": 2}"#;
+    let stride = r#", "prefetcher": {"kind": "stride", "degree": 2}"#;
+    // Every line in turn, then every fourth line, all read by the same instruction
+    let sequential = trace_of_reads(&(0..256).map(|line| line * 64).collect::<Vec<_>>());
+    let strided = trace_of_reads(&(0..256).map(|line| line * 256).collect::<Vec<_>>());
+    assert_eq!(misses("", &sequential)?, 256);
+    assert!(misses(next_line, &sequential)? < 128);
+    assert_eq!(misses("", &strided)?, 256);
+    assert_eq!(misses(next_line, &strided)?, 256);
+    assert!(misses(stride, &strided)? < 128);
+    Ok(())
+}
+
+#[test]
+fn invalid_prefetchers_are_rejected() {
+    let config = |prefetcher: &str| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 4096, "line_size": 64, "kind": "8way", "prefetcher": {prefetcher}}}
+    ]}}"#);
+    for (prefetcher, error) in [
+        (r#"{"kind": "next-line", "degree": 0}"#, "The prefetcher for cache L1 has a degree of 0, it would never prefetch"),
+        (r#"{"kind": "stride", "distance": 0}"#, "The prefetcher for cache L1 has a distance of 0, it would only prefetch lines already being accessed"),
+        (r#"{"kind": "stride", "table_size": 48}"#, "The stride prefetcher for cache L1 has a table_size of 48, which must be a power of two"),
+        (r#"{"kind": "next-line", "table_size": 64}"#, "The next-line prefetcher for cache L1 has a table_size, which only applies to stride prefetchers"),
+    ] {
+        let config: LayeredCacheConfig = serde_json::from_str(&config(prefetcher)).unwrap();
+        assert_eq!(config.validate().unwrap_err(), error);
+    }
+    assert!(serde_json::from_str::<LayeredCacheConfig>(&config(r#"{"kind": "markov"}"#)).is_err());
+}
+
+#[test]
+fn pipelined_simulation_matches_serial() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2425);
+    let mut config = arbitrary_config(&mut rng);
+    // Stride prefetchers use the program counter, which the parsers have to pass on
+    config.caches[0].prefetcher = Some(serde_json::from_str(r#"{"kind": "stride", "degree": 2}"#)?);
+    let trace = arbitrary_trace(&mut rng, 5000);
+    let mut serial = Simulator::new(&config);
+    serial.simulate(&trace)?;
+    assert!(serial.get_result().caches()[0].prefetches() > 0);
+    for threads in [2, 4] {
+        let mut pipelined = Simulator::new(&config);
+        let core = pipelined.topology().cores().next().unwrap();
+        // An uneven batch size leaves a short batch at the end
+        Pipeline::new(threads)?.with_batch_records(333).simulate_core(&mut pipelined, &trace, core)?;
+        assert_eq!(serial.get_result(), pipelined.get_result(), "{threads} threads");
+    }
+    Ok(())
+}
+
+#[test]
+fn level_pipelines_match_serial_simulation() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2454);
+    for _ in 0..20 {
+        let config = arbitrary_config(&mut rng);
+        let trace = arbitrary_trace(&mut rng, 3000);
+        let mut serial = Simulator::new(&config);
+        serial.simulate(&trace)?;
+        let mut pipelined = Simulator::new(&config);
+        let core = pipelined.topology().cores().next().unwrap();
+        // An uneven batch size leaves a short batch at the end of each run of the threads
+        LevelPipeline::new().with_batch_records(7).simulate_core(&mut pipelined, &trace, core)?;
+        assert_eq!(serial.get_result(), pipelined.get_result(), "{config:?}");
+        assert_eq!(serial.contents(), pipelined.contents(), "{config:?}");
+
+        // The warm up and intervals end part way through the threads' runs
+        let builder = || Simulator::builder(&config).warmup(1001).interval_statistics(250);
+        let mut serial = builder().build()?;
+        serial.simulate(&trace)?;
+        let mut pipelined = builder().build()?;
+        LevelPipeline::new().with_batch_records(7).simulate_core(&mut pipelined, &trace, core)?;
+        assert_eq!(serial.get_result(), pipelined.get_result(), "{config:?}");
+        assert_eq!(serial.interval_results(), pipelined.interval_results(), "{config:?}");
+    }
+    let mut config = arbitrary_config(&mut rng);
+    config.caches[0].prefetcher = Some(serde_json::from_str(r#"{"kind": "stride", "degree": 2}"#)?);
+    let mut simulator = Simulator::new(&config);
+    let core = simulator.topology().cores().next().unwrap();
+    assert!(LevelPipeline::new().simulate_core(&mut simulator, &arbitrary_trace(&mut rng, 10), core).is_err());
+    Ok(())
+}
+
+#[test]
+fn line_utilisation_counts_the_bytes_used_before_eviction() -> Result<(), Box<dyn Error>> {
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 128, "line_size": 64, "kind": "direct"},
+        {"name": "L2", "size": 1024, "line_size": 128, "kind": "full", "replacement_policy": "rr"},
+    ]}))?;
+    // The second read of line 0 hits in L1, and the read at 0x80 evicts it from L1
+    let trace: Vec<u8> = [(0x0, 8), (0x10, 8), (0x80, 4)].iter()
+        .flat_map(|(address, size): &(u64, u16)| format!("{:016x} {address:016x} R {size:03}\n", 0x400000).into_bytes())
+        .collect();
+    let mut simulator = Simulator::builder(&config).line_utilisation(true).build()?;
+    simulator.simulate(&trace)?;
+    assert_eq!(simulator.get_result(), Simulator::new(&config).simulate(&trace)?);
+    let utilisation = simulator.line_utilisation().unwrap();
+    assert_eq!((utilisation[0].evicted_lines(), utilisation[0].evicted_bytes_used()), (1, 16));
+    assert_eq!(utilisation[0].average(), Some((16 + 4) as f64 / (2 * 64) as f64));
+    assert_eq!(utilisation[1].evicted_lines(), 0);
+    assert_eq!(utilisation[1].average(), Some((8 + 4) as f64 / (2 * 128) as f64));
+
+    // Lines invalidated to keep a cache inclusive leave the caches above
+    let mut inclusive = config.clone();
+    inclusive.caches[1].size = 128;
+    inclusive.caches[1].inclusivity = InclusivityConfig::Inclusive;
+    let mut simulator = Simulator::builder(&inclusive).line_utilisation(true).build()?;
+    simulator.simulate(&trace)?;
+    let utilisation = simulator.line_utilisation().unwrap();
+    assert_eq!((utilisation[0].evicted_lines(), utilisation[0].evicted_bytes_used()), (1, 16));
+    assert_eq!((utilisation[1].evicted_lines(), utilisation[1].evicted_bytes_used()), (1, 8));
+
+    // Tracking evictions doesn't change the results
+    let mut rng = SplitMix64::new(2455);
+    for _ in 0..10 {
+        let config = arbitrary_config(&mut rng);
+        let trace = arbitrary_trace(&mut rng, 2000);
+        let mut simulator = Simulator::builder(&config).line_utilisation(true).build()?;
+        simulator.simulate(&trace)?;
+        assert_eq!(simulator.get_result(), Simulator::new(&config).simulate(&trace)?);
+        assert!(simulator.line_utilisation().unwrap().iter().all(|cache| cache.average().is_some_and(|average| average > 0.0 && average <= 1.0)));
+    }
+    Ok(())
+}
+
+#[test]
+fn way_statistics_count_the_hits_and_fills_of_each_way() -> Result<(), Box<dyn Error>> {
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 256, "line_size": 64, "kind": "2way", "replacement_policy": "rr"},
+    ]}))?;
+    // Lines 0x0 and 0x80 fill the ways of set 0 in turn, then line 0x0 hits in the first three times
+    let trace: Vec<u8> = [0x0, 0x80, 0x0, 0x0, 0x0].iter()
+        .flat_map(|address: &u64| format!("{:016x} {address:016x} R 008\n", 0x400000).into_bytes())
+        .collect();
+    let mut simulator = Simulator::builder(&config).way_statistics(true).build()?;
+    simulator.simulate(&trace)?;
+    let ways = &simulator.way_statistics().unwrap()[0];
+    assert_eq!((ways.hits(), ways.fills()), ([3, 0].as_slice(), [1, 1].as_slice()));
+    assert_eq!(ways.skew(), Some(4.0 * 2.0 / 5.0));
+
+    // Every hit is counted in some way, and the results are unchanged
+    let mut rng = SplitMix64::new(2482);
+    for _ in 0..10 {
+        let config = arbitrary_config(&mut rng);
+        let trace = arbitrary_trace(&mut rng, 2000);
+        let mut simulator = Simulator::builder(&config).way_statistics(true).build()?;
+        simulator.simulate(&trace)?;
+        assert_eq!(simulator.get_result(), Simulator::new(&config).simulate(&trace)?);
+        for (ways, cache) in simulator.way_statistics().unwrap().iter().zip(simulator.get_result().caches()) {
+            assert_eq!(ways.hits().iter().sum::<u64>(), cache.hits(), "{}", cache.name());
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn locality_report_of_two_sequential_passes() -> Result<(), Box<dyn Error>> {
+    // Two passes reading 8 bytes at a time over 8 lines of 64 bytes
+    let trace: Vec<u8> = (0..128u64)
+        .flat_map(|index| format!("{:016x} {:016x} R 008\n", 0x400000, index % 64 * 8).into_bytes())
+        .collect();
+    let mut analysis = LocalityAnalysis::new(64, 32, 12)?;
+    analysis.simulate(&trace)?;
+    let report = analysis.report();
+    assert_eq!(report.accesses, 128);
+    assert_eq!(report.strides, vec![
+        StrideBucket { min: -511, max: -256, count: 1 },
+        StrideBucket { min: 8, max: 15, count: 126 },
+    ]);
+    assert_eq!(report.spatial_score, 126.0 / 128.0);
+    assert_eq!(report.spatial_windows, vec![31.0 / 32.0, 1.0, 31.0 / 32.0, 1.0]);
+    // Each line is read 7 more times straight away, and once more after the 7 other lines
+    let reuse: Vec<(u64, f64)> = report.reuse.iter().map(|point| (point.lines, point.fraction)).collect();
+    assert_eq!(reuse, vec![(1, 112.0 / 128.0), (2, 112.0 / 128.0), (4, 112.0 / 128.0), (8, 120.0 / 128.0), (12, 120.0 / 128.0)]);
+    assert!(LocalityAnalysis::new(64, 0, 12).is_err());
+    Ok(())
+}
+
+#[test]
+fn working_sets_of_a_loop_and_a_stream() -> Result<(), Box<dyn Error>> {
+    // A loop over 4 lines of 64 bytes 4 times, then one access to each of 16 new lines
+    let addresses = (0..16u64).map(|index| index % 4 * 64).chain((0..16u64).map(|index| 0x1000 + index * 64));
+    let trace: Vec<u8> = addresses.flat_map(|address| format!("{:016x} {address:016x} R 008\n", 0x400000).into_bytes()).collect();
+    let mut analysis = WorkingSetAnalysis::new(64, 8, &[4, 1, 64])?;
+    analysis.simulate(&trace)?;
+    let report = analysis.report();
+    assert_eq!(report.accesses, 32);
+    assert_eq!(report.footprint, 20);
+    assert_eq!(report.windows, vec![4, 4, 8, 8]);
+    let curve: Vec<(u64, f64)> = report.curve.iter().map(|point| (point.window, point.lines)).collect();
+    // The windows ending at the first accesses are shorter, as they start at the start of the trace
+    assert_eq!(curve, vec![(1, 1.0), (4, (1.0 + 2.0 + 3.0 + 29.0 * 4.0) / 32.0), (64, (1.0 + 2.0 + 3.0 + 13.0 * 4.0 + (1..=16).map(|lines| lines as f64 + 4.0).sum::<f64>()) / 32.0)]);
+    assert!(WorkingSetAnalysis::new(64, 0, &[]).is_err());
+    Ok(())
+}
+
+#[test]
+fn ensembles_match_separate_simulations() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2426);
+    let configs: Vec<LayeredCacheConfig> = (0..5).map(|_| arbitrary_config(&mut rng)).collect();
+    let trace = arbitrary_trace(&mut rng, 3000);
+    let expected: Vec<LayeredCacheResult> = configs.iter()
+        .map(|config| {
+            let mut simulator = Simulator::new(config);
+            simulator.simulate(&trace)?;
+            Ok(simulator.into_result())
+        })
+        .collect::<Result<_, String>>()?;
+    for threads in [1, 2, 8] {
+        let mut ensemble = Ensemble::new(&configs).with_threads(threads).with_batch_records(250);
+        ensemble.simulate(&trace)?;
+        assert_eq!(ensemble.into_results(), expected, "{threads} threads");
+    }
+    Ok(())
+}
+
+#[test]
+fn sweep_lru_matches_simulated_lru_caches() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2427);
+    let trace = arbitrary_trace(&mut rng, 3000);
+    let mut sweep = SweepLru::new(64, 32, 16)?;
+    sweep.simulate(&trace)?;
+    for (kind, ways) in [("direct", 1), ("2way", 2), ("4way", 4), ("8way", 8)] {
+        for sets in [1, 2, 8, 32] {
+            let result = simulate_config(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+                {{"name": "L1", "size": {}, "line_size": 64, "kind": "{kind}", "replacement_policy": "lru"}}
+            ]}}"#, sets * ways * 64), &trace)?;
+            assert_eq!(Some(result["caches"][0]["hits"].as_u64().unwrap()), sweep.hits(sets, ways), "{sets} sets of {ways} ways");
+        }
+    }
+    for ways in [1, 4, 16] {
+        let result = simulate_config(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+            {{"name": "L1", "size": {}, "line_size": 64, "kind": "full", "replacement_policy": "lru"}}
+        ]}}"#, ways * 64), &trace)?;
+        assert_eq!(Some(result["caches"][0]["misses"].as_u64().unwrap()), sweep.misses(1, ways), "{ways} ways");
+    }
+    assert_eq!(sweep.points().len(), 6 * 16);
+    Ok(())
+}
+
+#[test]
+fn associativity_sweep_matches_simulated_lru_caches() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2458);
+    let trace = arbitrary_trace(&mut rng, 3000);
+    let mut sweep = AssociativitySweep::new(64, 2048, &[16, 1, 2, 4, 8])?;
+    sweep.simulate(&trace)?;
+    let report = sweep.report();
+    let simulated_misses = |kind: &str| -> Result<u64, Box<dyn Error>> {
+        let result = simulate_config(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+            {{"name": "L1", "size": 2048, "line_size": 64, "kind": "{kind}", "replacement_policy": "lru"}}
+        ]}}"#), &trace)?;
+        Ok(result["caches"][0]["misses"].as_u64().unwrap())
+    };
+    let full_misses = simulated_misses("full")?;
+    assert_eq!(report.fully_associative.misses, full_misses);
+    let mut before = None;
+    for (point, (kind, ways)) in report.points.iter().zip([("direct", 1), ("2way", 2), ("4way", 4), ("8way", 8), ("16way", 16)]) {
+        let misses = simulated_misses(kind)?;
+        assert_eq!((point.ways, point.sets, point.misses), (ways, 32 / ways, misses), "{kind}");
+        assert_eq!(point.conflict_misses, misses as i64 - full_misses as i64);
+        assert_eq!(point.misses_removed, before.map(|before: u64| before as i64 - misses as i64));
+        before = Some(misses);
+    }
+    assert!(AssociativitySweep::new(64, 2048, &[3]).is_err());
+    Ok(())
+}
+
+#[test]
+fn associativity_sweep_only_stands_in_for_simulations_it_matches() -> Result<(), Box<dyn Error>> {
+    // Accesses spread over four times the capacity of L1D, so every associativity misses differently
+    let mut rng = SplitMix64::new(2460);
+    let trace = (0..3000)
+        .map(|_| format!("{:016x} {:016x} {} 004\n", 0x400000, (0x10000 + rng.below(8192)) & !3, if rng.below(4) == 0 { 'W' } else { 'R' }))
+        .collect::<String>()
+        .into_bytes();
+    let config = |l1: &str, extra: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_str(&format!(r#"{{"caches": [
+            {l1}{{"name": "L1D", "size": 2048, "line_size": 64, "kind": "direct", "replacement_policy": "lru"}},
+            {{"name": "L2", "size": 8192, "line_size": 64, "kind": "8way"}}
+        ]{extra}}}"#))?)
+    };
+    // The hits and misses of the sweep and of a copy of the configuration with each associativity,
+    // which cachesim analyze associativity simulates when the sweep doesn't match
+    type Counts = Vec<(u64, u64)>;
+    let counts = |config: &LayeredCacheConfig, trace: &[u8]| -> Result<(Counts, Counts), Box<dyn Error>> {
+        let index = config.caches.iter().position(|cache| cache.name == "L1D").unwrap();
+        let mut sweep = AssociativitySweep::new(64, 2048, &[1, 2, 4, 8, 16])?;
+        sweep.simulate(trace)?;
+        let report = sweep.report();
+        let swept = report.points.iter().chain([&report.fully_associative]).map(|point| (point.hits, point.misses)).collect();
+        let variants: Vec<LayeredCacheConfig> = [CacheKindConfig::Direct, CacheKindConfig::TwoWay, CacheKindConfig::FourWay, CacheKindConfig::EightWay, CacheKindConfig::SixteenWay, CacheKindConfig::Full]
+            .into_iter()
+            .map(|kind| {
+                let mut variant = config.clone();
+                variant.caches[index].kind = kind;
+                variant
+            })
+            .collect();
+        let mut ensemble = Ensemble::new(&variants);
+        ensemble.simulate(trace)?;
+        Ok((swept, ensemble.into_results().iter().map(|result| (result.caches()[index].hits(), result.caches()[index].misses())).collect()))
+    };
+    let plain = config("", "")?;
+    assert!(AssociativitySweep::matches_simulation(&plain, 0, &trace)?);
+    let (swept, simulated) = counts(&plain, &trace)?;
+    assert_eq!(swept, simulated);
+    let modifies = String::from_utf8(trace.clone())?.replace(" W ", " M ").into_bytes();
+    let instructions = String::from_utf8(trace.clone())?.replace(" W ", " I ").into_bytes();
+    let instruction_cache = r#"{"name": "L1I", "size": 2048, "line_size": 64, "kind": "direct", "instructions": true}, "#;
+    let cases = [
+        ("context switches", config("", r#", "context_switches": {"interval": 100}"#)?, 0, &trace),
+        ("slices", config("", "")?, 0, &trace),
+        ("simulated writes", config("", "")?, 0, &trace),
+        ("modifies", plain.clone(), 0, &modifies),
+        ("an instruction cache", config(instruction_cache, "")?, 1, &instructions),
+        ("instruction fetches", plain.clone(), 0, &instructions),
+    ];
+    for (name, mut config, index, trace) in cases {
+        if name == "slices" {
+            config.caches[0].slices = Some(serde_json::from_str(r#"{"count": 2}"#)?);
+        }
+        if name == "simulated writes" {
+            config.caches[index].write_allocate = false;
+            config.caches[index].write_policy = WritePolicyConfig::WriteThrough;
+        }
+        assert!(!AssociativitySweep::matches_simulation(&config, index, trace)?, "{name}");
+        if name != "instruction fetches" {
+            let (swept, simulated) = counts(&config, trace)?;
+            assert_ne!(swept, simulated, "{name}");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn smaller_lines_below_read_every_line_missed_above() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2459);
+    let trace = arbitrary_trace(&mut rng, 3000);
+    let config: LayeredCacheConfig = serde_json::from_str(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+        {{"name": "L1", "size": 1024, "line_size": 128, "kind": "2way", "replacement_policy": "lru"}},
+        {{"name": "L2", "size": 4096, "line_size": 32, "kind": "4way", "replacement_policy": "rr"}}
+    ]}}"#))?;
+    config.validate()?;
+    let mut simulator = Simulator::new(&config);
+    let result = simulator.simulate(&trace)?.clone();
+    assert_eq!(result, simulate_reference(&config, &trace)?);
+    let (l1, l2) = (&result.caches()[0], &result.caches()[1]);
+    assert_eq!(l2.hits() + l2.misses(), 4 * l1.misses());
+    Ok(())
+}
+
+#[test]
+fn miss_traces_reproduce_the_levels_below() -> Result<(), Box<dyn Error>> {
+    // Collects what the observer writes where the test can read it back
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut rng = SplitMix64::new(2460);
+    let trace = arbitrary_trace(&mut rng, 3000);
+    let config: LayeredCacheConfig = serde_json::from_str(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+        {{"name": "L1", "size": 1024, "line_size": 64, "kind": "2way", "replacement_policy": "lru"}},
+        {{"name": "L2", "size": 8192, "line_size": 128, "kind": "4way", "replacement_policy": "rr"}}
+    ]}}"#))?;
+    let buffer = SharedBuffer::default();
+    let miss_trace = MissTrace::new(0, 64, buffer.clone());
+    let mut simulator = Simulator::builder(&config).observer(miss_trace.clone()).build()?;
+    let result = simulator.simulate(&trace)?.clone();
+    assert_eq!(miss_trace.finish()?, result.caches()[0].misses());
+    let below: LayeredCacheConfig = serde_json::from_str(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+        {{"name": "L2", "size": 8192, "line_size": 128, "kind": "4way", "replacement_policy": "rr"}}
+    ]}}"#))?;
+    let mut simulator = Simulator::new(&below);
+    let misses = buffer.0.lock().unwrap().clone();
+    assert_eq!(simulator.simulate(&misses)?.caches()[0], result.caches()[1]);
+    Ok(())
+}
+
+#[test]
+fn writeback_buffers_absorb_dirty_evictions() -> Result<(), Box<dyn Error>> {
+    // Lines 0x000 and 0x080 share set 0 of L1, and 0x040 and 0x0c0 share set 1
+    let trace = [("W", 0x000), ("R", 0x080), ("R", 0x000), ("R", 0x080), ("W", 0x040), ("R", 0x0c0)].iter()
+        .map(|(mode, address)| format!("{:016x} {address:016x} {mode} 001\n", 0x400000))
+        .collect::<String>()
+        .into_bytes();
+    let config = |buffer: &str| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct"{buffer}}},
+        {{"name": "L2", "size": 1024, "line_size": 64, "kind": "full"}}
+    ]}}"#);
+    let buffered = simulate_config(&config(r#", "writeback_buffer": {"entries": 1, "drain_interval": 100}"#), &trace)?;
+    // 0x000 is evicted dirty then read back from the buffer, and evicted again, so 0x040 evicted
+    // dirty while it waits in the full buffer stalls
+    assert_eq!(buffered["caches"][0]["misses"], 6);
+    assert_eq!(buffered["caches"][0]["writeback_buffer_hits"], 1);
+    assert_eq!(buffered["caches"][0]["writeback_stalls"], 1);
+    assert_eq!(buffered["caches"][0]["writebacks"], 1);
+    // L2 isn't read for the buffer hit, but is written 0x000 from the full buffer, which it holds
+    assert_eq!((&buffered["caches"][1]["hits"], &buffered["caches"][1]["misses"]), (&2.into(), &4.into()));
+    let unbuffered = simulate_config(&config(""), &trace)?;
+    assert_eq!((&unbuffered["caches"][1]["hits"], &unbuffered["caches"][1]["misses"]), (&2.into(), &4.into()));
+    assert!(unbuffered["caches"][0].get("writebacks").is_none());
+    // Draining at every access writes 0x000 below before it's read again, and 0x040 is still
+    // waiting at the end
+    let draining = simulate_config(&config(r#", "writeback_buffer": {"entries": 1, "drain_interval": 1}"#), &trace)?;
+    assert_eq!(draining["caches"][0]["writebacks"], 1);
+    assert!(draining["caches"][0].get("writeback_buffer_hits").is_none());
+    assert!(draining["caches"][0].get("writeback_stalls").is_none());
+    Ok(())
+}
+
+#[test]
+fn write_back_caches_write_dirty_lines_below_without_a_buffer() -> Result<(), Box<dyn Error>> {
+    let records = |accesses: &[(&str, u64)]| accesses.iter()
+        .map(|(mode, address)| format!("{:016x} {address:016x} {mode} 001\n", 0x400000))
+        .collect::<String>()
+        .into_bytes();
+    let config = |extra: &str| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct"}},
+        {{"name": "L2", "size": 1024, "line_size": 64, "kind": "full"}}
+    ]{extra}}}"#);
+    // 0x000 and 0x080 share set 0 of L1, so 0x000 is evicted dirty twice, and 0x080 once clean
+    let trace = records(&[("W", 0x000), ("R", 0x080), ("W", 0x000), ("R", 0x080), ("R", 0x040)]);
+    let written = simulate_config(&config(r#", "simulate_writes": true"#), &trace)?;
+    assert_eq!(written["caches"][0]["writebacks"], 2);
+    let read = simulate_config(&config(""), &trace)?;
+    assert!(read["caches"][0].get("writebacks").is_none());
+    assert_eq!((&written["caches"][0]["misses"], &written["main_memory_accesses"]), (&read["caches"][0]["misses"], &read["main_memory_accesses"]));
+    // A context switch flushes the dirty line too
+    let flushed = simulate_config(&config(r#", "simulate_writes": true, "context_switches": {"interval": 2}"#), &records(&[("W", 0x000), ("R", 0x040), ("R", 0x000)]))?;
+    assert_eq!(flushed["caches"][0]["writebacks"], 1);
+    let config: LayeredCacheConfig = serde_json::from_str(&config(r#", "simulate_writes": true"#))?;
+    let mut verified = Simulator::builder(&config).verify_invariants(1).build()?;
+    assert_eq!(verified.simulate(&trace)?.caches()[0].writebacks(), 2);
+    Ok(())
+}
+
+#[test]
+fn simulated_writes_reach_the_levels_below() -> Result<(), Box<dyn Error>> {
+    let records = |accesses: &[(&str, u64)]| accesses.iter()
+        .map(|(mode, address)| format!("{:016x} {address:016x} {mode} 001\n", 0x400000))
+        .collect::<String>()
+        .into_bytes();
+    let config = |l1: &str, l2: &str, simulate_writes: bool| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct", "write_policy": "{l1}"}},
+        {{"name": "L2", "size": 1024, "line_size": 64, "kind": "full", "write_policy": "{l2}"}}
+    ], "simulate_writes": {simulate_writes}}}"#);
+    // 0x000 is evicted dirty from L1 by 0x080, so is written to L2, which writes it through to
+    // memory
+    let trace = records(&[("W", 0x000), ("R", 0x080), ("R", 0x040)]);
+    let read = simulate_config(&config("write-back", "write-back", false), &trace)?;
+    assert_eq!((&read["caches"][1]["hits"], &read["caches"][1]["misses"], &read["main_memory_accesses"]), (&0.into(), &3.into(), &3.into()));
+    let written = simulate_config(&config("write-back", "write-through", true), &trace)?;
+    assert_eq!(written["caches"][0]["writebacks"], 1);
+    assert_eq!((&written["caches"][1]["hits"], &written["caches"][1]["misses"]), (&1.into(), &3.into()));
+    assert!(written["caches"][1].get("writebacks").is_none());
+    assert_eq!((&written["main_memory_accesses"], &written["main_memory_writes"]), (&4.into(), &1.into()));
+    // The write misses L1, while the writeback is the write to L2, which hits
+    let result: LayeredCacheResult = serde_json::from_value(written)?;
+    assert_eq!((result.caches()[0].reads(), result.caches()[0].writes()), ((0, 2), (0, 1)));
+    assert_eq!((result.caches()[1].reads(), result.caches()[1].writes()), ((0, 3), (1, 0)));
+    let output = SimulationOutput::new(&result);
+    let counts = |hits, misses| Some(AccessCounts { hits, misses });
+    assert_eq!((output.caches[1].reads, output.caches[1].writes), (counts(0, 3), counts(1, 0)));
+    assert_eq!(output.result(), result);
+    let output = SimulationOutput::new(&serde_json::from_value(read)?);
+    assert!(output.caches.iter().all(|cache| cache.reads.is_none() && cache.writes.is_none()));
+    // A write-through L1 passes its write hit to L2, where it stays dirty
+    let trace = records(&[("W", 0x000), ("W", 0x000), ("R", 0x080)]);
+    let through = simulate_config(&config("write-through", "write-back", true), &trace)?;
+    assert_eq!((&through["caches"][0]["hits"], &through["caches"][0]["misses"]), (&1.into(), &2.into()));
+    assert!(through["caches"][0].get("writebacks").is_none());
+    assert_eq!((&through["caches"][1]["hits"], &through["caches"][1]["misses"]), (&1.into(), &2.into()));
+    assert_eq!(through["main_memory_accesses"], 2);
+    assert!(through.get("main_memory_writes").is_none());
+    Ok(())
+}
+
+#[test]
+fn write_misses_pass_through_caches_which_do_not_allocate_them() -> Result<(), Box<dyn Error>> {
+    let records = |accesses: &[(&str, u64)]| accesses.iter()
+        .map(|(mode, address)| format!("{:016x} {address:016x} {mode} 001\n", 0x400000))
+        .collect::<String>()
+        .into_bytes();
+    let config = |allocate: bool| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct", "write_policy": "write-through", "write_allocate": {allocate}}},
+        {{"name": "L2", "size": 1024, "line_size": 64, "kind": "full"}}
+    ]}}"#);
+    // The first write leaves L1 without 0x000, so the read misses it, and the second write hits
+    let trace = records(&[("W", 0x000), ("R", 0x000), ("W", 0x000)]);
+    let allocating = simulate_config(&config(true), &trace)?;
+    assert_eq!((&allocating["caches"][0]["hits"], &allocating["caches"][0]["misses"]), (&2.into(), &1.into()));
+    let bypassing = simulate_config(&config(false), &trace)?;
+    assert_eq!((&bypassing["caches"][0]["hits"], &bypassing["caches"][0]["misses"]), (&1.into(), &2.into()));
+    assert_eq!((&bypassing["caches"][1]["hits"], &bypassing["caches"][1]["misses"]), (&2.into(), &1.into()));
+    // A last level which doesn't allocate a write miss only writes it to memory, while one which
+    // does reads the line then writes each write through
+    let last = |allocate: bool| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct", "write_policy": "write-through", "write_allocate": {allocate}}}
+    ]}}"#);
+    let trace = records(&[("W", 0x000), ("W", 0x000)]);
+    let bypassing = simulate_config(&last(false), &trace)?;
+    assert_eq!((&bypassing["caches"][0]["misses"], &bypassing["main_memory_accesses"]), (&2.into(), &2.into()));
+    assert!(bypassing.get("main_memory_writes").is_none());
+    let allocating = simulate_config(&last(true), &trace)?;
+    assert_eq!((&allocating["caches"][0]["misses"], &allocating["main_memory_accesses"], &allocating["main_memory_writes"]), (&1.into(), &3.into(), &2.into()));
+    Ok(())
+}
+
+#[test]
+fn write_back_caches_must_allocate_write_misses() -> Result<(), Box<dyn Error>> {
+    let config = |write_policy: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_str(&format!(r#"{{"caches": [
+            {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct", "write_policy": "{write_policy}", "write_allocate": false}}
+        ], "simulate_writes": true}}"#))?)
+    };
+    assert_eq!(config("write-back")?.validate().unwrap_err(), "Cache L1 is write-back without write allocation, which isn't supported; write-back caches must allocate on write misses");
+    // Write-through caches can leave write misses out, so a read after a write still misses
+    let through = config("write-through")?;
+    through.validate()?;
+    let trace = [b"0000000000400000 0000000000000000 W 001\n".as_slice(), b"0000000000400000 0000000000000000 R 001\n"].concat();
+    let result = Simulator::new(&through).simulate(&trace)?.clone();
+    assert_eq!((result.caches()[0].hits(), result.caches()[0].misses()), (0, 2));
+    Ok(())
+}
+
+#[test]
+fn context_switches_flush_the_caches() -> Result<(), Box<dyn Error>> {
+    // Each line has a set of its own in L1, so only compulsory misses and flushes miss
+    let trace = trace_of_reads(&[0x000, 0x040, 0x080, 0x0c0].repeat(100));
+    let config = |caches: &str| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 256, "line_size": 64, "kind": "direct"}},
+        {{"name": "L2", "size": 1024, "line_size": 64, "kind": "direct"}}
+    ], "context_switches": {{"interval": 40, "recovery": 4{caches}}}}}"#);
+    let every = simulate_config(&config(""), &trace)?;
+    // 9 context switches, each followed by a miss of every line
+    assert_eq!(every["caches"][0]["misses"], 40);
+    assert_eq!(every["caches"][0]["disturbed_misses"], 36);
+    assert!(every["caches"][0].get("disturbed_hits").is_none());
+    assert_eq!(every["caches"][1]["misses"], 40);
+    let below = simulate_config(&config(r#", "caches": ["L2"]"#), &trace)?;
+    assert_eq!(below["caches"][0]["misses"], 4);
+    assert!(below["caches"][0].get("disturbed_misses").is_none());
+    assert_eq!(below["caches"][1]["misses"], 4);
+    Ok(())
+}
+
+#[test]
+fn reordering_keeps_each_threads_order_within_the_window() -> Result<(), Box<dyn Error>> {
+    // Each thread's records are told apart by their program counter, the thread's number
+    let mut rng = SplitMix64::new(2478);
+    let threads: Vec<Vec<u8>> = [300, 200, 6].iter().enumerate()
+        .map(|(thread, &records)| (0..records).flat_map(|_| format!("{thread:016x} {:016x} R 008\n", rng.below(4096)).into_bytes()).collect())
+        .collect();
+    let threads: Vec<&[u8]> = threads.iter().map(Vec::as_slice).collect();
+    for strategy in [ReorderStrategy::Shuffle { seed: 1 }, ReorderStrategy::Cluster] {
+        let mut reordered = Vec::new();
+        let report = TraceReorderer::new(16, 64, strategy)?.reorder(&threads, |record| reordered.push(Access::from_record(record.try_into().unwrap())))?;
+        assert_eq!((report.records, report.windows), (506, 32));
+        assert!(report.moved > 0 && report.max_displacement < 16);
+        for (thread, trace) in threads.iter().enumerate() {
+            let own: Vec<Access> = reordered.iter().copied().filter(|access| access.pc == thread as u64).collect();
+            let original: Vec<Access> = trace.chunks_exact(40).map(|record| Access::from_record(record.try_into().unwrap())).collect();
+            assert_eq!(own, original);
+        }
+        if strategy == ReorderStrategy::Cluster {
+            assert!(report.same_line_after > report.same_line_before);
+        }
+    }
+    // A single trace is free to move within each window, so clustering brings its lines together
+    let mut reordered = Vec::new();
+    let trace = trace_of_reads(&[0, 64, 0, 64, 0, 64]);
+    let report = TraceReorderer::new(6, 64, ReorderStrategy::Cluster)?.reorder_records(&trace, |record| reordered.extend_from_slice(record))?;
+    assert_eq!(reordered, trace_of_reads(&[0, 0, 0, 64, 64, 64]));
+    assert_eq!((report.same_line_before, report.same_line_after), (0, 4));
+    Ok(())
+}
+
+#[test]
+fn random_placements_move_whole_regions() -> Result<(), Box<dyn Error>> {
+    let randomiser = PlacementRandomiser::new(4096, 2464)?;
+    let regions: std::collections::BTreeSet<u64> = (0..4096u64).map(|region| randomiser.map(region << 12 | 0x123) >> 12).collect();
+    assert_eq!(regions.len(), 4096);
+    assert_eq!(randomiser.map(0x5000) + 0x123, randomiser.map(0x5123));
+    assert_eq!(PlacementRandomiser::new(4096, 2464)?.map(0x5000), randomiser.map(0x5000));
+    // A fully associative cache doesn't care where its lines are, while a direct mapped one does.
+    // Single bytes never span regions, which would move the end of the access with the start
+    let mut rng = SplitMix64::new(2464);
+    let trace = trace_of_reads(&(0..3000).map(|_| rng.below(1 << 20)).collect::<Vec<_>>());
+    let config = |kind: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_str(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "caches": [
+            {{"name": "L1", "size": 8192, "line_size": 64, "kind": "{kind}", "replacement_policy": "lru"}}
+        ]}}"#))?)
+    };
+    let placed = |config: &LayeredCacheConfig| -> Result<LayeredCacheResult, String> {
+        let accesses = trace.chunks_exact(40).map(|record| randomiser.map_access(Access::from_record(record.try_into().unwrap())));
+        Simulator::new(config).simulate_accesses(accesses, 0).cloned()
+    };
+    let full = config("full")?;
+    assert_eq!(placed(&full)?, Simulator::new(&full).simulate(&trace)?.clone());
+    let direct = config("direct")?;
+    assert_ne!(placed(&direct)?, Simulator::new(&direct).simulate(&trace)?.clone());
+    Ok(())
+}
+
+#[test]
+fn merged_processes_keep_their_address_spaces_apart() -> Result<(), Box<dyn Error>> {
+    let (short, long) = (trace_of_reads(&[0x100, 0x200]), trace_of_reads(&[0x100, 0x200, 0x300, 0x400]));
+    let order = |interleaving| -> Result<Vec<(usize, u64)>, String> {
+        Ok(ProcessTraces::new(vec![&short, &long], interleaving, AddressSpaces::Tagged)?.map(|(process, access)| (process, access.address)).collect())
+    };
+    assert_eq!(order(Interleaving::Proportional)?, [(0, 0x100), (1, 0x1_0000_0000_0100), (1, 0x1_0000_0000_0200), (0, 0x200), (1, 0x1_0000_0000_0300), (1, 0x1_0000_0000_0400)]);
+    assert_eq!(order(Interleaving::RoundRobin { quantum: 3 })?.iter().map(|(process, _)| *process).collect::<Vec<_>>(), [0, 0, 1, 1, 1, 1]);
+    // Each process has its own half of the 4 page colours of a 16KiB direct mapped cache
+    let partitioned = ProcessTraces::new(vec![&short, &long], Interleaving::Proportional, AddressSpaces::Partitioned { colours: 4 })?;
+    let colours: Vec<u64> = (0..8).map(|page| partitioned.translate(1, page * 4096) / 4096 % 4).collect();
+    assert_eq!(colours, [2, 3, 2, 3, 2, 3, 2, 3]);
+    assert!(ProcessTraces::new(vec![&short, &long, &long, &long, &long], Interleaving::Proportional, AddressSpaces::Partitioned { colours: 4 }).is_err());
+    // Addresses reaching the address space id can't be tagged with it
+    for address in [0x1_0000_0000_0000, u64::MAX] {
+        let high = trace_of_reads(&[0x100, address]);
+        let e = ProcessTraces::new(vec![&short, &high], Interleaving::Proportional, AddressSpaces::Tagged).err().unwrap();
+        assert!(e.contains("Record 1 of the trace of process 1"), "{e}");
+        assert!(ProcessTraces::new(vec![&short, &high], Interleaving::Proportional, AddressSpaces::Partitioned { colours: 4 }).is_ok());
+    }
+    // Two copies of a trace on their own cores, with private first levels and a shared second level
+    // large enough for both, see the same hits and misses as the trace alone
+    let mut rng = SplitMix64::new(2465);
+    let trace = trace_of_reads(&(0..2000).map(|_| rng.below(16 * 1024)).collect::<Vec<_>>());
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1a", "size": 1024, "line_size": 64, "kind": "direct", "shared_by": [0], "next": "L2"},
+        {"name": "L1b", "size": 1024, "line_size": 64, "kind": "direct", "shared_by": [1], "next": "L2"},
+        {"name": "L2", "size": 65536, "line_size": 64, "kind": "full", "replacement_policy": "lru"},
+    ]}))?;
+    let alone = Simulator::new(&config).simulate_core(&trace, 1)?.clone();
+    let mut simulator = Simulator::new(&config);
+    let processes = simulator.simulate_processes(ProcessTraces::new(vec![&trace, &trace], Interleaving::RoundRobin { quantum: 7 }, AddressSpaces::Tagged)?, &[0, 1])?;
+    assert_eq!(processes[1], alone);
+    assert_eq!(processes[0].caches()[0].misses(), alone.caches()[1].misses());
+    assert_eq!(processes[0].clone() + &processes[1], *simulator.get_result());
+    Ok(())
+}
+
+#[test]
+fn optimal_replacement_bounds_the_misses_of_each_cache() -> Result<(), Box<dyn Error>> {
+    // The textbook reference string, which the optimal policy serves with 9 misses from 3 lines
+    let mut optimal = OptimalCache::new(1, 1, 3)?;
+    for line in [7, 0, 1, 2, 0, 3, 0, 4, 2, 3, 0, 3, 2, 1, 2, 0, 1, 7, 0, 1] {
+        optimal.read(line);
+    }
+    assert_eq!(optimal.misses_by_set(), [9]);
+    // Cycling through five lines of one set misses every time with lru, but once the set is full the
+    // optimal policy only misses on every fourth read, evicting the line read just before
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 1024, "line_size": 64, "kind": "4way", "replacement_policy": "lru"},
+    ]}))?;
+    let trace = trace_of_reads(&(0..100).map(|read| read % 5 * 256).collect::<Vec<_>>());
+    let gap = Arc::new(std::sync::Mutex::new(OptimalGap::new(&config)?));
+    let observed = gap.clone();
+    let mut simulator = Simulator::builder(&config).observer(move |access: CacheAccess| observed.lock().unwrap().observe(access)).build()?;
+    simulator.simulate(&trace)?;
+    let report = gap.lock().unwrap().report(2);
+    assert_eq!((report[0].reads, report[0].misses), (100, simulator.get_result().caches()[0].misses()));
+    assert_eq!(report[0].misses, 100);
+    assert_eq!(report[0].optimal_misses, 4 + 96 / 4);
+    assert_eq!(report[0].sets.iter().map(|set| (set.set, set.gap)).collect::<Vec<_>>(), [(0, 72), (1, 0)]);
+    Ok(())
+}
+
+#[test]
+fn latency_histograms_count_the_level_serving_each_access() -> Result<(), Box<dyn Error>> {
+    let config = |l2_penalty: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_str(&format!(r#"{{"version": {CURRENT_CONFIG_VERSION}, "memory_latency": 100, "caches": [
+            {{"name": "L1", "size": 256, "line_size": 64, "kind": "direct", "hit_latency": 2}},
+            {{"name": "L2", "size": 1024, "line_size": 64, "kind": "direct", "hit_latency": 10{l2_penalty}}}
+        ]}}"#))?)
+    };
+    // The last read spans a line in the first level and one which is only in the second, so waits
+    // for the second level
+    let mut trace = trace_of_reads(&[0x0, 0x40, 0x0, 0x100, 0x40]);
+    trace.extend_from_slice(b"0000000000400000 0000000000000030 R 032\n");
+    let mut simulator = Simulator::builder(&config("")?).latency_histogram(true).build()?;
+    simulator.simulate(&trace)?;
+    let histogram = simulator.latency_histogram().unwrap();
+    assert_eq!(histogram.served(0), [2, 1, 3]);
+    assert!(histogram.served(1).is_empty());
+    let buckets: Vec<(u64, String, u64)> = histogram.buckets(&config("")?)?.into_iter().map(|bucket| (bucket.latency, bucket.level, bucket.accesses)).collect();
+    assert_eq!(buckets, [(2, "L1".to_string(), 2), (12, "L2".to_string(), 1), (112, "main memory".to_string(), 3)]);
+    // A miss penalty replaces the latency of the levels below
+    let with_penalty = histogram.buckets(&config(r#", "miss_penalty": 50"#)?)?;
+    assert_eq!(with_penalty.last().map(|bucket| (bucket.latency, bucket.cumulative_fraction)), Some((62, 1.0)));
+    let mut missing = config("")?;
+    missing.memory_latency = None;
+    assert!(histogram.buckets(&missing).is_err());
+    // An access crossing a page is split, but is still served once, by the slower of its parts
+    let mut paged = config("")?;
+    paged.page_size = Some(4096);
+    let mut simulator = Simulator::builder(&paged).latency_histogram(true).build()?;
+    simulator.simulate(&trace_of_reads(&[0x1000]))?;
+    simulator.simulate(b"0000000000400000 0000000000000fe0 R 064\n")?;
+    assert_eq!(simulator.latency_histogram().unwrap().served(0), [0, 0, 2]);
+    Ok(())
+}
+
+#[test]
+fn instruction_fetches_take_the_instruction_cache_and_share_lower_levels() -> Result<(), Box<dyn Error>> {
+    let config: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [
+        {"name": "L1I", "size": 256, "line_size": 64, "kind": "direct", "instructions": true},
+        {"name": "L1D", "size": 256, "line_size": 64, "kind": "direct"},
+        {"name": "L2", "size": 1024, "line_size": 64, "kind": "direct"}
+    ]}"#)?;
+    let topology = Topology::from_config(&config)?;
+    assert_eq!((topology.path(0), topology.instruction_path(0)), (Some(&[1, 2][..]), Some(&[0, 2][..])));
+    // The data read of the line fetched misses the data cache, but hits the level they share
+    let trace = b"0000000000001000 0000000000001000 I 004\n0000000000001000 0000000000001000 R 008\n0000000000001004 0000000000001004 I 004\n";
+    let mut simulator = Simulator::new(&config);
+    let result = simulator.simulate(trace)?;
+    let counts: Vec<((u64, u64), (u64, u64))> = result.caches().iter().map(|cache| (cache.fetches(), cache.data())).collect();
+    assert_eq!(counts, [((1, 1), (0, 0)), ((0, 0), (0, 1)), ((0, 1), (1, 0))]);
+    // Fetching the program counter of each data access gives the same as the fetches of the trace
+    let mut fetching = Simulator::builder(&config).fetch_program_counters(true).build()?;
+    fetching.simulate(b"0000000000001000 0000000000001000 R 008\n0000000000001004 0000000000002000 R 008\n")?;
+    assert_eq!(fetching.get_result().caches()[0].fetches(), (1, 1));
+    let invalid = [
+        (r#"{"name": "L1", "size": 256, "next": "L1I"}, {"name": "L1I", "size": 256, "instructions": true}"#, "passes its misses to instruction cache L1I"),
+        (r#"{"name": "L1I", "size": 256, "instructions": true}, {"name": "L1I2", "size": 256, "instructions": true}, {"name": "L1", "size": 256}"#, "more than one instruction cache"),
+        (r#"{"name": "L1I", "size": 256, "instructions": true, "shared_by": [0]}, {"name": "L1", "size": 256, "shared_by": [0, 1]}"#, "must share an instruction cache"),
+    ];
+    for (caches, error) in invalid {
+        let config: LayeredCacheConfig = serde_json::from_str(&format!(r#"{{"caches": [{caches}]}}"#))?;
+        let e = Topology::from_config(&config).unwrap_err();
+        assert!(e.contains(error), "{e}");
+    }
+    Ok(())
+}
+
+#[test]
+fn accesses_crossing_a_page_boundary_are_split_and_counted() -> Result<(), Box<dyn Error>> {
+    let config = |page_size: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_str(&format!(r#"{{"caches": [{{"name": "L1", "size": 1024, "line_size": 64, "kind": "2way"}}]{page_size}}}"#))?)
+    };
+    let trace = b"0000000000000000 0000000000000ffc R 008\n0000000000000000 0000000000001000 R 008\n0000000000000000 0000000000001ff0 W 016\n0000000000000000 0000000000002ff8 R 016\n";
+    let mut paged = Simulator::new(&config(r#", "page_size": "4KiB""#)?);
+    let result = paged.simulate(trace)?.clone();
+    assert_eq!(result.page_crossing_accesses(), 2);
+    // Pages hold whole lines, so splitting accesses at them reads the same lines
+    let mut unpaged = Simulator::new(&config("")?);
+    let unpaged = unpaged.simulate(trace)?;
+    assert_eq!((unpaged.page_crossing_accesses(), unpaged.caches()), (0, result.caches()));
+    assert_eq!(SimulationOutput::new(&result).schema_version, SCHEMA_VERSION);
+    // Counted like any other statistic, after the warm up
+    let mut warmed = Simulator::builder(&config(r#", "page_size": 4096"#)?).warmup(2).build()?;
+    assert_eq!(warmed.simulate(trace)?.page_crossing_accesses(), 1);
+    for page_size in ["48", "32"] {
+        assert!(config(&format!(r#", "page_size": {page_size}"#))?.validate().is_err());
+    }
+    // An access running past the end of the address space stops at it
+    for page_size in ["", r#", "page_size": "4KiB""#] {
+        let mut simulator = Simulator::new(&config(page_size)?);
+        let result = simulator.simulate(b"0000000000000000 fffffffffffffff8 R 016\n")?;
+        assert_eq!((result.caches()[0].misses(), result.page_crossing_accesses()), (1, 0));
+    }
+    Ok(())
+}
+
+#[test]
+fn modify_records_read_then_write_their_lines() -> Result<(), Box<dyn Error>> {
+    let records = |accesses: &[(&str, u64)]| accesses.iter()
+        .map(|(mode, address)| format!("{:016x} {address:016x} {mode} 001\n", 0x400000))
+        .collect::<String>()
+        .into_bytes();
+    let config = |buffer: &str| format!(r#"{{"caches": [
+        {{"name": "L1", "size": 128, "line_size": 64, "kind": "direct"{buffer}}},
+        {{"name": "L2", "size": 1024, "line_size": 64, "kind": "full"}}
+    ]}}"#);
+    // 0x000 and 0x080 share set 0 of L1, so the modified line is evicted dirty
+    let modified = records(&[("M", 0x000), ("R", 0x080), ("M", 0x040), ("R", 0x000)]);
+    let expanded = records(&[("R", 0x000), ("W", 0x000), ("R", 0x080), ("R", 0x040), ("W", 0x040), ("R", 0x000)]);
+    for buffer in ["", r#", "writeback_buffer": {"entries": 1, "drain_interval": 1}"#] {
+        assert_eq!(simulate_config(&config(buffer), &modified)?, simulate_config(&config(buffer), &expanded)?);
+    }
+    let buffered = simulate_config(&config(r#", "writeback_buffer": {"entries": 1, "drain_interval": 1}"#), &modified)?;
+    assert_eq!((&buffered["caches"][0]["hits"], &buffered["caches"][0]["misses"]), (&2.into(), &4.into()));
+    assert_eq!(buffered["caches"][0]["writebacks"], 1);
+    let read = simulate_config(&config(r#", "writeback_buffer": {"entries": 1, "drain_interval": 1}"#), &records(&[("R", 0x000), ("R", 0x080), ("R", 0x040), ("R", 0x000)]))?;
+    assert!(read["caches"][0].get("writebacks").is_none());
+    Ok(())
+}
+
+#[test]
+fn sliced_caches_spread_strided_lines_over_their_slices() -> Result<(), Box<dyn Error>> {
+    let config = |slices: &str| format!(r#"{{"caches": [{{"name": "LLC", "size": "4KiB", "line_size": 64, "kind": "2way", "replacement_policy": "rr"{slices}}}]}}"#);
+    // Lines 2KiB apart share set 0 by bit selection, but bit 12 picks the slice under the Intel
+    // hash, so each slice holds two of the four lines
+    let trace = (0..8).map(|i| format!("0000000000000000 {:016x} R 001\n", (i % 4) * 0x800)).collect::<String>().into_bytes();
+    let selected = simulate_config(&config(""), &trace)?;
+    assert_eq!((&selected["caches"][0]["hits"], &selected["caches"][0]["misses"]), (&0.into(), &8.into()));
+    let sliced = simulate_config(&config(r#", "slices": {"count": 2}"#), &trace)?;
+    assert_eq!((&sliced["caches"][0]["hits"], &sliced["caches"][0]["misses"]), (&4.into(), &4.into()));
+    let masked = simulate_config(&config(r#", "slices": {"count": 2, "masks": ["0x1b5f575440"]}"#), &trace)?;
+    assert_eq!(masked, sliced);
+    let mut simulator = Simulator::builder(&serde_json::from_str(&config(r#", "slices": {"count": 2}"#))?).set_statistics(true).build()?;
+    simulator.simulate(&trace)?;
+    // Set 0 of each slice, the first of its 16 sets
+    let misses = simulator.set_statistics().unwrap()[0].misses();
+    assert_eq!((misses.len(), misses[0], misses[16]), (32, 2, 2));
+    let invalid = [
+        (r#""count": 3"#, "must be a power of two"),
+        (r#""count": 16"#, "give the masks"),
+        (r#""count": 64"#, "too few to split"),
+        (r#""count": 4, "masks": [1024]"#, "need 2 masks"),
+        (r#""count": 2, "masks": [63]"#, "none of the address bits"),
+    ];
+    for (slices, error) in invalid {
+        let config: LayeredCacheConfig = serde_json::from_str(&config(&format!(r#", "slices": {{{slices}}}"#)))?;
+        let e = config.validate().unwrap_err();
+        assert!(e.contains(error), "{e}");
+    }
+    Ok(())
+}
+
+#[test]
+fn snapshots_continue_from_the_warmed_state() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2428);
+    let config = arbitrary_config(&mut rng);
+    let (warm_up, trace) = (arbitrary_trace(&mut rng, 1000), Arc::new(arbitrary_trace(&mut rng, 1000)));
+    let mut simulator = Simulator::new(&config);
+    simulator.simulate(&warm_up)?;
+    let warm: Vec<(u64, u64)> = simulator.get_result().caches().iter().map(|c| (c.hits(), c.misses())).collect();
+    let mut snapshot = simulator.snapshot();
+    let forked_trace = trace.clone();
+    let forked = std::thread::spawn(move || snapshot.simulate(&forked_trace).cloned()).join().unwrap()?;
+    simulator.simulate(&trace)?;
+    for ((cache, forked), (hits, misses)) in simulator.get_result().caches().iter().zip(forked.caches()).zip(warm) {
+        assert_eq!((cache.hits() - hits, cache.misses() - misses), (forked.hits(), forked.misses()), "{}", cache.name());
+    }
+    Ok(())
+}
+
+#[test]
+fn builder_options_split_the_trace() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2429);
+    let config = arbitrary_config(&mut rng);
+    let trace = arbitrary_trace(&mut rng, 1000);
+    let counted = |simulator: &Simulator| simulator.get_result().caches()[0].hits() + simulator.get_result().caches()[0].misses();
+
+    // Warming up simulates the first records without counting them, or showing them to observers
+    let observed = Arc::new(AtomicU64::new(0));
+    let observer_count = observed.clone();
+    let mut warmed = Simulator::builder(&config)
+        .warmup(400)
+        .observer(move |access: CacheAccess| if access.cache == 0 { observer_count.fetch_add(1, Ordering::Relaxed); })
+        .build()?;
+    warmed.simulate(&trace[..200 * 40])?;
+    warmed.simulate(&trace[200 * 40..])?;
+    let mut unwarmed = Simulator::new(&config);
+    unwarmed.simulate(&trace[..400 * 40])?;
+    let first = counted(&unwarmed);
+    unwarmed.simulate(&trace[400 * 40..])?;
+    assert_eq!(counted(&warmed), counted(&unwarmed) - first);
+    assert_eq!(observed.load(Ordering::Relaxed), counted(&warmed));
+
+    // Intervals add up to the whole, leaving out the partial interval at the end
+    let mut intervals = Simulator::builder(&config).interval_statistics(300).build()?;
+    intervals.simulate(&trace)?;
+    assert_eq!(intervals.interval_results().len(), 3);
+    let mut first_900 = Simulator::new(&config);
+    first_900.simulate(&trace[..900 * 40])?;
+    let interval_hits: u64 = intervals.interval_results().iter().map(|r| r.caches()[0].hits()).sum();
+    assert_eq!(interval_hits, first_900.get_result().caches()[0].hits());
+
+    // Each interval is passed on as soon as it ends, while the rest of the trace is still to come
+    let streamed = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let received = streamed.clone();
+    let mut streaming = Simulator::builder(&config)
+        .interval_statistics(300)
+        .on_interval(move |index, result| received.lock().unwrap().push((index, result.clone())))
+        .build()?;
+    streaming.simulate(&trace[..700 * 40])?;
+    assert_eq!(streamed.lock().unwrap().iter().map(|(index, _)| *index).collect::<Vec<_>>(), [0, 1]);
+    streaming.simulate(&trace[700 * 40..])?;
+    let streamed: Vec<LayeredCacheResult> = streamed.lock().unwrap().iter().map(|(_, result)| result.clone()).collect();
+    assert_eq!(streamed, intervals.interval_results());
+    assert!(Simulator::builder(&config).on_interval(|_, _| ()).build().is_err());
+
+    // Sampling skips the records between samples entirely
+    let mut sampled = Simulator::builder(&config).sampling(100, 250).build()?;
+    sampled.simulate(&trace)?;
+    let samples: Vec<u8> = trace.chunks(250 * 40).flat_map(|period| period[..100 * 40].to_vec()).collect();
+    let mut expected = Simulator::new(&config);
+    expected.simulate(&samples)?;
+    assert_eq!(sampled.get_result(), expected.get_result());
+
+    let progress = Arc::new(AtomicU64::new(0));
+    let reported = progress.clone();
+    let mut strict = Simulator::builder(&config)
+        .strict(true)
+        .progress(100, move |records| reported.store(records, Ordering::Relaxed))
+        .build()?;
+    strict.simulate(&trace)?;
+    assert_eq!(progress.load(Ordering::Relaxed), 1000);
+    let mut malformed = trace[..80].to_vec();
+    malformed[40 + 34] = b'X';
+    let error = strict.simulate(&malformed).unwrap_err();
+    assert!(error.starts_with("Record 1002 "), "{error}");
+    malformed[40 + 34] = b'R';
+    malformed[40 + 20] = b'g';
+    let error = strict.simulate(&malformed).unwrap_err();
+    assert_eq!(error, "Record 1002 isn't in the trace format: the address isn't 16 hexadecimal digits, as byte 21 of the record is 'g'");
+    assert!(Simulator::builder(&config).sampling(300, 250).build().is_err());
+    Ok(())
+}
+
+/// Gives a trace a few bytes at a time, as a pipe or socket might
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.0.len().min(buffer.len()).min(7);
+        buffer[..read].copy_from_slice(&self.0[..read]);
+        self.0 = &self.0[read..];
+        Ok(read)
+    }
+}
+
+#[test]
+fn streamed_traces_match_simulating_the_bytes() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2430);
+    let config = arbitrary_config(&mut rng);
+    let trace = arbitrary_trace(&mut rng, 2000);
+    let mut expected = Simulator::new(&config);
+    expected.simulate(&trace)?;
+    let mut streamed = Simulator::new(&config);
+    assert_eq!(streamed.simulate_reader(Trickle(&trace))?, expected.get_result());
+    let error = Simulator::new(&config).simulate_reader(&trace[..trace.len() - 1]).unwrap_err();
+    assert_eq!(error, "The trace ends part way through a record");
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_streams_match_simulating_the_bytes() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2431);
+    let config = arbitrary_config(&mut rng);
+    let trace = arbitrary_trace(&mut rng, 2000);
+    let mut expected = Simulator::new(&config);
+    expected.simulate(&trace)?;
+    let mut streamed = Simulator::builder(&config).async_yield_records(300).build()?;
+    assert_eq!(streamed.simulate_async(&trace[..]).await?, expected.get_result());
+    Ok(())
+}
+
+#[test]
+fn cache_contents_show_the_lines_held() -> Result<(), Box<dyn Error>> {
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 256, "line_size": 64, "kind": "2way", "replacement_policy": "lru"}
+    ]}))?;
+    let mut simulator = Simulator::new(&config);
+    simulator.simulate(&trace_of_reads(&[0x1000, 0x1040, 0x1080, 0x1000]))?;
+    let contents = simulator.cache_contents(0).unwrap();
+    assert_eq!((contents.sets, contents.ways), (2, 2));
+    let held: Vec<(u64, u64, Option<u64>)> = contents.lines.iter().map(|line| (line.set, line.way, line.address)).collect();
+    assert_eq!(held, [(0, 0, Some(0x1080)), (0, 1, Some(0x1000)), (1, 0, Some(0x1040)), (1, 1, None)]);
+    // The first line used is as old as an empty line, so was evicted by the third read
+    assert_eq!(contents.lines[1].policy, Some(serde_json::json!({"recency": 1})));
+    assert!(simulator.cache_contents(1).is_none());
+    let dumped: serde_json::Value = serde_json::from_str(&simulator.contents_json()?)?;
+    assert_eq!(dumped[0]["lines"][2]["address"], 0x1040);
+    assert!(dumped[0]["lines"][2].get("dirty").is_none());
+    // Lines written since they were read are dirty, when writes are simulated
+    let mut config = config;
+    config.simulate_writes = true;
+    let mut simulator = Simulator::new(&config);
+    let trace = [("W", 0x1000), ("R", 0x1040)].iter()
+        .map(|(mode, address)| format!("{:016x} {address:016x} {mode} 001\n", 0x400000))
+        .collect::<String>()
+        .into_bytes();
+    simulator.simulate(&trace)?;
+    let dirty: Vec<(Option<u64>, bool)> = simulator.cache_contents(0).unwrap().lines.iter().map(|line| (line.address, line.dirty)).collect();
+    assert_eq!(dirty, [(Some(0x1000), true), (None, false), (Some(0x1040), false), (None, false)]);
+    Ok(())
+}
+
+#[test]
+fn replayed_decisions_match_the_recording() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2433);
+    let config = arbitrary_config(&mut rng);
+    let trace = arbitrary_trace(&mut rng, 2000);
+    let mut recorded = Simulator::builder(&config).record_decisions(true).build()?;
+    recorded.simulate(&trace)?;
+    assert_eq!(recorded.get_result(), Simulator::new(&config).simulate(&trace)?);
+    let log = DecisionLog::from_bytes(&recorded.decision_log().unwrap().to_bytes())?;
+    assert_eq!(&log, recorded.decision_log().unwrap());
+    let mut pipelined = Simulator::builder(&config).replay_decisions(log.clone()).build()?;
+    let core = pipelined.topology().cores().next().unwrap();
+    Pipeline::new(2)?.with_batch_records(333).simulate_core(&mut pipelined, &trace, core)?;
+    pipelined.finish_replay()?;
+    let mut partial = Simulator::builder(&config).replay_decisions(log).build()?;
+    partial.simulate(&trace[..1000 * 40])?;
+    assert!(partial.finish_replay().is_err());
+
+    // The first line lru places has the same time as the empty way, so the policies differ at once
+    let policy = |policy: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+        Ok(serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+            {"name": "L1", "size": 128, "line_size": 64, "kind": "2way", "replacement_policy": policy}
+        ]}))?)
+    };
+    let trace = trace_of_reads(&[0x1000, 0x2000, 0x1000, 0x3000]);
+    let mut lru = Simulator::builder(&policy("lru")?).record_decisions(true).build()?;
+    lru.simulate(&trace)?;
+    let ways: Vec<u64> = lru.decision_log().unwrap().iter().map(|decision| decision.way).collect();
+    assert_eq!(ways, [0, 0, 1, 0]);
+    let mut rr = Simulator::builder(&policy("rr")?).replay_decisions(lru.decision_log().unwrap().clone()).build()?;
+    let error = rr.simulate(&trace).unwrap_err();
+    assert_eq!(error, "Decision 2 differs from the log, way 1 of set 0 of L1 was chosen rather than way 0 of set 0 of L1");
+    Ok(())
+}
+
+#[test]
+fn policy_params_are_checked_against_the_policy() -> Result<(), Box<dyn Error>> {
+    let validate = |policy: &str, params: serde_json::Value| -> Result<Result<(), String>, Box<dyn Error>> {
+        let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+            {"name": "L1", "size": 1024, "replacement_policy": policy, "policy_params": params}
+        ]}))?;
+        Ok(config.validate())
+    };
+    validate("lfu", serde_json::json!({"counter_bits": 8}))??;
+    for value in [serde_json::json!(0), serde_json::json!(65), serde_json::json!("8")] {
+        let error = validate("lfu", serde_json::json!({"counter_bits": value}))?.unwrap_err();
+        assert!(error.contains(&format!("The policy parameter counter_bits must be a whole number from 1 to 64, got {value}")), "{error}");
+    }
+    let error = validate("lfu", serde_json::json!({"countr_bits": 8}))?.unwrap_err();
+    assert!(error.contains("Unknown parameter countr_bits for the lfu policy, accepted parameters are counter_bits, did you mean counter_bits?"), "{error}");
+    let error = validate("lfu", serde_json::json!({"ways": 8}))?.unwrap_err();
+    assert!(!error.contains("did you mean"), "{error}");
+    let error = validate("rr", serde_json::json!({"counter_bits": 8}))?.unwrap_err();
+    assert!(error.contains("Unknown parameter counter_bits for the rr policy, it doesn't accept any parameters"), "{error}");
+    Ok(())
+}
+
+#[test]
+fn lfu_counters_saturate_at_their_width() {
+    // Line 0 is used ten times and line 1 three times, which 2-bit counters can't tell apart, so
+    // the first line with the lowest count is chosen
+    let victim = |counter_bits| {
+        let mut policy = LeastFrequentlyUsed::with_counter_bits(2, counter_bits);
+        (0..10).for_each(|_| policy.update_on_read(0));
+        (0..3).for_each(|_| policy.update_on_read(1));
+        policy.get_new_line(0, 0, 2)
+    };
+    assert_eq!(victim(64), 1);
+    assert_eq!(victim(4), 1);
+    assert_eq!(victim(2), 0);
+}
+
+#[test]
+fn heap_lfu_replays_the_decisions_of_scanning_lfu() -> Result<(), Box<dyn Error>> {
+    // The scanning policy, registered so a configuration can use it for a set the heap is used for
+    register_policy(RegisteredPolicy {
+        name: "test-scan-lfu".to_string(),
+        parameters: LeastFrequentlyUsed::PARAMETERS.to_vec(),
+        factory: Arc::new(|lines, _, params| {
+            let counter_bits = params.get("counter_bits").and_then(|v| v.as_u64()).unwrap_or(u64::BITS as u64);
+            Box::new(LeastFrequentlyUsed::with_counter_bits(lines, counter_bits))
+        }),
+    })?;
+    // Skewed towards the lowest lines, so they're used more often than the rest
+    let mut rng = SplitMix64::new(2448);
+    let addresses: Vec<u64> = (0..20000).map(|_| {
+        let bound = rng.below(384) + 1;
+        0x10000 + rng.below(bound) * 64
+    }).collect();
+    let trace = trace_of_reads(&addresses);
+    for counter_bits in [64, 2] {
+        let config = |policy: &str| -> Result<LayeredCacheConfig, Box<dyn Error>> {
+            Ok(serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+                {"name": "L1", "size": 128 * 64, "line_size": 64, "kind": "full", "replacement_policy": policy,
+                    "policy_params": {"counter_bits": counter_bits}}
+            ]}))?)
+        };
+        let mut scan = Simulator::builder(&config("test-scan-lfu")?).record_decisions(true).build()?;
+        scan.simulate(&trace)?;
+        let mut heap = Simulator::builder(&config("lfu")?).replay_decisions(scan.decision_log().unwrap().clone()).build()?;
+        heap.simulate(&trace)?;
+        heap.finish_replay()?;
+        assert_eq!(heap.get_result(), scan.get_result());
+        assert!(heap.memory_usage()[0].policy > scan.memory_usage()[0].policy);
+    }
+    Ok(())
+}
+
+#[test]
+fn set_statistics_add_up_to_cache_totals() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(7);
+    let config = arbitrary_config(&mut rng);
+    let trace = arbitrary_trace(&mut rng, 2000);
+    let mut simulator = Simulator::new(&config);
+    simulator.enable_set_statistics();
+    simulator.simulate(&trace)?;
+    let statistics = simulator.set_statistics().unwrap();
+    for (cache, sets) in simulator.get_result().caches().iter().zip(statistics) {
+        assert_eq!(sets.hits().iter().sum::<u64>(), cache.hits());
+        assert_eq!(sets.misses().iter().sum::<u64>(), cache.misses());
+    }
+    Ok(())
+}
+
+#[test]
+fn pc_statistics_add_up_to_cache_totals() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(11);
+    let config = arbitrary_config(&mut rng);
+    let trace = arbitrary_trace(&mut rng, 2000);
+    let mut simulator = Simulator::builder(&config).pc_statistics(true).build()?;
+    simulator.simulate(&trace)?;
+    let statistics = simulator.pc_statistics().unwrap();
+    assert!(statistics[0].counts().len() > 1);
+    for (cache, pcs) in simulator.get_result().caches().iter().zip(statistics) {
+        assert_eq!(pcs.counts().values().map(|(hits, _)| hits).sum::<u64>(), cache.hits());
+        assert_eq!(pcs.counts().values().map(|(_, misses)| misses).sum::<u64>(), cache.misses());
+    }
+    Ok(())
+}
+
+#[test]
+fn snapshots_match_counters_exactly_and_timings_within_tolerance() -> Result<(), Box<dyn Error>> {
+    let directory = test_directory("snapshots_match_counters_exactly_and_timings_within_tolerance")?;
+    let config = serde_json::from_str(r#"{"caches": [{"name": "L1", "size": 1024, "line_size": 64, "kind": "direct"}]}"#)?;
+    let mut rng = SplitMix64::new(3);
+    let mut simulator = Simulator::new(&config);
+    simulator.simulate(&arbitrary_trace(&mut rng, 500))?;
+    let run = Snapshot::new(simulator.into_result()).with_timing("seconds", 1.0);
+    let store = SnapshotStore::new(&directory).updating(false);
+    assert!(store.check("direct", &run, Tolerance::default()).is_err());
+    store.clone().updating(true).check("direct", &run, Tolerance::default())?;
+    assert_eq!(store.names()?, ["direct"]);
+    assert_eq!(store.load("direct")?, run);
+    store.check("direct", &run.clone().with_timing("seconds", 1.05), Tolerance::default())?;
+    let error = store.check("direct", &run.clone().with_timing("seconds", 2.0), Tolerance::default()).unwrap_err();
+    assert!(error.contains("timings.seconds: expected 1, got 2"), "{error}");
+    let mut result: LayeredCacheResult = serde_json::from_value(serde_json::to_value(&run.result)?)?;
+    result.caches[0].misses += 1;
+    let mismatches = run.mismatches(&Snapshot { result, timings: run.timings.clone() }, Tolerance { timing: 1.0 });
+    let metrics: Vec<&str> = mismatches.iter().map(|delta| delta.metric.as_str()).collect();
+    assert_eq!(metrics, ["L1.misses"]);
+    assert!(store.path("../escape").is_err());
+    std::fs::remove_dir_all(&directory)?;
+    Ok(())
+}
+
+#[test]
+fn result_diffs_match_caches_by_name() -> Result<(), Box<dyn Error>> {
+    let first: LayeredCacheResult = serde_json::from_str(r#"{"main_memory_accesses": 50, "caches": [
+        {"name": "L1", "hits": 100, "misses": 100}, {"name": "L2", "hits": 50, "misses": 50}]}"#)?;
+    let second: LayeredCacheResult = serde_json::from_str(r#"{"main_memory_accesses": 50, "caches": [
+        {"name": "L1", "hits": 101, "misses": 99}, {"name": "L3", "hits": 0, "misses": 99}]}"#)?;
+    let deltas = diff_results(&first, &second);
+    let metrics: Vec<&str> = deltas.iter().map(|d| d.metric.as_str()).collect();
+    assert_eq!(metrics, ["L1.hits", "L1.misses", "L1.miss_ratio", "L2.hits", "L2.misses", "L2.miss_ratio", "L3.hits", "L3.misses", "L3.miss_ratio", "main_memory_accesses"]);
+    assert_eq!(deltas[0].relative(), Some(0.01));
+    assert!(deltas[0].exceeds(0.005) && !deltas[0].exceeds(0.02));
+    assert!(deltas[3].exceeds(1.0) && deltas[6].exceeds(1.0));
+    assert!(!deltas[9].exceeds(0.0));
+    Ok(())
+}
+
+#[test]
+fn merged_results_add_up_level_by_level() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2434);
+    let config = arbitrary_config(&mut rng);
+    let trace = arbitrary_trace(&mut rng, 2000);
+    let mut whole = Simulator::new(&config);
+    whole.simulate(&trace)?;
+    let mut first = Simulator::new(&config);
+    first.simulate(&trace[..1000 * 40])?;
+    let mut second = first.snapshot();
+    second.simulate(&trace[1000 * 40..])?;
+    let mut merged = first.get_result().clone();
+    merged.merge(second.get_result())?;
+    assert_eq!(&merged, whole.get_result());
+    assert_eq!(first.into_result() + second.into_result(), merged);
+
+    let other: LayeredCacheResult = serde_json::from_str(r#"{"main_memory_accesses": 0, "caches": [{"name": "L9", "hits": 0, "misses": 0}]}"#)?;
+    let mut single = other.clone();
+    single.caches[0].name = "L1".to_string();
+    assert_eq!(single.merge(&other).unwrap_err(), "Results can't be merged, as cache 0 is L1 in one and L9 in the other");
+    assert!(merged.merge(&other).is_err());
+    assert_eq!(&merged, whole.get_result());
+    Ok(())
+}
+
+#[test]
+fn wide_addresses_keep_their_high_bits() {
+    // Two lines which differ only above bit 63 map to the same set, so evict each other in a
+    // direct mapped cache, where a 64-bit cache would see them as the same line
+    let mut cache: Cache<NoPolicy, u128> = Cache::new(1024, 64, 16, NoPolicy);
+    let low = u128::parse_hex(b"440").unwrap();
+    let high = u128::parse_hex(b"10000000000000000000000440").unwrap();
+    assert_eq!(high, 1 << 100 | 0x440);
+    assert!(!cache.read_and_update_line(low));
+    assert!(cache.read_and_update_line(low));
+    assert_eq!(cache.access_line(high, true), LineAccess { hit: false, evicted: Some(low), way: Some(0) });
+    assert!(!cache.read_and_update_line(low));
+    assert_eq!(u64::parse_hex(b"10000000000000000"), None);
+    assert_eq!(u32::parse_hex(b"+ff"), None);
+}
+
+#[test]
+fn a_wide_trace_can_be_simulated_on_one_cache() {
+    // A program with its own trace format parses 128-bit addresses and feeds them to a cache, which
+    // keeps apart regions of memory that only differ above bit 63
+    let trace = ["1000000000000000000000000", "2000000000000000000000000", "1000000000000000000000020", "2000000000000000000000000", "0"];
+    let mut cache: Cache<RoundRobin, u128> = Cache::new(1024, 64, 4, RoundRobin::new(4));
+    let hits: Vec<bool> = trace.iter().map(|address| cache.read_and_update_line(u128::parse_hex(address.as_bytes()).unwrap())).collect();
+    assert_eq!(hits, [false, false, true, true, false]);
+    assert_eq!(cache.lines().iter().filter(|line| line.address.is_some()).count(), 3);
+}
+
+#[test]
+fn reads_from_addresses_match_the_trace_format() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2422);
+    let addresses: Vec<u64> = (0..2000).map(|_| 0x7ff0_0000 + rng.below(1 << 14)).collect();
+    let config = arbitrary_config(&mut rng);
+    let mut from_trace = Simulator::new(&config);
+    from_trace.simulate(&trace_of_reads(&addresses))?;
+    let mut from_addresses = Simulator::new(&config);
+    let core = from_addresses.topology().cores().next().unwrap();
+    from_addresses.simulate_reads(&addresses, 1, core)?;
+    assert_eq!(from_trace.get_result(), from_addresses.get_result());
+    Ok(())
+}
+
+#[test]
+fn layered_cache_hit_levels_match_the_simulator() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2436);
+    let addresses: Vec<u64> = (0..2000).map(|_| 0x7ff0_0000 + rng.below(1 << 14)).collect();
+    let config = arbitrary_config(&mut rng);
+    let mut simulator = Simulator::new(&config);
+    simulator.simulate(&trace_of_reads(&addresses))?;
+    let mut hierarchy = LayeredCache::new(&config);
+    let mut hits = vec![0; config.caches.len()];
+    let mut main_memory_accesses = 0;
+    for &address in &addresses {
+        match hierarchy.access(address, AccessKind::Read) {
+            HitLevel::Cache(cache) => hits[cache] += 1,
+            HitLevel::Memory => main_memory_accesses += 1,
+        }
+    }
+    let result = simulator.get_result();
+    assert_eq!(hits, result.caches().iter().map(|cache| cache.hits()).collect::<Vec<_>>());
+    assert_eq!(main_memory_accesses, result.main_memory_accesses());
+    assert_eq!(hierarchy.caches().len(), simulator.layered_cache().caches().len());
+    assert!(hierarchy.access_core(0, 0, AccessKind::Write, u32::MAX).is_err());
+    Ok(())
+}
+
+#[test]
+fn batches_of_lines_match_reading_them_one_at_a_time() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2445);
+    for _ in 0..20 {
+        let config = arbitrary_config(&mut rng);
+        let addresses: Vec<u64> = (0..5000).map(|_| 0x7ff0_0000 + rng.below(1 << 16)).collect();
+        let mut one_at_a_time = Simulator::new(&config);
+        one_at_a_time.simulate_reads(&addresses, 1, 0)?;
+        let mut batched = Simulator::new(&config);
+        for batch in addresses.chunks(700) {
+            batched.read_batch(batch, 0)?;
+        }
+        assert_eq!(one_at_a_time.get_result(), batched.get_result());
+        // The trace is read in batches unless something needs each access
+        let mut from_trace = Simulator::new(&config);
+        from_trace.simulate(&trace_of_reads(&addresses))?;
+        let mut observed = Simulator::builder(&config).observer(|_: CacheAccess| {}).build()?;
+        observed.simulate(&trace_of_reads(&addresses))?;
+        assert_eq!(from_trace.get_result(), batched.get_result());
+        assert_eq!(observed.get_result(), batched.get_result());
+    }
+    let mut cache: Cache<LeastRecentlyUsed> = Cache::new(1024, 64, 4, LeastRecentlyUsed::new(16));
+    let mut counters = HitMissCounters::default();
+    cache.read_and_update_lines(&[0, 64, 0, 0x40_0000, 64], &mut counters);
+    assert_eq!(counters, HitMissCounters { hits: 2, misses: 3 });
+    Ok(())
+}
+
+#[test]
+fn output_schema_is_only_versioned_when_extended() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2438);
+    let mut config = arbitrary_config(&mut rng);
+    for cache in &mut config.caches {
+        cache.prefetcher = None;
+    }
+    let trace = arbitrary_trace(&mut rng, 1000);
+    let mut simulator = Simulator::new(&config);
+    simulator.simulate(&trace)?;
+    let legacy = serde_json::to_value(simulator.output())?;
+    assert_eq!(legacy, serde_json::to_value(simulator.get_result())?);
+    assert!(legacy.get("schema_version").is_none());
+
+    let mut intervals = Simulator::builder(&config).interval_statistics(250).build()?;
+    intervals.simulate(&trace)?;
+    let json = serde_json::to_string(&intervals.output())?;
+    let extended = SimulationOutput::from_json(&json)?;
+    assert_eq!(extended.schema_version, SCHEMA_VERSION);
+    assert_eq!(extended.intervals.len(), 4);
+    assert_eq!(&extended.result(), intervals.get_result());
+    assert_eq!(SimulationOutput::from_json(&serde_json::to_string(&legacy)?)?.schema_version, 1);
+
+    // The configuration is metadata beyond the specification, so raises the version too
+    let configured = SimulationOutput::from_json(&serde_json::to_string(&simulator.output().with_config(&config))?)?;
+    assert_eq!(configured.schema_version, SCHEMA_VERSION);
+    assert_eq!(configured.config.as_ref(), Some(&config));
+    assert_eq!(&configured.result(), simulator.get_result());
+
+    let newer = json.replacen(&format!("\"schema_version\":{SCHEMA_VERSION}"), "\"schema_version\":99", 1);
+    assert!(SimulationOutput::from_json(&newer).unwrap_err().starts_with("The output is schema version 99"));
+    Ok(())
+}
+
+proptest::proptest! {
+    #![proptest_config(proptest::test_runner::Config::with_cases(64))]
+
+    #[test]
+    fn generated_traces_match_the_reference(config in config_strategy(), trace in trace_strategy(300)) {
+        check_against_reference(&config, &trace)?;
+    }
+}
+
+#[test]
+fn addresses_parse_the_same_with_simd_and_the_lookup_table() {
+    let mut rng = SplitMix64::new(2440);
+    for _ in 0..10000 {
+        let address = rng.next_u64() >> rng.below(64);
+        for digits in [format!("{address:016x}"), format!("{address:016X}")] {
+            let digits: &[u8; 16] = digits.as_bytes().try_into().unwrap();
+            assert_eq!(parse_address(digits), address);
+            assert_eq!(parse_address_with_lookup(digits), address);
+        }
+    }
+}
+
+#[test]
+fn compact_lru_evicts_the_same_lines_as_timestamps() {
+    let mut rng = SplitMix64::new(2441);
+    for ways in [1, 2, 4, 8, 16] {
+        let (size, line_size) = (64 * 64, 64);
+        let sets = size / line_size / ways;
+        let mut timestamps: Cache<LeastRecentlyUsed> = Cache::new(size, line_size, sets, LeastRecentlyUsed::new(size / line_size));
+        let mut compact: Cache<CompactLeastRecentlyUsed> = Cache::new(size, line_size, sets, CompactLeastRecentlyUsed::new(size / line_size, ways));
+        for step in 0..20000 {
+            let address = 0x7ff0_0000 + rng.below(4 * size);
+            if step % 1000 == 999 {
+                timestamps.advance_policy_clock();
+                compact.advance_policy_clock();
+            }
+            assert_eq!(timestamps.access_line(address, true), compact.access_line(address, true), "{ways} ways, step {step}");
+        }
+    }
+}
+
+#[test]
+fn address_zero_misses_in_an_empty_cache() {
+    let mut cache: Cache<NoPolicy> = Cache::new(1024, 64, 16, NoPolicy);
+    assert_eq!(cache.get_uninitialised_line_count(), 16);
+    assert!(!cache.read_and_update_line(0));
+    assert!(cache.read_and_update_line(0x3f));
+    assert_eq!(cache.get_uninitialised_line_count(), 15);
+    assert_eq!(cache.access_line(0x400, true), LineAccess { hit: false, evicted: Some(0), way: Some(0) });
+    assert!(cache.invalidate_line(0x400));
+    assert!(!cache.read_and_update_line(0x400));
+    assert_eq!(cache.lines()[0].address, Some(0x400));
+    assert_eq!(cache.lines()[1].address, None);
+}
+
+#[test]
+fn tags_widen_when_they_dont_fit_in_32_bits() {
+    let mut cache: Cache<RoundRobin> = Cache::new(1024, 64, 1, RoundRobin::new(1));
+    let high = 0xffff_ffff_ffff_ffc0;
+    assert!(cache.has_narrow_tags());
+    assert!(!cache.read_and_update_line(0x40_0040));
+    assert!(!cache.invalidate_line(high));
+    assert!(cache.has_narrow_tags());
+    assert!(!cache.read_and_update_line(high));
+    assert!(!cache.has_narrow_tags());
+    assert!(cache.read_and_update_line(0x40_0040));
+    assert!(cache.read_and_update_line(high + 0x3f));
+    let addresses: Vec<_> = cache.lines().iter().filter_map(|line| line.address).collect();
+    assert_eq!(addresses, vec![0x40_0040, high]);
+    assert!(!Cache::<NoPolicy, u32>::new(1024, 64, 16, NoPolicy).has_narrow_tags());
+}
+
+#[test]
+fn indexed_tags_find_the_same_lines_as_scanning() {
+    let mut rng = SplitMix64::new(2444);
+    let mut scanned: Cache<LeastRecentlyUsed> = Cache::new(4096, 64, 1, LeastRecentlyUsed::new(64)).with_tag_index(false);
+    let mut indexed: Cache<LeastRecentlyUsed> = Cache::new(4096, 64, 1, LeastRecentlyUsed::new(64));
+    assert!(indexed.has_tag_index() && !scanned.has_tag_index());
+    for _ in 0..20_000 {
+        let address = rng.below(96) * 64;
+        match rng.below(8) {
+            0 => assert_eq!(scanned.invalidate_line(address), indexed.invalidate_line(address)),
+            1 => assert_eq!(scanned.fill_line(address), indexed.fill_line(address)),
+            _ => assert_eq!(scanned.access_line(address, true), indexed.access_line(address, true)),
+        }
+    }
+    assert_eq!(scanned.lines(), indexed.lines());
+    // Indexing a cache already holding lines indexes them
+    let mut reindexed = scanned.clone().with_tag_index(true);
+    for address in (0..96).map(|line| line * 64) {
+        assert_eq!(scanned.read_and_update_line(address), reindexed.read_and_update_line(address));
+    }
+    assert!(!Cache::<NoPolicy>::new(4096, 64, 2, NoPolicy).with_tag_index(true).has_tag_index());
+}
+
+#[test]
+fn simd_scans_find_the_same_lines_as_scalar_scans() {
+    fn compare<const WAYS: u64>(ways: u64) {
+        let mut rng = SplitMix64::new(2450 + ways);
+        let lines = 16 * ways;
+        let mut scalar: Cache<RoundRobin, u64, WAYS> = Cache::new(lines * 64, 64, 16, RoundRobin::new(16)).with_simd_scan(false);
+        let mut simd: Cache<RoundRobin, u64, WAYS> = Cache::new(lines * 64, 64, 16, RoundRobin::new(16));
+        assert!(!scalar.has_simd_scan());
+        assert_eq!(simd.has_simd_scan(), cfg!(any(target_arch = "x86_64", target_arch = "aarch64")));
+        for _ in 0..20_000 {
+            // Three times as many lines as the cache holds, including those with a tag of 0
+            let address = rng.below(3 * lines) * 64;
+            match rng.below(8) {
+                0 => assert_eq!(scalar.invalidate_line(address), simd.invalidate_line(address)),
+                1 => assert_eq!(scalar.fill_line(address), simd.fill_line(address)),
+                _ => assert_eq!(scalar.access_line(address, true), simd.access_line(address, true)),
+            }
+        }
+        assert_eq!(scalar.lines(), simd.lines());
+    }
+    compare::<8>(8);
+    compare::<0>(8);
+    compare::<0>(16);
+    assert!(!Cache::<NoPolicy>::new(4096, 64, 16, NoPolicy).has_simd_scan());
+}
+
+#[test]
+fn memory_usage_counts_tags_and_policy_state() -> Result<(), Box<dyn Error>> {
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": "32KiB", "line_size": 64, "kind": "full", "replacement_policy": "lfu"},
+        {"name": "L2", "size": "1MiB", "line_size": 64, "kind": "8way", "replacement_policy": "lru"},
+    ]}))?;
+    let usage = Simulator::new(&config).memory_usage();
+    // 32-bit tags, a valid bit per line, and a tag index of twice as many slots as lines, each of
+    // a tag and a line. The lfu policy of so many ways keeps a heap of lines, and their positions
+    // in it, alongside their uses
+    assert_eq!(usage[0], MemoryUsage { tags: 512 * 4 + 64 + 1024 * 16, policy: 512 * (8 + 4 + 4) });
+    // The recency order of each set packed into a word
+    assert_eq!(usage[1], MemoryUsage { tags: 16384 * 4 + 2048, policy: 2048 * 8 });
+    let mut cache: Cache<LeastRecentlyUsed> = Cache::new(4096, 64, 16, LeastRecentlyUsed::new(64));
+    assert_eq!(cache.memory_usage(), MemoryUsage { tags: 64 * 4 + 8, policy: 64 * 8 });
+    cache.read_and_update_line(1 << 50);
+    assert_eq!(cache.memory_usage().tags, 64 * 8 + 8);
+    Ok(())
+}
+
+#[test]
+fn verifying_invariants_finds_a_policy_out_of_step_with_the_valid_lines() -> Result<(), Box<dyn Error>> {
+    // Hierarchies which keep their invariants pass every check, with the same results as without
+    let mut rng = SplitMix64::new(2479);
+    let trace = arbitrary_trace(&mut rng, 3000);
+    for (l2, l3, writeback) in [("inclusive", "nine", false), ("exclusive", "inclusive", false), ("nine", "exclusive", true)] {
+        let mut l1 = serde_json::json!({"name": "L1", "size": 512, "line_size": 64, "kind": "2way", "replacement_policy": "lru"});
+        if writeback {
+            l1["writeback_buffer"] = serde_json::json!({"entries": 2});
+        }
+        let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+            l1,
+            {"name": "L2", "size": 2048, "line_size": 64, "kind": "4way", "replacement_policy": "lfu", "inclusivity": l2},
+            {"name": "L3", "size": 8192, "line_size": 64, "kind": "full", "replacement_policy": "rr", "inclusivity": l3}
+        ]}))?;
+        let mut verified = Simulator::builder(&config).verify_invariants(1).build()?;
+        assert_eq!(verified.simulate(&trace)?, Simulator::new(&config).simulate(&trace)?);
+    }
+
+    // Fills the ways it believes are empty first, but isn't told when a line is invalidated
+    #[derive(Clone)]
+    struct Occupancy {
+        occupied: Vec<u64>,
+    }
+    impl ReplacementPolicy for Occupancy {
+        fn get_new_line(&mut self, set_lower_bound_index: u64, set: u64, cache_lines_per_set: u64) -> u64 {
+            let occupied = &mut self.occupied[set as usize];
+            let way = (0..cache_lines_per_set).find(|way| *occupied & 1 << way == 0).unwrap_or(0);
+            *occupied |= 1 << way;
+            set_lower_bound_index + way
+        }
+
+        fn check_set(&self, _set_lower_bound_index: u64, set: u64, valid: &[bool]) -> Result<(), String> {
+            let valid = valid.iter().rev().fold(0, |ways, &valid| ways << 1 | valid as u64);
+            match self.occupied[set as usize] {
+                occupied if occupied == valid => Ok(()),
+                occupied => Err(format!("the policy has ways {occupied:#b} occupied, but ways {valid:#b} are valid")),
+            }
+        }
+    }
+    register_policy(RegisteredPolicy {
+        name: "test-occupancy".to_string(),
+        parameters: Vec::new(),
+        factory: Arc::new(|_, sets, _| Box::new(Occupancy { occupied: vec![0; sets as usize] })),
+    })?;
+    let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+        {"name": "L1", "size": 256, "line_size": 64, "kind": "2way", "replacement_policy": "test-occupancy"},
+        {"name": "L2", "size": 128, "line_size": 64, "kind": "full", "replacement_policy": "rr", "inclusivity": "inclusive"}
+    ]}))?;
+    // The third read evicts the first from L2, which invalidates it in set 0 of L1, and nothing
+    // after it is simulated
+    let mut simulator = Simulator::builder(&config).verify_invariants(1).build()?;
+    let error = simulator.simulate(&trace_of_reads(&[0x0, 0x40, 0x80, 0xc0, 0x100])).unwrap_err();
+    let l1 = &simulator.get_result().caches()[0];
+    assert_eq!(l1.hits() + l1.misses(), 3);
+    let (description, dump) = error.split_once("\nThe sets concerned held:\n").unwrap();
+    assert_eq!(description, "An invariant was broken by access 3 to a line: Set 0 of cache L1 is inconsistent: the policy has ways 0b11 occupied, but ways 0b10 are valid");
+    let dump: serde_json::Value = serde_json::from_str(dump)?;
+    assert_eq!(dump[0]["cache"], "L1");
+    assert_eq!(dump[0]["lines"].as_array().unwrap().iter().map(|line| line["address"].clone()).collect::<Vec<_>>(), [serde_json::Value::Null, 0x80.into()]);
+    // Checked less often, the broken invariant is only found at the next check
+    let mut sparse = Simulator::builder(&config).verify_invariants(2).build()?;
+    sparse.simulate(&trace_of_reads(&[0x0, 0x40, 0x80]))?;
+    assert!(sparse.simulate(&trace_of_reads(&[0x40])).unwrap_err().starts_with("An invariant was broken by access 4 to a line"));
+    Ok(())
+}
+
+#[test]
+fn shadows_miss_as_the_caches_they_describe_would() -> Result<(), Box<dyn Error>> {
+    let mut rng = SplitMix64::new(2480);
+    let trace = arbitrary_trace(&mut rng, 3000);
+    let config_of = |l1: serde_json::Value, l2: serde_json::Value| serde_json::from_value::<LayeredCacheConfig>(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [l1, l2]}));
+    let l1 = serde_json::json!({"name": "L1", "size": 512, "line_size": 64, "kind": "2way", "replacement_policy": "rr"});
+    let l2 = serde_json::json!({"name": "L2", "size": 2048, "line_size": 64, "kind": "4way", "replacement_policy": "lfu", "policy_params": {"counter_bits": 2}});
+    let mut shadowed = (l1.clone(), l2.clone());
+    shadowed.0["shadow"] = serde_json::json!({"replacement_policy": "lru"});
+    shadowed.1["shadow"] = serde_json::json!({"size": "4KiB", "kind": "8way"});
+    let config = config_of(shadowed.0, shadowed.1)?;
+    config.validate()?;
+    let result = Simulator::new(&config).simulate(&trace)?.clone();
+
+    // The shadows leave the hierarchy as it was
+    let plain = Simulator::new(&config_of(l1.clone(), l2.clone())?).simulate(&trace)?.clone();
+    for (cache, plain) in result.caches().iter().zip(plain.caches()) {
+        assert_eq!((cache.hits(), cache.misses()), (plain.hits(), plain.misses()));
+        assert_eq!(plain.shadow(), None);
+    }
+    assert_eq!(result.main_memory_accesses(), plain.main_memory_accesses());
+
+    // Each shadow counts what the cache with its settings would, the L2 keeping its counter_bits
+    let lru_l1 = Simulator::new(&config_of(serde_json::json!({"name": "L1", "size": 512, "line_size": 64, "kind": "2way", "replacement_policy": "lru"}), l2.clone())?).simulate(&trace)?.clone();
+    let large_l2 = Simulator::new(&config_of(l1, serde_json::json!({"name": "L2", "size": 4096, "line_size": 64, "kind": "8way", "replacement_policy": "lfu", "policy_params": {"counter_bits": 2}}))?).simulate(&trace)?.clone();
+    assert_eq!(result.caches()[0].shadow(), Some((lru_l1.caches()[0].hits(), lru_l1.caches()[0].misses())));
+    assert_eq!(result.caches()[1].shadow(), Some((large_l2.caches()[1].hits(), large_l2.caches()[1].misses())));
+    assert_eq!(result.caches()[1].shadow_miss_delta(), Some(large_l2.caches()[1].misses() as i64 - result.caches()[1].misses() as i64));
+
+    // A shadow has to fit its ways into its lines
+    let mut config = config;
+    config.caches[0].shadow.as_mut().unwrap().kind = Some(CacheKindConfig::SixteenWay);
+    assert_eq!(config.validate().unwrap_err(), "The shadow of cache L1 has 8 lines, too few for 16 ways");
+    Ok(())
+}
+
+#[test]
+fn dinero_options_map_onto_a_configuration() -> Result<(), Box<dyn Error>> {
+    let args = |line: &str| line.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+    let options = parse_dinero_options(&args("-l1-isize 8k -l1-ibsize 32 -l1-dsize 16K -l1-dbsize 32 -l1-dassoc 2 -l1-dwback n -l1-dwalloc n -l2-usize 1m -l2-ubsize 64 -l2-uassoc 16 -l2-urepl f -l2-uccc -informat d -skipcount 10 -flushcount 1k"))?;
+    let names: Vec<&str> = options.config.caches.iter().map(|cache| cache.name.as_str()).collect();
+    assert_eq!(names, ["l1-icache", "l1-dcache", "l2-ucache"]);
+    let [icache, dcache, ucache] = &options.config.caches[..] else { unreachable!() };
+    assert!(icache.instructions && !dcache.instructions);
+    assert_eq!((icache.size, icache.line_size, icache.kind), (8192, 32, CacheKindConfig::Direct));
+    assert_eq!((dcache.kind, dcache.write_policy, dcache.write_allocate), (CacheKindConfig::TwoWay, WritePolicyConfig::WriteThrough, false));
+    assert_eq!((ucache.size, ucache.kind, &ucache.replacement_policy), (1 << 20, CacheKindConfig::SixteenWay, &ReplacementPolicyConfig::RoundRobin));
+    assert_eq!(options.config.context_switches.as_ref().map(|switches| switches.interval), Some(1024));
+    assert_eq!((options.skip_count, options.max_count, options.ignored.len()), (10, None, 1));
+    // Fetches go through the instruction cache, and other references around it, so a read of the
+    // fetched line only finds it in the unified cache. The write hitting the write-through data
+    // cache hits the unified cache too, and the write missing it isn't allocated, so the read
+    // after it misses the data cache again
+    let mut simulator = Simulator::new(&options.config);
+    let accesses = ["2 400000", "0 0x1000", "1 1004", "", "3 400000", "1 2000", "0 2000"].iter().filter_map(|record| parse_din_record(record).transpose()).collect::<Result<Vec<_>, _>>()?;
+    let result = simulator.simulate_accesses(accesses, 0)?;
+    let counts: Vec<(u64, u64)> = result.caches().iter().map(|cache| (cache.hits(), cache.misses())).collect();
+    assert_eq!(counts, [(0, 1), (1, 4), (3, 3)]);
+    // A dirty block evicted from a write-back cache is written to the level below
+    let options = parse_dinero_options(&args("-l1-usize 64 -l1-ubsize 32 -l2-usize 1k -l2-ubsize 32"))?;
+    let mut simulator = Simulator::new(&options.config);
+    let accesses = ["1 0", "0 40"].iter().filter_map(|record| parse_din_record(record).transpose()).collect::<Result<Vec<_>, _>>()?;
+    let result = simulator.simulate_accesses(accesses, 0)?;
+    assert_eq!(result.caches()[0].writebacks(), 1);
+    assert_eq!((result.caches()[1].hits(), result.caches()[1].misses()), (1, 2));
+
+    assert_eq!(parse_dinero_options(&args("-l1-usize 8k -l1-ubsize 32 -l1-uassoc 3")).unwrap_err(), "Cache l1-ucache is 3-way set associative, but only 1, 2, 4, 8 and 16 ways or fully associative caches are supported");
+    assert_eq!(parse_dinero_options(&args("-l1-usize 8k -l1-ubsize 32 -l1-urepl r")).unwrap_err(), "Cache l1-ucache uses random replacement, which isn't supported, -l1-urepl must be l or f");
+    assert_eq!(parse_dinero_options(&args("-l1-usize 8k -l1-ubsize 32 -l3-usize 1m -l3-ubsize 64")).unwrap_err(), "There's no level 2 cache, but there is a level 3 cache");
+    assert_eq!(parse_dinero_options(&args("-l1-usize 8k")).unwrap_err(), "Cache l1-ucache has no block size, give -l1-ubsize");
+    assert_eq!(parse_dinero_options(&args("-l1-usize 8k -l1-ubsize 32 -l1-uwalloc n")).unwrap_err(), "Cache l1-ucache is write-back without write allocation, which isn't supported, -l1-uwalloc n needs -l1-uwback n");
+    assert_eq!(parse_din_record("4 0").unwrap_err(), "Flush records, with label 4, aren't supported");
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use crate::config::{InclusivityConfig, LayeredCacheConfig};
+use crate::prelude::*;
+
+/// The resolved connections between the caches of a configuration
+///
+/// Caches are referred to by their index in the configuration. A configuration which doesn't use
+/// `next` or `shared_by` anywhere is a single core (core 0) with the caches in a chain, in the order
+/// they are listed. Otherwise each cache passes its misses to the cache named by `next`, or to main
+/// memory if it has none, and serves the cores listed in `shared_by`, or every core if it has none.
+///
+/// Instruction caches are first levels beside the data caches, which only instruction fetches
+/// pass through. In a chain they are left out of the order, each passing its misses to the second
+/// data cache, the first shared with data accesses, or to main memory if there is only one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    /// For each core, in ascending order, the caches an access passes through, from the first
+    /// level to the last
+    paths: Vec<(u32, Vec<usize>)>,
+    /// For each core with an instruction cache, in ascending order, the caches an instruction
+    /// fetch passes through
+    instruction_paths: Vec<(u32, Vec<usize>)>,
+    /// The cache each cache passes its misses to
+    next: Vec<Option<usize>>,
+    /// The caches which pass their misses to each cache, directly or indirectly
+    above: Vec<Vec<usize>>,
+    /// The caches each cache's misses pass through, from the next cache to the last level
+    below: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    /// Resolves and checks the topology of a configuration
+    ///
+    /// This checks that every core has a single first level, and at most one instruction cache,
+    /// that the links between caches form chains without cycles, that every cache in a core's chain
+    /// serves that core, and that every cache is used by some core. The line sizes of inclusive and
+    /// exclusive caches are also checked against the caches above them.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration
+    ///
+    /// returns: Result<Topology, String>, with a description of the first problem found
+    pub fn from_config(config: &LayeredCacheConfig) -> Result<Self, String> {
+        let caches = &config.caches;
+        let explicit = caches.iter().any(|c| c.next.is_some() || c.shared_by.is_some());
+        let next: Vec<Option<usize>> = if explicit {
+            caches.iter()
+                .map(|cache| cache.next.as_ref().map(|name| {
+                    caches.iter()
+                        .position(|c| &c.name == name)
+                        .ok_or(format!("Cache {} has next cache {name}, but there is no cache with that name", cache.name))
+                }).transpose())
+                .collect::<Result<_, _>>()?
+        } else {
+            let data: Vec<usize> = (0..caches.len()).filter(|&i| !caches[i].instructions).collect();
+            let mut next = vec![None; caches.len()];
+            for pair in data.windows(2) {
+                next[pair[0]] = Some(pair[1]);
+            }
+            for (next, _) in next.iter_mut().zip(caches).filter(|(_, cache)| cache.instructions) {
+                *next = data.get(1).copied();
+            }
+            next
+        };
+        if let Some((above, below)) = next.iter().enumerate().find_map(|(i, next)| next.filter(|&n| caches[n].instructions).map(|n| (i, n))) {
+            return Err(format!("Cache {} passes its misses to instruction cache {}, which only serves cores' instruction fetches", caches[above].name, caches[below].name));
+        }
+        let mut cores: Vec<u32> = caches.iter().flat_map(|c| c.shared_by.iter().flatten().copied()).collect();
+        cores.sort_unstable();
+        cores.dedup();
+        if cores.is_empty() {
+            cores.push(0);
+        }
+        let serves = |cache: usize, core: u32| match &caches[cache].shared_by {
+            Some(cores) => cores.contains(&core),
+            None => true,
+        };
+        // Follows the misses from a first level cache of a core down to main memory
+        let follow = |core: u32, entry: usize| {
+            let mut path = vec![entry];
+            while let Some(following) = next[*path.last().unwrap()] {
+                if path.contains(&following) {
+                    return Err(format!("The caches used by core {core} form a cycle at cache {}", caches[following].name));
+                }
+                if !serves(following, core) {
+                    return Err(format!("Cache {} passes misses from core {core} to cache {}, which isn't shared by that core", caches[*path.last().unwrap()].name, caches[following].name));
+                }
+                path.push(following);
+            }
+            Ok(path)
+        };
+        let names = |entries: &[usize]| entries.iter().map(|&i| caches[i].name.as_str()).collect::<Vec<_>>().join(", ");
+        let mut paths = Vec::with_capacity(cores.len());
+        let mut instruction_paths = Vec::new();
+        for core in cores {
+            let (instruction_entries, entries): (Vec<usize>, Vec<usize>) = (0..caches.len())
+                .filter(|&i| serves(i, core) && !(0..caches.len()).any(|j| serves(j, core) && next[j] == Some(i)))
+                .partition(|&i| caches[i].instructions);
+            let entry = match entries.as_slice() {
+                [entry] => *entry,
+                [] => return Err(format!("Core {core} has no first level cache, check for cycles between caches")),
+                _ => return Err(format!("Core {core} has more than one first level cache: {}", names(&entries))),
+            };
+            match instruction_entries.as_slice() {
+                [] => {}
+                [entry] => instruction_paths.push((core, follow(core, *entry)?)),
+                _ => return Err(format!("Core {core} has more than one instruction cache: {}", names(&instruction_entries))),
+            }
+            paths.push((core, follow(core, entry)?));
+        }
+        // Fetches are routed by the first level data cache they would otherwise read, so the cores
+        // sharing one must share their instruction cache too
+        for (core, path) in &paths {
+            let instruction_path = |core: &u32| instruction_paths.iter().find(|(c, _)| c == core).map(|(_, path)| path);
+            if let Some((other, _)) = paths.iter().find(|(other, other_path)| other < core && other_path[0] == path[0] && instruction_path(other) != instruction_path(core)) {
+                return Err(format!("Cores {other} and {core} share first level cache {}, so must share an instruction cache too", caches[path[0]].name));
+            }
+        }
+        if let Some(unused) = (0..caches.len()).find(|i| !paths.iter().chain(&instruction_paths).any(|(_, path)| path.contains(i))) {
+            return Err(format!("Cache {} isn't used by any core", caches[unused].name));
+        }
+        let above = (0..caches.len())
+            .map(|cache| (0..caches.len()).filter(|&other| {
+                let mut current = next[other];
+                while let Some(c) = current {
+                    if c == cache {
+                        return true;
+                    }
+                    // Paths are acyclic by this point, so this terminates
+                    current = next[c];
+                }
+                false
+            }).collect())
+            .collect();
+        let below = (0..caches.len())
+            .map(|cache| core::iter::successors(next[cache], |&c| next[c]).collect())
+            .collect();
+        let topology = Self { paths, instruction_paths, next, above, below };
+        topology.validate_inclusivity(config)?;
+        Ok(topology)
+    }
+
+    /// Checks that inclusive and exclusive caches can hold the lines of the caches above them
+    fn validate_inclusivity(&self, config: &LayeredCacheConfig) -> Result<(), String> {
+        for (index, cache) in config.caches.iter().enumerate() {
+            let directly_above: Vec<_> = (0..config.caches.len())
+                .filter(|&i| self.next[i] == Some(index))
+                .map(|i| &config.caches[i])
+                .collect();
+            if cache.inclusivity != InclusivityConfig::Nine && directly_above.is_empty() {
+                return Err(format!("Cache {} is inclusive or exclusive, but there are no caches above it", cache.name));
+            }
+            for above in directly_above {
+                match cache.inclusivity {
+                    InclusivityConfig::Inclusive if cache.line_size < above.line_size => {
+                        return Err(format!("Cache {} is inclusive, but its lines are smaller than those of cache {} above it, so it can't hold them", cache.name, above.name));
+                    }
+                    InclusivityConfig::Exclusive if cache.line_size != above.line_size => {
+                        return Err(format!("Cache {} is exclusive, so lines move between it and cache {} above it, but their line sizes differ", cache.name, above.name));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the cores, in ascending order
+    pub fn cores(&self) -> impl Iterator<Item = u32> + '_ {
+        self.paths.iter().map(|(core, _)| *core)
+    }
+
+    /// Gets the caches an access from a core passes through, from the first level to the last, or
+    /// None if the core doesn't exist
+    pub fn path(&self, core: u32) -> Option<&[usize]> {
+        self.paths.iter().find(|(c, _)| *c == core).map(|(_, path)| path.as_slice())
+    }
+
+    /// Gets the caches an instruction fetch from a core passes through, from its instruction cache
+    /// to the last level, or None if the core has no instruction cache, in which case fetches take
+    /// the same path as data accesses
+    pub fn instruction_path(&self, core: u32) -> Option<&[usize]> {
+        self.instruction_paths.iter().find(|(c, _)| *c == core).map(|(_, path)| path.as_slice())
+    }
+
+    /// Whether any core has an instruction cache
+    pub fn has_instruction_caches(&self) -> bool {
+        !self.instruction_paths.is_empty()
+    }
+
+    /// Gets the cache a cache passes its misses to, or None if it passes them to main memory
+    pub fn next(&self, cache: usize) -> Option<usize> {
+        self.next[cache]
+    }
+
+    /// Gets the caches which pass their misses to a cache, directly or indirectly
+    pub fn above(&self, cache: usize) -> &[usize] {
+        &self.above[cache]
+    }
+
+    /// Gets the caches a cache's misses pass through, from the next cache to the last level, which
+    /// is empty if it passes them to main memory
+    pub fn below(&self, cache: usize) -> &[usize] {
+        &self.below[cache]
+    }
+
+    /// Whether a cache passes its misses to main memory
+    pub fn is_last_level(&self, cache: usize) -> bool {
+        self.next[cache].is_none()
+    }
+}
@@ -0,0 +1,105 @@
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use serde_json::json;
+use crate::config::LayeredCacheConfig;
+use crate::config::migration::CURRENT_CONFIG_VERSION;
+use crate::simulator::Simulator;
+pub use super::reference::simulate_reference;
+
+/// The size of a page, for accesses which cross from one page to the next
+pub const PAGE_SIZE: u64 = 4096;
+/// The largest size a record can hold, as the size is 3 decimal digits
+pub const MAX_ACCESS_SIZE: u16 = 999;
+
+/// Generates valid cache hierarchies, in the same way as
+/// [arbitrary_config](crate::config::arbitrary::arbitrary_config)
+///
+/// The hierarchy is a chain of one to three levels, with power of two sizes, line sizes which
+/// don't decrease with level, and at least as many lines as ways. Failures shrink towards fewer,
+/// smaller levels.
+///
+/// returns: impl Strategy<Value = LayeredCacheConfig>
+pub fn config_strategy() -> impl Strategy<Value = LayeredCacheConfig> {
+    let level = (
+        any::<bool>(),
+        0..6u32,
+        prop::sample::select(vec!["direct", "full", "2way", "4way", "8way"]),
+        prop::sample::select(vec!["rr", "lru", "lfu"]),
+        prop::option::of(1..=8u64),
+    );
+    (prop::sample::select(vec![16u64, 32, 64]), prop::collection::vec(level, 1..=3)).prop_map(|(mut line_size, levels)| {
+        let mut caches = Vec::new();
+        for (index, (wider_lines, lines_log2, kind, policy, counter_bits)) in levels.into_iter().enumerate() {
+            if index > 0 && wider_lines {
+                line_size *= 2;
+            }
+            // At least 8 lines, so every kind has at least one set
+            let lines = 1u64 << (3 + lines_log2);
+            let mut cache = json!({
+                "name": format!("L{}", index + 1),
+                "size": lines * line_size,
+                "line_size": line_size,
+                "kind": kind,
+                "replacement_policy": policy,
+            });
+            if let (Some(counter_bits), "lfu") = (counter_bits, policy) {
+                cache["policy_params"] = json!({"counter_bits": counter_bits});
+            }
+            caches.push(cache);
+        }
+        serde_json::from_value(json!({"version": CURRENT_CONFIG_VERSION, "caches": caches}))
+            .expect("Generated configurations should always be well formed")
+    })
+}
+
+/// Generates a record in the format of the specification, including its newline
+///
+/// Most records access a small region, so lines are reused and caches see hits. The rest are edge
+/// cases: accesses which cross a page boundary, accesses of the largest size, and accesses near
+/// the bottom and top of the address space, including address 0.
+///
+/// returns: impl Strategy<Value = String>
+pub fn record_strategy() -> impl Strategy<Value = String> {
+    const REGION: u64 = 0x7ff0_0000_0000;
+    let access = prop_oneof![
+        6 => (REGION..REGION + (1 << 16), prop::sample::select(vec![1u16, 2, 4, 8, 16, 64, 128])),
+        // Start within the last few bytes of a page, so the access spills into the next
+        2 => (1..64u64, 0..256u64, 2..=128u16).prop_map(|(before, page, size)| (REGION + (page + 1) * PAGE_SIZE - before.min(size as u64 - 1), size)),
+        1 => (REGION..REGION + (1 << 16), Just(MAX_ACCESS_SIZE)),
+        // Leave room for the last line of the access, so its end doesn't overflow
+        1 => (u64::MAX - (1 << 20)..u64::MAX - 2 * PAGE_SIZE, 1..=MAX_ACCESS_SIZE),
+        1 => (0..PAGE_SIZE, 1..=64u16),
+    ];
+    (0x400000..0x400100u64, access, prop::sample::select(vec!['R', 'W', 'M'])).prop_map(|(pc, (address, size), mode)| {
+        format!("{pc:016x} {address:016x} {mode} {size:03}\n")
+    })
+}
+
+/// Generates well formed traces of up to `max_records` records, see [record_strategy]
+///
+/// # Arguments
+///
+/// * `max_records`: The largest number of records in a trace, which is at least 1
+///
+/// returns: impl Strategy<Value = Vec<u8>>
+pub fn trace_strategy(max_records: usize) -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(record_strategy(), 1..=max_records.max(1)).prop_map(|records| records.concat().into_bytes())
+}
+
+/// Checks the simulator gives the same results as the [reference](simulate_reference) for a
+/// configuration and trace, for use within a proptest
+///
+/// # Arguments
+///
+/// * `config`: A configuration using only the features the reference models, as generated by
+///   [config_strategy]
+/// * `trace`: A well formed trace
+///
+/// returns: Result<(), TestCaseError>, a failure describing the difference if the results differ
+pub fn check_against_reference(config: &LayeredCacheConfig, trace: &[u8]) -> Result<(), TestCaseError> {
+    let expected = simulate_reference(config, trace).map_err(TestCaseError::fail)?;
+    let mut simulator = Simulator::new(config);
+    let actual = simulator.simulate(trace).map_err(TestCaseError::fail)?;
+    prop_assert_eq!(actual, &expected);
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use crate::config::{CacheKindConfig, InclusivityConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+use crate::simulator::{CacheResult, LayeredCacheResult};
+
+/// A line of a [ReferenceCache]
+#[derive(Debug, Clone, Copy, Default)]
+struct ReferenceLine {
+    /// The line address held, or None if the line is empty
+    line: Option<u64>,
+    last_used: u64,
+    uses: u64,
+}
+
+/// A deliberately naive cache, storing each set as a list of lines
+#[derive(Debug)]
+struct ReferenceCache {
+    line_size: u64,
+    policy: ReplacementPolicyConfig,
+    max_uses: u64,
+    sets: Vec<Vec<ReferenceLine>>,
+    round_robin: Vec<usize>,
+    clock: u64,
+}
+
+impl ReferenceCache {
+    fn access(&mut self, address: u64) -> bool {
+        let line = address / self.line_size;
+        let set_index = (line % self.sets.len() as u64) as usize;
+        let set = &mut self.sets[set_index];
+        if let Some(way) = set.iter_mut().find(|way| way.line == Some(line)) {
+            way.last_used = self.clock;
+            self.clock += 1;
+            way.uses = (way.uses + 1).min(self.max_uses);
+            return true;
+        }
+        // Ties go to the lowest way, and empty lines look like lines last used at time 0
+        let victim = match self.policy {
+            ReplacementPolicyConfig::LeastRecentlyUsed => (0..set.len()).min_by_key(|&i| set[i].last_used).unwrap(),
+            ReplacementPolicyConfig::LeastFrequentlyUsed => (0..set.len()).min_by_key(|&i| set[i].uses).unwrap(),
+            _ => {
+                let victim = self.round_robin[set_index];
+                self.round_robin[set_index] = (victim + 1) % set.len();
+                victim
+            }
+        };
+        set[victim] = ReferenceLine { line: Some(line), last_used: self.clock, uses: 1 };
+        self.clock += 1;
+        false
+    }
+}
+
+/// Simulates a trace with a straightforward model of the cache hierarchy, to check the optimised
+/// [Simulator](crate::simulator::Simulator) against
+///
+/// Only chains of caches without prefetchers, and which are neither inclusive nor exclusive, are
+/// supported, as produced by [arbitrary_config](crate::config::arbitrary::arbitrary_config).
+///
+/// # Arguments
+///
+/// * `config`: A validated configuration
+/// * `trace`: The trace, in the format of the specification
+///
+/// returns: Result<LayeredCacheResult, String>, an error if the configuration uses a feature the
+/// reference doesn't model
+pub fn simulate_reference(config: &LayeredCacheConfig, trace: &[u8]) -> Result<LayeredCacheResult, String> {
+    let mut caches = Vec::new();
+    for cache in &config.caches {
+        if cache.prefetcher.is_some() || cache.inclusivity != InclusivityConfig::Nine || cache.next.is_some() || cache.shared_by.is_some() {
+            return Err(format!("Cache {} uses a feature the reference simulator doesn't model", cache.name));
+        }
+        let lines = cache.size / cache.line_size;
+        let ways = match cache.kind {
+            CacheKindConfig::Direct => 1,
+            CacheKindConfig::Full => lines,
+            CacheKindConfig::TwoWay => 2,
+            CacheKindConfig::FourWay => 4,
+            CacheKindConfig::EightWay => 8,
+            CacheKindConfig::SixteenWay => 16,
+        };
+        let counter_bits = cache.policy_params.get("counter_bits").and_then(|v| v.as_u64()).unwrap_or(64);
+        caches.push(ReferenceCache {
+            line_size: cache.line_size,
+            policy: cache.replacement_policy.clone(),
+            max_uses: u64::MAX >> (64 - counter_bits),
+            sets: vec![vec![ReferenceLine::default(); ways as usize]; (lines / ways) as usize],
+            round_robin: vec![0; (lines / ways) as usize],
+            clock: 0,
+        });
+    }
+    let mut counts = vec![(0, 0); caches.len()];
+    for record in trace.split(|&b| b == b'\n').filter(|record| !record.is_empty()) {
+        let record = std::str::from_utf8(record).map_err(|e| format!("The trace isn't text: {e}"))?;
+        let fields: Vec<&str> = record.split_whitespace().collect();
+        let [_, address, mode, size] = fields[..] else {
+            return Err(format!("Malformed trace record {record}"));
+        };
+        let address = u64::from_str_radix(address, 16).map_err(|e| format!("Malformed address in {record}: {e}"))?;
+        let size: u64 = size.parse().map_err(|e| format!("Malformed size in {record}: {e}"))?;
+        // Accesses are split into lines of the first level
+        let first_line_size = caches[0].line_size;
+        let mut line_address = address - address % first_line_size;
+        while line_address < address + size {
+            read_line(&mut caches, &mut counts, line_address);
+            // Writes are reads without a writeback buffer, so a modify reads each line twice
+            if mode == "M" {
+                read_line(&mut caches, &mut counts, line_address);
+            }
+            line_address += first_line_size;
+        }
+    }
+    Ok(LayeredCacheResult {
+        main_memory_accesses: counts.last().map_or(0, |(_, misses)| *misses),
+        main_memory_writes: 0,
+        caches: config.caches.iter()
+            .zip(counts)
+            .map(|(cache, (hits, misses))| CacheResult { name: cache.name.clone(), hits, misses, ..CacheResult::default() })
+            .collect(),
+        page_crossing_accesses: 0,
+    })
+}
+
+/// Reads a line from the first cache, and on a miss from those after it, counting the hits and
+/// misses of each. A miss in a cache with larger lines than the next reads every line of the next
+/// within the line missed
+fn read_line(caches: &mut [ReferenceCache], counts: &mut [(u64, u64)], line_address: u64) {
+    let (Some((cache, below)), Some(((hits, misses), below_counts))) = (caches.split_first_mut(), counts.split_first_mut()) else {
+        return;
+    };
+    if cache.access(line_address) {
+        *hits += 1;
+        return;
+    }
+    *misses += 1;
+    match below.first().map(|next| next.line_size) {
+        Some(next_line_size) if next_line_size < cache.line_size => {
+            let first = line_address - line_address % cache.line_size;
+            for line in (first..first + cache.line_size).step_by(next_line_size as usize) {
+                read_line(below, below_counts, line);
+            }
+        }
+        _ => read_line(below, below_counts, line_address),
+    }
+}
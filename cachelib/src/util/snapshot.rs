@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::simulator::diff::MetricDelta;
+use crate::simulator::{CacheResult, LayeredCacheResult};
+
+/// The environment variable which, when set to anything but `0`, makes
+/// [check](SnapshotStore::check) record each snapshot rather than assert against it
+pub const UPDATE_SNAPSHOTS_VARIABLE: &str = "CACHESIM_UPDATE_SNAPSHOTS";
+
+/// The results of a run recorded as a golden snapshot, which later runs are checked against
+///
+/// A snapshot is stored as the result in the format of the specification, with any timings
+/// alongside under `timings`, so an expected output of the examples is a snapshot without timings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(flatten)]
+    pub result: LayeredCacheResult,
+    /// Measurements which vary between runs or machines, such as the seconds taken or the average
+    /// memory access time, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub timings: BTreeMap<String, f64>,
+}
+
+/// How far a run may stray from its snapshot
+///
+/// Counters must always match exactly. Timings may differ by a fraction of their recorded value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// The largest allowed relative change of a timing, such as 0.1 for 10%
+    pub timing: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self { timing: 0.1 }
+    }
+}
+
+impl Snapshot {
+    /// Creates a snapshot of a result with no timings
+    pub fn new(result: LayeredCacheResult) -> Self {
+        Self { result, timings: BTreeMap::new() }
+    }
+
+    /// Adds a timing to the snapshot, replacing any of the same name
+    pub fn with_timing(mut self, name: &str, value: f64) -> Self {
+        self.timings.insert(name.to_string(), value);
+        self
+    }
+
+    /// Reads a snapshot from a file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("Couldn't read the snapshot {}: {e}", path.display()))?;
+        serde_json::from_str(&json).map_err(|e| format!("Couldn't parse the snapshot {}: {e}", path.display()))
+    }
+
+    /// Writes the snapshot to a file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|e| format!("Couldn't create the snapshot directory {}: {e}", directory.display()))?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("Couldn't serialise the snapshot {e}"))?;
+        fs::write(path, json + "\n").map_err(|e| format!("Couldn't write the snapshot {}: {e}", path.display()))
+    }
+
+    /// Finds the metrics of a run which stray from the snapshot by more than the tolerance
+    ///
+    /// Every counter of each cache, matched by name, and of the whole result is compared exactly.
+    /// A cache or timing in only one of the two is always a mismatch.
+    ///
+    /// # Arguments
+    ///
+    /// * `actual`: The snapshot of the run
+    /// * `tolerance`: How far each kind of metric may stray
+    ///
+    /// returns: Vec<MetricDelta>, the recorded value first and the run's second
+    pub fn mismatches(&self, actual: &Snapshot, tolerance: Tolerance) -> Vec<MetricDelta> {
+        let mut deltas = counter_deltas(&self.result, &actual.result);
+        deltas.retain(|delta| delta.first != delta.second);
+        let mut names: Vec<&String> = self.timings.keys().collect();
+        names.extend(actual.timings.keys().filter(|name| !self.timings.contains_key(*name)));
+        deltas.extend(names.into_iter()
+            .map(|name| MetricDelta { metric: format!("timings.{name}"), first: self.timings.get(name).copied(), second: actual.timings.get(name).copied() })
+            .filter(|delta| delta.exceeds(tolerance.timing)));
+        deltas
+    }
+}
+
+/// Gets a counter of a cache's result
+type Counter = fn(&CacheResult) -> u64;
+
+/// Each counter of a cache's result, by the name of its field
+const COUNTERS: [(&str, Counter); 14] = [
+    ("hits", CacheResult::hits),
+    ("misses", CacheResult::misses),
+    ("prefetches", CacheResult::prefetches),
+    ("writebacks", CacheResult::writebacks),
+    ("writeback_stalls", CacheResult::writeback_stalls),
+    ("writeback_buffer_hits", CacheResult::writeback_buffer_hits),
+    ("disturbed_hits", |cache| cache.disturbed().0),
+    ("disturbed_misses", |cache| cache.disturbed().1),
+    ("write_hits", |cache| cache.writes().0),
+    ("write_misses", |cache| cache.writes().1),
+    ("fetch_hits", |cache| cache.fetches().0),
+    ("fetch_misses", |cache| cache.fetches().1),
+    ("shadow_hits", |cache| cache.shadow().map_or(0, |(hits, _)| hits)),
+    ("shadow_misses", |cache| cache.shadow().map_or(0, |(_, misses)| misses)),
+];
+
+/// Lines up every counter of two results, matching caches by name, in the order of the first
+/// result followed by any caches only in the second
+fn counter_deltas(first: &LayeredCacheResult, second: &LayeredCacheResult) -> Vec<MetricDelta> {
+    let mut names: Vec<&str> = first.caches().iter().map(|c| c.name()).collect();
+    for cache in second.caches() {
+        if !names.contains(&cache.name()) {
+            names.push(cache.name());
+        }
+    }
+    let mut deltas = Vec::with_capacity(names.len() * COUNTERS.len() + 2);
+    for name in names {
+        let find = |result: &LayeredCacheResult| result.caches().iter().find(|c| c.name() == name).cloned();
+        let (first, second) = (find(first), find(second));
+        for (counter, value) in COUNTERS {
+            deltas.push(MetricDelta {
+                metric: format!("{name}.{counter}"),
+                first: first.as_ref().map(|cache| value(cache) as f64),
+                second: second.as_ref().map(|cache| value(cache) as f64),
+            });
+        }
+    }
+    let whole = |metric: &str, value: fn(&LayeredCacheResult) -> u64| MetricDelta {
+        metric: metric.to_string(),
+        first: Some(value(first) as f64),
+        second: Some(value(second) as f64),
+    };
+    deltas.push(whole("main_memory_accesses", LayeredCacheResult::main_memory_accesses));
+    deltas.push(whole("page_crossing_accesses", LayeredCacheResult::page_crossing_accesses));
+    deltas
+}
+
+/// A directory of named golden snapshots, each stored as `<name>.json`
+///
+/// Runs are checked against the snapshot of their name with [check](Self::check), which instead
+/// records the run when updating, so a fork adding a policy can keep the expected outputs of its
+/// own runs next to its tests, and refresh them when a change is intended.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    directory: PathBuf,
+    update: bool,
+}
+
+impl SnapshotStore {
+    /// Opens a directory of snapshots, which is created when the first is recorded
+    ///
+    /// Snapshots are updated rather than checked if [UPDATE_SNAPSHOTS_VARIABLE] is set.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let update = std::env::var(UPDATE_SNAPSHOTS_VARIABLE).is_ok_and(|value| value != "0");
+        Self { directory: directory.into(), update }
+    }
+
+    /// Sets whether [check](Self::check) records runs rather than checking them, overriding the
+    /// environment
+    pub fn updating(mut self, update: bool) -> Self {
+        self.update = update;
+        self
+    }
+
+    /// Gets the path of a snapshot
+    ///
+    /// returns: Result<PathBuf, String>, an error if the name has characters other than letters,
+    /// digits, `_` and `-`, so every snapshot stays within the directory
+    pub fn path(&self, name: &str) -> Result<PathBuf, String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Snapshot names can only use letters, digits, _ and -, not {name:?}"));
+        }
+        Ok(self.directory.join(format!("{name}.json")))
+    }
+
+    /// Lists the names of the snapshots recorded, in order
+    pub fn names(&self) -> Result<Vec<String>, String> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Couldn't read the snapshot directory {}: {e}", self.directory.display())),
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok()?.strip_suffix(".json").map(str::to_string))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Records a snapshot under a name, replacing any already recorded
+    pub fn record(&self, name: &str, snapshot: &Snapshot) -> Result<(), String> {
+        snapshot.save(&self.path(name)?)
+    }
+
+    /// Reads the snapshot recorded under a name
+    pub fn load(&self, name: &str) -> Result<Snapshot, String> {
+        Snapshot::load(&self.path(name)?)
+    }
+
+    /// Checks a run against the snapshot of its name, or records it when updating
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the snapshot
+    /// * `actual`: The snapshot of the run
+    /// * `tolerance`: How far each kind of metric may stray
+    ///
+    /// returns: Result<(), String>, an error listing the metrics which strayed too far, or if
+    /// there's no snapshot of the name
+    pub fn check(&self, name: &str, actual: &Snapshot, tolerance: Tolerance) -> Result<(), String> {
+        if self.update {
+            return self.record(name, actual);
+        }
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Err(format!("There is no snapshot {name} in {}, set {UPDATE_SNAPSHOTS_VARIABLE}=1 to record it", self.directory.display()));
+        }
+        let mismatches = Snapshot::load(&path)?.mismatches(actual, tolerance);
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        let show = |value: Option<f64>| value.map_or("missing".to_string(), |value| value.to_string());
+        let lines: Vec<String> = mismatches.iter()
+            .map(|delta| format!("  {}: expected {}, got {}", delta.metric, show(delta.first), show(delta.second)))
+            .collect();
+        Err(format!("The run differs from snapshot {name}, set {UPDATE_SNAPSHOTS_VARIABLE}=1 if the change is intended\n{}", lines.join("\n")))
+    }
+}
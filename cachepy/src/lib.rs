@@ -0,0 +1,152 @@
+//! # CachePy
+//!
+//! Python bindings for cachelib, built as the `cachelib` module with maturin, so experiments can
+//! drive the simulator from Python without shelling out and parsing JSON
+//!
+//! Configurations can be given as dicts, in the same format as configuration files, or as JSON
+//! strings, and results are returned as dicts in the format of the specification. Traces are read
+//! from any object supporting the buffer protocol, such as `bytes`, `bytearray`, `memoryview` or a
+//! numpy array. Only `bytes` are read in place, as anything else could be changed by another thread
+//! during the simulation.
+
+// The code pyo3 generates for functions returning PyResult converts the error into itself
+#![allow(clippy::useless_conversion)]
+
+use pyo3::buffer::{Element, PyBuffer};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+use serde::Serialize;
+use cachelib::config::{config_from_value, load_layered_config_with_warnings, LayeredCacheConfig};
+use cachelib::simulator::Simulator;
+
+/// Converts a dict or JSON string from Python into a JSON value
+fn to_value(config: &Bound<'_, PyAny>) -> PyResult<serde_json::Value> {
+    let json = match config.downcast::<PyString>() {
+        Ok(json) => json.to_string(),
+        Err(_) => config.py().import_bound("json")?.call_method1("dumps", (config,))?.extract()?,
+    };
+    serde_json::from_str(&json).map_err(|e| PyValueError::new_err(format!("Couldn't parse the config: {e}")))
+}
+
+/// Converts a value into Python dicts, lists and numbers
+fn to_python<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(|e| PyValueError::new_err(format!("Couldn't serialise the output {e}")))?;
+    Ok(py.import_bound("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// Copies the contents of an object supporting the buffer protocol
+///
+/// Simulating releases the GIL, and any buffer but `bytes`, such as a bytearray, numpy array or
+/// mmap, could then be written or resized by another thread while the simulator reads it, so is
+/// copied first
+fn copy_buffer<T: Element + Copy>(buffer: &Bound<'_, PyAny>) -> PyResult<Vec<T>> {
+    PyBuffer::<T>::get_bound(buffer)?.to_vec(buffer.py())
+}
+
+/// Parses and validates a configuration, returning it with its defaults filled in, along with any
+/// deprecation warnings
+///
+/// Configurations can't use `include` or variables, use load_config to load files
+#[pyfunction]
+fn parse_config(py: Python<'_>, config: &Bound<'_, PyAny>) -> PyResult<(PyObject, Vec<String>)> {
+    let (config, warnings) = config_from_value(to_value(config)?).map_err(PyValueError::new_err)?;
+    Ok((to_python(py, &config)?, warnings))
+}
+
+/// Loads a configuration from files, merging each over the previous ones, in the same way as the
+/// command line, returning it with any deprecation warnings
+#[pyfunction]
+#[pyo3(signature = (*paths))]
+fn load_config(py: Python<'_>, paths: Vec<String>) -> PyResult<(PyObject, Vec<String>)> {
+    let (config, warnings) = load_layered_config_with_warnings(&paths).map_err(PyValueError::new_err)?;
+    Ok((to_python(py, &config)?, warnings))
+}
+
+/// A simulator for a configuration, which keeps the contents of its caches between calls
+#[pyclass(name = "Simulator", module = "cachelib")]
+struct PySimulator {
+    simulator: Simulator,
+    config: LayeredCacheConfig,
+}
+
+impl PySimulator {
+    fn core(&self, core: Option<u32>) -> u32 {
+        core.unwrap_or_else(|| self.simulator.topology().cores().next().unwrap())
+    }
+}
+
+#[pymethods]
+impl PySimulator {
+    /// Creates a simulator with empty caches from a configuration dict or JSON string
+    #[new]
+    fn new(config: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let (config, _) = config_from_value(to_value(config)?).map_err(PyValueError::new_err)?;
+        Ok(PySimulator { simulator: Simulator::new(&config), config })
+    }
+
+    /// Simulates a trace in the 40 byte record format, such as the contents of a trace file, and
+    /// returns the results so far
+    ///
+    /// `core` is the core making the accesses, for configurations with per-core caches, defaulting
+    /// to the lowest numbered core. A `bytes` trace isn't copied, any other buffer is, and other
+    /// Python threads can run during the simulation.
+    #[pyo3(signature = (trace, core = None))]
+    fn simulate(&mut self, py: Python<'_>, trace: &Bound<'_, PyAny>, core: Option<u32>) -> PyResult<PyObject> {
+        let copy: Vec<u8>;
+        let bytes = match trace.downcast::<PyBytes>() {
+            Ok(bytes) => bytes.as_bytes(),
+            Err(_) => {
+                copy = copy_buffer(trace)?;
+                &copy
+            }
+        };
+        if !bytes.len().is_multiple_of(40) {
+            return Err(PyValueError::new_err("The trace ends part way through a record"));
+        }
+        let core = self.core(core);
+        let simulator = &mut self.simulator;
+        py.allow_threads(|| simulator.simulate_core(bytes, core).map(|_| ())).map_err(PyValueError::new_err)?;
+        self.result(py)
+    }
+
+    /// Simulates a read of `size` bytes at each address in a buffer of unsigned 64-bit integers,
+    /// such as a numpy array of dtype uint64, and returns the results so far
+    ///
+    /// The addresses are copied before simulating them.
+    #[pyo3(signature = (addresses, size = 8, core = None))]
+    fn simulate_reads(&mut self, py: Python<'_>, addresses: &Bound<'_, PyAny>, size: u16, core: Option<u32>) -> PyResult<PyObject> {
+        let addresses: Vec<u64> = copy_buffer(addresses)?;
+        let core = self.core(core);
+        let simulator = &mut self.simulator;
+        py.allow_threads(|| simulator.simulate_reads(&addresses, size, core).map(|_| ())).map_err(PyValueError::new_err)?;
+        self.result(py)
+    }
+
+    /// The results so far
+    #[getter]
+    fn result(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_python(py, self.simulator.get_result())
+    }
+
+    /// The configuration, with its defaults filled in
+    #[getter]
+    fn config(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_python(py, &self.config)
+    }
+
+    /// The average memory access time in cycles so far, or None if the configuration doesn't have
+    /// the latencies of every cache
+    fn average_memory_access_time(&self) -> Option<f64> {
+        self.simulator.get_result().average_memory_access_time(&self.config)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "cachelib")]
+fn cachelib_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySimulator>()?;
+    m.add_function(wrap_pyfunction!(parse_config, m)?)?;
+    m.add_function(wrap_pyfunction!(load_config, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::process::ExitCode;
+use clap::Args;
+use cachelib::analysis::{AssociativityPoint, AssociativityReport, AssociativitySweep, DEFAULT_ASSOCIATIVITIES};
+use cachelib::config::{load_layered_config_with_warnings, CacheKindConfig, LayeredCacheConfig};
+use cachelib::ensemble::Ensemble;
+use crate::report::{miss_rate, ReportArgs};
+use crate::trace::map_trace;
+
+/// Arguments for the analyze associativity subcommand
+#[derive(Args, Debug)]
+pub struct AssociativityArgs {
+    /// The path to the JSON configuration file
+    config: String,
+
+    /// The path to the trace file
+    trace: String,
+
+    /// The cache to vary. Defaults to the last cache in the configuration
+    #[arg(long, value_name = "NAME")]
+    cache: Option<String>,
+
+    /// The associativities to compare, separated by commas
+    #[arg(long, value_delimiter = ',', default_values_t = DEFAULT_ASSOCIATIVITIES)]
+    ways: Vec<u64>,
+
+    /// Spread the associativities across this many threads, when they can't be simulated in one
+    /// pass
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Simulates a trace with one cache of a configuration at each of a list of associativities, and
+/// fully associative, keeping its capacity, and reports the conflict misses removed by each step
+pub fn run(args: &AssociativityArgs) -> Result<ExitCode, String> {
+    let (config, warnings) = load_layered_config_with_warnings(&[&args.config])?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let index = match &args.cache {
+        Some(name) => config.caches.iter().position(|c| &c.name == name).ok_or(format!("There is no cache named {name}"))?,
+        None => config.caches.len() - 1,
+    };
+    let trace = map_trace(&args.trace)?;
+    let cache = &config.caches[index];
+    let report = if AssociativitySweep::matches_simulation(&config, index, &trace)? {
+        let mut sweep = AssociativitySweep::new(cache.line_size, cache.size, &args.ways)?;
+        sweep.simulate(&trace)?;
+        sweep.report()
+    } else {
+        simulate_variants(&config, index, &args.ways, args.threads, &trace)?
+    };
+    args.report.write(&report, || vec![report_rows(&report).into()])?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Simulates a copy of the configuration for each associativity, and fully associative, in a
+/// single pass over the trace
+fn simulate_variants(config: &LayeredCacheConfig, index: usize, ways: &[u64], threads: usize, trace: &[u8]) -> Result<AssociativityReport, String> {
+    let cache = &config.caches[index];
+    let lines = cache.size / cache.line_size;
+    let mut ways = ways.to_vec();
+    ways.sort_unstable();
+    ways.dedup();
+    let kinds = ways.iter()
+        .map(|&ways| kind_with_ways(ways, lines).map(|kind| (format!("{ways}-way"), kind)).ok_or(format!("Cache {} can't have {ways} ways, only 1, 2, 4, 8, 16, or all of its {lines} lines", cache.name)))
+        .chain([Ok(("fully associative".to_string(), CacheKindConfig::Full))]);
+    let mut variants = Vec::with_capacity(ways.len() + 1);
+    for kind in kinds {
+        let (label, kind) = kind?;
+        let mut variant = config.clone();
+        variant.caches[index].kind = kind;
+        variant.validate().map_err(|e| format!("With cache {} {label}: {e}", cache.name))?;
+        variants.push(variant);
+    }
+    let mut ensemble = Ensemble::new(&variants).with_threads(threads);
+    ensemble.simulate(trace)?;
+    let results = ensemble.into_results();
+    let counts: Vec<(u64, u64, u64)> = ways.iter().zip(&results)
+        .map(|(&ways, result)| (ways, result.caches()[index].hits(), result.caches()[index].misses()))
+        .collect();
+    let full = &results.last().unwrap().caches()[index];
+    Ok(AssociativityReport::new(cache.size, cache.line_size, &counts, (full.hits(), full.misses())))
+}
+
+/// Gets the kind of cache with a number of ways, if there is one
+fn kind_with_ways(ways: u64, lines: u64) -> Option<CacheKindConfig> {
+    match ways {
+        _ if ways == lines => Some(CacheKindConfig::Full),
+        1 => Some(CacheKindConfig::Direct),
+        2 => Some(CacheKindConfig::TwoWay),
+        4 => Some(CacheKindConfig::FourWay),
+        8 => Some(CacheKindConfig::EightWay),
+        16 => Some(CacheKindConfig::SixteenWay),
+        _ => None,
+    }
+}
+
+/// Lays out a report with a row for each associativity, followed by fully associative
+fn report_rows(report: &AssociativityReport) -> Vec<Vec<String>> {
+    let row = |ways: String, point: &AssociativityPoint| vec![
+        ways,
+        point.sets.to_string(),
+        point.hits.to_string(),
+        point.misses.to_string(),
+        miss_rate(point.hits, point.misses),
+        point.misses_removed.map_or("-".to_string(), |removed| removed.to_string()),
+        point.conflict_misses.to_string(),
+    ];
+    let mut rows = vec![["ways", "sets", "hits", "misses", "miss rate", "misses removed", "conflict misses"].map(str::to_string).to_vec()];
+    rows.extend(report.points.iter().map(|point| row(point.ways.to_string(), point)));
+    rows.push(row("full".to_string(), &report.fully_associative));
+    rows
+}
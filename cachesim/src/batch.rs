@@ -0,0 +1,304 @@
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use clap::Args;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use serde::{Deserialize, Serialize};
+use cachelib::config::{load_layered_config_with_warnings, LayeredCacheConfig};
+use cachelib::simulator::{LayeredCacheResult, Simulator};
+use crate::report::{open_output, write_json_line, OutputFormat};
+use crate::trace::map_trace;
+
+/// Arguments for the batch subcommand
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// The path to the JSON manifest listing the runs
+    manifest: String,
+
+    /// Write the results to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// The format of the results, json or jsonl. With jsonl, each run is written as soon as it
+    /// finishes, so runs sharing a trace are written together rather than in the order of the
+    /// manifest
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    output_format: OutputFormat,
+
+    /// The number of runs to simulate at once. Defaults to the number of CPUs
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Append each run to this SQLite database, creating it if needed
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH")]
+    db: Option<String>,
+}
+
+/// A list of simulations to run
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub runs: Vec<ManifestRun>,
+}
+
+/// A single simulation in a manifest. Paths are relative to the manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestRun {
+    pub label: String,
+    /// A configuration file, or a list of fragments merged in order
+    pub config: ConfigPaths,
+    pub trace: String,
+}
+
+/// One or more configuration fragments
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ConfigPaths {
+    Single(String),
+    Layered(Vec<String>),
+}
+
+impl ConfigPaths {
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            ConfigPaths::Single(path) => vec![path],
+            ConfigPaths::Layered(paths) => paths.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// The results of every run in a manifest, in the order of the manifest
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub runs: Vec<BatchRunResult>,
+}
+
+/// The result of a single run in a manifest
+#[derive(Debug, Serialize)]
+pub struct BatchRunResult {
+    /// The position of the run in the manifest
+    pub index: usize,
+    pub label: String,
+    #[serde(flatten)]
+    pub outcome: RunOutcome,
+}
+
+/// The result of a run, or why it couldn't be simulated
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RunOutcome {
+    Finished {
+        /// The effective configuration of the run, with defaults filled in
+        config: LayeredCacheConfig,
+        result: LayeredCacheResult,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+impl Manifest {
+    /// Reads a manifest, resolving its paths relative to the manifest's directory
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Couldn't open the manifest at path {path}: {e}"))?;
+        let mut manifest: Manifest = serde_json::from_reader(BufReader::new(file)).map_err(|e| format!("Couldn't parse the manifest: {e}"))?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let resolve = |p: &str| directory.join(p).to_string_lossy().into_owned();
+        for run in &mut manifest.runs {
+            run.trace = resolve(&run.trace);
+            run.config = ConfigPaths::Layered(run.config.paths().into_iter().map(resolve).collect());
+        }
+        Ok(manifest)
+    }
+}
+
+/// Simulates a single run of a manifest, returning the effective configuration alongside the result
+///
+/// Each run maps its own trace, so runs are independent and can be simulated on separate threads.
+/// Runs sharing a trace still share its pages through the OS's page cache
+pub fn simulate_run(run: &ManifestRun) -> Result<(LayeredCacheConfig, LayeredCacheResult), String> {
+    let trace = map_trace(&run.trace).map_err(|e| format!("Run {}: {e}", run.label))?;
+    let (config, warnings) = load_layered_config_with_warnings(&run.config.paths()).map_err(|e| format!("Run {}: {e}", run.label))?;
+    for warning in warnings {
+        eprintln!("Warning: run {}: {warning}", run.label);
+    }
+    let mut simulator = Simulator::new(&config);
+    simulator.simulate(&trace).map_err(|e| format!("Run {}: {e}", run.label))?;
+    Ok((config, simulator.into_result()))
+}
+
+/// Runs every simulation in a manifest, writing one combined results document, or a line per run
+///
+/// Runs are simulated in parallel, on a pool of `--jobs` threads. The combined document keeps the
+/// order of the manifest, while lines are written as runs finish. A run which fails is written with
+/// its error in place of its result, without stopping the others, and the batch fails once every
+/// run has been written
+pub fn run(args: &BatchArgs) -> Result<(), String> {
+    let streaming = match args.output_format {
+        OutputFormat::Json => false,
+        OutputFormat::Jsonl => true,
+        format => return Err(format!("Batch results can only be written as json or jsonl, not {}", format!("{format:?}").to_lowercase())),
+    };
+    let manifest = Manifest::load(&args.manifest)?;
+    #[cfg(feature = "sqlite")]
+    let mut db = args.db.as_deref().map(crate::report::db::ResultsDatabase::open).transpose()?;
+    // Runs often share a trace, which only needs fingerprinting once
+    #[cfg(feature = "sqlite")]
+    let mut fingerprints = std::collections::HashMap::new();
+    let mut output = open_output(args.output.as_deref())?;
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()
+        .map_err(|e| format!("Couldn't create the worker threads: {e}"))?;
+    let (sender, receiver) = mpsc::channel();
+    let mut results: Vec<Option<BatchRunResult>> = manifest.runs.iter().map(|_| None).collect();
+    let mut failed = 0;
+    thread::scope(|scope| {
+        let runs = &manifest.runs;
+        let workers = scope.spawn(move || pool.install(|| {
+            runs.par_iter().enumerate().try_for_each_with(sender, |sender, (index, run)| {
+                let outcome = match simulate_run(run) {
+                    Ok((config, result)) => RunOutcome::Finished { config, result },
+                    Err(error) => RunOutcome::Failed { error },
+                };
+                // The receiver only hangs up if writing the output failed, so stop the other runs
+                sender.send(BatchRunResult { index, label: run.label.clone(), outcome })
+                    .map_err(|_| "The output was closed".to_string())
+            })
+        }));
+        for run_result in receiver {
+            if let RunOutcome::Failed { error } = &run_result.outcome {
+                eprintln!("{error}");
+                failed += 1;
+            }
+            #[cfg(feature = "sqlite")]
+            if let (Some(db), RunOutcome::Finished { config, result }) = (&mut db, &run_result.outcome) {
+                let run = &manifest.runs[run_result.index];
+                let trace = match fingerprints.entry(run.trace.clone()) {
+                    std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                    std::collections::hash_map::Entry::Vacant(entry) => entry.insert(crate::provenance::TraceFingerprint::of(&run.trace, &map_trace(&run.trace)?, false)),
+                };
+                db.record("batch", &run.label, config, trace.clone(), result)?;
+            }
+            if streaming {
+                write_json_line(&mut output, &run_result)?;
+            } else {
+                let index = run_result.index;
+                results[index] = Some(run_result);
+            }
+        }
+        workers.join().map_err(|_| "A worker thread panicked".to_string())?
+    })?;
+    if !streaming {
+        let document = BatchResult { runs: results.into_iter().map(Option::unwrap).collect() };
+        let json = serde_json::to_string_pretty(&document).map_err(|e| format!("Couldn't serialise the output {e}"))?;
+        writeln!(output, "{json}").and_then(|_| output.flush()).map_err(|e| format!("Couldn't write the output: {e}"))?;
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{failed} of {} runs failed", manifest.runs.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use clap::Parser;
+    use serde_json::Value;
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: BatchArgs,
+    }
+
+    /// Writes a manifest of runs of increasingly large caches, with a trace and configurations
+    /// alongside it, returning its directory
+    fn write_manifest(name: &str, runs: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("cachesim-batch-{name}-{}", std::process::id()));
+        fs::create_dir_all(directory.join("configs")).unwrap();
+        let trace: String = (0..256u64).map(|i| format!("{:016x} {:016x} R 004\n", 0x400000, i % 32 * 64)).collect();
+        fs::write(directory.join("trace.out"), trace).unwrap();
+        for size in [512, 1024, 2048, 4096] {
+            fs::write(directory.join(format!("configs/{size}.json")), format!(r#"{{"caches": [{{"name": "L1", "size": {size}}}]}}"#)).unwrap();
+        }
+        fs::write(directory.join("manifest.json"), runs).unwrap();
+        directory
+    }
+
+    fn run_batch(directory: &Path, format: &str) -> (Result<(), String>, String) {
+        let output = directory.join("output");
+        let args = Cli::parse_from(["batch", directory.join("manifest.json").to_str().unwrap(), "--output", output.to_str().unwrap(), "--output-format", format, "--jobs", "4"]).args;
+        let result = run(&args);
+        let output = fs::read_to_string(output).unwrap();
+        fs::remove_dir_all(directory).unwrap();
+        (result, output)
+    }
+
+    #[test]
+    fn paths_in_a_manifest_are_relative_to_it() {
+        let directory = write_manifest("paths", r#"{"runs": [
+            {"label": "single", "config": "configs/512.json", "trace": "trace.out"},
+            {"label": "layered", "config": ["configs/512.json", "configs/1024.json"], "trace": "trace.out"}
+        ]}"#);
+        let manifest = Manifest::load(directory.join("manifest.json").to_str().unwrap()).unwrap();
+        let resolve = |path: &str| directory.join(path).to_string_lossy().into_owned();
+        assert_eq!(manifest.runs[0].config.paths(), [resolve("configs/512.json")]);
+        assert_eq!(manifest.runs[1].config.paths(), [resolve("configs/512.json"), resolve("configs/1024.json")]);
+        assert!(manifest.runs.iter().all(|run| run.trace == resolve("trace.out")));
+        fs::write(directory.join("manifest.json"), r#"{"runs": [{"label": "a", "config": "c.json", "trace": "t.out", "trcae": "t.out"}]}"#).unwrap();
+        assert!(Manifest::load(directory.join("manifest.json").to_str().unwrap()).unwrap_err().contains("unknown field `trcae`"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_failed_run_does_not_stop_the_others() {
+        let directory = write_manifest("failure", r#"{"runs": [
+            {"label": "small", "config": "configs/512.json", "trace": "trace.out"},
+            {"label": "missing", "config": "configs/512.json", "trace": "missing.out"},
+            {"label": "large", "config": "configs/4096.json", "trace": "trace.out"}
+        ]}"#);
+        let (result, output) = run_batch(&directory, "json");
+        assert_eq!(result, Err("1 of 3 runs failed".to_string()));
+        let runs = serde_json::from_str::<Value>(&output).unwrap()["runs"].as_array().unwrap().clone();
+        assert_eq!(runs.len(), 3);
+        assert!(runs[1]["error"].as_str().unwrap().starts_with("Run missing: Couldn't open the trace file"));
+        assert!(runs[1].get("result").is_none());
+        assert_eq!(runs[0]["result"]["caches"][0]["misses"], 256);
+        assert_eq!(runs[2]["result"]["caches"][0]["misses"], 32);
+    }
+
+    #[test]
+    fn the_document_keeps_the_order_of_the_manifest_and_lines_give_their_index() {
+        let runs = r#"{"runs": [
+            {"label": "4096", "config": "configs/4096.json", "trace": "trace.out"},
+            {"label": "512", "config": "configs/512.json", "trace": "trace.out"},
+            {"label": "2048", "config": "configs/2048.json", "trace": "trace.out"},
+            {"label": "1024", "config": "configs/1024.json", "trace": "trace.out"}
+        ]}"#;
+        let labels = ["4096", "512", "2048", "1024"];
+        let (result, output) = run_batch(&write_manifest("document", runs), "json");
+        result.unwrap();
+        let document: Value = serde_json::from_str(&output).unwrap();
+        for (index, run) in document["runs"].as_array().unwrap().iter().enumerate() {
+            assert_eq!((run["index"].as_u64(), run["label"].as_str()), (Some(index as u64), Some(labels[index])));
+            assert_eq!(run["config"]["caches"][0]["size"].to_string(), labels[index]);
+        }
+        let (result, output) = run_batch(&write_manifest("lines", runs), "jsonl");
+        result.unwrap();
+        let mut indices: Vec<u64> = output.lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .inspect(|run| assert_eq!(run["label"].as_str(), Some(labels[run["index"].as_u64().unwrap() as usize])))
+            .map(|run| run["index"].as_u64().unwrap())
+            .collect();
+        indices.sort();
+        assert_eq!(indices, [0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::process::ExitCode;
+use std::time::Instant;
+use clap::Args;
+use serde::Serialize;
+use cachelib::config::load_layered_config_with_warnings;
+use cachelib::simulator::Simulator;
+use crate::report::{ReportArgs, Table};
+
+/// Arguments for the bench subcommand
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// The path to the JSON configuration file
+    config: String,
+
+    /// The path to the trace file
+    trace: String,
+
+    /// The number of timed runs
+    #[arg(short = 'n', long, default_value_t = 10)]
+    runs: usize,
+
+    /// The number of untimed runs before the timed runs, to warm up the CPU's caches and clock
+    #[arg(short, long, default_value_t = 2)]
+    warmup: usize,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Summary statistics of a measurement over the timed runs
+#[derive(Debug, Serialize)]
+pub struct Statistics {
+    pub min: f64,
+    pub median: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+/// The results of a benchmark
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub runs: usize,
+    pub accesses: usize,
+    pub seconds: Statistics,
+    pub accesses_per_second: Statistics,
+    pub megabytes_per_second: Statistics,
+}
+
+impl Statistics {
+    fn of(samples: &[f64]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let middle = sorted.len() / 2;
+        let median = if sorted.len().is_multiple_of(2) { (sorted[middle - 1] + sorted[middle]) / 2.0 } else { sorted[middle] };
+        let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+        let variance = sorted.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (sorted.len() - 1).max(1) as f64;
+        Statistics { min: sorted[0], median, max: sorted[sorted.len() - 1], mean, stddev: variance.sqrt() }
+    }
+}
+
+/// Simulates a trace repeatedly, reporting the spread of the time taken and the throughput
+///
+/// The trace is read into memory first, so disk speed doesn't affect the measurements, and each
+/// run uses a new simulator, so starts with empty caches. Only the simulation itself is timed.
+pub fn run(args: &BenchArgs) -> Result<ExitCode, String> {
+    if args.runs == 0 {
+        return Err("At least one timed run is needed".to_string());
+    }
+    let (config, warnings) = load_layered_config_with_warnings(&[&args.config])?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let trace = fs::read(&args.trace).map_err(|e| format!("Couldn't read the trace file at path {}: {e}", args.trace))?;
+    if trace.len() % 40 != 0 {
+        return Err("The trace ends part way through a record".to_string());
+    }
+    let mut seconds = Vec::with_capacity(args.runs);
+    for run in 0..args.warmup + args.runs {
+        let mut simulator = Simulator::new(&config);
+        let start = Instant::now();
+        simulator.simulate(&trace)?;
+        let elapsed = start.elapsed().as_secs_f64();
+        if run >= args.warmup {
+            seconds.push(elapsed);
+        }
+    }
+    let accesses = trace.len() / 40;
+    let rate = |amount: f64| seconds.iter().map(|s| amount / s).collect::<Vec<_>>();
+    let result = BenchResult {
+        runs: args.runs,
+        accesses,
+        seconds: Statistics::of(&seconds),
+        accesses_per_second: Statistics::of(&rate(accesses as f64)),
+        megabytes_per_second: Statistics::of(&rate(trace.len() as f64 / 1e6)),
+    };
+    args.report.write(&result, || {
+        let row = |name: &str, statistics: &Statistics, precision: usize| -> Vec<String> {
+            std::iter::once(name.to_string())
+                .chain([statistics.min, statistics.median, statistics.max, statistics.mean, statistics.stddev].map(|v| format!("{v:.precision$}")))
+                .collect()
+        };
+        vec![Table::titled(format!("{} runs of {accesses} accesses, after {} warmup runs", args.runs, args.warmup), vec![
+            ["", "min", "median", "max", "mean", "stddev"].map(str::to_string).to_vec(),
+            row("time (s)", &result.seconds, 4),
+            row("accesses/s", &result.accesses_per_second, 0),
+            row("MB/s", &result.megabytes_per_second, 1),
+        ])]
+    })?;
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,72 @@
+use std::process::ExitCode;
+use clap::Args;
+use cachelib::config::load_layered_config_with_warnings;
+use cachelib::ensemble::Ensemble;
+use cachelib::simulator::LayeredCacheResult;
+use crate::report::{miss_rate, ReportArgs};
+use crate::trace::map_trace;
+
+/// Arguments for the compare subcommand
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// The paths to the configuration files, followed by the path to the trace file
+    #[arg(required = true, num_args = 2..)]
+    paths: Vec<String>,
+
+    /// Spread the configurations across this many threads
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    /// The JSON formats key the results by configuration path
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Simulates a trace on several configurations in a single pass over the trace, printing the
+/// results side by side
+pub fn run(args: &CompareArgs) -> Result<ExitCode, String> {
+    let (trace_path, config_paths) = args.paths.split_last().unwrap();
+    let mut configs = Vec::with_capacity(config_paths.len());
+    for path in config_paths {
+        let (config, warnings) = load_layered_config_with_warnings(&[path])?;
+        for warning in warnings {
+            eprintln!("Warning: {path}: {warning}");
+        }
+        configs.push(config);
+    }
+    let mut ensemble = Ensemble::new(&configs).with_threads(args.threads);
+    ensemble.simulate(&map_trace(trace_path)?)?;
+    let results = ensemble.into_results();
+    let keyed: serde_json::Map<String, serde_json::Value> = config_paths.iter()
+        .zip(&results)
+        .map(|(path, result)| Ok((path.clone(), serde_json::to_value(result)?)))
+        .collect::<Result<_, serde_json::Error>>()
+        .map_err(|e| format!("Couldn't serialise the output {e}"))?;
+    args.report.write(&keyed, || vec![table_rows(config_paths, &results).into()])?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Lays out the results with a column per configuration and rows for each cache, matching caches
+/// across configurations by name
+fn table_rows(config_paths: &[String], results: &[LayeredCacheResult]) -> Vec<Vec<String>> {
+    let mut names: Vec<&str> = Vec::new();
+    for cache in results.iter().flat_map(|r| r.caches()) {
+        if !names.contains(&cache.name()) {
+            names.push(cache.name());
+        }
+    }
+    let mut rows: Vec<Vec<String>> = vec![std::iter::once(String::new()).chain(config_paths.iter().cloned()).collect()];
+    for name in names {
+        let find = |result: &LayeredCacheResult| result.caches().iter().find(|c| c.name() == name).cloned();
+        let row = |label: &str, cell: &dyn Fn(&cachelib::simulator::CacheResult) -> String| -> Vec<String> {
+            std::iter::once(format!("{name} {label}"))
+                .chain(results.iter().map(|r| find(r).map_or("-".to_string(), |c| cell(&c))))
+                .collect()
+        };
+        rows.push(row("hits", &|c| c.hits().to_string()));
+        rows.push(row("misses", &|c| c.misses().to_string()));
+        rows.push(row("miss rate", &|c| miss_rate(c.hits(), c.misses())));
+    }
+    rows.push(std::iter::once("Main memory accesses".to_string()).chain(results.iter().map(|r| r.main_memory_accesses().to_string())).collect());
+    rows
+}
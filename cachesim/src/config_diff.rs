@@ -0,0 +1,36 @@
+use std::process::ExitCode;
+use clap::Args;
+use serde_json::Value;
+use cachelib::config::{diff_configs, load_layered_config};
+use crate::report::ReportArgs;
+
+/// Arguments for the config diff subcommand
+#[derive(Args, Debug)]
+pub struct ConfigDiffArgs {
+    /// The path to the first configuration file
+    first: String,
+
+    /// The path to the second configuration file
+    second: String,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Prints the differences between two resolved configurations, one field per line
+///
+/// Like diff, exits with 0 when the configurations are equivalent and 1 when they differ
+pub fn run(args: &ConfigDiffArgs) -> Result<ExitCode, String> {
+    let first = load_layered_config(&[&args.first])?;
+    let second = load_layered_config(&[&args.second])?;
+    let differences = diff_configs(&first, &second);
+    let output: Vec<Value> = differences.iter()
+        .map(|d| serde_json::json!({"path": d.path, "first": d.left, "second": d.right}))
+        .collect();
+    args.report.write(&output, || {
+        let describe = |value: &Option<Value>| value.as_ref().map_or("(not present)".to_string(), Value::to_string);
+        let rows = differences.iter().map(|d| vec![d.path.clone(), describe(&d.left), describe(&d.right)]);
+        vec![std::iter::once(["field", "first", "second"].map(str::to_string).to_vec()).chain(rows).collect::<Vec<_>>().into()]
+    })?;
+    Ok(if differences.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
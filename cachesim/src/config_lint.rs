@@ -0,0 +1,41 @@
+use std::process::ExitCode;
+use clap::Args;
+use cachelib::config::lint::lint_config;
+use cachelib::config::load_layered_config_with_warnings;
+
+/// Arguments for the config lint subcommand
+#[derive(Args, Debug)]
+pub struct ConfigLintArgs {
+    /// The path to the configuration file
+    config: String,
+
+    /// Additional configuration fragments, merged over the main configuration file in order
+    #[arg(long = "config", value_name = "PATH")]
+    config_overrides: Vec<String>,
+
+    /// Print the configuration in its canonical form, with defaults filled in, sizes in bytes,
+    /// and the current names for every setting. The problems found are printed to stderr instead
+    #[arg(long)]
+    normalise: bool,
+}
+
+/// Prints the suspicious settings in a configuration, one per line, along with any deprecation
+/// warnings from loading it
+///
+/// Exits with 0 when nothing was found and 1 otherwise
+pub fn run(args: &ConfigLintArgs) -> Result<ExitCode, String> {
+    let config_paths: Vec<&String> = std::iter::once(&args.config).chain(&args.config_overrides).collect();
+    let (config, mut problems) = load_layered_config_with_warnings(&config_paths)?;
+    problems.extend(lint_config(&config));
+    if args.normalise {
+        println!("{}", serde_json::to_string_pretty(&config).map_err(|e| format!("Couldn't serialise the configuration {e}"))?);
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+    } else {
+        for problem in &problems {
+            println!("{problem}");
+        }
+    }
+    Ok(if problems.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
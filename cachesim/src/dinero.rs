@@ -0,0 +1,82 @@
+use std::io::{self, BufRead};
+use std::process::ExitCode;
+use clap::Args;
+use cachelib::config::dinero::{parse_din_record, parse_dinero_options, DineroOptions};
+use cachelib::simulator::{Access, LayeredCacheResult, Simulator};
+use cachelib::topology::Topology;
+
+/// The references read from the trace before simulating them
+const DINERO_BLOCK_REFERENCES: usize = 64 * 1024;
+
+/// Arguments for the dinero subcommand
+#[derive(Args, Debug)]
+pub struct DineroArgs {
+    /// The options of Dinero IV, such as -l1-dsize 16k -l1-dbsize 32, with the din trace read from
+    /// stdin
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "DINERO OPTIONS")]
+    options: Vec<String>,
+}
+
+/// Simulates a din trace read from stdin with a hierarchy given by the options of Dinero IV,
+/// printing the demand fetches and misses of each cache in the layout of Dinero's output
+pub fn run(args: &DineroArgs) -> Result<ExitCode, String> {
+    let DineroOptions { config, skip_count, max_count, ignored } = parse_dinero_options(&args.options)?;
+    for ignored in ignored {
+        eprintln!("Warning: ignoring {ignored}");
+    }
+    let mut simulator = Simulator::new(&config);
+    let mut references = io::stdin().lock().lines().enumerate()
+        .filter_map(|(index, line)| {
+            let access = line.map_err(|e| format!("Couldn't read the trace: {e}"))
+                .and_then(|line| parse_din_record(&line))
+                .map_err(|e| format!("Line {} of the trace: {e}", index + 1));
+            access.transpose()
+        })
+        .skip(skip_count as usize)
+        .take(max_count.map_or(usize::MAX, |count| count as usize));
+    let mut block: Vec<Access> = Vec::with_capacity(DINERO_BLOCK_REFERENCES);
+    loop {
+        block.clear();
+        for access in references.by_ref().take(DINERO_BLOCK_REFERENCES) {
+            block.push(access?);
+        }
+        if block.is_empty() {
+            break;
+        }
+        simulator.simulate_accesses(block.drain(..), 0)?;
+    }
+    let split = Topology::from_config(&config)?.has_instruction_caches();
+    print!("{}", format_report(simulator.get_result(), split));
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Lays out the demand fetches and misses of each cache as Dinero does, with the fetches and data
+/// accesses apart when the first level is split, as only then are they counted apart
+fn format_report(result: &LayeredCacheResult, split: bool) -> String {
+    let mut report = String::new();
+    for cache in result.caches() {
+        let mut columns = vec![("Total", cache.hits(), cache.misses())];
+        if split {
+            let ((fetch_hits, fetch_misses), (data_hits, data_misses)) = (cache.fetches(), cache.data());
+            columns.push(("Instrn", fetch_hits, fetch_misses));
+            columns.push(("Data", data_hits, data_misses));
+        }
+        let row = |label: &str, value: &dyn Fn(u64, u64) -> String| {
+            let cells: String = columns.iter().map(|&(_, hits, misses)| format!("\t{:>12}", value(hits, misses))).collect();
+            format!(" {label:<20}{cells}\n")
+        };
+        let fraction = |part: u64, whole: u64| format!("{:.4}", if whole == 0 { 0.0 } else { part as f64 / whole as f64 });
+        let fetches = cache.hits() + cache.misses();
+        report += &format!("{}\n", cache.name());
+        report += &format!(" {:<20}{}\n", "Metrics", columns.iter().map(|(heading, _, _)| format!("\t{heading:>12}")).collect::<String>());
+        report += &row("-----------------", &|_, _| "------".to_string());
+        report += &row("Demand Fetches", &|hits, misses| (hits + misses).to_string());
+        report += &row(" Fraction of total", &|hits, misses| fraction(hits + misses, fetches));
+        report += "\n";
+        report += &row("Demand Misses", &|_, misses| misses.to_string());
+        report += &row(" Demand miss rate", &|hits, misses| fraction(misses, hits + misses));
+        report += "\n";
+    }
+    report += &format!("Main memory accesses\t{}\n", result.main_memory_accesses());
+    report
+}
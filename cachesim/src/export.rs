@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+use parquet::arrow::ArrowWriter;
+use cachelib::simulator::Simulator;
+
+/// Writes the interval, per-set and per-instruction statistics of a simulation to a directory as
+/// Parquet files, one table each, for analysis in pandas or Polars
+///
+/// * `intervals.parquet` has a row for each cache in each interval: `interval`, `cache`, `hits`,
+///   `misses` and the interval's `main_memory_accesses`
+/// * `sets.parquet` has a row for each set of each cache: `set`, `cache`, `hits` and `misses`
+/// * `pcs.parquet` has a row for each program counter which accessed each cache: `pc`, `cache`,
+///   `hits` and `misses`
+///
+/// Each table is only written if the simulator collected its statistics.
+pub fn write_parquet(directory: &str, simulator: &Simulator) -> Result<(), String> {
+    std::fs::create_dir_all(directory).map_err(|e| format!("Couldn't create the export directory {directory}: {e}"))?;
+    let names: Vec<&str> = simulator.get_result().caches().iter().map(|cache| cache.name()).collect();
+    let mut intervals = Table::default();
+    for (index, interval) in simulator.interval_results().iter().enumerate() {
+        for cache in interval.caches() {
+            intervals.push(cache.name(), index as u64, cache.hits(), cache.misses());
+            intervals.extra.push(interval.main_memory_accesses());
+        }
+    }
+    write_table(directory, "intervals", intervals.into_batch("interval", Some("main_memory_accesses"))?)?;
+    if let Some(statistics) = simulator.set_statistics() {
+        let mut sets = Table::default();
+        for (name, statistics) in names.iter().zip(statistics) {
+            for (set, (hits, misses)) in statistics.hits().iter().zip(statistics.misses()).enumerate() {
+                sets.push(name, set as u64, *hits, *misses);
+            }
+        }
+        write_table(directory, "sets", sets.into_batch("set", None)?)?;
+    }
+    if let Some(statistics) = simulator.pc_statistics() {
+        let mut pcs = Table::default();
+        for (name, statistics) in names.iter().zip(statistics) {
+            for (pc, (hits, misses)) in statistics.counts() {
+                pcs.push(name, *pc, *hits, *misses);
+            }
+        }
+        write_table(directory, "pcs", pcs.into_batch("pc", None)?)?;
+    }
+    Ok(())
+}
+
+/// The columns of a table of counts of each cache, keyed by an interval, set or program counter
+#[derive(Default)]
+struct Table {
+    caches: Vec<String>,
+    keys: Vec<u64>,
+    hits: Vec<u64>,
+    misses: Vec<u64>,
+    // A column of its own, for the tables which have one
+    extra: Vec<u64>,
+}
+
+impl Table {
+    fn push(&mut self, cache: &str, key: u64, hits: u64, misses: u64) {
+        self.caches.push(cache.to_string());
+        self.keys.push(key);
+        self.hits.push(hits);
+        self.misses.push(misses);
+    }
+
+    /// Gathers the columns into a batch, with the key first
+    fn into_batch(self, key: &str, extra: Option<&str>) -> Result<RecordBatch, String> {
+        let mut columns: Vec<(&str, ArrayRef)> = vec![
+            (key, Arc::new(UInt64Array::from(self.keys))),
+            ("cache", Arc::new(StringArray::from(self.caches))),
+        ];
+        columns.push(("hits", Arc::new(UInt64Array::from(self.hits))));
+        columns.push(("misses", Arc::new(UInt64Array::from(self.misses))));
+        if let Some(extra) = extra {
+            columns.push((extra, Arc::new(UInt64Array::from(self.extra))));
+        }
+        RecordBatch::try_from_iter(columns).map_err(|e| format!("Couldn't build the table: {e}"))
+    }
+}
+
+fn write_table(directory: &str, name: &str, batch: RecordBatch) -> Result<(), String> {
+    let path = Path::new(directory).join(format!("{name}.parquet"));
+    let file = File::create(&path).map_err(|e| format!("Couldn't create {}: {e}", path.display()))?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None).map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    writer.write(&batch).map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    writer.close().map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::process::ExitCode;
+use std::str::FromStr;
+use cachelib::config::LayeredCacheConfig;
+use cachelib::simulator::{CacheResult, LayeredCacheResult};
+
+/// The exit code when a `--fail-if` condition holds, telling a gated run apart from one which
+/// failed with an error, which exits with 1, or was given invalid arguments, which exits with 2
+pub const CONDITION_HELD: u8 = 3;
+
+/// A condition on the results which fails the run, such as `L1.miss_ratio > 0.05`
+#[derive(Debug, Clone)]
+pub struct FailCondition {
+    metric: Metric,
+    comparison: Comparison,
+    threshold: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Metric {
+    /// A quantity of the cache with this name, matched ignoring case
+    Cache(String, CacheQuantity),
+    MainMemoryAccesses,
+    AverageMemoryAccessTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CacheQuantity {
+    Hits,
+    Misses,
+    Accesses,
+    HitRatio,
+    MissRatio,
+    Prefetches,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+const CACHE_QUANTITIES: [(&str, CacheQuantity); 6] = [
+    ("hits", CacheQuantity::Hits),
+    ("misses", CacheQuantity::Misses),
+    ("accesses", CacheQuantity::Accesses),
+    ("hit_ratio", CacheQuantity::HitRatio),
+    ("miss_ratio", CacheQuantity::MissRatio),
+    ("prefetches", CacheQuantity::Prefetches),
+];
+
+/// Longer operators first, so `>=` isn't read as `>`
+const COMPARISONS: [(&str, Comparison); 6] = [
+    (">=", Comparison::GreaterOrEqual),
+    ("<=", Comparison::LessOrEqual),
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    (">", Comparison::Greater),
+    ("<", Comparison::Less),
+];
+
+impl FromStr for FailCondition {
+    type Err = String;
+
+    /// Parses a condition of the form `<metric> <operator> <number>`
+    ///
+    /// The metric is `main_memory_accesses`, `amat`, or a cache's name followed by one of `.hits`,
+    /// `.misses`, `.accesses`, `.hit_ratio`, `.miss_ratio` or `.prefetches`. The operator is one of
+    /// `>`, `>=`, `<`, `<=`, `==` or `!=`.
+    fn from_str(condition: &str) -> Result<Self, Self::Err> {
+        let (position, operator, comparison) = COMPARISONS.iter()
+            .filter_map(|(operator, comparison)| condition.find(operator).map(|position| (position, *operator, *comparison)))
+            .min_by_key(|(position, operator, _)| (*position, usize::MAX - operator.len()))
+            .ok_or(format!("The condition {condition} has no comparison, such as L1.miss_ratio > 0.05"))?;
+        let metric = condition[..position].trim();
+        let value = condition[position + operator.len()..].trim();
+        let threshold = value.parse().map_err(|_| format!("The condition {condition} compares against {value}, which isn't a number"))?;
+        let metric = match metric {
+            "main_memory_accesses" => Metric::MainMemoryAccesses,
+            "amat" => Metric::AverageMemoryAccessTime,
+            _ => {
+                let (cache, quantity) = metric.rsplit_once('.').ok_or(format!("The metric {metric} should be main_memory_accesses, amat, or a cache name and quantity, such as L1.miss_ratio"))?;
+                let quantity = CACHE_QUANTITIES.iter()
+                    .find(|(name, _)| *name == quantity)
+                    .map(|(_, quantity)| *quantity)
+                    .ok_or(format!("Unknown quantity {quantity} in {metric}, expected one of hits, misses, accesses, hit_ratio, miss_ratio or prefetches"))?;
+                Metric::Cache(cache.to_string(), quantity)
+            }
+        };
+        Ok(FailCondition { metric, comparison, threshold })
+    }
+}
+
+impl fmt::Display for FailCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = COMPARISONS.iter().find(|(_, c)| *c == self.comparison).unwrap().0;
+        write!(f, "{} {operator} {}", self.metric, self.threshold)
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Cache(cache, quantity) => {
+                let quantity = CACHE_QUANTITIES.iter().find(|(_, q)| q == quantity).unwrap().0;
+                write!(f, "{cache}.{quantity}")
+            }
+            Metric::MainMemoryAccesses => write!(f, "main_memory_accesses"),
+            Metric::AverageMemoryAccessTime => write!(f, "amat"),
+        }
+    }
+}
+
+impl FailCondition {
+    /// Checks the condition can be evaluated on results of a configuration, so mistakes are found
+    /// before simulating
+    pub fn check(&self, config: &LayeredCacheConfig) -> Result<(), String> {
+        match &self.metric {
+            Metric::Cache(name, _) if !config.caches.iter().any(|c| c.name.eq_ignore_ascii_case(name)) => {
+                Err(format!("The condition {self} refers to the cache {name}, which isn't in the configuration"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates the condition on a result
+    ///
+    /// # Arguments
+    ///
+    /// * `result`: The result of the simulation
+    /// * `config`: The configuration the result was produced with
+    ///
+    /// returns: Result<Option<String>, String>, a description of the failure if the condition holds
+    pub fn evaluate(&self, result: &LayeredCacheResult, config: &LayeredCacheConfig) -> Result<Option<String>, String> {
+        let value = match &self.metric {
+            Metric::MainMemoryAccesses => result.main_memory_accesses() as f64,
+            Metric::AverageMemoryAccessTime => result.average_memory_access_time(config)
+                .ok_or(format!("The condition {self} needs the latencies of every cache to be configured"))?,
+            Metric::Cache(name, quantity) => {
+                let cache = result.caches().iter()
+                    .find(|c| c.name().eq_ignore_ascii_case(name))
+                    .ok_or(format!("The condition {self} refers to the cache {name}, which isn't in the results"))?;
+                cache_quantity(cache, *quantity)
+            }
+        };
+        let holds = match self.comparison {
+            Comparison::Greater => value > self.threshold,
+            Comparison::GreaterOrEqual => value >= self.threshold,
+            Comparison::Less => value < self.threshold,
+            Comparison::LessOrEqual => value <= self.threshold,
+            Comparison::Equal => value == self.threshold,
+            Comparison::NotEqual => value != self.threshold,
+        };
+        Ok(holds.then(|| format!("{self}, as {} is {value}", self.metric)))
+    }
+}
+
+/// Evaluates every condition on a result, explaining each which holds on stderr
+///
+/// returns: Result<ExitCode, String>, [CONDITION_HELD] if any condition holds, and success
+/// otherwise
+pub fn check_conditions(conditions: &[FailCondition], result: &LayeredCacheResult, config: &LayeredCacheConfig) -> Result<ExitCode, String> {
+    let mut held = false;
+    for condition in conditions {
+        if let Some(failure) = condition.evaluate(result, config)? {
+            eprintln!("Failed: {failure}");
+            held = true;
+        }
+    }
+    Ok(if held { ExitCode::from(CONDITION_HELD) } else { ExitCode::SUCCESS })
+}
+
+fn cache_quantity(cache: &CacheResult, quantity: CacheQuantity) -> f64 {
+    let accesses = cache.hits() + cache.misses();
+    let ratio = |count: u64| if accesses == 0 { 0.0 } else { count as f64 / accesses as f64 };
+    match quantity {
+        CacheQuantity::Hits => cache.hits() as f64,
+        CacheQuantity::Misses => cache.misses() as f64,
+        CacheQuantity::Accesses => accesses as f64,
+        CacheQuantity::HitRatio => ratio(cache.hits()),
+        CacheQuantity::MissRatio => ratio(cache.misses()),
+        CacheQuantity::Prefetches => cache.prefetches() as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cachelib::config::migration::CURRENT_CONFIG_VERSION;
+    use super::*;
+
+    fn config() -> LayeredCacheConfig {
+        serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [
+            {"name": "L1", "size": 1024, "hit_latency": 1},
+            {"name": "L2", "size": 4096, "hit_latency": 10}
+        ], "memory_latency": 100})).unwrap()
+    }
+
+    fn result() -> LayeredCacheResult {
+        serde_json::from_value(serde_json::json!({"main_memory_accesses": 5, "caches": [
+            {"name": "L1", "hits": 75, "misses": 25, "prefetches": 3},
+            {"name": "L2", "hits": 20, "misses": 5}
+        ]})).unwrap()
+    }
+
+    fn holds(condition: &str) -> bool {
+        let condition: FailCondition = condition.parse().unwrap();
+        condition.evaluate(&result(), &config()).unwrap().is_some()
+    }
+
+    #[test]
+    fn rejects_malformed_conditions() {
+        let error = |condition: &str| condition.parse::<FailCondition>().unwrap_err();
+        assert_eq!(error("L1.miss_ratio 0.05"), "The condition L1.miss_ratio 0.05 has no comparison, such as L1.miss_ratio > 0.05");
+        assert_eq!(error("L1.misses > lots"), "The condition L1.misses > lots compares against lots, which isn't a number");
+        assert_eq!(error("misses > 5"), "The metric misses should be main_memory_accesses, amat, or a cache name and quantity, such as L1.miss_ratio");
+        assert_eq!(error("L1.evictions > 5"), "Unknown quantity evictions in L1.evictions, expected one of hits, misses, accesses, hit_ratio, miss_ratio or prefetches");
+    }
+
+    #[test]
+    fn compares_with_each_operator() {
+        for (condition, expected) in [
+            ("L1.misses > 24", true), ("L1.misses > 25", false),
+            ("L1.misses >= 25", true), ("L1.misses >= 26", false),
+            ("L1.misses < 26", true), ("L1.misses < 25", false),
+            ("L1.misses <= 25", true), ("L1.misses <= 24", false),
+            ("L1.misses == 25", true), ("L1.misses == 24", false),
+            ("L1.misses != 24", true), ("L1.misses != 25", false),
+        ] {
+            assert_eq!(holds(condition), expected, "{condition}");
+        }
+        // The longer operator is read where both match, and spaces are optional
+        let condition: FailCondition = "L1.misses>=25".parse().unwrap();
+        assert_eq!(condition.to_string(), "L1.misses >= 25");
+    }
+
+    #[test]
+    fn evaluates_each_metric() {
+        for (condition, value) in [
+            ("L1.hits", 75.0), ("L1.misses", 25.0), ("L1.accesses", 100.0), ("L1.hit_ratio", 0.75),
+            ("L1.miss_ratio", 0.25), ("L1.prefetches", 3.0), ("main_memory_accesses", 5.0),
+            // 1 + 0.25 * (10 + 0.2 * 100)
+            ("amat", 8.5),
+        ] {
+            let condition: FailCondition = format!("{condition} == {value}").parse().unwrap();
+            assert_eq!(condition.evaluate(&result(), &config()).unwrap(), Some(format!("{condition}, as {} is {value}", condition.metric)));
+        }
+        // Cache names are matched ignoring case
+        assert!(holds("l2.miss_ratio == 0.2"));
+    }
+
+    #[test]
+    fn reports_conditions_which_cant_be_evaluated() {
+        let condition: FailCondition = "L3.misses > 0".parse().unwrap();
+        assert_eq!(condition.check(&config()).unwrap_err(), "The condition L3.misses > 0 refers to the cache L3, which isn't in the configuration");
+        assert_eq!(condition.evaluate(&result(), &config()).unwrap_err(), "The condition L3.misses > 0 refers to the cache L3, which isn't in the results");
+        let mut config = config();
+        config.memory_latency = None;
+        let amat: FailCondition = "amat > 10".parse().unwrap();
+        assert_eq!(amat.evaluate(&result(), &config).unwrap_err(), "The condition amat > 10 needs the latencies of every cache to be configured");
+    }
+
+    #[test]
+    fn exits_with_its_own_code_when_a_condition_holds() {
+        let conditions = |conditions: &[&str]| conditions.iter().map(|c| c.parse().unwrap()).collect::<Vec<FailCondition>>();
+        assert_eq!(check_conditions(&conditions(&["L1.misses > 100", "main_memory_accesses > 100"]), &result(), &config()), Ok(ExitCode::SUCCESS));
+        assert_eq!(check_conditions(&conditions(&["L1.misses > 100", "L2.miss_ratio >= 0.2"]), &result(), &config()), Ok(ExitCode::from(CONDITION_HELD)));
+        assert_ne!(ExitCode::from(CONDITION_HELD), ExitCode::FAILURE);
+        assert!(check_conditions(&conditions(&["L3.misses > 0"]), &result(), &config()).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use clap::Args;
+use serde::Serialize;
+use cachelib::config::lint::lint_config;
+use cachelib::config::migration::CURRENT_CONFIG_VERSION;
+use cachelib::config::{parse_byte_size, CacheConfig, CacheKindConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+
+/// The sizes suggested for the first three levels, after which each level suggests four times the
+/// size of the one above
+const DEFAULT_SIZES: [&str; 3] = ["32KiB", "256KiB", "8MiB"];
+
+/// Arguments for the init subcommand
+#[derive(Args, Debug)]
+pub struct InitArgs {
+    /// The path to write the configuration to
+    #[arg(default_value = "config.json")]
+    path: String,
+
+    /// Overwrite the file if it already exists
+    #[arg(long)]
+    force: bool,
+}
+
+/// A cache as the wizard writes it, with only the settings it asks about, in the order it asks
+#[derive(Debug, Serialize)]
+struct WizardCache {
+    name: String,
+    size: String,
+    line_size: u64,
+    kind: CacheKindConfig,
+    replacement_policy: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WizardConfig {
+    version: u64,
+    caches: Vec<WizardCache>,
+}
+
+/// Asks for each level of the hierarchy on the terminal, and writes the configuration
+pub fn run(args: &InitArgs) -> Result<ExitCode, String> {
+    if Path::new(&args.path).exists() && !args.force {
+        return Err(format!("{} already exists, pass --force to overwrite it", args.path));
+    }
+    let json = ask_config(&mut io::stdin().lock(), &mut io::stderr())?;
+    std::fs::write(&args.path, json).map_err(|e| format!("Couldn't write the configuration to {}: {e}", args.path))?;
+    eprintln!("Wrote {}. Simulate a trace with it using `cachesim {} <trace>`", args.path, args.path);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Asks for the caches of a hierarchy, one level at a time, checking each answer and each level as
+/// it's given, so a mistake is asked again rather than found once the file is written
+///
+/// # Arguments
+///
+/// * `input`: Where the answers are read from, a line each
+/// * `output`: Where the questions and problems are written
+///
+/// returns: Result<String, String>, the configuration as JSON, or an error if the input ended
+/// before it was finished
+fn ask_config(input: &mut dyn BufRead, output: &mut dyn Write) -> Result<String, String> {
+    let mut prompts = Prompts { input, output };
+    let levels = prompts.ask("How many levels of cache", "2", |answer| match answer.parse::<usize>() {
+        Ok(levels) if levels > 0 => Ok(levels),
+        _ => Err("Give a whole number of levels, at least 1".to_string()),
+    })?;
+    let mut config = LayeredCacheConfig { version: CURRENT_CONFIG_VERSION, caches: Vec::new(), memory_latency: None, context_switches: None, page_size: None, simulate_writes: false };
+    let mut written = Vec::with_capacity(levels);
+    while config.caches.len() < levels {
+        let level = config.caches.len() + 1;
+        prompts.say(&format!("\nLevel {level}, which reads the misses of {}", if level == 1 { "the trace".to_string() } else { format!("level {}", level - 1) }))?;
+        let taken: Vec<String> = config.caches.iter().map(|cache| cache.name.clone()).collect();
+        let cache = prompts.ask_cache(level, config.caches.last(), &taken)?;
+        config.caches.push(cache_config(&cache)?);
+        // Anything the questions didn't catch only shows once the level is part of a hierarchy
+        if let Err(e) = config.validate() {
+            prompts.say(&format!("{e}, so level {level} will be asked again"))?;
+            config.caches.pop();
+            continue;
+        }
+        written.push(cache);
+    }
+    for lint in lint_config(&config) {
+        prompts.say(&format!("Warning: {lint}"))?;
+    }
+    let document = WizardConfig { version: CURRENT_CONFIG_VERSION, caches: written };
+    serde_json::to_string_pretty(&document).map(|json| json + "\n").map_err(|e| format!("Couldn't serialise the configuration {e}"))
+}
+
+/// Converts a cache as written to the configuration the simulator would load from it
+fn cache_config(cache: &WizardCache) -> Result<CacheConfig, String> {
+    serde_json::to_value(cache)
+        .and_then(serde_json::from_value)
+        .map_err(|e| format!("Couldn't convert the answers to a cache configuration {e}"))
+}
+
+struct Prompts<'a> {
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+}
+
+impl Prompts<'_> {
+    fn say(&mut self, message: &str) -> Result<(), String> {
+        writeln!(self.output, "{message}").map_err(|e| format!("Couldn't write to the terminal: {e}"))
+    }
+
+    /// Asks a question until an answer parses, taking the default for an empty answer
+    fn ask<T>(&mut self, question: &str, default: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, String> {
+        loop {
+            write!(self.output, "{question} [{default}]: ").and_then(|_| self.output.flush()).map_err(|e| format!("Couldn't write to the terminal: {e}"))?;
+            let mut answer = String::new();
+            if self.input.read_line(&mut answer).map_err(|e| format!("Couldn't read the answer: {e}"))? == 0 {
+                return Err("The input ended before the configuration was finished".to_string());
+            }
+            let answer = match answer.trim() {
+                "" => default,
+                answer => answer,
+            };
+            match parse(answer) {
+                Ok(value) => return Ok(value),
+                Err(e) => self.say(&e)?,
+            }
+        }
+    }
+
+    /// Asks for the settings of one cache, suggesting defaults from the level above
+    fn ask_cache(&mut self, level: usize, above: Option<&CacheConfig>, taken: &[String]) -> Result<WizardCache, String> {
+        let name = self.ask("Name", &format!("L{level}"), |answer| match taken.iter().any(|name| name == answer) {
+            true => Err(format!("There is already a cache named {answer}")),
+            false => Ok(answer.to_string()),
+        })?;
+        let default_size = match (DEFAULT_SIZES.get(level - 1), above) {
+            (Some(size), _) => size.to_string(),
+            (None, Some(above)) => format!("{}MiB", (above.size * 4).div_ceil(1 << 20)),
+            (None, None) => unreachable!("the first level has a default size"),
+        };
+        let line_size = self.ask("Line size, such as 64 or 128B", &above.map_or(64, |above| above.line_size).to_string(), |answer| {
+            match parse_byte_size(answer)? {
+                size if size.is_power_of_two() => Ok(size),
+                size => Err(format!("The line size must be a power of two, not {size} bytes")),
+            }
+        })?;
+        let size = self.ask("Size, such as 32KiB or 2MiB", &default_size, |answer| {
+            let size = parse_byte_size(answer)?;
+            if size < line_size || !size.is_multiple_of(line_size) {
+                return Err(format!("The size must be a whole number of {line_size}-byte lines, not {size} bytes"));
+            }
+            Ok(answer.to_string())
+        })?;
+        let lines = parse_byte_size(&size)? / line_size;
+        let kinds = "direct, 2way, 4way, 8way, 16way or full";
+        let default_kind = if level >= 3 { "16way" } else { "8way" };
+        let kind = self.ask(&format!("Associativity, {kinds}"), default_kind, |answer| {
+            let kind: CacheKindConfig = serde_json::from_value(serde_json::Value::String(answer.to_string()))
+                .map_err(|_| format!("The associativity must be one of {kinds}, not {answer}"))?;
+            let ways = kind.ways(lines);
+            if ways > lines {
+                return Err(format!("A {answer} cache needs at least {ways} lines, but this one holds {lines}"));
+            }
+            Ok(kind)
+        })?;
+        let replacement_policy = match kind {
+            // The policy has no effect on a direct mapped cache, so isn't asked for
+            CacheKindConfig::Direct => "lru".to_string(),
+            _ => self.ask("Replacement policy, rr, lru or lfu", "lru", |answer| {
+                ReplacementPolicyConfig::from(answer.to_string()).parameters().map(|_| answer.to_string())
+            })?,
+        };
+        Ok(WizardCache { name, size, line_size, kind, replacement_policy })
+    }
+}
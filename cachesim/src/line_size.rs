@@ -0,0 +1,120 @@
+use std::process::ExitCode;
+use clap::Args;
+use serde::Serialize;
+use cachelib::config::{load_layered_config_with_warnings, parse_byte_size, LayeredCacheConfig};
+use cachelib::ensemble::Ensemble;
+use cachelib::simulator::LayeredCacheResult;
+use cachelib::topology::Topology;
+use crate::report::{miss_rate, ReportArgs};
+use crate::trace::map_trace;
+
+/// Arguments for the analyze line-size subcommand
+#[derive(Args, Debug)]
+pub struct LineSizeArgs {
+    /// The path to the JSON configuration file
+    config: String,
+
+    /// The path to the trace file
+    trace: String,
+
+    /// The cache to vary. Defaults to the last cache in the configuration
+    #[arg(long, value_name = "NAME")]
+    cache: Option<String>,
+
+    /// The line sizes to compare, separated by commas
+    #[arg(long, value_delimiter = ',', default_value = "16,32,64,128,256", value_parser = parse_byte_size)]
+    line_sizes: Vec<u64>,
+
+    /// Spread the line sizes across this many threads
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// The result of simulating the cache with one line size
+#[derive(Debug, Serialize)]
+pub struct LineSizePoint {
+    pub line_size: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// The bytes read into the cache by its misses
+    pub fill_bytes: u64,
+    pub main_memory_accesses: u64,
+    /// The bytes read from main memory, by the misses of each last level cache
+    pub main_memory_bytes: u64,
+}
+
+/// Simulates a trace with one cache of a configuration at each of a list of line sizes, keeping
+/// its capacity, in a single pass over the trace
+pub fn run(args: &LineSizeArgs) -> Result<ExitCode, String> {
+    let (config, warnings) = load_layered_config_with_warnings(&[&args.config])?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let index = match &args.cache {
+        Some(name) => config.caches.iter().position(|c| &c.name == name).ok_or(format!("There is no cache named {name}"))?,
+        None => config.caches.len() - 1,
+    };
+    let mut line_sizes = args.line_sizes.clone();
+    line_sizes.sort_unstable();
+    line_sizes.dedup();
+    let variants = line_sizes.iter().map(|&line_size| with_line_size(&config, index, line_size)).collect::<Result<Vec<_>, String>>()?;
+    let mut ensemble = Ensemble::new(&variants).with_threads(args.threads);
+    ensemble.simulate(&map_trace(&args.trace)?)?;
+    let points: Vec<LineSizePoint> = line_sizes.iter().zip(&variants).zip(ensemble.into_results())
+        .map(|((&line_size, variant), result)| {
+            let cache = &result.caches()[index];
+            LineSizePoint {
+                line_size,
+                hits: cache.hits(),
+                misses: cache.misses(),
+                fill_bytes: cache.misses() * line_size,
+                main_memory_accesses: result.main_memory_accesses(),
+                main_memory_bytes: main_memory_bytes(variant, &result),
+            }
+        })
+        .collect();
+    args.report.write(&points, || vec![point_rows(&points).into()])?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Copies a configuration with one cache given a new line size, checking it can be simulated
+fn with_line_size(config: &LayeredCacheConfig, index: usize, line_size: u64) -> Result<LayeredCacheConfig, String> {
+    let mut config = config.clone();
+    let cache = &mut config.caches[index];
+    cache.line_size = line_size;
+    let lines = cache.size / line_size;
+    if !line_size.is_power_of_two() || !cache.size.is_multiple_of(line_size) || lines == 0 || cache.kind.ways(lines) > lines {
+        return Err(format!("Cache {} can't have {line_size}-byte lines, its {} bytes must be a whole number of sets of them", cache.name, cache.size));
+    }
+    config.validate().map_err(|e| format!("With cache {} at {line_size}-byte lines: {e}", config.caches[index].name))?;
+    Ok(config)
+}
+
+/// Gets the bytes read from main memory, the misses of each last level cache of its line size
+fn main_memory_bytes(config: &LayeredCacheConfig, result: &LayeredCacheResult) -> u64 {
+    let topology = Topology::from_config(config).expect("The configuration was validated");
+    config.caches.iter().zip(result.caches()).enumerate()
+        .filter(|(index, _)| topology.is_last_level(*index))
+        .map(|(_, (cache, counts))| counts.misses() * cache.line_size)
+        .sum()
+}
+
+/// Lays out the points with a row for each line size
+fn point_rows(points: &[LineSizePoint]) -> Vec<Vec<String>> {
+    let mut rows = vec![["line size", "hits", "misses", "miss rate", "fill bytes", "main memory accesses", "main memory bytes"].map(str::to_string).to_vec()];
+    for point in points {
+        rows.push(vec![
+            point.line_size.to_string(),
+            point.hits.to_string(),
+            point.misses.to_string(),
+            miss_rate(point.hits, point.misses),
+            point.fill_bytes.to_string(),
+            point.main_memory_accesses.to_string(),
+            point.main_memory_bytes.to_string(),
+        ]);
+    }
+    rows
+}
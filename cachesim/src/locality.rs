@@ -0,0 +1,76 @@
+use std::process::ExitCode;
+use clap::Args;
+use cachelib::analysis::{LocalityAnalysis, LocalityReport, DEFAULT_LOCALITY_WINDOW, DEFAULT_MAX_REUSE_DISTANCE};
+use cachelib::config::parse_byte_size;
+use crate::report::{ReportArgs, Table};
+use crate::trace::map_trace;
+
+/// Arguments for the analyze locality subcommand
+#[derive(Args, Debug)]
+pub struct LocalityArgs {
+    /// The path to the trace file
+    trace: String,
+
+    /// The line size of the spatial score and reuse distances
+    #[arg(long, default_value = "64", value_parser = parse_byte_size)]
+    line_size: u64,
+
+    /// The number of accesses in each window of the spatial score
+    #[arg(long, value_name = "ACCESSES", default_value_t = DEFAULT_LOCALITY_WINDOW)]
+    window: u64,
+
+    /// The most distinct lines a reuse is looked for behind. Larger distances take longer
+    #[arg(long, value_name = "LINES", default_value_t = DEFAULT_MAX_REUSE_DISTANCE)]
+    max_distance: u64,
+
+    /// The JSON formats include the spatial score of every window
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Measures the locality of a trace, printing its stride histogram, spatial score and reuse
+/// distance distribution
+pub fn run(args: &LocalityArgs) -> Result<ExitCode, String> {
+    if !args.line_size.is_power_of_two() {
+        return Err(format!("The line size must be a power of two, not {}", args.line_size));
+    }
+    let report = analyse(&map_trace(&args.trace)?, args.line_size, args.window, args.max_distance)?;
+    args.report.write(&report, || report_tables(&report))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Measures the locality of a trace, see [LocalityAnalysis]
+pub fn analyse(trace: &[u8], line_size: u64, window: u64, max_distance: u64) -> Result<LocalityReport, String> {
+    let mut analysis = LocalityAnalysis::new(line_size, window, max_distance)?;
+    analysis.simulate(trace)?;
+    Ok(analysis.report())
+}
+
+/// Serialises a report as pretty printed JSON
+pub fn to_json(report: &LocalityReport) -> Result<String, String> {
+    serde_json::to_string_pretty(report).map(|json| json + "\n").map_err(|e| format!("Couldn't serialise the locality report {e}"))
+}
+
+/// Lays out a report as tables, summarising the windows of the spatial score by their range
+fn report_tables(report: &LocalityReport) -> Vec<Table> {
+    let percent = |fraction: f64| format!("{:.2}%", 100.0 * fraction);
+    let (lowest, highest) = report.spatial_windows.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lowest, highest), &score| (lowest.min(score), highest.max(score)));
+    let mut summary = vec![
+        vec!["Accesses".to_string(), report.accesses.to_string()],
+        vec!["Spatial score".to_string(), percent(report.spatial_score)],
+    ];
+    if !report.spatial_windows.is_empty() {
+        summary.push(vec![format!("Spatial score of {} windows", report.spatial_windows.len()), format!("{} to {}", percent(lowest), percent(highest))]);
+    }
+    let total_strides: u64 = report.strides.iter().map(|bucket| bucket.count).sum();
+    let mut strides = vec![["stride (bytes)", "accesses", "share"].map(str::to_string).to_vec()];
+    for bucket in &report.strides {
+        let range = if bucket.min == bucket.max { bucket.min.to_string() } else { format!("{} to {}", bucket.min, bucket.max) };
+        strides.push(vec![range, bucket.count.to_string(), percent(bucket.count as f64 / total_strides as f64)]);
+    }
+    let mut reuse = vec![[format!("reused within ({}B lines)", report.line_size), "cumulative share".to_string()].to_vec()];
+    for point in &report.reuse {
+        reuse.push(vec![point.lines.to_string(), percent(point.fraction)]);
+    }
+    vec![summary.into(), strides.into(), reuse.into()]
+}
@@ -1,10 +1,86 @@
+#![deny(unsafe_op_in_unsafe_fn, clippy::undocumented_unsafe_blocks)]
+
+mod associativity;
+mod batch;
+mod bench;
+mod compare;
+mod config_diff;
+mod config_lint;
+mod dinero;
+#[cfg(feature = "parquet")]
+mod export;
+mod fail_if;
+mod init;
+mod line_size;
+mod locality;
+mod opt_gap;
+mod placement;
+#[cfg(feature = "plots")]
+mod plot;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod progress;
+mod provenance;
+mod reorder;
+mod report;
+mod results_diff;
+mod selftest;
+#[cfg(feature = "serve")]
+mod serve;
+mod sweep;
+mod trace;
+mod tune;
+#[cfg(feature = "tui")]
+mod tui;
+mod working_set;
+
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{BufReader};
-use std::time::Instant;
-use clap::Parser;
-use cachelib::config::LayeredCacheConfig;
-use cachelib::simulator::Simulator;
-use memmap2::{Advice, Mmap};
+use std::io::BufWriter;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use clap::{Args, Parser, Subcommand};
+use indicatif::BinaryBytes;
+use memmap2::Mmap;
+use serde::Serialize;
+use cachelib::analysis::{DEFAULT_LOCALITY_WINDOW, DEFAULT_MAX_REUSE_DISTANCE};
+use cachelib::cache::MemoryUsage;
+use cachelib::config::{load_layered_config_with_warnings, LayeredCacheConfig};
+use cachelib::parallel::{LevelPipeline, Pipeline};
+use cachelib::simulator::{LatencyHistogram, LayeredCacheResult, LineUtilisation, Simulator, WayStatistics};
+use cachelib::simulator::decisions::DecisionLog;
+use cachelib::simulator::processes::{AddressSpaces, Interleaving, ProcessTraces};
+use cachelib::simulator::miss_trace::MissTrace;
+#[cfg(feature = "plots")]
+use cachelib::simulator::SetStatistics;
+use cachelib::simulator::sharded::ShardedSimulator;
+use cachelib::simulator::speculative::SpeculativeSimulator;
+use cachelib::topology::Topology;
+use crate::associativity::AssociativityArgs;
+use crate::batch::BatchArgs;
+use crate::bench::BenchArgs;
+use crate::compare::CompareArgs;
+use crate::config_diff::ConfigDiffArgs;
+use crate::config_lint::ConfigLintArgs;
+use crate::dinero::DineroArgs;
+use crate::fail_if::FailCondition;
+use crate::init::InitArgs;
+use crate::line_size::LineSizeArgs;
+use crate::locality::LocalityArgs;
+use crate::opt_gap::OptGapArgs;
+use crate::placement::PlacementArgs;
+use crate::progress::trace_progress_bar;
+use crate::provenance::{Provenance, TraceFingerprint};
+use crate::reorder::ReorderArgs;
+use crate::report::{miss_rate, render, render_csv, write_json_line, write_output, OutputFormat};
+use crate::results_diff::ResultsDiffArgs;
+use crate::selftest::SelftestArgs;
+use crate::sweep::SweepArgs;
+use crate::tune::TuneArgs;
+use crate::trace::{map_trace, read_trace_blocks, simulate_windows, Interleave, IoBackend};
+use crate::working_set::WorkingSetArgs;
 
 #[cfg(debug_assertions)]
 const DEBUG_DEFAULT: bool = true;
@@ -13,65 +89,756 @@ const DEBUG_DEFAULT: bool = true;
 const DEBUG_DEFAULT: bool = false;
 
 #[derive(Parser, Debug)]
-#[command(about)]
+#[command(about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 /// Cache simulator for CS4202 Practical 1
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    simulate: SimulateArgs,
+
+    /// A shared library of replacement policies to register before running, see the README for the
+    /// interface it must implement. Can be repeated
+    #[cfg(feature = "plugins")]
+    #[arg(long, global = true, value_name = "PATH")]
+    policy_plugin: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check and compare configurations
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Create a configuration file by answering questions about each level of the hierarchy
+    Init(InitArgs),
+    /// Run every simulation listed in a manifest, printing one combined results document
+    Batch(BatchArgs),
+    /// Simulate a trace on several configurations in a single pass, printing the results side by side
+    Compare(CompareArgs),
+    /// Simulate a trace with one cache resized to each of a list of sizes, with each of a list of policies
+    Sweep(SweepArgs),
+    /// Simulate a trace repeatedly, reporting the spread of the time taken and the throughput
+    Bench(BenchArgs),
+    /// Simulate every case in a directory of examples, checking each against its expected output
+    Selftest(SelftestArgs),
+    /// Search for the configuration which minimises the miss rate or access time on a trace, within a
+    /// capacity budget
+    Tune(TuneArgs),
+    /// Print the changes in each metric between two results, matching caches by name
+    Diff(ResultsDiffArgs),
+    /// Reorder the records of one or more traces within bounded windows, keeping each thread's
+    /// order, writing the new trace and reporting how far the records moved
+    Reorder(ReorderArgs),
+    /// Simulate a din trace from stdin with a hierarchy given by the options of Dinero IV, printing
+    /// the results as Dinero does. Also run when the simulator is invoked as dineroIV
+    Dinero(DineroArgs),
+    /// Analyse how a trace uses the caches
+    #[command(subcommand)]
+    Analyze(AnalyzeCommand),
+    /// Run the simulator as an HTTP service, accepting jobs and reporting their results
+    #[cfg(feature = "serve")]
+    Serve(serve::ServeArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print settings which are valid but likely to be mistakes
+    Lint(ConfigLintArgs),
+    /// Print the field-level differences between two configurations
+    Diff(ConfigDiffArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum AnalyzeCommand {
+    /// Compare the misses of one cache at several associativities, keeping its capacity
+    Associativity(AssociativityArgs),
+    /// Compare the misses and memory traffic of one cache at several line sizes, keeping its
+    /// capacity
+    LineSize(LineSizeArgs),
+    /// Measure the spatial and temporal locality of a trace: its strides, spatial score and reuse
+    /// distances
+    Locality(LocalityArgs),
+    /// Compare the misses of each cache with those of Belady's optimal replacement on the same reads
+    OptGap(OptGapArgs),
+    /// Compare the misses of a trace placed at random in the address space, as by address space
+    /// layout randomisation
+    Placement(PlacementArgs),
+    /// Estimate the working set of a trace, the distinct lines it touches, in each window of
+    /// accesses and on average for a range of window sizes
+    WorkingSet(WorkingSetArgs),
+}
+
+/// Arguments for simulating a trace, used when no subcommand is given
+#[derive(Args, Debug)]
+struct SimulateArgs {
     /// The path to the JSON configuration file
-    config: String,
+    #[arg(required = true)]
+    config: Option<String>,
 
     /// The path to the trace file
-    trace: String,
+    #[arg(required_unless_present = "dump_effective_config")]
+    trace: Option<String>,
+
+    /// Additional configuration fragments, merged over the main configuration file in order
+    #[arg(long = "config", value_name = "PATH")]
+    config_overrides: Vec<String>,
 
-    /// Output performance statistics
+    /// Output performance statistics to stderr. Implied by -v
     #[arg(short, long)]
     performance: bool,
 
-    /// Output debug information
+    /// The core making the accesses in the trace, for configurations with per-core caches.
+    /// Defaults to the lowest numbered core
+    #[arg(long)]
+    core: Option<u32>,
+
+    /// The trace of another process running alongside the main trace, in its own address space, on
+    /// the next core if there is one. Can be repeated
+    #[arg(long = "process", value_name = "PATH", conflicts_with_all = ["core", "shards", "speculative", "threads", "io_backend", "pipeline_levels"])]
+    processes: Vec<String>,
+
+    /// How the records of the processes are interleaved
+    #[arg(long, value_enum, default_value_t = Interleave::RoundRobin, requires = "processes")]
+    interleave: Interleave,
+
+    /// The records each process makes in its turn, when interleaving round robin
+    #[arg(long, value_name = "RECORDS", default_value_t = 1, requires = "processes")]
+    quantum: u64,
+
+    /// Partition this cache between the processes by page colour, rather than including the address
+    /// space id of each process in its tags
+    #[arg(long, value_name = "NAME", requires = "processes")]
+    partition_cache: Option<String>,
+
+    /// Write the results to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// The format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    output_format: OutputFormat,
+
+    /// Add the fully-resolved configuration to the JSON results, as `config`, so they record the
+    /// values used for the settings left to their defaults
+    #[arg(long)]
+    embed_config: bool,
+
+    /// Exit with 1 if this condition holds for the results, such as 'L1.miss_ratio > 0.05'. Can be
+    /// repeated
+    #[arg(long, value_name = "CONDITION")]
+    fail_if: Vec<FailCondition>,
+
+    /// Write a record of what produced the results to this file, as JSON: the command line, hashes
+    /// of the configuration and trace, the simulator's version and git revision, and the time
+    #[arg(long, value_name = "PATH")]
+    provenance: Option<String>,
+
+    /// Hash the whole trace for the provenance record, rather than its first and last MiB and size
+    #[arg(long, requires = "provenance")]
+    full_checksum: bool,
+
+    /// Parse the trace on this many threads less one while the remaining thread simulates. The
+    /// results are the same as with one thread
+    #[arg(long, value_name = "N", default_value_t = 1, conflicts_with = "shards")]
+    threads: usize,
+
+    /// Simulate each cache level on its own thread, passing the misses of each level to the next.
+    /// The results are the same as with one thread. Hierarchies with prefetchers or inclusive or
+    /// exclusive caches aren't supported
+    #[arg(long, conflicts_with_all = ["shards", "speculative", "threads", "io_backend", "record_decisions", "replay_decisions"])]
+    pipeline_levels: bool,
+
+    /// How the trace is read. direct and uring bypass the page cache and read the next block of the
+    /// trace while simulating the last, for traces much larger than memory on fast storage
+    #[arg(long, value_enum, default_value_t = IoBackend::Mmap, conflicts_with_all = ["shards", "threads"])]
+    io_backend: IoBackend,
+
+    /// Simulate on this many threads, each holding a share of the sets of every cache. Must be a
+    /// power of two, and the configuration can't use prefetchers, exclusive caches or registered
+    /// policies
+    #[arg(long, value_name = "N")]
+    shards: Option<usize>,
+
+    /// Simulate the trace in this many chunks at once, each warmed up on the --overlap records
+    /// before it, reporting where the chunks' caches disagreed. Only direct mapped and round robin
+    /// caches without prefetchers are supported
+    #[arg(long, value_name = "N", conflicts_with_all = ["shards", "threads", "io_backend", "dump_contents", "record_decisions", "replay_decisions"])]
+    speculative: Option<usize>,
+
+    /// The number of records before each chunk of a --speculative simulation which warm up its
+    /// caches
+    #[arg(long, value_name = "RECORDS", default_value_t = 100_000, requires = "speculative")]
+    overlap: u64,
+
+    /// Write the contents of every cache at the end of the simulation to this file as JSON, with
+    /// the address and replacement policy state of each line
+    #[arg(long, value_name = "FILE", conflicts_with = "shards")]
+    dump_contents: Option<String>,
+
+    /// Write every replacement decision, the cache, set and way chosen, to this file in a compact
+    /// binary log
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["shards", "replay_decisions"])]
+    record_decisions: Option<String>,
+
+    /// Check every replacement decision against a log written by --record-decisions, exiting with
+    /// an error at the first which differs
+    #[arg(long, value_name = "FILE", conflicts_with = "shards")]
+    replay_decisions: Option<String>,
+
+    /// Check the invariants of the hierarchy after every N accesses to a line, 1 if not given,
+    /// exiting with a dump of the sets concerned at the first broken: that inclusive caches hold the
+    /// lines above them, exclusive caches share none, and each replacement policy agrees with the
+    /// valid lines. Each check reads every cache, so this is for debugging
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "1", conflicts_with_all = ["shards", "speculative"])]
+    verify_invariants: Option<u64>,
+
+    /// Track the bytes of each line used before it leaves each cache, printing the average
+    /// utilisation of the lines of each cache to stderr. Uses the slower simulation path
+    #[arg(long, conflicts_with_all = ["quiet", "shards", "speculative", "pipeline_levels"])]
+    line_utilisation: bool,
+
+    /// Count the hits and fills of each way, summed over the sets of each cache, printing the share
+    /// of each way and how unevenly they were used to stderr. Uses the slower simulation path
+    #[arg(long, conflicts_with_all = ["quiet", "shards", "speculative", "pipeline_levels"])]
+    way_statistics: bool,
+
+    /// Write the number of accesses served with each latency, by the level which served them, to
+    /// this file as CSV. Needs the hit latency of each cache and the memory latency
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["shards", "speculative", "pipeline_levels"])]
+    latency_histogram: Option<String>,
+
+    /// Fetch the instruction at the program counter of each record before its access, through the
+    /// core's instruction cache if it has one, for traces without instruction fetch records
+    #[arg(long, conflicts_with_all = ["shards", "speculative", "pipeline_levels"])]
+    fetch_pcs: bool,
+
+    /// Write each line which misses a cache to this file as a trace, the accesses the levels below
+    /// it see, to simulate them on their own or in another tool
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["shards", "speculative", "pipeline_levels"])]
+    miss_trace: Option<String>,
+
+    /// The cache whose misses --miss-trace writes. Defaults to the first level of the core
+    #[arg(long, value_name = "NAME", requires = "miss_trace")]
+    miss_trace_cache: Option<String>,
+
+    /// Write the results of each interval to this file as a line of JSON as soon as the interval
+    /// ends, so a long simulation can be followed. Uses the slower simulation path
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["shards", "speculative", "pipeline_levels"])]
+    #[cfg_attr(feature = "parquet", arg(conflicts_with = "export_parquet"))]
+    interval_output: Option<String>,
+
+    /// The records in each interval written by --interval-output
+    #[arg(long, value_name = "RECORDS", default_value_t = 1_000_000, requires = "interval_output")]
+    interval: u64,
+
+    /// Write the locality metrics of the trace to this file as JSON, as the locality subcommand
+    /// does, using the line size of the first cache
+    #[arg(long, value_name = "FILE")]
+    locality: Option<String>,
+
+    /// Print the fully-resolved configuration as JSON and exit, without running a simulation
+    #[arg(long)]
+    dump_effective_config: bool,
+
+    /// Write a heatmap of the misses of each set of each cache to this directory, one image per
+    /// cache
+    #[cfg(feature = "plots")]
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["shards", "speculative", "pipeline_levels"])]
+    heatmap: Option<String>,
+
+    /// The image format of the heatmaps, svg or png
+    #[cfg(feature = "plots")]
+    #[arg(long, default_value = "svg", value_parser = ["svg", "png"])]
+    heatmap_format: String,
+
+    /// Write the interval, per-set and per-instruction statistics of each cache to this directory as
+    /// Parquet files. Uses the slower simulation path
+    #[cfg(feature = "parquet")]
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["shards", "speculative", "pipeline_levels"])]
+    export_parquet: Option<String>,
+
+    /// The records in each interval of the exported interval statistics
+    #[cfg(feature = "parquet")]
+    #[arg(long, value_name = "RECORDS", default_value_t = 1_000_000, requires = "export_parquet")]
+    export_interval: u64,
+
+    /// Show a live dashboard of the hit ratio of each cache and the throughput while simulating,
+    /// instead of the progress bar. The results are printed once the dashboard closes
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with_all = ["quiet", "shards", "speculative", "threads", "io_backend", "pipeline_levels", "processes"])]
+    tui: bool,
+
+    /// Don't write anything but the results and errors: no progress bar, warnings, performance
+    /// statistics or debug information
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Write more information to stderr. -v adds performance statistics, -vv debug information
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Output debug information to stderr. Implied by -vv
     #[arg(short, long, default_value_t = DEBUG_DEFAULT)]
     debug: bool,
 }
 
-fn main() -> Result<(), String> {
+fn main() -> Result<ExitCode, String> {
+    // Run as dineroIV, such as through a link of that name, the arguments are Dinero's, so scripts
+    // written for Dinero run unchanged
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if args.first().and_then(|program| Path::new(program).file_stem()).is_some_and(|name| name == "dineroIV") {
+        args.insert(1, "dinero".into());
+    }
+    let cli = Cli::parse_from(args);
+    #[cfg(feature = "plugins")]
+    for path in &cli.policy_plugin {
+        // SAFETY: the user chose to run the plugin, so trusts it as much as the simulator itself
+        unsafe { plugin::load_policy_plugin(path)? };
+    }
+    match cli.command {
+        Some(Command::Config(ConfigCommand::Diff(args))) => config_diff::run(&args),
+        Some(Command::Config(ConfigCommand::Lint(args))) => config_lint::run(&args),
+        Some(Command::Init(args)) => init::run(&args),
+        Some(Command::Batch(args)) => batch::run(&args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Compare(args)) => compare::run(&args),
+        Some(Command::Sweep(args)) => sweep::run(&args),
+        Some(Command::Diff(args)) => results_diff::run(&args),
+        Some(Command::Analyze(AnalyzeCommand::Locality(args))) => locality::run(&args),
+        Some(Command::Analyze(AnalyzeCommand::WorkingSet(args))) => working_set::run(&args),
+        Some(Command::Analyze(AnalyzeCommand::Associativity(args))) => associativity::run(&args),
+        Some(Command::Analyze(AnalyzeCommand::LineSize(args))) => line_size::run(&args),
+        Some(Command::Analyze(AnalyzeCommand::OptGap(args))) => opt_gap::run(&args),
+        Some(Command::Analyze(AnalyzeCommand::Placement(args))) => placement::run(&args),
+        Some(Command::Reorder(args)) => reorder::run(&args),
+        Some(Command::Dinero(args)) => dinero::run(&args),
+        Some(Command::Tune(args)) => tune::run(&args),
+        Some(Command::Selftest(args)) => selftest::run(&args),
+        Some(Command::Bench(args)) => bench::run(&args),
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(args)) => serve::run(&args).map(|_| ExitCode::SUCCESS),
+        None => simulate(&cli.simulate),
+    }
+}
+
+fn simulate(args: &SimulateArgs) -> Result<ExitCode, String> {
     let start = Instant::now();
-    let args = Args::parse();
-    let config_file = File::open(&args.config).map_err(|e| format!("Couldn't open the config file at path {}: {e}", args.config))?;
-    let config: LayeredCacheConfig = serde_json::from_reader(BufReader::new(config_file)).map_err(|e| format!("Couldn't parse the config file: {e}"))?;
-    if config.caches.is_empty() {
-        return Err("The provided file is valid, but the list of caches was empty".to_string())
-    }
-    let mut simulator = Simulator::new(&config);
-    let trace_file = File::open(&args.trace).map_err(|e| format!("Couldn't open the trace file at path {}: {e}", args.trace))?;
-    // MMap for speed. If we wanted more portability we could use a BufReader and repeatedly call
-    // simulate - this is the main reason simulate explicitly supports multiple calls to simulate
-    let map = unsafe {
-        let m = Mmap::map(&trace_file).map_err(|e| format!("Couldn't memory map the file: {e}"))?;
-        m.advise(Advice::Sequential).map_err(|e| format!("Failed to provide access advice to the OS, {e}"))?;
-        m
+    let config_paths: Vec<&String> = args.config.iter().chain(&args.config_overrides).collect();
+    let (config, warnings) = load_layered_config_with_warnings(&config_paths)?;
+    // Only the results go to stdout, so they can be piped into other tools
+    let show_performance = !args.quiet && (args.performance || args.verbose >= 1);
+    let show_debug = !args.quiet && (args.debug || args.verbose >= 2);
+    for warning in warnings.iter().filter(|_| !args.quiet) {
+        eprintln!("Warning: {warning}");
+    }
+    if args.dump_effective_config {
+        println!("{}", serde_json::to_string_pretty(&config).map_err(|e| format!("Couldn't serialise the configuration {e}"))?);
+        return Ok(ExitCode::SUCCESS)
+    }
+    for condition in &args.fail_if {
+        condition.check(&config)?;
+    }
+    let map = map_trace(args.trace.as_deref().unwrap())?;
+    let core = match args.core {
+        Some(core) => core,
+        None => Topology::from_config(&config)?.cores().next().unwrap(),
     };
-    let result = simulator.simulate(map.as_ref())?;
-    println!("{}", serde_json::to_string_pretty(result).map_err(|e| format!("Couldn't serialise the output {e}"))?);
+    let mut processes = Vec::new();
+    let simulator = match (args.shards, args.speculative) {
+        (Some(shards), _) => Run::sharded(&config, shards, map.as_ref(), core)?,
+        (None, Some(chunks)) => Run::speculative(&config, chunks, args.overlap, map.as_ref(), core, args.quiet)?,
+        (None, None) if !args.processes.is_empty() => {
+            let (run, results) = Run::processes(&config, args, &map)?;
+            processes = results;
+            run
+        }
+        (None, None) => Run::serial(&config, args, &map, core)?,
+    };
+    write_output(args.output.as_deref(), &render(&simulator.result, args.embed_config.then_some(&config), args.output_format)?)?;
+    if let Some(path) = &args.provenance {
+        let trace = TraceFingerprint::of(args.trace.as_deref().unwrap(), map.as_ref(), args.full_checksum);
+        let provenance = Provenance::record(&config, trace)?;
+        let json = serde_json::to_string_pretty(&provenance).map_err(|e| format!("Couldn't serialise the provenance {e}"))?;
+        std::fs::write(path, json + "\n").map_err(|e| format!("Couldn't write the provenance to {path}: {e}"))?;
+    }
+    if let Some(utilisation) = &simulator.line_utilisation {
+        let formatted = config.caches.iter()
+            .zip(utilisation)
+            .map(|(cache, lines)| match lines.average() {
+                Some(average) => format!("{}: {:.1}% of {}B lines", cache.name, average * 100.0, lines.line_size()),
+                None => format!("{}: no lines", cache.name),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        eprintln!("Line utilisation by layer: ({formatted})");
+    }
+    if let Some(statistics) = &simulator.way_statistics {
+        eprintln!("Way utilisation by layer:");
+        for (cache, ways) in config.caches.iter().zip(statistics) {
+            let Some(skew) = ways.skew() else {
+                eprintln!("  {}: no hits or fills", cache.name);
+                continue;
+            };
+            let shares = |counts: &[u64]| {
+                let total: u64 = counts.iter().sum();
+                counts.iter().map(|&count| format!("{:.1}%", 100.0 * count as f64 / total.max(1) as f64)).collect::<Vec<_>>().join(" ")
+            };
+            eprintln!("  {}: skew {skew:.2}, hits by way ({}), fills by way ({})", cache.name, shares(ways.hits()), shares(ways.fills()));
+        }
+    }
+    if let (Some(path), Some(histogram)) = (&args.latency_histogram, &simulator.latency_histogram) {
+        let buckets = histogram.buckets(&config)?;
+        let mut rows = vec![["latency", "level", "accesses", "fraction", "cumulative fraction"].map(str::to_string).to_vec()];
+        rows.extend(buckets.iter().map(|bucket| vec![
+            bucket.latency.to_string(),
+            bucket.level.clone(),
+            bucket.accesses.to_string(),
+            bucket.fraction.to_string(),
+            bucket.cumulative_fraction.to_string(),
+        ]));
+        std::fs::write(path, render_csv(&rows)).map_err(|e| format!("Couldn't write the latency histogram to {path}: {e}"))?;
+        if !args.quiet {
+            let formatted = buckets.iter()
+                .map(|bucket| format!("{} {} cycles: {:.2}%", bucket.level, bucket.latency, 100.0 * bucket.fraction))
+                .collect::<Vec<_>>()
+                .join(", ");
+            eprintln!("Accesses by latency: ({formatted})");
+        }
+    }
+    if config.context_switches.is_some() && !args.quiet {
+        let formatted = simulator.result.caches().iter()
+            .map(|cache| {
+                let ((steady_hits, steady_misses), (disturbed_hits, disturbed_misses)) = (cache.steady(), cache.disturbed());
+                format!("{}: {} steady, {} disturbed", cache.name(), miss_rate(steady_hits, steady_misses), miss_rate(disturbed_hits, disturbed_misses))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        eprintln!("Miss rates around context switches by layer: ({formatted})");
+    }
+    if Topology::from_config(&config)?.has_instruction_caches() && !args.quiet {
+        let formatted = simulator.result.caches().iter()
+            .map(|cache| {
+                let ((fetch_hits, fetch_misses), (data_hits, data_misses)) = (cache.fetches(), cache.data());
+                format!("{}: {} of fetches, {} of data", cache.name(), miss_rate(fetch_hits, fetch_misses), miss_rate(data_hits, data_misses))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        eprintln!("Miss rates of instruction fetches and data accesses by layer: ({formatted})");
+    }
+    if config.caches.iter().any(|cache| cache.shadow.is_some()) && !args.quiet {
+        let formatted = simulator.result.caches().iter()
+            .filter_map(|cache| {
+                let ((shadow_hits, shadow_misses), delta) = (cache.shadow()?, cache.shadow_miss_delta()?);
+                Some(format!("{}: {} against {} for its shadow, {delta:+} misses", cache.name(), miss_rate(cache.hits(), cache.misses()), miss_rate(shadow_hits, shadow_misses)))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        eprintln!("Miss rates of the caches and their shadows by layer: ({formatted})");
+    }
+    if let (Some(page_size), false) = (config.page_size, args.quiet) {
+        eprintln!("Accesses crossing {} page boundaries: {}", BinaryBytes(page_size), simulator.result.page_crossing_accesses());
+    }
+    if !processes.is_empty() && !args.quiet {
+        let paths = args.trace.iter().chain(&args.processes);
+        let formatted = paths.zip(&processes)
+            .map(|(path, result)| {
+                let caches = result.caches().iter().map(|cache| format!("{} {}", cache.name(), miss_rate(cache.hits(), cache.misses()))).collect::<Vec<_>>().join(", ");
+                format!("{path}: {caches}, {} main memory accesses", result.main_memory_accesses())
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        eprintln!("Miss rates by process: ({formatted})");
+    }
+    if let Some(path) = &args.locality {
+        let line_size = config.caches[Topology::from_config(&config)?.path(core).unwrap()[0]].line_size;
+        let report = locality::analyse(map.as_ref(), line_size, DEFAULT_LOCALITY_WINDOW, DEFAULT_MAX_REUSE_DISTANCE)?;
+        std::fs::write(path, locality::to_json(&report)?).map_err(|e| format!("Couldn't write the locality metrics to {path}: {e}"))?;
+    }
+    #[cfg(feature = "plots")]
+    if let (Some(directory), Some(statistics)) = (&args.heatmap, &simulator.set_statistics) {
+        std::fs::create_dir_all(directory).map_err(|e| format!("Couldn't create the heatmap directory {directory}: {e}"))?;
+        for (cache, statistics) in config.caches.iter().zip(statistics) {
+            let path = Path::new(directory).join(format!("{}.{}", cache.name, args.heatmap_format));
+            plot::render_set_heatmap(&path, &cache.name, statistics)?;
+        }
+    }
     // Output performance characteristics
-    if args.performance {
+    if show_performance {
         let end = Instant::now();
-        let simulation_time = simulator.get_execution_time();
+        let simulation_time = simulator.simulation_time;
         let total_time = end - start;
-        println!("Simulation time: {}s", simulation_time.as_nanos() as f64 / 1e9);
-        println!("Total execution time (includes initial parsing, configuration, and output): {}s", total_time.as_nanos() as f64 / 1e9)
+        eprintln!("Simulation time: {}s", simulation_time.as_nanos() as f64 / 1e9);
+        eprintln!("Total execution time (includes initial parsing, configuration, and output): {}s", total_time.as_nanos() as f64 / 1e9)
     }
     // Output debug characteristics
-    if args.debug {
+    if show_debug {
         #[cfg(debug_assertions)]
-        println!("Running the debug binary, debug mode is enabled by default. If benchmarking, do not use this binary, re-compile with the --release argument when using cargo run");
-        println!("Parsed input configuration: {config:?}");
-        let uninitialised_lines = simulator.get_uninitialised_line_counts();
+        eprintln!("Running the debug binary, debug mode is enabled by default. If benchmarking, do not use this binary, re-compile with the --release argument when using cargo run");
+        eprintln!("Parsed input configuration: {config:?}");
+        let uninitialised_lines = &simulator.uninitialised_lines;
         let formatted = config.caches
             .iter()
             .map(|c| c.name.clone())
             .zip(uninitialised_lines.iter())
             .map(|(name, count)| format!("{name}: {}", *count))
             .reduce(|a, b| format!("{a}, {b}")).unwrap();
-        println!("Uninitialised cache lines by layer: ({formatted})");
-        println!("Total uninitialised cache lines: {}", uninitialised_lines.iter().sum::<u64>())
+        eprintln!("Uninitialised cache lines by layer: ({formatted})");
+        eprintln!("Total uninitialised cache lines: {}", uninitialised_lines.iter().sum::<u64>());
+        let memory_usage = config.caches
+            .iter()
+            .zip(&simulator.memory_usage)
+            .map(|(cache, usage)| format!("{}: {} of tags, {} of policy state", cache.name, BinaryBytes(usage.tags as u64), BinaryBytes(usage.policy as u64)))
+            .collect::<Vec<_>>()
+            .join("; ");
+        eprintln!("Memory usage by layer: ({memory_usage})");
+        eprintln!("Total memory usage: {}", BinaryBytes(simulator.memory_usage.iter().map(MemoryUsage::total).sum::<usize>() as u64));
+        if let Some(amat) = &simulator.result.average_memory_access_time(&config) {
+            eprintln!("Average memory access time: {amat} cycles");
+        }
+    }
+    fail_if::check_conditions(&args.fail_if, &simulator.result, &config)
+}
+/// The outcome of simulating a trace, with a [Simulator], a [ShardedSimulator] or a
+/// [SpeculativeSimulator]
+struct Run {
+    result: LayeredCacheResult,
+    simulation_time: Duration,
+    uninitialised_lines: Vec<u64>,
+    memory_usage: Vec<MemoryUsage>,
+    #[cfg(feature = "plots")]
+    set_statistics: Option<Vec<SetStatistics>>,
+    line_utilisation: Option<Vec<LineUtilisation>>,
+    way_statistics: Option<Vec<WayStatistics>>,
+    latency_histogram: Option<LatencyHistogram>,
+}
+
+impl Run {
+    fn serial(config: &LayeredCacheConfig, args: &SimulateArgs, trace: &Mmap, core: u32) -> Result<Self, String> {
+        let (mut simulator, streams) = Self::build(config, args, core)?;
+        #[cfg(feature = "tui")]
+        let show_dashboard = args.tui;
+        #[cfg(not(feature = "tui"))]
+        let show_dashboard = false;
+        if show_dashboard {
+            #[cfg(feature = "tui")]
+            tui::simulate_with_dashboard(&mut simulator, trace, core)?;
+        } else {
+            let progress = trace_progress_bar(trace.len() as u64, args.quiet);
+            let mut report_progress = |processed: usize| progress.set_position(processed as u64);
+            if args.io_backend != IoBackend::Mmap {
+                let mut processed = 0;
+                read_trace_blocks(args.trace.as_deref().unwrap(), args.io_backend, |block| {
+                    simulator.simulate_core_with_progress(block, core, &mut |read| report_progress(processed + read))?;
+                    processed += block.len();
+                    Ok(())
+                })?;
+            } else if args.pipeline_levels {
+                LevelPipeline::new().simulate_core_with_progress(&mut simulator, trace, core, &mut report_progress)?;
+            } else if args.threads > 1 {
+                Pipeline::new(args.threads)?.simulate_core_with_progress(&mut simulator, trace, core, &mut report_progress)?;
+            } else {
+                simulate_windows(trace, |window, offset| {
+                    simulator.simulate_core_with_progress(window, core, &mut |read| report_progress(offset + read)).map(|_| ())
+                })?;
+            }
+            progress.finish_and_clear();
+        }
+        Self::finish(simulator, streams, args)
+    }
+
+    /// Simulates the main trace and those of the other processes merged together, each process on
+    /// the next core of the topology, returning the results of each process as well
+    fn processes(config: &LayeredCacheConfig, args: &SimulateArgs, trace: &Mmap) -> Result<(Self, Vec<LayeredCacheResult>), String> {
+        let others = args.processes.iter().map(|path| map_trace(path)).collect::<Result<Vec<_>, String>>()?;
+        let traces: Vec<&[u8]> = std::iter::once(trace.as_ref()).chain(others.iter().map(|map| map.as_ref())).collect();
+        let interleaving = match args.interleave {
+            Interleave::RoundRobin => Interleaving::RoundRobin { quantum: args.quantum },
+            Interleave::Proportional => Interleaving::Proportional,
+        };
+        let address_spaces = match &args.partition_cache {
+            Some(name) => AddressSpaces::partitioning(config.caches.iter().find(|c| &c.name == name).ok_or(format!("There is no cache named {name}"))?)?,
+            None => AddressSpaces::Tagged,
+        };
+        let merged = ProcessTraces::new(traces, interleaving, address_spaces)?;
+        let cores: Vec<u32> = Topology::from_config(config)?.cores().collect();
+        let (mut simulator, streams) = Self::build(config, args, cores[0])?;
+        let results = simulator.simulate_processes(merged, &cores)?;
+        Ok((Self::finish(simulator, streams, args)?, results))
     }
-    Ok(())
-}
\ No newline at end of file
+
+    /// Creates the simulator of a serial run, with the observers and logs the arguments ask for
+    fn build(config: &LayeredCacheConfig, args: &SimulateArgs, core: u32) -> Result<(Simulator, Streams), String> {
+        #[cfg(feature = "plots")]
+        let set_statistics = args.heatmap.is_some();
+        #[cfg(not(feature = "plots"))]
+        let set_statistics = false;
+        #[cfg(feature = "parquet")]
+        let export = args.export_parquet.is_some();
+        #[cfg(not(feature = "parquet"))]
+        let export = false;
+        let mut builder = Simulator::builder(config)
+            .set_statistics(set_statistics || export)
+            .pc_statistics(export)
+            .line_utilisation(args.line_utilisation)
+            .way_statistics(args.way_statistics)
+            .latency_histogram(args.latency_histogram.is_some())
+            .fetch_program_counters(args.fetch_pcs)
+            .record_decisions(args.record_decisions.is_some());
+        if let Some(every) = args.verify_invariants {
+            builder = builder.verify_invariants(every);
+        }
+        #[cfg(feature = "parquet")]
+        if export {
+            builder = builder.interval_statistics(args.export_interval);
+        }
+        let miss_trace = args.miss_trace.as_deref().map(|path| miss_trace(config, args.miss_trace_cache.as_deref(), path, core)).transpose()?;
+        if let Some(miss_trace) = &miss_trace {
+            builder = builder.observer(miss_trace.clone());
+        }
+        let intervals = args.interval_output.as_deref().map(IntervalStream::create).transpose()?;
+        if let Some(intervals) = &intervals {
+            let intervals = intervals.clone();
+            builder = builder.interval_statistics(args.interval).on_interval(move |index, result| intervals.write(index, result));
+        }
+        if let Some(path) = &args.replay_decisions {
+            let log = std::fs::read(path).map_err(|e| format!("Couldn't read the decision log {path}: {e}"))?;
+            builder = builder.replay_decisions(DecisionLog::from_bytes(&log).map_err(|e| format!("{path}: {e}"))?);
+        }
+        let simulator = builder.build()?;
+        if let Some(histogram) = simulator.latency_histogram() {
+            histogram.buckets(config)?;
+        }
+        Ok((simulator, Streams { miss_trace, intervals }))
+    }
+
+    /// Writes the logs of a serial run, and takes its results
+    fn finish(simulator: Simulator, streams: Streams, args: &SimulateArgs) -> Result<Self, String> {
+        if let Some(path) = &args.record_decisions {
+            let log = simulator.decision_log().unwrap().to_bytes();
+            std::fs::write(path, log).map_err(|e| format!("Couldn't write the decision log to {path}: {e}"))?;
+        }
+        simulator.finish_replay()?;
+        if let Some(miss_trace) = &streams.miss_trace {
+            miss_trace.finish()?;
+        }
+        if let Some(intervals) = &streams.intervals {
+            intervals.finish()?;
+        }
+        if let Some(path) = &args.dump_contents {
+            std::fs::write(path, simulator.contents_json()? + "\n").map_err(|e| format!("Couldn't write the cache contents to {path}: {e}"))?;
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(directory) = &args.export_parquet {
+            export::write_parquet(directory, &simulator)?;
+        }
+        Ok(Run {
+            simulation_time: *simulator.get_execution_time(),
+            uninitialised_lines: simulator.get_uninitialised_line_counts(),
+            memory_usage: simulator.memory_usage(),
+            #[cfg(feature = "plots")]
+            set_statistics: simulator.set_statistics().map(<[SetStatistics]>::to_vec),
+            line_utilisation: simulator.line_utilisation().map(<[LineUtilisation]>::to_vec),
+            way_statistics: simulator.way_statistics().map(<[WayStatistics]>::to_vec),
+            latency_histogram: simulator.latency_histogram().cloned(),
+            result: simulator.into_result(),
+        })
+    }
+
+    fn sharded(config: &LayeredCacheConfig, shards: usize, trace: &[u8], core: u32) -> Result<Self, String> {
+        let mut simulator = ShardedSimulator::new(config, shards)?;
+        simulator.simulate_core(trace, core)?;
+        Ok(Run {
+            simulation_time: *simulator.get_execution_time(),
+            uninitialised_lines: simulator.get_uninitialised_line_counts(),
+            memory_usage: simulator.memory_usage(),
+            #[cfg(feature = "plots")]
+            set_statistics: None,
+            line_utilisation: None,
+            way_statistics: None,
+            latency_histogram: None,
+            result: simulator.into_result(),
+        })
+    }
+
+    fn speculative(config: &LayeredCacheConfig, chunks: usize, overlap: u64, trace: &[u8], core: u32, quiet: bool) -> Result<Self, String> {
+        let mut simulator = SpeculativeSimulator::new(config, chunks, overlap)?;
+        simulator.simulate_core(trace, core)?;
+        let reconciliation = simulator.reconciliation();
+        if !quiet && !reconciliation.is_exact() {
+            let sets: u64 = reconciliation.divergent_sets.iter().flatten().sum();
+            let bounds = match &reconciliation.error_bounds {
+                Some(bounds) => {
+                    let caches = config.caches.iter().zip(bounds).map(|(cache, bound)| format!("{}: {bound}", cache.name)).collect::<Vec<_>>().join(", ");
+                    format!("the hits and misses may each be off by up to ({caches}), and the main memory accesses by up to {}", bounds[config.caches.len()])
+                }
+                None => "round robin caches have no bound on how far the results may be off".to_string(),
+            };
+            eprintln!("Warning: {sets} sets differed where the speculative chunks met, so {bounds}. A larger --overlap makes them more likely to agree");
+        }
+        Ok(Run {
+            simulation_time: *simulator.get_execution_time(),
+            uninitialised_lines: simulator.get_uninitialised_line_counts(),
+            memory_usage: simulator.memory_usage(),
+            #[cfg(feature = "plots")]
+            set_statistics: None,
+            line_utilisation: None,
+            way_statistics: None,
+            latency_histogram: None,
+            result: simulator.into_result(),
+        })
+    }
+}
+
+/// The observer writing a miss trace to a file
+type FileMissTrace = MissTrace<BufWriter<File>>;
+
+/// The files a serial run writes as it goes, which are checked for errors once it finishes
+struct Streams {
+    miss_trace: Option<FileMissTrace>,
+    intervals: Option<IntervalStream>,
+}
+
+/// The results of one interval, as a line of the --interval-output file
+#[derive(Serialize)]
+struct IntervalLine<'a> {
+    interval: u64,
+    #[serde(flatten)]
+    result: &'a LayeredCacheResult,
+}
+
+/// Writes the results of each interval to a file as a line of JSON, flushed as soon as the interval
+/// ends. Copies write to the same file, and writing stops at the first error, which
+/// [finish](Self::finish) returns
+#[derive(Clone)]
+struct IntervalStream {
+    shared: Arc<Mutex<(BufWriter<File>, Option<String>)>>,
+}
+
+impl IntervalStream {
+    fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Couldn't create the interval output {path}: {e}"))?;
+        Ok(IntervalStream { shared: Arc::new(Mutex::new((BufWriter::new(file), None))) })
+    }
+
+    fn write(&self, interval: u64, result: &LayeredCacheResult) {
+        let mut shared = self.shared.lock().unwrap();
+        let (output, error) = &mut *shared;
+        if error.is_none() {
+            *error = write_json_line(output, &IntervalLine { interval, result }).err();
+        }
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        self.shared.lock().unwrap().1.clone().map_or(Ok(()), Err)
+    }
+}
+
+/// Creates the observer writing the misses of a cache, the first level of the core unless named
+fn miss_trace(config: &LayeredCacheConfig, cache: Option<&str>, path: &str, core: u32) -> Result<FileMissTrace, String> {
+    let index = match cache {
+        Some(name) => config.caches.iter().position(|c| c.name == name).ok_or(format!("There is no cache named {name}"))?,
+        None => Topology::from_config(config)?.path(core).unwrap()[0],
+    };
+    let file = File::create(path).map_err(|e| format!("Couldn't create the miss trace {path}: {e}"))?;
+    Ok(MissTrace::new(index, config.caches[index].line_size, BufWriter::new(file)))
+}
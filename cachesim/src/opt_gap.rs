@@ -0,0 +1,85 @@
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use clap::Args;
+use cachelib::analysis::{CacheGap, OptimalGap, DEFAULT_GAP_SETS};
+use cachelib::config::load_layered_config_with_warnings;
+use cachelib::simulator::{AccessObserver, CacheAccess, Simulator};
+use cachelib::topology::Topology;
+use crate::report::{miss_rate, ReportArgs, Table};
+use crate::trace::{map_trace, simulate_windows};
+
+/// Arguments for the analyze opt-gap subcommand
+#[derive(Args, Debug)]
+pub struct OptGapArgs {
+    /// The path to the JSON configuration file
+    config: String,
+
+    /// The path to the trace file
+    trace: String,
+
+    /// The core making the accesses in the trace. Defaults to the lowest numbered core
+    #[arg(long)]
+    core: Option<u32>,
+
+    /// The number of sets of each cache to list, those furthest from optimal
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_GAP_SETS)]
+    top_sets: usize,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Simulates a trace with the configured policies, and reports how many more misses each cache has
+/// than Belady's optimal replacement would on the same reads
+pub fn run(args: &OptGapArgs) -> Result<ExitCode, String> {
+    let (config, warnings) = load_layered_config_with_warnings(&[&args.config])?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let core = match args.core {
+        Some(core) => core,
+        None => Topology::from_config(&config)?.cores().next().unwrap(),
+    };
+    let gap = Arc::new(Mutex::new(OptimalGap::new(&config)?));
+    let observed = gap.clone();
+    let mut simulator = Simulator::builder(&config)
+        .observer(move |access: CacheAccess| observed.lock().unwrap().observe(access))
+        .build()?;
+    let trace = map_trace(&args.trace)?;
+    simulate_windows(&trace, |window, _| simulator.simulate_core(window, core).map(|_| ()))?;
+    let report = gap.lock().unwrap().report(args.top_sets);
+    args.report.write(&report, || report_tables(&report))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Lays out the gaps with a row for each cache, followed by a table of the sets of each cache
+/// furthest from optimal
+fn report_tables(report: &[CacheGap]) -> Vec<Table> {
+    let mut rows = vec![["", "reads", "misses", "miss rate", "optimal misses", "optimal miss rate", "gap"].map(str::to_string).to_vec()];
+    for cache in report {
+        rows.push(vec![
+            cache.name.clone(),
+            cache.reads.to_string(),
+            cache.misses.to_string(),
+            miss_rate(cache.reads - cache.misses, cache.misses),
+            cache.optimal_misses.to_string(),
+            miss_rate(cache.reads - cache.optimal_misses, cache.optimal_misses),
+            cache.gap.to_string(),
+        ]);
+    }
+    let mut tables: Vec<Table> = vec![rows.into()];
+    for cache in report.iter().filter(|cache| !cache.sets.is_empty() && cache.gap != 0) {
+        let mut sets = vec![["set", "misses", "optimal misses", "gap", "share of gap"].map(str::to_string).to_vec()];
+        for set in &cache.sets {
+            sets.push(vec![
+                set.set.to_string(),
+                set.misses.to_string(),
+                set.optimal_misses.to_string(),
+                set.gap.to_string(),
+                format!("{:.1}%", 100.0 * set.gap as f64 / cache.gap as f64),
+            ]);
+        }
+        tables.push(Table::titled(format!("Sets of {} furthest from optimal", cache.name), sets));
+    }
+    tables
+}
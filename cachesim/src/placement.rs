@@ -0,0 +1,157 @@
+use std::process::ExitCode;
+use clap::Args;
+use serde::Serialize;
+use cachelib::analysis::{PlacementRandomiser, DEFAULT_PLACEMENT_REGION_SIZE};
+use cachelib::config::{load_layered_config_with_warnings, parse_byte_size, LayeredCacheConfig};
+use cachelib::simulator::{Access, LayeredCacheResult, Simulator};
+use cachelib::topology::Topology;
+use crate::report::{ReportArgs, Table};
+use crate::trace::map_trace;
+
+/// Arguments for the analyze placement subcommand
+#[derive(Args, Debug)]
+pub struct PlacementArgs {
+    /// The path to the JSON configuration file
+    config: String,
+
+    /// The path to the trace file
+    trace: String,
+
+    /// The number of random placements to simulate
+    #[arg(long, value_name = "K", default_value_t = 8)]
+    runs: u64,
+
+    /// The seed of the first placement, each of the others using the seed after the one before
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// The size of each region moved as a whole, such as a page
+    #[arg(long, default_value_t = DEFAULT_PLACEMENT_REGION_SIZE, value_parser = parse_byte_size)]
+    region_size: u64,
+
+    /// Spread the placements across this many threads
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    /// The JSON formats include the results of every placement
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// The result of one random placement of the trace
+#[derive(Debug, Serialize)]
+pub struct PlacementRun {
+    pub seed: u64,
+    pub result: LayeredCacheResult,
+}
+
+/// How the misses of a cache, or main memory accesses, spread over the random placements
+#[derive(Debug, Serialize)]
+pub struct MissSpread {
+    pub name: String,
+    /// The misses with the placement of the trace itself
+    pub original: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    /// The sample standard deviation
+    pub stddev: f64,
+}
+
+/// The results of simulating a trace with its own placement and with random placements
+#[derive(Debug, Serialize)]
+pub struct PlacementReport {
+    pub region_size: u64,
+    pub original: LayeredCacheResult,
+    pub runs: Vec<PlacementRun>,
+    /// The spread of the misses of each cache, in the order of the configuration, followed by main
+    /// memory
+    pub spread: Vec<MissSpread>,
+}
+
+/// Simulates a trace with its regions moved to random places several times, reporting how much
+/// the misses of each cache vary with the placement
+pub fn run(args: &PlacementArgs) -> Result<ExitCode, String> {
+    let (config, warnings) = load_layered_config_with_warnings(&[&args.config])?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    if args.runs == 0 {
+        return Err("At least one placement is needed".to_string());
+    }
+    PlacementRandomiser::new(args.region_size, args.seed)?;
+    let trace = map_trace(&args.trace)?;
+    if !trace.len().is_multiple_of(40) {
+        return Err("The trace ends part way through a record".to_string());
+    }
+    let original = simulate_placement(&config, &trace, None)?;
+    let seeds: Vec<u64> = (0..args.runs).map(|run| args.seed.wrapping_add(run)).collect();
+    let results = simulate_placements(&config, &trace, args.region_size, &seeds, args.threads)?;
+    let runs: Vec<PlacementRun> = seeds.into_iter().zip(results).map(|(seed, result)| PlacementRun { seed, result }).collect();
+    let mut spread: Vec<MissSpread> = config.caches.iter().enumerate()
+        .map(|(index, cache)| MissSpread::of(&cache.name, original.caches()[index].misses(), runs.iter().map(|run| run.result.caches()[index].misses())))
+        .collect();
+    spread.push(MissSpread::of("main memory", original.main_memory_accesses(), runs.iter().map(|run| run.result.main_memory_accesses())));
+    let report = PlacementReport { region_size: args.region_size, original, runs, spread };
+    args.report.write(&report, || vec![report_table(&report)])?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Simulates the trace with the placement of each seed, spread across threads
+fn simulate_placements(config: &LayeredCacheConfig, trace: &[u8], region_size: u64, seeds: &[u64], threads: usize) -> Result<Vec<LayeredCacheResult>, String> {
+    let per_thread = seeds.len().div_ceil(threads.max(1));
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = seeds.chunks(per_thread)
+            .map(|seeds| scope.spawn(move || {
+                seeds.iter().map(|&seed| simulate_placement(config, trace, Some(PlacementRandomiser::new(region_size, seed)?))).collect::<Result<Vec<_>, String>>()
+            }))
+            .collect();
+        let mut results = Vec::with_capacity(seeds.len());
+        for handle in handles {
+            results.extend(handle.join().map_err(|_| "A thread simulating placements panicked".to_string())??);
+        }
+        Ok(results)
+    })
+}
+
+/// Simulates the trace on the first core, with its regions moved by a placement if one is given
+fn simulate_placement(config: &LayeredCacheConfig, trace: &[u8], randomiser: Option<PlacementRandomiser>) -> Result<LayeredCacheResult, String> {
+    let core = Topology::from_config(config)?.cores().next().unwrap();
+    let accesses = trace.chunks_exact(40).map(|record| {
+        let access = Access::from_record(record.try_into().unwrap());
+        randomiser.as_ref().map_or(access, |randomiser| randomiser.map_access(access))
+    });
+    Simulator::new(config).simulate_accesses(accesses, core).cloned()
+}
+
+impl MissSpread {
+    fn of(name: &str, original: u64, misses: impl Iterator<Item = u64>) -> Self {
+        let misses: Vec<u64> = misses.collect();
+        let mean = misses.iter().sum::<u64>() as f64 / misses.len() as f64;
+        let variance = misses.iter().map(|&m| (m as f64 - mean).powi(2)).sum::<f64>() / (misses.len() - 1).max(1) as f64;
+        Self {
+            name: name.to_string(),
+            original,
+            min: misses.iter().copied().min().unwrap(),
+            max: misses.iter().copied().max().unwrap(),
+            mean,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+/// Lays out the spread with a row for each cache and main memory
+fn report_table(report: &PlacementReport) -> Table {
+    let mut rows = vec![["", "original misses", "mean", "stddev", "min", "max"].map(str::to_string).to_vec()];
+    for spread in &report.spread {
+        rows.push(vec![
+            spread.name.clone(),
+            spread.original.to_string(),
+            format!("{:.1}", spread.mean),
+            format!("{:.1}", spread.stddev),
+            spread.min.to_string(),
+            spread.max.to_string(),
+        ]);
+    }
+    Table::titled(format!("{} random placements of {}B regions", report.runs.len(), report.region_size), rows)
+}
@@ -0,0 +1,151 @@
+use std::path::Path;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use cachelib::simulator::SetStatistics;
+
+/// Renders the misses of each set of a cache as a heatmap, with the sets laid out in rows
+///
+/// Sets with more conflicts than the others show up as hot cells. The image format is chosen by the file extension,
+/// `svg` or `png`.
+///
+/// # Arguments
+///
+/// * `path`: The image file to write
+/// * `cache_name`: The name of the cache, for the title
+/// * `statistics`: The per-set statistics of the cache
+///
+/// returns: Result<(), String>
+pub fn render_set_heatmap(path: &Path, cache_name: &str, statistics: &SetStatistics) -> Result<(), String> {
+    const SIZE: (u32, u32) = (900, 800);
+    let result = match image_format(path)? {
+        ImageFormat::Svg => draw_heatmap(SVGBackend::new(path, SIZE).into_drawing_area(), cache_name, statistics),
+        ImageFormat::Png => draw_heatmap(BitMapBackend::new(path, SIZE).into_drawing_area(), cache_name, statistics),
+    };
+    result.map_err(|e| format!("Couldn't render the heatmap to {}: {e}", path.display()))
+}
+
+/// The image formats which can be rendered
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+/// Chooses the image format from a file's extension
+pub fn image_format(path: &Path) -> Result<ImageFormat, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("svg") => Ok(ImageFormat::Svg),
+        Some("png") => Ok(ImageFormat::Png),
+        _ => Err(format!("Can't tell the image format of {}, use a .svg or .png extension", path.display())),
+    }
+}
+
+fn draw_heatmap<DB: DrawingBackend>(root: DrawingArea<DB, Shift>, cache_name: &str, statistics: &SetStatistics) -> Result<(), Box<dyn std::error::Error>>
+where DB::ErrorType: 'static {
+    let misses = statistics.misses();
+    let sets = misses.len() as u64;
+    // A power of two row width keeps sets which share their low index bits in the same column
+    let width = (sets as f64).sqrt().ceil().max(1.0) as u64;
+    let width = width.next_power_of_two().min(sets.max(1));
+    let rows = sets.div_ceil(width);
+    let min_misses = misses.iter().copied().min().unwrap_or(0);
+    let max_misses = misses.iter().copied().max().unwrap_or(0);
+
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption(format!("{cache_name}: misses by set, from {min_misses} (white) to {max_misses} (red)"), ("sans-serif", 22))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0..width, 0..rows)?;
+    chart.configure_mesh()
+        .disable_mesh()
+        .x_desc(format!("set % {width}"))
+        .y_desc(format!("set / {width}"))
+        .draw()?;
+    chart.draw_series(misses.iter().enumerate().map(|(set, &count)| {
+        let set = set as u64;
+        let (x, y) = (set % width, set / width);
+        // Scaled between the least and most missed sets, as uneven sets are what's interesting
+        let intensity = (count - min_misses) as f64 / (max_misses - min_misses).max(1) as f64;
+        let shade = (255.0 * (1.0 - intensity)) as u8;
+        Rectangle::new([(x, y), (x + 1, y + 1)], RGBColor(255, shade, shade).filled())
+    }))?;
+    root.present()?;
+    Ok(())
+}
+
+/// A line on a miss rate curve, as (size in bytes, miss rate) points in order of size
+pub struct CurveSeries {
+    pub label: String,
+    pub points: Vec<(u64, f64)>,
+}
+
+/// Renders miss rate against cache size, with a line for each series, such as each policy
+///
+/// Sizes are plotted on a logarithmic axis, as sweeps usually double the size at each step. The
+/// image format is chosen by the file extension, `svg` or `png`.
+///
+/// # Arguments
+///
+/// * `path`: The image file to write
+/// * `title`: The title of the plot
+/// * `series`: The lines to draw
+///
+/// returns: Result<(), String>
+pub fn render_miss_rate_curve(path: &Path, title: &str, series: &[CurveSeries]) -> Result<(), String> {
+    const SIZE: (u32, u32) = (900, 600);
+    let result = match image_format(path)? {
+        ImageFormat::Svg => draw_curve(SVGBackend::new(path, SIZE).into_drawing_area(), title, series),
+        ImageFormat::Png => draw_curve(BitMapBackend::new(path, SIZE).into_drawing_area(), title, series),
+    };
+    result.map_err(|e| format!("Couldn't render the miss rate curve to {}: {e}", path.display()))
+}
+
+fn draw_curve<DB: DrawingBackend>(root: DrawingArea<DB, Shift>, title: &str, series: &[CurveSeries]) -> Result<(), Box<dyn std::error::Error>>
+where DB::ErrorType: 'static {
+    // Sizes are plotted by their base 2 logarithm, so each doubling is the same distance apart and
+    // the ticks fall on powers of two
+    let log_sizes = series.iter().flat_map(|s| s.points.iter().map(|(size, _)| (*size as f64).log2()));
+    let min = log_sizes.clone().fold(f64::INFINITY, f64::min).floor();
+    let max = log_sizes.fold(f64::NEG_INFINITY, f64::max).ceil().max(min + 1.0);
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 22))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(60)
+        .build_cartesian_2d(min..max, 0.0..100.0)?;
+    chart.configure_mesh()
+        .x_desc("Cache size")
+        .y_desc("Miss rate (%)")
+        .x_labels((max - min) as usize + 1)
+        .x_label_formatter(&|log_size| match log_size.fract() == 0.0 {
+            true => format_size(1 << *log_size as u32),
+            false => String::new(),
+        })
+        .draw()?;
+    for (index, line) in series.iter().enumerate() {
+        let colour = Palette99::pick(index).to_rgba();
+        let points: Vec<(f64, f64)> = line.points.iter().map(|(size, rate)| ((*size as f64).log2(), rate * 100.0)).collect();
+        chart.draw_series(LineSeries::new(points.clone(), colour.stroke_width(2)))?
+            .label(&line.label)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], colour.stroke_width(2)));
+        chart.draw_series(points.into_iter().map(|point| Circle::new(point, 3, colour.filled())))?;
+    }
+    chart.configure_series_labels()
+        .position(SeriesLabelPosition::UpperRight)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
+
+/// Formats a size with the largest binary unit which divides it
+fn format_size(size: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+    UNITS.iter()
+        .find(|(unit, _)| size >= *unit && size.is_multiple_of(*unit))
+        .map(|(unit, name)| format!("{}{name}", size / unit))
+        .unwrap_or(format!("{size}B"))
+}
@@ -0,0 +1,151 @@
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::sync::Arc;
+use libloading::Library;
+use cachelib::replacement_policies::{register_policy, RegisteredPolicy, ReplacementPolicy};
+
+/// The version of the plugin interface, which plugins must report in each descriptor
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// The name of the function a plugin exports to list its policies
+///
+/// In C, it is `const struct cachesim_policy *cachesim_policies(size_t *count)`, returning an array
+/// of `count` descriptors which live as long as the library
+const ENTRY_POINT: &[u8] = b"cachesim_policies";
+
+/// Describes one policy of a plugin, laid out as the C struct
+///
+/// ```c
+/// struct cachesim_policy {
+///     uint32_t abi_version;
+///     const char *name;
+///     void *(*create)(uint64_t lines, uint64_t sets);
+///     void (*destroy)(void *state);
+///     void (*update_on_read)(void *state, uint64_t cache_index);
+///     uint64_t (*get_new_line)(void *state, uint64_t set_lower_bound_index, uint64_t set, uint64_t lines_per_set);
+///     void *(*clone)(const void *state);
+/// };
+/// ```
+///
+/// Each cache using the policy gets its own state from `create`, which is only used by one thread at
+/// a time, but may be moved between threads. `clone` copies a state when a simulator is copied, and
+/// may be called on the same state from several threads at once, so mustn't modify it.
+#[repr(C)]
+struct PolicyDescriptor {
+    abi_version: u32,
+    name: *const c_char,
+    create: extern "C" fn(lines: u64, sets: u64) -> *mut c_void,
+    destroy: extern "C" fn(state: *mut c_void),
+    update_on_read: extern "C" fn(state: *mut c_void, cache_index: u64),
+    get_new_line: extern "C" fn(state: *mut c_void, set_lower_bound_index: u64, set: u64, lines_per_set: u64) -> u64,
+    clone: extern "C" fn(state: *const c_void) -> *mut c_void,
+}
+
+type EntryPoint = unsafe extern "C" fn(count: *mut usize) -> *const PolicyDescriptor;
+
+/// A replacement policy implemented by a plugin, owning the state the plugin created for one cache
+struct PluginPolicy {
+    name: Arc<str>,
+    state: *mut c_void,
+    destroy: extern "C" fn(*mut c_void),
+    update_on_read: extern "C" fn(*mut c_void, u64),
+    get_new_line: extern "C" fn(*mut c_void, u64, u64, u64) -> u64,
+    clone: extern "C" fn(*const c_void) -> *mut c_void,
+}
+
+// SAFETY: the plugin interface requires states to be movable between threads, and the policy is
+// only changed through &mut self, so by one thread at a time
+unsafe impl Send for PluginPolicy {}
+
+// SAFETY: the only use of a shared policy is clone, which the interface requires to be safe to
+// call from several threads at once
+unsafe impl Sync for PluginPolicy {}
+
+impl Clone for PluginPolicy {
+    fn clone(&self) -> Self {
+        let state = (self.clone)(self.state);
+        assert!(!state.is_null(), "The {} policy couldn't copy its state", self.name);
+        Self { state, name: self.name.clone(), ..*self }
+    }
+}
+
+impl ReplacementPolicy for PluginPolicy {
+    fn update_on_read(&mut self, cache_index: u64) {
+        (self.update_on_read)(self.state, cache_index)
+    }
+
+    fn get_new_line(&mut self, set_lower_bound_index: u64, set: u64, cache_lines_per_set: u64) -> u64 {
+        let line = (self.get_new_line)(self.state, set_lower_bound_index, set, cache_lines_per_set);
+        // A line outside the set would corrupt another set, so is treated as a bug in the plugin
+        assert!((set_lower_bound_index..set_lower_bound_index + cache_lines_per_set).contains(&line),
+                "The {} policy chose line {line}, which isn't in set {set}", self.name);
+        line
+    }
+}
+
+impl Drop for PluginPolicy {
+    fn drop(&mut self) {
+        (self.destroy)(self.state)
+    }
+}
+
+/// Loads a plugin library and registers each of its policies, so configurations can use them by
+/// name
+///
+/// The library is never unloaded, as the policies created from it may live until the program
+/// exits, so a failure part way through leaves the earlier policies registered.
+///
+/// # Arguments
+///
+/// * `path`: The path to the shared library
+///
+/// returns: Result<Vec<String>, String>, the names of the policies registered
+///
+/// # Safety
+///
+/// Loading a library runs its initialisation code, and the library is trusted to implement the
+/// interface described on [PolicyDescriptor]
+pub unsafe fn load_policy_plugin(path: &str) -> Result<Vec<String>, String> {
+    // SAFETY: the caller trusts the library's initialisation code
+    let library = unsafe { Library::new(path) }.map_err(|e| format!("Couldn't load the policy plugin {path}: {e}"))?;
+    // The factories call into the library, so it must stay loaded
+    let library: &'static Library = Box::leak(Box::new(library));
+    // SAFETY: the interface requires the entry point to have the signature of EntryPoint
+    let entry_point = unsafe { library.get::<EntryPoint>(ENTRY_POINT) }
+        .map_err(|e| format!("The policy plugin {path} doesn't export cachesim_policies: {e}"))?;
+    let mut count = 0;
+    // SAFETY: the entry point only writes the number of descriptors to count
+    let descriptors = unsafe { entry_point(&mut count) };
+    if descriptors.is_null() || count == 0 {
+        return Err(format!("The policy plugin {path} doesn't provide any policies"));
+    }
+    // SAFETY: the interface requires the descriptors to be an array of count descriptors which lives
+    // as long as the library, which is never unloaded
+    let descriptors = unsafe { std::slice::from_raw_parts(descriptors, count) };
+    let mut names = Vec::with_capacity(count);
+    for descriptor in descriptors {
+        if descriptor.abi_version != PLUGIN_ABI_VERSION {
+            return Err(format!("The policy plugin {path} uses version {} of the plugin interface, but only version {PLUGIN_ABI_VERSION} is supported", descriptor.abi_version));
+        }
+        if descriptor.name.is_null() {
+            return Err(format!("A policy in the plugin {path} has no name"));
+        }
+        // SAFETY: the name isn't null, and the interface requires it to be nul terminated
+        let name = unsafe { CStr::from_ptr(descriptor.name) }.to_str()
+            .map_err(|_| format!("A policy in the plugin {path} has a name which isn't UTF-8"))?
+            .to_string();
+        let shared_name: Arc<str> = Arc::from(name.as_str());
+        let (create, destroy, update_on_read, get_new_line, clone) = (descriptor.create, descriptor.destroy, descriptor.update_on_read, descriptor.get_new_line, descriptor.clone);
+        register_policy(RegisteredPolicy {
+            name: name.clone(),
+            parameters: Vec::new(),
+            factory: Arc::new(move |lines, sets, _| {
+                let state = create(lines, sets);
+                assert!(!state.is_null(), "The {shared_name} policy couldn't be created for a cache of {lines} lines");
+                Box::new(PluginPolicy { name: shared_name.clone(), state, destroy, update_on_read, get_new_line, clone })
+            }),
+        }).map_err(|e| format!("Couldn't register a policy from the plugin {path}: {e}"))?;
+        names.push(name);
+    }
+    Ok(names)
+}
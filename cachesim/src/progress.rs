@@ -0,0 +1,21 @@
+use std::fmt::Write;
+use console::Term;
+use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+
+/// Creates a progress bar for simulating a trace of the given size, showing the bytes processed,
+/// the accesses simulated per second, and the time remaining
+///
+/// The bar is drawn to stderr, so never ends up in piped or redirected results, and is hidden when
+/// `quiet` is set or stderr isn't a terminal
+pub fn trace_progress_bar(total_bytes: u64, quiet: bool) -> ProgressBar {
+    if quiet || !Term::stderr().is_term() {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template("{wide_bar} {bytes}/{total_bytes} {accesses_per_sec} ETA {eta}")
+        .expect("The progress bar template should be valid")
+        .with_key("accesses_per_sec", |state: &ProgressState, w: &mut dyn Write| {
+            // Each access is one 40 byte record
+            let _ = write!(w, "{:.0} accesses/s", state.per_sec() / 40.0);
+        });
+    ProgressBar::new(total_bytes).with_style(style)
+}
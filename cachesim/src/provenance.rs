@@ -0,0 +1,81 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use cachelib::config::LayeredCacheConfig;
+
+/// The number of bytes hashed from each end of a trace when it isn't hashed in full
+const SAMPLE_BYTES: usize = 1 << 20;
+
+/// A record of what produced a result, so it can be reproduced or checked later
+#[derive(Debug, Serialize)]
+pub struct Provenance {
+    /// The arguments the simulator was run with, including the program name
+    pub command_line: Vec<String>,
+    pub working_directory: Option<String>,
+    /// The SHA-256 of the fully-resolved configuration, as printed by --dump-effective-config, so
+    /// it doesn't depend on how the configuration was split between files
+    pub config_sha256: String,
+    pub trace: TraceFingerprint,
+    /// The version of the simulator
+    pub version: &'static str,
+    /// The git commit the simulator was built from, if it was built from a checkout
+    pub git_revision: Option<&'static str>,
+    /// When the simulation finished, in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Identifies the contents of a trace file
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceFingerprint {
+    pub path: String,
+    pub size: u64,
+    /// `full` if every byte was hashed, or `sampled` if only the first and last MiB and the size were
+    pub method: &'static str,
+    pub sha256: String,
+}
+
+impl TraceFingerprint {
+    /// Fingerprints a trace, hashing all of it if `full` is set or the trace is small, and otherwise
+    /// only the first and last MiB along with the size, which is enough to tell traces apart without
+    /// reading the whole of a large file
+    pub fn of(path: &str, trace: &[u8], full: bool) -> Self {
+        let mut hasher = Sha256::new();
+        let method = if full || trace.len() <= 2 * SAMPLE_BYTES {
+            hasher.update(trace);
+            "full"
+        } else {
+            hasher.update(&trace[..SAMPLE_BYTES]);
+            hasher.update(&trace[trace.len() - SAMPLE_BYTES..]);
+            hasher.update((trace.len() as u64).to_le_bytes());
+            "sampled"
+        };
+        TraceFingerprint { path: path.to_string(), size: trace.len() as u64, method, sha256: hex(&hasher.finalize()) }
+    }
+}
+
+impl Provenance {
+    /// Records the provenance of a simulation which has just finished
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The fully-resolved configuration which was simulated
+    /// * `trace`: The fingerprint of the trace which was simulated
+    ///
+    /// returns: Result<Provenance, String>
+    pub fn record(config: &LayeredCacheConfig, trace: TraceFingerprint) -> Result<Self, String> {
+        let config = serde_json::to_string(config).map_err(|e| format!("Couldn't serialise the configuration {e}"))?;
+        Ok(Provenance {
+            command_line: std::env::args().collect(),
+            working_directory: std::env::current_dir().ok().map(|d| d.display().to_string()),
+            config_sha256: hex(&Sha256::digest(config.as_bytes())),
+            trace,
+            version: env!("CARGO_PKG_VERSION"),
+            git_revision: option_env!("CACHESIM_GIT_REVISION"),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        })
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::ExitCode;
+use clap::{Args, ValueEnum};
+use cachelib::analysis::{ReorderReport, ReorderStrategy, TraceReorderer, DEFAULT_REORDER_WINDOW};
+use cachelib::config::parse_byte_size;
+use crate::report::{render_report, write_output, OutputFormat};
+use crate::trace::map_trace;
+
+/// Arguments for the reorder subcommand
+#[derive(Args, Debug)]
+pub struct ReorderArgs {
+    /// The trace of each thread. A single trace's records are all free to move within their window
+    #[arg(required = true)]
+    traces: Vec<String>,
+
+    /// The path to write the reordered trace to
+    #[arg(short, long, value_name = "PATH")]
+    output: String,
+
+    /// The number of consecutive records reordered together, the furthest any record can move
+    #[arg(long, value_name = "RECORDS", default_value_t = DEFAULT_REORDER_WINDOW)]
+    window: usize,
+
+    /// How the records of each window are reordered
+    #[arg(long, value_enum, default_value_t = Strategy::Shuffle)]
+    strategy: Strategy,
+
+    /// The seed of the shuffle, the same seed always giving the same trace
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// The line size clustered and counted in the report
+    #[arg(long, default_value = "64", value_parser = parse_byte_size)]
+    line_size: u64,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    report: Option<String>,
+
+    /// The format of the report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    output_format: OutputFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    /// A random interleaving of the threads within each window
+    Shuffle,
+    /// Accesses to the same line brought together within each window
+    Cluster,
+}
+
+/// Reorders the records of one or more traces within bounded windows, writing the new trace and
+/// reporting how far the records moved
+pub fn run(args: &ReorderArgs) -> Result<ExitCode, String> {
+    let strategy = match args.strategy {
+        Strategy::Shuffle => ReorderStrategy::Shuffle { seed: args.seed },
+        Strategy::Cluster => ReorderStrategy::Cluster,
+    };
+    let reorderer = TraceReorderer::new(args.window, args.line_size, strategy)?;
+    let maps = args.traces.iter().map(|path| map_trace(path)).collect::<Result<Vec<_>, _>>()?;
+    let file = File::create(&args.output).map_err(|e| format!("Couldn't create the reordered trace {}: {e}", args.output))?;
+    let mut writer = BufWriter::new(file);
+    // The first error writing the trace, the rest of the records being skipped
+    let mut error = None;
+    let emit = |record: &[u8]| {
+        if error.is_none() {
+            error = writer.write_all(record).err();
+        }
+    };
+    let report = match maps.as_slice() {
+        [trace] => reorderer.reorder_records(trace, emit)?,
+        threads => reorderer.reorder(&threads.iter().map(|map| map.as_ref()).collect::<Vec<_>>(), emit)?,
+    };
+    error.map_or_else(|| writer.flush(), Err).map_err(|e| format!("Couldn't write the reordered trace {}: {e}", args.output))?;
+    let output = render_report(&report, || vec![report_rows(&report).into()], args.output_format)?;
+    write_output(args.report.as_deref(), &output)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn report_rows(report: &ReorderReport) -> Vec<Vec<String>> {
+    let fraction = |count: u64| match report.records {
+        0 => "-".to_string(),
+        records => format!("{:.2}%", 100.0 * count as f64 / records as f64),
+    };
+    let rows = [
+        ["records".to_string(), report.records.to_string()],
+        ["threads".to_string(), report.threads.to_string()],
+        ["windows".to_string(), report.windows.to_string()],
+        ["records moved".to_string(), format!("{} ({})", report.moved, fraction(report.moved))],
+        ["mean displacement".to_string(), format!("{:.2}", report.mean_displacement)],
+        ["max displacement".to_string(), report.max_displacement.to_string()],
+        ["same line as previous, before".to_string(), format!("{} ({})", report.same_line_before, fraction(report.same_line_before))],
+        ["same line as previous, after".to_string(), format!("{} ({})", report.same_line_after, fraction(report.same_line_after))],
+    ];
+    rows.map(Vec::from).to_vec()
+}
@@ -0,0 +1,227 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use cachelib::config::LayeredCacheConfig;
+use cachelib::results::SimulationOutput;
+use cachelib::simulator::LayeredCacheResult;
+
+#[cfg(feature = "sqlite")]
+pub mod db;
+
+/// The formats results can be written in
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The JSON format of the specification
+    #[default]
+    Json,
+    /// JSON Lines, one compact JSON object per line. Batches and sweeps write each line as soon as
+    /// its run finishes
+    Jsonl,
+    Csv,
+    /// An aligned plain text table
+    Table,
+    /// A GitHub flavoured Markdown table
+    Markdown,
+}
+
+/// Where and how a subcommand writes a report other than the results of a simulation, which is
+/// laid out as tables unless JSON is asked for
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// Write the report to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<String>,
+
+    /// The format of the report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub output_format: OutputFormat,
+}
+
+impl ReportArgs {
+    /// Renders a report in the chosen format, see [render_report], and writes it out
+    pub fn write<T: Serialize>(&self, report: &T, tables: impl FnOnce() -> Vec<Table>) -> Result<(), String> {
+        write_output(self.output.as_deref(), &render_report(report, tables, self.output_format)?)
+    }
+}
+
+/// A table of a report, with a title written above it if it has one
+pub struct Table {
+    pub title: Option<String>,
+    /// The header row, followed by the rest
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn titled(title: String, rows: Vec<Vec<String>>) -> Self {
+        Table { title: Some(title), rows }
+    }
+}
+
+impl From<Vec<Vec<String>>> for Table {
+    fn from(rows: Vec<Vec<String>>) -> Self {
+        Table { title: None, rows }
+    }
+}
+
+/// Renders a report in the given format
+///
+/// # Arguments
+///
+/// * `report`: The report, serialised as it is in the JSON formats
+/// * `tables`: Lays the report out as tables for the other formats, which are separated by a blank
+///   line
+/// * `format`: The format to render
+///
+/// returns: Result<String, String>
+pub fn render_report<T: Serialize>(report: &T, tables: impl FnOnce() -> Vec<Table>, format: OutputFormat) -> Result<String, String> {
+    let render_rows = match format {
+        OutputFormat::Json => return serde_json::to_string_pretty(report).map(|json| json + "\n").map_err(|e| format!("Couldn't serialise the output {e}")),
+        OutputFormat::Jsonl => return serde_json::to_string(report).map(|json| json + "\n").map_err(|e| format!("Couldn't serialise the output {e}")),
+        OutputFormat::Csv => render_csv,
+        OutputFormat::Table => render_table,
+        OutputFormat::Markdown => render_markdown,
+    };
+    Ok(tables().iter()
+        .map(|table| table.title.as_ref().map_or(String::new(), |title| format!("{title}\n")) + &render_rows(&table.rows))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Renders the result of a simulation in the given format, the JSON formats including the
+/// configuration it was produced with if one is given
+pub fn render(result: &LayeredCacheResult, config: Option<&LayeredCacheConfig>, format: OutputFormat) -> Result<String, String> {
+    let output = || match config {
+        Some(config) => SimulationOutput::new(result).with_config(config),
+        None => SimulationOutput::new(result),
+    };
+    match format {
+        OutputFormat::Json => serde_json::to_string_pretty(&output())
+            .map(|json| json + "\n")
+            .map_err(|e| format!("Couldn't serialise the output {e}")),
+        OutputFormat::Jsonl => serde_json::to_string(&output())
+            .map(|json| json + "\n")
+            .map_err(|e| format!("Couldn't serialise the output {e}")),
+        OutputFormat::Csv => Ok(render_csv(&result_rows(result))),
+        OutputFormat::Table => Ok(render_table(&result_rows(result))),
+        OutputFormat::Markdown => Ok(render_markdown(&result_rows(result))),
+    }
+}
+
+/// Writes rendered output to a file, or to stdout if no path is given
+pub fn write_output(path: Option<&str>, contents: &str) -> Result<(), String> {
+    match path {
+        Some(path) => fs::write(path, contents).map_err(|e| format!("Couldn't write the output to {path}: {e}")),
+        None => {
+            print!("{contents}");
+            Ok(())
+        }
+    }
+}
+
+/// Opens a file for output which is written incrementally, or stdout if no path is given
+pub fn open_output(path: Option<&str>) -> Result<Box<dyn Write>, String> {
+    match path {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("Couldn't create the output file {path}: {e}"))?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+/// Writes a value as a single line of JSON, flushing it so it can be read straight away
+pub fn write_json_line<T: serde::Serialize>(output: &mut dyn Write, value: &T) -> Result<(), String> {
+    let line = serde_json::to_string(value).map_err(|e| format!("Couldn't serialise the output {e}"))?;
+    writeln!(output, "{line}")
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Couldn't write the output: {e}"))
+}
+
+/// Formats a miss rate as a percentage, or "-" if there were no accesses
+pub fn miss_rate(hits: u64, misses: u64) -> String {
+    match hits + misses {
+        0 => "-".to_string(),
+        accesses => format!("{:.2}%", 100.0 * misses as f64 / accesses as f64),
+    }
+}
+
+/// Lays out a result as a header row and a row per cache, followed by main memory
+fn result_rows(result: &LayeredCacheResult) -> Vec<Vec<String>> {
+    let mut rows = vec![["cache", "accesses", "hits", "misses", "miss rate"].map(str::to_string).to_vec()];
+    for cache in result.caches() {
+        rows.push(vec![
+            cache.name().to_string(),
+            (cache.hits() + cache.misses()).to_string(),
+            cache.hits().to_string(),
+            cache.misses().to_string(),
+            miss_rate(cache.hits(), cache.misses()),
+        ]);
+    }
+    rows.push(vec!["main memory".to_string(), result.main_memory_accesses().to_string(), String::new(), String::new(), String::new()]);
+    rows
+}
+
+/// Renders rows as an aligned table, with the first column left aligned and the rest right aligned
+pub fn render_table(rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = (0..rows[0].len()).map(|i| rows.iter().map(|r| r[i].len()).max().unwrap_or(0)).collect();
+    let mut output = String::new();
+    for row in rows {
+        let cells: Vec<String> = row.iter().zip(&widths).enumerate()
+            .map(|(i, (cell, width))| if i == 0 { format!("{cell:<width$}") } else { format!("{cell:>width$}") })
+            .collect();
+        output.push_str(cells.join("  ").trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// Renders rows as a Markdown table, taking the first row as the header
+pub fn render_markdown(rows: &[Vec<String>]) -> String {
+    let mut output = String::new();
+    for (index, row) in rows.iter().enumerate() {
+        output.push_str(&format!("| {} |\n", row.join(" | ")));
+        if index == 0 {
+            output.push_str(&format!("|{}\n", "---|".repeat(row.len())));
+        }
+    }
+    output
+}
+
+/// Renders rows as CSV, quoting cells which need it
+pub fn render_csv(rows: &[Vec<String>]) -> String {
+    let quote = |cell: &String| if cell.contains([',', '"', '\n']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.clone()
+    };
+    rows.iter()
+        .map(|row| row.iter().map(quote).collect::<Vec<_>>().join(",") + "\n")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cachelib::config::migration::CURRENT_CONFIG_VERSION;
+    use super::*;
+
+    #[test]
+    fn the_configuration_is_only_embedded_when_asked_for() {
+        let result: LayeredCacheResult = serde_json::from_str(r#"{"main_memory_accesses": 2, "caches": [{"name": "L1", "hits": 1, "misses": 2}]}"#).unwrap();
+        let config: LayeredCacheConfig = serde_json::from_value(serde_json::json!({"version": CURRENT_CONFIG_VERSION, "caches": [{"name": "L1", "size": 1024}]})).unwrap();
+        assert_eq!(render(&result, None, OutputFormat::Json).unwrap(), "{\n  \"main_memory_accesses\": 2,\n  \"caches\": [\n    {\n      \"name\": \"L1\",\n      \"hits\": 1,\n      \"misses\": 2\n    }\n  ]\n}\n");
+        assert_eq!(render(&result, None, OutputFormat::Jsonl).unwrap(), "{\"main_memory_accesses\":2,\"caches\":[{\"name\":\"L1\",\"hits\":1,\"misses\":2}]}\n");
+        let embedded: serde_json::Value = serde_json::from_str(&render(&result, Some(&config), OutputFormat::Jsonl).unwrap()).unwrap();
+        assert!(embedded["schema_version"].is_u64());
+        assert_eq!(serde_json::from_value::<LayeredCacheConfig>(embedded["config"].clone()).unwrap(), config);
+    }
+
+    #[test]
+    fn a_report_is_written_in_each_format() {
+        let report = serde_json::json!({"misses": 3});
+        let tables = || vec![Table::titled("Misses".to_string(), vec![vec!["cache".to_string(), "misses".to_string()], vec!["L1".to_string(), "3".to_string()]])];
+        assert_eq!(render_report(&report, tables, OutputFormat::Jsonl).unwrap(), "{\"misses\":3}\n");
+        assert_eq!(render_report(&report, tables, OutputFormat::Csv).unwrap(), "Misses\ncache,misses\nL1,3\n");
+        assert_eq!(render_report(&report, tables, OutputFormat::Markdown).unwrap(), "Misses\n| cache | misses |\n|---|---|\n| L1 | 3 |\n");
+    }
+}
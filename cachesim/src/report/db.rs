@@ -0,0 +1,185 @@
+use rusqlite::{params, Connection};
+use cachelib::config::LayeredCacheConfig;
+use cachelib::simulator::LayeredCacheResult;
+use crate::provenance::{Provenance, TraceFingerprint};
+
+/// The migrations which bring a database from each version of the schema to the next, the first
+/// creating the tables. The version a database is at is kept in its `user_version`, and columns are
+/// only ever added, so queries written against an older database keep working
+const MIGRATIONS: [&str; 2] = [SCHEMA, WRITES];
+
+/// The version of the schema this simulator writes
+const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    mode TEXT NOT NULL,
+    label TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    version TEXT NOT NULL,
+    git_revision TEXT,
+    command_line TEXT NOT NULL,
+    config_sha256 TEXT NOT NULL,
+    config TEXT NOT NULL,
+    trace_path TEXT NOT NULL,
+    trace_size INTEGER NOT NULL,
+    trace_sha256 TEXT NOT NULL,
+    trace_fingerprint_method TEXT NOT NULL,
+    main_memory_accesses INTEGER NOT NULL,
+    page_crossing_accesses INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS cache_results (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    hits INTEGER NOT NULL,
+    misses INTEGER NOT NULL,
+    prefetches INTEGER NOT NULL,
+    writebacks INTEGER NOT NULL,
+    writeback_stalls INTEGER NOT NULL,
+    writeback_buffer_hits INTEGER NOT NULL,
+    disturbed_hits INTEGER NOT NULL,
+    disturbed_misses INTEGER NOT NULL,
+    fetch_hits INTEGER NOT NULL,
+    fetch_misses INTEGER NOT NULL,
+    PRIMARY KEY (run_id, position)
+);
+CREATE INDEX IF NOT EXISTS runs_by_config ON runs (config_sha256);
+CREATE INDEX IF NOT EXISTS runs_by_trace ON runs (trace_sha256);
+";
+
+/// Version 2 counts the writes to each cache, which are zero in the runs recorded before
+const WRITES: &str = "
+ALTER TABLE cache_results ADD COLUMN write_hits INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE cache_results ADD COLUMN write_misses INTEGER NOT NULL DEFAULT 0;
+";
+
+/// A SQLite database the results of runs are appended to, so many sweeps and batches can be
+/// queried together
+///
+/// Each run is a row of `runs`, with its provenance and fully-resolved configuration as JSON, and
+/// each of its caches a row of `cache_results`, in the order of the configuration.
+pub struct ResultsDatabase {
+    connection: Connection,
+}
+
+impl ResultsDatabase {
+    /// Opens a database, creating it and its tables if they don't exist, or bringing its tables up
+    /// to date if it was written by an older version of the simulator
+    ///
+    /// returns: Result<ResultsDatabase, String>, an error if the file isn't a SQLite database, or
+    /// was written by a newer version of the simulator
+    pub fn open(path: &str) -> Result<Self, String> {
+        let connection = Connection::open(path).map_err(|e| format!("Couldn't open the results database {path}: {e}"))?;
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|e| format!("Couldn't read the results database {path}: {e}"))?;
+        if version > SCHEMA_VERSION {
+            return Err(format!("The results database {path} has schema version {version}, newer than the {SCHEMA_VERSION} this simulator writes"));
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let to = from + 1;
+            connection.execute_batch(&format!("BEGIN; {migration} PRAGMA user_version = {to}; COMMIT;"))
+                .map_err(|e| format!("Couldn't bring the results database {path} up to schema version {to}: {e}"))?;
+        }
+        Ok(Self { connection })
+    }
+
+    /// Appends a run, with all of its caches or none of them
+    ///
+    /// # Arguments
+    ///
+    /// * `mode`: The subcommand which simulated the run, such as `batch` or `sweep`
+    /// * `label`: Tells the run apart from the others of the same invocation
+    /// * `config`: The fully-resolved configuration which was simulated
+    /// * `trace`: The fingerprint of the trace which was simulated
+    /// * `result`: The counts of the run
+    ///
+    /// returns: Result<(), String>
+    pub fn record(&mut self, mode: &str, label: &str, config: &LayeredCacheConfig, trace: TraceFingerprint, result: &LayeredCacheResult) -> Result<(), String> {
+        let provenance = Provenance::record(config, trace)?;
+        let config = serde_json::to_string(config).map_err(|e| format!("Couldn't serialise the configuration {e}"))?;
+        let command_line = serde_json::to_string(&provenance.command_line).map_err(|e| format!("Couldn't serialise the command line {e}"))?;
+        let error = |e: rusqlite::Error| format!("Couldn't record run {label} in the results database: {e}");
+        let transaction = self.connection.transaction().map_err(error)?;
+        transaction.execute(
+            "INSERT INTO runs (mode, label, timestamp, version, git_revision, command_line, config_sha256, config, trace_path, trace_size, trace_sha256, trace_fingerprint_method, main_memory_accesses, page_crossing_accesses)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                mode,
+                label,
+                provenance.timestamp as i64,
+                provenance.version,
+                provenance.git_revision,
+                command_line,
+                provenance.config_sha256,
+                config,
+                provenance.trace.path,
+                provenance.trace.size as i64,
+                provenance.trace.sha256,
+                provenance.trace.method,
+                result.main_memory_accesses() as i64,
+                result.page_crossing_accesses() as i64,
+            ],
+        ).map_err(error)?;
+        let run = transaction.last_insert_rowid();
+        for (position, cache) in result.caches().iter().enumerate() {
+            let ((disturbed_hits, disturbed_misses), (fetch_hits, fetch_misses), (write_hits, write_misses)) = (cache.disturbed(), cache.fetches(), cache.writes());
+            transaction.execute(
+                "INSERT INTO cache_results (run_id, position, name, hits, misses, prefetches, writebacks, writeback_stalls, writeback_buffer_hits, disturbed_hits, disturbed_misses, fetch_hits, fetch_misses, write_hits, write_misses)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                params![
+                    run,
+                    position as i64,
+                    cache.name(),
+                    cache.hits() as i64,
+                    cache.misses() as i64,
+                    cache.prefetches() as i64,
+                    cache.writebacks() as i64,
+                    cache.writeback_stalls() as i64,
+                    cache.writeback_buffer_hits() as i64,
+                    disturbed_hits as i64,
+                    disturbed_misses as i64,
+                    fetch_hits as i64,
+                    fetch_misses as i64,
+                    write_hits as i64,
+                    write_misses as i64,
+                ],
+            ).map_err(error)?;
+        }
+        transaction.commit().map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_recorded_run_can_be_read_back() -> Result<(), String> {
+        let path = std::env::temp_dir().join(format!("cachesim-results-{}.db", std::process::id()));
+        let config: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 4096}]}"#).unwrap();
+        let result: LayeredCacheResult = serde_json::from_str(r#"{"main_memory_accesses": 3, "caches": [{"name": "L1", "hits": 5, "misses": 4}, {"name": "L2", "hits": 1, "misses": 3}]}"#).unwrap();
+        let trace = b"0000000000400000 0000000000000000 R 004\n";
+        let mut database = ResultsDatabase::open(path.to_str().unwrap())?;
+        database.record("sweep", "L1=1024", &config, TraceFingerprint::of("trace.out", trace, true), &result)?;
+        let connection = &database.connection;
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        let (run, mode, label, stored, trace_size, main_memory_accesses): (i64, String, String, String, i64, i64) = connection
+            .query_row("SELECT id, mode, label, config, trace_size, main_memory_accesses FROM runs", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))
+            .unwrap();
+        assert_eq!((mode.as_str(), label.as_str(), trace_size, main_memory_accesses), ("sweep", "L1=1024", 40, 3));
+        assert_eq!(serde_json::from_str::<LayeredCacheConfig>(&stored).unwrap(), config);
+        let mut statement = connection.prepare("SELECT name, hits, misses FROM cache_results WHERE run_id = ?1 ORDER BY position").unwrap();
+        let caches: Vec<(String, i64, i64)> = statement.query_map([run], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(caches, [("L1".to_string(), 5, 4), ("L2".to_string(), 1, 3)]);
+        drop(statement);
+        drop(database);
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+}
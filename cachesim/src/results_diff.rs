@@ -0,0 +1,82 @@
+use std::fs;
+use std::process::ExitCode;
+use clap::Args;
+use cachelib::results::SimulationOutput;
+use cachelib::simulator::diff::{diff_results, MetricDelta};
+use cachelib::simulator::LayeredCacheResult;
+use crate::report::{render_table, ReportArgs};
+
+/// Arguments for the diff subcommand
+#[derive(Args, Debug)]
+pub struct ResultsDiffArgs {
+    /// The path to the baseline results, as written by a simulation with the json output format
+    first: String,
+
+    /// The path to the results to compare against the baseline
+    second: String,
+
+    /// The largest change allowed in any metric, as a percentage of the baseline value, before
+    /// exiting with 1
+    #[arg(long, value_name = "PERCENT", default_value_t = 0.0)]
+    threshold: f64,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Prints the hits, misses and miss ratio of each cache in two results, and the main memory
+/// accesses, with the changes between them
+///
+/// Exits with 1 if any metric changed by more than the threshold, or a cache is only in one result
+pub fn run(args: &ResultsDiffArgs) -> Result<ExitCode, String> {
+    let deltas = diff_results(&load_result(&args.first)?, &load_result(&args.second)?);
+    let threshold = args.threshold / 100.0;
+    let metrics: Vec<serde_json::Value> = deltas.iter()
+        .map(|d| serde_json::json!({
+            "metric": d.metric,
+            "first": d.first,
+            "second": d.second,
+            "absolute": d.absolute(),
+            "relative": d.relative().filter(|r| r.is_finite()),
+            "exceeds_threshold": d.exceeds(threshold),
+        }))
+        .collect();
+    args.report.write(&metrics, || vec![table_rows(&deltas, threshold).into()])?;
+    Ok(if deltas.iter().any(|d| d.exceeds(threshold)) { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+fn load_result(path: &str) -> Result<LayeredCacheResult, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("Couldn't open the results at path {path}: {e}"))?;
+    let output = SimulationOutput::from_json(&json).map_err(|e| format!("Couldn't parse the results at path {path}: {e}"))?;
+    Ok(output.result())
+}
+
+/// Lays out a row per metric, marking those which changed by more than the threshold
+pub fn format_table(deltas: &[MetricDelta], threshold: f64) -> String {
+    render_table(&table_rows(deltas, threshold))
+}
+
+fn table_rows(deltas: &[MetricDelta], threshold: f64) -> Vec<Vec<String>> {
+    let ratio = |delta: &MetricDelta| delta.metric.ends_with("miss_ratio");
+    let value = |delta: &MetricDelta, value: Option<f64>| match value {
+        None => "-".to_string(),
+        Some(value) if ratio(delta) => format!("{:.2}%", value * 100.0),
+        Some(value) => format!("{value:.0}"),
+    };
+    let mut rows = vec![["metric", "first", "second", "change", "relative", ""].map(str::to_string).to_vec()];
+    for delta in deltas {
+        let change = match delta.absolute() {
+            None => "-".to_string(),
+            Some(change) if ratio(delta) => format!("{:+.2}pp", change * 100.0),
+            Some(change) => format!("{change:+.0}"),
+        };
+        let relative = match delta.relative() {
+            None => "-".to_string(),
+            Some(relative) if relative.is_infinite() => format!("{}inf", if relative > 0.0 { "+" } else { "-" }),
+            Some(relative) => format!("{:+.2}%", relative * 100.0),
+        };
+        let marker = if delta.exceeds(threshold) { "*" } else { "" };
+        rows.push(vec![delta.metric.clone(), value(delta, delta.first), value(delta, delta.second), change, relative, marker.to_string()]);
+    }
+    rows
+}
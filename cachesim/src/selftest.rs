@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use clap::Args;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use cachelib::config::load_layered_config;
+use cachelib::simulator::diff::diff_results;
+use cachelib::simulator::{LayeredCacheResult, Simulator};
+use cachelib::util::snapshot::Snapshot;
+use cachelib::util::{get_configs_in, TestCasePaths, EXAMPLES_PATH};
+use crate::report::render_table;
+use crate::results_diff::format_table;
+use crate::trace::map_trace;
+
+/// Arguments for the selftest subcommand
+#[derive(Args, Debug)]
+pub struct SelftestArgs {
+    /// The directory of test cases, holding sample-inputs, sample-outputs and trace-files
+    #[arg(default_value = EXAMPLES_PATH)]
+    directory: String,
+
+    /// Overwrite the expected output of each failing case with its actual result
+    #[arg(long)]
+    bless: bool,
+
+    /// The number of cases to simulate at once. Defaults to the number of CPUs
+    #[arg(short, long)]
+    jobs: Option<usize>,
+}
+
+/// The outcome of a single case
+enum Outcome {
+    Passed,
+    Failed { expected: LayeredCacheResult, actual: LayeredCacheResult },
+    Error(String),
+}
+
+/// Simulates every case in a directory laid out like the examples, comparing each result against
+/// its expected output
+///
+/// Prints a table of the cases, followed by the metrics which differ for each failing case. Exits
+/// with 1 if any case failed, unless `--bless` updated its expected output.
+pub fn run(args: &SelftestArgs) -> Result<ExitCode, String> {
+    let cases = get_configs_in(&args.directory).map_err(|e| format!("Couldn't find the cases in {}: {e}", args.directory))?;
+    if cases.is_empty() {
+        return Err(format!("There are no cases in {}/sample-outputs", args.directory));
+    }
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()
+        .map_err(|e| format!("Couldn't create the worker threads: {e}"))?;
+    let outcomes: Vec<(Outcome, Duration)> = pool.install(|| cases.par_iter().map(run_case).collect());
+    let mut rows = vec![["case", "result", "time"].map(str::to_string).to_vec()];
+    let mut failures = 0;
+    let mut blessed = 0;
+    let mut details = String::new();
+    for (case, (outcome, time)) in cases.iter().zip(&outcomes) {
+        let name = Path::new(&case.output).file_stem().map_or(case.output.clone(), |s| s.to_string_lossy().into_owned());
+        let status = match outcome {
+            Outcome::Passed => "pass".to_string(),
+            Outcome::Failed { actual, .. } if args.bless => {
+                Snapshot::new(actual.clone()).save(Path::new(&case.output))?;
+                blessed += 1;
+                "blessed".to_string()
+            }
+            Outcome::Failed { expected, actual } => {
+                failures += 1;
+                let changed: Vec<_> = diff_results(expected, actual).into_iter().filter(|d| d.exceeds(0.0)).collect();
+                details.push_str(&format!("\n{name}: the expected output (first) differs from the result (second)\n{}", format_table(&changed, 0.0)));
+                "FAIL".to_string()
+            }
+            Outcome::Error(error) => {
+                failures += 1;
+                details.push_str(&format!("\n{name}: {error}\n"));
+                "ERROR".to_string()
+            }
+        };
+        rows.push(vec![name, status, format!("{:.3}s", time.as_secs_f64())]);
+    }
+    print!("{}", render_table(&rows));
+    print!("{details}");
+    match blessed {
+        0 => println!("\n{} passed, {failures} failed", cases.len() - failures),
+        _ => println!("\n{} passed, {blessed} blessed, {failures} failed", cases.len() - failures - blessed),
+    }
+    Ok(if failures == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn run_case(case: &TestCasePaths) -> (Outcome, Duration) {
+    let start = Instant::now();
+    let simulate = || -> Result<Outcome, String> {
+        let expected = Snapshot::load(Path::new(&case.output))?.result;
+        let config = load_layered_config(&[&case.config])?;
+        let trace = map_trace(&case.trace)?;
+        if trace.len() % 40 != 0 {
+            return Err(format!("The trace {} ends part way through a record", case.trace));
+        }
+        let mut simulator = Simulator::new(&config);
+        simulator.simulate(&trace)?;
+        let actual = simulator.into_result();
+        Ok(if actual == expected { Outcome::Passed } else { Outcome::Failed { expected, actual } })
+    };
+    (simulate().unwrap_or_else(Outcome::Error), start.elapsed())
+}
@@ -0,0 +1,558 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::Args;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use cachelib::config::{config_from_value, LayeredCacheConfig};
+use cachelib::simulator::{LayeredCacheResult, Simulator};
+use cachelib::topology::Topology;
+use crate::trace::{map_trace, simulate_windows};
+
+/// The number of records read from an uploaded trace before they are simulated
+const UPLOAD_CHUNK_RECORDS: usize = 16 * 1024;
+
+/// How long a finished job's metrics are still reported, long enough for a few scrapes to see its
+/// final values
+const FINISHED_JOB_METRICS: Duration = Duration::from_secs(300);
+
+/// Arguments for the serve subcommand
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The port to listen on
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    /// The address to listen on. Defaults to only accepting connections from this machine
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// The directory which jobs may reference traces in. Without it, traces must be uploaded
+    #[arg(long, value_name = "DIR")]
+    trace_dir: Option<String>,
+
+    /// The number of jobs referencing traces to simulate at once. Defaults to the number of CPUs.
+    /// Uploaded traces are simulated as they arrive, so aren't limited
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// The number of requests handled at once, including uploads, which hold theirs until the
+    /// trace is simulated. Further connections wait for one to finish
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    connections: usize,
+
+    /// The number of finished jobs kept, after which the oldest are forgotten
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    max_finished_jobs: usize,
+
+    /// The seconds a finished job is kept for before it's forgotten
+    #[arg(long, value_name = "SECONDS", default_value_t = 3600)]
+    finished_job_ttl: u64,
+
+    /// The number of jobs which can wait for a trace to be uploaded, after which new jobs without a
+    /// trace are refused
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    max_awaiting_jobs: usize,
+
+    /// The seconds a job waits for its trace to be uploaded before it's forgotten
+    #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+    awaiting_job_ttl: u64,
+}
+
+/// A request to simulate a trace
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobRequest {
+    /// The configuration, as it would appear in a configuration file
+    config: Value,
+    /// The path of the trace relative to the trace directory. If omitted, the trace is uploaded to
+    /// the job afterwards
+    trace: Option<String>,
+    /// The core making the accesses, defaulting to the lowest numbered core
+    core: Option<u32>,
+}
+
+/// The progress of a job, as reported to clients
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum JobStatus {
+    AwaitingTrace,
+    Queued,
+    Running,
+    Done { result: LayeredCacheResult },
+    Failed { error: String },
+}
+
+impl JobStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            JobStatus::AwaitingTrace => "awaiting_trace",
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done { .. } => "done",
+            JobStatus::Failed { .. } => "failed",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    config: LayeredCacheConfig,
+    core: Option<u32>,
+    warnings: Vec<String>,
+    status: JobStatus,
+    progress: JobProgress,
+    /// When the job was submitted
+    submitted: Instant,
+    /// When the job was done or failed
+    finished: Option<Instant>,
+}
+
+/// How far a job has got, updated as each block of its trace is simulated, for the metrics
+#[derive(Debug, Default, Clone)]
+struct JobProgress {
+    records: u64,
+    /// The hits and misses of each cache so far, in the order of the configuration
+    caches: Vec<(u64, u64)>,
+    main_memory_accesses: u64,
+    started: Option<Instant>,
+    /// The time spent simulating, up to the last block
+    elapsed: Duration,
+}
+
+/// The state shared by the threads handling requests
+struct State {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    /// The id of the next job, which is never reused once a finished job is forgotten
+    next_id: AtomicU64,
+    trace_dir: Option<PathBuf>,
+    pool: ThreadPool,
+    max_finished_jobs: usize,
+    finished_job_ttl: Duration,
+    max_awaiting_jobs: usize,
+    awaiting_job_ttl: Duration,
+}
+
+impl State {
+    fn new(args: &ServeArgs) -> Result<Self, String> {
+        let trace_dir = args.trace_dir.as_ref()
+            .map(|dir| Path::new(dir).canonicalize().map_err(|e| format!("Couldn't open the trace directory {dir}: {e}")))
+            .transpose()?;
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(args.jobs.unwrap_or(0))
+            .build()
+            .map_err(|e| format!("Couldn't create the worker threads: {e}"))?;
+        Ok(State {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            trace_dir,
+            pool,
+            max_finished_jobs: args.max_finished_jobs,
+            finished_job_ttl: Duration::from_secs(args.finished_job_ttl),
+            max_awaiting_jobs: args.max_awaiting_jobs,
+            awaiting_job_ttl: Duration::from_secs(args.awaiting_job_ttl),
+        })
+    }
+}
+
+/// An error response, with its HTTP status code
+struct HttpError(u16, String);
+
+impl From<String> for HttpError {
+    fn from(message: String) -> Self {
+        HttpError(400, message)
+    }
+}
+
+/// Runs the simulator as an HTTP service until the process is stopped
+///
+/// Jobs are submitted as JSON to `POST /jobs`, giving a configuration and either a trace in the
+/// trace directory or no trace, in which case it is uploaded with `PUT /jobs/{id}/trace`. The
+/// status and results of a job are polled with `GET /jobs/{id}`, and `GET /jobs` lists every job.
+/// `GET /metrics` reports the progress of running and recent jobs for Prometheus. Requests are
+/// handled by a fixed number of threads, and finished jobs are forgotten once there are too many or
+/// they're too old, as are jobs whose trace is never uploaded.
+pub fn run(args: &ServeArgs) -> Result<(), String> {
+    if args.connections == 0 {
+        return Err("The server must handle at least one connection at a time".to_string());
+    }
+    let state = Arc::new(State::new(args)?);
+    let address = format!("{}:{}", args.host, args.port);
+    let server = Arc::new(Server::http(&address).map_err(|e| format!("Couldn't listen on {address}: {e}"))?);
+    eprintln!("Listening on http://{address}");
+    for handler in spawn_handlers(&state, &server, args.connections) {
+        handler.join().map_err(|_| "A request handler panicked".to_string())?;
+    }
+    Ok(())
+}
+
+/// Starts the threads which handle the requests to a server, until it's dropped
+fn spawn_handlers(state: &Arc<State>, server: &Arc<Server>, connections: usize) -> Vec<thread::JoinHandle<()>> {
+    (0..connections)
+        .map(|_| {
+            let (state, server) = (Arc::clone(state), Arc::clone(server));
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            })
+        })
+        .collect()
+}
+
+fn handle(state: &Arc<State>, mut request: Request) {
+    let url = request.url().to_string();
+    let segments: Vec<&str> = url.split('?').next().unwrap_or("").split('/').filter(|s| !s.is_empty()).collect();
+    if let (Method::Get, ["metrics"]) = (request.method(), segments.as_slice()) {
+        let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+        if let Err(e) = request.respond(Response::from_string(metrics(state)).with_header(content_type)) {
+            eprintln!("Warning: couldn't send a response to {url}: {e}");
+        }
+        return;
+    }
+    let response = match (request.method(), segments.as_slice()) {
+        (Method::Get, ["jobs"]) => Ok((200, list_jobs(state))),
+        (Method::Post, ["jobs"]) => submit_job(state, &mut request).map(|job| (201, job)),
+        (Method::Get, ["jobs", id]) => parse_id(id).and_then(|id| job_json(state, id)).map(|job| (200, job)),
+        (Method::Put, ["jobs", id, "trace"]) => parse_id(id).and_then(|id| upload_trace(state, id, &mut request)).map(|job| (200, job)),
+        (_, ["jobs"] | ["jobs", _] | ["jobs", _, "trace"] | ["metrics"]) => Err(HttpError(405, format!("{} isn't supported on {url}", request.method()))),
+        _ => Err(HttpError(404, format!("There is nothing at {url}"))),
+    };
+    let (code, body) = response.unwrap_or_else(|HttpError(code, error)| (code, json!({ "error": error })));
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(body.to_string() + "\n").with_status_code(code).with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Warning: couldn't send a response to {url}: {e}");
+    }
+}
+
+fn parse_id(id: &str) -> Result<u64, HttpError> {
+    id.parse().map_err(|_| HttpError(404, format!("There is no job {id}")))
+}
+
+fn list_jobs(state: &State) -> Value {
+    let jobs = state.jobs.lock().unwrap();
+    let statuses: Vec<Value> = jobs.iter()
+        .map(|(id, job)| {
+            let status = serde_json::to_value(&job.status).unwrap();
+            json!({ "id": id, "status": status["status"] })
+        })
+        .collect();
+    json!({ "jobs": statuses })
+}
+
+fn job_json(state: &State, id: u64) -> Result<Value, HttpError> {
+    let jobs = state.jobs.lock().unwrap();
+    let job = jobs.get(&id).ok_or(HttpError(404, format!("There is no job {id}")))?;
+    Ok(job_value(id, job))
+}
+
+fn job_value(id: u64, job: &Job) -> Value {
+    let mut value = serde_json::to_value(&job.status).unwrap();
+    value["id"] = json!(id);
+    value["warnings"] = json!(job.warnings);
+    value
+}
+
+/// Creates a job, starting it straight away if its trace is in the trace directory
+///
+/// The configuration is validated, and the core checked against its topology, before the job is
+/// created, so a job can't fail to create its simulator.
+fn submit_job(state: &Arc<State>, request: &mut Request) -> Result<Value, HttpError> {
+    let job: JobRequest = serde_json::from_reader(request.as_reader()).map_err(|e| format!("Couldn't parse the job: {e}"))?;
+    let (config, warnings) = config_from_value(job.config)?;
+    if let Some(core) = job.core {
+        Topology::from_config(&config)?.path(core).ok_or(format!("The configuration has no caches for core {core}"))?;
+    }
+    let trace = job.trace.map(|trace| resolve_trace(state, &trace)).transpose()?;
+    let status = if trace.is_some() { JobStatus::Queued } else { JobStatus::AwaitingTrace };
+    let id = {
+        let mut jobs = state.jobs.lock().unwrap();
+        forget_jobs(state, &mut jobs);
+        if trace.is_none() && jobs.values().filter(|job| matches!(job.status, JobStatus::AwaitingTrace)).count() >= state.max_awaiting_jobs {
+            return Err(HttpError(503, format!("Too many jobs are waiting for a trace, the limit is {}", state.max_awaiting_jobs)));
+        }
+        let id = state.next_id.fetch_add(1, Ordering::Relaxed);
+        jobs.insert(id, Job { config, core: job.core, warnings, status, progress: JobProgress::default(), submitted: Instant::now(), finished: None });
+        id
+    };
+    if let Some(trace) = trace {
+        let state_for_job = Arc::clone(state);
+        state.pool.spawn(move || {
+            let (config, core) = start_job(state_for_job.jobs.lock().unwrap().get_mut(&id).unwrap());
+            let status = simulate_file(&state_for_job, id, &config, core, &trace);
+            finish_job(&state_for_job, id, status);
+        });
+    }
+    job_json(state, id)
+}
+
+/// Finds a trace in the trace directory, refusing paths which lead outside it
+fn resolve_trace(state: &State, trace: &str) -> Result<PathBuf, HttpError> {
+    let trace_dir = state.trace_dir.as_ref().ok_or(HttpError(400, "The server has no trace directory, upload the trace instead".to_string()))?;
+    let path = trace_dir.join(trace).canonicalize().map_err(|_| HttpError(400, format!("There is no trace {trace}")))?;
+    if !path.starts_with(trace_dir) {
+        return Err(HttpError(400, format!("The trace {trace} is outside the trace directory")));
+    }
+    Ok(path)
+}
+
+/// Marks a job as running, returning its configuration and core
+fn start_job(job: &mut Job) -> (LayeredCacheConfig, Option<u32>) {
+    job.status = JobStatus::Running;
+    job.progress.started = Some(Instant::now());
+    (job.config.clone(), job.core)
+}
+
+/// Records the result of a job, and forgets any finished jobs beyond those kept
+///
+/// returns: Value, the job as reported to clients, even if it's forgotten straight away
+fn finish_job(state: &State, id: u64, status: JobStatus) -> Value {
+    let mut jobs = state.jobs.lock().unwrap();
+    let job = jobs.get_mut(&id).unwrap();
+    job.status = status;
+    job.finished = Some(Instant::now());
+    let value = job_value(id, job);
+    forget_jobs(state, &mut jobs);
+    value
+}
+
+/// Forgets the jobs which have waited for their trace for longer than their time to live, and the
+/// finished jobs older than theirs, then the oldest finished jobs beyond the number kept
+fn forget_jobs(state: &State, jobs: &mut BTreeMap<u64, Job>) {
+    jobs.retain(|_, job| match (&job.status, job.finished) {
+        (JobStatus::AwaitingTrace, _) => job.submitted.elapsed() < state.awaiting_job_ttl,
+        (_, Some(finished)) => finished.elapsed() < state.finished_job_ttl,
+        (_, None) => true,
+    });
+    let mut finished: Vec<(Instant, u64)> = jobs.iter().filter_map(|(&id, job)| job.finished.map(|finished| (finished, id))).collect();
+    if finished.len() > state.max_finished_jobs {
+        finished.sort_unstable();
+        for (_, id) in &finished[..finished.len() - state.max_finished_jobs] {
+            jobs.remove(id);
+        }
+    }
+}
+
+/// Records the records a job has simulated, and the counts of its caches so far
+fn report_progress(state: &State, id: u64, records: u64, simulator: &Simulator) {
+    let result = simulator.get_result();
+    let caches = result.caches().iter().map(|cache| (cache.hits(), cache.misses())).collect();
+    let mut jobs = state.jobs.lock().unwrap();
+    let progress = &mut jobs.get_mut(&id).unwrap().progress;
+    progress.records += records;
+    progress.caches = caches;
+    progress.main_memory_accesses = result.main_memory_accesses();
+    progress.elapsed = progress.started.map_or(Duration::ZERO, |started| started.elapsed());
+}
+
+fn simulate_file(state: &State, id: u64, config: &LayeredCacheConfig, core: Option<u32>, trace: &Path) -> JobStatus {
+    let simulate = || -> Result<LayeredCacheResult, String> {
+        let map = map_trace(&trace.to_string_lossy())?;
+        if map.len() % 40 != 0 {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        let mut simulator = Simulator::new(config);
+        let core = core.unwrap_or_else(|| simulator.topology().cores().next().unwrap());
+        simulate_windows(&map, |window, _| {
+            simulator.simulate_core(window, core)?;
+            report_progress(state, id, (window.len() / 40) as u64, &simulator);
+            Ok(())
+        })?;
+        Ok(simulator.into_result())
+    };
+    finished(simulate())
+}
+
+/// Simulates a trace streamed in the body of the request, as it arrives
+fn upload_trace(state: &Arc<State>, id: u64, request: &mut Request) -> Result<Value, HttpError> {
+    // Checked and started under the same lock, so only one of two uploads at once takes the job
+    let (config, core) = {
+        let mut jobs = state.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id).ok_or(HttpError(404, format!("There is no job {id}")))?;
+        if !matches!(job.status, JobStatus::AwaitingTrace) {
+            return Err(HttpError(409, format!("Job {id} isn't waiting for a trace")));
+        }
+        start_job(job)
+    };
+    let mut simulate = || -> Result<LayeredCacheResult, String> {
+        let mut simulator = Simulator::new(&config);
+        let core = core.unwrap_or_else(|| simulator.topology().cores().next().unwrap());
+        let reader = request.as_reader();
+        let mut buffer = vec![0; UPLOAD_CHUNK_RECORDS * 40];
+        let mut filled = 0;
+        loop {
+            let read = reader.read(&mut buffer[filled..]).map_err(|e| format!("Couldn't read the trace: {e}"))?;
+            filled += read;
+            // Simulate every whole record, keeping a partial record for the next read
+            if read == 0 && filled % 40 != 0 {
+                return Err("The trace ends part way through a record".to_string());
+            }
+            let whole = filled - filled % 40;
+            simulator.simulate_core(&buffer[..whole], core)?;
+            report_progress(state, id, (whole / 40) as u64, &simulator);
+            buffer.copy_within(whole..filled, 0);
+            filled -= whole;
+            if read == 0 {
+                return Ok(simulator.into_result());
+            }
+        }
+    };
+    Ok(finish_job(state, id, finished(simulate())))
+}
+
+/// Renders the progress of the running and recently finished jobs in the Prometheus text format
+///
+/// The records, throughput, and cache counts of each job are labelled with its id, and those of its
+/// caches with the cache's name. Counts are those of the last block of the trace simulated, so
+/// running jobs update a few times a second. The progress is copied out of the jobs, and rendered
+/// once the lock is released.
+fn metrics(state: &State) -> String {
+    let statuses = ["awaiting_trace", "queued", "running", "done", "failed"];
+    let (counts, started) = {
+        let jobs = state.jobs.lock().unwrap();
+        let counts: Vec<usize> = statuses.iter().map(|status| jobs.values().filter(|job| job.status.name() == *status).count()).collect();
+        let started: Vec<(u64, Vec<String>, JobProgress)> = jobs.iter()
+            .filter(|(_, job)| job.progress.started.is_some() && job.finished.is_none_or(|finished| finished.elapsed() < FINISHED_JOB_METRICS))
+            .map(|(&id, job)| (id, job.config.caches.iter().map(|cache| escape_label(&cache.name)).collect(), job.progress.clone()))
+            .collect();
+        (counts, started)
+    };
+    let mut output = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+        let _ = writeln!(output, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(output, "{name}{{{labels}}} {value}");
+        }
+    };
+    family("cachesim_jobs", "gauge", "The jobs in each status", statuses.iter().zip(&counts)
+        .map(|(status, count)| (format!("status=\"{status}\""), count.to_string()))
+        .collect());
+    family("cachesim_records_total", "counter", "The records each job has simulated", started.iter()
+        .map(|(id, _, progress)| (format!("job=\"{id}\""), progress.records.to_string()))
+        .collect());
+    family("cachesim_records_per_second", "gauge", "The records each job has simulated each second, on average", started.iter()
+        .map(|(id, _, progress)| (format!("job=\"{id}\""), (progress.records as f64 / progress.elapsed.as_secs_f64().max(f64::EPSILON)).to_string()))
+        .collect());
+    let (mut hits, mut misses, mut ratios) = (Vec::new(), Vec::new(), Vec::new());
+    for (id, names, progress) in &started {
+        for (name, &(cache_hits, cache_misses)) in names.iter().zip(&progress.caches) {
+            let labels = format!("job=\"{id}\",cache=\"{name}\"");
+            hits.push((labels.clone(), cache_hits.to_string()));
+            misses.push((labels.clone(), cache_misses.to_string()));
+            ratios.push((labels, (cache_hits as f64 / (cache_hits + cache_misses).max(1) as f64).to_string()));
+        }
+    }
+    family("cachesim_cache_hits_total", "counter", "The hits of each cache of each job", hits);
+    family("cachesim_cache_misses_total", "counter", "The misses of each cache of each job", misses);
+    family("cachesim_cache_hit_ratio", "gauge", "The share of the accesses to each cache of each job which hit", ratios);
+    family("cachesim_main_memory_accesses_total", "counter", "The main memory accesses of each job", started.iter()
+        .filter(|(_, _, progress)| !progress.caches.is_empty())
+        .map(|(id, _, progress)| (format!("job=\"{id}\""), progress.main_memory_accesses.to_string()))
+        .collect());
+    output
+}
+
+/// Escapes a label value of the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn finished(result: Result<LayeredCacheResult, String>) -> JobStatus {
+    match result {
+        Ok(result) => JobStatus::Done { result },
+        Err(error) => JobStatus::Failed { error },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use super::*;
+
+    fn args() -> ServeArgs {
+        ServeArgs {
+            port: 0,
+            host: "127.0.0.1".to_string(),
+            trace_dir: None,
+            jobs: Some(1),
+            connections: 2,
+            max_finished_jobs: 1000,
+            finished_job_ttl: 3600,
+            max_awaiting_jobs: 100,
+            awaiting_job_ttl: 600,
+        }
+    }
+
+    /// Starts a server on a free port, returning its address
+    fn serve(args: &ServeArgs) -> String {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let address = server.server_addr().to_ip().unwrap().to_string();
+        spawn_handlers(&Arc::new(State::new(args).unwrap()), &server, args.connections);
+        address
+    }
+
+    /// Sends a request, returning the status code and the body of the response as JSON
+    fn request(address: &str, method: &str, path: &str, body: &[u8]) -> (u16, Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{method} {path} HTTP/1.1\r\nHost: {address}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let code = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (code, serde_json::from_str(body).unwrap())
+    }
+
+    fn trace() -> Vec<u8> {
+        [0x0, 0x40, 0x0].iter().map(|address| format!("{:016x} {address:016x} R 004\n", 0x400000)).collect::<String>().into_bytes()
+    }
+
+    #[test]
+    fn invalid_configurations_are_refused_without_stopping_the_server() {
+        let address = serve(&args());
+        let (code, body) = request(&address, "POST", "/jobs", br#"{"config": {"caches": [{"name": "L1", "size": 0}]}}"#);
+        assert_eq!(code, 400);
+        assert_eq!(body["error"], "Cache L1 has a size of 0 bytes, which must be a whole number of its 64-byte lines");
+        let (code, body) = request(&address, "POST", "/jobs", br#"{"config": {"caches": [{"name": "L1", "size": 1024}]}, "core": 3}"#);
+        assert_eq!((code, &body["error"]), (400, &"The configuration has no caches for core 3".into()));
+        let (code, body) = request(&address, "GET", "/jobs", b"");
+        assert_eq!((code, body), (200, json!({"jobs": []})));
+    }
+
+    #[test]
+    fn uploaded_traces_are_simulated_once() {
+        let address = serve(&args());
+        let (code, body) = request(&address, "POST", "/jobs", br#"{"config": {"caches": [{"name": "L1", "size": 1024}]}}"#);
+        assert_eq!((code, &body["status"]), (201, &"awaiting_trace".into()));
+        let path = format!("/jobs/{}/trace", body["id"]);
+        let (code, body) = request(&address, "PUT", &path, &trace());
+        assert_eq!((code, &body["status"]), (200, &"done".into()));
+        assert_eq!((&body["result"]["caches"][0]["hits"], &body["result"]["caches"][0]["misses"]), (&1.into(), &2.into()));
+        let (code, _) = request(&address, "PUT", &path, &trace());
+        assert_eq!(code, 409);
+    }
+
+    #[test]
+    fn jobs_waiting_for_a_trace_are_bounded() {
+        let address = serve(&ServeArgs { max_awaiting_jobs: 1, ..args() });
+        let job = br#"{"config": {"caches": [{"name": "L1", "size": 1024}]}}"#;
+        assert_eq!(request(&address, "POST", "/jobs", job).0, 201);
+        let (code, body) = request(&address, "POST", "/jobs", job);
+        assert_eq!((code, &body["error"]), (503, &"Too many jobs are waiting for a trace, the limit is 1".into()));
+
+        // Without a time to live, a waiting job is forgotten as soon as another is submitted
+        let address = serve(&ServeArgs { awaiting_job_ttl: 0, ..args() });
+        let (_, first) = request(&address, "POST", "/jobs", job);
+        let (_, second) = request(&address, "POST", "/jobs", job);
+        assert_eq!(request(&address, "GET", &format!("/jobs/{}", first["id"]), b"").0, 404);
+        assert_eq!(request(&address, "GET", &format!("/jobs/{}", second["id"]), b"").0, 200);
+    }
+}
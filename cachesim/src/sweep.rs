@@ -0,0 +1,253 @@
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use clap::Args;
+use serde::Serialize;
+use cachelib::config::{load_layered_config_with_warnings, parse_byte_size, LayeredCacheConfig, ReplacementPolicyConfig};
+use cachelib::ensemble::Ensemble;
+use cachelib::simulator::{LayeredCacheResult, Simulator};
+use crate::report::{miss_rate, open_output, render_csv, render_markdown, render_table, write_json_line, write_output, OutputFormat};
+use crate::trace::map_trace;
+
+/// Arguments for the sweep subcommand
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// The path to the JSON configuration file
+    config: String,
+
+    /// The path to the trace file
+    trace: String,
+
+    /// The cache to resize. Defaults to the last cache in the configuration
+    #[arg(long, value_name = "NAME")]
+    cache: Option<String>,
+
+    /// The sizes to simulate, separated by commas, such as 16KiB,32KiB,64KiB
+    #[arg(long, required = true, value_delimiter = ',', value_parser = parse_byte_size)]
+    sizes: Vec<u64>,
+
+    /// The replacement policies to simulate at each size, separated by commas. Defaults to the
+    /// cache's configured policy
+    #[arg(long, value_delimiter = ',')]
+    policies: Vec<String>,
+
+    /// Write the results to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// The format of the results. With jsonl, each size and policy is simulated on its own and
+    /// written as soon as it finishes, rather than all of them in one pass over the trace
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    output_format: OutputFormat,
+
+    /// Spread the sizes and policies across this many threads
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    /// Append each size and policy to this SQLite database as a run of its own, creating it if
+    /// needed
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH")]
+    db: Option<String>,
+
+    /// Render the miss rate of the cache against its size to this image, with a line per policy.
+    /// The format is chosen by the extension, svg or png
+    #[cfg(feature = "plots")]
+    #[arg(long, value_name = "PATH")]
+    plot: Option<String>,
+}
+
+/// The result of simulating one policy at one size
+#[derive(Debug, Serialize)]
+pub struct SweepPoint {
+    pub policy: String,
+    pub size: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub main_memory_accesses: u64,
+}
+
+/// Simulates a trace with one cache of a configuration resized to each of a list of sizes, and with
+/// each of a list of policies, in a single pass over the trace unless the points are streamed as
+/// JSON Lines
+pub fn run(args: &SweepArgs) -> Result<ExitCode, String> {
+    #[cfg(feature = "plots")]
+    if let Some(path) = &args.plot {
+        crate::plot::image_format(std::path::Path::new(path))?;
+    }
+    let (config, warnings) = load_layered_config_with_warnings(&[&args.config])?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let index = match &args.cache {
+        Some(name) => config.caches.iter().position(|c| &c.name == name).ok_or(format!("There is no cache named {name}"))?,
+        None => config.caches.len() - 1,
+    };
+    let policies: Vec<ReplacementPolicyConfig> = match args.policies.is_empty() {
+        true => vec![config.caches[index].replacement_policy.clone()],
+        false => args.policies.iter().cloned().map(ReplacementPolicyConfig::from).collect(),
+    };
+    let mut variants = Vec::with_capacity(policies.len() * args.sizes.len());
+    for policy in &policies {
+        for &size in &args.sizes {
+            variants.push((policy.name().to_string(), size, resize(&config, index, size, policy)?));
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    let mut db = args.db.as_deref().map(crate::report::db::ResultsDatabase::open).transpose()?;
+    let trace = map_trace(&args.trace)?;
+    #[cfg(feature = "sqlite")]
+    let fingerprint = crate::provenance::TraceFingerprint::of(&args.trace, &trace, false);
+    let mut points: Vec<Option<SweepPoint>> = variants.iter().map(|_| None).collect();
+    #[cfg(feature = "sqlite")]
+    let mut record = |variant: usize, result: &LayeredCacheResult| match &mut db {
+        Some(db) => {
+            let (policy, size, variant_config) = &variants[variant];
+            db.record("sweep", &format!("{} {policy} {size}", config.caches[index].name), variant_config, fingerprint.clone(), result)
+        }
+        None => Ok(()),
+    };
+    let point = |variant: usize, result: &LayeredCacheResult| {
+        let (policy, size, _) = &variants[variant];
+        let cache = &result.caches()[index];
+        SweepPoint { policy: policy.clone(), size: *size, hits: cache.hits(), misses: cache.misses(), main_memory_accesses: result.main_memory_accesses() }
+    };
+    if args.output_format == OutputFormat::Jsonl {
+        let mut output = open_output(args.output.as_deref())?;
+        stream_variants(&variants, &trace, args.threads, |variant, result| {
+            #[cfg(feature = "sqlite")]
+            record(variant, &result)?;
+            let point = point(variant, &result);
+            write_json_line(&mut output, &point)?;
+            points[variant] = Some(point);
+            Ok(())
+        })?;
+    } else {
+        let mut ensemble = Ensemble::new(variants.iter().map(|(_, _, config)| config)).with_threads(args.threads);
+        ensemble.simulate(&trace)?;
+        for (variant, result) in ensemble.results().into_iter().enumerate() {
+            #[cfg(feature = "sqlite")]
+            record(variant, result)?;
+            points[variant] = Some(point(variant, result));
+        }
+    }
+    let points: Vec<SweepPoint> = points.into_iter().map(Option::unwrap).collect();
+    if args.output_format != OutputFormat::Jsonl {
+        write_points(args, &points)?;
+    }
+    #[cfg(feature = "plots")]
+    if let Some(path) = &args.plot {
+        let series: Vec<crate::plot::CurveSeries> = policies.iter()
+            .map(|policy| crate::plot::CurveSeries {
+                label: policy.name().to_string(),
+                points: points.iter()
+                    .filter(|p| p.policy == policy.name() && p.hits + p.misses > 0)
+                    .map(|p| (p.size, p.misses as f64 / (p.hits + p.misses) as f64))
+                    .collect(),
+            })
+            .collect();
+        let title = format!("{} miss rate by size", config.caches[index].name);
+        crate::plot::render_miss_rate_curve(std::path::Path::new(path), &title, &series)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Copies a configuration with one cache given a new size and policy, checking it can be simulated
+fn resize(config: &LayeredCacheConfig, index: usize, size: u64, policy: &ReplacementPolicyConfig) -> Result<LayeredCacheConfig, String> {
+    let mut config = config.clone();
+    let cache = &mut config.caches[index];
+    cache.size = size;
+    cache.replacement_policy = policy.clone();
+    config.validate().map_err(|e| format!("With cache {} at {size} bytes using {}: {e}", config.caches[index].name, policy.name()))?;
+    Ok(config)
+}
+
+/// Simulates each variant on its own, spread across threads, calling `finished` with the index and
+/// results of each variant as soon as it finishes
+///
+/// Each variant reads the whole trace, rather than sharing one pass over it as an [Ensemble] does,
+/// so points can be written out before the slowest variant is done.
+fn stream_variants(variants: &[(String, u64, LayeredCacheConfig)], trace: &[u8], threads: usize, mut finished: impl FnMut(usize, LayeredCacheResult) -> Result<(), String>) -> Result<(), String> {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let (sender, next) = (sender.clone(), &next);
+            scope.spawn(move || loop {
+                let variant = next.fetch_add(1, Ordering::Relaxed);
+                let Some((_, _, config)) = variants.get(variant) else {
+                    break;
+                };
+                let mut simulator = Simulator::new(config);
+                let result = match simulator.simulate(trace) {
+                    Ok(_) => Ok(simulator.into_result()),
+                    Err(e) => Err(e),
+                };
+                // The receiver is only gone once an earlier variant failed
+                if sender.send((variant, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        receiver.into_iter().try_for_each(|(variant, result)| finished(variant, result?))
+    })
+}
+
+/// Writes the points in the chosen format, with a row for each
+fn write_points(args: &SweepArgs, points: &[SweepPoint]) -> Result<(), String> {
+    let rows = || -> Vec<Vec<String>> {
+        std::iter::once(["policy", "size", "hits", "misses", "miss rate", "main memory accesses"].map(str::to_string).to_vec())
+            .chain(points.iter().map(|p| vec![
+                p.policy.clone(),
+                p.size.to_string(),
+                p.hits.to_string(),
+                p.misses.to_string(),
+                miss_rate(p.hits, p.misses),
+                p.main_memory_accesses.to_string(),
+            ]))
+            .collect()
+    };
+    let contents = match args.output_format {
+        OutputFormat::Json => serde_json::to_string_pretty(points).map_err(|e| format!("Couldn't serialise the output {e}"))? + "\n",
+        OutputFormat::Jsonl => unreachable!("JSON Lines are written as each point finishes"),
+        OutputFormat::Csv => render_csv(&rows()),
+        OutputFormat::Table => render_table(&rows()),
+        OutputFormat::Markdown => render_markdown(&rows()),
+    };
+    write_output(args.output.as_deref(), &contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use serde_json::Value;
+    use super::*;
+
+    #[test]
+    fn each_point_can_be_read_before_the_sweep_finishes() -> Result<(), String> {
+        let config: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [{"name": "L1", "size": 1024}]}"#).unwrap();
+        let variants: Vec<_> = [512, 1024, 4096].into_iter()
+            .map(|size| Ok(("lru".to_string(), size, resize(&config, 0, size, &ReplacementPolicyConfig::LeastRecentlyUsed)?)))
+            .collect::<Result<_, String>>()?;
+        let trace: Vec<u8> = (0..64u64).map(|line| format!("{:016x} {:016x} R 004\n", 0x400000, line % 8 * 64)).collect::<String>().into_bytes();
+        let path = std::env::temp_dir().join(format!("cachesim-sweep-{}.jsonl", std::process::id()));
+        let mut output = open_output(path.to_str())?;
+        let mut written = Vec::new();
+        stream_variants(&variants, &trace, 1, |variant, result| {
+            // Every point before this one is already in the file, while the sweep is still running
+            let partial = fs::read_to_string(&path).unwrap();
+            let sizes: Vec<u64> = partial.lines().map(|line| serde_json::from_str::<Value>(line).unwrap()["size"].as_u64().unwrap()).collect();
+            assert_eq!(sizes, written);
+            let (policy, size, _) = &variants[variant];
+            let cache = &result.caches()[0];
+            write_json_line(&mut output, &SweepPoint { policy: policy.clone(), size: *size, hits: cache.hits(), misses: cache.misses(), main_memory_accesses: result.main_memory_accesses() })?;
+            written.push(*size);
+            Ok(())
+        })?;
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, [512, 1024, 4096]);
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use std::fs::File;
+use clap::ValueEnum;
+use memmap2::{Advice, Mmap};
+
+/// The size of a record of the trace format
+const RECORD_BYTES: usize = 40;
+/// The size of each block read by the streaming backends, a whole number of records and of
+/// [ALIGNMENT], about 5MiB
+#[cfg(target_os = "linux")]
+const BLOCK_BYTES: usize = RECORD_BYTES * ALIGNMENT * 32;
+/// The size of each window of a memory mapped trace which is advised ahead of being simulated, and
+/// released once it has been, a whole number of records and of pages, 40MiB
+const WINDOW_BYTES: usize = RECORD_BYTES * PAGE_BYTES * 16;
+/// The largest page size of the platforms supported, 64KiB on some aarch64 kernels, which the
+/// offsets of advice must be aligned to
+const PAGE_BYTES: usize = 65536;
+/// The alignment of the buffers and reads of files opened with O_DIRECT, which is at least the
+/// logical block size of any device
+#[cfg(target_os = "linux")]
+const ALIGNMENT: usize = 4096;
+
+/// How a trace file is read
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum IoBackend {
+    /// Memory map the file, letting the OS read ahead. The fastest when the trace is already in the
+    /// page cache
+    #[default]
+    Mmap,
+    /// Read the file with O_DIRECT on a second thread, so the next block is read while the last
+    /// is simulated. Linux only
+    Direct,
+    /// Read the file with O_DIRECT through io_uring, with the read of the next block in flight
+    /// while the last is simulated. Linux only, and needs the uring feature
+    Uring,
+}
+
+/// How the records of the traces of several processes are interleaved
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Interleave {
+    /// Each process makes --quantum accesses in turn
+    #[default]
+    RoundRobin,
+    /// The records of each trace are spread evenly over the run, so traces of different lengths
+    /// end together
+    Proportional,
+}
+
+/// Memory maps a trace file, advising the OS that it will be read sequentially
+///
+/// MMap for speed. If we wanted more portability we could use a BufReader and repeatedly call
+/// simulate - this is the main reason simulate explicitly supports multiple calls to simulate
+pub fn map_trace(path: &str) -> Result<Mmap, String> {
+    let trace_file = File::open(path).map_err(|e| format!("Couldn't open the trace file at path {path}: {e}"))?;
+    // SAFETY: the map is only read, and the trace is assumed not to be truncated or modified while
+    // it's simulated, which memmap2 can't rule out, see the README
+    let map = unsafe { Mmap::map(&trace_file) }.map_err(|e| format!("Couldn't memory map the file: {e}"))?;
+    map.advise(Advice::Sequential).map_err(|e| format!("Failed to provide access advice to the OS, {e}"))?;
+    Ok(map)
+}
+
+/// Simulates a memory mapped trace a window at a time, advising the OS that the next window will be
+/// needed before simulating each, and that it's done with each once simulated
+///
+/// The OS reads the next window while the last is simulated, hiding the latency of page faults on
+/// slow storage, and the windows already simulated are unmapped from the process, so its resident
+/// memory stays at a couple of windows however large the trace, and their pages are the first the
+/// OS reclaims. Advice is only a hint, so it's ignored where it fails
+///
+/// # Arguments
+///
+/// * `map`: The memory mapped trace
+/// * `simulate`: Called with each window in turn, each holding whole records, and the offset of
+///   the window in the trace
+///
+/// returns: Result<(), String>, an error if `simulate` fails, in which case simulating stops
+pub fn simulate_windows(map: &Mmap, mut simulate: impl FnMut(&[u8], usize) -> Result<(), String>) -> Result<(), String> {
+    let mut start = 0;
+    while start < map.len() {
+        let end = (start + WINDOW_BYTES).min(map.len());
+        if end < map.len() {
+            let _ = map.advise_range(Advice::WillNeed, end, WINDOW_BYTES.min(map.len() - end));
+        }
+        simulate(&map[start..end], start)?;
+        let _ = map.advise_range(Advice::DontNeed, start, end - start);
+        start = end;
+    }
+    Ok(())
+}
+
+/// Reads a trace file a block at a time with one of the streaming backends, for traces too large
+/// to be worth keeping in the page cache, such as 100GB traces read once from NVMe storage
+///
+/// The streaming backends bypass the page cache with O_DIRECT, falling back to ordinary reads on
+/// file systems which don't support it, and read the next block while the last is simulated
+///
+/// # Arguments
+///
+/// * `path`: The path to the trace file
+/// * `backend`: How to read the file, [Direct](IoBackend::Direct) or [Uring](IoBackend::Uring),
+///   or [Mmap](IoBackend::Mmap) a window at a time, see [simulate_windows]
+/// * `simulate`: Called with each block in turn, each holding whole records
+///
+/// returns: Result<(), String>, an error if the file can't be read, the trace ends part way through
+/// a record, or `simulate` fails, in which case reading stops
+pub fn read_trace_blocks(path: &str, backend: IoBackend, mut simulate: impl FnMut(&[u8]) -> Result<(), String>) -> Result<(), String> {
+    let mut simulate_block = |block: &[u8]| {
+        if !block.len().is_multiple_of(RECORD_BYTES) {
+            return Err("The trace ends part way through a record".to_string());
+        }
+        simulate(block)
+    };
+    match backend {
+        IoBackend::Mmap => {
+            let map = map_trace(path)?;
+            if map.is_empty() {
+                return simulate_block(&map);
+            }
+            simulate_windows(&map, |window, _| simulate_block(window))
+        }
+        #[cfg(target_os = "linux")]
+        IoBackend::Direct => linux::read_direct(&linux::open_direct(path)?, &mut simulate_block),
+        #[cfg(all(target_os = "linux", feature = "uring"))]
+        IoBackend::Uring => linux::read_uring(&linux::open_direct(path)?, &mut simulate_block),
+        #[cfg(all(target_os = "linux", not(feature = "uring")))]
+        IoBackend::Uring => Err("The uring backend needs cachesim built with the uring feature".to_string()),
+        #[cfg(not(target_os = "linux"))]
+        _ => Err(format!("The {backend:?} backend is only available on Linux")),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::{File, OpenOptions};
+    use std::io::{ErrorKind, Read};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::sync::mpsc::sync_channel;
+    use super::{ALIGNMENT, BLOCK_BYTES};
+
+    /// A block sized buffer aligned for O_DIRECT, found within a larger allocation
+    pub(super) struct AlignedBuffer {
+        storage: Vec<u8>,
+        offset: usize,
+    }
+
+    impl AlignedBuffer {
+        pub(super) fn new() -> Self {
+            let storage = vec![0; BLOCK_BYTES + ALIGNMENT];
+            let offset = storage.as_ptr().align_offset(ALIGNMENT);
+            Self { storage, offset }
+        }
+
+        pub(super) fn block(&self) -> &[u8] {
+            &self.storage[self.offset..self.offset + BLOCK_BYTES]
+        }
+
+        pub(super) fn block_mut(&mut self) -> &mut [u8] {
+            &mut self.storage[self.offset..self.offset + BLOCK_BYTES]
+        }
+    }
+
+    /// Opens a file with O_DIRECT, or without it if the file system doesn't support it, as tmpfs
+    /// doesn't
+    pub(super) fn open_direct(path: &str) -> Result<File, String> {
+        OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(path)
+            .or_else(|e| if e.kind() == ErrorKind::InvalidInput { File::open(path) } else { Err(e) })
+            .map_err(|e| format!("Couldn't open the trace file at path {path}: {e}"))
+    }
+
+    /// Reads the next block of a file, returning its length, which is only less than a block at
+    /// the end of the file
+    ///
+    /// The read isn't continued after a short read, as it would no longer be aligned, and regular
+    /// files only give short reads at their end
+    fn read_block(mut file: &File, buffer: &mut AlignedBuffer) -> Result<usize, String> {
+        loop {
+            match file.read(buffer.block_mut()) {
+                Ok(read) => return Ok(read),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("Couldn't read the trace: {e}")),
+            }
+        }
+    }
+
+    /// Reads blocks on a second thread, passing two buffers back and forth so one is filled while
+    /// the other is simulated
+    pub(super) fn read_direct(file: &File, simulate: &mut dyn FnMut(&[u8]) -> Result<(), String>) -> Result<(), String> {
+        let (full_sender, full_receiver) = sync_channel::<(AlignedBuffer, usize)>(1);
+        let (empty_sender, empty_receiver) = sync_channel::<AlignedBuffer>(2);
+        for _ in 0..2 {
+            empty_sender.send(AlignedBuffer::new()).expect("The receiver is held until the end of the function");
+        }
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(move || {
+                // Stops when the simulation stops, as the senders and receivers are dropped
+                while let Ok(mut buffer) = empty_receiver.recv() {
+                    let read = read_block(file, &mut buffer)?;
+                    if full_sender.send((buffer, read)).is_err() || read < BLOCK_BYTES {
+                        break;
+                    }
+                }
+                Ok(())
+            });
+            let mut simulated = Ok(());
+            for (buffer, read) in full_receiver {
+                simulated = simulate(&buffer.block()[..read]);
+                if simulated.is_err() {
+                    break;
+                }
+                // The reader may have finished, with the last block still to simulate
+                let _ = empty_sender.send(buffer);
+            }
+            drop(empty_sender);
+            let read = reader.join().unwrap_or_else(|_| Err("The thread reading the trace panicked".to_string()));
+            simulated.and(read)
+        })
+    }
+
+    /// Two buffers read into through an io_uring, which cancels any read still in flight and waits
+    /// for it when dropped, so the kernel never writes to a buffer once it's freed, however reading
+    /// stops
+    #[cfg(feature = "uring")]
+    struct UringReader<'a> {
+        file: &'a File,
+        ring: io_uring::IoUring,
+        // Only freed once no read into them is in flight
+        buffers: std::mem::ManuallyDrop<[AlignedBuffer; 2]>,
+        // The reads and cancellations pushed whose completions haven't been reaped
+        outstanding: usize,
+    }
+
+    #[cfg(feature = "uring")]
+    impl<'a> UringReader<'a> {
+        /// The user data of reads, which a cancellation names
+        const READ: u64 = 0;
+        const CANCEL: u64 = 1;
+
+        fn new(file: &'a File) -> Result<Self, String> {
+            let ring = io_uring::IoUring::new(2).map_err(|e| format!("Couldn't create an io_uring: {e}"))?;
+            Ok(Self { file, ring, buffers: std::mem::ManuallyDrop::new([AlignedBuffer::new(), AlignedBuffer::new()]), outstanding: 0 })
+        }
+
+        /// Submits a read of the block at `offset` into a buffer, which mustn't be used until the
+        /// read has [completed](Self::complete)
+        fn submit(&mut self, buffer: usize, offset: u64) -> Result<(), String> {
+            use std::os::fd::AsRawFd;
+            use io_uring::{opcode, types};
+
+            let read = opcode::Read::new(types::Fd(self.file.as_raw_fd()), self.buffers[buffer].block_mut().as_mut_ptr(), BLOCK_BYTES as u32)
+                .offset(offset)
+                .build()
+                .user_data(Self::READ);
+            // SAFETY: the buffer isn't used again until the read's completion has been reaped, and
+            // isn't freed until then, even when reading stops early, see Drop
+            unsafe { self.ring.submission().push(&read) }.map_err(|_| "The io_uring submission queue is full".to_string())?;
+            self.outstanding += 1;
+            loop {
+                match self.ring.submit() {
+                    Ok(_) => return Ok(()),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(format!("Couldn't submit a read of the trace: {e}")),
+                }
+            }
+        }
+
+        /// Waits for the next completion, retrying when a signal interrupts the wait, returning its
+        /// result
+        fn wait(&mut self) -> std::io::Result<i32> {
+            loop {
+                if let Some(entry) = self.ring.completion().next() {
+                    self.outstanding -= 1;
+                    return Ok(entry.result());
+                }
+                match self.ring.submit_and_wait(1) {
+                    Err(e) if e.kind() != ErrorKind::Interrupted => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+
+        /// Waits for the read in flight, returning the bytes it read
+        fn complete(&mut self) -> Result<usize, String> {
+            let result = self.wait().map_err(|e| format!("Couldn't wait for a read of the trace: {e}"))?;
+            usize::try_from(result).map_err(|_| format!("Couldn't read the trace: {}", std::io::Error::from_raw_os_error(-result)))
+        }
+    }
+
+    #[cfg(feature = "uring")]
+    impl Drop for UringReader<'_> {
+        fn drop(&mut self) {
+            if self.outstanding > 0 {
+                let cancel = io_uring::opcode::AsyncCancel::new(Self::READ).build().user_data(Self::CANCEL);
+                // SAFETY: the cancellation refers to no memory
+                if unsafe { self.ring.submission().push(&cancel) }.is_ok() {
+                    self.outstanding += 1;
+                }
+            }
+            while self.outstanding > 0 {
+                if self.wait().is_err() {
+                    // The kernel may still write to the buffers, so they're leaked rather than freed
+                    return;
+                }
+            }
+            // SAFETY: nothing is in flight, and the buffers aren't used again
+            unsafe { std::mem::ManuallyDrop::drop(&mut self.buffers) }
+        }
+    }
+
+    /// Reads blocks through io_uring, submitting the read of the next block before simulating the
+    /// last, so the two overlap on one thread
+    #[cfg(feature = "uring")]
+    pub(super) fn read_uring(file: &File, simulate: &mut dyn FnMut(&[u8]) -> Result<(), String>) -> Result<(), String> {
+        let mut reader = UringReader::new(file)?;
+        let mut offset = 0;
+        let mut current = 0;
+        reader.submit(current, offset)?;
+        loop {
+            let read = reader.complete()?;
+            offset += read as u64;
+            let in_flight = read == BLOCK_BYTES;
+            if in_flight {
+                reader.submit(1 - current, offset)?;
+            }
+            // A read left in flight when this fails is cancelled as the reader is dropped
+            simulate(&reader.buffers[current].block()[..read])?;
+            if !in_flight {
+                return Ok(());
+            }
+            current = 1 - current;
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux", feature = "uring"))]
+mod tests {
+    use cachelib::config::LayeredCacheConfig;
+    use cachelib::simulator::Simulator;
+    use super::*;
+
+    #[test]
+    fn the_uring_backend_matches_the_other_readers() -> Result<(), String> {
+        // Two and a half blocks, so the last read is short
+        let records = BLOCK_BYTES * 5 / 2 / RECORD_BYTES;
+        let trace: String = (0..records as u64)
+            .map(|i| format!("{:016x} {:016x} R 004\n", 0x400000, i.wrapping_mul(0x9e3779b97f4a7c15) % (1 << 14) * 64))
+            .collect();
+        let path = std::env::temp_dir().join(format!("cachesim-uring-{}.out", std::process::id()));
+        std::fs::write(&path, trace).map_err(|e| e.to_string())?;
+        let config: LayeredCacheConfig = serde_json::from_str(r#"{"caches": [{"name": "L1", "size": 4096}, {"name": "L2", "size": 65536}]}"#).unwrap();
+        let simulate = |backend| -> Result<_, String> {
+            let mut simulator = Simulator::new(&config);
+            read_trace_blocks(path.to_str().unwrap(), backend, |block| simulator.simulate_core(block, 0).map(|_| ()))?;
+            Ok(simulator.into_result())
+        };
+        let uring = simulate(IoBackend::Uring)?;
+        let buffered = simulate(IoBackend::Direct)?;
+        let mapped = simulate(IoBackend::Mmap)?;
+        std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+        assert_eq!(uring.caches()[0].hits() + uring.caches()[0].misses(), records as u64);
+        assert_eq!(uring, buffered);
+        assert_eq!(uring, mapped);
+        Ok(())
+    }
+}
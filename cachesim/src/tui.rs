@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use console::Term;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::widgets::{Block, Gauge, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+use cachelib::simulator::Simulator;
+
+/// The number of records simulated between checks for whether the dashboard is due a redraw
+const BLOCK_RECORDS: usize = 16 * 1024;
+
+/// How often the dashboard is redrawn, which is also the length of each interval on the sparklines
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The number of intervals kept for the sparklines, more than fit on most terminals
+const HISTORY: usize = 512;
+
+/// The state of the dashboard between redraws
+struct Dashboard {
+    names: Vec<String>,
+    total_bytes: usize,
+    processed: usize,
+    start: Instant,
+    /// The hits and misses of each cache at the end of the last interval
+    last_counts: Vec<(u64, u64)>,
+    /// The hit ratio of each cache in each recent interval, as a percentage
+    history: Vec<VecDeque<Option<u64>>>,
+}
+
+/// Simulates a trace while showing a live dashboard of the hit ratio of each cache, how it has
+/// changed over recent intervals, the throughput, and the lines not yet filled
+///
+/// The dashboard takes over the terminal until the simulation finishes. Pressing `q`, `Esc` or
+/// `Ctrl-C` stops the simulation early with an error.
+///
+/// # Arguments
+///
+/// * `simulator`: The simulator to run, the results are left in it
+/// * `trace`: The trace to simulate
+/// * `core`: The core making the accesses
+///
+/// returns: Result<(), String>
+pub fn simulate_with_dashboard(simulator: &mut Simulator, trace: &[u8], core: u32) -> Result<(), String> {
+    if !Term::stdout().is_term() {
+        return Err("The dashboard needs stdout to be a terminal, use --output to write the results to a file".to_string());
+    }
+    let mut terminal = ratatui::try_init().map_err(|e| format!("Couldn't set up the terminal: {e}"))?;
+    let result = run(&mut terminal, simulator, trace, core);
+    ratatui::try_restore().map_err(|e| format!("Couldn't restore the terminal: {e}"))?;
+    result
+}
+
+fn run(terminal: &mut DefaultTerminal, simulator: &mut Simulator, trace: &[u8], core: u32) -> Result<(), String> {
+    let names: Vec<String> = simulator.get_result().caches().iter().map(|c| c.name().to_string()).collect();
+    let mut dashboard = Dashboard {
+        last_counts: vec![(0, 0); names.len()],
+        history: vec![VecDeque::with_capacity(HISTORY); names.len()],
+        names,
+        total_bytes: trace.len(),
+        processed: 0,
+        start: Instant::now(),
+    };
+    let mut last_redraw = Instant::now();
+    for block in trace.chunks(BLOCK_RECORDS * 40) {
+        simulator.simulate_core(block, core)?;
+        dashboard.processed += block.len();
+        if last_redraw.elapsed() >= REDRAW_INTERVAL {
+            last_redraw = Instant::now();
+            dashboard.end_interval(simulator);
+            draw(terminal, &dashboard, simulator)?;
+            if cancel_requested()? {
+                return Err("The simulation was stopped before the end of the trace".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Dashboard {
+    /// Records the hit ratio of each cache since the last interval
+    fn end_interval(&mut self, simulator: &Simulator) {
+        for (index, cache) in simulator.get_result().caches().iter().enumerate() {
+            let (last_hits, last_misses) = self.last_counts[index];
+            let (hits, misses) = (cache.hits() - last_hits, cache.misses() - last_misses);
+            let history = &mut self.history[index];
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back((hits + misses > 0).then(|| 100 * hits / (hits + misses)));
+            self.last_counts[index] = (cache.hits(), cache.misses());
+        }
+    }
+}
+
+/// Checks, without waiting, whether a key to stop the simulation has been pressed
+fn cancel_requested() -> Result<bool, String> {
+    while event::poll(Duration::ZERO).map_err(|e| format!("Couldn't read from the terminal: {e}"))? {
+        if let Event::Key(key) = event::read().map_err(|e| format!("Couldn't read from the terminal: {e}"))? {
+            let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.kind == KeyEventKind::Press && (ctrl_c || key.code == KeyCode::Char('q') || key.code == KeyCode::Esc) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn draw(terminal: &mut DefaultTerminal, dashboard: &Dashboard, simulator: &Simulator) -> Result<(), String> {
+    let uninitialised = simulator.get_uninitialised_line_counts();
+    terminal.draw(|frame| draw_frame(frame, dashboard, simulator, &uninitialised))
+        .map(|_| ())
+        .map_err(|e| format!("Couldn't draw the dashboard: {e}"))
+}
+
+fn draw_frame(frame: &mut Frame, dashboard: &Dashboard, simulator: &Simulator, uninitialised: &[u64]) {
+    let caches = simulator.get_result().caches();
+    let [progress_area, table_area, sparkline_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(caches.len() as u16 + 4),
+        Constraint::Min(0),
+    ]).areas(frame.area());
+
+    let elapsed = dashboard.start.elapsed().as_secs_f64();
+    let accesses = dashboard.processed / 40;
+    let per_second = accesses as f64 / elapsed.max(f64::EPSILON);
+    let remaining = (dashboard.total_bytes - dashboard.processed) as f64 / 40.0 / per_second.max(1.0);
+    let progress = Gauge::default()
+        .block(Block::bordered().title(" Progress (q to stop) "))
+        .gauge_style(Style::new().fg(Color::Cyan))
+        .ratio(dashboard.processed as f64 / dashboard.total_bytes.max(1) as f64)
+        .label(format!("{accesses} of {} accesses, {per_second:.0} accesses/s, ETA {remaining:.0}s", dashboard.total_bytes / 40));
+    frame.render_widget(progress, progress_area);
+
+    let rows = caches.iter().zip(uninitialised).map(|(cache, uninitialised)| {
+        let accesses = cache.hits() + cache.misses();
+        let hit_ratio = match accesses {
+            0 => "-".to_string(),
+            accesses => format!("{:.2}%", 100.0 * cache.hits() as f64 / accesses as f64),
+        };
+        Row::new([cache.name().to_string(), accesses.to_string(), cache.hits().to_string(), cache.misses().to_string(), hit_ratio, uninitialised.to_string()])
+    });
+    let table = Table::new(rows, [Constraint::Fill(2), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1)])
+        .header(Row::new(["Cache", "Accesses", "Hits", "Misses", "Hit ratio", "Unfilled lines"]).bold())
+        .block(Block::bordered().title(format!(" Caches, {} main memory accesses ", simulator.get_result().main_memory_accesses())));
+    frame.render_widget(table, table_area);
+
+    let areas = Layout::vertical(vec![Constraint::Ratio(1, dashboard.names.len() as u32); dashboard.names.len()]).split(sparkline_area);
+    for ((name, history), area) in dashboard.names.iter().zip(&dashboard.history).zip(areas.iter()) {
+        draw_sparkline(frame, *area, name, history);
+    }
+}
+
+/// Draws the hit ratio of a cache in each interval, showing the most recent intervals which fit
+fn draw_sparkline(frame: &mut Frame, area: Rect, name: &str, history: &VecDeque<Option<u64>>) {
+    let shown = history.len().min(area.width.saturating_sub(2) as usize);
+    let sparkline = Sparkline::default()
+        .block(Block::bordered().title(format!(" {name} hit ratio per {}ms ", REDRAW_INTERVAL.as_millis())))
+        .style(Style::new().fg(Color::Green))
+        .max(100)
+        .data(history.iter().skip(history.len() - shown));
+    frame.render_widget(sparkline, area);
+}
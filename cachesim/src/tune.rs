@@ -0,0 +1,363 @@
+use std::process::ExitCode;
+use clap::{Args, ValueEnum};
+use serde_json::Value;
+use cachelib::config::{load_layered_config_with_warnings, parse_byte_size, CacheKindConfig, LayeredCacheConfig, ReplacementPolicyConfig};
+use cachelib::ensemble::Ensemble;
+use crate::report::write_output;
+use crate::trace::map_trace;
+
+/// The smallest cache considered, in lines
+const MIN_LINES: u64 = 8;
+
+/// Arguments for the tune subcommand
+#[derive(Args, Debug)]
+pub struct TuneArgs {
+    /// The path to the JSON configuration file to start from
+    config: String,
+
+    /// The path to the trace file
+    trace: String,
+
+    /// The largest total size of every cache in the hierarchy
+    #[arg(long, value_parser = parse_byte_size)]
+    budget: u64,
+
+    /// The caches to tune, separated by commas. Defaults to every cache
+    #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+    caches: Vec<String>,
+
+    /// The kinds of cache to consider, separated by commas
+    #[arg(long, value_delimiter = ',', default_value = "direct,2way,4way,8way", value_parser = parse_kind)]
+    kinds: Vec<CacheKindConfig>,
+
+    /// The replacement policies to consider, separated by commas
+    #[arg(long, value_delimiter = ',', default_value = "rr,lru,lfu")]
+    policies: Vec<String>,
+
+    /// What to minimise
+    #[arg(long, value_enum, default_value_t = Objective::MissRate)]
+    objective: Objective,
+
+    /// The most configurations simulated in the initial grid search. Larger grids are thinned out
+    /// evenly
+    #[arg(long, default_value_t = 256)]
+    grid_limit: usize,
+
+    /// The most rounds of hill climbing after the grid search
+    #[arg(long, default_value_t = 20)]
+    max_rounds: usize,
+
+    /// Spread the configurations of each round across this many threads
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    /// Write the best configuration to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<String>,
+}
+
+/// The quantity the tuner minimises
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Objective {
+    /// The average memory access time, which needs the latencies in the configuration
+    Amat,
+    /// The fraction of accesses in the trace which go to main memory
+    MissRate,
+}
+
+/// The tunable settings of one cache
+#[derive(Debug, Clone, PartialEq)]
+struct Choice {
+    size: u64,
+    kind: CacheKindConfig,
+    policy: ReplacementPolicyConfig,
+}
+
+/// The settings of every tuned cache, in the order of `--caches`
+type Candidate = Vec<Choice>;
+
+/// The search, with every candidate evaluated so far
+struct Tuner<'a> {
+    args: &'a TuneArgs,
+    base: LayeredCacheConfig,
+    /// The indices of the tuned caches in the configuration
+    tuned: Vec<usize>,
+    policies: Vec<ReplacementPolicyConfig>,
+    trace: &'a [u8],
+    /// Each candidate evaluated, with its objective, or None if it isn't a valid configuration
+    evaluated: Vec<(Candidate, Option<f64>)>,
+}
+
+fn parse_kind(kind: &str) -> Result<CacheKindConfig, String> {
+    serde_json::from_value(Value::String(kind.to_string())).map_err(|_| format!("Unknown cache kind {kind}, expected direct, full, 2way, 4way, 8way or 16way"))
+}
+
+/// Searches for the configuration which minimises the objective on a trace, while keeping the
+/// total size of the caches within the budget
+///
+/// A coarse grid of sizes, kinds and policies is searched first. The best configuration of the grid
+/// is then improved by hill climbing, moving to the best neighbour each round until none is better.
+/// A neighbour differs in a single cache, which is doubled or halved in size, moved to the next or
+/// previous kind, or given another policy. Every configuration in a step is simulated in a single
+/// pass over the trace. Line sizes, and caches which aren't tuned, are kept from the configuration.
+pub fn run(args: &TuneArgs) -> Result<ExitCode, String> {
+    let (base, warnings) = load_layered_config_with_warnings(&[&args.config])?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let tuned = match args.caches.is_empty() {
+        true => (0..base.caches.len()).collect(),
+        false => args.caches.iter()
+            .map(|name| base.caches.iter().position(|c| &c.name == name).ok_or(format!("There is no cache named {name}")))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    let policies: Vec<ReplacementPolicyConfig> = args.policies.iter().cloned().map(ReplacementPolicyConfig::from).collect();
+    for policy in &policies {
+        policy.parameters()?;
+    }
+    let map = map_trace(&args.trace)?;
+    let mut kinds = args.kinds.clone();
+    kinds.sort_by_key(|kind| kind.ways(u64::MAX));
+    kinds.dedup();
+    let mut tuner = Tuner {
+        args,
+        policies,
+        base,
+        tuned,
+        trace: &map,
+        evaluated: Vec::new(),
+    };
+    let mut grid = tuner.grid(&kinds);
+    // The starting configuration is always considered, so tuning never makes it worse
+    let start: Candidate = tuner.tuned.iter()
+        .map(|&index| &tuner.base.caches[index])
+        .map(|cache| Choice { size: cache.size, kind: cache.kind, policy: cache.replacement_policy.clone() })
+        .collect();
+    if tuner.total_size(&start) <= args.budget && !grid.contains(&start) {
+        grid.push(start);
+    }
+    tuner.evaluate(&grid)?;
+    let (mut best, mut best_value) = tuner.best().ok_or(format!("No configuration fits in the budget of {} bytes", args.budget))?;
+    eprintln!("Grid search: {} configurations, best {best_value:.6}", grid.len());
+    for round in 1..=args.max_rounds {
+        let neighbours: Vec<Candidate> = tuner.neighbours(&best, &kinds).into_iter()
+            .filter(|n| !tuner.evaluated.iter().any(|(c, _)| c == n))
+            .collect();
+        tuner.evaluate(&neighbours)?;
+        match tuner.best() {
+            Some((candidate, value)) if value < best_value => {
+                eprintln!("Round {round}: {} neighbours, best {value:.6}", neighbours.len());
+                (best, best_value) = (candidate, value);
+            }
+            _ => {
+                eprintln!("Round {round}: {} neighbours, none better", neighbours.len());
+                break;
+            }
+        }
+    }
+    let config = tuner.apply(&best);
+    eprintln!("Best {}: {best_value:.6}, after simulating {} configurations", args.objective.to_possible_value().unwrap().get_name(), tuner.evaluated.len());
+    let json = serde_json::to_string_pretty(&config).map_err(|e| format!("Couldn't serialise the configuration {e}"))?;
+    write_output(args.output.as_deref(), &(json + "\n"))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+impl Tuner<'_> {
+    /// Gets every candidate on a coarse grid which fits in the budget, with sizes a factor of four
+    /// apart, thinned out evenly if there are more than the grid limit
+    fn grid(&self, kinds: &[CacheKindConfig]) -> Vec<Candidate> {
+        let mut grid: Vec<Candidate> = vec![Vec::new()];
+        for &index in &self.tuned {
+            let cache = &self.base.caches[index];
+            let mut choices = Vec::new();
+            let mut size = cache.line_size * MIN_LINES;
+            while size <= self.args.budget {
+                for kind in kinds {
+                    choices.extend(self.policies_for(*kind).into_iter().map(|policy| Choice { size, kind: *kind, policy }));
+                }
+                size *= 4;
+            }
+            grid = grid.into_iter()
+                .flat_map(|candidate| choices.iter().map(move |choice| {
+                    let mut candidate = candidate.clone();
+                    candidate.push(choice.clone());
+                    candidate
+                }))
+                .filter(|candidate| self.total_size(candidate) <= self.args.budget)
+                .collect();
+        }
+        if grid.len() > self.args.grid_limit {
+            let stride = grid.len() as f64 / self.args.grid_limit as f64;
+            grid = (0..self.args.grid_limit).map(|i| grid[(i as f64 * stride) as usize].clone()).collect();
+        }
+        grid
+    }
+
+    /// Gets the candidates which differ from a candidate in one setting of one cache
+    fn neighbours(&self, candidate: &Candidate, kinds: &[CacheKindConfig]) -> Vec<Candidate> {
+        let mut neighbours = Vec::new();
+        for (position, choice) in candidate.iter().enumerate() {
+            let mut changes = vec![
+                Choice { size: choice.size * 2, ..choice.clone() },
+                Choice { size: choice.size / 2, ..choice.clone() },
+            ];
+            let kind = kinds.iter().position(|k| *k == choice.kind).unwrap_or(0);
+            for adjacent in [kind.checked_sub(1), Some(kind + 1)].into_iter().flatten().filter_map(|k| kinds.get(k)) {
+                let policies = self.policies_for(*adjacent);
+                let policy = if policies.contains(&choice.policy) { choice.policy.clone() } else { policies[0].clone() };
+                changes.push(Choice { kind: *adjacent, policy, ..choice.clone() });
+            }
+            changes.extend(self.policies_for(choice.kind).into_iter()
+                .filter(|policy| *policy != choice.policy)
+                .map(|policy| Choice { policy, ..choice.clone() }));
+            for change in changes {
+                let mut neighbour = candidate.clone();
+                neighbour[position] = change;
+                if self.total_size(&neighbour) <= self.args.budget && !neighbours.contains(&neighbour) {
+                    neighbours.push(neighbour);
+                }
+            }
+        }
+        neighbours
+    }
+
+    /// Gets the policies worth trying for a kind of cache. Direct mapped caches only need one
+    fn policies_for(&self, kind: CacheKindConfig) -> Vec<ReplacementPolicyConfig> {
+        match kind {
+            CacheKindConfig::Direct => vec![ReplacementPolicyConfig::default()],
+            _ => self.policies.clone(),
+        }
+    }
+
+    /// Gets the total size of every cache with a candidate applied
+    fn total_size(&self, candidate: &Candidate) -> u64 {
+        let tuned: u64 = candidate.iter().map(|choice| choice.size).sum();
+        let fixed: u64 = self.base.caches.iter().enumerate()
+            .filter(|(index, _)| !self.tuned.contains(index))
+            .map(|(_, cache)| cache.size)
+            .sum();
+        tuned + fixed
+    }
+
+    /// Gets the configuration for a candidate
+    fn apply(&self, candidate: &Candidate) -> LayeredCacheConfig {
+        let mut config = self.base.clone();
+        for (&index, choice) in self.tuned.iter().zip(candidate) {
+            let cache = &mut config.caches[index];
+            if cache.replacement_policy != choice.policy {
+                cache.policy_params.clear();
+            }
+            cache.size = choice.size;
+            cache.kind = choice.kind;
+            cache.replacement_policy = choice.policy.clone();
+        }
+        config
+    }
+
+    /// Simulates every valid candidate in a single pass over the trace, recording its objective
+    fn evaluate(&mut self, candidates: &[Candidate]) -> Result<(), String> {
+        let configs: Vec<Option<LayeredCacheConfig>> = candidates.iter()
+            .map(|candidate| Some(self.apply(candidate)).filter(|config| config.validate().is_ok()))
+            .collect();
+        let mut ensemble = Ensemble::new(configs.iter().flatten()).with_threads(self.args.threads);
+        ensemble.simulate(self.trace)?;
+        let mut results = ensemble.into_results().into_iter();
+        for (candidate, config) in candidates.iter().zip(&configs) {
+            let value = match config {
+                None => None,
+                Some(config) => {
+                    let result = results.next().unwrap();
+                    Some(match self.args.objective {
+                        Objective::Amat => result.average_memory_access_time(config)
+                            .ok_or("Tuning for the average memory access time needs every cache to have a hit latency, and the last to have a miss penalty or the configuration a memory latency")?,
+                        Objective::MissRate => result.main_memory_accesses() as f64 / (self.trace.len() / 40).max(1) as f64,
+                    })
+                }
+            };
+            self.evaluated.push((candidate.clone(), value));
+        }
+        Ok(())
+    }
+
+    /// Gets the candidate with the lowest objective so far, preferring the smallest on ties
+    fn best(&self) -> Option<(Candidate, f64)> {
+        self.evaluated.iter()
+            .filter_map(|(candidate, value)| value.map(|value| (candidate, value)))
+            .min_by(|(a, a_value), (b, b_value)| a_value.total_cmp(b_value).then(self.total_size(a).cmp(&self.total_size(b))))
+            .map(|(candidate, value)| (candidate.clone(), value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: TuneArgs,
+    }
+
+    fn new_tuner<'a>(args: &'a TuneArgs, config: &str, trace: &'a [u8]) -> Tuner<'a> {
+        let base: LayeredCacheConfig = serde_json::from_str(config).unwrap();
+        let tuned = match args.caches.is_empty() {
+            true => (0..base.caches.len()).collect(),
+            false => args.caches.iter().map(|name| base.caches.iter().position(|c| &c.name == name).unwrap()).collect(),
+        };
+        let policies = args.policies.iter().cloned().map(ReplacementPolicyConfig::from).collect();
+        Tuner { args, base, tuned, policies, trace, evaluated: Vec::new() }
+    }
+
+    /// Reads each of a number of lines in turn, several times over
+    fn cycle(lines: u64) -> Vec<u8> {
+        (0..lines * 8).map(|i| format!("{:016x} {:016x} R 004\n", 0x400000, i % lines * 64)).collect::<String>().into_bytes()
+    }
+
+    #[test]
+    fn candidates_keep_to_the_budget_and_invalid_ones_are_not_simulated() {
+        let args = Cli::parse_from(["tune", "config", "trace", "--budget", "8KiB"]).args;
+        let trace = cycle(4);
+        let mut tuner = new_tuner(&args, r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 4096}]}"#, &trace);
+        let kinds = args.kinds.clone();
+        let grid = tuner.grid(&kinds);
+        assert!(!grid.is_empty());
+        for candidate in &grid {
+            assert!(tuner.total_size(candidate) <= args.budget, "{candidate:?} is over the budget");
+            tuner.apply(candidate).validate().unwrap();
+        }
+        // Halving a cache can leave it with fewer lines than ways
+        let neighbours: Vec<Candidate> = grid.iter().flat_map(|candidate| tuner.neighbours(candidate, &kinds)).collect();
+        assert!(neighbours.iter().all(|candidate| tuner.total_size(candidate) <= args.budget));
+        tuner.evaluate(&neighbours).unwrap();
+        assert!(tuner.evaluated.iter().any(|(_, value)| value.is_none()));
+        for (candidate, value) in &tuner.evaluated {
+            assert_eq!(value.is_some(), tuner.apply(candidate).validate().is_ok(), "{candidate:?}");
+        }
+    }
+
+    #[test]
+    fn caches_which_are_not_tuned_count_towards_the_budget() {
+        let args = Cli::parse_from(["tune", "config", "trace", "--budget", "5KiB", "--caches", "L1"]).args;
+        let tuner = new_tuner(&args, r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 4096}]}"#, &[]);
+        let grid = tuner.grid(&args.kinds);
+        assert!(!grid.is_empty());
+        assert!(grid.iter().all(|candidate| candidate.len() == 1 && candidate[0].size <= 1024));
+        let too_small = Cli::parse_from(["tune", "config", "trace", "--budget", "4KiB", "--caches", "L1"]).args;
+        assert!(new_tuner(&too_small, r#"{"caches": [{"name": "L1", "size": 1024}, {"name": "L2", "size": 4096}]}"#, &[]).grid(&too_small.kinds).is_empty());
+    }
+
+    #[test]
+    fn the_best_candidate_has_the_lowest_objective_then_the_smallest_size() {
+        let args = Cli::parse_from(["tune", "config", "trace", "--budget", "4KiB"]).args;
+        // 16 lines read in turn miss every time in a cache of 8 lines, but only the first time in
+        // one of 16 or more
+        let trace = cycle(16);
+        let mut tuner = new_tuner(&args, r#"{"caches": [{"name": "L1", "size": 1024}]}"#, &trace);
+        let choice = |size| vec![Choice { size, kind: CacheKindConfig::Full, policy: ReplacementPolicyConfig::RoundRobin }];
+        tuner.evaluate(&[choice(512), choice(2048), choice(1024)]).unwrap();
+        let values: Vec<f64> = tuner.evaluated.iter().map(|(_, value)| value.unwrap()).collect();
+        assert_eq!(values, [1.0, 0.125, 0.125]);
+        assert_eq!(tuner.best(), Some((choice(1024), 0.125)));
+    }
+}
@@ -0,0 +1,63 @@
+use std::process::ExitCode;
+use clap::Args;
+use cachelib::analysis::{WorkingSetAnalysis, WorkingSetReport, DEFAULT_WORKING_SET_WINDOW};
+use cachelib::config::parse_byte_size;
+use crate::report::{ReportArgs, Table};
+use crate::trace::map_trace;
+
+/// Arguments for the analyze working-set subcommand
+#[derive(Args, Debug)]
+pub struct WorkingSetArgs {
+    /// The path to the trace file
+    trace: String,
+
+    /// The line size the working set is counted in
+    #[arg(long, default_value = "64", value_parser = parse_byte_size)]
+    line_size: u64,
+
+    /// The number of accesses in each window of the series
+    #[arg(long, value_name = "ACCESSES", default_value_t = DEFAULT_WORKING_SET_WINDOW)]
+    window: u64,
+
+    /// The window sizes to find the average working set for, in accesses, separated by commas,
+    /// such as 1000,10000,100000
+    #[arg(long, value_name = "ACCESSES", value_delimiter = ',')]
+    curve: Vec<u64>,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Estimates the working set of a trace in each window of accesses, and its average for each window
+/// size of the curve
+pub fn run(args: &WorkingSetArgs) -> Result<ExitCode, String> {
+    let mut analysis = WorkingSetAnalysis::new(args.line_size, args.window, &args.curve)?;
+    analysis.simulate(&map_trace(&args.trace)?)?;
+    let report = analysis.report();
+    args.report.write(&report, || report_tables(&report))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Lays out a report as tables of the series and the curve, with each working set in lines and
+/// bytes
+fn report_tables(report: &WorkingSetReport) -> Vec<Table> {
+    let bytes = |lines: f64| format!("{:.0}", lines * report.line_size as f64);
+    let summary = vec![
+        vec!["Accesses".to_string(), report.accesses.to_string()],
+        vec![format!("Footprint ({}B lines)", report.line_size), report.footprint.to_string()],
+        vec!["Footprint (bytes)".to_string(), bytes(report.footprint as f64)],
+    ];
+    let mut series = vec![["first access", "lines", "bytes"].map(str::to_string).to_vec()];
+    for (lines, index) in report.windows.iter().zip(0..) {
+        series.push(vec![(index * report.window).to_string(), lines.to_string(), bytes(*lines as f64)]);
+    }
+    let mut tables: Vec<Table> = vec![summary.into(), series.into()];
+    if !report.curve.is_empty() {
+        let mut curve = vec![["window (accesses)", "average lines", "average bytes"].map(str::to_string).to_vec()];
+        for point in &report.curve {
+            curve.push(vec![point.window.to_string(), format!("{:.2}", point.lines), bytes(point.lines)]);
+        }
+        tables.push(curve.into());
+    }
+    tables
+}
@@ -0,0 +1,90 @@
+//! # CacheWasm
+//!
+//! JavaScript bindings for cachelib, built for `wasm32-unknown-unknown` with wasm-bindgen, so the
+//! simulator can run in the browser
+//!
+//! Configurations are given as JSON strings, in the same format as configuration files, and results
+//! are returned as plain JavaScript objects in the format of the specification
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use cachelib::config::{config_from_value, LayeredCacheConfig};
+use cachelib::simulator::Simulator as CacheSimulator;
+
+/// Converts a value to a plain JavaScript object
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| JsError::new(&format!("Couldn't convert the output {e}")))
+}
+
+fn load_config(json: &str) -> Result<(LayeredCacheConfig, Vec<String>), JsError> {
+    let document = serde_json::from_str(json).map_err(|e| JsError::new(&format!("Couldn't parse the config: {e}")))?;
+    config_from_value(document).map_err(|e| JsError::new(&e))
+}
+
+#[derive(Serialize)]
+struct ParsedConfig {
+    config: LayeredCacheConfig,
+    warnings: Vec<String>,
+}
+
+/// Parses and validates a configuration, returning `{config, warnings}`, where `config` has its
+/// defaults filled in and `warnings` lists any deprecated settings
+///
+/// Configurations can't use `include`, as there are no files to include
+#[wasm_bindgen(js_name = parseConfig)]
+pub fn parse_config(json: &str) -> Result<JsValue, JsError> {
+    let (config, warnings) = load_config(json)?;
+    to_js(&ParsedConfig { config, warnings })
+}
+
+/// A simulator for a configuration, which keeps the state of its caches between calls to simulate
+#[wasm_bindgen]
+pub struct Simulator {
+    simulator: CacheSimulator,
+}
+
+#[wasm_bindgen]
+impl Simulator {
+    /// Creates a simulator with empty caches from a JSON configuration
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: &str) -> Result<Simulator, JsError> {
+        let (config, _) = load_config(config)?;
+        Ok(Simulator { simulator: CacheSimulator::new(&config) })
+    }
+
+    /// Simulates a trace in the 40 byte record format, such as the contents of a trace file,
+    /// returning the results so far
+    pub fn simulate(&mut self, trace: &[u8]) -> Result<JsValue, JsError> {
+        if !trace.len().is_multiple_of(40) {
+            return Err(JsError::new("The trace ends part way through a record"));
+        }
+        to_js(self.simulator.simulate(trace).map_err(|e| JsError::new(&e))?)
+    }
+
+    /// Simulates a trace pasted as text, returning the results so far
+    ///
+    /// Unlike [simulate](Simulator::simulate), blank lines, Windows line endings and a missing newline
+    /// at the end are accepted, and a malformed line is reported by its number
+    #[wasm_bindgen(js_name = simulateText)]
+    pub fn simulate_text(&mut self, trace: &str) -> Result<JsValue, JsError> {
+        let mut records = Vec::with_capacity(trace.len() + 1);
+        for (number, line) in trace.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            if line.len() != 39 {
+                return Err(JsError::new(&format!("Line {} should be 39 characters long, such as 00000000004005b4 00007ffd8e5d3a18 R 008, but is {}", number + 1, line.len())));
+            }
+            records.extend_from_slice(line.as_bytes());
+            records.push(b'\n');
+        }
+        self.simulate(&records)
+    }
+
+    /// Gets the results so far
+    pub fn result(&self) -> Result<JsValue, JsError> {
+        to_js(self.simulator.get_result())
+    }
+}